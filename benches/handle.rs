@@ -0,0 +1,163 @@
+//! Throughput of handling a message and sending what it causes, as a node's runtime does
+//! for every line, for bodies of a few sizes.
+
+use std::sync::Arc;
+
+use clap::Parser;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use distributed_system::clock::VirtualClock;
+use distributed_system::compact::encode_ranges;
+use distributed_system::kafka;
+use distributed_system::sim::SimNode;
+use distributed_system::Message;
+use serde_json::{json, Value};
+
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+const SIZES: [u64; 3] = [1, 100, 10_000];
+
+/// `size` broadcast messages with gaps between them, so that gossip can't encode them as a
+/// single range.
+fn sparse(size: u64) -> Vec<u64> {
+    (0..size).map(|i| 2 * i).collect()
+}
+
+fn line(src: &str, body: Value) -> String {
+    let message = Message {
+        src: src.to_string(),
+        dest: "n0".to_string(),
+        body,
+    };
+    serde_json::to_string(&message).unwrap()
+}
+
+/// Hands `node` each of `bodies` from `src`, and returns the node.
+fn fed(mut node: Box<dyn SimNode>, src: &str, bodies: Vec<Value>) -> Box<dyn SimNode> {
+    let mut output = Vec::new();
+    for body in bodies {
+        node.handle(&line(src, body), &mut output).unwrap();
+    }
+    node
+}
+
+fn broadcast_node() -> Box<dyn SimNode> {
+    let cli = broadcast::Cli::parse_from(["broadcast", "--seed=1"]);
+    let node = broadcast::simulated(cli, Arc::new(VirtualClock::new())).unwrap();
+    fed(
+        node,
+        "c1",
+        vec![
+            json!({"type": "init", "msg_id": 1, "node_id": "n0", "node_ids": ["n0", "n1", "n2"]}),
+            json!({"type": "topology", "msg_id": 2, "topology": {"n0": ["n1", "n2"]}}),
+        ],
+    )
+}
+
+fn kafka_node() -> Box<dyn SimNode> {
+    let cli = kafka::Cli::parse_from(["kafka"]);
+    let node = kafka::simulated(cli, Arc::new(VirtualClock::new())).unwrap();
+    let init = json!({"type": "init", "msg_id": 1, "node_id": "n0", "node_ids": ["n0"]});
+    fed(node, "c1", vec![init])
+}
+
+/// A node takes in gossip of new messages from a neighbour, and acknowledges it.
+fn broadcast_gossip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broadcast/gossip");
+    for size in SIZES {
+        let messages = sparse(size);
+        let gossip = line(
+            "n1",
+            json!({"type": "gossip", "msg_id": 1, "messages": encode_ranges(&messages)}),
+        );
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &gossip, |b, gossip| {
+            b.iter_batched(
+                || (broadcast_node(), Vec::new()),
+                |(mut node, mut output)| {
+                    node.handle(gossip, &mut output).unwrap();
+                    (node, output)
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// A node answers a read with every message it knows.
+fn broadcast_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broadcast/read");
+    for size in SIZES {
+        let messages = sparse(size);
+        let gossip = json!({"type": "gossip", "msg_id": 1, "messages": encode_ranges(&messages)});
+        let mut node = fed(broadcast_node(), "n1", vec![gossip]);
+        let read = line("c1", json!({"type": "read", "msg_id": 3}));
+        let mut output = Vec::new();
+        group.throughput(Throughput::Elements(size));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                output.clear();
+                node.handle(&read, &mut output).unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+/// A node appends a batch of messages to a log.
+fn kafka_send(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kafka/send");
+    for size in SIZES {
+        let msgs: Vec<u64> = (0..size).collect();
+        let send = line(
+            "c1",
+            json!({"type": "send", "msg_id": 2, "key": "k1", "msg": msgs}),
+        );
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &send, |b, send| {
+            b.iter_batched(
+                || (kafka_node(), Vec::new()),
+                |(mut node, mut output)| {
+                    node.handle(send, &mut output).unwrap();
+                    (node, output)
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// A node answers a poll of a log from its start.
+fn kafka_poll(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kafka/poll");
+    for size in SIZES {
+        let msgs: Vec<u64> = (0..size).collect();
+        let send = json!({"type": "send", "msg_id": 2, "key": "k1", "msg": msgs});
+        let mut node = fed(kafka_node(), "c1", vec![send]);
+        let poll = line(
+            "c1",
+            json!({"type": "poll", "msg_id": 3, "offsets": {"k1": 0}}),
+        );
+        let mut output = Vec::new();
+        group.throughput(Throughput::Elements(size));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                output.clear();
+                node.handle(&poll, &mut output).unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    broadcast_gossip,
+    broadcast_read,
+    kafka_send,
+    kafka_poll
+);
+criterion_main!(benches);
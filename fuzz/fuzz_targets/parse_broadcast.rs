@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/broadcast.rs"]
+mod broadcast;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = broadcast::parse_message(line);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/echo.rs"]
+mod echo;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = echo::parse_message(line);
+});
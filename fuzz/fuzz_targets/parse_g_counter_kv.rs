@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/g_counter_kv.rs"]
+mod g_counter_kv;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = g_counter_kv::parse_message(line);
+});
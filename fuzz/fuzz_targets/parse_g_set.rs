@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/g_set.rs"]
+mod g_set;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = g_set::parse_message(line);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/kafka.rs"]
+mod kafka;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = kafka::parse_message(line);
+});
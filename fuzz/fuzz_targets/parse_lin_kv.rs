@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/lin_kv.rs"]
+mod lin_kv;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = lin_kv::parse_message(line);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/pn_counter.rs"]
+mod pn_counter;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = pn_counter::parse_message(line);
+});
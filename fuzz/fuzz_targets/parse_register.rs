@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/register.rs"]
+mod register;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = register::parse_message(line);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/tob.rs"]
+mod tob;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = tob::parse_message(line);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/txn.rs"]
+mod txn;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = txn::parse_message(line);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/bin/unique_ids.rs"]
+mod unique_ids;
+
+fuzz_target!(|line: &[u8]| {
+    let _ = unique_ids::parse_message(line);
+});
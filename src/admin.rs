@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::message::Message;
+use crate::metrics::{self, Kind, Stats};
+
+/// Requests for inspecting a live node, which the runtime answers for every workload
+/// without the node handling them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminRequest {
+    /// The metrics counted so far.
+    Stats { msg_id: u64 },
+    /// A snapshot of the internal state of the node, as its `debug_state` hook builds it.
+    DebugState { msg_id: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminReply {
+    StatsOk { in_reply_to: u64, stats: Stats },
+    DebugStateOk { in_reply_to: u64, state: Value },
+}
+
+/// The admin request on `line`, a message of type `kind`, if it's one.
+pub fn parse(kind: Kind, line: &[u8]) -> Option<Message<AdminRequest>> {
+    if !matches!(kind.as_str(), "stats" | "debug_state") {
+        return None;
+    }
+    serde_json::from_slice(line).ok()
+}
+
+impl Message<AdminRequest> {
+    /// The reply, with the state of the node from `debug_state` if it's asked for.
+    pub fn answer(&self, debug_state: impl FnOnce() -> Value) -> Message<AdminReply> {
+        let body = match self.body {
+            AdminRequest::Stats { msg_id } => AdminReply::StatsOk {
+                in_reply_to: msg_id,
+                stats: metrics::snapshot(),
+            },
+            AdminRequest::DebugState { msg_id } => AdminReply::DebugStateOk {
+                in_reply_to: msg_id,
+                state: debug_state(),
+            },
+        };
+        self.reply(body)
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{JoinError, JoinSet};
+
+use crate::admin;
+use crate::error::ErrorBody;
+use crate::journal;
+use crate::message::{Message, RawMessage};
+use crate::metrics;
+use crate::protocol::{self, Handshake};
+use crate::runtime::{self, MalformedInput};
+
+/// How long handlers still running at the end of the input get to finish.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+type Task = Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send>>;
+
+/// A workload run by the async runtime. Every message it receives is handled in a task of
+/// its own, so a handler can await RPC responses and timers without holding up the rest.
+pub trait Handler: Send + Sync + Sized + 'static {
+    type Body: Serialize + DeserializeOwned + From<ErrorBody> + Send + 'static;
+
+    fn handle(
+        self: Arc<Self>,
+        node: Node<Self::Body>,
+        message: Message<Self::Body>,
+    ) -> impl Future<Output = Result<(), anyhow::Error>> + Send;
+
+    /// Runs once stdin is closed and the tasks of the node are stopped. What it sends is
+    /// still written before the node exits.
+    fn on_shutdown(&self, _node: &Node<Self::Body>) {}
+
+    /// A snapshot of the workload's internal state, for answering `debug_state` requests.
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+}
+
+enum Output {
+    Line(String),
+    Close,
+}
+
+struct Shared {
+    id: String,
+    node_ids: Vec<String>,
+    next_msg_id: AtomicU64,
+    /// Calls awaiting a response, by the `msg_id` of their request.
+    pending: Mutex<HashMap<u64, oneshot::Sender<RawMessage>>>,
+    output: mpsc::UnboundedSender<Output>,
+    tasks: mpsc::UnboundedSender<Task>,
+}
+
+/// A handle to the node, for handlers and the tasks they spawn to send messages with.
+pub struct Node<B> {
+    shared: Arc<Shared>,
+    body: PhantomData<fn() -> B>,
+}
+
+impl<B> Clone for Node<B> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            body: PhantomData,
+        }
+    }
+}
+
+impl<B: Serialize + DeserializeOwned + From<ErrorBody>> Node<B> {
+    pub fn id(&self) -> &str {
+        &self.shared.id
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.shared.node_ids
+    }
+
+    pub fn next_msg_id(&self) -> u64 {
+        self.shared.next_msg_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn send<T: Serialize>(&self, dest: &str, body: T) -> Result<(), anyhow::Error> {
+        let message = Message {
+            src: self.shared.id.clone(),
+            dest: dest.to_string(),
+            body,
+        };
+        let line = serde_json::to_string(&message).context("Failed to serialize message")?;
+        metrics::record_sent(line.as_bytes());
+        self.shared
+            .output
+            .send(Output::Line(line))
+            .map_err(|_| anyhow::anyhow!("STDOUT writer stopped"))
+    }
+
+    /// Sends lines that were serialized and counted in the metrics already, such as what a
+    /// node that writes to a buffer sent while handling a message.
+    pub fn send_lines(&self, lines: &[u8]) -> Result<(), anyhow::Error> {
+        for line in lines.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            let line = String::from_utf8(line.to_vec()).context("Sent a line that isn't UTF-8")?;
+            self.shared
+                .output
+                .send(Output::Line(line))
+                .map_err(|_| anyhow::anyhow!("STDOUT writer stopped"))?;
+        }
+        Ok(())
+    }
+
+    pub fn reply<T: Serialize, R>(
+        &self,
+        request: &Message<R>,
+        body: T,
+    ) -> Result<(), anyhow::Error> {
+        self.send(&request.src, body)
+    }
+
+    /// Sends a request built by `body` from a new `msg_id`, and waits for the response,
+    /// which may be an error body. Fails if none arrives within `timeout`.
+    pub async fn call(
+        &self,
+        dest: &str,
+        body: impl FnOnce(u64) -> B,
+        timeout: Duration,
+    ) -> Result<Message<B>, anyhow::Error> {
+        let msg_id = self.next_msg_id();
+        let (sender, receiver) = oneshot::channel();
+        self.shared.pending.lock().unwrap().insert(msg_id, sender);
+        self.send(dest, body(msg_id))?;
+
+        let response = tokio::time::timeout(timeout, receiver).await;
+        self.shared.pending.lock().unwrap().remove(&msg_id);
+        let response = response
+            .with_context(|| format!("No response from {dest} to {msg_id} in {timeout:?}"))?
+            .context("Node stopped while awaiting a response")?;
+        response
+            .decode()
+            .with_context(|| format!("Unsupported response from {dest} to {msg_id}"))
+    }
+
+    /// Runs `task` next to the handlers, such as a periodic one. It's stopped at shutdown,
+    /// and an error it returns stops the node.
+    pub fn spawn(&self, task: impl Future<Output = Result<(), anyhow::Error>> + Send + 'static) {
+        // Only fails once the node is shutting down, when the task would be stopped anyway.
+        let _ = self.shared.tasks.send(Box::pin(task));
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename = "init")]
+struct Init {
+    msg_id: u64,
+    node_id: String,
+    node_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "init_ok")]
+struct InitOk {
+    msg_id: u64,
+    in_reply_to: u64,
+    #[serde(flatten)]
+    handshake: Handshake,
+}
+
+/// Runs a node over stdin and stdout on a single-threaded tokio runtime, until stdin is
+/// closed. Once `init` arrived, `init` creates the workload, and the runtime answers `init`
+/// itself.
+pub fn run<H, F>(init: F) -> Result<(), anyhow::Error>
+where
+    H: Handler,
+    F: FnOnce(&Node<H::Body>) -> Result<H, anyhow::Error>,
+{
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    run_with(init, stdin, tokio::io::stdout()).map(|_| ())
+}
+
+/// Like `run`, over the given input and output, and returns the output once the input ends.
+pub fn run_with<H, F, R, W>(init: F, input: R, output: W) -> Result<W, anyhow::Error>
+where
+    H: Handler,
+    F: FnOnce(&Node<H::Body>) -> Result<H, anyhow::Error>,
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .context("Failed to start the tokio runtime")?
+        .block_on(serve(init, input, output))
+}
+
+async fn serve<H, F, R, W>(create: F, input: R, output: W) -> Result<W, anyhow::Error>
+where
+    H: Handler,
+    F: FnOnce(&Node<H::Body>) -> Result<H, anyhow::Error>,
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let malformed = MalformedInput::from_env()?;
+    let mut lines = input.lines();
+    let (output_sender, output_receiver) = mpsc::unbounded_channel();
+    let writer = tokio::spawn(write_lines(output_receiver, output));
+
+    let line = lines
+        .next_line()
+        .await
+        .context("Failed to read from STDIN.")?
+        .context("Maelstrom should provide input to STDIN.")?;
+    runtime::received(line.as_bytes());
+    let init: Message<Init> = serde_json::from_str(&line)
+        .context("Expected Init message as the first received message.")?;
+
+    let (task_sender, mut task_receiver) = mpsc::unbounded_channel();
+    let node = Node {
+        shared: Arc::new(Shared {
+            id: init.body.node_id.clone(),
+            node_ids: init.body.node_ids.clone(),
+            next_msg_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            output: output_sender,
+            tasks: task_sender,
+        }),
+        body: PhantomData,
+    };
+    node.reply(
+        &init,
+        InitOk {
+            msg_id: node.next_msg_id(),
+            in_reply_to: init.body.msg_id,
+            handshake: protocol::answer(),
+        },
+    )?;
+    let handler = Arc::new(create(&node)?);
+
+    let mut handlers = JoinSet::new();
+    let mut background = JoinSet::new();
+    let result = loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = match line.context("Failed to read from STDIN.") {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break Ok(()),
+                    Err(error) => break Err(error),
+                };
+                if let Err(error) = malformed.check(dispatch(&handler, &node, &mut handlers, line)) {
+                    break Err(error);
+                }
+            }
+            Some(task) = task_receiver.recv() => {
+                background.spawn(task);
+            }
+            Some(joined) = handlers.join_next() => {
+                if let Err(error) = finished(joined) {
+                    break Err(error);
+                }
+            }
+            Some(joined) = background.join_next() => {
+                if let Err(error) = finished(joined) {
+                    break Err(error);
+                }
+            }
+        }
+    };
+
+    // Messages read before the end of the input are still handled, but calls that are never
+    // answered don't keep the node from exiting.
+    let result = match result {
+        Ok(()) => tokio::time::timeout(SHUTDOWN_GRACE, async {
+            while let Some(joined) = handlers.join_next().await {
+                finished(joined)?;
+            }
+            Ok(())
+        })
+        .await
+        .unwrap_or(Ok(())),
+        Err(error) => Err(error),
+    };
+    handlers.shutdown().await;
+    background.shutdown().await;
+    handler.on_shutdown(&node);
+    metrics::log_snapshot();
+    let _ = node.shared.output.send(Output::Close);
+    let output = writer
+        .await
+        .map_err(|e| anyhow::anyhow!("Writer panicked: {e}"))??;
+    result.map(|()| output)
+}
+
+fn finished(joined: Result<Result<(), anyhow::Error>, JoinError>) -> Result<(), anyhow::Error> {
+    joined.map_err(|error| anyhow::anyhow!("Task panicked: {error}"))?
+}
+
+/// Completes the call a response belongs to, answers an admin request, or hands a message
+/// to the handler.
+fn dispatch<H: Handler>(
+    handler: &Arc<H>,
+    node: &Node<H::Body>,
+    tasks: &mut JoinSet<Result<(), anyhow::Error>>,
+    line: String,
+) -> Result<(), anyhow::Error> {
+    let kind = runtime::received(line.as_bytes());
+    if let Some(request) = admin::parse(kind, line.as_bytes()) {
+        let reply = request.answer(|| handler.debug_state());
+        return node.send(&reply.dest, reply.body);
+    }
+    if let Ok(message) = RawMessage::parse_raw(&line) {
+        if let Some(in_reply_to) = message.in_reply_to() {
+            let call = node.shared.pending.lock().unwrap().remove(&in_reply_to);
+            if let Some(call) = call {
+                let _ = call.send(message);
+                return Ok(());
+            }
+        }
+    }
+
+    match Message::parse(&line).context("Failed to deserialize provided input to STDIN.")? {
+        Ok(message) => {
+            let handled = handler.clone().handle(node.clone(), message);
+            let handler = handler.clone();
+            tasks.spawn(async move {
+                let start = Instant::now();
+                let result = handled.await;
+                metrics::record_latency(kind, start.elapsed());
+                // Handlers run concurrently, so the journal has them in the order they finish.
+                journal::event(kind.as_str(), || handler.debug_state());
+                result
+            });
+        }
+        Err(error_reply) => node.send(&error_reply.dest, error_reply.body)?,
+    }
+    Ok(())
+}
+
+/// Writes lines until closed, flushing once no more are queued.
+async fn write_lines<W: AsyncWrite + Unpin>(
+    mut receiver: mpsc::UnboundedReceiver<Output>,
+    mut output: W,
+) -> Result<W, anyhow::Error> {
+    while let Some(Output::Line(line)) = receiver.recv().await {
+        output
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to write to STDOUT.")?;
+        output
+            .write_all(b"\n")
+            .await
+            .context("Failed to write newline")?;
+        if receiver.is_empty() {
+            output.flush().await.context("Failed to flush STDOUT.")?;
+        }
+    }
+    output.flush().await.context("Failed to flush STDOUT.")?;
+    Ok(output)
+}
@@ -1,9 +1,250 @@
-use std::collections::{HashMap, HashSet};
-use std::io::{BufRead, StdoutLock, Write};
-use std::sync::mpsc::Sender;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
+use clap::{Args, Parser};
+#[cfg(feature = "tokio")]
+use distributed_system::async_runtime;
+use distributed_system::breaker::CircuitBreaker;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::compress;
+use distributed_system::config::{self, NodeArgs, RetryArgs};
+use distributed_system::digest::{self, BloomFilter, SetDigest};
+use distributed_system::event_queue::{EventReceiver, EventSender};
+use distributed_system::membership::{Membership, MembershipEvent, SwimConfig, SwimMessage};
+use distributed_system::message::Outgoing;
+use distributed_system::metrics;
+use distributed_system::node_id::{NodeId, NodeIds};
+use distributed_system::outbound::OutboundQueues;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::range_set::RangeSet;
+use distributed_system::rng::Rng;
+use distributed_system::rpc::PendingRequests;
+use distributed_system::rtt::RttEstimator;
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::sim::SimNode;
+use distributed_system::topology::Topology;
+use distributed_system::vector_clock::VectorClock;
+use distributed_system::{ErrorBody, ErrorCode};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+type Message = distributed_system::Message<Body>;
+
+/// A gossip not acknowledged within the retry timeout is considered lost, and what it
+/// carried is sent again in the next round. Once acks from a neighbour arrive, the timeout
+/// adapts to their round-trip time, but doesn't drop below this.
+const MIN_GOSSIP_ACK_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// In Plumtree mode, a message announced by a lazy peer that doesn't arrive within this
+/// time is requested from that peer with a graft.
+const GRAFT_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// A read that repairs from a neighbour waits for its answer until the first gossip tick
+/// after this long, and then returns the messages of this node.
+const READ_REPAIR_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Messages other than integers below this are identified by hashes at or above it, see
+/// `Node::id_of`.
+const HASHED_IDS: u64 = 1 << 62;
+
+/// Identifies a stamped message by its origin and the origin's count of broadcasts.
+type StampId = (String, u64);
+
+/// In which order broadcast messages become visible to `read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Ordering {
+    /// Messages are visible as soon as they arrive.
+    #[default]
+    Eventual,
+    /// A message is only visible once every message its origin had seen before it is.
+    Causal,
+}
+
+/// What runs a broadcast node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum NodeRuntime {
+    /// A reader thread and a timer thread, which queue events for the node.
+    #[default]
+    Threads,
+    /// A single-threaded tokio runtime, which needs the `tokio` feature.
+    Async,
+}
+
+impl FromStr for NodeRuntime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "threads" => Ok(NodeRuntime::Threads),
+            "async" => Ok(NodeRuntime::Async),
+            other => anyhow::bail!("Unknown runtime: {other}"),
+        }
+    }
+}
+
+impl FromStr for Ordering {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eventual" => Ok(Ordering::Eventual),
+            "causal" => Ok(Ordering::Causal),
+            other => anyhow::bail!("Unknown broadcast ordering: {other}"),
+        }
+    }
+}
+
+/// Tunes how gossip trades latency against the number of messages sent per broadcast.
+#[derive(Debug, Clone, Copy, Args)]
+struct GossipConfig {
+    /// Milliseconds between gossip rounds. Every round also resends what neighbours haven't
+    /// acknowledged yet.
+    #[arg(
+        long = "gossip-interval-ms",
+        env = "BROADCAST_GOSSIP_INTERVAL_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "150"
+    )]
+    interval: Duration,
+    /// Once new messages arrive, a round starts after this many milliseconds instead of at
+    /// the next interval, carrying everything that arrived in the meantime.
+    #[arg(
+        long = "batch-delay-ms",
+        env = "BROADCAST_BATCH_DELAY_MS",
+        value_name = "MS",
+        value_parser = config::millis
+    )]
+    batch_delay: Option<Duration>,
+    /// Number of neighbours gossiped to per round, picked at random. All of them when unset.
+    #[arg(long, env = "BROADCAST_FANOUT")]
+    fanout: Option<usize>,
+    /// Seeds the random choices of the node, combined with its id, so that runs repeat.
+    #[arg(long, env = "BROADCAST_SEED")]
+    seed: Option<u64>,
+    /// Forwards new messages to every neighbour as soon as they arrive. Rounds then only
+    /// resend what was lost.
+    #[arg(long, env = "BROADCAST_FLOOD")]
+    flood: bool,
+    /// The overlay to gossip over: maelstrom, tree:<fanout>, ring, grid, star, or
+    /// hubs:<count>.
+    #[arg(long, env = "BROADCAST_TOPOLOGY", default_value = "maelstrom")]
+    topology: Topology,
+    /// Milliseconds between push-pull exchanges with a random neighbour. When set, gossip
+    /// isn't acknowledged, and whatever got lost is found by comparing digests instead.
+    #[arg(
+        long = "anti-entropy-ms",
+        env = "BROADCAST_ANTI_ENTROPY_MS",
+        value_name = "MS",
+        value_parser = config::millis
+    )]
+    anti_entropy: Option<Duration>,
+    /// Spreads every message as a rumor instead: each round, hot rumors are gossiped to the
+    /// round's neighbours, and a rumor is retired once this many of them already knew it.
+    #[arg(long, env = "BROADCAST_RUMOR_STOP")]
+    rumor_stop: Option<u32>,
+    /// Spreads messages with Plumtree: pushed right away along a spanning tree that forms
+    /// from the neighbours that don't send duplicates, and announced lazily to the rest.
+    #[arg(long, env = "BROADCAST_PLUMTREE")]
+    plumtree: bool,
+    /// Probes neighbours with a SWIM failure detector every this many milliseconds, and
+    /// stops gossiping to the ones it declares faulty until they respond again.
+    #[arg(
+        long = "swim-probe-ms",
+        env = "BROADCAST_SWIM_PROBE_MS",
+        value_name = "MS",
+        value_parser = config::millis
+    )]
+    swim: Option<Duration>,
+    /// Messages a second the node sends each neighbour at most, on average, queuing the
+    /// rest. No gossip is made for a neighbour while messages to it are queued, so what
+    /// timed out in the meantime is retried in one. Unlimited when unset.
+    #[arg(long, env = "BROADCAST_PEER_RATE")]
+    peer_rate: Option<f64>,
+    /// Messages the node may send a neighbour at once before `--peer-rate` applies.
+    #[arg(long, env = "BROADCAST_PEER_BURST", default_value_t = 10)]
+    peer_burst: u32,
+    /// Pulls the messages a random neighbour has and this node lacks before answering a
+    /// read, so that reads are fresher than the gossip interval allows.
+    #[arg(long, env = "BROADCAST_READ_REPAIR")]
+    read_repair: bool,
+    #[command(flatten)]
+    retry: RetryArgs,
+}
+
+impl GossipConfig {
+    /// How often the node checks whether a round is due.
+    fn tick(&self) -> Duration {
+        self.batch_delay
+            .map_or(self.interval, |delay| delay.min(self.interval))
+            .max(Duration::from_millis(1))
+    }
+}
+
+/// A message broadcast in causal mode, with the vector clock of its origin at the time:
+/// the number of messages from each node delivered there, including this one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Stamped {
+    message: Value,
+    origin: String,
+    clock: VectorClock,
+}
+
+impl Stamped {
+    fn id(&self) -> StampId {
+        (self.origin.clone(), self.clock.get(&self.origin))
+    }
+}
+
+/// What a neighbour acknowledged, and what was gossiped to it without an ack yet. Messages
+/// in flight aren't gossiped again until their gossip times out.
+#[derive(Debug)]
+struct Peer {
+    /// Kept as ranges, as most messages are consecutive numbers and a neighbour ends up
+    /// seeing all of them.
+    seen: RangeSet,
+    in_flight: HashSet<u64>,
+    stamped_seen: HashSet<StampId>,
+    stamped_in_flight: HashSet<StampId>,
+    /// Measured from gossip acks, to time out gossip to this neighbour.
+    rtt: RttEstimator,
+    /// Opened by rounds in a row in which gossip to this neighbour timed out.
+    breaker: Option<CircuitBreaker>,
+}
+
+impl Peer {
+    fn new(retry: &RetryArgs) -> Self {
+        Self {
+            seen: RangeSet::new(),
+            in_flight: HashSet::new(),
+            stamped_seen: HashSet::new(),
+            stamped_in_flight: HashSet::new(),
+            rtt: RttEstimator::new(retry.timeout, MIN_GOSSIP_ACK_TIMEOUT, retry.max_timeout),
+            breaker: retry.breaker(),
+        }
+    }
+}
+
+/// In Plumtree mode, a message lazy peers announced but that didn't arrive yet.
+struct Missing {
+    /// When it was announced, or last requested.
+    since: Instant,
+    /// The peers that announced it, in the order they'll be asked for it.
+    announcers: VecDeque<NodeId>,
+}
+
+/// The messages carried by a gossip that wasn't acknowledged yet.
+struct Delta {
+    neighbour: NodeId,
+    messages: Vec<u64>,
+    stamped: Vec<StampId>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -17,11 +258,14 @@ enum Body {
     InitOk {
         msg_id: u64,
         in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
     },
 
+    /// Maelstrom sends integers by default, but any JSON value is accepted.
     Broadcast {
         msg_id: u64,
-        message: u64,
+        message: Value,
     },
 
     BroadcastOk {
@@ -36,7 +280,7 @@ enum Body {
     ReadOk {
         msg_id: u64,
         in_reply_to: u64,
-        messages: HashSet<u64>,
+        messages: Vec<Value>,
     },
 
     Topology {
@@ -51,174 +295,923 @@ enum Body {
 
     Gossip {
         msg_id: u64,
+        /// Compressed once `compressed_gossip` was agreed to at init, if large.
+        #[serde(flatten, with = "distributed_system::compress")]
         messages: HashSet<u64>,
+        /// The messages behind the ids in `messages` that aren't integers.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamped: Vec<Stamped>,
     },
 
+    /// Acknowledges everything the gossip carried. When spreading rumors, also tells which
+    /// of them the receiver already knew.
     GossipOk {
         msg_id: u64,
         in_reply_to: u64,
-        messages: HashSet<u64>,
+        #[serde(
+            default,
+            skip_serializing_if = "HashSet::is_empty",
+            with = "distributed_system::compact"
+        )]
+        known: HashSet<u64>,
     },
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
 
-impl Message {
-    fn process_received_message(&mut self, node: &mut Node, sender: Sender<Event>) -> Option<Self> {
-        let build_message_from = |body: Body| -> Option<Self> {
-            Some(Self {
-                src: self.dest.clone(),
-                dest: self.src.clone(),
-                body,
-            })
-        };
+    /// A summary of the sender's messages, in push-pull mode. Nothing is sent back if the
+    /// receiver's messages match.
+    Digest {
+        msg_id: u64,
+        digest: SetDigest,
+    },
 
-        match &mut self.body {
-            Body::Init {
-                msg_id, node_id, ..
-            } => {
-                node.initialize(node_id.clone(), sender.clone());
+    /// Sent when digests differ: the sender's Bloom filter, and the messages the receiver's
+    /// filter showed it's missing. `respond` asks for the same in return.
+    Reconcile {
+        msg_id: u64,
+        filter: BloomFilter,
+        #[serde(
+            default,
+            skip_serializing_if = "HashSet::is_empty",
+            with = "distributed_system::compact"
+        )]
+        messages: HashSet<u64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<Value>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        respond: bool,
+    },
 
-                build_message_from(Body::InitOk {
-                    msg_id: node.incremented_msg_id(),
-                    in_reply_to: *msg_id,
-                })
-            }
+    /// Asks for the messages the sender's Bloom filter shows it's missing, to answer a read
+    /// with them.
+    Repair {
+        msg_id: u64,
+        filter: BloomFilter,
+    },
 
-            Body::Broadcast { msg_id, message } => {
-                node.messages.insert(*message);
+    RepairOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(
+            default,
+            skip_serializing_if = "HashSet::is_empty",
+            with = "distributed_system::compact"
+        )]
+        messages: HashSet<u64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<Value>,
+    },
 
-                build_message_from(Body::BroadcastOk {
-                    msg_id: node.incremented_msg_id(),
-                    in_reply_to: *msg_id,
-                })
-            }
+    /// Announces messages to a lazy peer in Plumtree mode.
+    IHave {
+        msg_id: u64,
+        #[serde(with = "distributed_system::compact")]
+        messages: HashSet<u64>,
+    },
 
-            Body::Read { msg_id } => build_message_from(Body::ReadOk {
-                msg_id: node.incremented_msg_id(),
-                in_reply_to: *msg_id,
-                messages: node.messages.clone(),
-            }),
+    /// Asks for announced messages that never arrived, and makes the sender an eager peer.
+    Graft {
+        msg_id: u64,
+        #[serde(with = "distributed_system::compact")]
+        messages: HashSet<u64>,
+    },
 
-            Body::Topology { msg_id, topology } => {
-                if let Some(neighbours) = topology.remove(&node.node_id) {
-                    node.neighbours = neighbours;
-                }
+    /// Sent on receiving only duplicates, to make the sender a lazy peer.
+    Prune {
+        msg_id: u64,
+    },
 
-                build_message_from(Body::TopologyOk {
-                    msg_id: node.incremented_msg_id(),
-                    in_reply_to: *msg_id,
-                })
-            }
+    /// Probes a neighbour for the failure detector.
+    Ping {
+        msg_id: u64,
+        seq: u64,
+    },
 
-            Body::Gossip { msg_id, messages } => {
-                node.messages.extend(messages.iter().copied());
+    /// Asks to ping `target` and forward its ack, when it didn't ack a direct ping.
+    PingReq {
+        msg_id: u64,
+        target: String,
+        seq: u64,
+    },
 
-                build_message_from(Body::GossipOk {
-                    msg_id: node.incremented_msg_id(),
-                    in_reply_to: *msg_id,
-                    messages: messages.clone(),
-                })
-            }
+    PingAck {
+        msg_id: u64,
+        seq: u64,
+    },
 
-            Body::GossipOk { messages, .. } => {
-                node.messages_seen_by_others
-                    .entry(self.src.clone())
-                    .or_default()
-                    .extend(messages.iter().copied());
-                None
-            }
+    Error(ErrorBody),
+}
 
-            Body::InitOk { .. }
-            | Body::BroadcastOk { .. }
-            | Body::ReadOk { .. }
-            | Body::TopologyOk { .. } => None,
-        }
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
     }
 }
 
+/// A message to another node, addressed by its interned id.
+struct Outbound {
+    dest: NodeId,
+    body: Body,
+}
+
 enum Event {
     Message(Message),
+    Rejected(Message),
     GossipRequested,
-    ShutdownSignal,
 }
 
 impl Event {
     fn process_received_event(
         &mut self,
         node: &mut Node,
-        sender: &Sender<Event>,
-        mut output: &mut StdoutLock,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut impl Write,
     ) -> Result<(), anyhow::Error> {
         match self {
             Event::Message(message) => {
-                if let Some(reply) = message.process_received_message(node, sender.clone()) {
-                    Node::send(&reply, &mut output)?;
+                if let Some(body) = node.process_received_message(message, sender.clone()) {
+                    message.reply_ref(body).send(&mut output)?;
+                }
+                for reply in std::mem::take(&mut node.repaired_reads) {
+                    reply.send(&mut output)?;
                 }
-                Ok(())
+                let outbox = std::mem::take(&mut node.outbox);
+                node.send_all(outbox, output)?;
+                let gossips = node.flood();
+                node.send_all(gossips, output)?;
+                let pushes = node.eager_push();
+                node.send_all(pushes, output)
             }
 
+            Event::Rejected(error_reply) => error_reply.send(&mut output),
+
             Event::GossipRequested => {
-                for i in 0..node.neighbours.len() {
-                    let new_messages: HashSet<u64> = if let Some(seen_messages) = node
-                        .messages_seen_by_others
-                        .get(&node.neighbours[i].clone())
-                    {
-                        node.messages.difference(seen_messages).cloned().collect()
-                    } else {
-                        node.messages.clone()
-                    };
-
-                    let gossip = Message {
-                        src: node.node_id.clone(),
-                        dest: node.neighbours[i].clone(),
-                        body: Body::Gossip {
-                            msg_id: node.incremented_msg_id(),
-                            messages: new_messages,
-                        },
-                    };
-                    Node::send(&gossip, &mut output)?;
+                let now = node.time.now();
+                let gossips = node.gossip_round(now);
+                node.send_all(gossips, output)?;
+                let digest = node.anti_entropy_round(now);
+                node.send_all(digest, output)?;
+                let probes = node.probe(now);
+                node.send_all(probes, output)?;
+                for reply in node.expire_reads() {
+                    reply.send(&mut output)?;
                 }
-                Ok(())
+                node.send_queued(now, output)
             }
-
-            Event::ShutdownSignal => Ok(()),
         }
     }
 }
 
 struct Node {
     node_id: String,
+    /// Every node id this node came across, which its maps are keyed by.
+    node_ids: NodeIds,
     msg_id: u64,
+    ordering: Ordering,
+    gossip: GossipConfig,
+    /// When the first message not gossiped yet arrived.
+    pending_since: Option<Instant>,
+    last_round: Instant,
+    last_anti_entropy: Instant,
+    /// Ids of the messages, see `Node::id_of`.
     messages: HashSet<u64>,
-    neighbours: Vec<String>,
-    messages_seen_by_others: HashMap<String, HashSet<u64>>,
+    /// The messages that aren't integers, by id.
+    values: HashMap<u64, Value>,
+    /// Messages still spread as rumors, with how many neighbours already knew them.
+    rumors: HashMap<u64, u32>,
+    neighbours: Vec<NodeId>,
+    /// In Plumtree mode, the neighbours that only get announcements. All others are eager.
+    lazy: HashSet<NodeId>,
+    /// New messages to push to eager peers, with the node they came from, unless that's a
+    /// client.
+    fresh: Vec<(u64, Option<NodeId>)>,
+    /// Messages to announce to each lazy peer in the next round.
+    announcements: HashMap<NodeId, HashSet<u64>>,
+    missing: HashMap<u64, Missing>,
+    membership: Option<Membership>,
+    /// Messages sent while handling a message that aren't replies to its sender.
+    outbox: Vec<Outbound>,
+    /// Messages to neighbours held back by `--peer-rate`.
+    outbound: Option<OutboundQueues<NodeId, Body>>,
+    peers: HashMap<NodeId, Peer>,
+    gossips: PendingRequests<Delta>,
+    /// Reads waiting for a neighbour's messages, with the client and the msg_id of the read.
+    reads: PendingRequests<(String, u64)>,
+    /// Replies to reads whose repair completed while handling a message.
+    repaired_reads: Vec<Message>,
+    timer: Option<Timer>,
+    rng: Rng,
+    /// Number of messages from each origin delivered here, in causal mode.
+    clock: VectorClock,
+    delivered: HashMap<StampId, Stamped>,
+    /// Messages waiting for some message they depend on to be delivered.
+    buffered: HashMap<StampId, Stamped>,
+    /// Where the node reads the time.
+    time: Arc<dyn Clock>,
 }
 
 impl Node {
-    fn new() -> Self {
+    fn new(ordering: Ordering, gossip: GossipConfig, time: Arc<dyn Clock>) -> Self {
         Self {
             node_id: String::new(),
+            node_ids: NodeIds::new(),
             msg_id: 0,
+            ordering,
+            gossip,
+            pending_since: None,
+            last_round: time.now(),
+            last_anti_entropy: time.now(),
             messages: HashSet::new(),
+            values: HashMap::new(),
+            rumors: HashMap::new(),
             neighbours: Vec::new(),
-            messages_seen_by_others: HashMap::new(),
+            lazy: HashSet::new(),
+            fresh: Vec::new(),
+            announcements: HashMap::new(),
+            missing: HashMap::new(),
+            outbox: Vec::new(),
+            outbound: gossip
+                .peer_rate
+                .map(|rate| OutboundQueues::new(rate, gossip.peer_burst)),
+            membership: gossip.swim.map(|probe_interval| {
+                Membership::new(SwimConfig {
+                    probe_interval,
+                    ping_timeout: probe_interval / 3,
+                    suspect_timeout: probe_interval * 3,
+                    ..SwimConfig::default()
+                })
+            }),
+            peers: HashMap::new(),
+            gossips: PendingRequests::with_clock(time.clone()),
+            reads: PendingRequests::with_clock(time.clone()),
+            repaired_reads: Vec::new(),
+            timer: None,
+            rng: Rng::from_entropy(),
+            clock: VectorClock::new(),
+            delivered: HashMap::new(),
+            buffered: HashMap::new(),
+            time,
         }
     }
 
-    fn initialize(&mut self, node_id: String, sender: Sender<Event>) {
-        self.node_id = node_id;
+    /// Integers below `HASHED_IDS`, which Maelstrom broadcasts by default, are their own id,
+    /// so that sets of them stay compact. Any other message is identified by the hash of its
+    /// JSON, which is the same on every node since object keys are sorted, and is remembered
+    /// in `values`. Hashed ids are at or above `HASHED_IDS`, so they never stand for an
+    /// integer message too.
+    fn id_of(&mut self, message: Value) -> u64 {
+        match message.as_u64().filter(|&id| id < HASHED_IDS) {
+            Some(id) => id,
+            None => {
+                // Kept below 2^63, which JSON tools that only know signed integers can parse.
+                let id = HASHED_IDS | digest::hash_bytes(message.to_string().as_bytes()) >> 2;
+                self.values.insert(id, message);
+                id
+            }
+        }
+    }
+
+    fn message_of(&self, id: u64) -> Value {
+        match self.values.get(&id) {
+            Some(value) => value.clone(),
+            None => Value::from(id),
+        }
+    }
+
+    /// The values behind the ids among `messages` that aren't integers.
+    fn values_of(&self, messages: &HashSet<u64>) -> Vec<Value> {
+        messages
+            .iter()
+            .filter_map(|id| self.values.get(id).cloned())
+            .collect()
+    }
+
+    fn set_neighbours(&mut self, neighbours: Vec<String>) {
+        if let Some(membership) = &mut self.membership {
+            membership.set_members(neighbours.iter().cloned(), self.time.now());
+        }
+        self.neighbours = neighbours
+            .iter()
+            .map(|neighbour| self.node_ids.intern(neighbour))
+            .collect();
+    }
+
+    /// The neighbours not declared faulty by the failure detector, and whose circuit isn't
+    /// open, unless it's time to probe it.
+    fn available_neighbours(&self) -> Vec<NodeId> {
+        let now = self.time.now();
+        self.neighbours
+            .iter()
+            .copied()
+            .filter(|&neighbour| self.is_available(neighbour))
+            .filter(|neighbour| {
+                self.peers
+                    .get(neighbour)
+                    .and_then(|peer| peer.breaker)
+                    .is_none_or(|breaker| breaker.allows(now))
+            })
+            .collect()
+    }
+
+    fn is_available(&self, neighbour: NodeId) -> bool {
+        self.membership
+            .as_ref()
+            .is_none_or(|membership| membership.is_available(self.node_ids.name(neighbour)))
+    }
+
+    /// Sends messages, turning their ids back into strings without copying them. With
+    /// `--peer-rate`, those to neighbours that got their share are queued instead.
+    fn send_all(
+        &mut self,
+        messages: impl IntoIterator<Item = Outbound>,
+        output: &mut impl Write,
+    ) -> Result<(), anyhow::Error> {
+        let now = self.time.now();
+        for message in messages {
+            let message = match &mut self.outbound {
+                Some(queues) => match queues.push(message.dest, message.body, now) {
+                    Some(body) => Outbound {
+                        dest: message.dest,
+                        body,
+                    },
+                    None => continue,
+                },
+                None => message,
+            };
+            self.write(message, output)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the queued messages the rate allows by now. A gossip's ack is waited for from
+    /// when it's sent, not from when it was queued.
+    fn send_queued(&mut self, now: Instant, output: &mut impl Write) -> Result<(), anyhow::Error> {
+        let Some(queues) = &mut self.outbound else {
+            return Ok(());
+        };
+        for (dest, body) in queues.drain(now) {
+            if let Body::Gossip { msg_id, .. } = body {
+                if let Some(delta) = self.gossips.complete(msg_id) {
+                    self.gossips.insert(msg_id, delta);
+                }
+            }
+            self.write(Outbound { dest, body }, output)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, message: Outbound, output: &mut impl Write) -> Result<(), anyhow::Error> {
+        Outgoing {
+            src: &self.node_id,
+            dest: self.node_ids.name(message.dest),
+            body: message.body,
+        }
+        .send(output)
+    }
+    /// Runs the failure detector and turns what it sends into messages.
+    fn probe(&mut self, now: Instant) -> Vec<Outbound> {
+        let Some(membership) = &mut self.membership else {
+            return Vec::new();
+        };
+        let output = membership.tick(now, &mut self.rng);
+        log_membership_events(&output.events);
+        self.swim_messages(output.messages)
+    }
+
+    /// Passes a failure detector message from a neighbour to it. It sends at most one message
+    /// in return, which goes to another node when relaying a ping or its ack.
+    fn receive_swim(&mut self, from: &str, message: SwimMessage) -> Option<Outbound> {
+        let membership = self.membership.as_mut()?;
+        let output = membership.receive(from, message, self.time.now());
+        log_membership_events(&output.events);
+        self.swim_messages(output.messages).pop()
+    }
+
+    fn swim_messages(&mut self, messages: Vec<(String, SwimMessage)>) -> Vec<Outbound> {
+        messages
+            .into_iter()
+            .map(|(dest, message)| {
+                let msg_id = self.incremented_msg_id();
+                let body = match message {
+                    SwimMessage::Ping { seq } => Body::Ping { msg_id, seq },
+                    SwimMessage::PingReq { target, seq } => Body::PingReq {
+                        msg_id,
+                        target,
+                        seq,
+                    },
+                    SwimMessage::Ack { seq } => Body::PingAck { msg_id, seq },
+                };
+                Outbound {
+                    dest: self.node_ids.intern(&dest),
+                    body,
+                }
+            })
+            .collect()
+    }
+
+    fn mark_pending(&mut self) {
+        self.pending_since.get_or_insert(self.time.now());
+    }
+
+    /// Adds a message, which becomes a hot rumor if rumors are spread. Returns whether it
+    /// is new.
+    fn insert_message(&mut self, message: u64) -> bool {
+        if !self.messages.insert(message) {
+            return false;
+        }
+        if self.gossip.rumor_stop.is_some() {
+            self.rumors.insert(message, 0);
+        }
+        self.mark_pending();
+        true
+    }
+
+    /// Gossips to the round's neighbours once the interval passed, or earlier once the batch
+    /// delay passed since new messages arrived. Neighbours with nothing new are skipped.
+    fn gossip_round(&mut self, now: Instant) -> Vec<Outbound> {
+        let batch_due = self
+            .gossip
+            .batch_delay
+            .zip(self.pending_since)
+            .is_some_and(|(delay, since)| now >= since + delay);
+        // Half a tick of slack keeps a late tick from pushing the round to the next one.
+        let interval_due = now + self.gossip.tick() / 2 >= self.last_round + self.gossip.interval;
+        if !batch_due && !interval_due {
+            return Vec::new();
+        }
+        self.pending_since = None;
+        self.last_round = now;
 
-        // TODO: shutdown signal
-        std::thread::spawn(move || loop {
-            std::thread::sleep(std::time::Duration::from_millis(150));
-            let _ = sender.send(Event::GossipRequested);
+        let peers = &self.peers;
+        let expired = self.gossips.expire_with(|delta| {
+            peers
+                .get(&delta.neighbour)
+                .map_or(self.gossip.retry.timeout, |peer| peer.rtt.rto())
         });
+        let mut timed_out = HashSet::new();
+        for (_, delta) in expired {
+            metrics::increment(metrics::RETRIES);
+            let peer = self
+                .peers
+                .entry(delta.neighbour)
+                .or_insert_with(|| Peer::new(&self.gossip.retry));
+            for message in delta.messages {
+                peer.in_flight.remove(&message);
+            }
+            for id in delta.stamped {
+                peer.stamped_in_flight.remove(&id);
+            }
+            timed_out.insert(delta.neighbour);
+        }
+        // Once per round, however many gossips to the neighbour timed out.
+        for neighbour in timed_out {
+            let peer = self
+                .peers
+                .entry(neighbour)
+                .or_insert_with(|| Peer::new(&self.gossip.retry));
+            peer.rtt.back_off();
+            if peer
+                .breaker
+                .as_mut()
+                .is_some_and(|breaker| breaker.failure(now))
+            {
+                log::warn!(
+                    "Opened the circuit to {}, which stopped acknowledging gossip",
+                    self.node_ids.name(neighbour)
+                );
+            }
+        }
+
+        if self.gossip.plumtree {
+            return self.plumtree_round(now);
+        }
+        let neighbours = self.round_neighbours();
+        if self.gossip.rumor_stop.is_some() {
+            return self.spread_rumors(neighbours);
+        }
+        self.gossip_to(neighbours)
+    }
+
+    /// Gossips every hot rumor to each of the round's neighbours, whether it's known to have
+    /// them or not, as the replies are what retires them.
+    fn spread_rumors(&mut self, neighbours: Vec<NodeId>) -> Vec<Outbound> {
+        if self.rumors.is_empty() {
+            return Vec::new();
+        }
+
+        let messages: HashSet<u64> = self.rumors.keys().copied().collect();
+        let values = self.values_of(&messages);
+        neighbours
+            .into_iter()
+            .map(|neighbour| Outbound {
+                dest: neighbour,
+                body: Body::Gossip {
+                    msg_id: self.incremented_msg_id(),
+                    messages: messages.clone(),
+                    values: values.clone(),
+                    stamped: Vec::new(),
+                },
+            })
+            .collect()
+    }
+
+    /// In Plumtree mode, pushes new messages to the eager peers, except the one they came
+    /// from, and queues them to be announced to the lazy ones.
+    fn eager_push(&mut self) -> Vec<Outbound> {
+        let mut pushes: HashMap<NodeId, HashSet<u64>> = HashMap::new();
+        let neighbours = self.available_neighbours();
+        for (message, from) in std::mem::take(&mut self.fresh) {
+            for &neighbour in neighbours
+                .iter()
+                .filter(|&&neighbour| Some(neighbour) != from)
+            {
+                let queue = if self.lazy.contains(&neighbour) {
+                    self.announcements.entry(neighbour).or_default()
+                } else {
+                    pushes.entry(neighbour).or_default()
+                };
+                queue.insert(message);
+            }
+        }
+
+        pushes
+            .into_iter()
+            .map(|(neighbour, messages)| Outbound {
+                dest: neighbour,
+                body: Body::Gossip {
+                    msg_id: self.incremented_msg_id(),
+                    values: self.values_of(&messages),
+                    messages,
+                    stamped: Vec::new(),
+                },
+            })
+            .collect()
+    }
+
+    /// Sends the queued announcements, and grafts the peer that announced a message which
+    /// still didn't arrive, trying the next announcer if that doesn't help either.
+    fn plumtree_round(&mut self, now: Instant) -> Vec<Outbound> {
+        let mut messages: Vec<Outbound> = std::mem::take(&mut self.announcements)
+            .into_iter()
+            .map(|(neighbour, messages)| Outbound {
+                dest: neighbour,
+                body: Body::IHave {
+                    msg_id: self.incremented_msg_id(),
+                    messages,
+                },
+            })
+            .collect();
+
+        // Messages can also arrive through anti-entropy, which doesn't go through
+        // `receive_pushed`.
+        self.missing
+            .retain(|message, _| !self.messages.contains(message));
+        let mut grafts: HashMap<NodeId, HashSet<u64>> = HashMap::new();
+        for (&message, missing) in &mut self.missing {
+            if now < missing.since + GRAFT_TIMEOUT {
+                continue;
+            }
+            if let Some(announcer) = missing.announcers.pop_front() {
+                grafts.entry(announcer).or_default().insert(message);
+                missing.announcers.push_back(announcer);
+                missing.since = now;
+            }
+        }
+        for (neighbour, requested) in grafts {
+            self.lazy.remove(&neighbour);
+            messages.push(Outbound {
+                dest: neighbour,
+                body: Body::Graft {
+                    msg_id: self.incremented_msg_id(),
+                    messages: requested,
+                },
+            });
+        }
+        messages
+    }
+
+    /// In Plumtree mode, takes in gossip from a neighbour. It stays an eager peer if it sent
+    /// anything new, and is pruned otherwise, as the messages reached this node another way.
+    fn receive_pushed(
+        &mut self,
+        neighbour: NodeId,
+        messages: &HashSet<u64>,
+        values: Vec<Value>,
+    ) -> Option<Body> {
+        let fresh: Vec<u64> = messages.difference(&self.messages).copied().collect();
+        self.receive_messages(neighbour, messages, values);
+        for &message in &fresh {
+            self.missing.remove(&message);
+            self.fresh.push((message, Some(neighbour)));
+        }
+
+        if !fresh.is_empty() || messages.is_empty() {
+            self.lazy.remove(&neighbour);
+            return None;
+        }
+        self.lazy.insert(neighbour);
+        Some(Body::Prune {
+            msg_id: self.incremented_msg_id(),
+        })
+    }
+
+    /// Counts the rumors a neighbour already knew, retiring those known often enough.
+    fn retire_rumors(&mut self, known: &HashSet<u64>) {
+        let Some(stop) = self.gossip.rumor_stop else {
+            return;
+        };
+        for message in known {
+            if let Some(count) = self.rumors.get_mut(message) {
+                *count += 1;
+                if *count >= stop {
+                    self.rumors.remove(message);
+                }
+            }
+        }
+    }
+
+    /// In push-pull mode, sends the digest of this node's messages to a random neighbour once
+    /// per anti-entropy interval.
+    fn anti_entropy_round(&mut self, now: Instant) -> Option<Outbound> {
+        let interval = self.gossip.anti_entropy?;
+        let neighbours = self.available_neighbours();
+        if neighbours.is_empty() || now + self.gossip.tick() / 2 < self.last_anti_entropy + interval
+        {
+            return None;
+        }
+        self.last_anti_entropy = now;
+
+        let index = self.rng.below(neighbours.len());
+        Some(Outbound {
+            dest: neighbours[index],
+            body: Body::Digest {
+                msg_id: self.incremented_msg_id(),
+                digest: SetDigest::of(&self.messages),
+            },
+        })
+    }
+
+    /// Sends a random neighbour this node's Bloom filter, to answer the read `in_reply_to`
+    /// of `client` once the neighbour sent what it's missing. Without neighbours to ask, the
+    /// read is answered right away.
+    fn repair_read(&mut self, client: &str, in_reply_to: u64) -> Option<Body> {
+        let neighbours = self.available_neighbours();
+        if neighbours.is_empty() {
+            return Some(self.read_ok(in_reply_to));
+        }
+        let index = self.rng.below(neighbours.len());
+        let msg_id = self.incremented_msg_id();
+        self.reads.insert(msg_id, (client.to_string(), in_reply_to));
+        let filter = self.bloom_filter();
+        self.outbox.push(Outbound {
+            dest: neighbours[index],
+            body: Body::Repair { msg_id, filter },
+        });
+        None
+    }
+
+    fn read_ok(&mut self, in_reply_to: u64) -> Body {
+        Body::ReadOk {
+            msg_id: self.incremented_msg_id(),
+            in_reply_to,
+            messages: self
+                .messages
+                .iter()
+                .map(|&id| self.message_of(id))
+                .collect(),
+        }
+    }
+
+    /// Answers the reads whose neighbour didn't send its messages in time with those of
+    /// this node.
+    fn expire_reads(&mut self) -> Vec<Message> {
+        self.reads
+            .expire(READ_REPAIR_TIMEOUT)
+            .into_iter()
+            .map(|(_, (client, in_reply_to))| Message {
+                src: self.node_id.clone(),
+                dest: client,
+                body: self.read_ok(in_reply_to),
+            })
+            .collect()
+    }
+
+    /// A Bloom filter of this node's messages, with a fresh seed so that false positives of
+    /// earlier exchanges don't repeat.
+    fn bloom_filter(&mut self) -> BloomFilter {
+        let seed = self.rng.next_u64();
+        BloomFilter::new(self.messages.iter(), seed)
+    }
+
+    /// Takes in messages a neighbour sent, which it obviously has itself.
+    fn receive_messages(&mut self, neighbour: NodeId, messages: &HashSet<u64>, values: Vec<Value>) {
+        for value in values {
+            self.id_of(value);
+        }
+        for &message in messages {
+            self.insert_message(message);
+        }
+        self.peers
+            .entry(neighbour)
+            .or_insert_with(|| Peer::new(&self.gossip.retry))
+            .seen
+            .extend(messages.iter().copied());
+    }
+
+    /// This node's messages a neighbour's filter shows it's missing, marked as known by it.
+    fn missing_from(&mut self, neighbour: NodeId, filter: &BloomFilter) -> HashSet<u64> {
+        let missing: HashSet<u64> = self
+            .messages
+            .iter()
+            .filter(|&&message| !filter.contains(message))
+            .copied()
+            .collect();
+        self.peers
+            .entry(neighbour)
+            .or_insert_with(|| Peer::new(&self.gossip.retry))
+            .seen
+            .extend(missing.iter().copied());
+        missing
+    }
+
+    /// In flood mode, gossips newly arrived messages to every neighbour right away, instead
+    /// of at the next round. Neighbours that already have them, like the one they came from,
+    /// are skipped.
+    fn flood(&mut self) -> Vec<Outbound> {
+        if !self.gossip.flood || self.pending_since.is_none() {
+            return Vec::new();
+        }
+
+        self.pending_since = None;
+        self.gossip_to(self.available_neighbours())
+    }
+
+    /// Gossips to each neighbour whatever it neither has nor has in flight. Neighbours with
+    /// messages still queued are skipped, so that retries pile up in a single gossip rather
+    /// than in the queue.
+    fn gossip_to(&mut self, neighbours: Vec<NodeId>) -> Vec<Outbound> {
+        let mut gossips = Vec::new();
+        for neighbour in neighbours {
+            if self
+                .outbound
+                .as_ref()
+                .is_some_and(|queues| queues.queued(&neighbour) > 0)
+            {
+                continue;
+            }
+            let (messages, stamped) = match self.ordering {
+                Ordering::Eventual => (self.new_messages_for(neighbour), Vec::new()),
+                Ordering::Causal => (HashSet::new(), self.new_stamped_for(neighbour)),
+            };
+            if messages.is_empty() && stamped.is_empty() {
+                continue;
+            }
+            log::debug!(
+                "Gossiping {} messages to {}",
+                messages.len() + stamped.len(),
+                self.node_ids.name(neighbour)
+            );
+
+            let msg_id = self.incremented_msg_id();
+            let delta = Delta {
+                neighbour,
+                messages: messages.iter().copied().collect(),
+                stamped: stamped.iter().map(Stamped::id).collect(),
+            };
+            let peer = self
+                .peers
+                .entry(neighbour)
+                .or_insert_with(|| Peer::new(&self.gossip.retry));
+            if let Some(breaker) = &mut peer.breaker {
+                breaker.probed(self.time.now());
+            }
+            if self.gossip.anti_entropy.is_some() {
+                peer.seen.extend(delta.messages);
+            } else {
+                peer.in_flight.extend(delta.messages.iter().copied());
+                peer.stamped_in_flight.extend(delta.stamped.iter().cloned());
+                self.gossips.insert(msg_id, delta);
+            }
+
+            gossips.push(Outbound {
+                dest: neighbour,
+                body: Body::Gossip {
+                    msg_id,
+                    values: self.values_of(&messages),
+                    messages,
+                    stamped,
+                },
+            });
+        }
+        gossips
+    }
+
+    /// `fanout` neighbours sampled at random, or all of them.
+    fn round_neighbours(&mut self) -> Vec<NodeId> {
+        let neighbours = self.available_neighbours();
+        match self.gossip.fanout {
+            Some(fanout) => self.rng.sample(&neighbours, fanout),
+            None => neighbours,
+        }
+    }
+
+    fn new_messages_for(&self, neighbour: NodeId) -> HashSet<u64> {
+        match self.peers.get(&neighbour) {
+            Some(peer) => self
+                .messages
+                .iter()
+                .filter(|&&message| {
+                    !peer.seen.contains(message) && !peer.in_flight.contains(&message)
+                })
+                .copied()
+                .collect(),
+            None => self.messages.clone(),
+        }
+    }
+
+    /// Only delivered messages are gossiped, so a neighbour receives a message's
+    /// dependencies no later than the message itself.
+    fn new_stamped_for(&self, neighbour: NodeId) -> Vec<Stamped> {
+        let peer = self.peers.get(&neighbour);
+        self.delivered
+            .iter()
+            .filter(|(id, _)| {
+                peer.is_none_or(|peer| {
+                    !peer.stamped_seen.contains(*id) && !peer.stamped_in_flight.contains(*id)
+                })
+            })
+            .map(|(_, stamped)| stamped.clone())
+            .collect()
+    }
+
+    /// Stamps a message from a client with this node's clock and delivers it.
+    fn broadcast_causally(&mut self, message: Value) {
+        let mut clock = self.clock.clone();
+        clock.increment(&self.node_id);
+
+        self.deliver(Stamped {
+            message,
+            origin: self.node_id.clone(),
+            clock,
+        });
+    }
+
+    /// Buffers the gossiped messages and delivers every one whose dependencies are met.
+    fn receive_stamped(&mut self, stamped: Vec<Stamped>) {
+        for stamped in stamped {
+            let id = stamped.id();
+            if !self.delivered.contains_key(&id) {
+                self.buffered.insert(id, stamped);
+            }
+        }
+
+        while let Some(id) = self
+            .buffered
+            .iter()
+            .find(|(_, stamped)| self.is_deliverable(stamped))
+            .map(|(id, _)| id.clone())
+        {
+            if let Some(stamped) = self.buffered.remove(&id) {
+                self.deliver(stamped);
+            }
+        }
+    }
+
+    /// A message is deliverable once it is the next one from its origin, and every message
+    /// from other nodes its origin had delivered before sending it is delivered here.
+    fn is_deliverable(&self, stamped: &Stamped) -> bool {
+        stamped.clock.iter().all(|(node, count)| {
+            if node == stamped.origin {
+                count == self.clock.get(node) + 1
+            } else {
+                count <= self.clock.get(node)
+            }
+        })
+    }
+
+    fn deliver(&mut self, stamped: Stamped) {
+        let id = stamped.id();
+        self.clock.increment(&stamped.origin);
+        let message = self.id_of(stamped.message.clone());
+        self.messages.insert(message);
+        self.delivered.insert(id, stamped);
+    }
+
+    fn initialize(&mut self, node_id: String, node_ids: &[String]) {
+        self.rng = Rng::for_node(self.gossip.seed, &node_id);
+        if let Some(neighbours) = self.gossip.topology.neighbours(&node_id, node_ids) {
+            self.set_neighbours(neighbours);
+        }
+        self.node_id = node_id;
+        self.node_ids = node_ids.iter().collect();
+    }
+
+    /// Starts the gossip rounds, which `sender` queues as events.
+    fn start_timer(&mut self, sender: EventSender<Input<Event>>) {
+        let tick = self.gossip.tick();
+        self.timer = Some(Timer::start_on(self.time.as_ref(), tick, sender, || {
+            Input::Event(Event::GossipRequested)
+        }));
     }
 
     fn incremented_msg_id(&mut self) -> u64 {
@@ -226,46 +1219,530 @@ impl Node {
         self.msg_id
     }
 
-    fn send<W: Write>(msg: &Message, writer: &mut W) -> Result<(), anyhow::Error> {
-        serde_json::to_writer(&mut *writer, &msg).context("Failed to serialize reply message")?;
-        writer.write_all(b"\n").context("Failed to write newline")?;
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Option<Body> {
+        if let Some(membership) = &mut self.membership {
+            let event = membership.observe(&message.src, self.time.now());
+            log_membership_events(event.as_slice());
+        }
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids);
+                self.start_timer(sender);
+
+                Some(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                })
+            }
+
+            Body::Broadcast {
+                msg_id,
+                message: value,
+            } => {
+                match self.ordering {
+                    Ordering::Eventual => {
+                        let id = self.id_of(std::mem::take(value));
+                        if self.insert_message(id) && self.gossip.plumtree {
+                            let from = self.node_ids.get(&message.src);
+                            self.fresh.push((id, from));
+                        }
+                    }
+                    Ordering::Causal => {
+                        self.broadcast_causally(std::mem::take(value));
+                        self.mark_pending();
+                    }
+                }
+
+                Some(Body::BroadcastOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                })
+            }
+
+            Body::Read { msg_id } if self.gossip.read_repair => {
+                self.repair_read(&message.src, *msg_id)
+            }
+
+            Body::Read { msg_id } => Some(self.read_ok(*msg_id)),
+
+            Body::Topology { msg_id, topology } => {
+                if self.gossip.topology == Topology::Maelstrom {
+                    if let Some(neighbours) = topology.remove(&self.node_id) {
+                        self.set_neighbours(neighbours);
+                    }
+                }
+
+                Some(Body::TopologyOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                })
+            }
+
+            Body::Gossip {
+                msg_id,
+                messages,
+                values,
+                stamped,
+            } => {
+                let neighbour = self.node_ids.intern(&message.src);
+                if self.gossip.plumtree {
+                    return self.receive_pushed(neighbour, messages, std::mem::take(values));
+                }
+                let known: HashSet<u64> = match self.gossip.rumor_stop {
+                    Some(_) => messages.intersection(&self.messages).copied().collect(),
+                    None => HashSet::new(),
+                };
+                self.receive_messages(neighbour, messages, std::mem::take(values));
+                let stamped = std::mem::take(stamped);
+                self.peers
+                    .entry(neighbour)
+                    .or_insert_with(|| Peer::new(&self.gossip.retry))
+                    .stamped_seen
+                    .extend(stamped.iter().map(Stamped::id));
+                self.receive_stamped(stamped);
+
+                if self.gossip.anti_entropy.is_some() && self.gossip.rumor_stop.is_none() {
+                    return None;
+                }
+                Some(Body::GossipOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    known,
+                })
+            }
+
+            Body::GossipOk {
+                in_reply_to, known, ..
+            } => {
+                self.retire_rumors(known);
+                if let Some((delta, rtt)) = self.gossips.complete_timed(*in_reply_to) {
+                    let peer = self
+                        .peers
+                        .entry(delta.neighbour)
+                        .or_insert_with(|| Peer::new(&self.gossip.retry));
+                    // Every resend is a new gossip, so the ack can't be for an earlier one.
+                    peer.rtt.sample(rtt);
+                    if peer.breaker.as_mut().is_some_and(CircuitBreaker::success) {
+                        log::info!(
+                            "Closed the circuit to {}",
+                            self.node_ids.name(delta.neighbour)
+                        );
+                    }
+                    for message in delta.messages {
+                        peer.in_flight.remove(&message);
+                        peer.seen.insert(message);
+                    }
+                    for id in delta.stamped {
+                        peer.stamped_in_flight.remove(&id);
+                        peer.stamped_seen.insert(id);
+                    }
+                }
+                None
+            }
+
+            Body::Digest { digest, .. } => {
+                if *digest == SetDigest::of(&self.messages) {
+                    return None;
+                }
+
+                let msg_id = self.incremented_msg_id();
+                Some(Body::Reconcile {
+                    msg_id,
+                    filter: self.bloom_filter(),
+                    messages: HashSet::new(),
+                    values: Vec::new(),
+                    respond: true,
+                })
+            }
+
+            Body::Reconcile {
+                filter,
+                messages,
+                values,
+                respond,
+                ..
+            } => {
+                let neighbour = self.node_ids.intern(&message.src);
+                let missing = self.missing_from(neighbour, filter);
+                self.receive_messages(neighbour, messages, std::mem::take(values));
+                let msg_id = self.incremented_msg_id();
+                let values = self.values_of(&missing);
+
+                if *respond {
+                    Some(Body::Reconcile {
+                        msg_id,
+                        filter: self.bloom_filter(),
+                        messages: missing,
+                        values,
+                        respond: false,
+                    })
+                } else if !missing.is_empty() {
+                    Some(Body::Gossip {
+                        msg_id,
+                        messages: missing,
+                        values,
+                        stamped: Vec::new(),
+                    })
+                } else {
+                    None
+                }
+            }
+
+            Body::Repair { msg_id, filter } => {
+                let neighbour = self.node_ids.intern(&message.src);
+                let missing = self.missing_from(neighbour, filter);
+                Some(Body::RepairOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    values: self.values_of(&missing),
+                    messages: missing,
+                })
+            }
+
+            Body::RepairOk {
+                in_reply_to,
+                messages,
+                values,
+                ..
+            } => {
+                let neighbour = self.node_ids.intern(&message.src);
+                self.receive_messages(neighbour, messages, std::mem::take(values));
+                if let Some((client, in_reply_to)) = self.reads.complete(*in_reply_to) {
+                    let body = self.read_ok(in_reply_to);
+                    self.repaired_reads.push(Message {
+                        src: self.node_id.clone(),
+                        dest: client,
+                        body,
+                    });
+                }
+                None
+            }
+
+            Body::IHave { messages, .. } => {
+                let announcer = self.node_ids.intern(&message.src);
+                let now = self.time.now();
+                for &announced in messages.difference(&self.messages) {
+                    let missing = self.missing.entry(announced).or_insert_with(|| Missing {
+                        since: now,
+                        announcers: VecDeque::new(),
+                    });
+                    if !missing.announcers.contains(&announcer) {
+                        missing.announcers.push_back(announcer);
+                    }
+                }
+                None
+            }
+
+            Body::Graft { messages, .. } => {
+                let neighbour = self.node_ids.intern(&message.src);
+                self.lazy.remove(&neighbour);
+                let requested: HashSet<u64> =
+                    messages.intersection(&self.messages).copied().collect();
+                if requested.is_empty() {
+                    return None;
+                }
+
+                let msg_id = self.incremented_msg_id();
+                Some(Body::Gossip {
+                    msg_id,
+                    values: self.values_of(&requested),
+                    messages: requested,
+                    stamped: Vec::new(),
+                })
+            }
+
+            Body::Prune { .. } => {
+                let neighbour = self.node_ids.intern(&message.src);
+                self.lazy.insert(neighbour);
+                None
+            }
+
+            Body::Ping { seq, .. } => {
+                let ack = self.receive_swim(&message.src, SwimMessage::Ping { seq: *seq });
+                self.outbox.extend(ack);
+                None
+            }
+
+            Body::PingReq { target, seq, .. } => {
+                let request = SwimMessage::PingReq {
+                    target: std::mem::take(target),
+                    seq: *seq,
+                };
+                let ping = self.receive_swim(&message.src, request);
+                self.outbox.extend(ping);
+                None
+            }
+
+            Body::PingAck { seq, .. } => {
+                let relayed = self.receive_swim(&message.src, SwimMessage::Ack { seq: *seq });
+                self.outbox.extend(relayed);
+                None
+            }
+
+            Body::InitOk { msg_id, .. }
+            | Body::BroadcastOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. }
+            | Body::TopologyOk { msg_id, .. } => Some(Body::Error(ErrorBody::new(
+                *msg_id,
+                ErrorCode::NotSupported,
+                "Broadcast node does not accept client replies",
+            ))),
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+                None
+            }
+        }
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
         Ok(())
     }
+
+    fn debug_state(&self) -> Value {
+        let name = |&id: &NodeId| self.node_ids.name(id);
+        let in_flight: HashMap<&str, usize> = self
+            .peers
+            .iter()
+            .map(|(&id, peer)| {
+                let count = peer.in_flight.len() + peer.stamped_in_flight.len();
+                (self.node_ids.name(id), count)
+            })
+            .collect();
+        json!({
+            "node_id": self.node_id,
+            "messages": self.messages.len(),
+            "neighbours": self.neighbours.iter().map(name).collect::<Vec<_>>(),
+            "lazy": self.lazy.iter().map(name).collect::<Vec<_>>(),
+            "unacknowledged_gossips": self.gossips.len(),
+            "pending_reads": self.reads.len(),
+            "open_circuits": self
+                .peers
+                .values()
+                .filter(|peer| peer.breaker.is_some_and(|breaker| breaker.is_open()))
+                .count(),
+            "queued": self.outbound.as_ref().map_or(0, OutboundQueues::len),
+            "in_flight": in_flight,
+            "rumors": self.rumors.len(),
+            "missing": self.missing.len(),
+            "delivered": self.delivered.len(),
+            "buffered": self.buffered.len(),
+        })
+    }
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    let (sender, receiver) = std::sync::mpsc::channel();
-    let sender_clone = sender.clone();
-    let mut stdout = std::io::stdout().lock();
-    let mut node = Node::new();
+fn log_membership_events(events: &[MembershipEvent]) {
+    for event in events {
+        log::info!("Membership changed: {event:?}");
+    }
+}
 
-    let join_handle = std::thread::spawn(move || {
-        let stdin = std::io::stdin().lock();
-        let mut stdin = stdin.lines();
+/// Runs a node of the broadcast workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// In which order messages become visible to `read`: eventual or causal.
+    #[arg(long, env = "BROADCAST_ORDERING", default_value = "eventual")]
+    ordering: Ordering,
+    /// What runs the node: threads, or async, the tokio runtime of the `tokio` feature.
+    #[arg(long, env = "BROADCAST_RUNTIME", default_value = "threads")]
+    runtime: NodeRuntime,
+    #[command(flatten)]
+    gossip: GossipConfig,
+}
 
-        while let Ok(line) = stdin
-            .next()
-            .context("Maelstrom should provide input to STDIN.")?
-        {
-            let msg: Message = serde_json::from_str(&line)
-                .context("Failed to deserialize provided input to STDIN.")?;
+pub fn main() -> Result<(), anyhow::Error> {
+    run(config::parse("broadcast")?)
+}
+
+/// A node with the options of `cli`, unless they don't go together.
+fn build(cli: Cli, time: Arc<dyn Clock>) -> Result<Node, anyhow::Error> {
+    let Cli {
+        ordering, gossip, ..
+    } = cli;
+    if ordering == Ordering::Causal && gossip.anti_entropy.is_some() {
+        anyhow::bail!("Push-pull anti-entropy is not supported with causal ordering");
+    }
+    if ordering == Ordering::Causal && gossip.read_repair {
+        anyhow::bail!("Read repair is not supported with causal ordering");
+    }
+    if gossip.rumor_stop.is_some() && (ordering == Ordering::Causal || gossip.flood) {
+        anyhow::bail!("Rumor mongering is not supported with causal ordering or flooding");
+    }
+    if gossip.plumtree
+        && (ordering == Ordering::Causal || gossip.flood || gossip.rumor_stop.is_some())
+    {
+        anyhow::bail!("Plumtree is not supported with causal ordering, flooding, or rumors");
+    }
+    if gossip
+        .peer_rate
+        .is_some_and(|rate| !rate.is_finite() || rate <= 0.0)
+    {
+        anyhow::bail!("The peer rate must be a positive number of messages a second");
+    }
+    Ok(Node::new(ordering, gossip, time))
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn parse_event(line: &[u8]) -> Result<Event, anyhow::Error> {
+    let event =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(msg) => Event::Message(msg),
+            Err(error_reply) => Event::Rejected(error_reply),
+        };
+    Ok(event)
+}
+
+/// Makes the node able to agree to the extensions of the protocol it speaks.
+pub fn support_extensions() {
+    protocol::support(compress::CAPABILITY, 1);
+}
+
+pub fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    cli.node.apply()?;
+    support_extensions();
+    if cli.runtime == NodeRuntime::Async {
+        return run_async(cli);
+    }
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = build(cli, Arc::new(SystemClock))?;
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), parse_event);
+
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
+
+#[cfg(not(feature = "tokio"))]
+fn run_async(_cli: Cli) -> Result<(), anyhow::Error> {
+    anyhow::bail!("The async runtime needs the tokio feature");
+}
+
+/// Runs the node on the async runtime.
+#[cfg(feature = "tokio")]
+fn run_async(cli: Cli) -> Result<(), anyhow::Error> {
+    let node = build(cli, Arc::new(SystemClock))?;
+    async_runtime::run(|handle: &async_runtime::Node<Body>| Ok(AsyncNode::start(node, handle)))
+}
 
-            if sender_clone.send(Event::Message(msg)).is_err() {
-                return Ok::<_, anyhow::Error>(());
+/// A broadcast node on the async runtime. Messages are handled by the same `Node` as on
+/// threads, one at a time, and its gossip rounds run in a task instead of on a timer thread.
+#[cfg(feature = "tokio")]
+struct AsyncNode {
+    node: Arc<Mutex<Node>>,
+    /// Handed to the node with every event. It's only used to start the timer at `init`,
+    /// which the runtime answers instead.
+    sender: EventSender<Input<Event>>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncNode {
+    fn start(mut node: Node, handle: &async_runtime::Node<Body>) -> Self {
+        node.initialize(handle.id().to_string(), handle.node_ids());
+        // Carries on after the msg_id of the `init_ok` the runtime sent.
+        node.msg_id = handle.next_msg_id();
+        let tick = node.gossip.tick();
+        let this = Self {
+            node: Arc::new(Mutex::new(node)),
+            sender: runtime::event_channel().0,
+        };
+
+        let rounds = Self {
+            node: this.node.clone(),
+            sender: this.sender.clone(),
+        };
+        let output = handle.clone();
+        handle.spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                output.send_lines(&rounds.process(Event::GossipRequested)?)?;
             }
-        }
-        Ok(())
-    });
+        });
+        this
+    }
 
-    for mut event in receiver {
-        event.process_received_event(&mut node, &sender, &mut stdout)?
+    /// Lets the node process `event`, and returns the lines it sent.
+    fn process(&self, mut event: Event) -> Result<Vec<u8>, anyhow::Error> {
+        let mut output = Vec::new();
+        let mut node = self.node.lock().unwrap();
+        event.process_received_event(&mut node, &self.sender, &mut output)?;
+        Ok(output)
     }
+}
 
-    sender.send(Event::ShutdownSignal)?;
+#[cfg(feature = "tokio")]
+impl async_runtime::Handler for AsyncNode {
+    type Body = Body;
 
-    join_handle
-        .join()
-        .map_err(|e| anyhow::anyhow!("Thread panicked: {:?}", e))??;
+    async fn handle(
+        self: Arc<Self>,
+        handle: async_runtime::Node<Body>,
+        message: Message,
+    ) -> Result<(), anyhow::Error> {
+        handle.send_lines(&self.process(Event::Message(message))?)
+    }
 
-    Ok(())
+    fn debug_state(&self) -> Value {
+        self.node.lock().unwrap().debug_state()
+    }
+}
+
+/// A broadcast node for a `sim::Network`, which reads the time from `time` and schedules
+/// its gossip rounds on it.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Result<Box<dyn SimNode>, anyhow::Error> {
+    let (sender, receiver) = runtime::event_channel();
+    Ok(Box::new(Simulated {
+        node: build(cli, time)?,
+        sender,
+        receiver,
+    }))
+}
+
+struct Simulated {
+    node: Node,
+    sender: EventSender<Input<Event>>,
+    receiver: EventReceiver<Input<Event>>,
+}
+
+impl SimNode for Simulated {
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        parse_event(line.as_bytes())?.process_received_event(&mut self.node, &self.sender, output)
+    }
+
+    /// Processes the ticks of the node's timer.
+    fn tick(&mut self, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        while let Ok(input) = self.receiver.try_recv() {
+            if let Input::Event(mut event) = input {
+                event.process_received_event(&mut self.node, &self.sender, output)?;
+            }
+        }
+        Ok(())
+    }
 }
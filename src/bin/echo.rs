@@ -1,7 +1,17 @@
 use std::io::{BufRead, Write};
 
 use anyhow::{bail, Context};
+use clap::Parser;
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime::{self, Lifecycle, Output};
+use distributed_system::sim::{self, SimNode};
+use distributed_system::transport;
+use distributed_system::ErrorBody;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Init {
@@ -14,6 +24,8 @@ struct Init {
 struct InitOk {
     msg_id: u64,
     in_reply_to: u64,
+    #[serde(flatten)]
+    handshake: Handshake,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,44 +49,16 @@ enum Body {
     InitOk(InitOk),
     Echo(Echo),
     EchoOk(EchoOk),
+    Error(ErrorBody),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
-
-impl Message {
-    fn prepare_reply(&self, msg_id: u64) -> Option<Self> {
-        let body: Option<Body> = match &self.body {
-            Body::Init(init_body) => Some(Body::InitOk(InitOk {
-                msg_id,
-                in_reply_to: init_body.msg_id,
-            })),
-
-            Body::Echo(echo_body) => Some(Body::EchoOk(EchoOk {
-                msg_id,
-                in_reply_to: echo_body.msg_id,
-                echo: echo_body.echo.clone(),
-            })),
-
-            Body::InitOk(_) | Body::EchoOk(_) => None,
-        };
-
-        let body = body?;
-
-        Some(Self {
-            src: self.dest.clone(),
-            dest: self.src.clone(),
-            body,
-        })
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
     }
 }
 
 struct EchoServer {
-    #[allow(dead_code)]
     node_id: String,
     msg_id: u64,
 }
@@ -89,50 +73,128 @@ impl EchoServer {
         self.msg_id
     }
 
-    fn send<W: Write>(msg: &Message, writer: &mut W) -> Result<(), anyhow::Error> {
-        serde_json::to_writer(&mut *writer, &msg).context("Failed to serialize reply message")?;
-        writer.write_all(b"\n").context("Failed to write newline")?;
-        Ok(())
+    fn prepare_reply(&mut self, msg: &Message) -> Option<Message> {
+        let body: Body = match &msg.body {
+            Body::Init(init_body) => Body::InitOk(InitOk {
+                msg_id: self.incremented_msg_id(),
+                in_reply_to: init_body.msg_id,
+                handshake: protocol::answer(),
+            }),
+
+            Body::Echo(echo_body) => Body::EchoOk(EchoOk {
+                msg_id: self.incremented_msg_id(),
+                in_reply_to: echo_body.msg_id,
+                echo: echo_body.echo.clone(),
+            }),
+
+            // Replies are never answered, or two nodes could keep answering each other.
+            Body::InitOk(_) | Body::EchoOk(_) => {
+                log::debug!("Ignored a reply: {:?}", msg.body);
+                return None;
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+                return None;
+            }
+        };
+
+        Some(msg.reply(body))
+    }
+}
+
+impl Lifecycle for EchoServer {
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({ "node_id": self.node_id, "msg_id": self.msg_id })
     }
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
-    let mut stdout = std::io::stdout().lock();
+/// Runs a node of the echo workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+}
 
-    let init_msg: Message = serde_json::from_str(
-        &stdin
-            .next()
-            .context("Maelstrom should provide input to STDIN.")?
-            .context("Failed to read message from stdin.")?,
-    )
-    .context("Failed to deserialize provided input to STDIN.")?;
+pub fn main() -> Result<(), anyhow::Error> {
+    run(config::parse("echo")?)
+}
+
+pub fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    cli.node.apply()?;
+    let mut stdin = transport::input();
+    let mut stdout = Output::stdout();
+
+    let mut init_line = String::new();
+    stdin
+        .read_line(&mut init_line)
+        .context("Failed to read message from stdin.")?;
+    if init_line.is_empty() {
+        bail!("Maelstrom should provide input to STDIN.");
+    }
+    runtime::received(init_line.as_bytes());
+    let mut node = start(init_line.as_bytes(), &mut stdout)?;
+    stdout.flush().context("Failed to flush STDOUT.")?;
+
+    runtime::run_lines(&mut node, stdin, &mut stdout, handle_line)?;
+    stdout.finish()
+}
+
+/// Starts the node an `init` line is for, and answers the `init`.
+fn start(init_line: &[u8], stdout: &mut impl Write) -> Result<EchoServer, anyhow::Error> {
+    let init_msg: Message = serde_json::from_slice(init_line)
+        .context("Failed to deserialize provided input to STDIN.")?;
 
     let Body::Init(ref init_body) = init_msg.body else {
         bail!("Expected Init message as the first received message.");
     };
     let mut node = EchoServer::initialize(init_body.node_id.clone());
+    node.on_init(&init_body.node_id);
 
-    let init_reply = init_msg
-        .prepare_reply(node.incremented_msg_id())
+    let init_reply = node
+        .prepare_reply(&init_msg)
         .context("Failed to prepare InitOk message")?;
 
-    EchoServer::send(&init_reply, &mut stdout)?;
+    init_reply.send(stdout)?;
+    Ok(node)
+}
 
-    while let Ok(line) = stdin
-        .next()
-        .context("Maelstrom should provide input to STDIN.")?
-    {
-        let msg: Message = serde_json::from_str(&line)
-            .context("Failed to deserialize provided input to STDIN.")?;
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
 
-        if let Some(reply) = msg.prepare_reply(node.incremented_msg_id()) {
-            EchoServer::send(&reply, &mut stdout)?;
-        } else {
-            eprintln!("No reply was prepared for message: {:?}", msg);
+fn handle_line(
+    node: &mut EchoServer,
+    line: &[u8],
+    stdout: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let msg =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(msg) => msg,
+            Err(error_reply) => {
+                error_reply.send(stdout)?;
+                return Ok(());
+            }
         };
-    }
 
+    if let Some(reply) = node.prepare_reply(&msg) {
+        reply.send(stdout)?;
+    }
     Ok(())
 }
+
+/// An echo node for a `sim::Network`, started by the first line it gets.
+pub fn simulated() -> Box<dyn SimNode> {
+    sim::lines(
+        None,
+        |node: &mut Option<EchoServer>, line, output| match node {
+            Some(node) => handle_line(node, line, output),
+            None => {
+                *node = Some(start(line, output)?);
+                Ok(())
+            }
+        },
+    )
+}
@@ -1,10 +1,45 @@
-use std::collections::HashMap;
-use std::io::{BufRead, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
+use clap::Parser;
+use distributed_system::checkpoint::Checkpoint;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::crdt::{Crdt, GCounter, Merge};
+use distributed_system::event_queue::{EventReceiver, EventSender};
+use distributed_system::membership::MembershipEvent;
+use distributed_system::metrics;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::reply_cache::ReplyCache;
+use distributed_system::rng::Rng;
+use distributed_system::rpc::PendingRequests;
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::scuttlebutt::Digest;
+use distributed_system::sim::SimNode;
+use distributed_system::{ErrorBody, ErrorCode};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Serialize, Deserialize)]
+type Message = distributed_system::Message<Body>;
+
+/// Number of recent `add` replies remembered to answer retried requests.
+const REPLY_CACHE_SIZE: usize = 1_000;
+
+/// A sync that isn't acknowledged within this long is sent again, together with whatever
+/// was added since.
+const SYNC_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A quorum read that a majority didn't answer within this long fails.
+const QUORUM_READ_TIMEOUT: Duration = Duration::from_millis(1_000);
+
+/// A read waits this long at most for the peer it repairs from, and then returns the count
+/// of this node.
+const READ_REPAIR_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 enum Body {
     Init {
@@ -16,6 +51,8 @@ enum Body {
     InitOk {
         msg_id: u64,
         in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
     },
 
     Add {
@@ -38,151 +75,731 @@ enum Body {
         value: u64,
     },
 
+    /// Counts the receiver lacks, as far as the sender knows.
     Sync {
         msg_id: u64,
-        counters: HashMap<String, u64>,
+        counters: GCounter,
     },
+
+    SyncOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// In scuttlebutt mode, the count of every node the sender has.
+    Digest {
+        msg_id: u64,
+        digest: Digest,
+    },
+
+    /// The counts newer than those of the digest, and the digest of the replying node, to
+    /// be answered with a `sync` of the counts it lacks.
+    DigestOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        digest: Digest,
+        counters: GCounter,
+    },
+
+    /// For a quorum read or read repair, asks a peer for the count of every node it has.
+    ReadCounters {
+        msg_id: u64,
+    },
+
+    ReadCountersOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        counters: GCounter,
+    },
+
+    Error(ErrorBody),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
 }
 
-impl Message {
-    fn process_received_message(&mut self, node: &mut Node) -> Vec<Self> {
+enum Event {
+    Message(Message),
+    Rejected(Message),
+    SyncRequested,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut impl Write,
+    ) -> Result<(), anyhow::Error> {
+        let responses = match self {
+            Event::Message(message) => node.process_received_message(message, sender.clone())?,
+            Event::Rejected(error_reply) => return error_reply.send(&mut output),
+            Event::SyncRequested => {
+                let mut responses = node.expire_reads();
+                responses.extend(node.sync_round());
+                responses
+            }
+        };
+
+        for response in responses {
+            response.send(&mut output)?;
+        }
+        Ok(())
+    }
+}
+
+/// How far a peer got through the deltas of this node.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct Peer {
+    /// The last delta it acknowledged, and every one before it.
+    acked: u64,
+    /// The last delta sent to it. Those after `acked` are in flight, and aren't sent again
+    /// until their sync times out.
+    sent: u64,
+}
+
+/// A read waiting for peers to send their counts: a majority of the cluster for a quorum
+/// read, or a single peer to repair from.
+#[derive(Debug)]
+struct PendingRead {
+    client: String,
+    in_reply_to: u64,
+    /// Peers whose counts were merged so far.
+    answered: BTreeSet<String>,
+    /// Answers it takes, with this node's own.
+    needed: usize,
+    /// Whether it's a read repair, which is answered with what the node has if the peer is
+    /// slow, rather than failing.
+    repair: bool,
+}
+
+struct Node {
+    node_id: String,
+    cluster: Vec<String>,
+    msg_id: u64,
+    counters: GCounter,
+    /// What was added here since the last sync round.
+    pending: GCounter,
+    /// The deltas of past sync rounds, by sequence number, until every peer acknowledged
+    /// them.
+    deltas: BTreeMap<u64, GCounter>,
+    last_seq: u64,
+    peers: HashMap<String, Peer>,
+    /// Syncs not acknowledged yet, with the peer and the last delta they carried.
+    syncs: PendingRequests<(String, u64)>,
+    replies: ReplyCache<Body>,
+    checkpoint: Option<Checkpoint>,
+    sync_interval: Duration,
+    full_sync_interval: Duration,
+    last_full_sync: Instant,
+    /// Reconciles with a random peer each sync round rather than sending every peer deltas.
+    scuttlebutt: bool,
+    rng: Rng,
+    /// Serves reads only once a majority of the cluster sent their counts.
+    quorum_reads: bool,
+    /// Merges the counts of a random peer before answering a read.
+    read_repair: bool,
+    reads: PendingRequests<PendingRead>,
+    timer: Option<Timer>,
+    /// Where the node reads the time, and schedules its sync rounds.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(cli: &Cli, time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            cluster: Vec::new(),
+            msg_id: 0,
+            counters: GCounter::new(),
+            pending: GCounter::new(),
+            deltas: BTreeMap::new(),
+            last_seq: 0,
+            peers: HashMap::new(),
+            syncs: PendingRequests::with_clock(time.clone()),
+            replies: ReplyCache::new(REPLY_CACHE_SIZE),
+            checkpoint: None,
+            sync_interval: cli.sync_interval,
+            full_sync_interval: cli.full_sync_interval,
+            last_full_sync: time.now(),
+            scuttlebutt: cli.scuttlebutt,
+            rng: Rng::from_entropy(),
+            quorum_reads: cli.quorum_reads,
+            read_repair: cli.read_repair,
+            reads: PendingRequests::with_clock(time.clone()),
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) -> Result<(), anyhow::Error> {
+        self.checkpoint = Checkpoint::from_env(&node_id)?;
+        self.node_id = node_id;
+        self.cluster.extend_from_slice(node_ids);
+        self.counters = GCounter::new();
+        for node_id in node_ids {
+            self.counters.increment(node_id, 0);
+        }
+        self.peers = node_ids
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .map(|id| (id.clone(), Peer::default()))
+            .collect();
+
+        let restored = match &mut self.checkpoint {
+            Some(checkpoint) => checkpoint.restore::<GCounter>()?,
+            None => None,
+        };
+        if let Some((msg_id, counters)) = restored {
+            self.msg_id = msg_id;
+            self.merge(counters);
+            // Peers may have missed the last adds before the restart.
+            self.pending = self.counters.delta(&self.node_id);
+        }
+        self.save_checkpoint(false)?;
+
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            self.sync_interval,
+            sender,
+            || Input::Event(Event::SyncRequested),
+        ));
+        Ok(())
+    }
+
+    /// Takes the highest value known for each node's counter, whether the node was in the
+    /// cluster at `init` or not. Nodes first heard of join the cluster. Entries that aren't
+    /// of a node are left out.
+    fn merge(&mut self, mut counters: GCounter) -> bool {
+        counters.retain(|node_id| {
+            let is_node = runtime::is_node_id(node_id);
+            if !is_node {
+                log::warn!("Dropped the count of {node_id}, which isn't a node");
+            }
+            is_node
+        });
+        let joined: Vec<String> = counters
+            .nodes()
+            .filter(|node| !self.cluster.iter().any(|id| id == node))
+            .map(str::to_string)
+            .collect();
+        for node in joined {
+            self.on_membership_event(MembershipEvent::Joined(node));
+        }
+        self.counters.merge(&counters)
+    }
+
+    /// Syncs with nodes that joined from the next round on. They get this node's count with
+    /// the next full sync, as the deltas before they joined may be gone.
+    fn on_membership_event(&mut self, event: MembershipEvent) {
+        log::info!("Membership changed: {event:?}");
+        if let MembershipEvent::Joined(node) = event {
+            self.peers.insert(node.clone(), Peer::default());
+            self.cluster.push(node);
+        }
+    }
+
+    /// Saves the counters if they changed, or if the msg_id lease is running out. An `add` is
+    /// only acknowledged once it is saved.
+    fn save_checkpoint(&mut self, changed: bool) -> Result<(), anyhow::Error> {
+        match &mut self.checkpoint {
+            Some(checkpoint) if changed || checkpoint.needs_save(self.msg_id) => {
+                checkpoint.save(self.msg_id, &self.counters)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    /// Seals what was added since the last round into a delta, and sends every peer the
+    /// deltas it neither acknowledged nor has in flight, joined into a single sync. Deltas
+    /// of syncs that timed out are sent again. Once every full sync interval, the node's
+    /// count goes out whether it changed or not, for peers that lost what they had of it.
+    fn sync_round(&mut self) -> Vec<Message> {
+        if self.scuttlebutt {
+            return self.scuttlebutt_round();
+        }
+        let now = self.time.now();
+        if now >= self.last_full_sync + self.full_sync_interval {
+            self.last_full_sync = now;
+            self.pending.merge(&self.counters.delta(&self.node_id));
+        }
+        if !self.pending.is_empty() {
+            self.last_seq += 1;
+            let delta = std::mem::take(&mut self.pending);
+            self.deltas.insert(self.last_seq, delta);
+        }
+
+        for (_, (peer, _)) in self.syncs.expire(SYNC_TIMEOUT) {
+            metrics::increment(metrics::RETRIES);
+            if let Some(peer) = self.peers.get_mut(&peer) {
+                peer.sent = peer.acked;
+            }
+        }
+
+        let mut syncs = Vec::new();
+        let mut peers: Vec<String> = self.peers.keys().cloned().collect();
+        peers.sort();
+        for peer in peers {
+            let sent = self.peers[&peer].sent;
+            let counters = GCounter::join(self.deltas.range(sent + 1..).map(|(_, delta)| delta));
+            if counters.is_empty() {
+                continue;
+            }
+
+            let msg_id = self.incremented_msg_id();
+            self.syncs.insert(msg_id, (peer.clone(), self.last_seq));
+            if let Some(peer) = self.peers.get_mut(&peer) {
+                peer.sent = self.last_seq;
+            }
+            syncs.push(Message {
+                src: self.node_id.clone(),
+                dest: peer,
+                body: Body::Sync { msg_id, counters },
+            });
+        }
+        syncs
+    }
+
+    /// Sends the digest of this node to a random peer.
+    fn scuttlebutt_round(&mut self) -> Vec<Message> {
+        let mut peers: Vec<String> = self.peers.keys().cloned().collect();
+        peers.sort();
+        let Some(peer) = self.rng.sample(&peers, 1).pop() else {
+            return Vec::new();
+        };
+        let msg_id = self.incremented_msg_id();
+        vec![Message {
+            src: self.node_id.clone(),
+            dest: peer,
+            body: Body::Digest {
+                msg_id,
+                digest: self.digest(),
+            },
+        }]
+    }
+
+    /// The count of every node, which is the version of its entry, as it only grows.
+    fn digest(&self) -> Digest {
+        self.counters.iter().collect()
+    }
+
+    /// The counts newer than those of `digest`.
+    fn newer_than(&self, digest: &Digest) -> GCounter {
+        let ours = self.digest();
+        let deltas: Vec<GCounter> = digest
+            .missing_from(&ours)
+            .map(|node| self.counters.delta(node))
+            .collect();
+        GCounter::join(&deltas)
+    }
+
+    /// Asks every peer for their counts, and answers the read once a majority of the
+    /// cluster, this node included, sent theirs. It then counts every add acknowledged by
+    /// any node of that majority, rather than only those that reached this node.
+    fn quorum_read(&mut self, client: &str, in_reply_to: u64) -> Vec<Message> {
+        let needed = self.cluster.len() / 2 + 1;
+        let mut peers: Vec<String> = self.peers.keys().cloned().collect();
+        peers.sort();
+        self.read_from(client, in_reply_to, peers, needed, false)
+    }
+
+    /// Asks a random peer for its counts, and answers the read once they're merged, to
+    /// count adds that haven't reached this node yet even if syncs are far apart. If the
+    /// peer doesn't answer soon, the read gets the count of this node.
+    fn repaired_read(&mut self, client: &str, in_reply_to: u64) -> Vec<Message> {
+        let mut peers: Vec<String> = self.peers.keys().cloned().collect();
+        peers.sort();
+        let peers = self.rng.sample(&peers, 1);
+        self.read_from(client, in_reply_to, peers, 2, true)
+    }
+
+    /// Sends `peers` a `read_counters` for a read that takes `needed` answers, this node's
+    /// included. Answers the read right away if this node's is enough.
+    fn read_from(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        peers: Vec<String>,
+        needed: usize,
+        repair: bool,
+    ) -> Vec<Message> {
+        if needed <= 1 || peers.is_empty() {
+            return vec![self.read_reply(client, in_reply_to)];
+        }
+        let msg_id = self.incremented_msg_id();
+        self.reads.insert(
+            msg_id,
+            PendingRead {
+                client: client.to_string(),
+                in_reply_to,
+                answered: BTreeSet::new(),
+                needed,
+                repair,
+            },
+        );
+        peers
+            .into_iter()
+            .map(|peer| Message {
+                src: self.node_id.clone(),
+                dest: peer,
+                body: Body::ReadCounters { msg_id },
+            })
+            .collect()
+    }
+
+    /// Notes that `peer` sent its counts for the read `msg_id`, and answers the read if that
+    /// makes enough.
+    fn read_answered(&mut self, peer: &str, msg_id: u64) -> Vec<Message> {
+        let Some(read) = self.reads.get_mut(msg_id) else {
+            return Vec::new();
+        };
+        read.answered.insert(peer.to_string());
+        if read.answered.len() + 1 < read.needed {
+            return Vec::new();
+        }
+        let read = self.reads.complete(msg_id).expect("Checked it's pending");
+        vec![self.read_reply(&read.client, read.in_reply_to)]
+    }
+
+    fn read_reply(&mut self, client: &str, in_reply_to: u64) -> Message {
+        Message {
+            src: self.node_id.clone(),
+            dest: client.to_string(),
+            body: Body::ReadOk {
+                msg_id: self.incremented_msg_id(),
+                in_reply_to,
+                value: self.counters.value(),
+            },
+        }
+    }
+
+    /// Fails the quorum reads that a majority didn't answer in time, e.g. as a partition
+    /// cut this node off from it, and answers the read repairs whose peer didn't with the
+    /// count of this node.
+    fn expire_reads(&mut self) -> Vec<Message> {
+        let expired = self.reads.expire_with(|read| {
+            if read.repair {
+                READ_REPAIR_TIMEOUT
+            } else {
+                QUORUM_READ_TIMEOUT
+            }
+        });
+        expired
+            .into_iter()
+            .map(|(_, read)| {
+                if read.repair {
+                    return self.read_reply(&read.client, read.in_reply_to);
+                }
+                Message {
+                    src: self.node_id.clone(),
+                    dest: read.client,
+                    body: Body::Error(ErrorBody::new(
+                        read.in_reply_to,
+                        ErrorCode::TemporarilyUnavailable,
+                        "A majority of the cluster did not answer the read",
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    /// Notes that `peer` has every delta up to `seq`, and drops the deltas every peer has.
+    fn acknowledged(&mut self, peer: &str, seq: u64) {
+        if let Some(peer) = self.peers.get_mut(peer) {
+            peer.acked = peer.acked.max(seq);
+            peer.sent = peer.sent.max(peer.acked);
+        }
+        let everywhere = self
+            .peers
+            .values()
+            .map(|peer| peer.acked)
+            .min()
+            .unwrap_or(self.last_seq);
+        self.deltas.retain(|&seq, _| seq > everywhere);
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        self.save_checkpoint(false)?;
         let mut responses: Vec<Message> = Vec::new();
 
-        let build_message_from = |body: Body| -> Self {
-            Self {
-                src: self.dest.clone(),
-                dest: self.src.clone(),
+        let build_message_from = |body: Body| -> Message {
+            Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
                 body,
             }
         };
 
-        match &mut self.body {
+        match &mut message.body {
             Body::Init {
                 msg_id,
                 node_id,
                 node_ids,
             } => {
-                node.initialize(node_id.clone(), node_ids);
+                self.initialize(node_id.clone(), node_ids, sender)?;
 
                 responses.push(build_message_from(Body::InitOk {
-                    msg_id: node.incremented_msg_id(),
+                    msg_id: self.incremented_msg_id(),
                     in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
                 }));
             }
 
             Body::Add { msg_id, delta } => {
-                node.counters
-                    .entry(node.node_id.clone())
-                    .and_modify(|value| *value += *delta);
-
-                let incremented_msg_id = node.incremented_msg_id();
-
-                for destination_node in node.cluster.iter().filter(|&id| *id != node.node_id) {
-                    responses.push(Message {
-                        src: node.node_id.clone(),
-                        dest: destination_node.clone(),
-                        body: Body::Sync {
-                            msg_id: incremented_msg_id,
-                            counters: node.counters.clone(),
-                        },
-                    });
+                // A retried add was already counted, so it only gets the original reply.
+                if let Some(reply) = self.replies.get(&message.src, *msg_id) {
+                    responses.push(reply);
+                    return Ok(responses);
                 }
 
-                responses.push(build_message_from(Body::AddOk {
-                    msg_id: node.incremented_msg_id(),
+                self.counters.increment(&self.node_id, *delta);
+                self.save_checkpoint(*delta > 0)?;
+                if *delta > 0 && !self.scuttlebutt {
+                    self.pending.merge(&self.counters.delta(&self.node_id));
+                }
+
+                let reply = build_message_from(Body::AddOk {
+                    msg_id: self.incremented_msg_id(),
                     in_reply_to: *msg_id,
-                }));
+                });
+                self.replies.insert(&message.src, *msg_id, reply.clone());
+                responses.push(reply);
+            }
+
+            Body::Read { msg_id } if self.quorum_reads => {
+                responses.extend(self.quorum_read(&message.src, *msg_id));
+            }
+
+            Body::Read { msg_id } if self.read_repair => {
+                responses.extend(self.repaired_read(&message.src, *msg_id));
             }
 
             Body::Read { msg_id } => {
-                responses.push(build_message_from(Body::ReadOk {
-                    msg_id: node.incremented_msg_id(),
+                responses.push(self.read_reply(&message.src, *msg_id));
+            }
+
+            Body::ReadCounters { msg_id } => {
+                responses.push(build_message_from(Body::ReadCountersOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    counters: self.counters.clone(),
+                }));
+            }
+
+            Body::ReadCountersOk {
+                in_reply_to,
+                counters,
+                ..
+            } => {
+                let changed = self.merge(std::mem::take(counters));
+                self.save_checkpoint(changed)?;
+                responses.extend(self.read_answered(&message.src, *in_reply_to));
+            }
+
+            Body::Sync { msg_id, counters } => {
+                let changed = self.merge(std::mem::take(counters));
+                self.save_checkpoint(changed)?;
+                responses.push(build_message_from(Body::SyncOk {
+                    msg_id: self.incremented_msg_id(),
                     in_reply_to: *msg_id,
-                    value: node.counters.values().sum::<u64>(),
                 }));
             }
 
-            Body::Sync { counters, .. } => {
-                for (key, &remote_value) in counters.iter() {
-                    node.counters.entry(key.clone()).and_modify(|local_value| {
-                        if remote_value > *local_value {
-                            *local_value = remote_value;
-                        }
-                    });
+            Body::SyncOk { in_reply_to, .. } => {
+                if let Some((peer, seq)) = self.syncs.complete(*in_reply_to) {
+                    self.acknowledged(&peer, seq);
                 }
             }
 
-            Body::InitOk { .. } | Body::AddOk { .. } | Body::ReadOk { .. } => {}
-        }
+            Body::Digest { msg_id, digest } => {
+                let counters = self.newer_than(digest);
+                responses.push(build_message_from(Body::DigestOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    digest: self.digest(),
+                    counters,
+                }));
+            }
 
-        responses
-    }
-}
+            Body::DigestOk {
+                digest, counters, ..
+            } => {
+                let changed = self.merge(std::mem::take(counters));
+                self.save_checkpoint(changed)?;
+                let counters = self.newer_than(digest);
+                if !counters.is_empty() {
+                    responses.push(build_message_from(Body::Sync {
+                        msg_id: self.incremented_msg_id(),
+                        counters,
+                    }));
+                }
+            }
 
-struct Node {
-    node_id: String,
-    cluster: Vec<String>,
-    msg_id: u64,
-    counters: HashMap<String, u64>,
-}
+            Body::InitOk { msg_id, .. }
+            | Body::AddOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. } => {
+                responses.push(build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Counter node does not accept replies",
+                ))));
+            }
 
-impl Node {
-    fn new() -> Self {
-        Self {
-            node_id: String::new(),
-            cluster: Vec::new(),
-            msg_id: 0,
-            counters: HashMap::new(),
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+            }
         }
-    }
 
-    fn initialize(&mut self, node_id: String, node_ids: &[String]) {
-        self.node_id = node_id;
-        self.cluster.extend_from_slice(node_ids);
-        self.counters = node_ids
-            .iter()
-            .map(|node_id| (node_id.clone(), 0))
-            .collect();
+        Ok(responses)
     }
+}
 
-    fn incremented_msg_id(&mut self) -> u64 {
-        self.msg_id += 1;
-        self.msg_id
+impl Lifecycle for Node {
+    /// Saves the counters once more, so that a restart doesn't depend on how recently they
+    /// were saved last.
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        self.save_checkpoint(true)
     }
 
-    fn send<W: Write>(msg: &Message, writer: &mut W) -> Result<(), anyhow::Error> {
-        serde_json::to_writer(&mut *writer, &msg).context("Failed to serialize reply message")?;
-        writer.write_all(b"\n").context("Failed to write newline")?;
-        Ok(())
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node_id": self.node_id,
+            "cluster": self.cluster,
+            "counters": self.counters,
+            "pending": self.pending,
+            "deltas": self.deltas.len(),
+            "peers": self.peers,
+            "unacknowledged_syncs": self.syncs.len(),
+            "pending_reads": self.reads.len(),
+            "cached_replies": self.replies.len(),
+        })
     }
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
-    let mut stdout = std::io::stdout().lock();
-    let mut node = Node::new();
+/// Runs a node of the g-counter workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Milliseconds between rounds that send peers what was added since they last
+    /// acknowledged a sync.
+    #[arg(
+        long = "sync-interval-ms",
+        env = "G_COUNTER_SYNC_INTERVAL_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "100"
+    )]
+    sync_interval: Duration,
+    /// Milliseconds between rounds that send peers the node's count even if it didn't
+    /// change, so that a peer that restarted without its counts gets them back.
+    #[arg(
+        long = "full-sync-ms",
+        env = "G_COUNTER_FULL_SYNC_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "1000"
+    )]
+    full_sync_interval: Duration,
+    /// Reconciles with one random peer each round instead, scuttlebutt style: the two
+    /// exchange the count they have of every node, and send each other only the counts the
+    /// other is behind on. Nothing is tracked per peer, which suits larger clusters.
+    #[arg(long, env = "G_COUNTER_SCUTTLEBUTT")]
+    scuttlebutt: bool,
+    /// Answers a read only once a majority of the cluster sent the node their counts, so
+    /// that it counts every add that reached a majority, not only those that reached the
+    /// node. Reads a majority doesn't answer within a second fail.
+    #[arg(long, env = "G_COUNTER_QUORUM_READS")]
+    quorum_reads: bool,
+    /// Merges the counts of a random peer before answering a read, for fresher reads
+    /// between syncs. A read whose peer doesn't answer within 100 milliseconds returns the
+    /// count of the node. Ignored with `--quorum-reads`, which already asks every peer.
+    #[arg(long, env = "G_COUNTER_READ_REPAIR")]
+    read_repair: bool,
+}
+
+pub fn main() -> Result<(), anyhow::Error> {
+    run(config::parse("g-counter")?)
+}
 
-    while let Ok(line) = stdin
-        .next()
-        .context("Maelstrom should provide input to STDIN.")?
-    {
-        let mut message: Message = serde_json::from_str(&line)
-            .context("Failed to deserialize provided input to STDIN.")?;
+pub fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    cli.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(&cli, Arc::new(SystemClock));
 
-        let responses = message.process_received_message(&mut node);
+    let reader = runtime::spawn_stdin_reader(sender.clone(), parse_event);
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
 
-        for response in responses {
-            Node::send(&response, &mut stdout)?;
-        }
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn parse_event(line: &[u8]) -> Result<Event, anyhow::Error> {
+    let event =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(message) => Event::Message(message),
+            Err(error_reply) => Event::Rejected(error_reply),
+        };
+    Ok(event)
+}
+
+/// A g-counter node for a `sim::Network`, which syncs as scheduled by `time`.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Box<dyn SimNode> {
+    let (sender, receiver) = runtime::event_channel();
+    Box::new(Simulated {
+        node: Node::new(&cli, time),
+        sender,
+        receiver,
+    })
+}
+
+struct Simulated {
+    node: Node,
+    sender: EventSender<Input<Event>>,
+    receiver: EventReceiver<Input<Event>>,
+}
+
+impl SimNode for Simulated {
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        parse_event(line.as_bytes())?.process_received_event(&mut self.node, &self.sender, output)
     }
 
-    Ok(())
+    /// Runs the sync rounds that are due.
+    fn tick(&mut self, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        while let Ok(input) = self.receiver.try_recv() {
+            if let Input::Event(mut event) = input {
+                event.process_received_event(&mut self.node, &self.sender, output)?;
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,282 @@
+use std::io::Write;
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::kv::{self, KvBody, KvClient, KvError, KvReply, KvService};
+use distributed_system::metrics;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime::{self, Lifecycle, Output};
+use distributed_system::transport;
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+const COUNTER_KEY: &str = "counter";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Add {
+        msg_id: u64,
+        delta: u64,
+    },
+
+    AddOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Read {
+        msg_id: u64,
+    },
+
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        value: u64,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+/// A client request in flight. A `read` is an `add` of zero, which makes the final `cas`
+/// confirm that the value read is still the latest one in seq-kv.
+struct Operation {
+    request: Message,
+    delta: u64,
+}
+
+enum Step {
+    Read(Operation),
+    Cas(Operation, u64),
+}
+
+struct Node {
+    node_id: String,
+    msg_id: u64,
+    kv: KvClient<Step>,
+}
+
+impl Node {
+    fn new() -> Self {
+        Self {
+            node_id: String::new(),
+            msg_id: 0,
+            kv: KvClient::new(KvService::SeqKv),
+        }
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn read_counter<W: Write>(
+        &mut self,
+        operation: Operation,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        let msg_id = self.incremented_msg_id();
+        self.kv
+            .read(&self.node_id, msg_id, COUNTER_KEY, Step::Read(operation))?
+            .send(output)
+    }
+
+    fn swap_counter<W: Write>(
+        &mut self,
+        operation: Operation,
+        value: u64,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        let msg_id = self.incremented_msg_id();
+        let to = value + operation.delta;
+        self.kv
+            .cas(
+                &self.node_id,
+                msg_id,
+                COUNTER_KEY,
+                value,
+                to,
+                true,
+                Step::Cas(operation, value),
+            )?
+            .send(output)
+    }
+
+    fn process_received_message<W: Write>(
+        &mut self,
+        message: Message,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        match &message.body {
+            Body::Init {
+                msg_id, node_id, ..
+            } => {
+                self.node_id = node_id.clone();
+
+                message
+                    .reply(Body::InitOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: *msg_id,
+                        handshake: protocol::answer(),
+                    })
+                    .send(output)
+            }
+
+            Body::Add { delta, .. } => {
+                let delta = *delta;
+                self.read_counter(
+                    Operation {
+                        request: message,
+                        delta,
+                    },
+                    output,
+                )
+            }
+
+            Body::Read { .. } => self.read_counter(
+                Operation {
+                    request: message,
+                    delta: 0,
+                },
+                output,
+            ),
+
+            Body::InitOk { msg_id, .. }
+            | Body::AddOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. } => message
+                .error_reply::<Body>(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Counter node does not accept replies",
+                )
+                .send(output),
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+                Ok(())
+            }
+        }
+    }
+
+    fn process_kv_reply<W: Write>(
+        &mut self,
+        reply: distributed_system::Message<KvBody>,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        let Some((step, result)) = self.kv.handle_reply(reply) else {
+            return Ok(());
+        };
+
+        match (step, result) {
+            (Step::Read(operation), Ok(reply)) => {
+                let value: u64 = reply.value()?;
+                self.swap_counter(operation, value, output)
+            }
+
+            (Step::Read(operation), Err(KvError::KeyDoesNotExist(_))) => {
+                self.swap_counter(operation, 0, output)
+            }
+
+            (Step::Cas(operation, value), Ok(KvReply::Cas)) => {
+                let request = &operation.request;
+                let body = match request.body {
+                    Body::Add { msg_id, .. } => Body::AddOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: msg_id,
+                    },
+                    Body::Read { msg_id } => Body::ReadOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: msg_id,
+                        value: value + operation.delta,
+                    },
+                    _ => unreachable!("Only add and read requests are forwarded to seq-kv"),
+                };
+
+                request.reply(body).send(output)
+            }
+
+            (Step::Read(operation) | Step::Cas(operation, _), Err(error)) => {
+                log::warn!("Retrying after seq-kv error: {error}");
+                metrics::increment(metrics::RETRIES);
+                self.read_counter(operation, output)
+            }
+
+            (Step::Cas(operation, _), Ok(unexpected)) => {
+                log::warn!("Unexpected seq-kv reply to cas: {:?}", unexpected);
+                self.read_counter(operation, output)
+            }
+        }
+    }
+}
+
+impl Lifecycle for Node {
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node_id": self.node_id,
+            "pending_kv_requests": self.kv.pending(),
+        })
+    }
+}
+
+/// Runs a node of the g-counter workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    config::parse::<Cli>("g-counter")?.node.apply()?;
+    let stdin = transport::input();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new();
+
+    runtime::run_lines(&mut node, stdin, &mut stdout, |node, line, stdout| {
+        if let Some(reply) = kv::parse_reply(line)? {
+            node.process_kv_reply(reply, stdout)?;
+            return Ok(());
+        }
+
+        let message =
+            match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+                Ok(message) => message,
+                Err(error_reply) => {
+                    error_reply.send(stdout)?;
+                    return Ok(());
+                }
+            };
+
+        node.process_received_message(message, stdout)?;
+        Ok(())
+    })?;
+    stdout.finish()
+}
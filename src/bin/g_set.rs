@@ -0,0 +1,463 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::crdt::{Crdt, GSet, Merge, ORSet};
+use distributed_system::event_queue::{EventReceiver, EventSender};
+use distributed_system::hlc::{Hlc, HlcTimestamp};
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::reply_cache::ReplyCache;
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::sim::SimNode;
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+/// Number of recent `add` and `remove` replies remembered to answer retried requests.
+const REPLY_CACHE_SIZE: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Add {
+        msg_id: u64,
+        element: u64,
+    },
+
+    AddOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// Only supported by OR-Set nodes.
+    Remove {
+        msg_id: u64,
+        element: u64,
+    },
+
+    RemoveOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Read {
+        msg_id: u64,
+    },
+
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        value: Vec<u64>,
+    },
+
+    /// The whole set of the sender.
+    Sync {
+        msg_id: u64,
+        #[serde(flatten)]
+        set: Set,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+/// The set a node replicates, sent in a `sync` under the name of its kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Set {
+    Elements(GSet<u64>),
+    OrSet(ORSet<u64>),
+}
+
+impl Set {
+    /// Merges `other` if it's of the same kind, and returns whether anything changed.
+    fn merge(&mut self, other: &Set) -> Result<bool, anyhow::Error> {
+        match (self, other) {
+            (Set::Elements(ours), Set::Elements(theirs)) => Ok(ours.merge(theirs)),
+            (Set::OrSet(ours), Set::OrSet(theirs)) => Ok(ours.merge(theirs)),
+            _ => anyhow::bail!("Can't merge a G-Set with an OR-Set"),
+        }
+    }
+
+    fn elements(&self) -> Vec<u64> {
+        match self {
+            Set::Elements(set) => set.value(),
+            Set::OrSet(set) => set.value(),
+        }
+    }
+}
+
+enum Event {
+    Message(Message),
+    Rejected(Message),
+    SyncRequested,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut impl Write,
+    ) -> Result<(), anyhow::Error> {
+        let responses = match self {
+            Event::Message(message) => node.process_received_message(message, sender.clone()),
+            Event::Rejected(error_reply) => return error_reply.send(&mut output),
+            Event::SyncRequested => node.sync_round(),
+        };
+
+        for response in responses {
+            response.send(&mut output)?;
+        }
+        Ok(())
+    }
+}
+
+struct Node {
+    node_id: String,
+    cluster: Vec<String>,
+    msg_id: u64,
+    set: Set,
+    /// Numbers the tags of adds in OR-Set mode with its timestamps. Unlike a counter, it
+    /// doesn't start over when the node restarts, so a tag a peer already has isn't reused.
+    hlc: Hlc,
+    replies: ReplyCache<Body>,
+    /// Whether the set changed since the last sync round.
+    changed: bool,
+    sync_interval: Duration,
+    full_sync_interval: Duration,
+    last_full_sync: Instant,
+    timer: Option<Timer>,
+    /// Where the node schedules its sync rounds.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(cli: &Cli, time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            cluster: Vec::new(),
+            msg_id: 0,
+            set: if cli.or_set {
+                Set::OrSet(ORSet::new())
+            } else {
+                Set::Elements(GSet::new())
+            },
+            hlc: Hlc::with_clock(time.clone()),
+            replies: ReplyCache::new(REPLY_CACHE_SIZE),
+            changed: false,
+            sync_interval: cli.sync_interval,
+            full_sync_interval: cli.full_sync_interval,
+            last_full_sync: time.now(),
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) {
+        self.node_id = node_id;
+        self.cluster.extend_from_slice(node_ids);
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            self.sync_interval,
+            sender,
+            || Input::Event(Event::SyncRequested),
+        ));
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn add(&mut self, element: u64) {
+        match &mut self.set {
+            Set::Elements(set) => self.changed |= set.insert(element),
+            Set::OrSet(set) => {
+                let tag = self.hlc.now().to_u64();
+                set.insert(element, (self.node_id.clone(), tag));
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Sends the whole set to every other node if it changed since the last round, or once
+    /// every full sync interval, whatever the changes, so that nodes that lost a sync catch
+    /// up.
+    fn sync_round(&mut self) -> Vec<Message> {
+        let now = self.time.now();
+        let full_sync_due = now >= self.last_full_sync + self.full_sync_interval;
+        if !self.changed && !full_sync_due {
+            return Vec::new();
+        }
+        self.changed = false;
+        if full_sync_due {
+            self.last_full_sync = now;
+        }
+
+        let msg_id = self.incremented_msg_id();
+        self.cluster
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .map(|peer| Message {
+                src: self.node_id.clone(),
+                dest: peer.clone(),
+                body: Body::Sync {
+                    msg_id,
+                    set: self.set.clone(),
+                },
+            })
+            .collect()
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Vec<Message> {
+        let mut responses: Vec<Message> = Vec::new();
+
+        let build_message_from = |body: Body| -> Message {
+            Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }
+        };
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids, sender);
+
+                responses.push(build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                }));
+            }
+
+            Body::Add { msg_id, element } => {
+                // A retried add may have been removed since, and mustn't come back.
+                if let Some(reply) = self.replies.get(&message.src, *msg_id) {
+                    responses.push(reply);
+                    return responses;
+                }
+
+                self.add(*element);
+
+                let reply = build_message_from(Body::AddOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                });
+                self.replies.insert(&message.src, *msg_id, reply.clone());
+                responses.push(reply);
+            }
+
+            Body::Remove { msg_id, element } => {
+                if let Some(reply) = self.replies.get(&message.src, *msg_id) {
+                    responses.push(reply);
+                    return responses;
+                }
+
+                let Set::OrSet(set) = &mut self.set else {
+                    responses.push(build_message_from(Body::Error(ErrorBody::new(
+                        *msg_id,
+                        ErrorCode::NotSupported,
+                        "Elements can only be removed from an OR-Set, see --or-set",
+                    ))));
+                    return responses;
+                };
+                self.changed |= set.remove(element);
+
+                let reply = build_message_from(Body::RemoveOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                });
+                self.replies.insert(&message.src, *msg_id, reply.clone());
+                responses.push(reply);
+            }
+
+            Body::Read { msg_id } => {
+                responses.push(build_message_from(Body::ReadOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    value: self.set.elements(),
+                }));
+            }
+
+            Body::Sync { set, .. } => {
+                if let Err(error) = self.set.merge(set) {
+                    log::warn!("Dropped the sync from {}: {error}", message.src);
+                }
+                // Tags of peers move the clock forward, so that the tags of this node stay
+                // later than those it had before a restart, even if its clock went back.
+                if let Set::OrSet(set) = set {
+                    for (_, tag) in set.tags() {
+                        self.hlc.observe(HlcTimestamp::from_u64(*tag));
+                    }
+                }
+            }
+
+            Body::InitOk { msg_id, .. }
+            | Body::AddOk { msg_id, .. }
+            | Body::RemoveOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. } => {
+                responses.push(build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Set node does not accept replies",
+                ))));
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+            }
+        }
+
+        responses
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        Ok(())
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node_id": self.node_id,
+            "cluster": self.cluster,
+            "set": self.set,
+            "cached_replies": self.replies.len(),
+        })
+    }
+}
+
+/// Runs a node of the g-set workload, or of an OR-Set that also takes removes.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Replicates an observed-remove set instead, which also takes `remove` requests. An add
+    /// wins over a concurrent remove of the same element.
+    #[arg(long, env = "G_SET_OR_SET")]
+    or_set: bool,
+    /// Milliseconds between sync rounds, which send the set once it changed.
+    #[arg(
+        long = "sync-interval-ms",
+        env = "G_SET_SYNC_INTERVAL_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "100"
+    )]
+    sync_interval: Duration,
+    /// Milliseconds between rounds that send the set even if it didn't change, so that
+    /// nodes that lost a sync catch up.
+    #[arg(
+        long = "full-sync-ms",
+        env = "G_SET_FULL_SYNC_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "1000"
+    )]
+    full_sync_interval: Duration,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = config::parse::<Cli>("g-set")?;
+    cli.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(&cli, Arc::new(SystemClock));
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), parse_event);
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn parse_event(line: &[u8]) -> Result<Event, anyhow::Error> {
+    let event =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(message) => Event::Message(message),
+            Err(error_reply) => Event::Rejected(error_reply),
+        };
+    Ok(event)
+}
+
+/// A set node for a `sim::Network`, which syncs as scheduled by `time`.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Box<dyn SimNode> {
+    let (sender, receiver) = runtime::event_channel();
+    Box::new(Simulated {
+        node: Node::new(&cli, time),
+        sender,
+        receiver,
+    })
+}
+
+struct Simulated {
+    node: Node,
+    sender: EventSender<Input<Event>>,
+    receiver: EventReceiver<Input<Event>>,
+}
+
+impl SimNode for Simulated {
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        parse_event(line.as_bytes())?.process_received_event(&mut self.node, &self.sender, output)
+    }
+
+    /// Runs the sync rounds that are due.
+    fn tick(&mut self, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        while let Ok(input) = self.receiver.try_recv() {
+            if let Input::Event(mut event) = input {
+                event.process_received_event(&mut self.node, &self.sender, output)?;
+            }
+        }
+        Ok(())
+    }
+}
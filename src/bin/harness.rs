@@ -0,0 +1,403 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use clap::{Parser, ValueEnum};
+use distributed_system::config::{self, LogLevel};
+use distributed_system::harness::{
+    self, Cluster, Generator, GeneratorConfig, KeyDistribution, Link, Mix, Op, Workload,
+};
+use distributed_system::logging::{LogFormat, Logger};
+use distributed_system::protocol::Capabilities;
+use distributed_system::rng::Rng;
+use distributed_system::topology::Topology;
+use serde_json::json;
+
+/// The client every generated request comes from.
+const CLIENT: &str = "c1";
+
+/// The client that sends `topology` and the final reads, like Maelstrom's setup client.
+const SETUP_CLIENT: &str = "c0";
+
+/// Runs a cluster of a workload binary without Maelstrom: starts the nodes, sends them
+/// `init` and a grid `topology`, sends them client requests while injecting faults, and then
+/// checks what came back against what the workload promises.
+#[derive(Debug, Parser)]
+struct Cli {
+    /// The workload the nodes run, by its Maelstrom name, e.g. `broadcast` or `g-counter`.
+    #[arg(long, short)]
+    workload: Workload,
+    #[arg(long, default_value_t = 3)]
+    node_count: usize,
+    /// Milliseconds to send requests for.
+    #[arg(
+        long = "time-limit-ms",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "10000"
+    )]
+    time_limit: Duration,
+    /// Requests per second, over all nodes.
+    #[arg(long, default_value_t = 10.0)]
+    rate: f64,
+    /// Weights of the types of requests sent, e.g. `send=8,poll=2`. The workload's usual mix
+    /// when unset.
+    #[arg(long, value_name = "TYPE=WEIGHT,...")]
+    mix: Option<Mix>,
+    /// Keys kafka and txn requests pick from.
+    #[arg(long, default_value_t = 5)]
+    keys: usize,
+    /// How keys are picked: `uniform`, or `zipfian[:<exponent>]` for a few hot keys.
+    #[arg(long, default_value = "uniform")]
+    key_distribution: KeyDistribution,
+    /// Bytes in each echoed or broadcast value. Broadcast values are integers when unset.
+    #[arg(long, default_value_t = 0)]
+    value_size: usize,
+    /// Milliseconds after which a request without a reply counts as failed.
+    #[arg(
+        long = "timeout-ms",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "1000"
+    )]
+    timeout: Duration,
+    /// Faults to inject, e.g. `partition,kill`. None when unset.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    nemesis: Vec<Fault>,
+    /// Milliseconds between starting and healing a fault.
+    #[arg(
+        long = "nemesis-interval-ms",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "2000"
+    )]
+    nemesis_interval: Duration,
+    /// Milliseconds the healed cluster gets to converge before the final reads.
+    #[arg(
+        long = "recovery-ms",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "2000"
+    )]
+    recovery: Duration,
+    /// Seeds the requests and faults, so that runs repeat. Printed when unset.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Writes each node's diagnostics to `<node>.log` in this directory instead of stderr.
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+    /// Extensions of the protocol to offer the nodes in `init`, e.g. `compressed_gossip=1`.
+    /// Nothing is negotiated when unset, as with Maelstrom.
+    #[arg(long, value_name = "NAME=VERSION,...")]
+    capabilities: Option<Capabilities>,
+    /// How to exchange messages with the nodes: their stdin and stdout, or a Unix domain
+    /// socket each.
+    #[arg(long, value_enum, default_value_t = Link::Pipes)]
+    link: Link,
+    #[arg(long, env = "LOG_LEVEL", value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+    /// The node binary to run, followed by its arguments.
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<OsString>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Fault {
+    /// Cuts the network in two, with a minority on one side.
+    Partition,
+    /// Kills a node, and restarts it once healed.
+    Kill,
+}
+
+/// The fault in effect, undone by `heal`.
+#[derive(Debug, Default)]
+struct Nemesis {
+    partitioned: bool,
+    killed: Vec<String>,
+}
+
+impl Nemesis {
+    fn strike(
+        &mut self,
+        cluster: &mut Cluster,
+        fault: Fault,
+        rng: &mut Rng,
+    ) -> Result<(), anyhow::Error> {
+        let nodes = cluster.node_ids().to_vec();
+        match fault {
+            Fault::Partition => {
+                let shuffled = rng.sample(&nodes, nodes.len());
+                let (minority, majority) = shuffled.split_at(nodes.len() / 2);
+                log::info!("Partitioning {minority:?} from {majority:?}");
+                cluster.partition(&[minority.to_vec(), majority.to_vec()]);
+                self.partitioned = true;
+            }
+            Fault::Kill => {
+                let alive = cluster.alive();
+                // A cluster without nodes has nobody to answer requests, or to restart from.
+                if alive.len() > 1 {
+                    let node = alive[rng.below(alive.len())].clone();
+                    log::info!("Killing {node}");
+                    cluster.kill(&node)?;
+                    self.killed.push(node);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn heal(&mut self, cluster: &mut Cluster) -> Result<(), anyhow::Error> {
+        if std::mem::take(&mut self.partitioned) {
+            log::info!("Healing the partition");
+            cluster.heal();
+        }
+        for node in std::mem::take(&mut self.killed) {
+            log::info!("Restarting {node}");
+            cluster.restart(&node)?;
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> bool {
+        self.partitioned || !self.killed.is_empty()
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    Logger::new(cli.log_level, Default::default(), LogFormat::Human).install();
+    if !(cli.rate > 0.0 && cli.rate.is_finite()) {
+        bail!("The rate must be a number above 0");
+    }
+    if cli.node_count == 0 {
+        bail!("A cluster needs at least one node");
+    }
+    let seed = cli.seed.unwrap_or_else(|| Rng::from_entropy().next_u64());
+    println!("Seed: {seed}");
+    let mut rng = Rng::from_seed(seed);
+    let config = GeneratorConfig {
+        mix: cli.mix,
+        keys: cli.keys,
+        key_distribution: cli.key_distribution,
+        value_size: cli.value_size,
+    };
+    let mut generator =
+        Generator::with_config(cli.workload, config, Rng::from_seed(rng.next_u64()))?;
+
+    let mut cluster = Cluster::start(cli.command, cli.node_count, cli.log_dir, cli.link)?;
+    if let Some(capabilities) = cli.capabilities {
+        cluster.offer(capabilities);
+    }
+    cluster.init()?;
+    if cli.workload == Workload::Broadcast {
+        send_topology(&mut cluster, cli.timeout)?;
+    }
+
+    let mut nemesis = Nemesis::default();
+    let mut pending: BTreeMap<u64, Op> = BTreeMap::new();
+    let mut ops = Vec::new();
+    let period = Duration::from_secs_f64(1.0 / cli.rate);
+    let started = Instant::now();
+    let end = started + cli.time_limit;
+    let mut next_send = started;
+    let mut next_fault = started + cli.nemesis_interval;
+
+    loop {
+        let now = Instant::now();
+        if now >= end {
+            break;
+        }
+        if now >= next_send {
+            let alive = cluster.alive();
+            let node = alive[rng.below(alive.len())].clone();
+            let request = generator.next_request();
+            let msg_id = cluster.send(CLIENT, &node, request.clone())?;
+            pending.insert(
+                msg_id,
+                Op {
+                    node,
+                    request,
+                    sent_at: now,
+                    reply: None,
+                    replied_at: None,
+                },
+            );
+            next_send += period;
+        }
+        if now >= next_fault {
+            if nemesis.active() {
+                nemesis.heal(&mut cluster)?;
+            } else if !cli.nemesis.is_empty() {
+                let fault = cli.nemesis[rng.below(cli.nemesis.len())];
+                nemesis.strike(&mut cluster, fault, &mut rng)?;
+            }
+            next_fault += cli.nemesis_interval;
+        }
+        expire(&mut pending, &mut ops, now, cli.timeout);
+        let deadline = next_send.min(next_fault).min(end);
+        collect(
+            &mut cluster,
+            &mut pending,
+            &mut ops,
+            &mut generator,
+            deadline,
+        )?;
+    }
+
+    nemesis.heal(&mut cluster)?;
+    let recovered = Instant::now() + cli.recovery.max(cli.timeout);
+    collect(
+        &mut cluster,
+        &mut pending,
+        &mut ops,
+        &mut generator,
+        recovered,
+    )?;
+    expire(&mut pending, &mut ops, Instant::now(), Duration::ZERO);
+
+    let final_reads = match harness::final_read(cli.workload) {
+        Some(body) => final_reads(&mut cluster, body, cli.timeout)?,
+        None => Vec::new(),
+    };
+
+    report(&ops, &cluster, cli.time_limit);
+    let checks = harness::check(cli.workload, &ops, &final_reads);
+    let failed_nodes = cluster.stop()?;
+
+    let mut valid = true;
+    for check in &checks {
+        match &check.failure {
+            None => println!("{}: ok", check.name),
+            Some(failure) => {
+                println!("{}: FAILED: {failure}", check.name);
+                valid = false;
+            }
+        }
+    }
+    if !failed_nodes.is_empty() {
+        bail!("Nodes exited with failure: {}", failed_nodes.join(", "));
+    }
+    if !valid {
+        bail!("The run is invalid");
+    }
+    println!("Everything looks good");
+    Ok(())
+}
+
+/// Sends every node its neighbours on a grid, as Maelstrom does by default.
+fn send_topology(cluster: &mut Cluster, timeout: Duration) -> Result<(), anyhow::Error> {
+    let nodes = cluster.node_ids().to_vec();
+    let topology: BTreeMap<&String, Vec<String>> = nodes
+        .iter()
+        .map(|node| {
+            (
+                node,
+                Topology::Grid.neighbours(node, &nodes).unwrap_or_default(),
+            )
+        })
+        .collect();
+    for node in &nodes {
+        let body = json!({"type": "topology", "topology": topology});
+        if cluster
+            .request(SETUP_CLIENT, node, body, Instant::now() + timeout)?
+            .is_none()
+        {
+            bail!("{node} didn't answer topology");
+        }
+    }
+    Ok(())
+}
+
+/// Routes messages until `deadline`, matching replies to the requests they answer.
+fn collect(
+    cluster: &mut Cluster,
+    pending: &mut BTreeMap<u64, Op>,
+    ops: &mut Vec<Op>,
+    generator: &mut Generator,
+    deadline: Instant,
+) -> Result<(), anyhow::Error> {
+    while let Some(reply) = cluster.next_reply(deadline)? {
+        let Some(mut op) = reply.body["in_reply_to"]
+            .as_u64()
+            .and_then(|msg_id| pending.remove(&msg_id))
+        else {
+            continue;
+        };
+        generator.observe(&reply.body);
+        op.reply = Some(reply.body);
+        op.replied_at = Some(Instant::now());
+        ops.push(op);
+    }
+    Ok(())
+}
+
+/// Gives up on the requests that have waited for `timeout`.
+fn expire(pending: &mut BTreeMap<u64, Op>, ops: &mut Vec<Op>, now: Instant, timeout: Duration) {
+    let expired: Vec<u64> = pending
+        .iter()
+        .filter(|(_, op)| now.duration_since(op.sent_at) >= timeout)
+        .map(|(&msg_id, _)| msg_id)
+        .collect();
+    for msg_id in expired {
+        ops.extend(pending.remove(&msg_id));
+    }
+}
+
+/// Sends `body` to every node, and returns what each of them answered.
+fn final_reads(
+    cluster: &mut Cluster,
+    body: serde_json::Value,
+    timeout: Duration,
+) -> Result<Vec<Op>, anyhow::Error> {
+    let mut reads = Vec::new();
+    for node in cluster.node_ids().to_vec() {
+        let sent_at = Instant::now();
+        let reply = cluster.request(SETUP_CLIENT, &node, body.clone(), sent_at + timeout)?;
+        reads.push(Op {
+            node,
+            request: body.clone(),
+            sent_at,
+            replied_at: reply.is_some().then(Instant::now),
+            reply: reply.map(|reply| reply.body),
+        });
+    }
+    Ok(reads)
+}
+
+/// Prints how many requests succeeded, how fast, and what they cost in messages between
+/// nodes. Throughput is over the time requests were sent for.
+fn report(ops: &[Op], cluster: &Cluster, sending: Duration) {
+    let ok = ops.iter().filter(|op| op.is_ok()).count();
+    let timed_out = ops.iter().filter(|op| op.reply.is_none()).count();
+    println!(
+        "Ops: {} ({ok} ok, {} failed, {timed_out} timed out), {:.1} ok per second",
+        ops.len(),
+        ops.len() - ok - timed_out,
+        ok as f64 / sending.as_secs_f64().max(f64::EPSILON)
+    );
+    println!(
+        "Messages between nodes: {} delivered, {} dropped, {:.1} per op",
+        cluster.delivered(),
+        cluster.dropped(),
+        cluster.delivered() as f64 / ops.len().max(1) as f64
+    );
+    println!(
+        "{:<24} {:>6} {:>6} {:>9} {:>9} {:>9} {:>9}",
+        "Type", "Sent", "Ok", "p50 ms", "p95 ms", "p99 ms", "max ms"
+    );
+    let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+    for latencies in harness::latencies(ops) {
+        println!(
+            "{:<24} {:>6} {:>6} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+            latencies.kind,
+            latencies.sent,
+            latencies.ok,
+            ms(latencies.p50),
+            ms(latencies.p95),
+            ms(latencies.p99),
+            ms(latencies.max)
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use distributed_system::journal::{self, Delta};
+use serde_json::Value;
+
+/// Reads the journals nodes write with `--journal`: shows the state of a node after an
+/// event, or how the states of two nodes differ after the same number of events.
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Prints the state of a node after an event.
+    State {
+        /// The journal of the node.
+        journal: PathBuf,
+        /// The index of the event, the last one if left out.
+        #[arg(long, value_name = "INDEX")]
+        at: Option<u64>,
+    },
+    /// Prints the values that differ between the states of two nodes after an event, one per
+    /// line as `path: first -> second`.
+    Diff {
+        /// The journal of the first node.
+        first: PathBuf,
+        /// The journal of the second node.
+        second: PathBuf,
+        /// The index of the event in both journals, the last one of each if left out.
+        #[arg(long, value_name = "INDEX")]
+        at: Option<u64>,
+    },
+}
+
+/// The state in the journal at `path` after the event with index `at`.
+fn state(path: &PathBuf, at: Option<u64>) -> Result<Value, anyhow::Error> {
+    let entries = journal::read(path)?;
+    Ok(journal::state_at(&entries, at.unwrap_or(u64::MAX)))
+}
+
+fn show(value: Option<&Value>) -> String {
+    value.map_or_else(|| "(none)".to_string(), Value::to_string)
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    match Cli::parse().command {
+        Command::State { journal, at } => {
+            println!("{}", serde_json::to_string_pretty(&state(&journal, at)?)?);
+        }
+        Command::Diff { first, second, at } => {
+            let first = state(&first, at)?;
+            let second = state(&second, at)?;
+            let Delta { set, unset } = Delta::between(&first, &second);
+            let mut paths: Vec<&String> = set.keys().chain(&unset).collect();
+            paths.sort();
+            for path in paths {
+                let shown = if path.is_empty() { "(root)" } else { path };
+                println!(
+                    "{shown}: {} -> {}",
+                    show(first.pointer(path)),
+                    show(second.pointer(path))
+                );
+            }
+        }
+    }
+    Ok(())
+}
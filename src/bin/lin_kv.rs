@@ -0,0 +1,866 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::event_queue::EventSender;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::raft::{self, Proposal, Raft, RaftBody, StateMachine};
+use distributed_system::rpc::PendingRequests;
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+type RaftMessage = distributed_system::Message<RaftBody<Command>>;
+
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a request forwarded to the leader may go unanswered before the client is told
+/// that its outcome is unknown.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Read {
+        msg_id: u64,
+        key: Value,
+    },
+
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        value: Value,
+    },
+
+    Write {
+        msg_id: u64,
+        key: Value,
+        value: Value,
+    },
+
+    WriteOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Cas {
+        msg_id: u64,
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+
+    CasOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// Asks the leader to hand leadership over to `target`, e.g. before shutting it down.
+    /// Answered once this node has stepped down.
+    TransferLeadership {
+        msg_id: u64,
+        target: String,
+    },
+
+    TransferLeadershipOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// Asks the leader to change the voting members of the cluster to `members`. Answered
+    /// once the new configuration is committed.
+    Reconfigure {
+        msg_id: u64,
+        members: Vec<String>,
+    },
+
+    ReconfigureOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+/// A client request as it is stored in the Raft log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Command {
+    Read {
+        key: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    },
+}
+
+impl Command {
+    fn into_request(self, msg_id: u64) -> Body {
+        match self {
+            Command::Read { key } => Body::Read { msg_id, key },
+            Command::Write { key, value } => Body::Write { msg_id, key, value },
+            Command::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => Body::Cas {
+                msg_id,
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            },
+        }
+    }
+}
+
+enum Outcome {
+    Read(Value),
+    Written,
+    Swapped,
+    Failed(ErrorCode, String),
+}
+
+impl Outcome {
+    /// Returns the outcome carried by a reply, with the request it answers.
+    fn from_reply(body: &Body) -> Option<(u64, Outcome)> {
+        match body {
+            Body::ReadOk {
+                in_reply_to, value, ..
+            } => Some((*in_reply_to, Outcome::Read(value.clone()))),
+            Body::WriteOk { in_reply_to, .. } => Some((*in_reply_to, Outcome::Written)),
+            Body::CasOk { in_reply_to, .. } => Some((*in_reply_to, Outcome::Swapped)),
+            Body::Error(error) => Some((
+                error.in_reply_to,
+                Outcome::Failed(error.code, error.text.clone()),
+            )),
+            _ => None,
+        }
+    }
+
+    fn into_reply(self, msg_id: u64, in_reply_to: u64) -> Body {
+        match self {
+            Outcome::Read(value) => Body::ReadOk {
+                msg_id,
+                in_reply_to,
+                value,
+            },
+            Outcome::Written => Body::WriteOk {
+                msg_id,
+                in_reply_to,
+            },
+            Outcome::Swapped => Body::CasOk {
+                msg_id,
+                in_reply_to,
+            },
+            Outcome::Failed(code, text) => Body::Error(ErrorBody::new(in_reply_to, code, text)),
+        }
+    }
+}
+
+/// Keys are JSON values, so they are stored by their JSON text.
+#[derive(Debug, Default)]
+struct KvStore {
+    values: HashMap<String, Value>,
+}
+
+impl KvStore {
+    fn read(&self, key: &Value) -> Outcome {
+        match self.values.get(&key.to_string()) {
+            Some(value) => Outcome::Read(value.clone()),
+            None => Outcome::Failed(ErrorCode::KeyDoesNotExist, format!("Key {key} not found")),
+        }
+    }
+}
+
+impl StateMachine for KvStore {
+    type Command = Command;
+    type Output = Outcome;
+    type Snapshot = HashMap<String, Value>;
+
+    fn apply(&mut self, command: &Command) -> Outcome {
+        match command {
+            Command::Read { key } => self.read(key),
+
+            Command::Write { key, value } => {
+                self.values.insert(key.to_string(), value.clone());
+                Outcome::Written
+            }
+
+            Command::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get_mut(&key.to_string()) {
+                Some(value) if value == from => {
+                    *value = to.clone();
+                    Outcome::Swapped
+                }
+                Some(value) => Outcome::Failed(
+                    ErrorCode::PreconditionFailed,
+                    format!("Expected {from}, but key {key} has value {value}"),
+                ),
+                None if *create_if_not_exists => {
+                    self.values.insert(key.to_string(), to.clone());
+                    Outcome::Swapped
+                }
+                None => Outcome::Failed(ErrorCode::KeyDoesNotExist, format!("Key {key} not found")),
+            },
+        }
+    }
+
+    fn snapshot(&self) -> HashMap<String, Value> {
+        self.values.clone()
+    }
+
+    fn restore(&mut self, snapshot: HashMap<String, Value>) {
+        self.values = snapshot;
+    }
+}
+
+enum Event {
+    Message(Message),
+    Raft(RaftMessage),
+    Rejected(Message),
+    Tick,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        let responses = match self {
+            Event::Message(message) => node.process_received_message(message, sender.clone()),
+
+            Event::Raft(message) => {
+                let now = node.time.now();
+                if let Some(raft) = node.raft.as_mut() {
+                    raft.handle(message.clone(), now);
+                }
+                Vec::new()
+            }
+
+            Event::Rejected(error_reply) => return error_reply.send(&mut output),
+
+            Event::Tick => {
+                let now = node.time.now();
+                if let Some(raft) = node.raft.as_mut() {
+                    raft.tick(now);
+                }
+                node.expire_forwarded()
+            }
+        };
+
+        for response in responses {
+            response.send(&mut output)?;
+        }
+        node.flush_raft(output)
+    }
+}
+
+/// A client request proposed by this node, waiting for its log entry to be applied.
+struct Waiting {
+    proposal: Proposal,
+    client: String,
+    in_reply_to: u64,
+}
+
+/// A read served by this node as the leader without going through the log.
+struct PendingRead {
+    client: String,
+    in_reply_to: u64,
+    key: Value,
+}
+
+struct Node {
+    node_id: String,
+    msg_id: u64,
+    raft: Option<Raft<KvStore>>,
+    waiting: HashMap<u64, Waiting>,
+    reads: HashMap<u64, PendingRead>,
+    forwarded: PendingRequests<(String, u64)>,
+    /// Client and message id of the `transfer_leadership` request in progress.
+    transfer: Option<(String, u64)>,
+    /// Client, message id, and requested members of the `reconfigure` request in progress.
+    reconfiguration: Option<(String, u64, BTreeSet<String>)>,
+    timer: Option<Timer>,
+    /// Where Raft reads the time for its elections and heartbeats, and the node schedules
+    /// its ticks.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            msg_id: 0,
+            raft: None,
+            waiting: HashMap::new(),
+            reads: HashMap::new(),
+            forwarded: PendingRequests::with_clock(time.clone()),
+            transfer: None,
+            reconfiguration: None,
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) {
+        self.raft = Some(Raft::new(
+            node_id.clone(),
+            node_ids,
+            KvStore::default(),
+            self.time.now(),
+        ));
+        self.node_id = node_id;
+
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            TICK_INTERVAL,
+            sender,
+            || Input::Event(Event::Tick),
+        ));
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    /// Sends the messages produced by Raft and replies to clients whose commands were applied.
+    fn flush_raft(&mut self, output: &mut Output) -> Result<(), anyhow::Error> {
+        let Some(raft) = self.raft.as_mut() else {
+            return Ok(());
+        };
+        let messages = raft.take_messages();
+        let applied = raft.take_applied();
+        let ready_reads: Vec<(PendingRead, Outcome)> = raft
+            .take_reads()
+            .into_iter()
+            .filter_map(|id| self.reads.remove(&id))
+            .map(|read| {
+                let outcome = raft.state_machine().read(&read.key);
+                (read, outcome)
+            })
+            .collect();
+        let last_applied = raft.last_applied();
+        let transfer_finished = raft.transferring_to().is_none();
+        let is_leader = raft.is_leader();
+        let voters = raft.configuration().voters.clone();
+        let reconfigured = !raft.configuration().is_joint() && raft.configuration_committed();
+
+        for message in messages {
+            message.send(output)?;
+        }
+
+        for (read, outcome) in ready_reads {
+            Message {
+                src: self.node_id.clone(),
+                dest: read.client,
+                body: outcome.into_reply(self.incremented_msg_id(), read.in_reply_to),
+            }
+            .send(output)?;
+        }
+
+        // Reads are dropped when leadership is lost, and nothing was changed by them.
+        if !is_leader {
+            for (_, read) in self.reads.drain() {
+                Message {
+                    src: self.node_id.clone(),
+                    dest: read.client,
+                    body: Body::Error(ErrorBody::new(
+                        read.in_reply_to,
+                        ErrorCode::TemporarilyUnavailable,
+                        "Leadership was lost before the read was confirmed",
+                    )),
+                }
+                .send(output)?;
+            }
+        }
+
+        for applied in applied {
+            let Some(waiting) = self.waiting.remove(&applied.index) else {
+                continue;
+            };
+
+            let body = if applied.term == waiting.proposal.term {
+                applied
+                    .output
+                    .into_reply(self.incremented_msg_id(), waiting.in_reply_to)
+            } else {
+                Body::Error(ErrorBody::new(
+                    waiting.in_reply_to,
+                    ErrorCode::TemporarilyUnavailable,
+                    "Leadership was lost before the request was committed",
+                ))
+            };
+
+            Message {
+                src: self.node_id.clone(),
+                dest: waiting.client,
+                body,
+            }
+            .send(output)?;
+        }
+
+        // Entries covered by a snapshot installed from the leader are never applied one by
+        // one, so whether those requests took effect is unknown.
+        let skipped: Vec<u64> = self
+            .waiting
+            .keys()
+            .filter(|&&index| index <= last_applied)
+            .copied()
+            .collect();
+        for index in skipped {
+            if let Some(waiting) = self.waiting.remove(&index) {
+                Message {
+                    src: self.node_id.clone(),
+                    dest: waiting.client,
+                    body: Body::Error(ErrorBody::new(
+                        waiting.in_reply_to,
+                        ErrorCode::Timeout,
+                        "Request was overtaken by a snapshot from the leader",
+                    )),
+                }
+                .send(output)?;
+            }
+        }
+
+        if transfer_finished {
+            if let Some((client, in_reply_to)) = self.transfer.take() {
+                let body = if is_leader {
+                    Body::Error(ErrorBody::new(
+                        in_reply_to,
+                        ErrorCode::Timeout,
+                        "Target did not take over leadership in time",
+                    ))
+                } else {
+                    Body::TransferLeadershipOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to,
+                    }
+                };
+                Message {
+                    src: self.node_id.clone(),
+                    dest: client,
+                    body,
+                }
+                .send(output)?;
+            }
+        }
+
+        if let Some((client, in_reply_to, members)) = self.reconfiguration.take() {
+            let body = if reconfigured && voters == members {
+                Body::ReconfigureOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to,
+                }
+            } else if !is_leader {
+                Body::Error(ErrorBody::new(
+                    in_reply_to,
+                    ErrorCode::Timeout,
+                    "Leadership was lost before the reconfiguration was committed",
+                ))
+            } else {
+                self.reconfiguration = Some((client, in_reply_to, members));
+                return Ok(());
+            };
+
+            Message {
+                src: self.node_id.clone(),
+                dest: client,
+                body,
+            }
+            .send(output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serves the read without appending it to the log when this node is the leader, and
+    /// treats it like any other command otherwise.
+    fn read(&mut self, client: &str, in_reply_to: u64, key: Value) -> Vec<Message> {
+        let read_id = self.raft.as_mut().and_then(Raft::read_index);
+        let Some(read_id) = read_id else {
+            return self.submit(client, in_reply_to, Command::Read { key });
+        };
+
+        self.reads.insert(
+            read_id,
+            PendingRead {
+                client: client.to_string(),
+                in_reply_to,
+                key,
+            },
+        );
+        Vec::new()
+    }
+
+    /// Proposes the command when this node is the leader, and forwards it to the leader
+    /// otherwise.
+    fn submit(&mut self, client: &str, in_reply_to: u64, command: Command) -> Vec<Message> {
+        let error = |code, text: &str| {
+            vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body: Body::Error(ErrorBody::new(in_reply_to, code, text)),
+            }]
+        };
+
+        let Some(raft) = self.raft.as_mut() else {
+            return error(ErrorCode::TemporarilyUnavailable, "Node is not initialized");
+        };
+
+        if let Some(proposal) = raft.propose(command.clone()) {
+            self.waiting.insert(
+                proposal.index,
+                Waiting {
+                    proposal,
+                    client: client.to_string(),
+                    in_reply_to,
+                },
+            );
+            return Vec::new();
+        }
+
+        let Some(leader) = raft.leader().map(str::to_string) else {
+            return error(ErrorCode::TemporarilyUnavailable, "No leader elected yet");
+        };
+        if leader == self.node_id {
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                "Leadership is being transferred",
+            );
+        }
+
+        let msg_id = self.incremented_msg_id();
+        self.forwarded
+            .insert(msg_id, (client.to_string(), in_reply_to));
+
+        vec![Message {
+            src: self.node_id.clone(),
+            dest: leader,
+            body: command.into_request(msg_id),
+        }]
+    }
+
+    /// Starts handing leadership over to `target`; the request is answered by `flush_raft`
+    /// once the transfer has finished.
+    fn transfer_leadership(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        target: &str,
+    ) -> Vec<Message> {
+        let reply = |body| {
+            vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body,
+            }]
+        };
+        let error =
+            |code, text: String| reply(Body::Error(ErrorBody::new(in_reply_to, code, text)));
+
+        let now = self.time.now();
+        let Some(raft) = self.raft.as_mut() else {
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                "Node is not initialized".to_string(),
+            );
+        };
+
+        if raft.leader() == Some(target) {
+            self.msg_id += 1;
+            return reply(Body::TransferLeadershipOk {
+                msg_id: self.msg_id,
+                in_reply_to,
+            });
+        }
+        if !raft.is_leader() {
+            let leader = raft.leader().unwrap_or("unknown");
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                format!("Only the leader can transfer leadership, current leader is {leader}"),
+            );
+        }
+        if self.transfer.is_some() {
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                "Another leadership transfer is in progress".to_string(),
+            );
+        }
+        if !raft.transfer_leadership(target, now) {
+            return error(
+                ErrorCode::MalformedRequest,
+                format!("Node {target} is not part of the cluster"),
+            );
+        }
+
+        self.transfer = Some((client.to_string(), in_reply_to));
+        Vec::new()
+    }
+
+    /// Starts changing the voting members; the request is answered by `flush_raft` once the
+    /// new configuration is committed.
+    fn reconfigure(&mut self, client: &str, in_reply_to: u64, members: &[String]) -> Vec<Message> {
+        let error = |code, text: &str| {
+            vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body: Body::Error(ErrorBody::new(in_reply_to, code, text)),
+            }]
+        };
+
+        let Some(raft) = self.raft.as_mut() else {
+            return error(ErrorCode::TemporarilyUnavailable, "Node is not initialized");
+        };
+
+        if !raft.is_leader() {
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                "Only the leader can change the members of the cluster",
+            );
+        }
+        if members.is_empty() {
+            return error(
+                ErrorCode::MalformedRequest,
+                "Cluster needs at least one member",
+            );
+        }
+        if self.reconfiguration.is_some() || !raft.reconfigure(members.iter().cloned()) {
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                "Another membership change or leadership transfer is in progress",
+            );
+        }
+
+        let members = members.iter().cloned().collect();
+        self.reconfiguration = Some((client.to_string(), in_reply_to, members));
+        Vec::new()
+    }
+
+    /// Relays the leader's reply to a forwarded request back to the client.
+    fn relay(&mut self, body: &Body) -> Vec<Message> {
+        let Some((in_reply_to, outcome)) = Outcome::from_reply(body) else {
+            return Vec::new();
+        };
+        let Some((client, client_msg_id)) = self.forwarded.complete(in_reply_to) else {
+            return Vec::new();
+        };
+
+        vec![Message {
+            src: self.node_id.clone(),
+            dest: client,
+            body: outcome.into_reply(self.incremented_msg_id(), client_msg_id),
+        }]
+    }
+
+    /// A forwarded request may or may not have been applied, so its outcome is unknown.
+    fn expire_forwarded(&mut self) -> Vec<Message> {
+        self.forwarded
+            .expire(FORWARD_TIMEOUT)
+            .into_iter()
+            .map(|(_, (client, in_reply_to))| Message {
+                src: self.node_id.clone(),
+                dest: client,
+                body: Body::Error(ErrorBody::new(
+                    in_reply_to,
+                    ErrorCode::Timeout,
+                    "Leader did not answer the forwarded request",
+                )),
+            })
+            .collect()
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Vec<Message> {
+        let build_message_from = |body: Body| -> Vec<Message> {
+            vec![Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }]
+        };
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids, sender);
+
+                build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                })
+            }
+
+            Body::Read { msg_id, key } => self.read(&message.src, *msg_id, key.take()),
+
+            Body::Write { msg_id, key, value } => {
+                let command = Command::Write {
+                    key: key.take(),
+                    value: value.take(),
+                };
+                self.submit(&message.src, *msg_id, command)
+            }
+
+            Body::Cas {
+                msg_id,
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                let command = Command::Cas {
+                    key: key.take(),
+                    from: from.take(),
+                    to: to.take(),
+                    create_if_not_exists: *create_if_not_exists,
+                };
+                self.submit(&message.src, *msg_id, command)
+            }
+
+            Body::TransferLeadership { msg_id, target } => {
+                self.transfer_leadership(&message.src, *msg_id, target)
+            }
+
+            Body::Reconfigure { msg_id, members } => {
+                self.reconfigure(&message.src, *msg_id, members)
+            }
+
+            Body::ReadOk { .. } | Body::WriteOk { .. } | Body::CasOk { .. } | Body::Error(_) => {
+                self.relay(&message.body)
+            }
+
+            Body::InitOk { msg_id, .. }
+            | Body::TransferLeadershipOk { msg_id, .. }
+            | Body::ReconfigureOk { msg_id, .. } => {
+                build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Lin-kv node does not accept client replies",
+                )))
+            }
+        }
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn std::io::Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        Ok(())
+    }
+
+    fn debug_state(&self) -> Value {
+        let raft = self.raft.as_ref().map(|raft| {
+            serde_json::json!({
+                "role": format!("{:?}", raft.role()),
+                "term": raft.term(),
+                "leader": raft.leader(),
+                "members": raft.configuration().members().collect::<Vec<_>>(),
+                "last_index": raft.last_index(),
+                "commit_index": raft.commit_index(),
+                "last_applied": raft.last_applied(),
+            })
+        });
+        serde_json::json!({
+            "node_id": self.node_id,
+            "raft": raft,
+            "waiting": self.waiting.len(),
+            "reads": self.reads.len(),
+            "forwarded": self.forwarded.len(),
+        })
+    }
+}
+
+/// Runs a node of the lin-kv workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    config::parse::<Cli>("lin-kv")?.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(Arc::new(SystemClock));
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), |line| {
+        let event = if let Some(message) = raft::parse(line)? {
+            Event::Raft(message)
+        } else {
+            match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+                Ok(msg) => Event::Message(msg),
+                Err(error_reply) => Event::Rejected(error_reply),
+            }
+        };
+        Ok(event)
+    });
+
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
@@ -0,0 +1,241 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::Stdio;
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use distributed_system::chunk;
+use distributed_system::config;
+use distributed_system::kafka;
+use distributed_system::message::RawMessage;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime;
+use distributed_system::Message;
+use serde::{Deserialize, Serialize};
+
+// The `main` of each workload is only used by the workload's own binary, which also checks
+// the rest of it for dead code.
+#[allow(dead_code)]
+#[path = "broadcast.rs"]
+mod broadcast;
+#[allow(dead_code)]
+#[path = "echo.rs"]
+mod echo;
+#[allow(dead_code)]
+#[path = "g_counter.rs"]
+mod g_counter;
+#[allow(dead_code)]
+#[path = "txn.rs"]
+mod txn;
+#[allow(dead_code)]
+#[path = "unique_ids.rs"]
+mod unique_ids;
+
+/// Runs a node of any workload: the one given, or else the one the first client request
+/// belongs to.
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(subcommand)]
+    workload: Option<Command>,
+}
+
+/// A workload with its options, which are those of the workload's own binary.
+#[derive(Debug, Subcommand)]
+enum Command {
+    Echo(echo::Cli),
+    UniqueIds(unique_ids::Cli),
+    Broadcast(broadcast::Cli),
+    GCounter(g_counter::Cli),
+    Kafka(kafka::Cli),
+    Txn(txn::Cli),
+}
+
+impl Command {
+    /// Runs the workload over stdin and stdout.
+    fn run(self) -> Result<(), anyhow::Error> {
+        match self {
+            Command::Echo(cli) => echo::run(cli),
+            Command::UniqueIds(cli) => unique_ids::run(cli),
+            Command::Broadcast(cli) => broadcast::run(cli),
+            Command::GCounter(cli) => g_counter::run(cli),
+            Command::Kafka(cli) => kafka::run(cli),
+            Command::Txn(cli) => txn::run(cli),
+        }
+    }
+}
+
+/// The workloads this binary can tell apart by their requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Workload {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    GCounter,
+    Kafka,
+    Txn,
+}
+
+impl Workload {
+    /// The workload a client request belongs to. A broadcast test always starts with
+    /// `topology`, so a `read` that comes first is one of a counter.
+    fn of_request(kind: &str) -> Option<Self> {
+        match kind {
+            "echo" => Some(Workload::Echo),
+            "generate" => Some(Workload::UniqueIds),
+            "topology" | "broadcast" => Some(Workload::Broadcast),
+            "add" | "read" => Some(Workload::GCounter),
+            "send" | "poll" | "commit_offsets" | "list_committed_offsets" => Some(Workload::Kafka),
+            "txn" => Some(Workload::Txn),
+            _ => None,
+        }
+    }
+
+    /// The subcommand that runs the workload.
+    fn name(self) -> &'static str {
+        match self {
+            Workload::Echo => "echo",
+            Workload::UniqueIds => "unique-ids",
+            Workload::Broadcast => "broadcast",
+            Workload::GCounter => "g-counter",
+            Workload::Kafka => "kafka",
+            Workload::Txn => "txn",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename = "init")]
+struct Init {
+    msg_id: u64,
+    #[serde(flatten)]
+    handshake: Handshake,
+}
+
+/// Sent without a `msg_id`, so that it can't clash with those of the workload.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "init_ok")]
+struct InitOk {
+    in_reply_to: u64,
+    #[serde(flatten)]
+    handshake: Handshake,
+}
+
+fn is_client(id: &str) -> bool {
+    id.starts_with('c')
+}
+
+/// Reads stdin until a client request shows which workload the test runs. Maelstrom only
+/// sends requests once every node answered `init`, so `init` is answered here. Returns the
+/// workload, if stdin didn't end first, and the lines read so far.
+fn detect(input: &mut impl BufRead) -> Result<(Option<Workload>, Vec<String>), anyhow::Error> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input
+            .read_line(&mut line)
+            .context("Failed to read from STDIN.")?
+            == 0
+        {
+            return Ok((None, lines));
+        }
+        lines.push(line.clone());
+
+        if runtime::init_node_id(&line).is_some() {
+            let init: Message<Init> = serde_json::from_str(&line)
+                .context("Failed to deserialize provided input to STDIN.")?;
+            protocol::negotiate(&init.body.handshake);
+            let reply = init.reply_ref(InitOk {
+                in_reply_to: init.body.msg_id,
+                handshake: protocol::answer(),
+            });
+            let mut stdout = std::io::stdout().lock();
+            reply.send(&mut stdout)?;
+            stdout.flush().context("Failed to flush STDOUT.")?;
+            continue;
+        }
+
+        // Other nodes only send their own messages once they know the workload, and those
+        // are handed to it along with the rest.
+        let message = RawMessage::parse_raw(&line)?;
+        if !is_client(&message.src) {
+            continue;
+        }
+        let kind = message.kind().unwrap_or_default();
+        match Workload::of_request(&kind) {
+            Some(workload) => return Ok((Some(workload), lines)),
+            None => bail!("Can't tell the workload from a {kind} request"),
+        }
+    }
+}
+
+/// Runs `workload` in a child process of this binary, handing it the lines read already
+/// followed by the rest of stdin. The `init_ok` it sends is dropped, as `init` was answered.
+fn hand_over(workload: Workload, lines: Vec<String>) -> Result<(), anyhow::Error> {
+    let exe = std::env::current_exe().context("Failed to find the node binary")?;
+    let mut child = std::process::Command::new(exe)
+        .arg(workload.name())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start the {} node", workload.name()))?;
+
+    let mut child_stdin = child.stdin.take().context("Child has no stdin")?;
+    let forward = std::thread::spawn(move || -> std::io::Result<()> {
+        for line in lines {
+            child_stdin.write_all(line.as_bytes())?;
+        }
+        child_stdin.flush()?;
+        std::io::copy(&mut std::io::stdin().lock(), &mut child_stdin)?;
+        Ok(())
+    });
+
+    let mut child_stdout = BufReader::new(child.stdout.take().context("Child has no stdout")?);
+    let mut stdout = std::io::stdout().lock();
+    let mut line = String::new();
+    while child_stdout
+        .read_line(&mut line)
+        .context("Failed to read from the node.")?
+        > 0
+    {
+        let is_init_ok = RawMessage::parse_raw(&line)
+            .is_ok_and(|message| message.kind().as_deref() == Some("init_ok"));
+        if !is_init_ok {
+            stdout
+                .write_all(line.as_bytes())
+                .context("Failed to write to STDOUT.")?;
+            stdout.flush().context("Failed to flush STDOUT.")?;
+        }
+        line.clear();
+        if is_init_ok {
+            break;
+        }
+    }
+    std::io::copy(&mut child_stdout, &mut stdout).context("Failed to write to STDOUT.")?;
+    stdout.flush().context("Failed to flush STDOUT.")?;
+
+    let status = child.wait().context("Failed to wait for the node")?;
+    if !status.success() {
+        // The forwarding thread may still wait for stdin, and is stopped by exiting.
+        bail!("The {} node failed: {status}", workload.name());
+    }
+    forward
+        .join()
+        .map_err(|e| anyhow::anyhow!("Thread panicked: {:?}", e))?
+        .context("Failed to forward STDIN to the node.")
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    if let Some(workload) = config::parse_subcommand::<Cli>()?.workload {
+        return workload.run();
+    }
+
+    // `init` is answered before the workload is known, so it agrees to what any of them
+    // supports.
+    broadcast::support_extensions();
+    protocol::support(chunk::CAPABILITY, 1);
+    let (workload, lines) = detect(&mut std::io::stdin().lock())?;
+    match workload {
+        Some(workload) => hand_over(workload, lines),
+        None => Ok(()),
+    }
+}
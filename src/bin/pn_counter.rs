@@ -0,0 +1,527 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::crdt::{Crdt, GCounter, Merge};
+use distributed_system::event_queue::{EventReceiver, EventSender};
+use distributed_system::membership::MembershipEvent;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::reply_cache::ReplyCache;
+use distributed_system::rng::Rng;
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::scuttlebutt::Digest;
+use distributed_system::sim::SimNode;
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+/// Number of recent `add` replies remembered to answer retried requests.
+const REPLY_CACHE_SIZE: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Add {
+        msg_id: u64,
+        delta: i64,
+    },
+
+    AddOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Read {
+        msg_id: u64,
+    },
+
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        value: i64,
+    },
+
+    Sync {
+        msg_id: u64,
+        increments: GCounter,
+        decrements: GCounter,
+    },
+
+    /// In scuttlebutt mode, the version of every node's counts the sender has.
+    Digest {
+        msg_id: u64,
+        digest: Digest,
+    },
+
+    /// The counts newer than those of the digest, and the digest of the replying node, to
+    /// be answered with a `sync` of the counts it lacks.
+    DigestOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        digest: Digest,
+        increments: GCounter,
+        decrements: GCounter,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+enum Event {
+    Message(Message),
+    Rejected(Message),
+    SyncRequested,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut impl Write,
+    ) -> Result<(), anyhow::Error> {
+        let responses = match self {
+            Event::Message(message) => node.process_received_message(message, sender.clone()),
+            Event::Rejected(error_reply) => return error_reply.send(&mut output),
+            Event::SyncRequested => node.sync_round(),
+        };
+
+        for response in responses {
+            response.send(&mut output)?;
+        }
+        Ok(())
+    }
+}
+
+struct Node {
+    node_id: String,
+    cluster: Vec<String>,
+    msg_id: u64,
+    increments: GCounter,
+    decrements: GCounter,
+    replies: ReplyCache<Body>,
+    /// Whether an `add` arrived since the last sync round.
+    added: bool,
+    /// Reconciles with a random peer each round rather than syncing with every node.
+    scuttlebutt: bool,
+    sync_interval: Duration,
+    full_sync_interval: Duration,
+    last_full_sync: Instant,
+    rng: Rng,
+    timer: Option<Timer>,
+    /// Where the node schedules its sync rounds.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(cli: &Cli, time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            cluster: Vec::new(),
+            msg_id: 0,
+            increments: GCounter::new(),
+            decrements: GCounter::new(),
+            replies: ReplyCache::new(REPLY_CACHE_SIZE),
+            added: false,
+            scuttlebutt: cli.scuttlebutt,
+            sync_interval: cli.sync_interval,
+            full_sync_interval: cli.full_sync_interval,
+            last_full_sync: time.now(),
+            rng: Rng::from_entropy(),
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) {
+        self.node_id = node_id;
+        self.cluster.extend_from_slice(node_ids);
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            self.sync_interval,
+            sender,
+            || Input::Event(Event::SyncRequested),
+        ));
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    /// Takes the highest counts known of each node, whether the node was in the cluster at
+    /// `init` or not. Nodes first heard of join the cluster. Entries that aren't of a node
+    /// are left out.
+    fn merge(&mut self, increments: &mut GCounter, decrements: &mut GCounter) {
+        for counters in [&mut *increments, &mut *decrements] {
+            counters.retain(|node_id| {
+                let is_node = runtime::is_node_id(node_id);
+                if !is_node {
+                    log::warn!("Dropped the counts of {node_id}, which isn't a node");
+                }
+                is_node
+            });
+        }
+        let mut joined: Vec<String> = increments
+            .nodes()
+            .chain(decrements.nodes())
+            .filter(|node| !self.cluster.iter().any(|id| id == node))
+            .map(str::to_string)
+            .collect();
+        joined.sort();
+        joined.dedup();
+        for node in joined {
+            self.on_membership_event(MembershipEvent::Joined(node));
+        }
+        self.increments.merge(increments);
+        self.decrements.merge(decrements);
+    }
+
+    /// Syncs with nodes that joined from the next round on.
+    fn on_membership_event(&mut self, event: MembershipEvent) {
+        log::info!("Membership changed: {event:?}");
+        if let MembershipEvent::Joined(node) = event {
+            self.cluster.push(node);
+        }
+    }
+
+    /// Sends both maps of counts to every other node if an `add` arrived since the last
+    /// round, or once every full sync interval, whatever the adds. However many adds arrive,
+    /// a round sends one sync to each node, and nodes that lost a sync catch up.
+    fn sync_round(&mut self) -> Vec<Message> {
+        if self.scuttlebutt {
+            return self.scuttlebutt_round();
+        }
+        let now = self.time.now();
+        let full_sync_due = now >= self.last_full_sync + self.full_sync_interval;
+        if !self.added && !full_sync_due {
+            return Vec::new();
+        }
+        self.added = false;
+        if full_sync_due {
+            self.last_full_sync = now;
+        }
+
+        let msg_id = self.incremented_msg_id();
+        self.cluster
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .map(|peer| Message {
+                src: self.node_id.clone(),
+                dest: peer.clone(),
+                body: Body::Sync {
+                    msg_id,
+                    increments: self.increments.clone(),
+                    decrements: self.decrements.clone(),
+                },
+            })
+            .collect()
+    }
+
+    /// Sends the digest of this node to a random peer.
+    fn scuttlebutt_round(&mut self) -> Vec<Message> {
+        let peers: Vec<String> = self
+            .cluster
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .cloned()
+            .collect();
+        let Some(peer) = self.rng.sample(&peers, 1).pop() else {
+            return Vec::new();
+        };
+        let msg_id = self.incremented_msg_id();
+        vec![Message {
+            src: self.node_id.clone(),
+            dest: peer,
+            body: Body::Digest {
+                msg_id,
+                digest: self.digest(),
+            },
+        }]
+    }
+
+    /// The sum of both counts of every node, which grows whenever either does, and so is
+    /// the version of its entry.
+    fn digest(&self) -> Digest {
+        let mut digest = Digest::new();
+        for (node, _) in self.increments.iter().chain(self.decrements.iter()) {
+            let version = self.increments.get(node) + self.decrements.get(node);
+            digest.observe(node, version);
+        }
+        digest
+    }
+
+    /// The increments and decrements newer than those of `digest`.
+    fn newer_than(&self, digest: &Digest) -> (GCounter, GCounter) {
+        let ours = self.digest();
+        let mut increments = GCounter::new();
+        let mut decrements = GCounter::new();
+        for node in digest.missing_from(&ours) {
+            increments.merge(&self.increments.delta(node));
+            decrements.merge(&self.decrements.delta(node));
+        }
+        (increments, decrements)
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Vec<Message> {
+        let mut responses: Vec<Message> = Vec::new();
+
+        let build_message_from = |body: Body| -> Message {
+            Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }
+        };
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids, sender);
+
+                responses.push(build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                }));
+            }
+
+            Body::Add { msg_id, delta } => {
+                // A retried add was already counted, so it only gets the original reply.
+                if let Some(reply) = self.replies.get(&message.src, *msg_id) {
+                    responses.push(reply);
+                    return responses;
+                }
+
+                let counters = if *delta >= 0 {
+                    &mut self.increments
+                } else {
+                    &mut self.decrements
+                };
+                counters.increment(&self.node_id, delta.unsigned_abs());
+
+                self.added = true;
+
+                let reply = build_message_from(Body::AddOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                });
+                self.replies.insert(&message.src, *msg_id, reply.clone());
+                responses.push(reply);
+            }
+
+            Body::Read { msg_id } => {
+                responses.push(build_message_from(Body::ReadOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    value: self.value(),
+                }));
+            }
+
+            Body::Sync {
+                increments,
+                decrements,
+                ..
+            } => {
+                self.merge(increments, decrements);
+            }
+
+            Body::Digest { msg_id, digest } => {
+                let (increments, decrements) = self.newer_than(digest);
+                responses.push(build_message_from(Body::DigestOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    digest: self.digest(),
+                    increments,
+                    decrements,
+                }));
+            }
+
+            Body::DigestOk {
+                digest,
+                increments,
+                decrements,
+                ..
+            } => {
+                self.merge(increments, decrements);
+                let (increments, decrements) = self.newer_than(digest);
+                if !increments.is_empty() || !decrements.is_empty() {
+                    responses.push(build_message_from(Body::Sync {
+                        msg_id: self.incremented_msg_id(),
+                        increments,
+                        decrements,
+                    }));
+                }
+            }
+
+            Body::InitOk { msg_id, .. }
+            | Body::AddOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. } => {
+                responses.push(build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Counter node does not accept replies",
+                ))));
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+            }
+        }
+
+        responses
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        Ok(())
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node_id": self.node_id,
+            "cluster": self.cluster,
+            "increments": self.increments,
+            "decrements": self.decrements,
+            "cached_replies": self.replies.len(),
+        })
+    }
+}
+
+/// Runs a node of the pn-counter workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Reconciles with one random peer each round instead of sending every node both maps
+    /// of counts, scuttlebutt style: the two exchange the version of every node's counts
+    /// they have, and send each other only the counts the other is behind on.
+    #[arg(long, env = "PN_COUNTER_SCUTTLEBUTT")]
+    scuttlebutt: bool,
+    /// Milliseconds between sync rounds, which send the counts once an `add` changed them.
+    #[arg(
+        long = "sync-interval-ms",
+        env = "PN_COUNTER_SYNC_INTERVAL_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "100"
+    )]
+    sync_interval: Duration,
+    /// Milliseconds between rounds that send the counts even if no `add` changed them, so
+    /// that nodes that lost a sync catch up.
+    #[arg(
+        long = "full-sync-ms",
+        env = "PN_COUNTER_FULL_SYNC_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "1000"
+    )]
+    full_sync_interval: Duration,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = config::parse::<Cli>("pn-counter")?;
+    cli.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(&cli, Arc::new(SystemClock));
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), parse_event);
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn parse_event(line: &[u8]) -> Result<Event, anyhow::Error> {
+    let event =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(message) => Event::Message(message),
+            Err(error_reply) => Event::Rejected(error_reply),
+        };
+    Ok(event)
+}
+
+/// A pn-counter node for a `sim::Network`, which syncs as scheduled by `time`.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Box<dyn SimNode> {
+    let (sender, receiver) = runtime::event_channel();
+    Box::new(Simulated {
+        node: Node::new(&cli, time),
+        sender,
+        receiver,
+    })
+}
+
+struct Simulated {
+    node: Node,
+    sender: EventSender<Input<Event>>,
+    receiver: EventReceiver<Input<Event>>,
+}
+
+impl SimNode for Simulated {
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        parse_event(line.as_bytes())?.process_received_event(&mut self.node, &self.sender, output)
+    }
+
+    /// Runs the scuttlebutt rounds that are due.
+    fn tick(&mut self, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        while let Ok(input) = self.receiver.try_recv() {
+            if let Input::Event(mut event) = input {
+                event.process_received_event(&mut self.node, &self.sender, output)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::crdt::{LwwRegister, Merge, MvRegister};
+use distributed_system::event_queue::{EventReceiver, EventSender};
+use distributed_system::hlc::Hlc;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::sim::SimNode;
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Read {
+        msg_id: u64,
+        key: Value,
+    },
+
+    /// A multi-value register reads as the list of its concurrent values.
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        value: Value,
+    },
+
+    Write {
+        msg_id: u64,
+        key: Value,
+        value: Value,
+    },
+
+    WriteOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Cas {
+        msg_id: u64,
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+
+    CasOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// Registers of the sender, by the JSON of their keys.
+    Sync {
+        msg_id: u64,
+        #[serde(flatten)]
+        registers: Registers,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+/// A value written to a register, ordered by its JSON, so that last-writer-wins registers
+/// can break ties between writes at the same timestamp.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+struct JsonValue(Value);
+
+impl Ord for JsonValue {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.to_string().cmp(&other.0.to_string())
+    }
+}
+
+impl PartialOrd for JsonValue {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The registers of a node by the JSON of their keys, sent in a `sync` under the name of
+/// their kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Registers {
+    Lww(BTreeMap<String, LwwRegister<JsonValue>>),
+    MultiValue(BTreeMap<String, MvRegister<Value>>),
+}
+
+impl Registers {
+    /// The value of the register of `key`, or the list of its values for a multi-value
+    /// register.
+    fn read(&self, key: &str) -> Option<Value> {
+        match self {
+            Registers::Lww(registers) => registers
+                .get(key)
+                .map(|register| register.value().0.clone()),
+            Registers::MultiValue(registers) => registers
+                .get(key)
+                .map(|register| register.values().cloned().collect()),
+        }
+    }
+
+    /// The registers of `keys`, of the same kind as these.
+    fn subset(&self, keys: &BTreeSet<String>) -> Registers {
+        match self {
+            Registers::Lww(registers) => Registers::Lww(
+                registers
+                    .iter()
+                    .filter(|(key, _)| keys.contains(*key))
+                    .map(|(key, register)| (key.clone(), register.clone()))
+                    .collect(),
+            ),
+            Registers::MultiValue(registers) => Registers::MultiValue(
+                registers
+                    .iter()
+                    .filter(|(key, _)| keys.contains(*key))
+                    .map(|(key, register)| (key.clone(), register.clone()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+enum Event {
+    Message(Message),
+    Rejected(Message),
+    SyncRequested,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut impl Write,
+    ) -> Result<(), anyhow::Error> {
+        let responses = match self {
+            Event::Message(message) => node.process_received_message(message, sender.clone()),
+            Event::Rejected(error_reply) => return error_reply.send(&mut output),
+            Event::SyncRequested => node.sync_round(),
+        };
+
+        for response in responses {
+            response.send(&mut output)?;
+        }
+        Ok(())
+    }
+}
+
+struct Node {
+    node_id: String,
+    cluster: Vec<String>,
+    msg_id: u64,
+    registers: Registers,
+    /// Timestamps the writes of last-writer-wins registers, past every write synced here.
+    hlc: Hlc,
+    /// Keys written here since the last sync round.
+    written: BTreeSet<String>,
+    sync_interval: Duration,
+    full_sync_interval: Duration,
+    last_full_sync: Instant,
+    timer: Option<Timer>,
+    /// Where the node schedules its sync rounds.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(cli: &Cli, time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            cluster: Vec::new(),
+            msg_id: 0,
+            registers: if cli.multi_value {
+                Registers::MultiValue(BTreeMap::new())
+            } else {
+                Registers::Lww(BTreeMap::new())
+            },
+            hlc: Hlc::with_clock(time.clone()),
+            written: BTreeSet::new(),
+            sync_interval: cli.sync_interval,
+            full_sync_interval: cli.full_sync_interval,
+            last_full_sync: time.now(),
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) {
+        self.node_id = node_id;
+        self.cluster.extend_from_slice(node_ids);
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            self.sync_interval,
+            sender,
+            || Input::Event(Event::SyncRequested),
+        ));
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    /// Replaces the value of `key`: with a later timestamp than any write seen here, or as
+    /// a write that saw every value this node has.
+    fn write(&mut self, key: &Value, value: Value) {
+        let key = key.to_string();
+        match &mut self.registers {
+            Registers::Lww(registers) => {
+                let register = LwwRegister::new(JsonValue(value), self.hlc.now(), &self.node_id);
+                registers.insert(key.clone(), register);
+            }
+            Registers::MultiValue(registers) => {
+                registers
+                    .entry(key.clone())
+                    .or_default()
+                    .write(value, &self.node_id);
+            }
+        }
+        self.written.insert(key);
+    }
+
+    /// Writes `to` if `key` has the value `from` here. A multi-value register with
+    /// concurrent values has none of them. As this only checks the value on this node, two
+    /// nodes may both swap the same value.
+    fn cas(
+        &mut self,
+        key: &Value,
+        from: &Value,
+        to: &Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), (ErrorCode, String)> {
+        let current = match self.registers.read(&key.to_string()) {
+            Some(current) => current,
+            None if create_if_not_exists => {
+                self.write(key, to.clone());
+                return Ok(());
+            }
+            None => {
+                return Err((ErrorCode::KeyDoesNotExist, format!("Key {key} not found")));
+            }
+        };
+        let matches = match &self.registers {
+            Registers::Lww(_) => current == *from,
+            Registers::MultiValue(_) => current
+                .as_array()
+                .is_some_and(|values| values == std::slice::from_ref(from)),
+        };
+        if !matches {
+            return Err((
+                ErrorCode::PreconditionFailed,
+                format!("Expected {from}, but key {key} has value {current}"),
+            ));
+        }
+        self.write(key, to.clone());
+        Ok(())
+    }
+
+    /// Merges the registers of another node. Their timestamps move the clock forward, so
+    /// that a later write here wins over them.
+    fn merge(&mut self, registers: &Registers) -> Result<(), anyhow::Error> {
+        match (&mut self.registers, registers) {
+            (Registers::Lww(ours), Registers::Lww(theirs)) => {
+                for (key, register) in theirs {
+                    self.hlc.observe(register.timestamp());
+                    match ours.get_mut(key) {
+                        Some(ours) => {
+                            ours.merge(register);
+                        }
+                        None => {
+                            ours.insert(key.clone(), register.clone());
+                        }
+                    }
+                }
+            }
+            (Registers::MultiValue(ours), Registers::MultiValue(theirs)) => {
+                for (key, register) in theirs {
+                    ours.entry(key.clone()).or_default().merge(register);
+                }
+            }
+            _ => anyhow::bail!("Can't merge last-writer-wins with multi-value registers"),
+        }
+        Ok(())
+    }
+
+    /// Sends every other node the registers written here since the last round, or all of
+    /// them once every full sync interval, so that nodes that lost a sync catch up.
+    fn sync_round(&mut self) -> Vec<Message> {
+        let now = self.time.now();
+        let registers = if now >= self.last_full_sync + self.full_sync_interval {
+            self.last_full_sync = now;
+            self.registers.clone()
+        } else if !self.written.is_empty() {
+            self.registers.subset(&self.written)
+        } else {
+            return Vec::new();
+        };
+        self.written.clear();
+
+        let msg_id = self.incremented_msg_id();
+        self.cluster
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .map(|peer| Message {
+                src: self.node_id.clone(),
+                dest: peer.clone(),
+                body: Body::Sync {
+                    msg_id,
+                    registers: registers.clone(),
+                },
+            })
+            .collect()
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Vec<Message> {
+        let mut responses: Vec<Message> = Vec::new();
+
+        let build_message_from = |body: Body| -> Message {
+            Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }
+        };
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids, sender);
+
+                responses.push(build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                }));
+            }
+
+            Body::Read { msg_id, key } => {
+                let body = match self.registers.read(&key.to_string()) {
+                    Some(value) => Body::ReadOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: *msg_id,
+                        value,
+                    },
+                    None => Body::Error(ErrorBody::new(
+                        *msg_id,
+                        ErrorCode::KeyDoesNotExist,
+                        format!("Key {key} not found"),
+                    )),
+                };
+                responses.push(build_message_from(body));
+            }
+
+            Body::Write { msg_id, key, value } => {
+                self.write(key, std::mem::take(value));
+                responses.push(build_message_from(Body::WriteOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                }));
+            }
+
+            Body::Cas {
+                msg_id,
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                let body = match self.cas(key, from, to, *create_if_not_exists) {
+                    Ok(()) => Body::CasOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: *msg_id,
+                    },
+                    Err((code, text)) => Body::Error(ErrorBody::new(*msg_id, code, text)),
+                };
+                responses.push(build_message_from(body));
+            }
+
+            Body::Sync { registers, .. } => {
+                if let Err(error) = self.merge(registers) {
+                    log::warn!("Dropped the sync from {}: {error}", message.src);
+                }
+            }
+
+            Body::InitOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. }
+            | Body::WriteOk { msg_id, .. }
+            | Body::CasOk { msg_id, .. } => {
+                responses.push(build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Register node does not accept replies",
+                ))));
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+            }
+        }
+
+        responses
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        Ok(())
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node_id": self.node_id,
+            "cluster": self.cluster,
+            "registers": self.registers,
+            "unsynced_writes": self.written,
+            "hlc": self.hlc.last(),
+        })
+    }
+}
+
+/// Runs a node of eventually consistent registers, which take the requests of the lin-kv
+/// workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Keeps every value of concurrent writes, ordered by vector clocks, instead of the
+    /// latest by hybrid logical clock timestamp. A read then returns the list of values.
+    #[arg(long, env = "REGISTER_MULTI_VALUE")]
+    multi_value: bool,
+    /// Milliseconds between sync rounds, which send the registers written since the last.
+    #[arg(
+        long = "sync-interval-ms",
+        env = "REGISTER_SYNC_INTERVAL_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "100"
+    )]
+    sync_interval: Duration,
+    /// Milliseconds between rounds that send every register, so that nodes that lost a sync
+    /// catch up.
+    #[arg(
+        long = "full-sync-ms",
+        env = "REGISTER_FULL_SYNC_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "1000"
+    )]
+    full_sync_interval: Duration,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = config::parse::<Cli>("register")?;
+    cli.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(&cli, Arc::new(SystemClock));
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), parse_event);
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn parse_event(line: &[u8]) -> Result<Event, anyhow::Error> {
+    let event =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(message) => Event::Message(message),
+            Err(error_reply) => Event::Rejected(error_reply),
+        };
+    Ok(event)
+}
+
+/// A register node for a `sim::Network`, which syncs as scheduled by `time`.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Box<dyn SimNode> {
+    let (sender, receiver) = runtime::event_channel();
+    Box::new(Simulated {
+        node: Node::new(&cli, time),
+        sender,
+        receiver,
+    })
+}
+
+struct Simulated {
+    node: Node,
+    sender: EventSender<Input<Event>>,
+    receiver: EventReceiver<Input<Event>>,
+}
+
+impl SimNode for Simulated {
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        parse_event(line.as_bytes())?.process_received_event(&mut self.node, &self.sender, output)
+    }
+
+    /// Runs the sync rounds that are due.
+    fn tick(&mut self, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        while let Ok(input) = self.receiver.try_recv() {
+            if let Input::Event(mut event) = input {
+                event.process_received_event(&mut self.node, &self.sender, output)?;
+            }
+        }
+        Ok(())
+    }
+}
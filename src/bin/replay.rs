@@ -0,0 +1,63 @@
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use distributed_system::record;
+
+/// Feeds the lines a node recorded with `--record` to a node again, with the delays they
+/// arrived with, so that a failing run can be reproduced offline. The node's output goes to
+/// stdout, and its diagnostics to stderr.
+#[derive(Debug, Parser)]
+struct Cli {
+    /// The recording of one node.
+    recording: PathBuf,
+    /// How many times faster than recorded to replay. At 0, lines are sent without delay.
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// The node binary to run, followed by its arguments.
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<OsString>,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    if !(cli.speed >= 0.0 && cli.speed.is_finite()) {
+        bail!("The speed must be a number of at least 0");
+    }
+    let entries = record::read(&cli.recording)?;
+
+    let (program, args) = cli.command.split_first().context("No node to replay to")?;
+    let mut child = Command::new(program)
+        .args(args)
+        // The node would otherwise record the replay over the recording.
+        .env_remove("NODE_RECORD")
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start {}", program.to_string_lossy()))?;
+
+    let mut stdin = child.stdin.take().context("Child has no stdin")?;
+    let started = Instant::now();
+    for entry in &entries {
+        if cli.speed > 0.0 {
+            let due = Duration::from_micros(entry.at_us).div_f64(cli.speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        // The node may have stopped early, which its exit status tells.
+        if writeln!(stdin, "{}", entry.line).is_err() {
+            break;
+        }
+    }
+    drop(stdin);
+
+    let status = child.wait().context("Failed to wait for the node")?;
+    if !status.success() {
+        bail!("The node failed: {status}");
+    }
+    Ok(())
+}
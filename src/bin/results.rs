@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use distributed_system::results::{self, Summary};
+
+/// The quantiles of latencies printed.
+const QUANTILES: [f64; 4] = [0.5, 0.95, 0.99, 1.0];
+
+/// Summarizes a Maelstrom run: whether each check passed, messages per operation, and
+/// latencies, from its results and history. Fails if the run is invalid, so that it can end
+/// a script that runs Maelstrom.
+#[derive(Debug, Parser)]
+struct Cli {
+    /// A `results.edn`, a `jepsen.log`, or the directory of a run with them.
+    #[arg(default_value = "store/latest")]
+    path: PathBuf,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let summary = Summary::of(&results::read(&cli.path)?);
+
+    for (check, valid) in &summary.checks {
+        println!("{check:<16} {}", verdict(*valid));
+    }
+    if let Some(msgs) = summary.server_msgs_per_op {
+        println!("Messages per op: {msgs:.2} between servers");
+    }
+    if let Some(msgs) = summary.all_msgs_per_op {
+        println!("Messages per op: {msgs:.2} in all");
+    }
+    if !summary.stable_latencies.is_empty() {
+        let latencies: Vec<String> = summary
+            .stable_latencies
+            .iter()
+            .map(|&(quantile, latency)| format!("{} {}", label(quantile), ms(latency)))
+            .collect();
+        println!("Stable latencies: {}", latencies.join(", "));
+    }
+    if let Some(history) = history_next_to(&cli.path) {
+        let history = std::fs::read_to_string(&history)
+            .with_context(|| format!("Failed to read {}", history.display()))?;
+        for (f, latencies) in results::latencies(&results::parse_all(&history)?) {
+            let quantiles: Vec<String> = QUANTILES
+                .iter()
+                .map(|&q| format!("{} {}", label(q), ms(results::quantile(&latencies, q))))
+                .collect();
+            println!(
+                "Latencies of {f} ({} ok): {}",
+                latencies.len(),
+                quantiles.join(", ")
+            );
+        }
+    }
+
+    println!("Valid: {}", verdict(summary.valid));
+    if summary.valid != Some(true) {
+        bail!("The run is invalid");
+    }
+    Ok(())
+}
+
+/// The `history.edn` of the run `path` is in or of, if there is one.
+fn history_next_to(path: &Path) -> Option<PathBuf> {
+    let dir = if path.is_dir() { path } else { path.parent()? };
+    Some(dir.join("history.edn")).filter(|history| history.exists())
+}
+
+fn verdict(valid: Option<bool>) -> &'static str {
+    match valid {
+        Some(true) => "ok",
+        Some(false) => "FAILED",
+        None => "unknown",
+    }
+}
+
+fn label(quantile: f64) -> String {
+    if quantile >= 1.0 {
+        "max".to_string()
+    } else {
+        format!("p{}", quantile * 100.0)
+    }
+}
+
+fn ms(latency: Duration) -> String {
+    format!("{:.0}ms", latency.as_secs_f64() * 1000.0)
+}
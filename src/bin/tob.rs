@@ -0,0 +1,491 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::event_queue::EventSender;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a node waits for its message to be delivered before sending it to the
+/// sequencer again.
+const ORDER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Maximum number of messages the sequencer resends to a node in a single `tob_deliver`.
+const MAX_MESSAGES_PER_DELIVER: usize = 100;
+
+/// A message with the node it was sent to and that node's id for it, which identify it
+/// when it is sent to the sequencer more than once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sequenced {
+    message: u64,
+    origin: String,
+    id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    /// Answered once the message was delivered on the node it was sent to.
+    TobSend {
+        msg_id: u64,
+        message: u64,
+    },
+
+    TobSendOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// Returns every delivered message, in the order of delivery.
+    Read {
+        msg_id: u64,
+    },
+
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        messages: Vec<u64>,
+    },
+
+    /// Asks the sequencer to assign the next sequence number to a message.
+    Order {
+        msg_id: u64,
+        entry: Sequenced,
+    },
+
+    /// Sent by the sequencer: the entries with sequence numbers `seq`, `seq + 1`, and so on.
+    TobDeliver {
+        msg_id: u64,
+        seq: u64,
+        entries: Vec<Sequenced>,
+    },
+
+    /// Acknowledges every entry below sequence number `delivered`.
+    TobDeliverOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        delivered: u64,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+enum Event {
+    Message(Message),
+    Rejected(Message),
+    Tick,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        let responses = match self {
+            Event::Message(message) => node.process_received_message(message, sender.clone()),
+            Event::Rejected(error_reply) => return error_reply.send(&mut output),
+            Event::Tick => node.retransmit(),
+        };
+
+        for response in responses {
+            response.send(&mut output)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `tob_send` waiting for its message to be delivered on this node.
+struct Waiting {
+    client: String,
+    in_reply_to: u64,
+    message: u64,
+    sent_at: Instant,
+}
+
+struct Node {
+    node_id: String,
+    msg_id: u64,
+    node_ids: Vec<String>,
+    /// The node with the lowest id assigns the sequence numbers.
+    sequencer: String,
+    delivered: Vec<Sequenced>,
+    /// Entries received ahead of a gap, by sequence number.
+    buffered: BTreeMap<u64, Sequenced>,
+    waiting: HashMap<u64, Waiting>,
+    next_id: u64,
+    /// Entries the sequencer has already ordered, so that resent ones aren't ordered twice.
+    ordered: HashSet<(String, u64)>,
+    /// How many entries each node acknowledged, as known by the sequencer.
+    acknowledged: HashMap<String, u64>,
+    timer: Option<Timer>,
+    /// Where the node reads the time for its resends, and schedules its ticks.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            msg_id: 0,
+            node_ids: Vec::new(),
+            sequencer: String::new(),
+            delivered: Vec::new(),
+            buffered: BTreeMap::new(),
+            waiting: HashMap::new(),
+            next_id: 0,
+            ordered: HashSet::new(),
+            acknowledged: HashMap::new(),
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) {
+        self.node_id = node_id;
+        self.node_ids = node_ids.to_vec();
+        self.sequencer = node_ids.iter().min().cloned().unwrap_or_default();
+
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            TICK_INTERVAL,
+            sender,
+            || Input::Event(Event::Tick),
+        ));
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn is_sequencer(&self) -> bool {
+        self.node_id == self.sequencer
+    }
+
+    fn message_to(&self, dest: &str, body: Body) -> Message {
+        Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body,
+        }
+    }
+
+    fn send_order(&mut self, entry: Sequenced) -> Message {
+        let body = Body::Order {
+            msg_id: self.incremented_msg_id(),
+            entry,
+        };
+        self.message_to(&self.sequencer.clone(), body)
+    }
+
+    /// Assigns the next sequence number to the entry, delivers it here, and sends it to
+    /// every other node.
+    fn order(&mut self, entry: Sequenced) -> Vec<Message> {
+        if !self.ordered.insert((entry.origin.clone(), entry.id)) {
+            // Already ordered; lost deliveries are resent by `retransmit`.
+            return Vec::new();
+        }
+
+        let seq = self.delivered.len() as u64;
+        let mut responses = self.deliver(seq, vec![entry.clone()]);
+
+        let peers: Vec<String> = self
+            .node_ids
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .cloned()
+            .collect();
+        for peer in peers {
+            let body = Body::TobDeliver {
+                msg_id: self.incremented_msg_id(),
+                seq,
+                entries: vec![entry.clone()],
+            };
+            responses.push(self.message_to(&peer, body));
+        }
+
+        responses
+    }
+
+    /// Buffers the entries and delivers every one without a gap before it, answering the
+    /// clients whose messages were delivered.
+    fn deliver(&mut self, seq: u64, entries: Vec<Sequenced>) -> Vec<Message> {
+        for (offset, entry) in entries.into_iter().enumerate() {
+            let seq = seq + offset as u64;
+            if seq >= self.delivered.len() as u64 {
+                self.buffered.insert(seq, entry);
+            }
+        }
+
+        let mut responses = Vec::new();
+        while let Some(entry) = self.buffered.remove(&(self.delivered.len() as u64)) {
+            if entry.origin == self.node_id {
+                if let Some(waiting) = self.waiting.remove(&entry.id) {
+                    let body = Body::TobSendOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: waiting.in_reply_to,
+                    };
+                    responses.push(self.message_to(&waiting.client, body));
+                }
+            }
+            self.delivered.push(entry);
+        }
+
+        responses
+    }
+
+    /// Resends messages that weren't delivered in time to the sequencer, and, on the
+    /// sequencer, every entry a node hasn't acknowledged yet.
+    fn retransmit(&mut self) -> Vec<Message> {
+        let now = self.time.now();
+        let mut responses = Vec::new();
+
+        let timed_out: Vec<Sequenced> = self
+            .waiting
+            .iter_mut()
+            .filter(|(_, waiting)| now >= waiting.sent_at + ORDER_TIMEOUT)
+            .map(|(&id, waiting)| {
+                waiting.sent_at = now;
+                Sequenced {
+                    message: waiting.message,
+                    origin: self.node_id.clone(),
+                    id,
+                }
+            })
+            .collect();
+        for entry in timed_out {
+            responses.push(self.send_order(entry));
+        }
+
+        if !self.is_sequencer() {
+            return responses;
+        }
+
+        let peers: Vec<String> = self
+            .node_ids
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .cloned()
+            .collect();
+        for peer in peers {
+            let acknowledged = self.acknowledged.get(&peer).copied().unwrap_or(0);
+            if acknowledged >= self.delivered.len() as u64 {
+                continue;
+            }
+
+            let entries = self.delivered[acknowledged as usize..]
+                .iter()
+                .take(MAX_MESSAGES_PER_DELIVER)
+                .cloned()
+                .collect();
+            let body = Body::TobDeliver {
+                msg_id: self.incremented_msg_id(),
+                seq: acknowledged,
+                entries,
+            };
+            responses.push(self.message_to(&peer, body));
+        }
+
+        responses
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Vec<Message> {
+        let build_message_from = |body: Body| -> Message {
+            Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }
+        };
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids, sender);
+
+                vec![build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                })]
+            }
+
+            Body::TobSend {
+                msg_id,
+                message: value,
+            } => {
+                self.next_id += 1;
+                let entry = Sequenced {
+                    message: *value,
+                    origin: self.node_id.clone(),
+                    id: self.next_id,
+                };
+                self.waiting.insert(
+                    self.next_id,
+                    Waiting {
+                        client: message.src.clone(),
+                        in_reply_to: *msg_id,
+                        message: *value,
+                        sent_at: self.time.now(),
+                    },
+                );
+
+                if self.is_sequencer() {
+                    self.order(entry)
+                } else {
+                    vec![self.send_order(entry)]
+                }
+            }
+
+            Body::Read { msg_id } => vec![build_message_from(Body::ReadOk {
+                msg_id: self.incremented_msg_id(),
+                in_reply_to: *msg_id,
+                messages: self.delivered.iter().map(|entry| entry.message).collect(),
+            })],
+
+            Body::Order { entry, .. } if self.is_sequencer() => self.order(entry.clone()),
+
+            Body::Order { msg_id, .. } => vec![build_message_from(Body::Error(ErrorBody::new(
+                *msg_id,
+                ErrorCode::TemporarilyUnavailable,
+                "Node is not the sequencer",
+            )))],
+
+            Body::TobDeliver {
+                msg_id,
+                seq,
+                entries,
+            } => {
+                let mut responses = self.deliver(*seq, std::mem::take(entries));
+                responses.push(build_message_from(Body::TobDeliverOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    delivered: self.delivered.len() as u64,
+                }));
+                responses
+            }
+
+            Body::TobDeliverOk { delivered, .. } => {
+                let acknowledged = self.acknowledged.entry(message.src.clone()).or_insert(0);
+                *acknowledged = (*acknowledged).max(*delivered);
+                Vec::new()
+            }
+
+            Body::InitOk { msg_id, .. }
+            | Body::TobSendOk { msg_id, .. }
+            | Body::ReadOk { msg_id, .. } => {
+                vec![build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Total-order broadcast node does not accept client replies",
+                )))]
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn std::io::Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        Ok(())
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node_id": self.node_id,
+            "sequencer": self.sequencer,
+            "delivered": self.delivered.len(),
+            "buffered": self.buffered.len(),
+            "waiting": self.waiting.len(),
+            "next_id": self.next_id,
+            "acknowledged": self.acknowledged,
+        })
+    }
+}
+
+/// Runs a node of the total order broadcast workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    config::parse::<Cli>("tob")?.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(Arc::new(SystemClock));
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), |line| {
+        let event =
+            match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+                Ok(msg) => Event::Message(msg),
+                Err(error_reply) => Event::Rejected(error_reply),
+            };
+        Ok(event)
+    });
+
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use clap::Parser;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::event_queue::EventSender;
+use distributed_system::metrics;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime::{self, Input, Lifecycle, Output, Timer};
+use distributed_system::txn::{
+    AwaitingDecisions, Coordinator, Isolation, MicroOp, Outcome, Participant, Partitioning, Store,
+    Write,
+};
+use distributed_system::{ErrorBody, ErrorCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+const REPLICATION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A coordinator aborts transactions whose participants haven't all voted by then.
+const PREPARE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A participant asks the coordinator for the outcome of a transaction it prepared when it
+/// hasn't heard about it for this long.
+const DECISION_TIMEOUT: Duration = Duration::from_millis(2000);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Txn {
+        msg_id: u64,
+        txn: Vec<MicroOp>,
+    },
+
+    TxnOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        txn: Vec<MicroOp>,
+    },
+
+    Replicate {
+        msg_id: u64,
+        writes: Vec<Write>,
+    },
+
+    ReplicateOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// Asks the owner of the keys in `txn` to lock them and vote on the transaction.
+    Prepare {
+        msg_id: u64,
+        txn_id: String,
+        txn: Vec<MicroOp>,
+    },
+
+    /// A vote to commit, with the read values filled in.
+    PrepareOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        txn_id: String,
+        txn: Vec<MicroOp>,
+    },
+
+    /// A vote to abort, as some key is locked by another transaction.
+    PrepareFailed {
+        msg_id: u64,
+        in_reply_to: u64,
+        txn_id: String,
+    },
+
+    Commit {
+        msg_id: u64,
+        txn_id: String,
+    },
+
+    CommitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        txn_id: String,
+    },
+
+    Abort {
+        msg_id: u64,
+        txn_id: String,
+    },
+
+    AbortOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        txn_id: String,
+    },
+
+    /// Sent by a participant that prepared a transaction but never heard the decision. The
+    /// coordinator answers with `commit` or `abort`.
+    QueryDecision {
+        msg_id: u64,
+        txn_id: String,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+enum Event {
+    Message(Message),
+    Rejected(Message),
+    ReplicationRequested,
+}
+
+impl Event {
+    fn process_received_event(
+        &mut self,
+        node: &mut Node,
+        sender: &EventSender<Input<Event>>,
+        mut output: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Event::Message(message) => {
+                for response in node.process_received_message(message, sender.clone()) {
+                    response.send(&mut output)?;
+                }
+                Ok(())
+            }
+
+            Event::Rejected(error_reply) => error_reply.send(&mut output),
+
+            Event::ReplicationRequested => {
+                for replication in node.retry_replication() {
+                    replication.send(&mut output)?;
+                }
+                for message in node.check_transactions() {
+                    message.send(&mut output)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes sent to a peer that haven't been acknowledged yet.
+struct PendingReplication {
+    writes: Vec<Write>,
+    sent_at: Instant,
+}
+
+/// A transaction this node coordinates, waiting for the votes of the owners of its keys.
+struct Coordination {
+    client: String,
+    in_reply_to: u64,
+    txn: Vec<MicroOp>,
+    /// Positions of the micro-ops handled by each participant.
+    parts: HashMap<String, Vec<usize>>,
+}
+
+struct Node {
+    node_id: String,
+    cluster: Vec<String>,
+    msg_id: u64,
+    isolation: Isolation,
+    partitioning: Partitioning,
+    /// Time between rounds that send writes to the nodes that haven't acknowledged them.
+    replication_interval: Duration,
+    store: Store,
+    unacknowledged: HashMap<String, HashMap<u64, PendingReplication>>,
+    participant: Participant,
+    coordinator: Coordinator<Coordination>,
+    /// Transactions this node prepared, with their coordinators.
+    awaiting_decision: AwaitingDecisions<String>,
+    timer: Option<Timer>,
+    /// Where the node reads the time for its timeouts, and schedules its replication
+    /// rounds.
+    time: Arc<dyn Clock>,
+}
+
+impl Node {
+    fn new(
+        isolation: Isolation,
+        partitioning: Partitioning,
+        replication_interval: Duration,
+        time: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            node_id: String::new(),
+            cluster: Vec::new(),
+            msg_id: 0,
+            isolation,
+            partitioning,
+            replication_interval,
+            store: Store::default(),
+            unacknowledged: HashMap::new(),
+            participant: Participant::default(),
+            coordinator: Coordinator::with_clock(time.clone()),
+            awaiting_decision: AwaitingDecisions::with_clock(time.clone()),
+            timer: None,
+            time,
+        }
+    }
+
+    fn initialize(
+        &mut self,
+        node_id: String,
+        node_ids: &[String],
+        sender: EventSender<Input<Event>>,
+    ) {
+        self.store = Store::with_isolation(node_id.clone(), self.isolation);
+        self.participant = Participant::new(node_id.clone());
+        self.node_id = node_id;
+        self.cluster.extend_from_slice(node_ids);
+        self.cluster.sort();
+
+        self.timer = Some(Timer::start_on(
+            self.time.as_ref(),
+            self.replication_interval,
+            sender,
+            || Input::Event(Event::ReplicationRequested),
+        ));
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn replicate_to(&mut self, peer: &str, writes: Vec<Write>) -> Message {
+        let msg_id = self.incremented_msg_id();
+
+        let replication = Message {
+            src: self.node_id.clone(),
+            dest: peer.to_string(),
+            body: Body::Replicate {
+                msg_id,
+                writes: writes.clone(),
+            },
+        };
+
+        self.unacknowledged
+            .entry(peer.to_string())
+            .or_default()
+            .insert(
+                msg_id,
+                PendingReplication {
+                    writes,
+                    sent_at: self.time.now(),
+                },
+            );
+
+        replication
+    }
+
+    /// Resends every write that timed out, coalesced into a single message per peer.
+    fn retry_replication(&mut self) -> Vec<Message> {
+        let now = self.time.now();
+        let mut replications = Vec::new();
+        let peers: Vec<String> = self.unacknowledged.keys().cloned().collect();
+
+        for peer in peers {
+            let Some(pending) = self.unacknowledged.get_mut(&peer) else {
+                continue;
+            };
+
+            let timed_out: Vec<u64> = pending
+                .iter()
+                .filter(|(_, replication)| now >= replication.sent_at + REPLICATION_TIMEOUT)
+                .map(|(&msg_id, _)| msg_id)
+                .collect();
+
+            if timed_out.is_empty() {
+                continue;
+            }
+            metrics::increment(metrics::RETRIES);
+
+            let writes: Vec<Write> = timed_out
+                .iter()
+                .filter_map(|msg_id| pending.remove(msg_id))
+                .flat_map(|replication| replication.writes)
+                .collect();
+
+            replications.push(self.replicate_to(&peer, writes));
+        }
+
+        replications
+    }
+
+    /// Keys are spread over the sorted node ids, so every node agrees on their owners.
+    fn owner(&self, key: u64) -> String {
+        self.cluster[key as usize % self.cluster.len()].clone()
+    }
+
+    fn message_to(&self, dest: &str, body: Body) -> Message {
+        Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body,
+        }
+    }
+
+    /// Starts two-phase commit of a client transaction: the micro-ops are split by the owner
+    /// of their key, and every owner is asked to prepare its part. This node's own part is
+    /// prepared directly.
+    fn coordinate(&mut self, client: &str, in_reply_to: u64, txn: &[MicroOp]) -> Vec<Message> {
+        let txn_id = self.incremented_msg_id();
+        let txn_id = format!("{}-{txn_id}", self.node_id);
+
+        let mut parts: HashMap<String, Vec<usize>> = HashMap::new();
+        for (position, op) in txn.iter().enumerate() {
+            let (MicroOp::Read { key, .. } | MicroOp::Write { key, .. }) = *op;
+            parts.entry(self.owner(key)).or_default().push(position);
+        }
+        let part_of = |positions: &[usize]| -> Vec<MicroOp> {
+            positions.iter().map(|&i| txn[i].clone()).collect()
+        };
+
+        let mut coordination = Coordination {
+            client: client.to_string(),
+            in_reply_to,
+            txn: txn.to_vec(),
+            parts,
+        };
+
+        // The local part is prepared first, so that nobody else has to be told if it fails.
+        if let Some(positions) = coordination.parts.get(&self.node_id) {
+            let Some(prepared) = self.participant.prepare(&txn_id, &part_of(positions)) else {
+                self.coordinator
+                    .start(&txn_id, [self.node_id.clone()], coordination);
+                return self.decide(&txn_id, false);
+            };
+            for (&position, op) in positions.iter().zip(prepared) {
+                coordination.txn[position] = op;
+            }
+        }
+
+        let mut messages = Vec::new();
+        let remote_parts: Vec<(String, Vec<MicroOp>)> = coordination
+            .parts
+            .iter()
+            .filter(|(owner, _)| **owner != self.node_id)
+            .map(|(owner, positions)| (owner.clone(), part_of(positions)))
+            .collect();
+        for (owner, part) in remote_parts {
+            let body = Body::Prepare {
+                msg_id: self.incremented_msg_id(),
+                txn_id: txn_id.clone(),
+                txn: part,
+            };
+            messages.push(self.message_to(&owner, body));
+        }
+
+        let participants: Vec<String> = coordination.parts.keys().cloned().collect();
+        let local = coordination.parts.contains_key(&self.node_id);
+        self.coordinator.start(&txn_id, participants, coordination);
+        if local {
+            let node_id = self.node_id.clone();
+            if let Some(outcome) = self.coordinator.vote(&txn_id, &node_id, true) {
+                return self.decided(outcome);
+            }
+        }
+
+        messages
+    }
+
+    fn vote(&mut self, txn_id: &str, voter: &str, prepared: Option<Vec<MicroOp>>) -> Vec<Message> {
+        if let (Some(prepared), Some(coordination)) =
+            (&prepared, self.coordinator.state_mut(txn_id))
+        {
+            if let Some(positions) = coordination.parts.get(voter) {
+                for (&position, op) in positions.iter().zip(prepared.iter().cloned()) {
+                    coordination.txn[position] = op;
+                }
+            }
+        }
+
+        match self.coordinator.vote(txn_id, voter, prepared.is_some()) {
+            Some(outcome) => self.decided(outcome),
+            None => Vec::new(),
+        }
+    }
+
+    /// Ends voting on a transaction with `commit`, e.g. to abort it before every vote
+    /// arrived.
+    fn decide(&mut self, txn_id: &str, commit: bool) -> Vec<Message> {
+        match self.coordinator.decide(txn_id, commit) {
+            Some(outcome) => self.decided(outcome),
+            None => Vec::new(),
+        }
+    }
+
+    /// Commits the transaction if every participant voted to, and aborts it otherwise. The
+    /// client is answered right away, as participants that prepared are bound to follow the
+    /// decision, which is resent until they acknowledge it.
+    fn decided(&mut self, outcome: Outcome<Coordination>) -> Vec<Message> {
+        let Outcome {
+            txn_id,
+            commit,
+            state: coordination,
+            participants,
+        } = outcome;
+
+        let mut messages = Vec::new();
+        let mut told = Vec::new();
+        for participant in participants {
+            if participant == self.node_id {
+                if commit {
+                    self.participant.commit(&txn_id);
+                } else {
+                    self.participant.abort(&txn_id);
+                }
+            } else {
+                messages.push(self.decision_message(&participant, &txn_id, commit));
+                told.push(participant);
+            }
+        }
+        self.coordinator.tell(&txn_id, commit, told);
+
+        let body = if commit {
+            Body::TxnOk {
+                msg_id: self.incremented_msg_id(),
+                in_reply_to: coordination.in_reply_to,
+                txn: coordination.txn,
+            }
+        } else {
+            Body::Error(ErrorBody::new(
+                coordination.in_reply_to,
+                ErrorCode::TxnConflict,
+                "Transaction conflicts with a concurrent one",
+            ))
+        };
+        messages.push(self.message_to(&coordination.client, body));
+
+        messages
+    }
+
+    fn decision_message(&mut self, participant: &str, txn_id: &str, commit: bool) -> Message {
+        let msg_id = self.incremented_msg_id();
+        let txn_id = txn_id.to_string();
+        let body = if commit {
+            Body::Commit { msg_id, txn_id }
+        } else {
+            Body::Abort { msg_id, txn_id }
+        };
+        self.message_to(participant, body)
+    }
+
+    /// Aborts transactions whose votes didn't all arrive in time, resends decisions that
+    /// weren't acknowledged, and asks coordinators about transactions prepared long ago.
+    fn check_transactions(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+
+        for outcome in self.coordinator.expire(PREPARE_TIMEOUT) {
+            messages.extend(self.decided(outcome));
+        }
+
+        for (participant, txn_id, commit) in self.coordinator.unacknowledged(REPLICATION_TIMEOUT) {
+            metrics::increment(metrics::RETRIES);
+            messages.push(self.decision_message(&participant, &txn_id, commit));
+        }
+
+        for txn_id in self.awaiting_decision.overdue(DECISION_TIMEOUT) {
+            let Some(coordinator) = self.awaiting_decision.get_mut(&txn_id).cloned() else {
+                continue;
+            };
+            let body = Body::QueryDecision {
+                msg_id: self.incremented_msg_id(),
+                txn_id,
+            };
+            messages.push(self.message_to(&coordinator, body));
+        }
+
+        messages
+    }
+
+    fn process_received_message(
+        &mut self,
+        message: &mut Message,
+        sender: EventSender<Input<Event>>,
+    ) -> Vec<Message> {
+        let mut responses: Vec<Message> = Vec::new();
+
+        let build_message_from = |body: Body| -> Message {
+            Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }
+        };
+
+        match &mut message.body {
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.initialize(node_id.clone(), node_ids, sender);
+
+                responses.push(build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                }));
+            }
+
+            Body::Txn { msg_id, txn } if self.partitioning == Partitioning::Partitioned => {
+                responses.extend(self.coordinate(&message.src, *msg_id, txn));
+            }
+
+            Body::Txn { msg_id, txn } => {
+                let (txn, writes) = self.store.apply(txn);
+
+                if !writes.is_empty() {
+                    let peers: Vec<String> = self
+                        .cluster
+                        .iter()
+                        .filter(|&id| *id != self.node_id)
+                        .cloned()
+                        .collect();
+
+                    for peer in peers {
+                        responses.push(self.replicate_to(&peer, writes.clone()));
+                    }
+                }
+
+                responses.push(build_message_from(Body::TxnOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    txn,
+                }));
+            }
+
+            Body::Replicate { msg_id, writes } => {
+                self.store.merge(writes);
+
+                responses.push(build_message_from(Body::ReplicateOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                }));
+            }
+
+            Body::ReplicateOk { in_reply_to, .. } => {
+                if let Some(pending) = self.unacknowledged.get_mut(&message.src) {
+                    pending.remove(in_reply_to);
+                }
+            }
+
+            Body::Prepare {
+                msg_id,
+                txn_id,
+                txn,
+            } => {
+                let body = match self.participant.prepare(txn_id, txn) {
+                    Some(txn) => {
+                        self.awaiting_decision.insert(txn_id, message.src.clone());
+                        Body::PrepareOk {
+                            msg_id: self.incremented_msg_id(),
+                            in_reply_to: *msg_id,
+                            txn_id: txn_id.clone(),
+                            txn,
+                        }
+                    }
+                    None => Body::PrepareFailed {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: *msg_id,
+                        txn_id: txn_id.clone(),
+                    },
+                };
+                responses.push(build_message_from(body));
+            }
+
+            Body::PrepareOk { txn_id, txn, .. } => {
+                let txn = std::mem::take(txn);
+                responses.extend(self.vote(txn_id, &message.src, Some(txn)));
+            }
+
+            Body::PrepareFailed { txn_id, .. } => {
+                responses.extend(self.vote(txn_id, &message.src, None));
+            }
+
+            Body::Commit { msg_id, txn_id } => {
+                self.participant.commit(txn_id);
+                self.awaiting_decision.remove(txn_id);
+                responses.push(build_message_from(Body::CommitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    txn_id: txn_id.clone(),
+                }));
+            }
+
+            Body::Abort { msg_id, txn_id } => {
+                self.participant.abort(txn_id);
+                self.awaiting_decision.remove(txn_id);
+                responses.push(build_message_from(Body::AbortOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    txn_id: txn_id.clone(),
+                }));
+            }
+
+            Body::CommitOk { txn_id, .. } | Body::AbortOk { txn_id, .. } => {
+                self.coordinator.acknowledged(txn_id, &message.src);
+            }
+
+            Body::QueryDecision { txn_id, .. } => {
+                if self.coordinator.is_voting(txn_id) {
+                    // Still waiting for votes, so the transaction can't have committed yet.
+                    responses.extend(self.decide(txn_id, false));
+                } else {
+                    // Decisions are forgotten once acknowledged by everyone, and
+                    // transactions that were never decided here were aborted.
+                    let commit = self.coordinator.decision(txn_id).unwrap_or(false);
+                    responses.push(self.decision_message(&message.src, txn_id, commit));
+                }
+            }
+
+            Body::InitOk { msg_id, .. } | Body::TxnOk { msg_id, .. } => {
+                responses.push(build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Transaction node does not accept client replies",
+                ))));
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+            }
+        }
+
+        responses
+    }
+}
+
+impl Lifecycle for Node {
+    fn on_shutdown(&mut self, _output: &mut dyn std::io::Write) -> Result<(), anyhow::Error> {
+        self.timer = None;
+        Ok(())
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        let unacknowledged: HashMap<&str, usize> = self
+            .unacknowledged
+            .iter()
+            .map(|(peer, pending)| (peer.as_str(), pending.len()))
+            .collect();
+        serde_json::json!({
+            "node_id": self.node_id,
+            "cluster": self.cluster,
+            "keys": self.store.len(),
+            "participant_keys": self.participant.store().len(),
+            "prepared": self.participant.prepared_count(),
+            "unacknowledged_replications": unacknowledged,
+            "coordinating": self.coordinator.voting_count(),
+            "decisions": self.coordinator.decision_count(),
+            "awaiting_decision": self.awaiting_decision.len(),
+        })
+    }
+}
+
+/// Runs a node of the txn workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Isolation level of transactions: read-uncommitted or read-committed.
+    #[arg(long, env = "TXN_ISOLATION", default_value = "read-uncommitted")]
+    isolation: Isolation,
+    /// Where each key is stored: replicated or partitioned.
+    #[arg(long, env = "TXN_PARTITIONING", default_value = "replicated")]
+    partitioning: Partitioning,
+    /// Milliseconds between rounds that send writes to the nodes that haven't acknowledged
+    /// them yet.
+    #[arg(
+        long = "replication-interval-ms",
+        env = "TXN_REPLICATION_INTERVAL_MS",
+        value_name = "MS",
+        value_parser = config::millis,
+        default_value = "200"
+    )]
+    replication_interval: Duration,
+}
+
+pub fn main() -> Result<(), anyhow::Error> {
+    run(config::parse("txn")?)
+}
+
+pub fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    cli.node.apply()?;
+    let (sender, receiver) = runtime::event_channel();
+    let mut stdout = Output::stdout();
+    let mut node = Node::new(
+        cli.isolation,
+        cli.partitioning,
+        cli.replication_interval,
+        Arc::new(SystemClock),
+    );
+
+    let reader = runtime::spawn_stdin_reader(sender.clone(), |line| {
+        let event =
+            match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+                Ok(msg) => Event::Message(msg),
+                Err(error_reply) => Event::Rejected(error_reply),
+            };
+        Ok(event)
+    });
+
+    runtime::run_events(
+        &mut node,
+        receiver,
+        reader,
+        &mut stdout,
+        |node, mut event, output| event.process_received_event(node, &sender, output),
+    )?;
+    stdout.finish()
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
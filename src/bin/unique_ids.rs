@@ -1,7 +1,36 @@
+use std::collections::VecDeque;
 use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::{bail, Context};
+use clap::Parser;
+use distributed_system::checkpoint::Checkpoint;
+use distributed_system::clock::{Clock, SystemClock};
+use distributed_system::config::{self, NodeArgs};
+use distributed_system::hlc::Hlc;
+use distributed_system::ids::{self, Snowflake, Ulid, MAX_SNOWFLAKE_NODES};
+use distributed_system::kv::{self, KvBody, KvClient, KvError, KvReply, KvService};
+use distributed_system::metrics;
+use distributed_system::protocol::{self, Handshake};
+use distributed_system::runtime::{self, Lifecycle, Output};
+use distributed_system::sim::{self, SimNode};
+use distributed_system::transport;
+use distributed_system::{ErrorBody, ErrorCode};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type Message = distributed_system::Message<Body>;
+
+/// The most IDs a single `generate` request may ask for.
+const MAX_COUNT: u64 = 10_000;
+
+/// How many IDs a node reserves from lin-kv at once in the `block` format.
+const BLOCK_SIZE: u64 = 1_000;
+
+/// The lin-kv key holding the first ID not reserved by any node yet.
+const NEXT_BLOCK_KEY: &str = "next_block";
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Init {
@@ -14,18 +43,27 @@ struct Init {
 struct InitOk {
     msg_id: u64,
     in_reply_to: u64,
+    #[serde(flatten)]
+    handshake: Handshake,
 }
 
+/// Asks for a single ID, or for `count` IDs at once.
 #[derive(Debug, Serialize, Deserialize)]
 struct Generate {
     msg_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
 }
 
+/// Carries `id` when a single ID was asked for, and `ids` otherwise.
 #[derive(Debug, Serialize, Deserialize)]
 struct GenerateOk {
     msg_id: u64,
     in_reply_to: u64,
-    r#id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r#id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ids: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,51 +74,140 @@ enum Body {
     InitOk(InitOk),
     Generate(Generate),
     GenerateOk(GenerateOk),
+    Error(ErrorBody),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
-
-impl Message {
-    fn prepare_reply(&self, node: &mut Node) -> Option<Self> {
-        let body: Option<Body> = match &self.body {
-            Body::Init(init_body) => Some(Body::InitOk(InitOk {
-                msg_id: node.incremented_msg_id(),
-                in_reply_to: init_body.msg_id,
-            })),
-
-            Body::Generate(generate_body) => Some(Body::GenerateOk(GenerateOk {
-                msg_id: node.incremented_msg_id(),
-                in_reply_to: generate_body.msg_id,
-                r#id: format!("{}_{}", node.node_id, node.msg_id),
-            })),
-
-            Body::InitOk(_) | Body::GenerateOk(_) => None,
-        };
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+/// How generated IDs are built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum IdFormat {
+    /// The node ID followed by the message ID, e.g. `n1_5`.
+    #[default]
+    Counter,
+    /// A hybrid logical clock timestamp packed with the node's index into a decimal `u128`,
+    /// so that IDs sort roughly by the time they were generated.
+    Hlc,
+    /// A 64-bit snowflake ID packing a timestamp, the node's index, and a sequence number,
+    /// in decimal.
+    Snowflake,
+    /// A 128-bit ULID of a timestamp and random bits, as 26 characters that sort
+    /// lexicographically by the time they were generated.
+    Ulid,
+    /// Consecutive integers from blocks reserved in lin-kv, so that IDs stay unique even if
+    /// a node restarts and forgets its counters.
+    Block,
+}
 
-        let body = body?;
+impl FromStr for IdFormat {
+    type Err = anyhow::Error;
 
-        Some(Self {
-            src: self.dest.clone(),
-            dest: self.src.clone(),
-            body,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "counter" => Ok(IdFormat::Counter),
+            "hlc" => Ok(IdFormat::Hlc),
+            "snowflake" => Ok(IdFormat::Snowflake),
+            "ulid" => Ok(IdFormat::Ulid),
+            "block" => Ok(IdFormat::Block),
+            other => anyhow::bail!("Unknown ID format: {other}"),
+        }
+    }
+}
+
+/// A step of reserving the next block in lin-kv, which reads the first free ID and then
+/// moves it past the block with a `cas`. Another node winning the race makes the `cas` fail,
+/// and the reservation starts over.
+enum Reservation {
+    Read,
+    Cas(Range<u64>),
+}
+
+/// Per-format state of the ID generator.
+enum Generator {
+    Counter,
+    Hlc {
+        clock: Hlc,
+        node_index: u64,
+    },
+    Snowflake(Snowflake),
+    Ulid(Ulid),
+    /// The IDs left in the reserved block, and the requests waiting for the next block.
+    Block {
+        reserved: Range<u64>,
+        waiting: VecDeque<(Message, u64)>,
+        kv: KvClient<Reservation>,
+    },
+}
+
+impl Generator {
+    /// Time-based formats read the time from `time`.
+    fn new(format: IdFormat, node_index: u64, time: Arc<dyn Clock>) -> Result<Self, anyhow::Error> {
+        Ok(match format {
+            IdFormat::Counter => Generator::Counter,
+            IdFormat::Hlc => Generator::Hlc {
+                clock: Hlc::with_clock(time),
+                node_index,
+            },
+            IdFormat::Snowflake => {
+                Generator::Snowflake(Snowflake::with_clock(node_index, time).with_context(
+                    || format!("Snowflake IDs support at most {MAX_SNOWFLAKE_NODES} nodes."),
+                )?)
+            }
+            IdFormat::Ulid => Generator::Ulid(Ulid::with_clock(time)),
+            IdFormat::Block => Generator::Block {
+                reserved: 0..0,
+                waiting: VecDeque::new(),
+                kv: KvClient::new(KvService::LinKv),
+            },
         })
     }
 }
 
 struct Node {
-    #[allow(dead_code)]
     node_id: String,
     msg_id: u64,
+    generator: Generator,
+    checkpoint: Option<Checkpoint>,
 }
 
 impl Node {
-    fn initialize(node_id: String) -> Self {
-        Self { node_id, msg_id: 0 }
+    fn initialize(
+        init: &Init,
+        format: IdFormat,
+        time: Arc<dyn Clock>,
+    ) -> Result<Self, anyhow::Error> {
+        let node_index = init
+            .node_ids
+            .iter()
+            .position(|id| *id == init.node_id)
+            .unwrap_or(init.node_ids.len());
+
+        // A restarted node resumes its msg_id, which the counter format builds IDs from.
+        let mut checkpoint = Checkpoint::from_env(&init.node_id)?;
+        let msg_id = match &mut checkpoint {
+            Some(checkpoint) => checkpoint.restore::<()>()?.map_or(0, |(msg_id, ())| msg_id),
+            None => 0,
+        };
+
+        Ok(Self {
+            node_id: init.node_id.clone(),
+            msg_id,
+            generator: Generator::new(format, node_index as u64, time)?,
+            checkpoint,
+        })
+    }
+
+    fn save_checkpoint(&mut self) -> Result<(), anyhow::Error> {
+        match &mut self.checkpoint {
+            Some(checkpoint) if checkpoint.needs_save(self.msg_id) => {
+                checkpoint.save(self.msg_id, &())
+            }
+            _ => Ok(()),
+        }
     }
 
     fn incremented_msg_id(&mut self) -> u64 {
@@ -88,50 +215,348 @@ impl Node {
         self.msg_id
     }
 
-    fn send<W: Write>(msg: &Message, writer: &mut W) -> Result<(), anyhow::Error> {
-        serde_json::to_writer(&mut *writer, &msg).context("Failed to serialize reply message")?;
-        writer.write_all(b"\n").context("Failed to write newline")?;
-        Ok(())
+    /// Generates IDs right away, except in the `block` format, where they may have to wait
+    /// for a block to be reserved.
+    fn generate<W: Write>(
+        &mut self,
+        msg: Message,
+        count: u64,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        if let Generator::Block { waiting, .. } = &mut self.generator {
+            waiting.push_back((msg, count));
+            return self.serve_waiting(output);
+        }
+
+        let msg_id = self.incremented_msg_id();
+        let ids: Vec<String> = match &mut self.generator {
+            Generator::Counter if count == 1 => vec![format!("{}_{}", self.node_id, msg_id)],
+            Generator::Counter => (0..count)
+                .map(|i| format!("{}_{}_{}", self.node_id, msg_id, i))
+                .collect(),
+            Generator::Hlc { clock, node_index } => (0..count)
+                .map(|_| clock.now().unique_id(*node_index).to_string())
+                .collect(),
+            Generator::Snowflake(snowflake) => (0..count)
+                .map(|_| snowflake.next_id().to_string())
+                .collect(),
+            Generator::Ulid(ulid) => (0..count)
+                .map(|_| ids::encode_ulid(ulid.next_id()))
+                .collect(),
+            Generator::Block { .. } => unreachable!("Block requests wait for a reservation"),
+        };
+
+        self.reply_with_ids(&msg, msg_id, ids, output)
+    }
+
+    fn reply_with_ids<W: Write>(
+        &mut self,
+        msg: &Message,
+        msg_id: u64,
+        mut ids: Vec<String>,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        let Body::Generate(Generate {
+            msg_id: in_reply_to,
+            count,
+        }) = msg.body
+        else {
+            unreachable!("Only generate requests are answered with IDs");
+        };
+
+        let (id, ids) = match count {
+            None => (ids.pop(), None),
+            Some(_) => (None, Some(ids)),
+        };
+
+        msg.reply(Body::GenerateOk(GenerateOk {
+            msg_id,
+            in_reply_to,
+            id,
+            ids,
+        }))
+        .send(output)
+    }
+
+    /// Answers waiting requests in order while the reserved block has enough IDs left, and
+    /// reserves a new block once it runs out. IDs left in a block too small for the next
+    /// request are skipped.
+    fn serve_waiting<W: Write>(&mut self, output: &mut W) -> Result<(), anyhow::Error> {
+        loop {
+            let Generator::Block {
+                reserved,
+                waiting,
+                kv,
+            } = &mut self.generator
+            else {
+                return Ok(());
+            };
+            let Some((_, count)) = waiting.front() else {
+                return Ok(());
+            };
+
+            if reserved.end - reserved.start < *count {
+                if kv.pending() > 0 {
+                    return Ok(());
+                }
+                let msg_id = self.incremented_msg_id();
+                let Generator::Block { kv, .. } = &mut self.generator else {
+                    unreachable!();
+                };
+                return kv
+                    .read(&self.node_id, msg_id, NEXT_BLOCK_KEY, Reservation::Read)?
+                    .send(output);
+            }
+
+            let ids = (reserved.start..reserved.start + count)
+                .map(|id| id.to_string())
+                .collect();
+            reserved.start += count;
+            let (msg, _) = waiting.pop_front().expect("front was checked above");
+
+            let msg_id = self.incremented_msg_id();
+            self.reply_with_ids(&msg, msg_id, ids, output)?;
+        }
+    }
+
+    fn process_kv_reply<W: Write>(
+        &mut self,
+        reply: distributed_system::Message<KvBody>,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        self.save_checkpoint()?;
+        let Generator::Block { waiting, kv, .. } = &mut self.generator else {
+            return Ok(());
+        };
+        let Some((step, result)) = kv.handle_reply(reply) else {
+            return Ok(());
+        };
+        let size = waiting
+            .front()
+            .map_or(BLOCK_SIZE, |(_, count)| BLOCK_SIZE.max(*count));
+
+        match (step, result) {
+            (Reservation::Read, Ok(reply)) => {
+                let start: u64 = reply.value()?;
+                self.reserve(start..start + size, output)
+            }
+
+            (Reservation::Read, Err(KvError::KeyDoesNotExist(_))) => self.reserve(0..size, output),
+
+            (Reservation::Cas(block), Ok(KvReply::Cas)) => {
+                let Generator::Block { reserved, .. } = &mut self.generator else {
+                    unreachable!();
+                };
+                *reserved = block;
+                self.serve_waiting(output)
+            }
+
+            (_, Err(error)) => {
+                log::warn!("Retrying block reservation after lin-kv error: {error}");
+                metrics::increment(metrics::RETRIES);
+                self.serve_waiting(output)
+            }
+
+            (_, Ok(unexpected)) => {
+                log::warn!("Unexpected lin-kv reply: {:?}", unexpected);
+                self.serve_waiting(output)
+            }
+        }
+    }
+
+    fn reserve<W: Write>(
+        &mut self,
+        block: Range<u64>,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        let msg_id = self.incremented_msg_id();
+        let Generator::Block { kv, .. } = &mut self.generator else {
+            unreachable!();
+        };
+        kv.cas(
+            &self.node_id,
+            msg_id,
+            NEXT_BLOCK_KEY,
+            block.start,
+            block.end,
+            block.start == 0,
+            Reservation::Cas(block),
+        )?
+        .send(output)
+    }
+
+    fn process_received_message<W: Write>(
+        &mut self,
+        msg: Message,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        self.save_checkpoint()?;
+        match msg.body {
+            Body::Init(Init { msg_id, .. }) => msg
+                .reply(Body::InitOk(InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: msg_id,
+                    handshake: protocol::answer(),
+                }))
+                .send(output),
+
+            Body::Generate(Generate { msg_id, count }) => match count {
+                Some(count) if count == 0 || count > MAX_COUNT => msg
+                    .error_reply::<Body>(
+                        msg_id,
+                        ErrorCode::MalformedRequest,
+                        format!("count must be between 1 and {MAX_COUNT}"),
+                    )
+                    .send(output),
+                count => self.generate(msg, count.unwrap_or(1), output),
+            },
+
+            Body::InitOk(InitOk { msg_id, .. }) | Body::GenerateOk(GenerateOk { msg_id, .. }) => {
+                msg.error_reply::<Body>(
+                    msg_id,
+                    ErrorCode::NotSupported,
+                    "Unique ID generator does not accept replies",
+                )
+                .send(output)
+            }
+
+            Body::Error(error_body) => {
+                log::warn!("Received error: {:?}", error_body);
+                Ok(())
+            }
+        }
     }
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
-    let mut stdout = std::io::stdout().lock();
+impl Lifecycle for Node {
+    /// Saves the exact `msg_id`, so that a restart doesn't have to skip a whole interval.
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        match &mut self.checkpoint {
+            Some(checkpoint) => checkpoint.save(self.msg_id, &()),
+            None => Ok(()),
+        }
+    }
 
-    let init_msg: Message = serde_json::from_str(
-        &stdin
-            .next()
-            .context("Maelstrom should provide input to STDIN.")?
-            .context("Failed to read message from stdin.")?,
-    )
-    .context("Failed to deserialize provided input to STDIN.")?;
+    fn debug_state(&self) -> serde_json::Value {
+        let generator = match &self.generator {
+            Generator::Counter => serde_json::json!("counter"),
+            Generator::Hlc { node_index, .. } => serde_json::json!({ "hlc": node_index }),
+            Generator::Snowflake(_) => serde_json::json!("snowflake"),
+            Generator::Ulid(_) => serde_json::json!("ulid"),
+            Generator::Block {
+                reserved,
+                waiting,
+                kv,
+            } => serde_json::json!({
+                "block": {
+                    "reserved": [reserved.start, reserved.end],
+                    "waiting": waiting.len(),
+                    "pending_kv_requests": kv.pending(),
+                }
+            }),
+        };
+        serde_json::json!({
+            "node_id": self.node_id,
+            "msg_id": self.msg_id,
+            "generator": generator,
+        })
+    }
+}
+
+/// Runs a node of the unique-ids workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// How generated IDs are built: counter, hlc, snowflake, ulid, or block.
+    #[arg(long, env = "UNIQUE_IDS_FORMAT", default_value = "counter")]
+    format: IdFormat,
+}
+
+pub fn main() -> Result<(), anyhow::Error> {
+    run(config::parse("unique-ids")?)
+}
+
+pub fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    cli.node.apply()?;
+    let mut stdin = transport::input();
+    let mut stdout = Output::stdout();
+
+    let mut init_line = String::new();
+    stdin
+        .read_line(&mut init_line)
+        .context("Failed to read message from stdin.")?;
+    if init_line.is_empty() {
+        bail!("Maelstrom should provide input to STDIN.");
+    }
+    runtime::received(init_line.as_bytes());
+    let mut node = start(
+        init_line.as_bytes(),
+        cli.format,
+        Arc::new(SystemClock),
+        &mut stdout,
+    )?;
+    stdout.flush().context("Failed to flush STDOUT.")?;
+
+    runtime::run_lines(&mut node, stdin, &mut stdout, handle_line)?;
+    stdout.finish()
+}
+
+/// Starts the node an `init` line is for, and answers the `init`.
+fn start(
+    init_line: &[u8],
+    format: IdFormat,
+    time: Arc<dyn Clock>,
+    stdout: &mut impl Write,
+) -> Result<Node, anyhow::Error> {
+    let init_msg: Message = serde_json::from_slice(init_line)
+        .context("Failed to deserialize provided input to STDIN.")?;
 
     let Body::Init(ref init_body) = init_msg.body else {
         bail!("Expected Init message as the first received message.");
     };
-    let mut node = Node::initialize(init_body.node_id.clone());
+    let mut node = Node::initialize(init_body, format, time)?;
+    node.on_init(&init_body.node_id);
 
-    let init_reply = init_msg
-        .prepare_reply(&mut node)
-        .context("Failed to prepare InitOk message")?;
+    node.process_received_message(init_msg, stdout)?;
+    Ok(node)
+}
 
-    Node::send(&init_reply, &mut stdout)?;
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
 
-    while let Ok(line) = stdin
-        .next()
-        .context("Maelstrom should provide input to STDIN.")?
-    {
-        let msg: Message = serde_json::from_str(&line)
-            .context("Failed to deserialize provided input to STDIN.")?;
+fn handle_line(node: &mut Node, line: &[u8], stdout: &mut impl Write) -> Result<(), anyhow::Error> {
+    if let Some(reply) = kv::parse_reply(line)? {
+        node.process_kv_reply(reply, stdout)?;
+        return Ok(());
+    }
 
-        if let Some(reply) = msg.prepare_reply(&mut node) {
-            Node::send(&reply, &mut stdout)?;
-        } else {
-            eprintln!("No reply was prepared for message: {:?}", msg);
+    let msg =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(msg) => msg,
+            Err(error_reply) => {
+                error_reply.send(stdout)?;
+                return Ok(());
+            }
         };
-    }
 
-    Ok(())
+    node.process_received_message(msg, stdout)
+}
+
+/// A unique-ids node for a `sim::Network`, started by the first line it gets, which reads
+/// the time from `time`.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Box<dyn SimNode> {
+    sim::lines(
+        None,
+        move |node: &mut Option<Node>, line, output| match node {
+            Some(node) => handle_line(node, line, output),
+            None => {
+                *node = Some(start(line, cli.format, time.clone(), output)?);
+                Ok(())
+            }
+        },
+    )
 }
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+/// Circuits a node opened to peers that stopped responding.
+pub const OPENED: &str = "circuits_opened";
+
+/// Stops sending a peer requests once too many in a row went unanswered, so that a node
+/// doesn't keep sending messages into a partition. While the circuit is open, a request
+/// may go through once every probe interval, to find out whether the peer is back, and the
+/// first reply closes the circuit again.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    probe_interval: Duration,
+    /// Timeouts in a row since the last reply.
+    failures: u32,
+    /// While the circuit is open, when it opened or was last probed.
+    last_probe: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Opens after `threshold` timeouts in a row.
+    pub fn new(threshold: u32, probe_interval: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            probe_interval,
+            failures: 0,
+            last_probe: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.last_probe.is_some()
+    }
+
+    /// Whether a request may be sent: always while closed, and once a probe interval
+    /// passed since the last probe while open.
+    pub fn allows(&self, now: Instant) -> bool {
+        self.last_probe
+            .is_none_or(|last| now >= last + self.probe_interval)
+    }
+
+    /// Notes that a request was sent. While open, it's a probe, and the next waits another
+    /// interval.
+    pub fn probed(&mut self, now: Instant) {
+        if self.is_open() {
+            self.last_probe = Some(now);
+        }
+    }
+
+    /// Notes that a request timed out. Returns whether that opened the circuit.
+    pub fn failure(&mut self, now: Instant) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        if self.is_open() || self.failures < self.threshold {
+            return false;
+        }
+        metrics::increment(OPENED);
+        self.last_probe = Some(now);
+        true
+    }
+
+    /// Notes that a reply arrived. Returns whether that closed the circuit.
+    pub fn success(&mut self) -> bool {
+        self.failures = 0;
+        self.last_probe.take().is_some()
+    }
+}
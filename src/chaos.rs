@@ -0,0 +1,269 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::metrics;
+use crate::rng::Rng;
+use crate::runtime::is_node_id;
+
+/// Messages to other nodes a node dropped on purpose.
+pub const DROPPED: &str = "chaos_dropped";
+/// Messages to other nodes a node sent twice on purpose.
+pub const DUPLICATED: &str = "chaos_duplicated";
+/// Messages to other nodes a node held back on purpose.
+pub const DELAYED: &str = "chaos_delayed";
+
+/// Faults a node injects into its own messages to other nodes, to exercise its retries and
+/// deduplication without Maelstrom's nemesis. Only meant for testing. Given as a comma
+/// separated list of `name=value`, e.g. `drop=0.1,duplicate=0.05,delay=0.2`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chaos {
+    /// The fraction of messages dropped.
+    pub drop: f64,
+    /// The fraction of messages sent twice.
+    pub duplicate: f64,
+    /// The fraction of messages held back, each for up to `max_delay`.
+    pub delay: f64,
+    /// Set by `max-delay-ms`.
+    pub max_delay: Duration,
+    /// Seeds the faults, combined with the node's id, so that runs repeat.
+    pub seed: Option<u64>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            drop: 0.0,
+            duplicate: 0.0,
+            delay: 0.0,
+            max_delay: Duration::from_millis(100),
+            seed: None,
+        }
+    }
+}
+
+impl FromStr for Chaos {
+    type Err = anyhow::Error;
+
+    /// Parses `drop`, `duplicate`, and `delay` fractions, `max-delay-ms`, and `seed`. Those
+    /// left out are 0, 100ms, and none.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Chaos::default();
+        for setting in s.split(',').filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .with_context(|| format!("Expected name=value, got {setting}"))?;
+            let fraction = || -> Result<f64, anyhow::Error> {
+                let fraction: f64 = value
+                    .parse()
+                    .with_context(|| format!("Invalid {name}: {value}"))?;
+                anyhow::ensure!(
+                    (0.0..=1.0).contains(&fraction),
+                    "The {name} fraction must be between 0 and 1"
+                );
+                Ok(fraction)
+            };
+            match name {
+                "drop" => chaos.drop = fraction()?,
+                "duplicate" => chaos.duplicate = fraction()?,
+                "delay" => chaos.delay = fraction()?,
+                "max-delay-ms" => {
+                    let ms = value
+                        .parse()
+                        .with_context(|| format!("Invalid max-delay-ms: {value}"))?;
+                    chaos.max_delay = Duration::from_millis(ms);
+                }
+                "seed" => {
+                    let seed = value
+                        .parse()
+                        .with_context(|| format!("Invalid seed: {value}"))?;
+                    chaos.seed = Some(seed);
+                }
+                other => anyhow::bail!("Unknown chaos setting: {other}"),
+            }
+        }
+        Ok(chaos)
+    }
+}
+
+static CHAOS: Mutex<Option<Chaos>> = Mutex::new(None);
+
+/// Makes `chaos` apply to the output of the whole process, from now on.
+pub fn install(chaos: Chaos) {
+    *CHAOS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(chaos);
+}
+
+/// The chaos installed, if any.
+pub fn installed() -> Option<Chaos> {
+    *CHAOS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+    #[serde(borrow)]
+    dest: Cow<'a, str>,
+}
+
+/// Writes lines to a writer as they are, except for messages to other nodes, which it
+/// drops, duplicates, or holds back as `Chaos` says. Held back lines are written by a thread
+/// of its own once due, and those still held back when the writer is dropped are written
+/// once due before it returns.
+pub struct ChaosWriter<W: Write + Send + 'static> {
+    inner: Arc<Mutex<W>>,
+    chaos: Chaos,
+    /// Made once the node's id is known, from the first message to another node.
+    rng: Option<Rng>,
+    /// The start of a line not written to the end yet.
+    partial: Vec<u8>,
+    delayed: Option<Sender<(Instant, Vec<u8>)>>,
+    delayer: Option<JoinHandle<()>>,
+}
+
+impl<W: Write + Send + 'static> ChaosWriter<W> {
+    pub fn new(inner: W, chaos: Chaos) -> Self {
+        let inner = Arc::new(Mutex::new(inner));
+        let (delayed, receiver) = mpsc::channel::<(Instant, Vec<u8>)>();
+        let writer = Arc::clone(&inner);
+        let delayer = std::thread::spawn(move || {
+            // Ordered by when they're due, and then by when they were held back.
+            let mut due: BinaryHeap<(Reverse<Instant>, u64, Vec<u8>)> = BinaryHeap::new();
+            let mut next = 0u64;
+            let mut open = true;
+            while open || !due.is_empty() {
+                let timeout = match due.peek() {
+                    Some(&(Reverse(at), _, _)) => at.saturating_duration_since(Instant::now()),
+                    None => Duration::MAX,
+                };
+                match receiver.recv_timeout(timeout) {
+                    Ok((at, line)) => {
+                        due.push((Reverse(at), next, line));
+                        next += 1;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) if open => {
+                        open = false;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        std::thread::sleep(timeout);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+                let now = Instant::now();
+                let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+                while due.peek().is_some_and(|&(Reverse(at), _, _)| at <= now) {
+                    let (_, _, line) = due.pop().expect("A line is due");
+                    // A node whose output is gone has nobody to send to.
+                    if writer
+                        .write_all(&line)
+                        .and_then(|_| writer.flush())
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            inner,
+            chaos,
+            rng: None,
+            partial: Vec::new(),
+            delayed: Some(delayed),
+            delayer: Some(delayer),
+        }
+    }
+
+    /// Writes `line`, with its newline, or what the chaos makes of it.
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let header = serde_json::from_slice::<Header>(line)
+            .ok()
+            .filter(|header| is_node_id(&header.dest));
+        let Some(header) = header else {
+            return self.lock().write_all(line);
+        };
+        let rng = self
+            .rng
+            .get_or_insert_with(|| Rng::for_node(self.chaos.seed, &header.src));
+        if rng.chance(self.chaos.drop) {
+            log::debug!("Chaos dropped a message to {}", header.dest);
+            metrics::increment(DROPPED);
+            return Ok(());
+        }
+        let copies = if rng.chance(self.chaos.duplicate) {
+            metrics::increment(DUPLICATED);
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let rng = self.rng.as_mut().expect("The rng was made above");
+            if rng.chance(self.chaos.delay) {
+                let max_ms = self.chaos.max_delay.as_millis().max(1) as usize;
+                let delay = Duration::from_millis(1 + rng.below(max_ms) as u64);
+                metrics::increment(DELAYED);
+                if let Some(delayed) = &self.delayed {
+                    if delayed
+                        .send((Instant::now() + delay, line.to_vec()))
+                        .is_ok()
+                    {
+                        continue;
+                    }
+                }
+            }
+            self.lock().write_all(line)?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, W> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write + Send + 'static> Write for ChaosWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(buf.len());
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        for line in complete.split_inclusive(|&b| b == b'\n') {
+            self.write_line(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for ChaosWriter<W> {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            let _ = self.lock().write_all(&partial);
+        }
+        self.delayed.take();
+        if let Some(delayer) = self.delayer.take() {
+            let _ = delayer.join();
+        }
+        let _ = self.lock().flush();
+    }
+}
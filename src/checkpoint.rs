@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// How many msg_ids a node may use past the last checkpoint. A checkpoint records the
+/// msg_id a restarted node resumes from, this far ahead of the current one, so that
+/// msg_ids are never reused even if the node crashes before saving again. Nodes save again
+/// once half of the lease is used up.
+pub const MSG_ID_LEASE: u64 = 10_000;
+
+/// What a checkpoint file holds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot<S> {
+    pub node_id: String,
+    pub msg_id: u64,
+    pub state: S,
+}
+
+/// Saves a node's msg_id counter and workload state to `<dir>/<node_id>.json`, and restores
+/// them when the node starts again. Files are replaced atomically, so a crash while saving
+/// leaves the previous checkpoint intact.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    node_id: String,
+    msg_id_limit: u64,
+}
+
+impl Checkpoint {
+    pub fn new(dir: impl AsRef<Path>, node_id: &str) -> Result<Self, anyhow::Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        Ok(Self {
+            path: dir.join(format!("{node_id}.json")),
+            node_id: node_id.to_string(),
+            msg_id_limit: 0,
+        })
+    }
+
+    /// Uses the directory in `CHECKPOINT_DIR`, if set. Nodes don't persist anything otherwise.
+    pub fn from_env(node_id: &str) -> Result<Option<Self>, anyhow::Error> {
+        match std::env::var("CHECKPOINT_DIR") {
+            Ok(dir) => Self::new(dir, node_id).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Returns the msg_id to resume from and the saved state, if this node saved any.
+    pub fn restore<S: DeserializeOwned>(&mut self) -> Result<Option<(u64, S)>, anyhow::Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to read {}", self.path.display()))
+            }
+        };
+
+        let snapshot: Snapshot<S> = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to deserialize {}", self.path.display()))?;
+        if snapshot.node_id != self.node_id {
+            anyhow::bail!(
+                "{} belongs to {}, not {}",
+                self.path.display(),
+                snapshot.node_id,
+                self.node_id
+            );
+        }
+
+        self.msg_id_limit = snapshot.msg_id;
+        Ok(Some((snapshot.msg_id, snapshot.state)))
+    }
+
+    /// Whether a node at `msg_id` used up half of the lease of the last checkpoint, and should
+    /// save a new one before sending anything else.
+    pub fn needs_save(&self, msg_id: u64) -> bool {
+        msg_id + MSG_ID_LEASE / 2 >= self.msg_id_limit
+    }
+
+    /// Saves `state`, with a msg_id lease starting at `msg_id`.
+    pub fn save<S: Serialize>(&mut self, msg_id: u64, state: &S) -> Result<(), anyhow::Error> {
+        let snapshot = Snapshot {
+            node_id: self.node_id.clone(),
+            msg_id: msg_id + MSG_ID_LEASE,
+            state,
+        };
+        let contents =
+            serde_json::to_string(&snapshot).context("Failed to serialize checkpoint")?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to rename {}", tmp_path.display()))?;
+
+        self.msg_id_limit = snapshot.msg_id;
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::message::{Message, Outgoing};
+use crate::metrics;
+use crate::protocol;
+use crate::rng::Rng;
+use crate::runtime::is_node_id;
+
+/// The extension under which nodes agree to send each other chunks.
+pub const CAPABILITY: &str = "chunking";
+
+/// Lines to other nodes longer than this many bytes are split into chunks of at most this
+/// many bytes of the line each.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Messages whose chunks are partly received that a node keeps. The oldest is given up on
+/// beyond this, as its other chunks were likely lost with its sender.
+const MAX_PENDING: usize = 64;
+
+/// The most chunks a message may be split into, so that a bad chunk can't make a node
+/// reserve room for more.
+const MAX_CHUNKS: usize = 16 * 1024;
+
+/// Messages a node split into chunks.
+pub const CHUNKED: &str = "messages_chunked";
+
+/// A piece of a message too large for a single line. `data` is the piece of the message's
+/// line, which is split between characters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "chunk")]
+pub struct Chunk {
+    /// Tells the chunks of a message from those of other messages of the same sender.
+    pub id: u64,
+    pub index: usize,
+    pub count: usize,
+    pub data: String,
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+    #[serde(borrow)]
+    dest: Cow<'a, str>,
+}
+
+/// Splits `line` into the data of chunks of at most `size` bytes each, between characters.
+pub fn split(line: &str, size: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let mut end = size.max(1).min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end += 1;
+        }
+        let (piece, after) = rest.split_at(end);
+        pieces.push(piece);
+        rest = after;
+    }
+    pieces
+}
+
+/// Writes lines to a writer as they are, except for those to other nodes longer than its
+/// chunk size, which it writes as a `chunk` message each, once chunking was agreed to.
+pub struct ChunkingWriter<W: Write> {
+    inner: W,
+    chunk_size: usize,
+    /// Starts at random, so that the chunks of a node that restarted aren't taken for
+    /// those of its previous run.
+    next_id: u64,
+    /// The start of a line not written to the end yet.
+    partial: Vec<u8>,
+}
+
+impl<W: Write> ChunkingWriter<W> {
+    pub fn new(inner: W, chunk_size: usize) -> Self {
+        Self {
+            inner,
+            chunk_size,
+            next_id: Rng::from_entropy().next_u64(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// Writes `line`, with its newline, or the chunks it's split into.
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if line.len() <= self.chunk_size || protocol::agreed(CAPABILITY).is_none() {
+            return self.inner.write_all(line);
+        }
+        let text = std::str::from_utf8(line.strip_suffix(b"\n").unwrap_or(line));
+        let header = text
+            .ok()
+            .and_then(|text| Some((text, serde_json::from_str::<Header>(text).ok()?)))
+            .filter(|(_, header)| is_node_id(&header.dest));
+        let Some((text, header)) = header else {
+            return self.inner.write_all(line);
+        };
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let pieces = split(text, self.chunk_size);
+        metrics::increment(CHUNKED);
+        for (index, data) in pieces.iter().enumerate() {
+            let chunk = Outgoing {
+                src: &header.src,
+                dest: &header.dest,
+                body: Chunk {
+                    id,
+                    index,
+                    count: pieces.len(),
+                    data: data.to_string(),
+                },
+            };
+            serde_json::to_writer(&mut self.inner, &chunk)?;
+            self.inner.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(buf.len());
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        for line in complete.split_inclusive(|&b| b == b'\n') {
+            self.write_line(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The chunks of a message received so far.
+struct Partial {
+    pieces: Vec<Option<String>>,
+    received: usize,
+}
+
+/// Puts messages split into chunks back together.
+#[derive(Default)]
+pub struct Reassembler {
+    /// By sender and id.
+    pending: HashMap<(String, u64), Partial>,
+    /// The keys of `pending`, oldest first.
+    order: VecDeque<(String, u64)>,
+}
+
+impl Reassembler {
+    /// Takes a line received. Returns it as it is unless it's a chunk, and the line of the
+    /// message once a chunk completes it, with a newline. Returns `None` for other chunks,
+    /// which are kept until the rest of their message arrives.
+    pub fn receive<'a>(&mut self, line: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        if !line.windows(7).any(|window| window == b"\"chunk\"") {
+            return Some(Cow::Borrowed(line));
+        }
+        let Ok(message) = serde_json::from_slice::<Message<Chunk>>(line) else {
+            return Some(Cow::Borrowed(line));
+        };
+        let Message {
+            src, body: chunk, ..
+        } = message;
+        if chunk.count == 0 || chunk.count > MAX_CHUNKS || chunk.index >= chunk.count {
+            log::warn!(
+                "Dropped a chunk {} of {} from {src}",
+                chunk.index,
+                chunk.count
+            );
+            return None;
+        }
+
+        let key = (src, chunk.id);
+        if !self.pending.contains_key(&key) {
+            if self.order.len() == MAX_PENDING {
+                if let Some(oldest) = self.order.pop_front() {
+                    log::warn!(
+                        "Gave up on the chunks of message {} of {}",
+                        oldest.1,
+                        oldest.0
+                    );
+                    self.pending.remove(&oldest);
+                }
+            }
+            self.order.push_back(key.clone());
+            self.pending.insert(
+                key.clone(),
+                Partial {
+                    pieces: vec![None; chunk.count],
+                    received: 0,
+                },
+            );
+        }
+        let partial = self.pending.get_mut(&key).expect("Inserted above");
+        if partial.pieces.len() != chunk.count {
+            log::warn!(
+                "Dropped a chunk of message {} of {} with another count",
+                key.1,
+                key.0
+            );
+            return None;
+        }
+        let piece = &mut partial.pieces[chunk.index];
+        if piece.is_none() {
+            *piece = Some(chunk.data);
+            partial.received += 1;
+        }
+        if partial.received < partial.pieces.len() {
+            return None;
+        }
+
+        let partial = self.pending.remove(&key).expect("Found above");
+        self.order.retain(|pending| *pending != key);
+        let mut line: Vec<u8> = partial
+            .pieces
+            .into_iter()
+            .flatten()
+            .collect::<String>()
+            .into();
+        line.push(b'\n');
+        Some(Cow::Owned(line))
+    }
+}
+
+/// The lines of an input, with messages split into chunks put back together.
+pub struct Reassembling<R: BufRead> {
+    inner: R,
+    reassembler: Reassembler,
+    line: Vec<u8>,
+    read: usize,
+}
+
+impl<R: BufRead> Reassembling<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            reassembler: Reassembler::default(),
+            line: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Reassembling<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Reassembling<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        let mut received = Vec::new();
+        while self.read == self.line.len() {
+            received.clear();
+            if self.inner.read_until(b'\n', &mut received)? == 0 {
+                return Ok(&[]);
+            }
+            if let Some(line) = self.reassembler.receive(&received) {
+                self.line = line.into_owned();
+                self.read = 0;
+            }
+        }
+        Ok(&self.line[self.read..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.read = (self.read + amount).min(self.line.len());
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Where a node reads the time, and what runs its periodic work. Nodes use a clock they
+/// were given instead of calling `Instant::now` or starting threads that sleep, so that a
+/// simulation or a test can run them in virtual time.
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time, for timestamps that other nodes compare with theirs.
+    fn system_time(&self) -> SystemTime;
+
+    /// Waits for `duration` to pass.
+    fn sleep(&self, duration: Duration);
+
+    /// Calls `tick` every `interval`, until it returns `false` or the returned `Ticker` is
+    /// dropped. `tick` must not use the clock.
+    fn schedule(&self, interval: Duration, tick: Box<dyn FnMut() -> bool + Send>) -> Ticker;
+}
+
+/// Stops a schedule of `Clock::schedule` when dropped.
+#[must_use = "The schedule stops once its ticker is dropped"]
+pub struct Ticker {
+    stop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Ticker {
+    fn new(stop: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            stop: Some(Box::new(stop)),
+        }
+    }
+}
+
+impl fmt::Debug for Ticker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ticker").finish_non_exhaustive()
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+    }
+}
+
+/// The clock of the machine, which nodes run on outside of tests. Each schedule ticks on a
+/// thread of its own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    /// Dropping the ticker stops the thread right away, without waiting for the current
+    /// interval to pass.
+    fn schedule(&self, interval: Duration, mut tick: Box<dyn FnMut() -> bool + Send>) -> Ticker {
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if !tick() {
+                        return;
+                    }
+                }
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+        Ticker::new(move || {
+            drop(stop);
+            let _ = handle.join();
+        })
+    }
+}
+
+/// A clock that only moves when it's advanced. It starts at the time it was created, and
+/// nodes sharing it see time pass in the same steps, whatever their real timing. Schedules
+/// tick while it's advanced, at the virtual times they're due, in the order they were made
+/// when several are due at once.
+pub struct VirtualClock {
+    start: Instant,
+    started_at: SystemTime,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    elapsed: Duration,
+    next_id: u64,
+    schedules: Vec<Scheduled>,
+}
+
+struct Scheduled {
+    id: u64,
+    /// When it ticks next, as time since the clock started.
+    due: Duration,
+    interval: Duration,
+    tick: Box<dyn FnMut() -> bool + Send>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            started_at: SystemTime::now(),
+            state: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for VirtualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("VirtualClock")
+            .field("elapsed", &state.elapsed)
+            .field("schedules", &state.schedules.len())
+            .finish()
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// How much time passed since the clock started.
+    pub fn elapsed(&self) -> Duration {
+        self.state().elapsed
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let to = self.elapsed() + by;
+        self.advance_to(self.start + to);
+    }
+
+    /// Moves the clock forward to `at`, unless it's there already, ticking every schedule
+    /// that's due on the way.
+    pub fn advance_to(&self, at: Instant) {
+        let target = at.saturating_duration_since(self.start);
+        let mut state = self.state();
+        loop {
+            let next = state
+                .schedules
+                .iter_mut()
+                .filter(|scheduled| scheduled.due <= target)
+                .min_by_key(|scheduled| (scheduled.due, scheduled.id));
+            let Some(scheduled) = next else {
+                break;
+            };
+            let due = scheduled.due;
+            scheduled.due += scheduled.interval.max(Duration::from_nanos(1));
+            let id = scheduled.id;
+            let more = (scheduled.tick)();
+            state.elapsed = state.elapsed.max(due);
+            if !more {
+                state.schedules.retain(|scheduled| scheduled.id != id);
+            }
+        }
+        state.elapsed = state.elapsed.max(target);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.started_at + self.elapsed()
+    }
+
+    /// Advances the clock, as nothing else would.
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    fn schedule(&self, interval: Duration, tick: Box<dyn FnMut() -> bool + Send>) -> Ticker {
+        let mut state = self.state();
+        let id = state.next_id;
+        state.next_id += 1;
+        let due = state.elapsed + interval;
+        state.schedules.push(Scheduled {
+            id,
+            due,
+            interval,
+            tick,
+        });
+
+        let schedules: Weak<Mutex<State>> = Arc::downgrade(&self.state);
+        Ticker::new(move || {
+            if let Some(state) = schedules.upgrade() {
+                let mut state = state
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                state.schedules.retain(|scheduled| scheduled.id != id);
+            }
+        })
+    }
+}
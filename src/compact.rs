@@ -0,0 +1,65 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Encodes a set of numbers as the ranges of consecutive numbers it's made of, each as the
+/// gap from the end of the previous range followed by its length. Broadcast messages are
+/// mostly consecutive, so a set of thousands of them encodes to a handful of small numbers.
+pub fn encode_ranges<'a>(numbers: impl IntoIterator<Item = &'a u64>) -> Vec<u64> {
+    let mut sorted: Vec<u64> = numbers.into_iter().copied().collect();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut encoded = Vec::new();
+    // One past the end of the previous range, which is past `u64::MAX` if it ends there.
+    let mut end = 0u128;
+    let mut numbers = sorted.into_iter().peekable();
+    while let Some(start) = numbers.next() {
+        let mut last = start;
+        while let Some(next) = numbers.next_if(|&next| next == last + 1) {
+            last = next;
+        }
+        encoded.extend([start - end as u64, last - start + 1]);
+        end = last as u128 + 1;
+    }
+    encoded
+}
+
+/// Decodes what `encode_ranges` produced.
+pub fn decode_ranges<C: FromIterator<u64>>(encoded: &[u64]) -> Result<C, anyhow::Error> {
+    if !encoded.len().is_multiple_of(2) {
+        anyhow::bail!(
+            "Ranges need a gap and a length each, got {} numbers",
+            encoded.len()
+        );
+    }
+
+    let mut end = 0u128;
+    let mut ranges = Vec::with_capacity(encoded.len() / 2);
+    for pair in encoded.chunks(2) {
+        let start = end + pair[0] as u128;
+        end = start + pair[1] as u128;
+        if pair[1] == 0 || end > u64::MAX as u128 + 1 {
+            anyhow::bail!("Invalid range of {} numbers from {start}", pair[1]);
+        }
+        ranges.push(start as u64..=(end - 1) as u64);
+    }
+    Ok(ranges.into_iter().flatten().collect())
+}
+
+/// Serializes a set of numbers with `encode_ranges`, for use with `#[serde(with = ...)]`.
+pub fn serialize<'a, S, C>(numbers: &'a C, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    &'a C: IntoIterator<Item = &'a u64>,
+{
+    encode_ranges(numbers).serialize(serializer)
+}
+
+/// Deserializes a set of numbers serialized with `serialize`.
+pub fn deserialize<'de, D, C>(deserializer: D) -> Result<C, D::Error>
+where
+    D: Deserializer<'de>,
+    C: FromIterator<u64>,
+{
+    let encoded = Vec::<u64>::deserialize(deserializer)?;
+    decode_ranges(&encoded).map_err(serde::de::Error::custom)
+}
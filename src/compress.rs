@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::Error as _;
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::{compact, metrics, protocol};
+
+/// The extension under which nodes agree to send each other compressed gossip.
+pub const CAPABILITY: &str = "compressed_gossip";
+
+/// Sets of messages that encode to fewer bytes than this are sent as they are, as
+/// compressing them saves little, and costs both ends the time.
+pub const THRESHOLD: usize = 1024;
+
+/// The most bytes a compressed payload may expand to, so that a bad one can't exhaust the
+/// memory of the node that receives it.
+const MAX_DECOMPRESSED: u64 = 64 << 20;
+
+/// Gossip a node compressed.
+pub const COMPRESSED: &str = "gossip_compressed";
+
+/// Gzips `bytes`, and encodes them in base64, so that they fit in a JSON string.
+pub fn compress(bytes: &[u8]) -> String {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder
+        .write_all(bytes)
+        .expect("Writing to a Vec doesn't fail");
+    STANDARD.encode(encoder.finish().expect("Writing to a Vec doesn't fail"))
+}
+
+/// Undoes `compress`.
+pub fn decompress(text: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let compressed = STANDARD
+        .decode(text)
+        .context("Compressed payload isn't base64")?;
+    let mut bytes = Vec::new();
+    GzDecoder::new(compressed.as_slice())
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut bytes)
+        .context("Compressed payload isn't gzip")?;
+    anyhow::ensure!(
+        bytes.len() as u64 <= MAX_DECOMPRESSED,
+        "Compressed payload expands to more than {MAX_DECOMPRESSED} bytes"
+    );
+    Ok(bytes)
+}
+
+/// Serializes a set of numbers into the body it's flattened into, for use with
+/// `#[serde(flatten, with = ...)]`. They go under `messages` in the encoding of `compact`, or
+/// once `compressed_gossip` was agreed to and the encoding is large, under
+/// `compressed_messages` as that encoding in JSON, compressed.
+pub fn serialize<'a, S, C>(numbers: &'a C, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    &'a C: IntoIterator<Item = &'a u64>,
+{
+    let encoded = compact::encode_ranges(numbers);
+    let mut map = serializer.serialize_map(Some(1))?;
+    if protocol::agreed(CAPABILITY).is_some() {
+        let json = serde_json::to_vec(&encoded).map_err(S::Error::custom)?;
+        if json.len() >= THRESHOLD {
+            metrics::increment(COMPRESSED);
+            map.serialize_entry("compressed_messages", &compress(&json))?;
+            return map.end();
+        }
+    }
+    map.serialize_entry("messages", &encoded)?;
+    map.end()
+}
+
+#[derive(Deserialize)]
+struct Messages {
+    messages: Option<Vec<u64>>,
+    compressed_messages: Option<String>,
+}
+
+/// Deserializes a set of numbers serialized with `serialize`. Compressed ones are accepted
+/// whether or not they were agreed to.
+pub fn deserialize<'de, D, C>(deserializer: D) -> Result<C, D::Error>
+where
+    D: Deserializer<'de>,
+    C: FromIterator<u64>,
+{
+    let encoded = match Messages::deserialize(deserializer)? {
+        Messages {
+            messages: Some(messages),
+            compressed_messages: None,
+        } => messages,
+        Messages {
+            messages: None,
+            compressed_messages: Some(compressed),
+        } => {
+            let json = decompress(&compressed).map_err(D::Error::custom)?;
+            serde_json::from_slice(&json).map_err(D::Error::custom)?
+        }
+        Messages { messages: None, .. } => return Err(D::Error::missing_field("messages")),
+        Messages { .. } => {
+            return Err(D::Error::custom(
+                "Expected messages or compressed_messages, not both",
+            ))
+        }
+    };
+    compact::decode_ranges(&encoded).map_err(D::Error::custom)
+}
@@ -0,0 +1,273 @@
+use std::ffi::OsString;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, Command, Parser, ValueEnum};
+
+use crate::breaker::CircuitBreaker;
+use crate::chaos::{self, Chaos};
+use crate::journal::{self, Journal};
+use crate::logging::{Filter, LogFormat, Logger};
+use crate::record::{self, Recorder};
+use crate::transport::{self, Codec, Peers, Tcp, Unix};
+
+/// Options every node binary takes, as a flag or from the environment.
+#[derive(Debug, Clone, Args)]
+pub struct NodeArgs {
+    /// Least severe diagnostics written to stderr.
+    #[arg(long, env = "LOG_LEVEL", value_enum, default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+    /// Levels for some modules instead, e.g. `broadcast=debug,raft=trace`.
+    #[arg(long, env = "LOG_FILTER", value_name = "MODULE=LEVEL,...")]
+    pub log_filter: Option<Filter>,
+    /// How diagnostics are written: human or json, one record per line.
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Human)]
+    pub log_format: LogFormat,
+    /// TOML file with options, under the flags and environment variables that are set.
+    #[arg(long, env = "NODE_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Records every line received, with when it arrived, to a file named after the node in
+    /// this directory, for the `replay` binary.
+    #[arg(long, env = "NODE_RECORD", value_name = "DIR")]
+    pub record: Option<PathBuf>,
+    /// Journals every event a node handles, with how its state changed, to a file named after
+    /// the node in this directory, for the `journal` binary.
+    #[arg(long, env = "NODE_JOURNAL", value_name = "DIR")]
+    pub journal: Option<PathBuf>,
+    /// For testing only: drops, duplicates, or delays some messages to other nodes, e.g.
+    /// `drop=0.1,duplicate=0.05,delay=0.2,max-delay-ms=100,seed=7`.
+    #[arg(long, env = "NODE_CHAOS", value_name = "NAME=VALUE,...")]
+    pub chaos: Option<Chaos>,
+    /// Sends and receives newline-delimited JSON over TCP instead of stdio, listening on this
+    /// address, e.g. `0.0.0.0:7000`.
+    #[arg(long, env = "NODE_LISTEN", value_name = "HOST:PORT")]
+    pub listen: Option<String>,
+    /// The addresses of the other nodes when listening on TCP, e.g.
+    /// `n1=10.0.0.2:7000,n2=10.0.0.3:7000`.
+    #[arg(
+        long,
+        env = "NODE_PEERS",
+        value_name = "ID=HOST:PORT,...",
+        requires = "listen"
+    )]
+    pub peers: Option<Peers>,
+    /// What to ask other nodes to write messages in over TCP: `json`, or the smaller and
+    /// cheaper `msgpack`. Clients always get JSON.
+    #[arg(
+        long,
+        env = "NODE_CODEC",
+        value_enum,
+        default_value_t = Codec::Json,
+        requires = "listen"
+    )]
+    pub codec: Codec,
+    /// Sends and receives over a connection to the Unix domain socket at this path instead of
+    /// stdio, as the `harness` binary has nodes do.
+    #[arg(
+        long,
+        env = "NODE_UNIX",
+        value_name = "PATH",
+        conflicts_with = "listen"
+    )]
+    pub unix: Option<PathBuf>,
+}
+
+impl NodeArgs {
+    /// Makes the options take effect for the whole process.
+    pub fn apply(&self) -> Result<(), anyhow::Error> {
+        let filter = self.log_filter.clone().unwrap_or_default();
+        Logger::new(self.log_level, filter, self.log_format).install();
+        if let Some(dir) = &self.record {
+            record::install(Recorder::create(dir)?);
+        }
+        if let Some(dir) = &self.journal {
+            journal::install(Journal::create(dir)?);
+        }
+        if let Some(address) = &self.listen {
+            let peers = self.peers.clone().unwrap_or_default();
+            transport::install(Box::new(Tcp::bind(address, peers, self.codec)?));
+        }
+        if let Some(path) = &self.unix {
+            transport::install(Box::new(Unix::connect(path)?));
+        }
+        if let Some(chaos) = self.chaos {
+            log::warn!("Injecting chaos into messages to other nodes: {chaos:?}");
+            chaos::install(chaos);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// How long to wait for a reply before sending a request again. The wait doubles after
+/// every retry, up to the maximum, and adapts to the round-trip time once replies arrive.
+#[derive(Debug, Clone, Copy, Args)]
+pub struct RetryArgs {
+    /// Milliseconds to wait for the first reply.
+    #[arg(
+        long = "retry-timeout-ms",
+        env = "RETRY_TIMEOUT_MS",
+        value_name = "MS",
+        value_parser = millis,
+        default_value = "500"
+    )]
+    pub timeout: Duration,
+    /// Milliseconds the wait grows to at most.
+    #[arg(
+        long = "retry-max-timeout-ms",
+        env = "RETRY_MAX_TIMEOUT_MS",
+        value_name = "MS",
+        value_parser = millis,
+        default_value = "5000"
+    )]
+    pub max_timeout: Duration,
+    /// Timeouts in a row after which a peer's circuit opens, and requests to it are only
+    /// sent as probes. Never opens when unset.
+    #[arg(long = "circuit-breaker-after", env = "CIRCUIT_BREAKER_AFTER")]
+    pub breaker_after: Option<u32>,
+    /// Milliseconds between probes of a peer whose circuit is open.
+    #[arg(
+        long = "circuit-probe-ms",
+        env = "CIRCUIT_PROBE_MS",
+        value_name = "MS",
+        value_parser = millis,
+        default_value = "1000"
+    )]
+    pub probe_interval: Duration,
+}
+
+impl RetryArgs {
+    /// The circuit breaker of a peer, if there's one.
+    pub fn breaker(&self) -> Option<CircuitBreaker> {
+        self.breaker_after
+            .map(|threshold| CircuitBreaker::new(threshold, self.probe_interval))
+    }
+}
+
+/// Parses a number of milliseconds, for options that take a duration.
+pub fn millis(s: &str) -> Result<Duration, ParseIntError> {
+    s.parse().map(Duration::from_millis)
+}
+
+/// Parses the options of a single-workload binary from its arguments, layered over those
+/// its config file sets for `workload`. See `parse_from`.
+pub fn parse<C: Parser>(workload: &str) -> Result<C, anyhow::Error> {
+    parse_from(std::env::args_os(), workload)
+}
+
+/// Parses options from `args`, layered over the config file given with `--config`, if any.
+/// The file sets options by their flag names, such as `gossip-interval-ms = 100`. Those at
+/// its top level apply to every workload, and those in the `[workload]` table override them.
+/// Tables of other workloads are ignored, so one file can tune several. A flag or an
+/// environment variable that is set takes precedence over the file.
+///
+/// Exits on invalid arguments or `--help`, like `Parser::parse`.
+pub fn parse_from<C: Parser>(
+    args: impl IntoIterator<Item = impl Into<OsString>>,
+    workload: &str,
+) -> Result<C, anyhow::Error> {
+    let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let command = C::command();
+    let matches = command.clone().get_matches_from(&args);
+    let file_args = file_args(&command, &matches, workload)?;
+    args.splice(1..1, file_args);
+    C::try_parse_from(&args).context("Invalid option in config file")
+}
+
+/// Like `parse`, for a binary that takes the workload as a subcommand. The subcommand names
+/// the table of the config file that applies.
+pub fn parse_subcommand<C: Parser>() -> Result<C, anyhow::Error> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let command = C::command();
+    let matches = command.clone().get_matches_from(&args);
+    let Some((workload, sub_matches)) = matches.subcommand() else {
+        return C::from_arg_matches(&matches).map_err(Into::into);
+    };
+    let subcommand = command
+        .find_subcommand(workload)
+        .context("Unknown subcommand")?;
+    let file_args = file_args(subcommand, sub_matches, workload)?;
+    let at = args
+        .iter()
+        .position(|arg| arg == workload)
+        .map_or(args.len(), |position| position + 1);
+    args.splice(at..at, file_args);
+    C::try_parse_from(&args).context("Invalid option in config file")
+}
+
+/// The options the config file in `matches` sets for `workload`, as flags, leaving out the
+/// ones set already.
+fn file_args(
+    command: &Command,
+    matches: &ArgMatches,
+    workload: &str,
+) -> Result<Vec<OsString>, anyhow::Error> {
+    let Some(path) = matches.try_get_one::<PathBuf>("config").ok().flatten() else {
+        return Ok(Vec::new());
+    };
+    let options = read_options(path, workload)?;
+
+    let mut args = Vec::new();
+    for (name, value) in options {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+            .with_context(|| format!("Unknown option {name} in {}", path.display()))?;
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        let value = match value {
+            toml::Value::String(value) => value,
+            toml::Value::Integer(value) => value.to_string(),
+            toml::Value::Float(value) => value.to_string(),
+            toml::Value::Boolean(value) if !arg.get_action().takes_values() => {
+                if value {
+                    args.push(OsString::from(format!("--{name}")));
+                }
+                continue;
+            }
+            toml::Value::Boolean(value) => value.to_string(),
+            _ => anyhow::bail!("Option {name} in {} isn't a single value", path.display()),
+        };
+        args.push(OsString::from(format!("--{name}={value}")));
+    }
+    Ok(args)
+}
+
+/// The options at the top level of the file at `path`, overridden by those in the table of
+/// `workload`.
+fn read_options(path: &Path, workload: &str) -> Result<toml::Table, anyhow::Error> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: toml::Table = contents
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let mut options = toml::Table::new();
+    let mut section = None;
+    for (name, value) in file {
+        match value {
+            toml::Value::Table(table) if name == workload => section = Some(table),
+            toml::Value::Table(_) => {}
+            value => {
+                options.insert(name, value);
+            }
+        }
+    }
+    options.extend(section.unwrap_or_default());
+    Ok(options)
+}
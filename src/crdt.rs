@@ -0,0 +1,582 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::HlcTimestamp;
+use crate::vector_clock::VectorClock;
+
+/// State that replicas converge on by merging each other's. Merging must be commutative,
+/// associative, and idempotent, so that replicas end up equal whatever order they see each
+/// other's state in, and however often.
+pub trait Merge {
+    /// Takes in the state of `other`, and returns whether that changed anything.
+    fn merge(&mut self, other: &Self) -> bool;
+}
+
+/// A conflict-free replicated data type: state that merges, starts out empty, travels in
+/// messages, and reads as a value. Workloads keep their state in one of these rather than
+/// merging it by hand.
+pub trait Crdt: Merge + Clone + Default + Serialize + DeserializeOwned {
+    type Value;
+
+    /// What a client reads of the state.
+    fn value(&self) -> Self::Value;
+
+    /// The merge of every state of `states`, e.g. of the deltas a peer hasn't seen yet.
+    fn join<'a>(states: impl IntoIterator<Item = &'a Self>) -> Self
+    where
+        Self: 'a,
+    {
+        let mut joined = Self::default();
+        for state in states {
+            joined.merge(state);
+        }
+        joined
+    }
+}
+
+/// A grow-only counter: a count per node, of which only the node itself adds to its own.
+/// Replicas converge by merging, whatever order they see each other's counts in, and
+/// however often.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter(BTreeMap<String, u64>);
+
+impl GCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The count of `node`, zero if it has none yet.
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    /// Adds `delta` to the count of `node`, which starts at zero.
+    pub fn increment(&mut self, node: &str, delta: u64) {
+        *self.0.entry(node.to_string()).or_insert(0) += delta;
+    }
+
+    /// Whether it has no counts, not even zeros.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// A counter with only the count of `node`, which merges into a replica like this
+    /// whole counter would, as far as `node` goes. Adds of a node thus travel as deltas
+    /// rather than as every node's count.
+    pub fn delta(&self, node: &str) -> GCounter {
+        GCounter(
+            self.0
+                .get_key_value(node)
+                .map(|(node, &count)| (node.clone(), count))
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /// Keeps only the counts of the nodes `keep` accepts.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.0.retain(|node, _| keep(node));
+    }
+
+    /// The nodes it has a count of, zero or not.
+    pub fn nodes(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0.iter().map(|(node, &count)| (node.as_str(), count))
+    }
+}
+
+impl Merge for GCounter {
+    /// Takes the highest count of every node, and returns whether any count grew.
+    fn merge(&mut self, other: &GCounter) -> bool {
+        let mut changed = false;
+        for (node, &count) in &other.0 {
+            let entry = self.0.entry(node.clone()).or_insert(0);
+            if count > *entry {
+                *entry = count;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl Crdt for GCounter {
+    type Value = u64;
+
+    /// The sum of every node's count.
+    fn value(&self) -> u64 {
+        self.0.values().sum()
+    }
+}
+
+/// A counter that also goes down: a grow-only counter of what each node added, and another
+/// of what it subtracted, which merge on their own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `delta` to the count of `node`, counting it as a decrement if it's negative.
+    pub fn add(&mut self, node: &str, delta: i64) {
+        if delta < 0 {
+            self.decrements.increment(node, delta.unsigned_abs());
+        } else {
+            self.increments.increment(node, delta as u64);
+        }
+    }
+
+    pub fn increments(&self) -> &GCounter {
+        &self.increments
+    }
+
+    pub fn decrements(&self) -> &GCounter {
+        &self.decrements
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.increments.is_empty() && self.decrements.is_empty()
+    }
+}
+
+impl Merge for PNCounter {
+    fn merge(&mut self, other: &PNCounter) -> bool {
+        let incremented = self.increments.merge(&other.increments);
+        let decremented = self.decrements.merge(&other.decrements);
+        incremented || decremented
+    }
+}
+
+impl Crdt for PNCounter {
+    type Value = i64;
+
+    /// What every node added, less what every node subtracted.
+    fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+}
+
+/// A grow-only set: elements are only ever added, so replicas converge on the union of
+/// what each of them saw.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GSet<T: Ord>(BTreeSet<T>);
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        Self(BTreeSet::new())
+    }
+}
+
+impl<T: Ord + Clone> GSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `element`, and returns whether it's new.
+    pub fn insert(&mut self, element: T) -> bool {
+        self.0.insert(element)
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.0.contains(element)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The elements, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.iter()
+    }
+}
+
+impl<T: Ord + Clone> Merge for GSet<T> {
+    /// Adds the elements of `other`, and returns whether any was new.
+    fn merge(&mut self, other: &GSet<T>) -> bool {
+        let before = self.0.len();
+        self.0.extend(other.0.iter().cloned());
+        self.0.len() > before
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for GSet<T> {
+    type Value = Vec<T>;
+
+    /// The elements, in order.
+    fn value(&self) -> Vec<T> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+/// Tells apart the adds of an element: the node that added it, and a number that node never
+/// used for another add.
+pub type Tag = (String, u64);
+
+/// An observed-remove set, in which an add wins over a concurrent remove of the element.
+/// Every add gets a unique tag, and a remove only removes the tags of the element that the
+/// removing replica saw, so that an add a replica didn't see yet survives the remove once
+/// the replicas merge. Removed tags are kept, so that merging an older replica doesn't
+/// bring back what was removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+pub struct ORSet<T: Ord> {
+    /// The tags of each element's adds that weren't removed.
+    #[serde(with = "entries")]
+    adds: BTreeMap<T, BTreeSet<Tag>>,
+    removed: BTreeSet<Tag>,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        Self {
+            adds: BTreeMap::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `element` under `tag`, which no other add may have.
+    pub fn insert(&mut self, element: T, tag: Tag) {
+        if !self.removed.contains(&tag) {
+            self.adds.entry(element).or_default().insert(tag);
+        }
+    }
+
+    /// Removes `element` as far as this replica saw it added, and returns whether it was
+    /// there.
+    pub fn remove(&mut self, element: &T) -> bool {
+        match self.adds.remove(element) {
+            Some(tags) => {
+                self.removed.extend(tags);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.adds.contains_key(element)
+    }
+
+    pub fn len(&self) -> usize {
+        self.adds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adds.is_empty()
+    }
+
+    /// The elements, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.adds.keys()
+    }
+
+    /// Every tag this replica knows of, removed or not.
+    pub fn tags(&self) -> impl Iterator<Item = &Tag> {
+        self.adds.values().flatten().chain(&self.removed)
+    }
+}
+
+impl<T: Ord + Clone> Merge for ORSet<T> {
+    /// Takes in the adds and removes of `other`, and returns whether that changed anything.
+    fn merge(&mut self, other: &ORSet<T>) -> bool {
+        let mut changed = false;
+        for tag in &other.removed {
+            changed |= self.removed.insert(tag.clone());
+        }
+        for tags in self.adds.values_mut() {
+            tags.retain(|tag| !other.removed.contains(tag));
+        }
+        for (element, tags) in &other.adds {
+            for tag in tags {
+                if !self.removed.contains(tag) {
+                    changed |= self
+                        .adds
+                        .entry(element.clone())
+                        .or_default()
+                        .insert(tag.clone());
+                }
+            }
+        }
+        self.adds.retain(|_, tags| !tags.is_empty());
+        changed
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for ORSet<T> {
+    type Value = Vec<T>;
+
+    /// The elements added and not removed since, in order.
+    fn value(&self) -> Vec<T> {
+        self.adds.keys().cloned().collect()
+    }
+}
+
+/// A last-writer-wins register: a value with the hybrid logical clock timestamp of its
+/// write, and the node that wrote it, which breaks ties between writes of the same
+/// timestamp. Replicas converge on the latest write, and concurrent writes but one are lost.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    value: T,
+    timestamp: HlcTimestamp,
+    node: String,
+}
+
+impl<T: Clone> LwwRegister<T> {
+    pub fn new(value: T, timestamp: HlcTimestamp, node: &str) -> Self {
+        Self {
+            value,
+            timestamp,
+            node: node.to_string(),
+        }
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn timestamp(&self) -> HlcTimestamp {
+        self.timestamp
+    }
+}
+
+impl<T: Clone + Ord> Merge for LwwRegister<T> {
+    /// Takes the write of `other` if it's later, and returns whether it was. Writes of a
+    /// node at the same timestamp, such as those of a node that restarted with its clock
+    /// behind, are ordered by their values, so that every replica keeps the same one.
+    fn merge(&mut self, other: &LwwRegister<T>) -> bool {
+        let later = (other.timestamp, &other.node, &other.value);
+        if later <= (self.timestamp, &self.node, &self.value) {
+            return false;
+        }
+        *self = other.clone();
+        true
+    }
+}
+
+/// A map of last-writer-wins registers, one per key, each merging on its own. A key only
+/// ever gets a later value, never removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LwwMap<T> {
+    registers: BTreeMap<String, LwwRegister<T>>,
+}
+
+impl<T> Default for LwwMap<T> {
+    fn default() -> Self {
+        Self {
+            registers: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Clone + Ord> LwwMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `value` to `key`, unless the key has a later write already.
+    pub fn insert(&mut self, key: &str, value: T, timestamp: HlcTimestamp, node: &str) {
+        let register = LwwRegister::new(value, timestamp, node);
+        match self.registers.get_mut(key) {
+            Some(ours) => {
+                ours.merge(&register);
+            }
+            None => {
+                self.registers.insert(key.to_string(), register);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.registers.get(key).map(LwwRegister::value)
+    }
+
+    /// The register of `key`, with the timestamp of its write.
+    pub fn register(&self, key: &str) -> Option<&LwwRegister<T>> {
+        self.registers.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.registers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty()
+    }
+
+    /// The keys and their registers, in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &LwwRegister<T>)> {
+        self.registers
+            .iter()
+            .map(|(key, register)| (key.as_str(), register))
+    }
+}
+
+impl<T: Clone + Ord> Merge for LwwMap<T> {
+    /// Takes the later write of every key, and returns whether any was of `other`.
+    fn merge(&mut self, other: &LwwMap<T>) -> bool {
+        let mut changed = false;
+        for (key, register) in &other.registers {
+            match self.registers.get_mut(key) {
+                Some(ours) => changed |= ours.merge(register),
+                None => {
+                    self.registers.insert(key.clone(), register.clone());
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl<T: Clone + Ord + Serialize + DeserializeOwned> Crdt for LwwMap<T> {
+    type Value = BTreeMap<String, T>;
+
+    /// The latest value of every key.
+    fn value(&self) -> BTreeMap<String, T> {
+        self.registers
+            .iter()
+            .map(|(key, register)| (key.clone(), register.value().clone()))
+            .collect()
+    }
+}
+
+/// A multi-value register: every write carries the vector clock of the writes it saw, and
+/// replaces those. Writes that didn't see each other are concurrent, and all of them stay
+/// until a write that saw them replaces them, so that no concurrent write is lost.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MvRegister<T> {
+    /// The concurrent values, with the clocks of their writes.
+    entries: Vec<(T, VectorClock)>,
+}
+
+impl<T> Default for MvRegister<T> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// Registers are equal if they have the same writes, in whatever order they merged them.
+impl<T: PartialEq> PartialEq for MvRegister<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries.len() == other.entries.len()
+            && self
+                .entries
+                .iter()
+                .all(|entry| other.entries.contains(entry))
+    }
+}
+
+impl<T: Eq> Eq for MvRegister<T> {}
+
+impl<T: Clone + PartialEq> MvRegister<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The clock of the writes this register saw, which a write that replaces all its
+    /// values must count one more event of its node than.
+    pub fn clock(&self) -> VectorClock {
+        let mut clock = VectorClock::new();
+        for (_, entry) in &self.entries {
+            clock.merge(entry);
+        }
+        clock
+    }
+
+    /// Replaces every value with `value`, written by `node`.
+    pub fn write(&mut self, value: T, node: &str) {
+        let mut clock = self.clock();
+        clock.increment(node);
+        self.entries = vec![(value, clock)];
+    }
+
+    /// The values of the concurrent writes, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter().map(|(value, _)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T: Clone + PartialEq> Merge for MvRegister<T> {
+    /// Keeps the writes of either register that no write of the other saw, and returns
+    /// whether that changed anything.
+    fn merge(&mut self, other: &MvRegister<T>) -> bool {
+        let mut changed = false;
+        for (value, clock) in &other.entries {
+            if self.entries.iter().any(|(_, ours)| clock <= ours) {
+                continue;
+            }
+            self.entries
+                .retain(|(_, ours)| ours.partial_cmp(clock) != Some(Ordering::Less));
+            self.entries.push((value.clone(), clock.clone()));
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl<T: Clone + PartialEq + Serialize + DeserializeOwned> Crdt for MvRegister<T> {
+    type Value = Vec<T>;
+
+    /// The values of the concurrent writes, in no particular order.
+    fn value(&self) -> Vec<T> {
+        self.values().cloned().collect()
+    }
+}
+
+/// Writes a map as a list of `[key, value]` pairs, as JSON object keys can only be
+/// strings, and a map with keys of another type can't be read back from one inside a
+/// message body.
+mod entries {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K: Serialize, V: Serialize, S: Serializer>(
+        map: &BTreeMap<K, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        K: Ord + Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let entries = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// Bits per element of a Bloom filter, which gives about 1% false positives.
+const BITS_PER_ELEMENT: usize = 10;
+const HASHES: u64 = 7;
+
+/// Mixes the bits of `x` thoroughly (SplitMix64). Unlike the standard library's hashers it's
+/// the same on every node, which digests compared across nodes need.
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Hashes `bytes` the same way on every node, e.g. to identify a JSON value.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes
+        .chunks(8)
+        .fold(mix(bytes.len() as u64), |hash, chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            mix(hash ^ u64::from_le_bytes(word))
+        })
+}
+
+/// A summary of a set of numbers that doesn't depend on their order. Two sets with equal
+/// digests are the same set, barring a hash collision.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetDigest {
+    pub count: u64,
+    pub hash: u64,
+}
+
+impl SetDigest {
+    pub fn of<'a>(elements: impl IntoIterator<Item = &'a u64>) -> Self {
+        elements
+            .into_iter()
+            .fold(Self::default(), |digest, &element| Self {
+                count: digest.count + 1,
+                hash: digest.hash.wrapping_add(mix(element)),
+            })
+    }
+}
+
+/// A Bloom filter of numbers, which tells which elements of one set are missing from
+/// another without sending the whole set. It may claim to contain an element it doesn't,
+/// but never the other way around. The seed changes which elements are affected, so
+/// retrying with another seed eventually finds every missing element.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    seed: u64,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub fn new<'a>(elements: impl ExactSizeIterator<Item = &'a u64>, seed: u64) -> Self {
+        let words = (elements.len() * BITS_PER_ELEMENT).div_ceil(64).max(1);
+        let mut filter = Self {
+            seed,
+            bits: vec![0; words],
+        };
+        for &element in elements {
+            for bit in filter.bit_indexes(element) {
+                filter.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    pub fn contains(&self, element: u64) -> bool {
+        self.bit_indexes(element).all(|bit| {
+            self.bits
+                .get(bit / 64)
+                .is_some_and(|word| word & (1 << (bit % 64)) != 0)
+        })
+    }
+
+    fn bit_indexes(&self, element: u64) -> impl Iterator<Item = usize> {
+        let size = self.bits.len() as u64 * 64;
+        let hash = mix(element ^ self.seed);
+        let step = mix(hash) | 1;
+        (0..HASHES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % size) as usize)
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{TryRecvError, TrySendError};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Which queue an event waits in. High priority events are always received first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+struct State<T> {
+    high: VecDeque<T>,
+    low: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+}
+
+impl<T> State<T> {
+    fn queue(&mut self, priority: Priority) -> &mut VecDeque<T> {
+        match priority {
+            Priority::High => &mut self.high,
+            Priority::Low => &mut self.low,
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.high.pop_front().or_else(|| self.low.pop_front())
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+/// Creates a channel with two bounded queues, each holding up to `capacity` events.
+/// Events of the same priority are received in the order they were sent.
+pub fn channel<T>(capacity: usize) -> (EventSender<T>, EventReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            high: VecDeque::new(),
+            low: VecDeque::new(),
+            senders: 1,
+            receiver: true,
+        }),
+        capacity,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+    (
+        EventSender {
+            shared: shared.clone(),
+        },
+        EventReceiver { shared },
+    )
+}
+
+pub struct EventSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> EventSender<T> {
+    /// Waits while the queue of `priority` is full. Returns the event if the receiver is gone.
+    pub fn send(&self, event: T, priority: Priority) -> Result<(), T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if !state.receiver {
+                return Err(event);
+            }
+            if state.queue(priority).len() < self.shared.capacity {
+                state.queue(priority).push_back(event);
+                self.shared.not_empty.notify_one();
+                return Ok(());
+            }
+            state = self.shared.not_full.wait(state).unwrap();
+        }
+    }
+
+    pub fn try_send(&self, event: T, priority: Priority) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.receiver {
+            return Err(TrySendError::Disconnected(event));
+        }
+        if state.queue(priority).len() >= self.shared.capacity {
+            return Err(TrySendError::Full(event));
+        }
+        state.queue(priority).push_back(event);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for EventSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+pub struct EventReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> EventReceiver<T> {
+    /// Waits for the next event. Returns `None` once every sender is gone and the queues are
+    /// empty.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(event) = state.pop() {
+                self.shared.not_full.notify_all();
+                return Some(event);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self.shared.not_empty.wait(state).unwrap();
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.pop() {
+            Some(event) => {
+                self.shared.not_full.notify_all();
+                Ok(event)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for EventReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver = false;
+        self.shared.not_full.notify_all();
+    }
+}
@@ -0,0 +1,1159 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::error::{ErrorBody, ErrorCode};
+use crate::kv::{KvBody, KvService};
+use crate::message::{Message, RawMessage};
+use crate::protocol::{Capabilities, PROTOCOL_VERSION};
+use crate::rng::Rng;
+
+/// The client that sends `init`, as Maelstrom's `c0` does.
+const INIT_CLIENT: &str = "c0";
+
+/// How long a node gets to answer `init`, or to connect to its socket.
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the harness exchanges messages with each node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Link {
+    /// The node's stdin and stdout, as with Maelstrom.
+    #[default]
+    Pipes,
+    /// A Unix domain socket per node, which the node connects to as `NODE_UNIX` tells it.
+    Unix,
+}
+
+/// Where the harness writes a node's messages to.
+enum Input {
+    Pipe(ChildStdin),
+    Socket(UnixStream),
+}
+
+impl Input {
+    /// Ends the node's input, so that it exits.
+    fn close(self) {
+        match self {
+            Input::Pipe(stdin) => drop(stdin),
+            // The thread reading from the node holds the socket open too.
+            Input::Socket(stream) => stream.shutdown(Shutdown::Write).unwrap_or_default(),
+        }
+    }
+}
+
+impl Write for Input {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Input::Pipe(stdin) => stdin.write(buf),
+            Input::Socket(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Input::Pipe(stdin) => stdin.flush(),
+            Input::Socket(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A node process, and where its messages are written to.
+struct Process {
+    child: Child,
+    input: Input,
+}
+
+/// Nodes of a workload binary, each run as a process of its own, with the lines they write
+/// routed to each other like Maelstrom's network does. A message is taken to be from the node
+/// whose pipe or socket it came over, whatever its `src` says. Messages to `seq-kv`, `lin-kv`, and
+/// `lww-kv` are answered by an in-memory store, and messages to clients are handed to the
+/// caller of `next_reply`.
+pub struct Cluster {
+    command: Vec<OsString>,
+    log_dir: Option<PathBuf>,
+    link: Link,
+    /// Where the nodes' sockets are, with `Link::Unix`.
+    socket_dir: PathBuf,
+    node_ids: Vec<String>,
+    processes: BTreeMap<String, Process>,
+    lines: Sender<(String, String)>,
+    receiver: Receiver<(String, String)>,
+    /// The group of each node while the network is partitioned.
+    groups: HashMap<String, usize>,
+    /// The keys of each service, by name.
+    services: HashMap<&'static str, BTreeMap<String, Value>>,
+    /// The extensions offered in `init`, if any.
+    capabilities: Option<Capabilities>,
+    /// Replies to clients that arrived while the cluster waited for something else.
+    inbox: VecDeque<Message<Value>>,
+    next_msg_id: u64,
+    delivered: u64,
+    dropped: u64,
+}
+
+impl Cluster {
+    /// Starts `count` nodes, `n0` and up, by running `command`. A node's diagnostics go to
+    /// `<log_dir>/<node>.log`, or to the harness's stderr without a directory.
+    pub fn start(
+        command: Vec<OsString>,
+        count: usize,
+        log_dir: Option<PathBuf>,
+        link: Link,
+    ) -> Result<Self, anyhow::Error> {
+        anyhow::ensure!(!command.is_empty(), "No node binary to run");
+        if let Some(dir) = &log_dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let socket_dir = std::env::temp_dir().join(format!(
+            "distributed-system-harness-{}-{}",
+            std::process::id(),
+            Rng::from_entropy().next_u64()
+        ));
+        if link == Link::Unix {
+            std::fs::create_dir_all(&socket_dir)
+                .with_context(|| format!("Failed to create {}", socket_dir.display()))?;
+        }
+        let (lines, receiver) = mpsc::channel();
+        let mut cluster = Self {
+            command,
+            log_dir,
+            link,
+            socket_dir,
+            node_ids: (0..count).map(|i| format!("n{i}")).collect(),
+            processes: BTreeMap::new(),
+            lines,
+            receiver,
+            groups: HashMap::new(),
+            services: HashMap::new(),
+            capabilities: None,
+            inbox: VecDeque::new(),
+            next_msg_id: 0,
+            delivered: 0,
+            dropped: 0,
+        };
+        for id in cluster.node_ids.clone() {
+            cluster.spawn(&id)?;
+        }
+        Ok(cluster)
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// The nodes that are running.
+    pub fn alive(&self) -> Vec<String> {
+        self.processes.keys().cloned().collect()
+    }
+
+    /// Messages delivered from node to node.
+    pub fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    /// Messages between nodes lost to partitions or to nodes that were down.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn spawn(&mut self, id: &str) -> Result<(), anyhow::Error> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .context("No node binary to run")?;
+        let stderr = match &self.log_dir {
+            Some(dir) => {
+                let path = dir.join(format!("{id}.log"));
+                let file = File::options()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                Stdio::from(file)
+            }
+            None => Stdio::inherit(),
+        };
+        let mut command = Command::new(program);
+        command.args(args).stderr(stderr);
+        let (child, input, output): (_, _, Box<dyn Read + Send>) = match self.link {
+            Link::Pipes => {
+                let mut child = command
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("Failed to start {}", program.to_string_lossy()))?;
+                let stdin = child.stdin.take().context("Child has no stdin")?;
+                let stdout = child.stdout.take().context("Child has no stdout")?;
+                (child, Input::Pipe(stdin), Box::new(stdout))
+            }
+            Link::Unix => {
+                let path = self.socket_dir.join(format!("{id}.sock"));
+                // A socket left by a node that was killed can't be bound to again.
+                std::fs::remove_file(&path).ok();
+                let listener = UnixListener::bind(&path)
+                    .with_context(|| format!("Failed to listen on {}", path.display()))?;
+                let mut child = command
+                    .env("NODE_UNIX", &path)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .spawn()
+                    .with_context(|| format!("Failed to start {}", program.to_string_lossy()))?;
+                let stream = match accept(&listener, &mut child) {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        child.kill().ok();
+                        child.wait().ok();
+                        return Err(error.context(format!("{id} didn't connect to its socket")));
+                    }
+                };
+                let reader = stream.try_clone()?;
+                (child, Input::Socket(stream), Box::new(reader))
+            }
+        };
+
+        let lines = self.lines.clone();
+        let node = id.to_string();
+        std::thread::spawn(move || {
+            for line in BufReader::new(output).lines() {
+                let Ok(line) = line else { break };
+                if lines.send((node.clone(), line)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.processes
+            .insert(id.to_string(), Process { child, input });
+        Ok(())
+    }
+
+    /// Makes `init` negotiate the protocol, offering `capabilities`. Without this, `init` is
+    /// Maelstrom's.
+    pub fn offer(&mut self, capabilities: Capabilities) {
+        self.capabilities = Some(capabilities);
+    }
+
+    /// Sends `init` to every node, and waits until all of them answered.
+    pub fn init(&mut self) -> Result<(), anyhow::Error> {
+        for id in self.node_ids.clone() {
+            self.init_node(&id)?;
+        }
+        Ok(())
+    }
+
+    fn init_node(&mut self, id: &str) -> Result<(), anyhow::Error> {
+        let mut body = json!({"type": "init", "node_id": id, "node_ids": self.node_ids});
+        if let Some(capabilities) = &self.capabilities {
+            body["protocol_version"] = json!(PROTOCOL_VERSION);
+            body["capabilities"] = json!(capabilities);
+        }
+        let reply = self
+            .request(INIT_CLIENT, id, body, Instant::now() + INIT_TIMEOUT)?
+            .with_context(|| format!("{id} didn't answer init"))?;
+        anyhow::ensure!(
+            reply.body["type"] == "init_ok",
+            "{id} failed to init: {}",
+            reply.body
+        );
+        Ok(())
+    }
+
+    /// Sends a request from `client` to `node`, and routes messages until its reply arrives,
+    /// or `None` once `deadline` passes.
+    pub fn request(
+        &mut self,
+        client: &str,
+        node: &str,
+        body: Value,
+        deadline: Instant,
+    ) -> Result<Option<Message<Value>>, anyhow::Error> {
+        let msg_id = self.send(client, node, body)?;
+        self.route_until(deadline, |message| {
+            message.dest == client && message.body["in_reply_to"].as_u64() == Some(msg_id)
+        })
+    }
+
+    /// Sends a request from `client` to `node`, filling in its `msg_id`, and returns the
+    /// `msg_id`. A request to a node that's down is lost.
+    pub fn send(
+        &mut self,
+        client: &str,
+        node: &str,
+        mut body: Value,
+    ) -> Result<u64, anyhow::Error> {
+        self.next_msg_id += 1;
+        body["msg_id"] = json!(self.next_msg_id);
+        let message = Message {
+            src: client.to_string(),
+            dest: node.to_string(),
+            body,
+        };
+        let line = serde_json::to_string(&message)?;
+        self.write(node, &line);
+        Ok(self.next_msg_id)
+    }
+
+    /// Writes `line` to `node`, unless it's down. A node that exits while being written to
+    /// loses the line, as it would if it crashed a moment earlier.
+    fn write(&mut self, node: &str, line: &str) -> bool {
+        match self.processes.get_mut(node) {
+            Some(process) => writeln!(process.input, "{line}")
+                .and_then(|_| process.input.flush())
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    /// Routes messages until one to a client arrives, and returns it, or `None` once
+    /// `deadline` passes.
+    pub fn next_reply(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<Option<Message<Value>>, anyhow::Error> {
+        if let Some(reply) = self.inbox.pop_front() {
+            return Ok(Some(reply));
+        }
+        self.route_until(deadline, |_| true)
+    }
+
+    /// Routes messages until a message to a client that `wanted` accepts arrives, keeping
+    /// the others for `next_reply`.
+    fn route_until(
+        &mut self,
+        deadline: Instant,
+        mut wanted: impl FnMut(&Message<Value>) -> bool,
+    ) -> Result<Option<Message<Value>>, anyhow::Error> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (src, line) = match self.receiver.recv_timeout(timeout) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => unreachable!("The cluster keeps a sender"),
+            };
+            let Ok(message) = RawMessage::parse_raw(&line) else {
+                log::warn!("{src} wrote a line that isn't a message: {line}");
+                continue;
+            };
+            if message.src != src {
+                log::warn!("{src} sent a message as {}, dropping it", message.src);
+                continue;
+            }
+
+            if self.node_ids.contains(&message.dest) {
+                if self.connected(&message.src, &message.dest) && self.write(&message.dest, &line) {
+                    self.delivered += 1;
+                } else {
+                    self.dropped += 1;
+                }
+            } else if let Some(service) = KvService::from_name(&message.dest) {
+                self.serve(service, &message)?;
+            } else {
+                let message: Message<Value> = message.decode()?;
+                if wanted(&message) {
+                    return Ok(Some(message));
+                }
+                self.inbox.push_back(message);
+            }
+        }
+    }
+
+    /// Answers a request to a key-value service like Maelstrom's would, linearizably.
+    fn serve(&mut self, service: KvService, request: &RawMessage) -> Result<(), anyhow::Error> {
+        let Ok(Message { body, .. }) = request.decode::<KvBody>() else {
+            log::warn!("{} sent {} an unknown request", request.src, service.name());
+            return Ok(());
+        };
+        let store = self.services.entry(service.name()).or_default();
+        let missing = |msg_id, key: &Value| {
+            KvBody::Error(ErrorBody::new(
+                msg_id,
+                ErrorCode::KeyDoesNotExist,
+                format!("Key {key} does not exist"),
+            ))
+        };
+        let reply = match body {
+            KvBody::Read { msg_id, key } => match store.get(&key.to_string()) {
+                Some(value) => KvBody::ReadOk {
+                    in_reply_to: msg_id,
+                    value: value.clone(),
+                },
+                None => missing(msg_id, &key),
+            },
+            KvBody::Write { msg_id, key, value } => {
+                store.insert(key.to_string(), value);
+                KvBody::WriteOk {
+                    in_reply_to: msg_id,
+                }
+            }
+            KvBody::Cas {
+                msg_id,
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match store.get(&key.to_string()) {
+                Some(value) if *value == from => {
+                    store.insert(key.to_string(), to);
+                    KvBody::CasOk {
+                        in_reply_to: msg_id,
+                    }
+                }
+                Some(value) => KvBody::Error(ErrorBody::new(
+                    msg_id,
+                    ErrorCode::PreconditionFailed,
+                    format!("Expected {from}, but had {value}"),
+                )),
+                None if create_if_not_exists => {
+                    store.insert(key.to_string(), to);
+                    KvBody::CasOk {
+                        in_reply_to: msg_id,
+                    }
+                }
+                None => missing(msg_id, &key),
+            },
+            KvBody::ReadOk { .. } | KvBody::WriteOk { .. } | KvBody::CasOk { .. } => return Ok(()),
+            KvBody::Error(_) => return Ok(()),
+        };
+        let line = serde_json::to_string(&request.reply(reply))?;
+        self.write(&request.src, &line);
+        Ok(())
+    }
+
+    fn connected(&self, src: &str, dest: &str) -> bool {
+        match (self.groups.get(src), self.groups.get(dest)) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+
+    /// Cuts the network into `groups`, so that nodes only reach those in their own group.
+    pub fn partition(&mut self, groups: &[Vec<String>]) {
+        self.groups = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| group.iter().map(move |node| (node.clone(), i)))
+            .collect();
+    }
+
+    pub fn heal(&mut self) {
+        self.groups.clear();
+    }
+
+    /// Kills `node`, which loses whatever it only kept in memory.
+    pub fn kill(&mut self, node: &str) -> Result<(), anyhow::Error> {
+        if let Some(mut process) = self.processes.remove(node) {
+            process.child.kill().ok();
+            process
+                .child
+                .wait()
+                .context("Failed to wait for a killed node")?;
+        }
+        Ok(())
+    }
+
+    /// Starts `node` again after `kill`, and inits it like the first time.
+    pub fn restart(&mut self, node: &str) -> Result<(), anyhow::Error> {
+        if self.processes.contains_key(node) {
+            return Ok(());
+        }
+        self.spawn(node)?;
+        self.init_node(node)
+    }
+
+    /// Closes every node's input, and waits for the nodes to exit. Returns the nodes that
+    /// failed.
+    pub fn stop(mut self) -> Result<Vec<String>, anyhow::Error> {
+        let mut failed = Vec::new();
+        for (id, process) in std::mem::take(&mut self.processes) {
+            let Process { mut child, input } = process;
+            input.close();
+            let status = child
+                .wait()
+                .with_context(|| format!("Failed to wait for {id}"))?;
+            if !status.success() {
+                failed.push(id);
+            }
+        }
+        Ok(failed)
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for process in self.processes.values_mut() {
+            process.child.kill().ok();
+            process.child.wait().ok();
+        }
+        if self.link == Link::Unix {
+            std::fs::remove_dir_all(&self.socket_dir).ok();
+        }
+    }
+}
+
+/// Waits for `child` to connect to `listener`, for up to `INIT_TIMEOUT`.
+fn accept(listener: &UnixListener, child: &mut Child) -> Result<UnixStream, anyhow::Error> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + INIT_TIMEOUT;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(error) => return Err(error.into()),
+        }
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("It exited with {status}");
+        }
+        anyhow::ensure!(Instant::now() < deadline, "It took too long");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// The workloads the harness can generate requests for and check, by their Maelstrom names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    GCounter,
+    PnCounter,
+    GSet,
+    Kafka,
+    TxnRwRegister,
+}
+
+impl Workload {
+    pub fn name(self) -> &'static str {
+        match self {
+            Workload::Echo => "echo",
+            Workload::UniqueIds => "unique-ids",
+            Workload::Broadcast => "broadcast",
+            Workload::GCounter => "g-counter",
+            Workload::PnCounter => "pn-counter",
+            Workload::GSet => "g-set",
+            Workload::Kafka => "kafka",
+            Workload::TxnRwRegister => "txn-rw-register",
+        }
+    }
+}
+
+impl FromStr for Workload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "echo" => Ok(Workload::Echo),
+            "unique-ids" => Ok(Workload::UniqueIds),
+            "broadcast" => Ok(Workload::Broadcast),
+            "g-counter" => Ok(Workload::GCounter),
+            "pn-counter" => Ok(Workload::PnCounter),
+            "g-set" => Ok(Workload::GSet),
+            "kafka" => Ok(Workload::Kafka),
+            "txn-rw-register" => Ok(Workload::TxnRwRegister),
+            other => anyhow::bail!("Unknown workload: {other}"),
+        }
+    }
+}
+
+/// How often a workload's requests of each type are sent, relative to each other.
+fn default_mix(workload: Workload) -> &'static [(&'static str, u32)] {
+    match workload {
+        Workload::Echo => &[("echo", 1)],
+        Workload::UniqueIds => &[("generate", 1)],
+        Workload::Broadcast => &[("broadcast", 1), ("read", 1)],
+        Workload::GCounter | Workload::PnCounter | Workload::GSet => &[("add", 1), ("read", 1)],
+        Workload::Kafka => &[
+            ("send", 5),
+            ("poll", 3),
+            ("commit_offsets", 1),
+            ("list_committed_offsets", 1),
+        ],
+        Workload::TxnRwRegister => &[("txn", 1)],
+    }
+}
+
+/// Weights of request types, given as a comma separated list of `type=weight`, e.g.
+/// `send=8,poll=2`. Types left out aren't sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mix {
+    weights: Vec<(String, u32)>,
+}
+
+impl FromStr for Mix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s
+            .split(',')
+            .map(|pair| {
+                let (kind, weight) = pair
+                    .split_once('=')
+                    .with_context(|| format!("Expected type=weight, got {pair}"))?;
+                let weight = weight
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid weight for {kind}: {weight}"))?;
+                Ok((kind.trim().to_string(), weight))
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        anyhow::ensure!(
+            weights.iter().any(|&(_, weight)| weight > 0),
+            "At least one weight must be above 0"
+        );
+        Ok(Self { weights })
+    }
+}
+
+/// How the keys of kafka and txn requests are picked.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyDistribution {
+    /// Every key as likely as any other.
+    #[default]
+    Uniform,
+    /// The `i`th key in proportion to `1 / i^exponent`, so that a few keys are hot.
+    Zipfian { exponent: f64 },
+}
+
+impl FromStr for KeyDistribution {
+    type Err = anyhow::Error;
+
+    /// Parses `uniform`, `zipfian`, or `zipfian:<exponent>`. The exponent defaults to 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "uniform" => Ok(KeyDistribution::Uniform),
+            None if s == "zipfian" => Ok(KeyDistribution::Zipfian { exponent: 1.0 }),
+            Some(("zipfian", exponent)) => {
+                let exponent: f64 = exponent
+                    .parse()
+                    .with_context(|| format!("Invalid zipfian exponent: {exponent}"))?;
+                anyhow::ensure!(
+                    exponent >= 0.0 && exponent.is_finite(),
+                    "The zipfian exponent must be a number of at least 0"
+                );
+                Ok(KeyDistribution::Zipfian { exponent })
+            }
+            _ => anyhow::bail!("Unknown key distribution: {s}"),
+        }
+    }
+}
+
+/// What requests a `Generator` makes up.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    /// The types of requests and their weights, or the workload's usual ones.
+    pub mix: Option<Mix>,
+    /// Keys requests of the kafka and txn workloads pick from.
+    pub keys: usize,
+    pub key_distribution: KeyDistribution,
+    /// Bytes in each echoed or broadcast value, padded out from a unique number. At 0,
+    /// broadcast values are plain integers.
+    pub value_size: usize,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            mix: None,
+            keys: 5,
+            key_distribution: KeyDistribution::Uniform,
+            value_size: 0,
+        }
+    }
+}
+
+/// Makes up client requests for a workload.
+#[derive(Debug)]
+pub struct Generator {
+    workload: Workload,
+    rng: Rng,
+    mix: Vec<(String, u32)>,
+    keys: usize,
+    /// The cumulative probability of each key, up to and including it.
+    key_cdf: Vec<f64>,
+    value_size: usize,
+    /// The next value to broadcast, send, or write, so that every value is unique.
+    next_value: u64,
+    /// The offset of each kafka key to poll from next, past what was polled already.
+    offsets: BTreeMap<String, u64>,
+}
+
+impl Generator {
+    pub fn new(workload: Workload, rng: Rng) -> Self {
+        Self::with_config(workload, GeneratorConfig::default(), rng)
+            .expect("The default config is valid")
+    }
+
+    /// Fails if the mix has types of requests the workload doesn't have, or there are no keys.
+    pub fn with_config(
+        workload: Workload,
+        config: GeneratorConfig,
+        rng: Rng,
+    ) -> Result<Self, anyhow::Error> {
+        anyhow::ensure!(config.keys > 0, "There must be at least one key");
+        let known = default_mix(workload);
+        let mix = match config.mix {
+            Some(Mix { weights }) => {
+                for (kind, _) in &weights {
+                    anyhow::ensure!(
+                        known.iter().any(|&(known, _)| known == kind),
+                        "{} has no {kind} requests",
+                        workload.name()
+                    );
+                }
+                weights
+            }
+            None => known
+                .iter()
+                .map(|&(kind, weight)| (kind.to_string(), weight))
+                .collect(),
+        };
+        let weights: Vec<f64> = (0..config.keys)
+            .map(|i| match config.key_distribution {
+                KeyDistribution::Uniform => 1.0,
+                KeyDistribution::Zipfian { exponent } => 1.0 / ((i + 1) as f64).powf(exponent),
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let key_cdf = weights
+            .iter()
+            .scan(0.0, |sum, weight| {
+                *sum += weight / total;
+                Some(*sum)
+            })
+            .collect();
+        Ok(Self {
+            workload,
+            rng,
+            mix,
+            keys: config.keys,
+            key_cdf,
+            value_size: config.value_size,
+            next_value: 0,
+            offsets: BTreeMap::new(),
+        })
+    }
+
+    fn value(&mut self) -> u64 {
+        self.next_value += 1;
+        self.next_value
+    }
+
+    /// A unique value, padded to `value_size` bytes if that's longer.
+    fn padded(&mut self, prefix: &str) -> String {
+        let value = format!("{prefix}{}", self.value());
+        format!("{value:.<width$}", width = self.value_size)
+    }
+
+    fn key_index(&mut self) -> usize {
+        let point = (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        self.key_cdf
+            .partition_point(|&cumulative| cumulative <= point)
+            .min(self.keys - 1)
+    }
+
+    fn key(&mut self) -> String {
+        format!("k{}", self.key_index())
+    }
+
+    fn all_keys(&self) -> impl Iterator<Item = String> {
+        (0..self.keys).map(|key| format!("k{key}"))
+    }
+
+    fn kind(&mut self) -> String {
+        let total: u32 = self.mix.iter().map(|&(_, weight)| weight).sum();
+        let mut point = self.rng.below(total as usize) as u32;
+        for (kind, weight) in &self.mix {
+            if point < *weight {
+                return kind.clone();
+            }
+            point -= weight;
+        }
+        unreachable!("The point is below the total weight")
+    }
+
+    /// The body of the next request, without its `msg_id`.
+    pub fn next_request(&mut self) -> Value {
+        match self.kind().as_str() {
+            "echo" => json!({"type": "echo", "echo": self.padded("Please echo ")}),
+            "generate" => json!({"type": "generate"}),
+            "broadcast" if self.value_size > 0 => {
+                json!({"type": "broadcast", "message": self.padded("")})
+            }
+            "broadcast" => json!({"type": "broadcast", "message": self.value()}),
+            "add" if self.workload == Workload::PnCounter => {
+                json!({"type": "add", "delta": self.rng.below(11) as i64 - 5})
+            }
+            "add" if self.workload == Workload::GSet => {
+                json!({"type": "add", "element": self.value()})
+            }
+            "add" => json!({"type": "add", "delta": self.rng.below(5)}),
+            "read" => json!({"type": "read"}),
+            "send" => json!({"type": "send", "key": self.key(), "msg": self.value()}),
+            "poll" => json!({"type": "poll", "offsets": self.poll_offsets()}),
+            "commit_offsets" => json!({"type": "commit_offsets", "offsets": self.offsets}),
+            "list_committed_offsets" => {
+                let keys: Vec<String> = self.all_keys().collect();
+                json!({"type": "list_committed_offsets", "keys": keys})
+            }
+            "txn" => {
+                let ops: Vec<Value> = (0..1 + self.rng.below(4))
+                    .map(|_| {
+                        let key = self.key_index();
+                        if self.rng.chance(0.5) {
+                            json!(["r", key, null])
+                        } else {
+                            json!(["w", key, self.value()])
+                        }
+                    })
+                    .collect();
+                json!({"type": "txn", "txn": ops})
+            }
+            other => unreachable!("The mix was checked to have no {other} requests"),
+        }
+    }
+
+    fn poll_offsets(&self) -> BTreeMap<String, u64> {
+        self.all_keys()
+            .map(|key| {
+                let offset = self.offsets.get(&key).copied().unwrap_or(0);
+                (key, offset)
+            })
+            .collect()
+    }
+
+    /// Learns from the reply to a request, such as how far kafka keys were polled.
+    pub fn observe(&mut self, reply: &Value) {
+        if reply["type"] != "poll_ok" {
+            return;
+        }
+        let Some(msgs) = reply["msgs"].as_object() else {
+            return;
+        };
+        for (key, entries) in msgs {
+            let last = entries
+                .as_array()
+                .and_then(|entries| entries.last())
+                .and_then(|entry| entry[0].as_u64());
+            if let Some(last) = last {
+                let offset = self.offsets.entry(key.clone()).or_insert(0);
+                *offset = (*offset).max(last + 1);
+            }
+        }
+    }
+}
+
+/// A request a client sent, and what came of it.
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub node: String,
+    pub request: Value,
+    pub sent_at: Instant,
+    /// The body of the reply, if one arrived in time.
+    pub reply: Option<Value>,
+    pub replied_at: Option<Instant>,
+}
+
+impl Op {
+    pub fn is_ok(&self) -> bool {
+        self.reply.as_ref().is_some_and(|reply| {
+            reply["type"]
+                .as_str()
+                .is_some_and(|kind| kind.ends_with("_ok"))
+        })
+    }
+
+    fn kind(&self) -> &str {
+        self.request["type"].as_str().unwrap_or_default()
+    }
+
+    /// How long the reply took, if one arrived.
+    pub fn latency(&self) -> Option<Duration> {
+        Some(self.replied_at?.duration_since(self.sent_at))
+    }
+}
+
+/// How the requests of one type fared, with latencies of those that were answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latencies {
+    pub kind: String,
+    pub sent: usize,
+    pub ok: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// The latencies of `ops` by type of request, in order of type.
+pub fn latencies(ops: &[Op]) -> Vec<Latencies> {
+    let mut by_kind: BTreeMap<&str, Vec<&Op>> = BTreeMap::new();
+    for op in ops {
+        by_kind.entry(op.kind()).or_default().push(op);
+    }
+    by_kind
+        .into_iter()
+        .map(|(kind, ops)| {
+            let mut answered: Vec<Duration> = ops.iter().filter_map(|op| op.latency()).collect();
+            answered.sort();
+            // The nearest rank, so that every percentile is a latency that was seen.
+            let percentile = |p: f64| {
+                let rank = (p * answered.len() as f64).ceil() as usize;
+                answered
+                    .get(rank.saturating_sub(1))
+                    .copied()
+                    .unwrap_or_default()
+            };
+            Latencies {
+                kind: kind.to_string(),
+                sent: ops.len(),
+                ok: ops.iter().filter(|op| op.is_ok()).count(),
+                p50: percentile(0.5),
+                p95: percentile(0.95),
+                p99: percentile(0.99),
+                max: answered.last().copied().unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// The outcome of one check of a history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    /// Why the check failed, or `None` if it passed.
+    pub failure: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, failures: Vec<String>) -> Self {
+        let failure = (!failures.is_empty()).then(|| {
+            let shown: Vec<&str> = failures.iter().take(5).map(String::as_str).collect();
+            let more = failures.len().saturating_sub(shown.len());
+            let more = if more > 0 {
+                format!(" and {more} more")
+            } else {
+                String::new()
+            };
+            format!("{}{more}", shown.join("; "))
+        });
+        Self { name, failure }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Checks `ops`, ending with `final_reads` that were sent once the cluster was healed and
+/// had time to converge, against what the workload promises.
+pub fn check(workload: Workload, ops: &[Op], final_reads: &[Op]) -> Vec<Check> {
+    let ok = |kind: &'static str| ops.iter().filter(move |op| op.kind() == kind && op.is_ok());
+    let reply = |op: &Op| op.reply.clone().unwrap_or_default();
+    match workload {
+        Workload::Echo => {
+            let failures = ok("echo")
+                .filter(|op| reply(op)["echo"] != op.request["echo"])
+                .map(|op| {
+                    format!(
+                        "{} echoed {} for {}",
+                        op.node,
+                        reply(op)["echo"],
+                        op.request["echo"]
+                    )
+                })
+                .collect();
+            vec![Check::new("echoes", failures)]
+        }
+        Workload::UniqueIds => {
+            let mut seen = BTreeSet::new();
+            let mut failures = Vec::new();
+            for op in ok("generate") {
+                let reply = reply(op);
+                let ids = match reply.get("ids").and_then(Value::as_array) {
+                    Some(ids) => ids.clone(),
+                    None => vec![reply["id"].clone()],
+                };
+                for id in ids {
+                    if !seen.insert(id.to_string()) {
+                        failures.push(format!("{} generated {id} again", op.node));
+                    }
+                }
+            }
+            vec![Check::new("unique", failures)]
+        }
+        Workload::Broadcast => check_sets(
+            ops,
+            final_reads,
+            ("broadcast", "message"),
+            "messages",
+            ("messages", "broadcast"),
+        ),
+        Workload::GSet => check_sets(
+            ops,
+            final_reads,
+            ("add", "element"),
+            "value",
+            ("elements", "added"),
+        ),
+        Workload::GCounter | Workload::PnCounter => {
+            let adds = ops.iter().filter(|op| op.kind() == "add");
+            let (mut low, mut high) = (0i64, 0i64);
+            for op in adds {
+                let delta = op.request["delta"].as_i64().unwrap_or_default();
+                if op.is_ok() {
+                    low += delta;
+                    high += delta;
+                } else if delta < 0 {
+                    low += delta;
+                } else {
+                    high += delta;
+                }
+            }
+            let failures = final_reads
+                .iter()
+                .filter_map(|read| {
+                    let value = read.reply.as_ref().filter(|_| read.is_ok())?["value"].as_i64();
+                    match value {
+                        Some(value) if (low..=high).contains(&value) => None,
+                        Some(value) => Some(format!(
+                            "{} read {value}, expected between {low} and {high}",
+                            read.node
+                        )),
+                        None => Some(format!("{} didn't answer the final read", read.node)),
+                    }
+                })
+                .collect();
+            vec![Check::new("final value", failures)]
+        }
+        Workload::Kafka => {
+            let mut offsets: BTreeMap<(String, u64), u64> = BTreeMap::new();
+            let mut failures = Vec::new();
+            for op in ok("send") {
+                let key = op.request["key"].as_str().unwrap_or_default().to_string();
+                let msg = op.request["msg"].as_u64().unwrap_or_default();
+                let offset = reply(op)["offset"].as_u64().unwrap_or_default();
+                if let Some(other) = offsets.insert((key.clone(), offset), msg) {
+                    failures.push(format!("{key} got {other} and {msg} at offset {offset}"));
+                }
+            }
+            for op in ok("poll") {
+                let reply = reply(op);
+                let Some(msgs) = reply["msgs"].as_object() else {
+                    continue;
+                };
+                for (key, entries) in msgs {
+                    for entry in entries.as_array().into_iter().flatten() {
+                        let (Some(offset), Some(msg)) = (entry[0].as_u64(), entry[1].as_u64())
+                        else {
+                            continue;
+                        };
+                        match offsets.get(&(key.clone(), offset)) {
+                            Some(&sent) if sent != msg => failures.push(format!(
+                                "{} polled {msg} at {key} offset {offset}, where {sent} was sent",
+                                op.node
+                            )),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            vec![Check::new("offsets", failures)]
+        }
+        Workload::TxnRwRegister => {
+            let written: BTreeSet<u64> = ops
+                .iter()
+                .filter(|op| op.kind() == "txn")
+                .flat_map(|op| op.request["txn"].as_array().cloned().unwrap_or_default())
+                .filter(|micro| micro[0] == "w")
+                .filter_map(|micro| micro[2].as_u64())
+                .collect();
+            let mut failures = Vec::new();
+            for op in ok("txn") {
+                for micro in reply(op)["txn"].as_array().into_iter().flatten() {
+                    if micro[0] != "r" || micro[2].is_null() {
+                        continue;
+                    }
+                    if !micro[2]
+                        .as_u64()
+                        .is_some_and(|value| written.contains(&value))
+                    {
+                        failures.push(format!("{} read {}, which nobody wrote", op.node, micro[2]));
+                    }
+                }
+            }
+            vec![Check::new("no phantoms", failures)]
+        }
+    }
+}
+
+/// Checks that the element in `field` of every acknowledged request of type `kind` is in
+/// every final read, in the list under `read_field`, and that reads have no element that
+/// no request had. Failures call the elements `noun`, and what requests do to them `verb`.
+fn check_sets(
+    ops: &[Op],
+    final_reads: &[Op],
+    (kind, field): (&str, &str),
+    read_field: &str,
+    (noun, verb): (&str, &str),
+) -> Vec<Check> {
+    // Values are compared as JSON, as they may be integers or anything else.
+    let elements = |acked: bool| -> BTreeSet<String> {
+        ops.iter()
+            .filter(|op| op.kind() == kind && (!acked || op.is_ok()))
+            .map(|op| op.request[field].to_string())
+            .collect()
+    };
+    let (acked, sent) = (elements(true), elements(false));
+    let mut lost = Vec::new();
+    let mut made_up = Vec::new();
+    for read in final_reads {
+        let Some(reply) = read.reply.as_ref().filter(|_| read.is_ok()) else {
+            lost.push(format!("{} didn't answer the final read", read.node));
+            continue;
+        };
+        let read_elements: BTreeSet<String> = reply[read_field]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Value::to_string)
+            .collect();
+        let missing = acked.difference(&read_elements).count();
+        if missing > 0 {
+            lost.push(format!(
+                "{} misses {missing} acknowledged {noun}",
+                read.node
+            ));
+        }
+        if let Some(element) = read_elements.difference(&sent).next() {
+            made_up.push(format!("{} read {element}, which nobody {verb}", read.node));
+        }
+    }
+    vec![
+        Check::new("delivered", lost),
+        Check::new("no phantoms", made_up),
+    ]
+}
+
+/// The request that reads the state a workload converges on at the end of a run, if it has
+/// one.
+pub fn final_read(workload: Workload) -> Option<Value> {
+    match workload {
+        Workload::Broadcast | Workload::GCounter | Workload::PnCounter | Workload::GSet => {
+            Some(json!({"type": "read"}))
+        }
+        _ => None,
+    }
+}
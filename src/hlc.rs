@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SystemClock};
+
+/// Bits of the packed timestamp used by the logical counter; the rest hold milliseconds
+/// since the Unix epoch, which lasts until the year 10889.
+const LOGICAL_BITS: u32 = 16;
+
+/// A hybrid logical clock reading: wall-clock milliseconds, and a counter that orders
+/// events within the same millisecond or while the wall clock lags behind a timestamp
+/// received from another node. Serialized packed into a single `u64`, which sorts the same
+/// way as the timestamps.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(from = "u64", into = "u64")]
+pub struct HlcTimestamp {
+    pub wall: u64,
+    pub logical: u16,
+}
+
+impl HlcTimestamp {
+    pub fn to_u64(self) -> u64 {
+        (self.wall << LOGICAL_BITS) | u64::from(self.logical)
+    }
+
+    pub fn from_u64(packed: u64) -> Self {
+        Self {
+            wall: packed >> LOGICAL_BITS,
+            logical: packed as u16,
+        }
+    }
+
+    /// A unique id made of the timestamp and the index of the node that took it. Ids taken
+    /// by one node never repeat, and ids of different nodes sort roughly by time.
+    pub fn unique_id(self, node_index: u64) -> u128 {
+        (u128::from(self.to_u64()) << 64) | u128::from(node_index)
+    }
+}
+
+impl From<u64> for HlcTimestamp {
+    fn from(packed: u64) -> Self {
+        Self::from_u64(packed)
+    }
+}
+
+impl From<HlcTimestamp> for u64 {
+    fn from(timestamp: HlcTimestamp) -> Self {
+        timestamp.to_u64()
+    }
+}
+
+/// Hands out timestamps that never go backwards, even when the wall clock does, and that
+/// are higher than every timestamp observed from other nodes.
+#[derive(Debug)]
+pub struct Hlc {
+    last: HlcTimestamp,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Hlc {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl Hlc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the wall clock from `clock` instead of the system clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            last: HlcTimestamp::default(),
+            clock,
+        }
+    }
+
+    /// The highest timestamp handed out or observed so far.
+    pub fn last(&self) -> HlcTimestamp {
+        self.last
+    }
+
+    /// Timestamps a local event, such as sending a message.
+    pub fn now(&mut self) -> HlcTimestamp {
+        self.now_at(self.wall_clock_millis())
+    }
+
+    /// Advances the clock past a timestamp received from another node.
+    pub fn observe(&mut self, remote: HlcTimestamp) -> HlcTimestamp {
+        self.observe_at(remote, self.wall_clock_millis())
+    }
+
+    fn wall_clock_millis(&self) -> u64 {
+        self.clock
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+
+    /// Like `now`, with the wall clock reading `wall` milliseconds.
+    pub fn now_at(&mut self, wall: u64) -> HlcTimestamp {
+        self.last = if wall > self.last.wall {
+            HlcTimestamp { wall, logical: 0 }
+        } else {
+            next(self.last)
+        };
+        self.last
+    }
+
+    /// Like `observe`, with the wall clock reading `wall` milliseconds.
+    pub fn observe_at(&mut self, remote: HlcTimestamp, wall: u64) -> HlcTimestamp {
+        let highest = self.last.max(remote);
+        self.last = if wall > highest.wall {
+            HlcTimestamp { wall, logical: 0 }
+        } else {
+            next(highest)
+        };
+        self.last
+    }
+}
+
+/// The timestamp right after `timestamp`. Once the logical counter is exhausted, the clock
+/// runs a millisecond ahead of the wall clock instead.
+fn next(timestamp: HlcTimestamp) -> HlcTimestamp {
+    match timestamp.logical.checked_add(1) {
+        Some(logical) => HlcTimestamp {
+            wall: timestamp.wall,
+            logical,
+        },
+        None => HlcTimestamp {
+            wall: timestamp.wall + 1,
+            logical: 0,
+        },
+    }
+}
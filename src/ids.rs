@@ -0,0 +1,166 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::clock::{Clock, SystemClock};
+
+/// Milliseconds since the Unix epoch at 2024-01-01T00:00:00Z, where snowflake timestamps
+/// start. The 41 timestamp bits last about 69 years from there.
+pub const SNOWFLAKE_EPOCH: u64 = 1_704_067_200_000;
+
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+
+pub const MAX_SNOWFLAKE_NODES: u64 = 1 << NODE_BITS;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// Generates 64-bit snowflake IDs: 41 bits of milliseconds since `SNOWFLAKE_EPOCH`, 10 bits
+/// of node index, and a 12-bit sequence number within the millisecond. IDs of one node
+/// strictly increase, and IDs of all nodes sort roughly by time.
+///
+/// If the wall clock goes backwards, the generator keeps using the last millisecond it
+/// issued IDs for. Once the sequence of a millisecond is exhausted, it moves on to the next
+/// millisecond without waiting for the wall clock to get there.
+#[derive(Debug)]
+pub struct Snowflake {
+    node_index: u64,
+    last_millis: u64,
+    sequence: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl Snowflake {
+    /// Returns `None` if the node index doesn't fit into the 10 node bits.
+    pub fn new(node_index: u64) -> Option<Self> {
+        Self::with_clock(node_index, Arc::new(SystemClock))
+    }
+
+    /// Like `new`, reading the wall clock from `clock` instead of the system clock.
+    pub fn with_clock(node_index: u64, clock: Arc<dyn Clock>) -> Option<Self> {
+        (node_index < MAX_SNOWFLAKE_NODES).then_some(Self {
+            node_index,
+            last_millis: 0,
+            sequence: 0,
+            clock,
+        })
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        self.next_id_at(unix_millis(self.clock.as_ref()))
+    }
+
+    /// Like `next_id`, with the wall clock reading `unix_millis`.
+    pub fn next_id_at(&mut self, unix_millis: u64) -> u64 {
+        let millis = unix_millis.saturating_sub(SNOWFLAKE_EPOCH);
+
+        if millis > self.last_millis {
+            self.last_millis = millis;
+            self.sequence = 0;
+        } else if self.sequence < MAX_SEQUENCE {
+            self.sequence += 1;
+        } else {
+            self.last_millis += 1;
+            self.sequence = 0;
+        }
+
+        (self.last_millis << (NODE_BITS + SEQUENCE_BITS))
+            | (self.node_index << SEQUENCE_BITS)
+            | self.sequence
+    }
+}
+
+/// Splits a snowflake ID into milliseconds since `SNOWFLAKE_EPOCH`, node index, and sequence.
+pub fn split_snowflake(id: u64) -> (u64, u64, u64) {
+    (
+        id >> (NODE_BITS + SEQUENCE_BITS),
+        (id >> SEQUENCE_BITS) & (MAX_SNOWFLAKE_NODES - 1),
+        id & MAX_SEQUENCE,
+    )
+}
+
+const ULID_RANDOM_BITS: u32 = 80;
+const ULID_RANDOM_MASK: u128 = (1 << ULID_RANDOM_BITS) - 1;
+const CROCKFORD_BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Generates 128-bit ULIDs: 48 bits of milliseconds since the Unix epoch followed by 80
+/// random bits, which keep IDs of different nodes apart without any coordination.
+///
+/// IDs generated within the same millisecond increment the random part of the previous
+/// one, so the IDs of one generator strictly increase. As with `Snowflake`, a wall clock
+/// going backwards is ignored, and an exhausted millisecond moves on to the next one.
+#[derive(Debug)]
+pub struct Ulid {
+    last_millis: u64,
+    random: u128,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Ulid {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl Ulid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the wall clock from `clock` instead of the system clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            last_millis: 0,
+            random: 0,
+            clock,
+        }
+    }
+
+    pub fn next_id(&mut self) -> u128 {
+        self.next_id_at(unix_millis(self.clock.as_ref()))
+    }
+
+    /// Like `next_id`, with the wall clock reading `unix_millis`.
+    pub fn next_id_at(&mut self, unix_millis: u64) -> u128 {
+        if unix_millis > self.last_millis {
+            self.last_millis = unix_millis;
+            self.random = random_bits();
+        } else if self.random < ULID_RANDOM_MASK {
+            self.random += 1;
+        } else {
+            self.last_millis += 1;
+            self.random = random_bits();
+        }
+
+        (u128::from(self.last_millis) << ULID_RANDOM_BITS) | self.random
+    }
+}
+
+fn unix_millis(clock: &dyn Clock) -> u64 {
+    clock
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// 80 random bits, with the top one cleared so that a millisecond always has room for
+/// plenty of increments.
+fn random_bits() -> u128 {
+    let high = RandomState::new().build_hasher().finish();
+    let low = RandomState::new().build_hasher().finish();
+    ((u128::from(high) << 64) | u128::from(low)) & (ULID_RANDOM_MASK >> 1)
+}
+
+/// Encodes a ULID as 26 characters of Crockford's base32, which sort lexicographically in
+/// the same order as the IDs.
+pub fn encode_ulid(id: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|i| char::from(CROCKFORD_BASE32[((id >> (i * 5)) & 0x1f) as usize]))
+        .collect()
+}
+
+/// The milliseconds since the Unix epoch embedded in a ULID.
+pub fn ulid_millis(id: u128) -> u64 {
+    (id >> ULID_RANDOM_BITS) as u64
+}
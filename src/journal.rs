@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// How a state changed, by JSON pointer: the values that are new or different, and the keys
+/// that are gone. Objects are compared key by key, and anything else as a whole.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delta {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unset: Vec<String>,
+}
+
+impl Delta {
+    /// The changes that turn `old` into `new`.
+    pub fn between(old: &Value, new: &Value) -> Self {
+        let mut delta = Delta::default();
+        delta.compare(String::new(), old, new);
+        delta
+    }
+
+    fn compare(&mut self, path: String, old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                for (key, value) in new {
+                    let path = format!("{path}/{}", escape(key));
+                    match old.get(key) {
+                        Some(old) => self.compare(path, old, value),
+                        None => {
+                            self.set.insert(path, value.clone());
+                        }
+                    }
+                }
+                for key in old.keys().filter(|key| !new.contains_key(*key)) {
+                    self.unset.push(format!("{path}/{}", escape(key)));
+                }
+            }
+            (old, new) if old != new => {
+                self.set.insert(path, new.clone());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.unset.is_empty()
+    }
+
+    /// Makes the changes to `state`, creating the objects that a set value is nested in.
+    pub fn apply(&self, state: &mut Value) {
+        for path in &self.unset {
+            if let Some((parent, key)) = split(path) {
+                if let Some(Value::Object(parent)) = state.pointer_mut(parent) {
+                    parent.remove(&key);
+                }
+            }
+        }
+        for (path, value) in &self.set {
+            let Some((parent, key)) = split(path) else {
+                *state = value.clone();
+                continue;
+            };
+            let mut target = &mut *state;
+            for segment in parent.split('/').skip(1).map(unescape) {
+                target = object(target)
+                    .entry(segment)
+                    .or_insert_with(|| Value::Object(Map::new()));
+            }
+            object(target).insert(key, value.clone());
+        }
+    }
+}
+
+/// `value` as an object, replacing it with an empty one if it's anything else.
+fn object(value: &mut Value) -> &mut Map<String, Value> {
+    if !value.is_object() {
+        *value = Value::Object(Map::new());
+    }
+    match value {
+        Value::Object(map) => map,
+        _ => unreachable!("Replaced with an object above"),
+    }
+}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+/// The pointer to the parent of `path`, and the key within it. `None` for the root.
+fn split(path: &str) -> Option<(&str, String)> {
+    let (parent, key) = path.rsplit_once('/')?;
+    Some((parent, unescape(key)))
+}
+
+/// An event a node handled, and how its state changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Counts the events of the node from 1.
+    pub index: u64,
+    /// Microseconds since the node started.
+    pub at_us: u64,
+    /// The type of the message handled, or `timer`.
+    pub event: String,
+    #[serde(flatten)]
+    pub delta: Delta,
+}
+
+/// Writes an entry for every event a node handles to a file of its own in a directory,
+/// named after the node. The state comes from the node's `debug_state` hook, and only how
+/// it changed is written.
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    started: Instant,
+    node_id: Option<String>,
+    file: Option<File>,
+    index: u64,
+    state: Value,
+}
+
+impl Journal {
+    /// Journals into `dir`, which is created if needed.
+    pub fn create(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Journal {
+            dir,
+            started: Instant::now(),
+            node_id: None,
+            file: None,
+            index: 0,
+            state: Value::Null,
+        })
+    }
+
+    /// Names the file after `node_id`, if no entry was written yet.
+    pub fn set_node_id(&mut self, node_id: &str) {
+        self.node_id.get_or_insert_with(|| node_id.to_string());
+    }
+
+    pub fn path(&self) -> PathBuf {
+        let name = match &self.node_id {
+            Some(node_id) => node_id.clone(),
+            None => format!("pid-{}", std::process::id()),
+        };
+        self.dir.join(format!("{name}.jsonl"))
+    }
+
+    /// Writes an entry for `event`, after which the node is in `state`.
+    pub fn write(&mut self, event: &str, state: Value) -> Result<(), anyhow::Error> {
+        self.index += 1;
+        let entry = Entry {
+            index: self.index,
+            at_us: self.started.elapsed().as_micros() as u64,
+            event: event.to_string(),
+            delta: Delta::between(&self.state, &state),
+        };
+        self.state = state;
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let path = self.path();
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                self.file.insert(file)
+            }
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        file.write_all(&line).context("Failed to write journal")
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Journal>> {
+    JOURNAL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Makes `journal` journal the events of the whole process, from now on.
+pub fn install(journal: Journal) {
+    *lock() = Some(journal);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether events are journaled, so that the state isn't built for nothing.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Names the journal of the process after `node_id`, if journaling.
+pub fn set_node_id(node_id: &str) {
+    if let Some(journal) = lock().as_mut() {
+        journal.set_node_id(node_id);
+    }
+}
+
+/// Journals `event`, with the state `state` builds, if journaling. A journal that fails to
+/// write is dropped, so that the node goes on without it.
+pub fn event(event: &str, state: impl FnOnce() -> Value) {
+    if !enabled() {
+        return;
+    }
+    let mut journal = lock();
+    if let Some(Err(error)) = journal
+        .as_mut()
+        .map(|journal| journal.write(event, state()))
+    {
+        log::error!("Stopped journaling: {error:#}");
+        *journal = None;
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Reads the entries of a journal.
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Entry>, anyhow::Error> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            serde_json::from_str(&line).with_context(|| {
+                format!("Invalid entry on line {} of {}", index + 1, path.display())
+            })
+        })
+        .collect()
+}
+
+/// The state after the event with `index`, or after the last one if there are fewer. At 0,
+/// that's the state before any event, `null`.
+pub fn state_at(entries: &[Entry], index: u64) -> Value {
+    let mut state = Value::Null;
+    for entry in entries.iter().take_while(|entry| entry.index <= index) {
+        entry.delta.apply(&mut state);
+    }
+    state
+}
@@ -0,0 +1,1236 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::NodeArgs;
+use crate::hlc::{Hlc, HlcTimestamp};
+use crate::kv::{self, KvClient, KvService};
+use crate::log_store::{DiskLogStore, LogStore, MemoryLogStore, Retention, TxnMark};
+use crate::protocol::{self, Handshake};
+use crate::rpc::PendingRequests;
+use crate::runtime::{self, Lifecycle, Output};
+use crate::sim::{self, SimNode};
+use crate::transport;
+use crate::txn::{AwaitingDecisions, Coordinator};
+use crate::{ErrorBody, ErrorCode};
+
+mod chain;
+mod fencing;
+mod lin_kv;
+mod poll;
+mod replication;
+mod transactions;
+
+use fencing::FencedCommit;
+use lin_kv::KvStep;
+use poll::LogMetadata;
+use replication::Replication;
+use transactions::{CommittedTxn, PendingTxn, Transaction, TxnCommit};
+
+type Message = crate::Message<Body>;
+
+type Entries<V> = Vec<(String, V)>;
+
+/// Offset ranges assigned to the sends of one client to one key, by msg_id, oldest first.
+type RecentSends = VecDeque<(u64, [u64; 2])>;
+
+/// Default maximum number of entries per key returned by a single poll. Every entry costs a
+/// seq-kv read in lin-kv mode, so the limit is lower there.
+const POLL_LIMIT: u64 = 100;
+
+const KV_POLL_LIMIT: u64 = 10;
+
+/// Number of recent sends remembered per client and key to detect retries.
+const PRODUCER_WINDOW: usize = 100;
+
+/// How long entries sent to a follower, or a request for a follower's logs, wait for an
+/// answer before they're sent again. In a chain, the follower is taken for failed instead,
+/// after this long for every node down to the tail, which all have to answer first.
+const REPLICATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A transaction that wasn't committed this long after it began is aborted. Entries of a
+/// transaction that stay pending this long are settled without its coordinator.
+const TXN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+
+    InitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(flatten)]
+        handshake: Handshake,
+    },
+
+    Send {
+        msg_id: u64,
+        key: String,
+        msg: Msgs,
+    },
+
+    SendOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        offset: u64,
+        /// Offset of the last message, when more than one was appended.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_offset: Option<u64>,
+    },
+
+    SendBatch {
+        msg_id: u64,
+        msgs: HashMap<String, Vec<u64>>,
+        /// Identifies the original request when a batch is forwarded to key owners.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        producer: Option<Producer>,
+    },
+
+    SendBatchOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        /// First and last offset assigned to the messages of every key.
+        offsets: HashMap<String, [u64; 2]>,
+    },
+
+    Poll {
+        msg_id: u64,
+        offsets: HashMap<String, u64>,
+        /// Lowers the node's per-key limit for this poll only.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_msgs: Option<u64>,
+        /// Consumer group whose committed offsets the reply's metadata has.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+
+    PollOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        msgs: HashMap<String, Vec<[u64; 2]>>,
+        /// First offset still kept, for every polled key whose requested offset was truncated.
+        /// Only in the legacy layout, as `metadata` has the first offset of every key.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        low_watermarks: HashMap<String, u64>,
+        /// Bounds of the log and committed offset of every polled key.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        metadata: HashMap<String, LogMetadata>,
+    },
+
+    CommitOffsets {
+        msg_id: u64,
+        offsets: HashMap<String, u64>,
+        /// Consumer group the offsets are committed for; groups commit independently.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Fencing token of the committing consumer: a commit with an epoch older than one
+        /// the group already committed a key with is rejected.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        epoch: Option<u64>,
+    },
+
+    CommitOffsetsOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    /// The part of a commit whose epoch the heads of all its keys checked, forwarded to the
+    /// head of its keys, which doesn't fail it on an epoch anymore. Only taken from nodes,
+    /// so that clients can't skip the check. Answered with `commit_offsets_ok`.
+    CommitCheckedOffsets {
+        msg_id: u64,
+        offsets: HashMap<String, u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        epoch: u64,
+    },
+
+    /// Asks the head of `keys` whether `group` may commit them with `epoch`, before the
+    /// keys of a commit are committed on any node.
+    CheckEpochs {
+        msg_id: u64,
+        keys: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        epoch: u64,
+    },
+
+    CheckEpochsOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    ListCommittedOffsets {
+        msg_id: u64,
+        keys: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+
+    ListCommittedOffsetsOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        offsets: HashMap<String, u64>,
+    },
+
+    /// Asks for the first offset of every key appended at or after a time, in milliseconds
+    /// since the Unix epoch.
+    ListOffsets {
+        msg_id: u64,
+        timestamps: HashMap<String, u64>,
+    },
+
+    ListOffsetsOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        offsets: HashMap<String, u64>,
+    },
+
+    /// Starts a transaction, whose `produce` requests are appended on `commit`, all or none.
+    Begin {
+        msg_id: u64,
+    },
+
+    BeginOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        /// Identifies the transaction on the node that began it, which gets all its requests.
+        txn_id: u64,
+    },
+
+    Produce {
+        msg_id: u64,
+        txn_id: u64,
+        key: String,
+        msg: Msgs,
+    },
+
+    ProduceOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+
+    Commit {
+        msg_id: u64,
+        txn_id: u64,
+    },
+
+    CommitOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        /// First and last offset the transaction's messages got in every key.
+        offsets: HashMap<String, [u64; 2]>,
+    },
+
+    /// Asks the head of the keys of `msgs` to append the messages of the transaction `txn`
+    /// as pending entries, which polls don't return, the first phase of committing it. The
+    /// head of `primary` decides whether the transaction commits.
+    PrepareTxn {
+        msg_id: u64,
+        txn: String,
+        primary: String,
+        msgs: HashMap<String, Vec<u64>>,
+    },
+
+    PrepareTxnOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        /// First and last offset the transaction's messages got in every key.
+        offsets: HashMap<String, [u64; 2]>,
+    },
+
+    /// Tells the head of `key` whether the transaction `txn` commits, the second phase of
+    /// committing it. Without `commit`, asks the head of the primary key what it decided.
+    DecideTxn {
+        msg_id: u64,
+        txn: String,
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commit: Option<bool>,
+    },
+
+    DecideTxnOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        committed: bool,
+    },
+
+    /// Entries of a key its owner copies to a follower, the first of which has `offset`.
+    Replicate {
+        msg_id: u64,
+        key: String,
+        offset: u64,
+        msgs: Vec<u64>,
+        /// When the owner appended each of `msgs`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        timestamps: Vec<HlcTimestamp>,
+        /// An offset of the key a group committed, which the follower commits too.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commit: Option<OffsetCommit>,
+        /// The mark of a transaction with entries in the key, which the follower keeps too.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        txn: Option<TxnMark>,
+    },
+
+    ReplicateOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        /// Offset the next entry of the follower's copy of the log will get.
+        log_end: u64,
+    },
+
+    /// Asks a follower for its copies of the logs the sender owns.
+    Recover {
+        msg_id: u64,
+    },
+
+    RecoverOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        logs: HashMap<String, Vec<[u64; 2]>>,
+        /// When each entry of `logs` was appended.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        timestamps: HashMap<String, Vec<HlcTimestamp>>,
+        /// The offsets every group committed of the keys of `logs`, and of keys without
+        /// entries.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        commits: HashMap<String, Vec<OffsetCommit>>,
+        /// The marks of the transactions with entries in `logs`.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        txns: HashMap<String, Vec<TxnMark>>,
+    },
+
+    /// Tells the other nodes that `node` failed, and is left out of chains, or is back. A
+    /// node that is `rejoining` gets new entries passed on, but neither heads nor tails a
+    /// chain until it caught up on the entries it lacks.
+    Reconfigure {
+        msg_id: u64,
+        node: String,
+        failed: bool,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        rejoining: bool,
+    },
+
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+/// An offset of a key committed by `group`, with the epoch of the commit, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct OffsetCommit {
+    group: String,
+    offset: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+}
+
+/// The client request that produced a send, used to recognize retries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Producer {
+    client: String,
+    msg_id: u64,
+}
+
+/// Offset ranges assigned to the most recent sends of every client, per key, so that a send
+/// retried after a timeout gets its original offsets back instead of being appended again.
+#[derive(Debug, Default)]
+struct Producers {
+    sent: HashMap<(String, String), RecentSends>,
+}
+
+impl Producers {
+    fn get(&self, key: &str, producer: &Producer) -> Option<[u64; 2]> {
+        self.sent
+            .get(&(key.to_string(), producer.client.clone()))?
+            .iter()
+            .find(|(msg_id, _)| *msg_id == producer.msg_id)
+            .map(|(_, range)| *range)
+    }
+
+    fn record(&mut self, key: &str, producer: &Producer, range: [u64; 2]) {
+        let sent = self
+            .sent
+            .entry((key.to_string(), producer.client.clone()))
+            .or_default();
+        if sent.len() == PRODUCER_WINDOW {
+            sent.pop_front();
+        }
+        sent.push_back((producer.msg_id, range));
+    }
+}
+
+/// A `send` carries either a single message or an array of messages for its key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Msgs {
+    One(u64),
+    Many(Vec<u64>),
+}
+
+impl Msgs {
+    fn into_vec(self) -> Vec<u64> {
+        match self {
+            Msgs::One(msg) => vec![msg],
+            Msgs::Many(msgs) => msgs,
+        }
+    }
+}
+
+/// Where logs and offsets are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Storage {
+    /// Every key is owned by one node, which keeps its log in memory.
+    Local,
+    /// Like `Local`, but logs and commits are also written to disk and replayed on restart.
+    Disk,
+    /// Next offsets and commits live in lin-kv and entries in seq-kv, so any node accepts any key.
+    LinKv,
+}
+
+impl std::str::FromStr for Storage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Storage::Local),
+            "disk" => Ok(Storage::Disk),
+            "lin-kv" => Ok(Storage::LinKv),
+            other => anyhow::bail!("Unknown kafka storage: {other}"),
+        }
+    }
+}
+
+/// How many copies of a key's log must have a send before it's acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Acks {
+    /// The owner's own log; followers get the entries afterwards.
+    Leader,
+    /// A majority of the owner and its followers.
+    Quorum,
+    /// The owner and every follower.
+    All,
+}
+
+impl std::str::FromStr for Acks {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leader" => Ok(Acks::Leader),
+            "quorum" => Ok(Acks::Quorum),
+            "all" => Ok(Acks::All),
+            other => anyhow::bail!("Unknown ack level: {other}"),
+        }
+    }
+}
+
+/// How a key's owner gets new entries to its followers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplicationMode {
+    /// The owner sends them to every follower.
+    Followers,
+    /// The owner, as the head of a chain of itself and its followers, sends them to the next
+    /// node, which passes them on, down to the tail.
+    Chain,
+}
+
+impl std::str::FromStr for ReplicationMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "followers" => Ok(ReplicationMode::Followers),
+            "chain" => Ok(ReplicationMode::Chain),
+            other => anyhow::bail!("Unknown replication mode: {other}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RequestKind {
+    Send,
+    SendBatch,
+    Poll,
+    CommitOffsets,
+    ListCommittedOffsets,
+    ListOffsets,
+    Commit,
+    PrepareTxn,
+    DecideTxn,
+}
+
+/// Results gathered from the nodes owning the keys of a client request.
+#[derive(Debug, Default)]
+struct PartialResult {
+    ranges: HashMap<String, [u64; 2]>,
+    msgs: HashMap<String, Vec<[u64; 2]>>,
+    offsets: HashMap<String, u64>,
+    poll_from: HashMap<String, u64>,
+    low_watermarks: HashMap<String, u64>,
+    metadata: HashMap<String, LogMetadata>,
+    /// Followers that have yet to acknowledge the entries appended to each key of a send.
+    acks: HashMap<String, usize>,
+    /// Whether the transaction a `decide_txn` is about committed.
+    committed: bool,
+}
+
+impl PartialResult {
+    /// Entries read from seq-kv may arrive out of order or with gaps; only the contiguous run
+    /// starting at the requested offset is returned.
+    fn contiguous_msgs(mut self) -> HashMap<String, Vec<[u64; 2]>> {
+        for (key, from) in &self.poll_from {
+            if let Some(msgs) = self.msgs.get_mut(key) {
+                msgs.sort();
+                let contiguous = msgs
+                    .iter()
+                    .zip(*from..)
+                    .take_while(|([offset, _], expected)| offset == expected)
+                    .count();
+                msgs.truncate(contiguous);
+            }
+        }
+
+        self.msgs
+    }
+}
+
+/// A client request whose keys are owned by other nodes and was forwarded to them.
+struct ProxiedRequest {
+    client: String,
+    in_reply_to: u64,
+    kind: RequestKind,
+    waiting: usize,
+    result: PartialResult,
+}
+
+struct Node {
+    node_id: String,
+    cluster: Vec<String>,
+    msg_id: u64,
+    storage: Storage,
+    data_dir: PathBuf,
+    retention: Retention,
+    poll_limit: u64,
+    /// Answers polls in the layout from before `metadata`, with `low_watermarks` instead.
+    legacy_poll_ok: bool,
+    store: Box<dyn LogStore>,
+    /// Timestamps the entries this node appends.
+    hlc: Hlc,
+    producers: Producers,
+    proxied: HashMap<u64, ProxiedRequest>,
+    forwarded: PendingRequests<u64>,
+    /// Followers each key's log is copied to, besides its owner.
+    replicas: usize,
+    acks: Acks,
+    mode: ReplicationMode,
+    /// Entries sent to followers, with how long they wait for an answer.
+    replicating: PendingRequests<(Replication, Duration)>,
+    /// Followers asked for their copies of this node's logs, which haven't answered yet.
+    recovering: PendingRequests<String>,
+    /// Nodes left out of chains, as they didn't answer in time.
+    failed: BTreeSet<String>,
+    /// Nodes back in chains that are still catching up, this one included, which pass
+    /// entries on but don't take requests as a head or a tail.
+    rejoining: BTreeSet<String>,
+    /// Commits waiting for the epochs of their keys to be checked, by request id.
+    fenced_commits: PendingRequests<FencedCommit>,
+    /// Requests of `check_epochs` sent, with the id of the commit they're for.
+    epoch_checks: PendingRequests<u64>,
+    /// Transactions begun on this node that the client hasn't asked to commit yet, by id.
+    transactions: PendingRequests<Transaction>,
+    /// Votes on the transactions begun on this node, and their decisions the heads of
+    /// their keys haven't acknowledged yet, by the name of the transaction.
+    coordinator: Coordinator<TxnCommit>,
+    /// Votes asked for, with the name of their transaction.
+    votes: PendingRequests<String>,
+    /// Transactions that committed, whose keys are told, by name.
+    committed: HashMap<String, CommittedTxn>,
+    /// Decisions sent to the heads of keys, with the transaction and the key.
+    deciding: PendingRequests<(String, String)>,
+    /// Transactions with pending entries this node keeps, by name.
+    undecided: AwaitingDecisions<PendingTxn>,
+    /// Questions to the head of a primary key about the decision on a transaction, with
+    /// the name of the transaction.
+    queries: PendingRequests<String>,
+    lin_kv: KvClient<KvStep>,
+    seq_kv: KvClient<KvStep>,
+}
+
+impl Node {
+    fn new(cli: Cli, poll_limit: u64, time: Arc<dyn Clock>) -> Self {
+        Self {
+            node_id: String::new(),
+            cluster: Vec::new(),
+            msg_id: 0,
+            storage: cli.storage,
+            data_dir: cli.data_dir,
+            retention: cli.retention,
+            poll_limit,
+            legacy_poll_ok: cli.legacy_poll_ok,
+            store: Box::new(MemoryLogStore::new()),
+            hlc: Hlc::with_clock(time.clone()),
+            producers: Producers::default(),
+            proxied: HashMap::new(),
+            forwarded: PendingRequests::new(),
+            replicas: cli.replicas,
+            acks: cli.acks,
+            mode: cli.replication,
+            replicating: PendingRequests::with_clock(time.clone()),
+            recovering: PendingRequests::with_clock(time.clone()),
+            failed: BTreeSet::new(),
+            rejoining: BTreeSet::new(),
+            fenced_commits: PendingRequests::with_clock(time.clone()),
+            epoch_checks: PendingRequests::with_clock(time.clone()),
+            transactions: PendingRequests::with_clock(time.clone()),
+            coordinator: Coordinator::with_clock(time.clone()),
+            votes: PendingRequests::with_clock(time.clone()),
+            committed: HashMap::new(),
+            deciding: PendingRequests::with_clock(time.clone()),
+            undecided: AwaitingDecisions::with_clock(time.clone()),
+            queries: PendingRequests::with_clock(time),
+            lin_kv: KvClient::new(KvService::LinKv),
+            seq_kv: KvClient::new(KvService::SeqKv),
+        }
+    }
+
+    fn initialize(&mut self, node_id: String, node_ids: &[String]) -> Result<(), anyhow::Error> {
+        if self.storage == Storage::Disk {
+            self.store = Box::new(DiskLogStore::open(self.data_dir.join(&node_id))?);
+        }
+
+        self.node_id = node_id;
+        self.cluster.extend_from_slice(node_ids);
+        self.cluster.sort();
+        self.await_replayed_decisions();
+        Ok(())
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    /// Every node hashes keys the same way (FNV-1a), so they agree on owners without talking.
+    fn owner_of(&self, key: &str) -> &str {
+        if self.cluster.is_empty() {
+            return &self.node_id;
+        }
+
+        let hash = key.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        &self.cluster[(hash % self.cluster.len() as u64) as usize]
+    }
+
+    /// Sends again the messages that weren't answered in time, as they may have been lost,
+    /// and gives up on those that took too long. The node has no timer, so this happens as
+    /// messages arrive.
+    fn resend_expired(&mut self) -> Vec<Message> {
+        let mut sent = self.resend_replications();
+        sent.extend(self.expire_transactions());
+        sent
+    }
+
+    /// Handles the messages this node addresses to itself right away, such as the parts of
+    /// transactions with keys it owns, and returns the rest.
+    fn loop_back(&mut self, messages: Vec<Message>) -> Vec<Message> {
+        let mut queue = VecDeque::from(messages);
+        let mut outgoing = Vec::new();
+        while let Some(mut message) = queue.pop_front() {
+            if message.dest == self.node_id {
+                queue.extend(self.process_received_message(&mut message));
+            } else {
+                outgoing.push(message);
+            }
+        }
+        outgoing
+    }
+
+    /// Every node of the cluster but this one.
+    fn peers(&self) -> Vec<String> {
+        self.cluster
+            .iter()
+            .filter(|id| **id != self.node_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Splits keys by the node `route` picks for them. Requests forwarded by other nodes are
+    /// always handled locally to avoid forwarding loops.
+    fn split_by_owner<V>(
+        &self,
+        src: &str,
+        entries: impl IntoIterator<Item = (String, V)>,
+        route: impl Fn(&str) -> String,
+    ) -> (Entries<V>, HashMap<String, Entries<V>>) {
+        let from_peer = self.cluster.iter().any(|id| id == src);
+        let mut local = Vec::new();
+        let mut remote: HashMap<String, Entries<V>> = HashMap::new();
+
+        for (key, value) in entries {
+            let owner = route(&key);
+            if from_peer || owner == self.node_id {
+                local.push((key, value));
+            } else {
+                remote.entry(owner).or_default().push((key, value));
+            }
+        }
+
+        (local, remote)
+    }
+
+    fn forward(&mut self, owner: String, build_body: impl FnOnce(u64) -> Body) -> (u64, Message) {
+        let msg_id = self.incremented_msg_id();
+
+        let forwarded = Message {
+            src: self.node_id.clone(),
+            dest: owner,
+            body: build_body(msg_id),
+        };
+
+        (msg_id, forwarded)
+    }
+
+    /// Replies straight away when every key was local, otherwise sends the forwarded requests
+    /// and replies once all owners have answered.
+    fn proxy(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        kind: RequestKind,
+        result: PartialResult,
+        forwarded: Vec<(u64, Message)>,
+    ) -> Vec<Message> {
+        self.proxy_replicated(client, in_reply_to, kind, result, forwarded, Vec::new())
+    }
+
+    fn complete_forwarded(
+        &mut self,
+        in_reply_to: u64,
+        update: impl FnOnce(&mut PartialResult),
+    ) -> Vec<Message> {
+        let Some(id) = self.forwarded.complete(in_reply_to) else {
+            return Vec::new();
+        };
+        self.complete_part(id, update)
+    }
+
+    /// Records one answered part of a client request and replies once no parts are left.
+    fn complete_part(&mut self, id: u64, update: impl FnOnce(&mut PartialResult)) -> Vec<Message> {
+        let Some(proxied) = self.proxied.get_mut(&id) else {
+            return Vec::new();
+        };
+
+        update(&mut proxied.result);
+        proxied.waiting -= 1;
+
+        if proxied.waiting > 0 {
+            return Vec::new();
+        }
+
+        match self.proxied.remove(&id) {
+            Some(proxied) => vec![self.reply_to_client(proxied)],
+            None => Vec::new(),
+        }
+    }
+
+    fn reply_to_client(&mut self, proxied: ProxiedRequest) -> Message {
+        let msg_id = self.incremented_msg_id();
+        let in_reply_to = proxied.in_reply_to;
+        let mut result = proxied.result;
+
+        let body = match proxied.kind {
+            RequestKind::Send => {
+                let [first, last] = result.ranges.into_values().next().unwrap_or_default();
+                Body::SendOk {
+                    msg_id,
+                    in_reply_to,
+                    offset: first,
+                    last_offset: (last != first).then_some(last),
+                }
+            }
+            RequestKind::SendBatch => Body::SendBatchOk {
+                msg_id,
+                in_reply_to,
+                offsets: result.ranges,
+            },
+            RequestKind::Poll if self.legacy_poll_ok => Body::PollOk {
+                msg_id,
+                in_reply_to,
+                low_watermarks: std::mem::take(&mut result.low_watermarks),
+                metadata: HashMap::new(),
+                msgs: result.contiguous_msgs(),
+            },
+            RequestKind::Poll => Body::PollOk {
+                msg_id,
+                in_reply_to,
+                low_watermarks: HashMap::new(),
+                metadata: std::mem::take(&mut result.metadata),
+                msgs: result.contiguous_msgs(),
+            },
+            RequestKind::CommitOffsets => Body::CommitOffsetsOk {
+                msg_id,
+                in_reply_to,
+            },
+            RequestKind::ListCommittedOffsets => Body::ListCommittedOffsetsOk {
+                msg_id,
+                in_reply_to,
+                offsets: result.offsets,
+            },
+            RequestKind::ListOffsets => Body::ListOffsetsOk {
+                msg_id,
+                in_reply_to,
+                offsets: result.offsets,
+            },
+            RequestKind::Commit => Body::CommitOk {
+                msg_id,
+                in_reply_to,
+                offsets: result.ranges,
+            },
+            RequestKind::PrepareTxn => Body::PrepareTxnOk {
+                msg_id,
+                in_reply_to,
+                offsets: result.ranges,
+            },
+            RequestKind::DecideTxn => Body::DecideTxnOk {
+                msg_id,
+                in_reply_to,
+                committed: result.committed,
+            },
+        };
+
+        Message {
+            src: self.node_id.clone(),
+            dest: proxied.client,
+            body,
+        }
+    }
+
+    /// Every key of a send has to carry at least one message to be assigned offsets.
+    fn reject_empty_send(
+        &self,
+        client: &str,
+        in_reply_to: u64,
+        entries: &Entries<Vec<u64>>,
+    ) -> Option<Message> {
+        let (key, _) = entries.iter().find(|(_, msgs)| msgs.is_empty())?;
+
+        Some(Message {
+            src: self.node_id.clone(),
+            dest: client.to_string(),
+            body: Body::Error(ErrorBody::new(
+                in_reply_to,
+                ErrorCode::MalformedRequest,
+                format!("No messages to send to {key}"),
+            )),
+        })
+    }
+
+    fn append_all(&mut self, key: &str, msgs: &[u64]) -> Result<[u64; 2], anyhow::Error> {
+        let mut range = [0; 2];
+
+        for (i, msg) in msgs.iter().enumerate() {
+            let offset = self.store.append(key, *msg, self.hlc.now())?;
+            if i == 0 {
+                range[0] = offset;
+            }
+            range[1] = offset;
+        }
+
+        self.store.enforce(key, self.retention)?;
+        Ok(range)
+    }
+
+    /// Appends the messages of owned keys and forwards the rest to their owners as a single
+    /// batch per owner.
+    fn send(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        kind: RequestKind,
+        producer: Producer,
+        entries: Entries<Vec<u64>>,
+    ) -> Vec<Message> {
+        if let Some(rejection) = self.reject_empty_send(client, in_reply_to, &entries) {
+            return vec![rejection];
+        }
+
+        let (local, remote) = self.split_by_owner(client, entries, |key| self.head_of(key));
+        let mut result = PartialResult::default();
+        let mut forwarded = Vec::new();
+        let mut replications = Vec::new();
+
+        for (key, msgs) in local {
+            if let Some(range) = self.producers.get(&key, &producer) {
+                result.ranges.insert(key, range);
+                continue;
+            }
+            // A node that takes another node for the head may have entries this one lacks.
+            if self.mode == ReplicationMode::Chain && self.head_of(&key) != self.node_id {
+                return vec![Message {
+                    src: self.node_id.clone(),
+                    dest: client.to_string(),
+                    body: Body::Error(ErrorBody::new(
+                        in_reply_to,
+                        ErrorCode::TemporarilyUnavailable,
+                        format!("Not the head of the chain of {key}"),
+                    )),
+                }];
+            }
+            match self.append_all(&key, &msgs) {
+                Ok(range) => {
+                    self.producers.record(&key, &producer, range);
+                    replications.extend(self.replicate_to_followers(
+                        &key,
+                        range[0],
+                        None,
+                        None,
+                        &mut result,
+                    ));
+                    result.ranges.insert(key, range);
+                }
+                Err(error) => {
+                    return vec![Message {
+                        src: self.node_id.clone(),
+                        dest: client.to_string(),
+                        body: Body::Error(ErrorBody::new(
+                            in_reply_to,
+                            ErrorCode::Crash,
+                            format!("Failed to append to {key}: {error}"),
+                        )),
+                    }]
+                }
+            }
+        }
+        for (owner, entries) in remote {
+            forwarded.push(self.forward(owner, |msg_id| Body::SendBatch {
+                msg_id,
+                msgs: entries.into_iter().collect(),
+                producer: Some(producer.clone()),
+            }));
+        }
+
+        self.proxy_replicated(client, in_reply_to, kind, result, forwarded, replications)
+    }
+
+    fn process_received_message(&mut self, message: &mut Message) -> Vec<Message> {
+        let build_message_from = |body: Body| -> Vec<Message> {
+            vec![Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }]
+        };
+
+        if !self.recovering.is_empty() {
+            if let Body::Send { msg_id, .. }
+            | Body::SendBatch { msg_id, .. }
+            | Body::Poll { msg_id, .. }
+            | Body::CommitOffsets { msg_id, .. }
+            | Body::CommitCheckedOffsets { msg_id, .. }
+            | Body::ListCommittedOffsets { msg_id, .. }
+            | Body::ListOffsets { msg_id, .. }
+            | Body::CheckEpochs { msg_id, .. }
+            | Body::PrepareTxn { msg_id, .. }
+            | Body::DecideTxn { msg_id, .. } = &message.body
+            {
+                return build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::TemporarilyUnavailable,
+                    "Recovering the logs from followers",
+                )));
+            }
+        }
+
+        match &mut message.body {
+            Body::Poll { .. }
+            | Body::PollOk { .. }
+            | Body::ListCommittedOffsets { .. }
+            | Body::ListCommittedOffsetsOk { .. }
+            | Body::ListOffsets { .. }
+            | Body::ListOffsetsOk { .. } => self.process_poll(message),
+
+            Body::CommitOffsets { .. }
+            | Body::CommitOffsetsOk { .. }
+            | Body::CommitCheckedOffsets { .. }
+            | Body::CheckEpochs { .. }
+            | Body::CheckEpochsOk { .. } => self.process_commit(message),
+            Body::Error(error_body)
+                if self.epoch_checks.get_mut(error_body.in_reply_to).is_some() =>
+            {
+                self.process_commit(message)
+            }
+
+            Body::Begin { .. }
+            | Body::Produce { .. }
+            | Body::Commit { .. }
+            | Body::PrepareTxn { .. }
+            | Body::PrepareTxnOk { .. }
+            | Body::DecideTxn { .. }
+            | Body::DecideTxnOk { .. } => self.process_transaction(message),
+            Body::Error(error_body)
+                if self.votes.get_mut(error_body.in_reply_to).is_some()
+                    || self.deciding.get_mut(error_body.in_reply_to).is_some()
+                    || self.queries.get_mut(error_body.in_reply_to).is_some() =>
+            {
+                self.process_transaction(message)
+            }
+
+            Body::Replicate { .. }
+            | Body::ReplicateOk { .. }
+            | Body::Recover { .. }
+            | Body::RecoverOk { .. } => self.process_replication(message),
+
+            Body::Reconfigure { .. } => self.process_chain(message),
+
+            Body::Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                if let Err(error) = self.initialize(node_id.clone(), node_ids) {
+                    log::error!("Failed to initialize storage: {error:?}");
+                    return build_message_from(Body::Error(ErrorBody::new(
+                        *msg_id,
+                        ErrorCode::Crash,
+                        format!("Failed to initialize storage: {error}"),
+                    )));
+                }
+
+                let mut responses = build_message_from(Body::InitOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    handshake: protocol::answer(),
+                });
+                responses.extend(match self.mode {
+                    ReplicationMode::Followers => self.recover(),
+                    ReplicationMode::Chain => self.rejoin(),
+                });
+                responses
+            }
+
+            Body::Send { msg_id, key, msg } => {
+                let producer = Producer {
+                    client: message.src.clone(),
+                    msg_id: *msg_id,
+                };
+                let entries = vec![(key.clone(), msg.clone().into_vec())];
+                self.send(&message.src, *msg_id, RequestKind::Send, producer, entries)
+            }
+
+            Body::SendBatch {
+                msg_id,
+                msgs,
+                producer,
+            } => {
+                let producer = producer.take().unwrap_or_else(|| Producer {
+                    client: message.src.clone(),
+                    msg_id: *msg_id,
+                });
+                let entries = msgs.drain().collect();
+                self.send(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::SendBatch,
+                    producer,
+                    entries,
+                )
+            }
+
+            Body::SendBatchOk {
+                in_reply_to,
+                offsets,
+                ..
+            } => self
+                .complete_forwarded(*in_reply_to, |result| result.ranges.extend(offsets.drain())),
+
+            Body::InitOk { msg_id, .. }
+            | Body::SendOk { msg_id, .. }
+            | Body::BeginOk { msg_id, .. }
+            | Body::ProduceOk { msg_id, .. }
+            | Body::CommitOk { msg_id, .. } => build_message_from(Body::Error(ErrorBody::new(
+                *msg_id,
+                ErrorCode::NotSupported,
+                "Kafka node does not accept replies",
+            ))),
+
+            Body::Error(error_body) => {
+                let Some(id) = self.forwarded.complete(error_body.in_reply_to) else {
+                    log::warn!("Received error: {:?}", error_body);
+                    return Vec::new();
+                };
+                let Some(proxied) = self.proxied.remove(&id) else {
+                    return Vec::new();
+                };
+
+                vec![Message {
+                    src: self.node_id.clone(),
+                    dest: proxied.client,
+                    body: Body::Error(ErrorBody::new(
+                        proxied.in_reply_to,
+                        error_body.code,
+                        error_body.text.clone(),
+                    )),
+                }]
+            }
+        }
+    }
+}
+
+impl Lifecycle for Node {
+    fn debug_state(&self) -> serde_json::Value {
+        let logs: HashMap<String, serde_json::Value> = self
+            .store
+            .keys()
+            .into_iter()
+            .map(|key| {
+                let bounds = serde_json::json!({
+                    "start": self.store.log_start(&key),
+                    "end": self.store.log_end(&key),
+                });
+                (key, bounds)
+            })
+            .collect();
+        serde_json::json!({
+            "node_id": self.node_id,
+            "cluster": self.cluster,
+            "storage": format!("{:?}", self.storage),
+            "logs": logs,
+            "committed_offsets": self.store.offsets(),
+            "proxied": self.proxied.len(),
+            "forwarded": self.forwarded.len(),
+            "replicating": self.replicating.len(),
+            "recovering": self.recovering.len(),
+            "failed": self.failed,
+            "rejoining": self.rejoining,
+            "fenced_commits": self.fenced_commits.len(),
+            "transactions": self.transactions.len(),
+            "committing": self.coordinator.voting_count(),
+            "deciding": self.coordinator.decision_count(),
+            "undecided": self.undecided.len(),
+            "pending_kv_requests": self.lin_kv.pending() + self.seq_kv.pending(),
+        })
+    }
+}
+
+/// Runs a node of the kafka workload.
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Where logs and offsets are kept: local, disk, or lin-kv.
+    #[arg(long, env = "KAFKA_STORAGE", default_value = "local")]
+    storage: Storage,
+    /// Directory the disk storage writes to.
+    #[arg(long, env = "KAFKA_DATA_DIR", default_value = "kafka-data")]
+    data_dir: PathBuf,
+    /// How much of each log is kept: all, committed, or a number of newest entries.
+    #[arg(long, env = "KAFKA_RETENTION", default_value = "all")]
+    retention: Retention,
+    /// Most entries a poll returns per key.
+    #[arg(long, env = "KAFKA_POLL_LIMIT")]
+    poll_limit: Option<u64>,
+    /// Followers each key's log is copied to, besides its owner.
+    #[arg(long, env = "KAFKA_REPLICAS", default_value_t = 0)]
+    replicas: usize,
+    /// Copies of a log that must have a send before it's acknowledged: leader, quorum, or all.
+    #[arg(long, env = "KAFKA_ACKS", default_value = "leader")]
+    acks: Acks,
+    /// How new entries get to followers: followers, from the owner to each, or chain.
+    #[arg(long, env = "KAFKA_REPLICATION", default_value = "followers")]
+    replication: ReplicationMode,
+    /// Leaves the per-key metadata out of poll_ok, for clients of the old layout.
+    #[arg(long, env = "KAFKA_LEGACY_POLL_OK")]
+    legacy_poll_ok: bool,
+}
+
+pub fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    cli.node.apply()?;
+    let stdin = transport::input();
+    let mut stdout = Output::stdout();
+    let mut node = build(cli, Arc::new(SystemClock))?;
+
+    runtime::run_lines(&mut node, stdin, &mut stdout, handle_line)?;
+    stdout.finish()
+}
+
+/// A node with the options of `cli`, unless they don't go together.
+fn build(cli: Cli, time: Arc<dyn Clock>) -> Result<Node, anyhow::Error> {
+    let storage = cli.storage;
+    if storage == Storage::LinKv && cli.retention != Retention::KeepAll {
+        anyhow::bail!("Retention is not supported with lin-kv storage");
+    }
+    if cli.replicas > 0 && storage == Storage::LinKv {
+        anyhow::bail!("Replication is not supported with lin-kv storage");
+    }
+    // A follower that lost its copy gets the log from its start, which retention may drop.
+    if cli.replicas > 0 && cli.retention != Retention::KeepAll {
+        anyhow::bail!("Retention is not supported with replication");
+    }
+    let poll_limit = match cli.poll_limit {
+        Some(poll_limit) => poll_limit,
+        None if storage == Storage::LinKv => KV_POLL_LIMIT,
+        None => POLL_LIMIT,
+    };
+    Ok(Node::new(cli, poll_limit, time))
+}
+
+/// Parses a line as a message of this workload, like the node does, and returns it as
+/// JSON, or the error reply to send instead. See `Message::parse_value`.
+pub fn parse_message(line: &[u8]) -> Result<Result<Value, Value>, anyhow::Error> {
+    Message::parse_value(line)
+}
+
+fn handle_line(node: &mut Node, line: &[u8], stdout: &mut impl Write) -> Result<(), anyhow::Error> {
+    if let Some(reply) = kv::parse_reply(line)? {
+        for response in node.process_kv_reply(reply, stdout)? {
+            response.send(stdout)?;
+        }
+        return Ok(());
+    }
+
+    let mut message =
+        match Message::parse(line).context("Failed to deserialize provided input to STDIN.")? {
+            Ok(message) => message,
+            Err(error_reply) => {
+                error_reply.send(stdout)?;
+                return Ok(());
+            }
+        };
+
+    let mut responses = node.resend_expired();
+    responses.extend(node.check_failed(&message));
+    responses.extend(match node.storage {
+        Storage::Local | Storage::Disk => node.process_received_message(&mut message),
+        Storage::LinKv => node.process_with_kv(&mut message, stdout)?,
+    });
+
+    for response in node.loop_back(responses) {
+        response.send(stdout)?;
+    }
+    Ok(())
+}
+
+/// A kafka node for a `sim::Network`, with the options of `cli`, timing requests to other
+/// nodes with `time`.
+pub fn simulated(cli: Cli, time: Arc<dyn Clock>) -> Result<Box<dyn SimNode>, anyhow::Error> {
+    Ok(sim::lines(build(cli, time)?, handle_line))
+}
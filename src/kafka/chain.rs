@@ -0,0 +1,144 @@
+use super::{Body, Message, Node, ReplicationMode};
+
+impl Node {
+    /// The owner of `key` and its followers that didn't fail, in the order entries pass
+    /// down the chain.
+    pub(super) fn chain_of(&self, key: &str) -> Vec<String> {
+        let owner = self.owner_of(key).to_string();
+        let followers = self.followers_of(&owner);
+        std::iter::once(owner)
+            .chain(followers)
+            .filter(|node| !self.failed.contains(node))
+            .collect()
+    }
+
+    /// The nodes of the chain of `key` that can take requests, as they aren't rejoining.
+    fn serving_chain_of(&self, key: &str) -> Vec<String> {
+        let mut chain = self.chain_of(key);
+        chain.retain(|node| !self.rejoining.contains(node));
+        chain
+    }
+
+    /// The node that takes the sends and commits of `key`: its owner, or the first node of
+    /// its chain that didn't fail and isn't rejoining.
+    pub(super) fn head_of(&self, key: &str) -> String {
+        match self.mode {
+            ReplicationMode::Followers => self.owner_of(key).to_string(),
+            ReplicationMode::Chain => self
+                .serving_chain_of(key)
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| self.owner_of(key).to_string()),
+        }
+    }
+
+    /// The node that answers the polls of `key`: its owner, or the last node of its chain
+    /// that didn't fail and isn't rejoining, which only has entries every node before it
+    /// has.
+    pub(super) fn tail_of(&self, key: &str) -> String {
+        match self.mode {
+            ReplicationMode::Followers => self.owner_of(key).to_string(),
+            ReplicationMode::Chain => self
+                .serving_chain_of(key)
+                .pop()
+                .unwrap_or_else(|| self.owner_of(key).to_string()),
+        }
+    }
+
+    /// Catches up on the logs of the chains this node is part of, as it was taken for
+    /// failed or restarted, and may lack entries the others acknowledged. The other nodes
+    /// hear it's rejoining, and pass it new entries from then on, but send it no requests,
+    /// while it recovers the older ones from them. Once every node answered, it's back.
+    pub(super) fn rejoin(&mut self) -> Vec<Message> {
+        log::info!("Catching up on the logs of chains before rejoining them");
+        self.rejoining.insert(self.node_id.clone());
+        let node_id = self.node_id.clone();
+        let mut sent = self.reconfigure(&node_id, false, true);
+        sent.extend(self.recover());
+        sent
+    }
+
+    /// Tells the node that sent `message` that it's taken for failed, so that it catches
+    /// up and rejoins the chains, unless the message is about its membership already.
+    pub(super) fn check_failed(&mut self, message: &Message) -> Vec<Message> {
+        if !self.failed.contains(&message.src) || matches!(message.body, Body::Reconfigure { .. }) {
+            return Vec::new();
+        }
+        vec![Message {
+            src: self.node_id.clone(),
+            dest: message.src.clone(),
+            body: Body::Reconfigure {
+                msg_id: self.incremented_msg_id(),
+                node: message.src.clone(),
+                failed: true,
+                rejoining: false,
+            },
+        }]
+    }
+
+    /// Leaves `node` out of chains, and tells the other nodes, unless it was left out
+    /// already.
+    pub(super) fn fail(&mut self, node: &str) -> Vec<Message> {
+        if !self.failed.insert(node.to_string()) {
+            return Vec::new();
+        }
+        self.rejoining.remove(node);
+        log::warn!("{node} failed, and is left out of chains");
+        self.reconfigure(node, true, false)
+    }
+
+    /// Tells every other node that `node` failed, is rejoining, or is back.
+    pub(super) fn reconfigure(
+        &mut self,
+        node: &str,
+        failed: bool,
+        rejoining: bool,
+    ) -> Vec<Message> {
+        self.peers()
+            .into_iter()
+            .map(|peer| Message {
+                src: self.node_id.clone(),
+                dest: peer,
+                body: Body::Reconfigure {
+                    msg_id: self.incremented_msg_id(),
+                    node: node.to_string(),
+                    failed,
+                    rejoining,
+                },
+            })
+            .collect()
+    }
+
+    /// Handles a change of the nodes serving in chains.
+    pub(super) fn process_chain(&mut self, message: &mut Message) -> Vec<Message> {
+        match &mut message.body {
+            Body::Reconfigure {
+                node,
+                failed,
+                rejoining,
+                ..
+            } => {
+                if *node == self.node_id {
+                    // Whoever took this node for failed may have acknowledged entries it
+                    // lacks, so it catches up again, even if it was doing so already.
+                    if *failed {
+                        return self.rejoin();
+                    }
+                } else if *failed {
+                    self.failed.insert(node.clone());
+                    self.rejoining.remove(node);
+                } else {
+                    self.failed.remove(node);
+                    if *rejoining {
+                        self.rejoining.insert(node.clone());
+                    } else if self.rejoining.remove(node) {
+                        log::info!("{node} caught up, and is back in chains");
+                    }
+                }
+                Vec::new()
+            }
+
+            _ => Vec::new(),
+        }
+    }
+}
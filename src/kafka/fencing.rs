@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use crate::runtime;
+use crate::{ErrorBody, ErrorCode};
+
+use super::{Body, Entries, Message, Node, OffsetCommit, PartialResult, RequestKind};
+
+/// A commit of offsets with an epoch to keys of several nodes, waiting for the heads of
+/// the keys to check their epochs.
+pub(super) struct FencedCommit {
+    client: String,
+    in_reply_to: u64,
+    offsets: HashMap<String, u64>,
+    group: Option<String>,
+    epoch: u64,
+    /// Heads that haven't answered their `check_epochs` yet.
+    checking: usize,
+}
+
+impl Node {
+    /// Commits `offsets` of the keys this node heads, and forwards the others to their
+    /// heads. A commit with an epoch older than one a key was committed with fails with
+    /// error 22, without committing any key: the heads of keys on other nodes check their
+    /// epochs first. Once they all did, the commit is `checked`, and a key committed with a
+    /// newer epoch in the meantime is left as it is, as if this commit came before.
+    fn commit_offsets(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        offsets: HashMap<String, u64>,
+        group: Option<String>,
+        epoch: Option<u64>,
+        checked: bool,
+    ) -> Vec<Message> {
+        let (local, remote) = self.split_by_owner(client, offsets, |key| self.head_of(key));
+        let group_name = group.as_deref().unwrap_or_default();
+        let error = |code, text| {
+            vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body: Body::Error(ErrorBody::new(in_reply_to, code, text)),
+            }]
+        };
+
+        if let (Some(epoch), false) = (epoch, checked) {
+            let keys = local.iter().map(|(key, _)| key.as_str());
+            if let Some(text) = self.stale_epoch(group_name, keys, epoch) {
+                return error(ErrorCode::PreconditionFailed, text);
+            }
+            if !remote.is_empty() {
+                let offsets = local.into_iter().chain(remote.values().flatten().cloned());
+                let commit = FencedCommit {
+                    client: client.to_string(),
+                    in_reply_to,
+                    offsets: offsets.collect(),
+                    group,
+                    epoch,
+                    checking: remote.len(),
+                };
+                return self.check_epochs(commit, remote);
+            }
+        }
+        let mut result = PartialResult::default();
+        let mut replications = Vec::new();
+        for (key, value) in local {
+            if checked && self.store.epoch(group_name, &key) > epoch {
+                continue;
+            }
+            let commit = OffsetCommit {
+                group: group_name.to_string(),
+                offset: value,
+                epoch,
+            };
+            if let Err(error) = self.apply_commit(&key, &commit) {
+                return vec![Message {
+                    src: self.node_id.clone(),
+                    dest: client.to_string(),
+                    body: Body::Error(ErrorBody::new(
+                        in_reply_to,
+                        ErrorCode::Crash,
+                        format!("Failed to commit {key}: {error}"),
+                    )),
+                }];
+            }
+            let end = self.store.log_end(&key);
+            replications.extend(self.replicate_to_followers(
+                &key,
+                end,
+                Some(commit),
+                None,
+                &mut result,
+            ));
+        }
+        let mut forwarded = Vec::new();
+        for (owner, entries) in remote {
+            let offsets = entries.into_iter().collect();
+            let group = group.clone();
+            forwarded.push(self.forward(owner, |msg_id| match (epoch, checked) {
+                (Some(epoch), true) => Body::CommitCheckedOffsets {
+                    msg_id,
+                    offsets,
+                    group,
+                    epoch,
+                },
+                _ => Body::CommitOffsets {
+                    msg_id,
+                    offsets,
+                    group,
+                    epoch,
+                },
+            }));
+        }
+
+        self.proxy_replicated(
+            client,
+            in_reply_to,
+            RequestKind::CommitOffsets,
+            result,
+            forwarded,
+            replications,
+        )
+    }
+
+    /// Commits an offset of `key` a group committed on this node, or on the node that
+    /// replicated or recovered it from, unless the key was committed with a newer epoch.
+    pub(super) fn apply_commit(
+        &mut self,
+        key: &str,
+        commit: &OffsetCommit,
+    ) -> Result<(), anyhow::Error> {
+        if self.store.epoch(&commit.group, key) > commit.epoch {
+            return Ok(());
+        }
+        self.store
+            .commit(&commit.group, key, commit.offset, commit.epoch)?;
+        self.store.enforce(key, self.retention)
+    }
+
+    /// Why `group` can't commit `keys` with `epoch`, if one of them was committed with a
+    /// newer epoch.
+    fn stale_epoch<'a>(
+        &self,
+        group: &str,
+        keys: impl IntoIterator<Item = &'a str>,
+        epoch: u64,
+    ) -> Option<String> {
+        keys.into_iter()
+            .find_map(|key| match self.store.epoch(group, key) {
+                Some(current) if current > epoch => Some(format!(
+                    "Epoch {epoch} is older than {current} for {key} in group {group:?}"
+                )),
+                _ => None,
+            })
+    }
+
+    /// Asks the heads of the keys of `remote` to check their epochs for `commit`.
+    fn check_epochs(
+        &mut self,
+        commit: FencedCommit,
+        remote: HashMap<String, Entries<u64>>,
+    ) -> Vec<Message> {
+        let id = self.incremented_msg_id();
+        let mut sent = Vec::new();
+        for (owner, entries) in remote {
+            let (msg_id, message) = self.forward(owner, |msg_id| Body::CheckEpochs {
+                msg_id,
+                keys: entries.into_iter().map(|(key, _)| key).collect(),
+                group: commit.group.clone(),
+                epoch: commit.epoch,
+            });
+            self.epoch_checks.insert(msg_id, id);
+            sent.push(message);
+        }
+        self.fenced_commits.insert(id, commit);
+        sent
+    }
+
+    /// Counts the answer of a head to a `check_epochs`, and commits the offsets once every
+    /// head answered. An error fails the commit with it.
+    fn epoch_checked(&mut self, in_reply_to: u64, error: Option<&ErrorBody>) -> Vec<Message> {
+        let Some(id) = self.epoch_checks.complete(in_reply_to) else {
+            return Vec::new();
+        };
+        if let Some(error) = error {
+            let Some(commit) = self.fenced_commits.complete(id) else {
+                return Vec::new();
+            };
+            return vec![Message {
+                src: self.node_id.clone(),
+                dest: commit.client,
+                body: Body::Error(ErrorBody::new(
+                    commit.in_reply_to,
+                    error.code,
+                    error.text.clone(),
+                )),
+            }];
+        }
+        let Some(commit) = self.fenced_commits.get_mut(id) else {
+            return Vec::new();
+        };
+        commit.checking -= 1;
+        if commit.checking > 0 {
+            return Vec::new();
+        }
+        let Some(commit) = self.fenced_commits.complete(id) else {
+            return Vec::new();
+        };
+        self.commit_offsets(
+            &commit.client,
+            commit.in_reply_to,
+            commit.offsets,
+            commit.group,
+            Some(commit.epoch),
+            true,
+        )
+    }
+
+    /// Handles offset commits, and the epoch checks of keys other nodes head.
+    pub(super) fn process_commit(&mut self, message: &mut Message) -> Vec<Message> {
+        let build_message_from = |body: Body| -> Vec<Message> {
+            vec![Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }]
+        };
+
+        match &mut message.body {
+            Body::CommitOffsets {
+                msg_id,
+                offsets,
+                group,
+                epoch,
+            } => {
+                let offsets = std::mem::take(offsets);
+                let group = group.clone();
+                self.commit_offsets(&message.src, *msg_id, offsets, group, *epoch, false)
+            }
+
+            Body::CommitCheckedOffsets { msg_id, .. } if !runtime::is_node_id(&message.src) => {
+                build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Checked commits are only taken from nodes",
+                )))
+            }
+
+            Body::CommitCheckedOffsets {
+                msg_id,
+                offsets,
+                group,
+                epoch,
+            } => {
+                let offsets = std::mem::take(offsets);
+                let group = group.clone();
+                self.commit_offsets(&message.src, *msg_id, offsets, group, Some(*epoch), true)
+            }
+
+            Body::CheckEpochs {
+                msg_id,
+                keys,
+                group,
+                epoch,
+            } => {
+                let group = group.as_deref().unwrap_or_default();
+                match self.stale_epoch(group, keys.iter().map(String::as_str), *epoch) {
+                    Some(text) => build_message_from(Body::Error(ErrorBody::new(
+                        *msg_id,
+                        ErrorCode::PreconditionFailed,
+                        text,
+                    ))),
+                    None => build_message_from(Body::CheckEpochsOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: *msg_id,
+                    }),
+                }
+            }
+
+            Body::CheckEpochsOk { in_reply_to, .. } => self.epoch_checked(*in_reply_to, None),
+
+            Body::CommitOffsetsOk { in_reply_to, .. } => {
+                self.complete_forwarded(*in_reply_to, |_| {})
+            }
+
+            Body::Error(error_body)
+                if self.epoch_checks.get_mut(error_body.in_reply_to).is_some() =>
+            {
+                self.epoch_checked(error_body.in_reply_to, Some(error_body))
+            }
+
+            _ => Vec::new(),
+        }
+    }
+}
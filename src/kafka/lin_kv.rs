@@ -0,0 +1,513 @@
+use std::io::Write;
+
+use crate::kv::{KvBody, KvError, KvService};
+use crate::{ErrorBody, ErrorCode};
+
+use super::{Body, Entries, Message, Node, PartialResult, Producer, ProxiedRequest, RequestKind};
+
+/// The step a KV request belongs to; `id` identifies the client request being served.
+pub(super) enum KvStep {
+    ReadNextOffset {
+        id: u64,
+        key: String,
+        msgs: Vec<u64>,
+    },
+    AllocateOffsets {
+        id: u64,
+        key: String,
+        msgs: Vec<u64>,
+        offset: u64,
+    },
+    WriteEntry {
+        id: u64,
+    },
+    ReadEndOffset {
+        id: u64,
+        key: String,
+        from: u64,
+        limit: u64,
+    },
+    ReadEntry {
+        id: u64,
+        key: String,
+        offset: u64,
+    },
+    WriteCommit {
+        id: u64,
+    },
+    ReadCommit {
+        id: u64,
+        key: String,
+    },
+    /// Reads the offset the polling group committed of `key`, for the metadata of a poll.
+    ReadPolledCommit {
+        id: u64,
+        key: String,
+    },
+}
+
+impl KvStep {
+    fn id(&self) -> u64 {
+        match self {
+            KvStep::ReadNextOffset { id, .. }
+            | KvStep::AllocateOffsets { id, .. }
+            | KvStep::WriteEntry { id }
+            | KvStep::ReadEndOffset { id, .. }
+            | KvStep::ReadEntry { id, .. }
+            | KvStep::WriteCommit { id }
+            | KvStep::ReadCommit { id, .. }
+            | KvStep::ReadPolledCommit { id, .. } => *id,
+        }
+    }
+}
+
+pub(super) fn next_offset_key(key: &str) -> String {
+    format!("next_offset/{key}")
+}
+
+pub(super) fn entry_key(key: &str, offset: u64) -> String {
+    format!("entry/{key}/{offset}")
+}
+
+/// Commits without a group keep the key layout they had before consumer groups existed.
+pub(super) fn committed_key(group: Option<&str>, key: &str) -> String {
+    match group {
+        Some(group) => format!("group/{group}/committed/{key}"),
+        None => format!("committed/{key}"),
+    }
+}
+
+impl Node {
+    /// Starts serving a client request made of `parts` KV operations.
+    fn start_request(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        kind: RequestKind,
+        parts: usize,
+        result: PartialResult,
+    ) -> (u64, Vec<Message>) {
+        let id = self.incremented_msg_id();
+        let proxied = ProxiedRequest {
+            client: client.to_string(),
+            in_reply_to,
+            kind,
+            waiting: parts,
+            result,
+        };
+
+        if parts == 0 {
+            return (id, vec![self.reply_to_client(proxied)]);
+        }
+
+        self.proxied.insert(id, proxied);
+        (id, Vec::new())
+    }
+
+    fn send_with_kv<W: Write>(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        kind: RequestKind,
+        entries: Entries<Vec<u64>>,
+        output: &mut W,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        if let Some(rejection) = self.reject_empty_send(client, in_reply_to, &entries) {
+            return Ok(vec![rejection]);
+        }
+
+        let producer = Producer {
+            client: client.to_string(),
+            msg_id: in_reply_to,
+        };
+        let mut result = PartialResult::default();
+        let mut unsent = Vec::new();
+
+        for (key, msgs) in entries {
+            match self.producers.get(&key, &producer) {
+                Some(range) => {
+                    result.ranges.insert(key, range);
+                }
+                None => unsent.push((key, msgs)),
+            }
+        }
+
+        let (id, responses) = self.start_request(client, in_reply_to, kind, unsent.len(), result);
+        for (key, msgs) in unsent {
+            self.read_next_offset(id, key, msgs, output)?;
+        }
+        Ok(responses)
+    }
+
+    fn read_next_offset<W: Write>(
+        &mut self,
+        id: u64,
+        key: String,
+        msgs: Vec<u64>,
+        output: &mut W,
+    ) -> Result<(), anyhow::Error> {
+        let msg_id = self.incremented_msg_id();
+        let kv_key = next_offset_key(&key);
+        self.lin_kv
+            .read(
+                &self.node_id,
+                msg_id,
+                kv_key,
+                KvStep::ReadNextOffset { id, key, msgs },
+            )?
+            .send(output)
+    }
+
+    pub(super) fn process_with_kv<W: Write>(
+        &mut self,
+        message: &mut Message,
+        output: &mut W,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        match &mut message.body {
+            Body::Send { msg_id, key, msg } => {
+                let entries = vec![(key.clone(), msg.clone().into_vec())];
+                self.send_with_kv(&message.src, *msg_id, RequestKind::Send, entries, output)
+            }
+
+            Body::SendBatch { msg_id, msgs, .. } => {
+                let entries = msgs.drain().collect();
+                self.send_with_kv(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::SendBatch,
+                    entries,
+                    output,
+                )
+            }
+
+            Body::Poll {
+                msg_id,
+                offsets,
+                max_msgs,
+                group,
+            } => {
+                let limit = self.poll_limit_for(*max_msgs);
+                let result = PartialResult {
+                    poll_from: offsets.clone(),
+                    ..PartialResult::default()
+                };
+                // Every key takes reading its end offset and the offset its group committed.
+                let (id, responses) = self.start_request(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::Poll,
+                    2 * offsets.len(),
+                    result,
+                );
+
+                for (key, from) in offsets.drain() {
+                    let msg_id = self.incremented_msg_id();
+                    let kv_key = committed_key(group.as_deref(), &key);
+                    self.lin_kv
+                        .read(
+                            &self.node_id,
+                            msg_id,
+                            kv_key,
+                            KvStep::ReadPolledCommit {
+                                id,
+                                key: key.clone(),
+                            },
+                        )?
+                        .send(output)?;
+
+                    let msg_id = self.incremented_msg_id();
+                    let kv_key = next_offset_key(&key);
+                    self.lin_kv
+                        .read(
+                            &self.node_id,
+                            msg_id,
+                            kv_key,
+                            KvStep::ReadEndOffset {
+                                id,
+                                key,
+                                from,
+                                limit,
+                            },
+                        )?
+                        .send(output)?;
+                }
+                Ok(responses)
+            }
+
+            Body::CommitOffsets {
+                msg_id,
+                offsets,
+                group,
+                epoch,
+                ..
+            } => {
+                if epoch.is_some() {
+                    return Ok(vec![Message {
+                        src: self.node_id.clone(),
+                        dest: message.src.clone(),
+                        body: Body::Error(ErrorBody::new(
+                            *msg_id,
+                            ErrorCode::NotSupported,
+                            "Commit epochs are not supported with lin-kv storage",
+                        )),
+                    }]);
+                }
+                let (id, responses) = self.start_request(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::CommitOffsets,
+                    offsets.len(),
+                    PartialResult::default(),
+                );
+
+                for (key, offset) in offsets.drain() {
+                    let msg_id = self.incremented_msg_id();
+                    self.lin_kv
+                        .write(
+                            &self.node_id,
+                            msg_id,
+                            committed_key(group.as_deref(), &key),
+                            offset,
+                            KvStep::WriteCommit { id },
+                        )?
+                        .send(output)?;
+                }
+                Ok(responses)
+            }
+
+            Body::ListCommittedOffsets {
+                msg_id,
+                keys,
+                group,
+            } => {
+                let (id, responses) = self.start_request(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::ListCommittedOffsets,
+                    keys.len(),
+                    PartialResult::default(),
+                );
+
+                for key in keys.drain(..) {
+                    let msg_id = self.incremented_msg_id();
+                    let kv_key = committed_key(group.as_deref(), &key);
+                    self.lin_kv
+                        .read(
+                            &self.node_id,
+                            msg_id,
+                            kv_key,
+                            KvStep::ReadCommit { id, key },
+                        )?
+                        .send(output)?;
+                }
+                Ok(responses)
+            }
+
+            Body::ListOffsets { msg_id, .. } => Ok(vec![Message {
+                src: self.node_id.clone(),
+                dest: message.src.clone(),
+                body: Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Entries have no timestamps with lin-kv storage",
+                )),
+            }]),
+
+            Body::Begin { msg_id } => Ok(vec![Message {
+                src: self.node_id.clone(),
+                dest: message.src.clone(),
+                body: Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::NotSupported,
+                    "Transactions are not supported with lin-kv storage",
+                )),
+            }]),
+
+            _ => Ok(self.process_received_message(message)),
+        }
+    }
+
+    pub(super) fn process_kv_reply<W: Write>(
+        &mut self,
+        reply: crate::Message<KvBody>,
+        output: &mut W,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let client = match KvService::from_name(&reply.src) {
+            Some(KvService::LinKv) => &mut self.lin_kv,
+            Some(KvService::SeqKv) => &mut self.seq_kv,
+            _ => return Ok(Vec::new()),
+        };
+        let Some((step, result)) = client.handle_reply(reply) else {
+            return Ok(Vec::new());
+        };
+
+        match (step, result) {
+            (KvStep::ReadNextOffset { id, key, msgs }, result) => {
+                let offset = match result {
+                    Ok(reply) => reply.value()?,
+                    Err(KvError::KeyDoesNotExist(_)) => 0,
+                    Err(error) => return Ok(self.fail_request(id, error)),
+                };
+
+                let msg_id = self.incremented_msg_id();
+                let kv_key = next_offset_key(&key);
+                self.lin_kv
+                    .cas(
+                        &self.node_id,
+                        msg_id,
+                        kv_key,
+                        offset,
+                        offset + msgs.len() as u64,
+                        true,
+                        KvStep::AllocateOffsets {
+                            id,
+                            key,
+                            msgs,
+                            offset,
+                        },
+                    )?
+                    .send(output)?;
+                Ok(Vec::new())
+            }
+
+            (
+                KvStep::AllocateOffsets {
+                    id,
+                    key,
+                    msgs,
+                    offset,
+                },
+                Ok(_),
+            ) => {
+                if let Some(proxied) = self.proxied.get_mut(&id) {
+                    let range = [offset, offset + msgs.len() as u64 - 1];
+                    let producer = Producer {
+                        client: proxied.client.clone(),
+                        msg_id: proxied.in_reply_to,
+                    };
+                    self.producers.record(&key, &producer, range);
+                    proxied.result.ranges.insert(key.clone(), range);
+                    proxied.waiting += msgs.len() - 1;
+                }
+                for (msg, offset) in msgs.into_iter().zip(offset..) {
+                    let msg_id = self.incremented_msg_id();
+                    self.seq_kv
+                        .write(
+                            &self.node_id,
+                            msg_id,
+                            entry_key(&key, offset),
+                            msg,
+                            KvStep::WriteEntry { id },
+                        )?
+                        .send(output)?;
+                }
+                Ok(Vec::new())
+            }
+
+            (
+                KvStep::AllocateOffsets { id, key, msgs, .. },
+                Err(KvError::PreconditionFailed(_)),
+            ) => {
+                self.read_next_offset(id, key, msgs, output)?;
+                Ok(Vec::new())
+            }
+
+            (KvStep::WriteEntry { id }, Ok(_)) => Ok(self.complete_part(id, |_| {})),
+
+            (
+                KvStep::ReadEndOffset {
+                    id,
+                    key,
+                    from,
+                    limit,
+                },
+                Ok(reply),
+            ) => {
+                let end: u64 = reply.value()?;
+                let to = end.min(from.saturating_add(limit));
+
+                if let Some(proxied) = self.proxied.get_mut(&id) {
+                    proxied.waiting += to.saturating_sub(from) as usize;
+                    proxied
+                        .result
+                        .metadata
+                        .entry(key.clone())
+                        .or_default()
+                        .log_end = end;
+                }
+                for offset in from..to {
+                    let msg_id = self.incremented_msg_id();
+                    self.seq_kv
+                        .read(
+                            &self.node_id,
+                            msg_id,
+                            entry_key(&key, offset),
+                            KvStep::ReadEntry {
+                                id,
+                                key: key.clone(),
+                                offset,
+                            },
+                        )?
+                        .send(output)?;
+                }
+
+                Ok(self.complete_part(id, |_| {}))
+            }
+
+            (KvStep::ReadEntry { id, key, offset }, Ok(reply)) => {
+                let msg: u64 = reply.value()?;
+                Ok(self.complete_part(id, |result| {
+                    result.msgs.entry(key).or_default().push([offset, msg])
+                }))
+            }
+
+            (KvStep::WriteCommit { id }, Ok(_)) => Ok(self.complete_part(id, |_| {})),
+
+            (KvStep::ReadCommit { id, key }, Ok(reply)) => {
+                let offset: u64 = reply.value()?;
+                Ok(self.complete_part(id, |result| {
+                    result.offsets.insert(key, offset);
+                }))
+            }
+
+            (KvStep::ReadPolledCommit { id, key }, Ok(reply)) => {
+                let offset: u64 = reply.value()?;
+                Ok(self.complete_part(id, |result| {
+                    result.metadata.entry(key).or_default().committed = Some(offset);
+                }))
+            }
+
+            // Logs are never truncated with lin-kv storage, so a key nothing was sent to yet
+            // starts and ends at 0.
+            (
+                KvStep::ReadEndOffset { id, key, .. } | KvStep::ReadPolledCommit { id, key },
+                Err(KvError::KeyDoesNotExist(_)),
+            ) => Ok(self.complete_part(id, |result| {
+                result.metadata.entry(key).or_default();
+            })),
+
+            (
+                KvStep::ReadEntry { id, .. } | KvStep::ReadCommit { id, .. },
+                Err(KvError::KeyDoesNotExist(_)),
+            ) => Ok(self.complete_part(id, |_| {})),
+
+            (step, Err(error)) => Ok(self.fail_request(step.id(), error)),
+        }
+    }
+
+    fn fail_request(&mut self, id: u64, error: KvError) -> Vec<Message> {
+        let Some(proxied) = self.proxied.remove(&id) else {
+            return Vec::new();
+        };
+
+        vec![Message {
+            src: self.node_id.clone(),
+            dest: proxied.client,
+            body: Body::Error(ErrorBody::new(
+                proxied.in_reply_to,
+                ErrorCode::TemporarilyUnavailable,
+                format!("Storage failed: {error}"),
+            )),
+        }]
+    }
+}
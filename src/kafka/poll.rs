@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::HlcTimestamp;
+
+use super::{Body, Message, Node, PartialResult, RequestKind};
+
+/// Where the log of a key starts and ends, and how far the polling group consumed it, so that
+/// a client sees truncation and lag without asking for them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct LogMetadata {
+    pub(super) log_start: u64,
+    pub(super) log_end: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) committed: Option<u64>,
+}
+
+impl Node {
+    pub(super) fn poll_limit_for(&self, max_msgs: Option<u64>) -> u64 {
+        max_msgs.map_or(self.poll_limit, |max_msgs| max_msgs.min(self.poll_limit))
+    }
+
+    /// Handles polls, and the offsets consumers list, with the replies of the nodes they
+    /// were forwarded to.
+    pub(super) fn process_poll(&mut self, message: &mut Message) -> Vec<Message> {
+        match &mut message.body {
+            Body::Poll {
+                msg_id,
+                offsets,
+                max_msgs,
+                group,
+            } => {
+                let limit = self.poll_limit_for(*max_msgs);
+                let (local, remote) =
+                    self.split_by_owner(&message.src, offsets.drain(), |key| self.tail_of(key));
+                let mut result = PartialResult::default();
+                let mut forwarded = Vec::new();
+
+                for (key, offset_from) in local {
+                    let metadata = LogMetadata {
+                        log_start: self.store.log_start(&key),
+                        log_end: self.store.log_end(&key),
+                        committed: self
+                            .store
+                            .committed(group.as_deref().unwrap_or_default(), &key),
+                    };
+                    result.metadata.insert(key.clone(), metadata);
+                    if let Some(new_messages) = self.store.read_committed(&key, offset_from, limit)
+                    {
+                        let log_start = self.store.log_start(&key);
+                        if offset_from < log_start {
+                            result.low_watermarks.insert(key.clone(), log_start);
+                        }
+                        result.msgs.insert(key, new_messages);
+                    }
+                }
+                for (owner, entries) in remote {
+                    forwarded.push(self.forward(owner, |msg_id| Body::Poll {
+                        msg_id,
+                        offsets: entries.into_iter().collect(),
+                        max_msgs: Some(limit),
+                        group: group.clone(),
+                    }));
+                }
+
+                self.proxy(&message.src, *msg_id, RequestKind::Poll, result, forwarded)
+            }
+
+            Body::PollOk {
+                in_reply_to,
+                msgs,
+                low_watermarks,
+                metadata,
+                ..
+            } => self.complete_forwarded(*in_reply_to, |result| {
+                result.msgs.extend(msgs.drain());
+                result.low_watermarks.extend(low_watermarks.drain());
+                result.metadata.extend(metadata.drain());
+            }),
+
+            Body::ListCommittedOffsets {
+                msg_id,
+                keys,
+                group,
+            } => {
+                let (local, remote) =
+                    self.split_by_owner(&message.src, keys.drain(..).map(|key| (key, ())), |key| {
+                        self.head_of(key)
+                    });
+                let mut result = PartialResult::default();
+                let mut forwarded = Vec::new();
+
+                for (key, _) in local {
+                    let committed = self
+                        .store
+                        .committed(group.as_deref().unwrap_or_default(), &key);
+                    if let Some(offset) = committed {
+                        result.offsets.insert(key, offset);
+                    }
+                }
+                for (owner, entries) in remote {
+                    forwarded.push(self.forward(owner, |msg_id| Body::ListCommittedOffsets {
+                        msg_id,
+                        keys: entries.into_iter().map(|(key, _)| key).collect(),
+                        group: group.clone(),
+                    }));
+                }
+
+                self.proxy(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::ListCommittedOffsets,
+                    result,
+                    forwarded,
+                )
+            }
+
+            Body::ListOffsets { msg_id, timestamps } => {
+                let (local, remote) =
+                    self.split_by_owner(&message.src, timestamps.drain(), |key| self.tail_of(key));
+                let mut result = PartialResult::default();
+                let mut forwarded = Vec::new();
+
+                for (key, wall) in local {
+                    let offset = self.store.seek(&key, HlcTimestamp { wall, logical: 0 });
+                    result.offsets.insert(key, offset);
+                }
+                for (owner, entries) in remote {
+                    forwarded.push(self.forward(owner, |msg_id| Body::ListOffsets {
+                        msg_id,
+                        timestamps: entries.into_iter().collect(),
+                    }));
+                }
+
+                self.proxy(
+                    &message.src,
+                    *msg_id,
+                    RequestKind::ListOffsets,
+                    result,
+                    forwarded,
+                )
+            }
+
+            Body::ListCommittedOffsetsOk {
+                in_reply_to,
+                offsets,
+                ..
+            }
+            | Body::ListOffsetsOk {
+                in_reply_to,
+                offsets,
+                ..
+            } => self.complete_forwarded(*in_reply_to, |result| {
+                result.offsets.extend(offsets.drain())
+            }),
+
+            _ => Vec::new(),
+        }
+    }
+}
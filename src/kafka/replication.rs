@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::hlc::HlcTimestamp;
+use crate::log_store::TxnMark;
+use crate::{ErrorBody, ErrorCode};
+
+use super::{
+    Acks, Body, Message, Node, OffsetCommit, PartialResult, ProxiedRequest, ReplicationMode,
+    RequestKind, REPLICATE_TIMEOUT,
+};
+
+/// Entries of a key sent to a follower, from offset `from` up to `end`, with an offset of
+/// the key committed or the mark of a transaction, if any, and the client request that may
+/// be waiting for the follower to have them.
+pub(super) struct Replication {
+    request: Option<u64>,
+    /// In a chain, the node that sent this node the entries, and the msg_id of its
+    /// `replicate`, answered once the rest of the chain has them.
+    relay: Option<(String, u64)>,
+    follower: String,
+    key: String,
+    from: u64,
+    end: u64,
+    commit: Option<OffsetCommit>,
+    txn: Option<TxnMark>,
+}
+
+impl Node {
+    /// The nodes that keep a copy of the logs `owner` owns: the `replicas` nodes after it in
+    /// the sorted cluster, wrapping around.
+    pub(super) fn followers_of(&self, owner: &str) -> Vec<String> {
+        let Some(index) = self.cluster.iter().position(|id| id == owner) else {
+            return Vec::new();
+        };
+        let count = self.replicas.min(self.cluster.len() - 1);
+        (1..=count)
+            .map(|i| self.cluster[(index + i) % self.cluster.len()].clone())
+            .collect()
+    }
+
+    /// Whether `node` keeps a copy of the log of `key`: as its owner, or with chain
+    /// replication as one of its followers too, which may head or tail its chain.
+    fn keeps_log_of(&self, key: &str, node: &str) -> bool {
+        let owner = self.owner_of(key);
+        owner == node
+            || (self.mode == ReplicationMode::Chain
+                && self
+                    .followers_of(owner)
+                    .iter()
+                    .any(|follower| follower == node))
+    }
+
+    /// The nodes this node sends the new entries of `key` to: every follower of its owner,
+    /// or the next node of its chain.
+    fn replicas_of(&self, key: &str) -> Vec<String> {
+        match self.mode {
+            ReplicationMode::Followers => self.followers_of(self.owner_of(key)),
+            ReplicationMode::Chain => {
+                let chain = self.chain_of(key);
+                let next = chain
+                    .iter()
+                    .position(|node| *node == self.node_id)
+                    .map(|i| i + 1);
+                next.and_then(|next| chain.get(next).cloned())
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+
+    /// How many of `followers` must have a send before it's acknowledged. A quorum is a
+    /// majority of the followers and the owner, which always has it. In a chain, the next
+    /// node only answers once the tail has the send.
+    fn acks_needed(&self, followers: usize) -> usize {
+        match (self.mode, self.acks) {
+            (ReplicationMode::Chain, _) => followers,
+            (_, Acks::Leader) => 0,
+            (_, Acks::Quorum) => followers.div_ceil(2),
+            (_, Acks::All) => followers,
+        }
+    }
+
+    /// Sends a follower the entries of `replication`, and keeps it until the follower
+    /// answers.
+    pub(super) fn replicate(&mut self, replication: Replication) -> Message {
+        let msg_id = self.incremented_msg_id();
+        let count = replication.end - replication.from;
+        let msgs = self
+            .store
+            .read(&replication.key, replication.from, count)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|[_, msg]| msg)
+            .collect();
+        let timestamps = self
+            .store
+            .timestamps(&replication.key, replication.from, count);
+        let message = Message {
+            src: self.node_id.clone(),
+            dest: replication.follower.clone(),
+            body: Body::Replicate {
+                msg_id,
+                key: replication.key.clone(),
+                offset: replication.from,
+                msgs,
+                timestamps,
+                commit: replication.commit.clone(),
+                txn: replication.txn.clone(),
+            },
+        };
+        let timeout = self.replicate_timeout(&replication);
+        self.replicating.insert(msg_id, (replication, timeout));
+        message
+    }
+
+    /// The replications that copy the entries of `key` from `from` up to the end of its log,
+    /// with `commit` or `txn` if set, to the followers this node sends them to. The request
+    /// they're for waits for as many of them as the ack level asks for.
+    pub(super) fn replicate_to_followers(
+        &self,
+        key: &str,
+        from: u64,
+        commit: Option<OffsetCommit>,
+        txn: Option<TxnMark>,
+        result: &mut PartialResult,
+    ) -> Vec<Replication> {
+        let followers = self.replicas_of(key);
+        let needed = self.acks_needed(followers.len());
+        if needed > 0 {
+            result.acks.insert(key.to_string(), needed);
+        }
+        let end = self.store.log_end(key);
+        followers
+            .into_iter()
+            .map(|follower| Replication {
+                request: None,
+                relay: None,
+                follower,
+                key: key.to_string(),
+                from,
+                end,
+                commit: commit.clone(),
+                txn: txn.clone(),
+            })
+            .collect()
+    }
+
+    /// How long the entries of `replication` wait for the follower. In a chain, the
+    /// follower only answers once every node after it did.
+    fn replicate_timeout(&self, replication: &Replication) -> Duration {
+        if self.mode == ReplicationMode::Followers {
+            return REPLICATE_TIMEOUT;
+        }
+        let chain = self.chain_of(&replication.key);
+        let waiting = match chain.iter().position(|node| *node == replication.follower) {
+            Some(index) => chain.len() - index,
+            None => 1,
+        };
+        REPLICATE_TIMEOUT * waiting as u32
+    }
+
+    /// Counts the answer of a follower towards the send waiting for it, or sends the
+    /// follower what its log lacks, such as after it restarted and lost its copy.
+    fn replicated(&mut self, in_reply_to: u64, log_end: u64) -> Vec<Message> {
+        let Some((mut replication, _)) = self.replicating.complete(in_reply_to) else {
+            return Vec::new();
+        };
+        if log_end < replication.end {
+            replication.from = log_end;
+            return vec![self.replicate(replication)];
+        }
+        self.replication_done(replication)
+    }
+
+    /// Answers the node that passed the entries of `replication` on, and counts them
+    /// towards the send waiting for them.
+    fn replication_done(&mut self, replication: Replication) -> Vec<Message> {
+        if let Some((node, in_reply_to)) = replication.relay {
+            return vec![Message {
+                src: self.node_id.clone(),
+                dest: node,
+                body: Body::ReplicateOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to,
+                    log_end: self.store.log_end(&replication.key),
+                },
+            }];
+        }
+        let Some(id) = replication.request else {
+            return Vec::new();
+        };
+        let Some(needed) = self
+            .proxied
+            .get_mut(&id)
+            .and_then(|proxied| proxied.result.acks.get_mut(&replication.key))
+        else {
+            return Vec::new();
+        };
+        *needed -= 1;
+        if *needed > 0 {
+            return Vec::new();
+        }
+        self.complete_part(id, |result| {
+            result.acks.remove(&replication.key);
+        })
+    }
+
+    /// Appends the entries of `msgs` that the log of `key` doesn't have yet, the first of
+    /// which has `offset`, and returns where the log ends. Entries past a gap are left out,
+    /// as they would get other offsets than the owner gave them. Entries keep the
+    /// `timestamps` the owner appended them at; those without one, from an owner that
+    /// didn't send them, are timestamped now.
+    fn append_from(
+        &mut self,
+        key: &str,
+        offset: u64,
+        msgs: &[u64],
+        timestamps: &[HlcTimestamp],
+    ) -> Result<u64, anyhow::Error> {
+        for (i, (msg, at)) in msgs.iter().zip(offset..).enumerate() {
+            if at == self.store.log_end(key) {
+                let timestamp = match timestamps.get(i) {
+                    Some(timestamp) => {
+                        self.hlc.observe(*timestamp);
+                        *timestamp
+                    }
+                    None => self.hlc.now(),
+                };
+                self.store.append(key, *msg, timestamp)?;
+            }
+        }
+        Ok(self.store.log_end(key))
+    }
+
+    /// Asks the followers of this node for their copies of its logs, which a restarted node
+    /// lost unless they were on disk. Until every follower answered, requests fail, so that
+    /// no send gets an offset an entry it lost had. With chain replication, every other node
+    /// is asked for the logs of every chain this node is part of.
+    pub(super) fn recover(&mut self) -> Vec<Message> {
+        let nodes = match self.mode {
+            ReplicationMode::Followers => self.followers_of(&self.node_id),
+            ReplicationMode::Chain => self.peers(),
+        };
+        nodes
+            .into_iter()
+            .map(|node| self.recover_from(node))
+            .collect()
+    }
+
+    fn recover_from(&mut self, follower: String) -> Message {
+        let msg_id = self.incremented_msg_id();
+        self.recovering.insert(msg_id, follower.clone());
+        Message {
+            src: self.node_id.clone(),
+            dest: follower,
+            body: Body::Recover { msg_id },
+        }
+    }
+
+    /// Sends again the entries and recovery requests followers didn't answer in time, as
+    /// they may have been lost. In a chain, the follower is taken for failed, and the
+    /// entries go to the node after it, if any.
+    pub(super) fn resend_replications(&mut self) -> Vec<Message> {
+        let mut sent = Vec::new();
+        let expired = self.replicating.expire_with(|(_, timeout)| *timeout);
+        for (_, (replication, _)) in expired {
+            if self.mode == ReplicationMode::Followers {
+                sent.push(self.replicate(replication));
+                continue;
+            }
+            sent.extend(self.fail(&replication.follower));
+            match self.replicas_of(&replication.key).pop() {
+                Some(next) => sent.push(self.replicate(Replication {
+                    follower: next,
+                    ..replication
+                })),
+                None => sent.extend(self.replication_done(replication)),
+            }
+        }
+        for (_, follower) in self.recovering.expire(REPLICATE_TIMEOUT) {
+            sent.push(self.recover_from(follower));
+        }
+        sent
+    }
+
+    /// Like `proxy`, also copying appended entries to followers, and replying once as many
+    /// of them have the entries of every key as the ack level asks for.
+    pub(super) fn proxy_replicated(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        kind: RequestKind,
+        result: PartialResult,
+        forwarded: Vec<(u64, Message)>,
+        replications: Vec<Replication>,
+    ) -> Vec<Message> {
+        let proxied = ProxiedRequest {
+            client: client.to_string(),
+            in_reply_to,
+            kind,
+            waiting: forwarded.len() + result.acks.len(),
+            result,
+        };
+
+        if proxied.waiting == 0 {
+            let mut sent = vec![self.reply_to_client(proxied)];
+            for replication in replications {
+                sent.push(self.replicate(replication));
+            }
+            return sent;
+        }
+
+        let id = self.incremented_msg_id();
+        for (msg_id, _) in &forwarded {
+            self.forwarded.insert(*msg_id, id);
+        }
+        self.proxied.insert(id, proxied);
+
+        let mut sent: Vec<Message> = forwarded.into_iter().map(|(_, message)| message).collect();
+        for replication in replications {
+            sent.push(self.replicate(Replication {
+                request: Some(id),
+                ..replication
+            }));
+        }
+        sent
+    }
+
+    /// The offsets every group committed of the keys that `node` keeps the logs of.
+    fn commits_kept_by(&self, node: &str) -> HashMap<String, Vec<OffsetCommit>> {
+        let mut commits: HashMap<String, Vec<OffsetCommit>> = HashMap::new();
+        for (group, offsets) in self.store.offsets() {
+            for (key, offset) in offsets {
+                if !self.keeps_log_of(&key, node) {
+                    continue;
+                }
+                let epoch = self.store.epoch(&group, &key);
+                commits.entry(key).or_default().push(OffsetCommit {
+                    group: group.clone(),
+                    offset,
+                    epoch,
+                });
+            }
+        }
+        commits
+    }
+
+    /// Handles the entries and recovery requests exchanged with followers.
+    pub(super) fn process_replication(&mut self, message: &mut Message) -> Vec<Message> {
+        let build_message_from = |body: Body| -> Vec<Message> {
+            vec![Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }]
+        };
+
+        match &mut message.body {
+            Body::Replicate {
+                msg_id,
+                key,
+                offset,
+                msgs,
+                timestamps,
+                commit,
+                txn,
+            } => match self
+                .append_from(key, *offset, msgs, timestamps)
+                .and_then(|log_end| {
+                    if let Some(commit) = commit {
+                        self.apply_commit(key, commit)?;
+                    }
+                    if let Some(txn) = txn {
+                        self.apply_txn(key, txn.clone())?;
+                    }
+                    Ok(log_end)
+                }) {
+                Ok(log_end) => {
+                    let end = *offset + msgs.len() as u64;
+                    let next = match self.mode {
+                        ReplicationMode::Chain if log_end >= end => self.replicas_of(key).pop(),
+                        _ => None,
+                    };
+                    match next {
+                        Some(next) => vec![self.replicate(Replication {
+                            request: None,
+                            relay: Some((message.src.clone(), *msg_id)),
+                            follower: next,
+                            key: key.clone(),
+                            from: *offset,
+                            end,
+                            commit: commit.clone(),
+                            txn: txn.clone(),
+                        })],
+                        None => build_message_from(Body::ReplicateOk {
+                            msg_id: self.incremented_msg_id(),
+                            in_reply_to: *msg_id,
+                            log_end,
+                        }),
+                    }
+                }
+                Err(error) => build_message_from(Body::Error(ErrorBody::new(
+                    *msg_id,
+                    ErrorCode::Crash,
+                    format!("Failed to append to {key}: {error}"),
+                ))),
+            },
+
+            Body::ReplicateOk {
+                in_reply_to,
+                log_end,
+                ..
+            } => self.replicated(*in_reply_to, *log_end),
+
+            Body::Recover { msg_id } => {
+                let keys: Vec<String> = self
+                    .store
+                    .keys()
+                    .into_iter()
+                    .filter(|key| self.keeps_log_of(key, &message.src))
+                    .collect();
+                let mut logs = HashMap::new();
+                let mut timestamps = HashMap::new();
+                let mut txns = HashMap::new();
+                for key in keys {
+                    if let Some(entries) = self.store.read(&key, 0, u64::MAX) {
+                        timestamps.insert(key.clone(), self.store.timestamps(&key, 0, u64::MAX));
+                        logs.insert(key.clone(), entries);
+                    }
+                    let marks = self.store.txns(&key);
+                    if !marks.is_empty() {
+                        txns.insert(key, marks);
+                    }
+                }
+                build_message_from(Body::RecoverOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    logs,
+                    timestamps,
+                    commits: self.commits_kept_by(&message.src),
+                    txns,
+                })
+            }
+
+            Body::RecoverOk {
+                in_reply_to,
+                logs,
+                timestamps,
+                commits,
+                txns,
+                ..
+            } => {
+                if self.recovering.complete(*in_reply_to).is_none() {
+                    return Vec::new();
+                }
+                for (key, entries) in logs.drain() {
+                    let timestamps = timestamps.remove(&key).unwrap_or_default();
+                    for (i, [offset, msg]) in entries.into_iter().enumerate() {
+                        let timestamp = timestamps.get(i..=i).unwrap_or_default();
+                        if let Err(error) = self.append_from(&key, offset, &[msg], timestamp) {
+                            log::error!("Failed to recover {key}: {error:?}");
+                            break;
+                        }
+                    }
+                }
+                // Of the commits this node and the follower have, the one with the newest
+                // epoch, or else the furthest offset, is the latest.
+                for (key, commits) in commits.drain() {
+                    for commit in commits {
+                        let kept = self
+                            .store
+                            .committed(&commit.group, &key)
+                            .map(|offset| (self.store.epoch(&commit.group, &key), offset));
+                        if kept >= Some((commit.epoch, commit.offset)) {
+                            continue;
+                        }
+                        if let Err(error) = self.apply_commit(&key, &commit) {
+                            log::error!("Failed to recover the commits of {key}: {error:?}");
+                        }
+                    }
+                }
+                for (key, marks) in txns.drain() {
+                    for mark in marks {
+                        if let Err(error) = self.apply_txn(&key, mark) {
+                            log::error!("Failed to recover the transactions of {key}: {error:?}");
+                        }
+                    }
+                }
+                if !self.recovering.is_empty() || !self.rejoining.remove(&self.node_id) {
+                    return Vec::new();
+                }
+                log::info!("Caught up on the logs of chains, and rejoins them");
+                let node_id = self.node_id.clone();
+                self.reconfigure(&node_id, false, false)
+            }
+
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,750 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::log_store::{TxnMark, TxnState};
+use crate::{ErrorBody, ErrorCode};
+
+use super::replication::Replication;
+use super::{
+    Body, Message, Node, PartialResult, ProxiedRequest, RequestKind, REPLICATE_TIMEOUT, TXN_TIMEOUT,
+};
+
+/// A transaction a client began on this node, which keeps what the client produces until
+/// it commits.
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    msgs: HashMap<String, Vec<u64>>,
+}
+
+/// A transaction whose client asked to commit it, while the owners of its keys vote on it.
+#[derive(Debug)]
+pub(super) struct TxnCommit {
+    txn_id: u64,
+    /// The client and msg_id of the commit request, replaced by a retried commit.
+    client: String,
+    in_reply_to: u64,
+    /// The keys of the transaction in order, the first of which is its primary key.
+    keys: Vec<String>,
+    /// First and last offset of the pending entries the owners that voted appended.
+    offsets: HashMap<String, [u64; 2]>,
+}
+
+/// A transaction that committed, whose decision the heads of its keys are told.
+#[derive(Debug)]
+pub(super) struct CommittedTxn {
+    /// The commit request waiting for the heads to answer.
+    request: u64,
+    primary: String,
+    /// The other keys of the transaction, whose heads are told once the head of the
+    /// primary key committed the transaction.
+    rest: Vec<String>,
+}
+
+/// A transaction with pending entries in keys this node keeps, which waits to hear whether
+/// it committed.
+#[derive(Debug)]
+pub(super) struct PendingTxn {
+    txn: String,
+    primary: String,
+    keys: BTreeSet<String>,
+}
+
+impl Node {
+    /// Asks the owners of the keys of a transaction to append its messages as pending
+    /// entries, once its client asked to commit, which is how they vote for it. Owners that
+    /// can't take the messages, e.g. as they're recovering, vote against it, and the
+    /// transaction is aborted. The smallest of its keys is its primary key.
+    fn prepare(&mut self, client: &str, in_reply_to: u64, txn_id: u64) -> Vec<Message> {
+        let txn = self.txn_name(txn_id);
+        // A retried commit only replaces the request that gets the answer.
+        if let Some(commit) = self.coordinator.state_mut(&txn) {
+            commit.client = client.to_string();
+            commit.in_reply_to = in_reply_to;
+            return Vec::new();
+        }
+        let Some(transaction) = self.transactions.complete(txn_id) else {
+            return vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body: Body::Error(ErrorBody::new(
+                    in_reply_to,
+                    ErrorCode::Abort,
+                    format!("Transaction {txn_id} is not open"),
+                )),
+            }];
+        };
+        let mut keys: Vec<String> = transaction.msgs.keys().cloned().collect();
+        keys.sort();
+        let commit = TxnCommit {
+            txn_id,
+            client: client.to_string(),
+            in_reply_to,
+            keys,
+            offsets: HashMap::new(),
+        };
+        let Some(primary) = commit.keys.first().cloned() else {
+            return self.commit_txn(commit);
+        };
+
+        let mut parts: HashMap<String, HashMap<String, Vec<u64>>> = HashMap::new();
+        for (key, msgs) in transaction.msgs {
+            parts
+                .entry(self.head_of(&key))
+                .or_default()
+                .insert(key, msgs);
+        }
+        self.coordinator.start(&txn, parts.keys().cloned(), commit);
+        let mut sent = Vec::new();
+        for (owner, msgs) in parts {
+            let (msg_id, message) = self.forward(owner, |msg_id| Body::PrepareTxn {
+                msg_id,
+                txn: txn.clone(),
+                primary: primary.clone(),
+                msgs,
+            });
+            self.votes.insert(msg_id, txn.clone());
+            sent.push(message);
+        }
+        sent
+    }
+
+    /// Names the transaction `txn_id` of this node in the logs of its keys.
+    fn txn_name(&self, txn_id: u64) -> String {
+        format!("{}:{txn_id}", self.node_id)
+    }
+
+    /// Counts the vote of `voter` on the transaction a `prepare_txn` asked about, with the
+    /// offsets of the entries it appended, and commits the transaction once every owner
+    /// voted for it. A vote against it, an error, aborts it.
+    fn voted(
+        &mut self,
+        in_reply_to: u64,
+        voter: &str,
+        against: Option<&str>,
+        offsets: HashMap<String, [u64; 2]>,
+    ) -> Vec<Message> {
+        let Some(txn) = self.votes.complete(in_reply_to) else {
+            return Vec::new();
+        };
+        if let Some(commit) = self.coordinator.state_mut(&txn) {
+            commit.offsets.extend(offsets);
+        }
+        let Some(outcome) = self.coordinator.vote(&txn, voter, against.is_none()) else {
+            return Vec::new();
+        };
+        if outcome.commit {
+            return self.commit_txn(outcome.state);
+        }
+        let reason = against.unwrap_or_default();
+        self.abort(outcome.state, &format!("{voter} refused it: {reason}"))
+    }
+
+    /// Commits a transaction every owner voted for. The head of its primary key hears
+    /// first, and once it committed the transaction there, which no node can settle
+    /// otherwise from then on, the heads of the other keys hear too. The client gets the
+    /// offsets of the messages once every head let polls return them.
+    fn commit_txn(&mut self, commit: TxnCommit) -> Vec<Message> {
+        let TxnCommit {
+            txn_id,
+            client,
+            in_reply_to,
+            mut keys,
+            offsets,
+        } = commit;
+        let request = self.incremented_msg_id();
+        let proxied = ProxiedRequest {
+            client,
+            in_reply_to,
+            kind: RequestKind::Commit,
+            waiting: keys.len(),
+            result: PartialResult {
+                ranges: offsets,
+                ..PartialResult::default()
+            },
+        };
+        if keys.is_empty() {
+            return vec![self.reply_to_client(proxied)];
+        }
+        self.proxied.insert(request, proxied);
+
+        let txn = self.txn_name(txn_id);
+        let primary = keys.remove(0);
+        let committed = CommittedTxn {
+            request,
+            primary: primary.clone(),
+            rest: keys,
+        };
+        self.committed.insert(txn.clone(), committed);
+        self.coordinator.tell(&txn, true, [primary.clone()]);
+        vec![self.send_decision(&txn, primary)]
+    }
+
+    /// Sends the commit of `txn` to the current head of `key`. The coordinator has it sent
+    /// again until the head answers.
+    fn send_decision(&mut self, txn: &str, key: String) -> Message {
+        let (msg_id, message) = self.forward(self.head_of(&key), |msg_id| Body::DecideTxn {
+            msg_id,
+            txn: txn.to_string(),
+            key: key.clone(),
+            commit: Some(true),
+        });
+        self.deciding.insert(msg_id, (txn.to_string(), key));
+        message
+    }
+
+    /// Counts the answer of the head of `key` to the commit of `txn`. Once the head of the
+    /// primary key committed it, the heads of the other keys are told. If that head aborted
+    /// it instead, as it settled the transaction before the decision arrived, they are told
+    /// so, and the commit fails.
+    fn decided(&mut self, txn: String, key: String, committed: bool) -> Vec<Message> {
+        // The head may answer a decision sent again too.
+        if !self.coordinator.acknowledged(&txn, &key) {
+            return Vec::new();
+        }
+        let Some(committing) = self.committed.get_mut(&txn) else {
+            return Vec::new();
+        };
+        let request = committing.request;
+        let mut sent = Vec::new();
+        if key == committing.primary {
+            let rest = std::mem::take(&mut committing.rest);
+            if !committed {
+                self.committed.remove(&txn);
+                for key in rest {
+                    sent.push(self.abort_part(&txn, key));
+                }
+                if let Some(proxied) = self.proxied.remove(&request) {
+                    sent.push(Message {
+                        src: self.node_id.clone(),
+                        dest: proxied.client,
+                        body: Body::Error(ErrorBody::new(
+                            proxied.in_reply_to,
+                            ErrorCode::Abort,
+                            format!("Transaction {txn} was aborted, as it timed out"),
+                        )),
+                    });
+                }
+                return sent;
+            }
+            self.coordinator.tell(&txn, true, rest.iter().cloned());
+            for key in rest {
+                sent.push(self.send_decision(&txn, key));
+            }
+        }
+        if self.coordinator.decision(&txn).is_none() {
+            self.committed.remove(&txn);
+        }
+        sent.extend(self.complete_part(request, |_| {}));
+        sent
+    }
+
+    /// Tells the head of `key` that the transaction `txn` aborted, without waiting for an
+    /// answer: a head that doesn't hear it settles the pending entries in time.
+    fn abort_part(&mut self, txn: &str, key: String) -> Message {
+        let dest = self.head_of(&key);
+        Message {
+            src: self.node_id.clone(),
+            dest,
+            body: Body::DecideTxn {
+                msg_id: self.incremented_msg_id(),
+                txn: txn.to_string(),
+                key,
+                commit: Some(false),
+            },
+        }
+    }
+
+    /// Drops a transaction that didn't commit, and tells its client. The owners that
+    /// appended its messages as pending entries leave them out of polls.
+    fn abort(&mut self, commit: TxnCommit, reason: &str) -> Vec<Message> {
+        let txn = self.txn_name(commit.txn_id);
+        let mut sent = Vec::new();
+        for key in commit.keys {
+            sent.push(self.abort_part(&txn, key));
+        }
+        sent.push(Message {
+            src: self.node_id.clone(),
+            dest: commit.client,
+            body: Body::Error(ErrorBody::new(
+                commit.in_reply_to,
+                ErrorCode::Abort,
+                format!("Transaction {} was aborted, as it {reason}", commit.txn_id),
+            )),
+        });
+        sent
+    }
+
+    /// Appends the messages of the transaction `txn` to the keys this node heads as pending
+    /// entries, which polls leave out until the transaction committed, and votes for it once
+    /// as many followers have them as the ack level asks for. A transaction this node
+    /// aborted already, such as one it settled without its coordinator, is voted against.
+    fn prepare_part(
+        &mut self,
+        coordinator: &str,
+        in_reply_to: u64,
+        txn: String,
+        primary: String,
+        msgs: HashMap<String, Vec<u64>>,
+    ) -> Vec<Message> {
+        let node_id = self.node_id.clone();
+        let error = |code, text: String| {
+            vec![Message {
+                src: node_id.clone(),
+                dest: coordinator.to_string(),
+                body: Body::Error(ErrorBody::new(in_reply_to, code, text)),
+            }]
+        };
+        // A node that takes another node for the head may have entries this one lacks.
+        if let Some(key) = msgs.keys().find(|key| self.head_of(key) != self.node_id) {
+            return error(
+                ErrorCode::TemporarilyUnavailable,
+                format!("Not the head of the chain of {key}"),
+            );
+        }
+        let aborted = |mark: TxnMark| mark.state == TxnState::Aborted;
+        if msgs
+            .keys()
+            .any(|key| self.txn_mark(key, &txn).is_some_and(aborted))
+        {
+            return error(ErrorCode::Abort, format!("Transaction {txn} was aborted"));
+        }
+
+        let mut result = PartialResult::default();
+        let mut replications = Vec::new();
+        let mut keys = BTreeSet::new();
+        for (key, msgs) in msgs {
+            // A prepare sent again gets the entries appended the first time.
+            if let Some(mark) = self.txn_mark(&key, &txn) {
+                result.ranges.insert(key, [mark.start, mark.end - 1]);
+                continue;
+            }
+            let appended = self.append_all(&key, &msgs).and_then(|range| {
+                let mark = TxnMark {
+                    txn: txn.clone(),
+                    primary: primary.clone(),
+                    start: range[0],
+                    end: range[1] + 1,
+                    state: TxnState::Pending,
+                };
+                self.store.mark_txn(&key, mark.clone())?;
+                Ok((range, mark))
+            });
+            let (range, mark) = match appended {
+                Ok(appended) => appended,
+                Err(error) => {
+                    return vec![Message {
+                        src: self.node_id.clone(),
+                        dest: coordinator.to_string(),
+                        body: Body::Error(ErrorBody::new(
+                            in_reply_to,
+                            ErrorCode::Crash,
+                            format!("Failed to append to {key}: {error}"),
+                        )),
+                    }]
+                }
+            };
+            replications.extend(self.replicate_to_followers(
+                &key,
+                range[0],
+                None,
+                Some(mark),
+                &mut result,
+            ));
+            result.ranges.insert(key.clone(), range);
+            keys.insert(key);
+        }
+        if !keys.is_empty() {
+            self.await_decision(PendingTxn { txn, primary, keys });
+        }
+
+        self.proxy_replicated(
+            coordinator,
+            in_reply_to,
+            RequestKind::PrepareTxn,
+            result,
+            Vec::new(),
+            replications,
+        )
+    }
+
+    /// The mark of the transaction `txn` in the log of `key`, if it has entries there, or
+    /// was settled there.
+    fn txn_mark(&self, key: &str, txn: &str) -> Option<TxnMark> {
+        self.store
+            .txns(key)
+            .into_iter()
+            .find(|mark| mark.txn == txn)
+    }
+
+    /// Makes the pending entries replayed from disk wait for their decision like new ones.
+    pub(super) fn await_replayed_decisions(&mut self) {
+        for key in self.store.keys() {
+            for mark in self.store.txns(&key) {
+                if mark.state == TxnState::Pending {
+                    self.await_decision(PendingTxn {
+                        txn: mark.txn,
+                        primary: mark.primary,
+                        keys: BTreeSet::from([key.clone()]),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Times the pending entries of `pending`, which are settled if no decision on them
+    /// arrives in time, along with the other entries of the transaction waiting already.
+    fn await_decision(&mut self, pending: PendingTxn) {
+        match self.undecided.get_mut(&pending.txn) {
+            Some(waiting) => waiting.keys.extend(pending.keys),
+            None => self.undecided.insert(&pending.txn.clone(), pending),
+        }
+    }
+
+    /// Applies the decision on the transaction `txn` to its pending entries in `key`, or
+    /// answers with the decision made already. A head without entries of the transaction
+    /// aborts it, so that a prepare arriving late is voted against. A question without a
+    /// decision, about a transaction still pending, isn't answered, as the head of the
+    /// primary key settles it in time.
+    fn decide_part(
+        &mut self,
+        client: &str,
+        in_reply_to: u64,
+        txn: String,
+        key: String,
+        commit: Option<bool>,
+    ) -> Vec<Message> {
+        if self.head_of(&key) != self.node_id {
+            return vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body: Body::Error(ErrorBody::new(
+                    in_reply_to,
+                    ErrorCode::TemporarilyUnavailable,
+                    format!("Not the head of the chain of {key}"),
+                )),
+            }];
+        }
+        let (mark, commit) = match self.txn_mark(&key, &txn) {
+            Some(mark) if mark.state != TxnState::Pending => {
+                return vec![Message {
+                    src: self.node_id.clone(),
+                    dest: client.to_string(),
+                    body: Body::DecideTxnOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to,
+                        committed: mark.state == TxnState::Committed,
+                    },
+                }];
+            }
+            Some(_) if commit.is_none() => return Vec::new(),
+            Some(mark) => (mark, commit == Some(true)),
+            None => {
+                let end = self.store.log_end(&key);
+                let mark = TxnMark {
+                    txn,
+                    primary: key.clone(),
+                    start: end,
+                    end,
+                    state: TxnState::Pending,
+                };
+                (mark, false)
+            }
+        };
+
+        let mut result = PartialResult {
+            committed: commit,
+            ..PartialResult::default()
+        };
+        match self.settle(&key, mark, commit, &mut result) {
+            Ok(replications) => self.proxy_replicated(
+                client,
+                in_reply_to,
+                RequestKind::DecideTxn,
+                result,
+                Vec::new(),
+                replications,
+            ),
+            Err(error) => vec![Message {
+                src: self.node_id.clone(),
+                dest: client.to_string(),
+                body: Body::Error(ErrorBody::new(
+                    in_reply_to,
+                    ErrorCode::Crash,
+                    format!("Failed to settle {key}: {error}"),
+                )),
+            }],
+        }
+    }
+
+    /// Marks the pending entries `mark` of `key` as committed or aborted, and returns the
+    /// replications that tell the followers.
+    fn settle(
+        &mut self,
+        key: &str,
+        mut mark: TxnMark,
+        commit: bool,
+        result: &mut PartialResult,
+    ) -> Result<Vec<Replication>, anyhow::Error> {
+        mark.state = match commit {
+            true => TxnState::Committed,
+            false => TxnState::Aborted,
+        };
+        self.store.mark_txn(key, mark.clone())?;
+        let end = self.store.log_end(key);
+        Ok(self.replicate_to_followers(key, end, None, Some(mark), result))
+    }
+
+    /// Settles the entries of a transaction that stayed pending, as its coordinator may have
+    /// crashed before it told this node the decision. The head of the primary key decides:
+    /// it aborts the transaction, unless the coordinator got it to commit there in time. The
+    /// heads of the other keys ask it what it decided. Keys this node doesn't head are left
+    /// to their heads, which pass the decision on.
+    fn resolve(&mut self, mut pending: PendingTxn) -> Vec<Message> {
+        let txn = pending.txn.clone();
+        let is_pending = |mark: TxnMark| mark.state == TxnState::Pending;
+        pending
+            .keys
+            .retain(|key| self.txn_mark(key, &txn).is_some_and(is_pending));
+        if pending.keys.is_empty() {
+            return Vec::new();
+        }
+        let arbiter = self.head_of(&pending.primary);
+        if arbiter == self.node_id {
+            let committed = self
+                .txn_mark(&pending.primary, &txn)
+                .is_some_and(|mark| mark.state == TxnState::Committed);
+            if !committed {
+                pending.keys.insert(pending.primary.clone());
+            }
+            return self.settle_pending(pending, committed);
+        }
+        if pending
+            .keys
+            .iter()
+            .all(|key| self.head_of(key) != self.node_id)
+        {
+            self.await_decision(pending);
+            return Vec::new();
+        }
+        let msg_id = self.incremented_msg_id();
+        let message = Message {
+            src: self.node_id.clone(),
+            dest: arbiter,
+            body: Body::DecideTxn {
+                msg_id,
+                txn,
+                key: pending.primary.clone(),
+                commit: None,
+            },
+        };
+        self.queries.insert(msg_id, pending.txn.clone());
+        self.await_decision(pending);
+        vec![message]
+    }
+
+    /// Applies the decision on a transaction to its pending entries in the keys this node
+    /// heads, and keeps waiting for the decision on the others.
+    fn settle_pending(&mut self, mut pending: PendingTxn, commit: bool) -> Vec<Message> {
+        log::info!("Settling {} as committed: {commit}", pending.txn);
+        let mut sent = Vec::new();
+        let mut waiting = BTreeSet::new();
+        for key in std::mem::take(&mut pending.keys) {
+            let Some(mark) = self.txn_mark(&key, &pending.txn) else {
+                continue;
+            };
+            if mark.state != TxnState::Pending {
+                continue;
+            }
+            if self.head_of(&key) != self.node_id {
+                waiting.insert(key);
+                continue;
+            }
+            match self.settle(&key, mark, commit, &mut PartialResult::default()) {
+                Ok(replications) => {
+                    for replication in replications {
+                        sent.push(self.replicate(replication));
+                    }
+                }
+                Err(error) => log::error!("Failed to settle {key}: {error:?}"),
+            }
+        }
+        if !waiting.is_empty() {
+            self.await_decision(PendingTxn {
+                keys: waiting,
+                ..pending
+            });
+        }
+        sent
+    }
+
+    /// Keeps the mark of a transaction that the head of `key` sent, unless this node knows
+    /// whether the transaction committed already, and waits for the decision on pending
+    /// entries.
+    pub(super) fn apply_txn(&mut self, key: &str, mark: TxnMark) -> Result<(), anyhow::Error> {
+        match self.txn_mark(key, &mark.txn) {
+            Some(kept) if kept.state != TxnState::Pending || kept == mark => return Ok(()),
+            None if mark.state == TxnState::Pending => self.await_decision(PendingTxn {
+                txn: mark.txn.clone(),
+                primary: mark.primary.clone(),
+                keys: BTreeSet::from([key.to_string()]),
+            }),
+            _ => {}
+        }
+        self.store.mark_txn(key, mark)
+    }
+
+    /// Aborts transactions that took too long to commit, sends again decisions keys didn't
+    /// take, and settles entries whose decision didn't come in time.
+    pub(super) fn expire_transactions(&mut self) -> Vec<Message> {
+        let mut sent = Vec::new();
+        // Transactions the client never asked to commit have nothing to abort.
+        self.transactions.expire(TXN_TIMEOUT);
+        for outcome in self.coordinator.expire(TXN_TIMEOUT) {
+            sent.extend(self.abort(outcome.state, "timed out"));
+        }
+        self.votes.expire(TXN_TIMEOUT);
+        for (key, txn, _) in self.coordinator.unacknowledged(REPLICATE_TIMEOUT) {
+            sent.push(self.send_decision(&txn, key));
+        }
+        self.deciding.expire(TXN_TIMEOUT);
+        for txn in self.undecided.overdue(TXN_TIMEOUT) {
+            if let Some(pending) = self.undecided.remove(&txn) {
+                sent.extend(self.resolve(pending));
+            }
+        }
+        self.queries.expire(TXN_TIMEOUT);
+        sent
+    }
+
+    /// Handles transactions of clients, and the votes and decisions of their keys.
+    pub(super) fn process_transaction(&mut self, message: &mut Message) -> Vec<Message> {
+        let build_message_from = |body: Body| -> Vec<Message> {
+            vec![Message {
+                src: message.dest.clone(),
+                dest: message.src.clone(),
+                body,
+            }]
+        };
+
+        match &mut message.body {
+            Body::Begin { msg_id } => {
+                let txn_id = self.incremented_msg_id();
+                self.transactions.insert(txn_id, Transaction::default());
+                build_message_from(Body::BeginOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: *msg_id,
+                    txn_id,
+                })
+            }
+
+            Body::Produce {
+                msg_id,
+                txn_id,
+                key,
+                msg,
+            } => {
+                let msgs = msg.clone().into_vec();
+                let committing = self.coordinator.is_voting(&self.txn_name(*txn_id));
+                let rejection = match self.transactions.get_mut(*txn_id) {
+                    None if committing => Some((
+                        ErrorCode::Abort,
+                        format!("Transaction {txn_id} is committing"),
+                    )),
+                    None => Some((
+                        ErrorCode::Abort,
+                        format!("Transaction {txn_id} is not open"),
+                    )),
+                    Some(_) if msgs.is_empty() => Some((
+                        ErrorCode::MalformedRequest,
+                        format!("No messages to produce to {key}"),
+                    )),
+                    Some(transaction) => {
+                        transaction
+                            .msgs
+                            .entry(key.clone())
+                            .or_default()
+                            .extend(msgs);
+                        None
+                    }
+                };
+                let body = match rejection {
+                    Some((code, text)) => Body::Error(ErrorBody::new(*msg_id, code, text)),
+                    None => Body::ProduceOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: *msg_id,
+                    },
+                };
+                build_message_from(body)
+            }
+
+            Body::Commit { msg_id, txn_id } => self.prepare(&message.src, *msg_id, *txn_id),
+
+            Body::PrepareTxn {
+                msg_id,
+                txn,
+                primary,
+                msgs,
+            } => {
+                let (txn, primary, msgs) = (txn.clone(), primary.clone(), std::mem::take(msgs));
+                self.prepare_part(&message.src, *msg_id, txn, primary, msgs)
+            }
+
+            Body::PrepareTxnOk {
+                in_reply_to,
+                offsets,
+                ..
+            } => self.voted(*in_reply_to, &message.src, None, std::mem::take(offsets)),
+
+            Body::DecideTxn {
+                msg_id,
+                txn,
+                key,
+                commit,
+            } => {
+                let (txn, key) = (txn.clone(), key.clone());
+                self.decide_part(&message.src, *msg_id, txn, key, *commit)
+            }
+
+            Body::DecideTxnOk {
+                in_reply_to,
+                committed,
+                ..
+            } => {
+                if let Some((txn, key)) = self.deciding.complete(*in_reply_to) {
+                    return self.decided(txn, key, *committed);
+                }
+                let txn = self.queries.complete(*in_reply_to);
+                match txn.and_then(|txn| self.undecided.remove(&txn)) {
+                    Some(pending) => self.settle_pending(pending, *committed),
+                    None => Vec::new(),
+                }
+            }
+
+            Body::Error(error_body) if self.votes.get_mut(error_body.in_reply_to).is_some() => {
+                let against = Some(error_body.text.as_str());
+                self.voted(
+                    error_body.in_reply_to,
+                    &message.src,
+                    against,
+                    HashMap::new(),
+                )
+            }
+
+            // The decision is sent again once it expires, until the head takes it.
+            Body::Error(error_body) if self.deciding.get_mut(error_body.in_reply_to).is_some() => {
+                log::warn!("Failed to decide a transaction: {:?}", error_body);
+                Vec::new()
+            }
+
+            // The question is asked again once it expires, until the head answers.
+            Body::Error(error_body) if self.queries.get_mut(error_body.in_reply_to).is_some() => {
+                log::warn!(
+                    "Failed to learn the decision on a transaction: {:?}",
+                    error_body
+                );
+                Vec::new()
+            }
+
+            _ => Vec::new(),
+        }
+    }
+}
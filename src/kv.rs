@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{ErrorBody, ErrorCode};
+use crate::message::Message;
+use crate::rpc::PendingRequests;
+
+/// Maelstrom's built-in key-value services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvService {
+    SeqKv,
+    LinKv,
+    LwwKv,
+}
+
+impl KvService {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KvService::SeqKv => "seq-kv",
+            KvService::LinKv => "lin-kv",
+            KvService::LwwKv => "lww-kv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "seq-kv" => Some(KvService::SeqKv),
+            "lin-kv" => Some(KvService::LinKv),
+            "lww-kv" => Some(KvService::LwwKv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvBody {
+    Read {
+        msg_id: u64,
+        key: Value,
+    },
+
+    ReadOk {
+        in_reply_to: u64,
+        value: Value,
+    },
+
+    Write {
+        msg_id: u64,
+        key: Value,
+        value: Value,
+    },
+
+    WriteOk {
+        in_reply_to: u64,
+    },
+
+    Cas {
+        msg_id: u64,
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+
+    CasOk {
+        in_reply_to: u64,
+    },
+
+    Error(ErrorBody),
+}
+
+#[derive(Debug)]
+pub enum KvReply {
+    Read(Value),
+    Write,
+    Cas,
+}
+
+impl KvReply {
+    pub fn value<T: serde::de::DeserializeOwned>(self) -> Result<T, anyhow::Error> {
+        match self {
+            KvReply::Read(value) => {
+                serde_json::from_value(value).context("Unexpected value type stored in KV")
+            }
+            other => anyhow::bail!("Expected read_ok, got {:?}", other),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KvError {
+    KeyDoesNotExist(String),
+    PreconditionFailed(String),
+    Other(ErrorBody),
+}
+
+impl From<ErrorBody> for KvError {
+    fn from(error: ErrorBody) -> Self {
+        match error.code {
+            ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist(error.text),
+            ErrorCode::PreconditionFailed => KvError::PreconditionFailed(error.text),
+            _ => KvError::Other(error),
+        }
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist(text) => write!(f, "key does not exist: {text}"),
+            KvError::PreconditionFailed(text) => write!(f, "precondition failed: {text}"),
+            KvError::Other(error) => write!(f, "error {:?}: {}", error.code, error.text),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+#[derive(Deserialize)]
+struct Source<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+}
+
+/// Returns the reply if the line was sent by one of the KV services.
+pub fn parse_reply(line: impl AsRef<[u8]>) -> Result<Option<Message<KvBody>>, anyhow::Error> {
+    let line = line.as_ref();
+    let source: Source =
+        serde_json::from_slice(line).context("Failed to deserialize provided input to STDIN.")?;
+
+    if KvService::from_name(&source.src).is_none() {
+        return Ok(None);
+    }
+
+    let reply = serde_json::from_slice(line).context("Failed to deserialize KV service reply.")?;
+    Ok(Some(reply))
+}
+
+/// Builds requests for a KV service and correlates replies with the caller's context `T`.
+pub struct KvClient<T> {
+    service: KvService,
+    pending: PendingRequests<T>,
+}
+
+impl<T> KvClient<T> {
+    pub fn new(service: KvService) -> Self {
+        Self {
+            service,
+            pending: PendingRequests::new(),
+        }
+    }
+
+    pub fn service(&self) -> KvService {
+        self.service
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn read<K: Serialize>(
+        &mut self,
+        node_id: &str,
+        msg_id: u64,
+        key: K,
+        context: T,
+    ) -> Result<Message<KvBody>, anyhow::Error> {
+        let body = KvBody::Read {
+            msg_id,
+            key: serde_json::to_value(key)?,
+        };
+        Ok(self.request(node_id, msg_id, body, context))
+    }
+
+    pub fn write<K: Serialize, V: Serialize>(
+        &mut self,
+        node_id: &str,
+        msg_id: u64,
+        key: K,
+        value: V,
+        context: T,
+    ) -> Result<Message<KvBody>, anyhow::Error> {
+        let body = KvBody::Write {
+            msg_id,
+            key: serde_json::to_value(key)?,
+            value: serde_json::to_value(value)?,
+        };
+        Ok(self.request(node_id, msg_id, body, context))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn cas<K: Serialize, V: Serialize>(
+        &mut self,
+        node_id: &str,
+        msg_id: u64,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        context: T,
+    ) -> Result<Message<KvBody>, anyhow::Error> {
+        let body = KvBody::Cas {
+            msg_id,
+            key: serde_json::to_value(key)?,
+            from: serde_json::to_value(from)?,
+            to: serde_json::to_value(to)?,
+            create_if_not_exists,
+        };
+        Ok(self.request(node_id, msg_id, body, context))
+    }
+
+    fn request(&mut self, node_id: &str, msg_id: u64, body: KvBody, context: T) -> Message<KvBody> {
+        self.pending.insert(msg_id, context);
+
+        Message {
+            src: node_id.to_string(),
+            dest: self.service.name().to_string(),
+            body,
+        }
+    }
+
+    /// Matches a reply with the request that caused it. Unknown or duplicate replies yield `None`.
+    pub fn handle_reply(
+        &mut self,
+        reply: Message<KvBody>,
+    ) -> Option<(T, Result<KvReply, KvError>)> {
+        let (in_reply_to, result) = match reply.body {
+            KvBody::ReadOk { in_reply_to, value } => (in_reply_to, Ok(KvReply::Read(value))),
+            KvBody::WriteOk { in_reply_to } => (in_reply_to, Ok(KvReply::Write)),
+            KvBody::CasOk { in_reply_to } => (in_reply_to, Ok(KvReply::Cas)),
+            KvBody::Error(error) => (error.in_reply_to, Err(KvError::from(error))),
+            KvBody::Read { .. } | KvBody::Write { .. } | KvBody::Cas { .. } => return None,
+        };
+
+        let context = self.pending.complete(in_reply_to)?;
+        Some((context, result))
+    }
+}
@@ -0,0 +1,50 @@
+pub mod admin;
+#[cfg(feature = "tokio")]
+pub mod async_runtime;
+pub mod breaker;
+pub mod chaos;
+pub mod checkpoint;
+pub mod chunk;
+pub mod clock;
+pub mod compact;
+pub mod compress;
+pub mod config;
+pub mod crdt;
+pub mod digest;
+pub mod error;
+pub mod event_queue;
+pub mod harness;
+pub mod hlc;
+pub mod ids;
+pub mod journal;
+pub mod kafka;
+pub mod kv;
+pub mod log_store;
+pub mod logging;
+pub mod membership;
+pub mod message;
+pub mod metrics;
+pub mod mvcc;
+pub mod node_id;
+pub mod outbound;
+pub mod paxos;
+pub mod protocol;
+pub mod raft;
+pub mod range_set;
+pub mod record;
+pub mod reply_cache;
+pub mod results;
+pub mod rng;
+pub mod rpc;
+pub mod rtt;
+pub mod runtime;
+pub mod scuttlebutt;
+pub mod sim;
+pub mod testkit;
+pub mod topology;
+pub mod transport;
+pub mod txn;
+pub mod vector_clock;
+
+pub use error::{ErrorBody, ErrorCode};
+pub use message::Message;
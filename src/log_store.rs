@@ -0,0 +1,646 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::HlcTimestamp;
+
+/// Storage for the kafka workload: append-only logs per key plus offsets committed by every
+/// consumer group.
+pub trait LogStore {
+    /// Appends `msg`, appended at `timestamp`, to the log of `key` and returns its offset.
+    /// Timestamps of a log must not go backwards.
+    fn append(
+        &mut self,
+        key: &str,
+        msg: u64,
+        timestamp: HlcTimestamp,
+    ) -> Result<u64, anyhow::Error>;
+
+    /// Returns up to `max` `[offset, msg]` pairs starting at `from`, or `None` if the key has
+    /// no log.
+    fn read(&self, key: &str, from: u64, max: u64) -> Option<Vec<[u64; 2]>>;
+
+    /// Returns the timestamps of the entries `read` returns.
+    fn timestamps(&self, key: &str, from: u64, max: u64) -> Vec<HlcTimestamp>;
+
+    /// Returns the first offset kept of `key` whose entry was appended at or after
+    /// `timestamp`, or the end of the log if every entry is older.
+    fn seek(&self, key: &str, timestamp: HlcTimestamp) -> u64;
+
+    /// Commits `offset` of `key` for `group`, and raises the epoch of the group's commits to
+    /// the key to `epoch`, if the commit has one.
+    fn commit(
+        &mut self,
+        group: &str,
+        key: &str,
+        offset: u64,
+        epoch: Option<u64>,
+    ) -> Result<(), anyhow::Error>;
+
+    fn committed(&self, group: &str, key: &str) -> Option<u64>;
+
+    /// Returns the highest epoch any commit of `group` to `key` had.
+    fn epoch(&self, group: &str, key: &str) -> Option<u64>;
+
+    /// Returns the lowest offset of `key` committed by any consumer group.
+    fn min_committed(&self, key: &str) -> Option<u64>;
+
+    /// Returns the first offset still kept in the log of `key` (the low watermark).
+    fn log_start(&self, key: &str) -> u64;
+
+    /// Returns the offset the next entry appended to `key` will get.
+    fn log_end(&self, key: &str) -> u64;
+
+    /// Returns every key that has a log.
+    fn keys(&self) -> Vec<String>;
+
+    /// Returns the offsets committed by every consumer group, by group and key.
+    fn offsets(&self) -> HashMap<String, HashMap<String, u64>>;
+
+    /// Records `mark` of the log of `key`, in place of the mark of the same transaction.
+    fn mark_txn(&mut self, key: &str, mark: TxnMark) -> Result<(), anyhow::Error>;
+
+    /// Returns the marks of the transactions with entries in the log of `key`.
+    fn txns(&self, key: &str) -> Vec<TxnMark>;
+
+    /// Like `read`, but leaves out the entries of aborted transactions, and stops at the
+    /// first entry of a pending one, which may still abort. Up to `max` entries are
+    /// returned, however many of the entries after `from` were aborted.
+    fn read_committed(&self, key: &str, from: u64, max: u64) -> Option<Vec<[u64; 2]>> {
+        let txns = self.txns(key);
+        let pending = txns
+            .iter()
+            .filter(|mark| mark.state == TxnState::Pending)
+            .map(|mark| mark.start)
+            .min()
+            .unwrap_or(u64::MAX);
+        let aborted: Vec<&TxnMark> = txns
+            .iter()
+            .filter(|mark| mark.state == TxnState::Aborted)
+            .collect();
+        let skipped: u64 = aborted
+            .iter()
+            .map(|mark| mark.end.saturating_sub(mark.start.max(from)))
+            .sum();
+        let entries = self.read(key, from, max.saturating_add(skipped))?;
+        Some(
+            entries
+                .into_iter()
+                .take_while(|[offset, _]| *offset < pending)
+                .filter(|[offset, _]| !aborted.iter().any(|mark| mark.contains(*offset)))
+                .take(max as usize)
+                .collect(),
+        )
+    }
+
+    /// Drops every entry of `key` with an offset below `before`. Offsets of the remaining
+    /// entries don't change.
+    fn truncate(&mut self, key: &str, before: u64) -> Result<(), anyhow::Error>;
+
+    /// Truncates the log of `key` as far as `retention` allows.
+    fn enforce(&mut self, key: &str, retention: Retention) -> Result<(), anyhow::Error> {
+        let before = match retention {
+            Retention::KeepAll => return Ok(()),
+            Retention::Committed => match self.min_committed(key) {
+                Some(offset) => offset,
+                None => return Ok(()),
+            },
+            Retention::Latest(count) => self.log_end(key).saturating_sub(count),
+        };
+
+        if before > self.log_start(key) {
+            self.truncate(key, before)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the entries of a transaction are polled yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxnState {
+    Pending,
+    Committed,
+    Aborted,
+}
+
+/// The entries of a transaction in a log, from `start` up to `end`, and whether it
+/// committed. `primary` is the key whose head decides that for every key of `txn`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxnMark {
+    pub txn: String,
+    pub primary: String,
+    pub start: u64,
+    pub end: u64,
+    pub state: TxnState,
+}
+
+impl TxnMark {
+    pub fn contains(&self, offset: u64) -> bool {
+        (self.start..self.end).contains(&offset)
+    }
+}
+
+/// How much of a log is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retention {
+    #[default]
+    KeepAll,
+    /// Entries below the offsets committed by every consumer group are dropped, as none of
+    /// the groups will poll them again.
+    Committed,
+    /// Only the given number of newest entries is kept.
+    Latest(u64),
+}
+
+impl std::str::FromStr for Retention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Retention::KeepAll),
+            "committed" => Ok(Retention::Committed),
+            count => match count.parse() {
+                Ok(count) => Ok(Retention::Latest(count)),
+                Err(_) => anyhow::bail!("Unknown retention: {s}"),
+            },
+        }
+    }
+}
+
+/// Entries of a single key; `start` is the offset of the first entry kept. `timestamps`
+/// has the time every entry of `msgs` was appended at.
+#[derive(Debug, Default)]
+struct Log {
+    start: u64,
+    msgs: VecDeque<u64>,
+    timestamps: VecDeque<HlcTimestamp>,
+}
+
+impl Log {
+    fn end(&self) -> u64 {
+        self.start + self.msgs.len() as u64
+    }
+
+    /// Where the entries from `from` are in `msgs`.
+    fn index(&self, from: u64) -> usize {
+        from.saturating_sub(self.start) as usize
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryLogStore {
+    logs: HashMap<String, Log>,
+    /// Committed offsets by group and key.
+    offsets: HashMap<String, HashMap<String, u64>>,
+    /// Highest epochs of commits, by group and key.
+    epochs: HashMap<String, HashMap<String, u64>>,
+    /// Marks of the transactions of every key, in the order they were first marked.
+    txns: HashMap<String, Vec<TxnMark>>,
+}
+
+impl MemoryLogStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LogStore for MemoryLogStore {
+    fn append(
+        &mut self,
+        key: &str,
+        msg: u64,
+        timestamp: HlcTimestamp,
+    ) -> Result<u64, anyhow::Error> {
+        let log = self.logs.entry(key.to_string()).or_default();
+        log.msgs.push_back(msg);
+        log.timestamps.push_back(timestamp);
+        Ok(log.end() - 1)
+    }
+
+    fn read(&self, key: &str, from: u64, max: u64) -> Option<Vec<[u64; 2]>> {
+        let log = self.logs.get(key)?;
+
+        Some(
+            log.msgs
+                .iter()
+                .zip(log.start..)
+                .skip(log.index(from))
+                .take(max as usize)
+                .map(|(msg, offset)| [offset, *msg])
+                .collect(),
+        )
+    }
+
+    fn timestamps(&self, key: &str, from: u64, max: u64) -> Vec<HlcTimestamp> {
+        let Some(log) = self.logs.get(key) else {
+            return Vec::new();
+        };
+        log.timestamps
+            .iter()
+            .skip(log.index(from))
+            .take(max as usize)
+            .copied()
+            .collect()
+    }
+
+    fn seek(&self, key: &str, timestamp: HlcTimestamp) -> u64 {
+        let Some(log) = self.logs.get(key) else {
+            return 0;
+        };
+        log.start
+            + log
+                .timestamps
+                .partition_point(|appended| *appended < timestamp) as u64
+    }
+
+    fn commit(
+        &mut self,
+        group: &str,
+        key: &str,
+        offset: u64,
+        epoch: Option<u64>,
+    ) -> Result<(), anyhow::Error> {
+        self.offsets
+            .entry(group.to_string())
+            .or_default()
+            .insert(key.to_string(), offset);
+        if let Some(epoch) = epoch {
+            let highest = self
+                .epochs
+                .entry(group.to_string())
+                .or_default()
+                .entry(key.to_string())
+                .or_insert(epoch);
+            *highest = (*highest).max(epoch);
+        }
+        Ok(())
+    }
+
+    fn committed(&self, group: &str, key: &str) -> Option<u64> {
+        self.offsets.get(group)?.get(key).copied()
+    }
+
+    fn epoch(&self, group: &str, key: &str) -> Option<u64> {
+        self.epochs.get(group)?.get(key).copied()
+    }
+
+    fn min_committed(&self, key: &str) -> Option<u64> {
+        self.offsets
+            .values()
+            .filter_map(|offsets| offsets.get(key))
+            .min()
+            .copied()
+    }
+
+    fn log_start(&self, key: &str) -> u64 {
+        self.logs.get(key).map_or(0, |log| log.start)
+    }
+
+    fn log_end(&self, key: &str) -> u64 {
+        self.logs.get(key).map_or(0, Log::end)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.logs.keys().cloned().collect()
+    }
+
+    fn offsets(&self) -> HashMap<String, HashMap<String, u64>> {
+        self.offsets.clone()
+    }
+
+    fn mark_txn(&mut self, key: &str, mark: TxnMark) -> Result<(), anyhow::Error> {
+        let marks = self.txns.entry(key.to_string()).or_default();
+        match marks.iter_mut().find(|marked| marked.txn == mark.txn) {
+            Some(marked) => *marked = mark,
+            None => marks.push(mark),
+        }
+        Ok(())
+    }
+
+    fn txns(&self, key: &str) -> Vec<TxnMark> {
+        self.txns.get(key).cloned().unwrap_or_default()
+    }
+
+    fn truncate(&mut self, key: &str, before: u64) -> Result<(), anyhow::Error> {
+        let Some(log) = self.logs.get_mut(key) else {
+            return Ok(());
+        };
+
+        let before = before.clamp(log.start, log.end());
+        log.msgs.drain(..log.index(before));
+        log.timestamps.drain(..log.index(before));
+        log.start = before;
+        Ok(())
+    }
+}
+
+/// Keeps every log in memory and mirrors each append to a per-key segment file, plus
+/// journals of commits and of transaction marks, all of which are replayed when the store
+/// is reopened.
+///
+/// Segment files are named `{key}-{start}.log` after the offset of their first entry, and
+/// have a `{msg} {timestamp}` line per entry; entries of segments written before timestamps
+/// were kept have none, and count as appended at time zero. A truncated log is written to
+/// a new segment before the old one is removed, so after a crash the segment with the
+/// highest start offset is the complete one.
+pub struct DiskLogStore {
+    memory: MemoryLogStore,
+    segments_dir: PathBuf,
+    segments: HashMap<String, File>,
+    offsets: File,
+    txns: File,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CommitRecord {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    group: String,
+    key: String,
+    offset: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TxnRecord {
+    key: String,
+    #[serde(flatten)]
+    mark: TxnMark,
+}
+
+impl DiskLogStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let dir = dir.as_ref();
+        let segments_dir = dir.join("segments");
+        fs::create_dir_all(&segments_dir)
+            .with_context(|| format!("Failed to create {}", segments_dir.display()))?;
+
+        let mut latest: HashMap<String, (u64, PathBuf)> = HashMap::new();
+
+        for entry in fs::read_dir(&segments_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "tmp") {
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let Some((key, start)) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(parse_segment_name)
+            else {
+                continue;
+            };
+
+            match latest.get(&key) {
+                Some((newest, _)) if *newest > start => fs::remove_file(&path)?,
+                _ => {
+                    if let Some((_, stale)) = latest.insert(key, (start, path)) {
+                        fs::remove_file(stale)?;
+                    }
+                }
+            }
+        }
+
+        let mut memory = MemoryLogStore::new();
+
+        for (key, (start, path)) in latest {
+            let mut log = Log {
+                start,
+                ..Log::default()
+            };
+            for line in recover_lines(&path)? {
+                let (msg, timestamp) = line.split_once(' ').unwrap_or((&line, "0"));
+                log.msgs.push_back(msg.parse()?);
+                log.timestamps
+                    .push_back(HlcTimestamp::from_u64(timestamp.parse()?));
+            }
+            memory.logs.insert(key, log);
+        }
+
+        let offsets_path = dir.join("offsets.log");
+        if offsets_path.exists() {
+            for line in recover_lines(&offsets_path)? {
+                let record: CommitRecord = serde_json::from_str(&line)?;
+                memory.commit(&record.group, &record.key, record.offset, record.epoch)?;
+            }
+        }
+
+        let offsets = open_for_append(&offsets_path)?;
+
+        let txns_path = dir.join("txns.log");
+        if txns_path.exists() {
+            for line in recover_lines(&txns_path)? {
+                let record: TxnRecord = serde_json::from_str(&line)?;
+                memory.mark_txn(&record.key, record.mark)?;
+            }
+        }
+
+        let txns = open_for_append(&txns_path)?;
+
+        Ok(Self {
+            memory,
+            segments_dir,
+            segments: HashMap::new(),
+            offsets,
+            txns,
+        })
+    }
+
+    fn segment_path(&self, key: &str, start: u64) -> PathBuf {
+        self.segments_dir
+            .join(format!("{}-{start}.log", encode_key(key)))
+    }
+
+    fn segment(&mut self, key: &str) -> Result<&mut File, anyhow::Error> {
+        let path = self.segment_path(key, self.memory.log_start(key));
+
+        match self.segments.entry(key.to_string()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let file = open_for_append(&path)?;
+                Ok(entry.insert(file))
+            }
+        }
+    }
+}
+
+impl LogStore for DiskLogStore {
+    fn append(
+        &mut self,
+        key: &str,
+        msg: u64,
+        timestamp: HlcTimestamp,
+    ) -> Result<u64, anyhow::Error> {
+        self.segment(key)?
+            .write_all(segment_line(msg, timestamp).as_bytes())
+            .context("Failed to append to segment")?;
+        self.memory.append(key, msg, timestamp)
+    }
+
+    fn read(&self, key: &str, from: u64, max: u64) -> Option<Vec<[u64; 2]>> {
+        self.memory.read(key, from, max)
+    }
+
+    fn timestamps(&self, key: &str, from: u64, max: u64) -> Vec<HlcTimestamp> {
+        self.memory.timestamps(key, from, max)
+    }
+
+    fn seek(&self, key: &str, timestamp: HlcTimestamp) -> u64 {
+        self.memory.seek(key, timestamp)
+    }
+
+    fn commit(
+        &mut self,
+        group: &str,
+        key: &str,
+        offset: u64,
+        epoch: Option<u64>,
+    ) -> Result<(), anyhow::Error> {
+        let record = CommitRecord {
+            group: group.to_string(),
+            key: key.to_string(),
+            offset,
+            epoch,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        self.offsets
+            .write_all(&line)
+            .context("Failed to append to offsets journal")?;
+        self.memory.commit(group, key, offset, epoch)
+    }
+
+    fn committed(&self, group: &str, key: &str) -> Option<u64> {
+        self.memory.committed(group, key)
+    }
+
+    fn epoch(&self, group: &str, key: &str) -> Option<u64> {
+        self.memory.epoch(group, key)
+    }
+
+    fn min_committed(&self, key: &str) -> Option<u64> {
+        self.memory.min_committed(key)
+    }
+
+    fn log_start(&self, key: &str) -> u64 {
+        self.memory.log_start(key)
+    }
+
+    fn log_end(&self, key: &str) -> u64 {
+        self.memory.log_end(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.memory.keys()
+    }
+
+    fn offsets(&self) -> HashMap<String, HashMap<String, u64>> {
+        self.memory.offsets()
+    }
+
+    fn mark_txn(&mut self, key: &str, mark: TxnMark) -> Result<(), anyhow::Error> {
+        let record = TxnRecord {
+            key: key.to_string(),
+            mark,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        self.txns
+            .write_all(&line)
+            .context("Failed to append to transactions journal")?;
+        self.memory.mark_txn(key, record.mark)
+    }
+
+    fn txns(&self, key: &str) -> Vec<TxnMark> {
+        self.memory.txns(key)
+    }
+
+    fn truncate(&mut self, key: &str, before: u64) -> Result<(), anyhow::Error> {
+        let old_start = self.memory.log_start(key);
+        self.memory.truncate(key, before)?;
+        let new_start = self.memory.log_start(key);
+
+        if new_start == old_start {
+            return Ok(());
+        }
+
+        let new_path = self.segment_path(key, new_start);
+        let tmp_path = new_path.with_extension("tmp");
+        let contents: String = self
+            .memory
+            .logs
+            .get(key)
+            .into_iter()
+            .flat_map(|log| log.msgs.iter().zip(&log.timestamps))
+            .map(|(msg, timestamp)| segment_line(*msg, *timestamp))
+            .collect();
+        fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &new_path)
+            .with_context(|| format!("Failed to rename {}", tmp_path.display()))?;
+
+        self.segments.remove(key);
+        let old_path = self.segment_path(key, old_start);
+        if old_path.exists() {
+            fs::remove_file(&old_path)
+                .with_context(|| format!("Failed to remove {}", old_path.display()))?;
+        }
+        self.segments
+            .insert(key.to_string(), open_for_append(&new_path)?);
+        Ok(())
+    }
+}
+
+fn segment_line(msg: u64, timestamp: HlcTimestamp) -> String {
+    format!("{msg} {}\n", timestamp.to_u64())
+}
+
+fn open_for_append(path: &Path) -> Result<File, anyhow::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Splits a segment file stem into the key and the offset of the segment's first entry.
+fn parse_segment_name(stem: &str) -> Option<(String, u64)> {
+    let (key, start) = stem.split_once('-')?;
+    Some((decode_key(key)?, start.parse().ok()?))
+}
+
+/// Keys are hex-encoded so that any key makes a valid file name.
+fn encode_key(key: &str) -> String {
+    key.bytes().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_key(encoded: &str) -> Option<String> {
+    let bytes = (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Returns the complete lines of a file. A torn write left behind by a crash is cut off so
+/// that new appends start on a fresh line.
+fn recover_lines(path: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let complete = contents.rfind('\n').map_or(0, |newline| newline + 1);
+
+    if complete < contents.len() {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+
+    Ok(contents[..complete].lines().map(str::to_string).collect())
+}
@@ -0,0 +1,169 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::config::LogLevel;
+
+/// How each record is written to stderr, one per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LogFormat {
+    /// `12:34:56.789 WARN  broadcast: message`, with the time of day in UTC.
+    #[default]
+    Human,
+    /// `{"level":"WARN","msg":"message","target":"broadcast","ts":1700000000000}`, with
+    /// milliseconds since the epoch.
+    Json,
+}
+
+/// Levels for some modules, overriding the default level. Given as a comma separated list
+/// of `module=level`, e.g. `broadcast=debug,raft=trace`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    directives: Vec<(String, LogLevel)>,
+}
+
+impl Filter {
+    /// The level of the most specific directive for `target`, if any applies.
+    pub fn level(&self, target: &str) -> Option<LogLevel> {
+        self.directives
+            .iter()
+            .filter_map(|&(ref module, level)| Some((depth(module, target)?, level)))
+            .max_by_key(|&(depth, _)| depth)
+            .map(|(_, level)| level)
+    }
+}
+
+/// How deep into `target` a directive for `module` reaches, if it covers it. A directive
+/// covers the module itself and its submodules, named with or without the crate. So `raft`
+/// covers `distributed_system::raft` more specifically than `distributed_system` does, and
+/// `broadcast` covers both the broadcast binary and the module of the node binary.
+fn depth(module: &str, target: &str) -> Option<usize> {
+    let covers = |path: &str| {
+        path.strip_prefix(module)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    };
+    let segments = module.split("::").count();
+    if covers(target) {
+        return Some(segments);
+    }
+    let (_, path) = target.split_once("::")?;
+    covers(path).then_some(segments + 1)
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let directives = s
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(|directive| {
+                let Some((module, level)) = directive.split_once('=') else {
+                    anyhow::bail!("Log filter directive {directive} isn't module=level");
+                };
+                let level = LogLevel::from_str(level, true)
+                    .map_err(|_| anyhow::anyhow!("Unknown log level: {level}"))?;
+                Ok((module.to_string(), level))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Filter { directives })
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Writes the records of the `log` macros to stderr, leaving out those below the level of
+/// their module.
+#[derive(Debug)]
+pub struct Logger {
+    level: LogLevel,
+    filter: Filter,
+    format: LogFormat,
+}
+
+impl Logger {
+    pub fn new(level: LogLevel, filter: Filter, format: LogFormat) -> Self {
+        Logger {
+            level,
+            filter,
+            format,
+        }
+    }
+
+    /// Makes this the logger of the process. Only the first logger installed takes effect.
+    pub fn install(self) {
+        let max_level = self
+            .filter
+            .directives
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.level, Ord::max);
+        if log::set_boxed_logger(Box::new(self)).is_ok() {
+            log::set_max_level(max_level.into());
+        }
+    }
+
+    /// One line for `record`, without the newline.
+    pub fn format(&self, record: &Record, now: SystemTime) -> String {
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        match self.format {
+            LogFormat::Human => {
+                let day_millis = millis % 86_400_000;
+                format!(
+                    "{:02}:{:02}:{:02}.{:03} {:<5} {}: {}",
+                    day_millis / 3_600_000,
+                    day_millis / 60_000 % 60,
+                    day_millis / 1000 % 60,
+                    day_millis % 1000,
+                    record.level(),
+                    record.target(),
+                    record.args()
+                )
+            }
+            LogFormat::Json => serde_json::json!({
+                "ts": millis as u64,
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "msg": record.args().to_string(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = self.filter.level(metadata.target()).unwrap_or(self.level);
+        metadata.level() <= LevelFilter::from(level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record, SystemTime::now());
+        // There's nowhere to report a failure to write diagnostics.
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
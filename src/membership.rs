@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::rng::Rng;
+
+/// Timing of the failure detector.
+#[derive(Debug, Clone, Copy)]
+pub struct SwimConfig {
+    /// Time between probes, each of which checks one member.
+    pub probe_interval: Duration,
+    /// A member that doesn't ack a ping within this time is probed indirectly. Must be shorter
+    /// than `probe_interval`, which is when the probe gives up.
+    pub ping_timeout: Duration,
+    /// A suspect member that shows no sign of life within this time is declared faulty.
+    pub suspect_timeout: Duration,
+    /// Number of other members asked to ping a member that didn't ack.
+    pub indirect_probes: usize,
+}
+
+impl Default for SwimConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_millis(1000),
+            ping_timeout: Duration::from_millis(300),
+            suspect_timeout: Duration::from_millis(3000),
+            indirect_probes: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberState {
+    Alive,
+    /// Didn't ack a probe, directly or indirectly, but may just be slow.
+    Suspect,
+    /// Stayed suspect for too long. Still probed, so that it comes back once it recovers.
+    Faulty,
+}
+
+/// A change in the state of a member, for the workload to react to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipEvent {
+    /// A member not known before, such as a node that wasn't among the node ids of `init`.
+    /// The failure detector only knows the members it's given, so workloads report this
+    /// themselves, when they first hear of the node.
+    Joined(String),
+    Alive(String),
+    Suspect(String),
+    Faulty(String),
+}
+
+/// What the failure detector sends to other members. `seq` matches an ack to its ping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwimMessage {
+    Ping {
+        seq: u64,
+    },
+    /// Asks the receiver to ping `target` and forward its ack.
+    PingReq {
+        target: String,
+        seq: u64,
+    },
+    Ack {
+        seq: u64,
+    },
+}
+
+/// Messages to send, and the changes in membership, after an input.
+#[derive(Debug, Default)]
+pub struct Output {
+    pub messages: Vec<(String, SwimMessage)>,
+    pub events: Vec<MembershipEvent>,
+}
+
+struct Member {
+    state: MemberState,
+    /// When the member entered its state.
+    since: Instant,
+}
+
+struct Probe {
+    target: String,
+    seq: u64,
+    sent: Instant,
+    indirect: bool,
+}
+
+/// A SWIM-style failure detector. Every probe interval it pings one member, going through
+/// all of them in random order. A member that doesn't ack in time is pinged indirectly
+/// through a few others, which gets around a lossy or partitioned link, and becomes suspect
+/// if that doesn't work either. Suspects are declared faulty after a while. Any message from
+/// a member proves it alive.
+///
+/// Each node detects failures on its own; suspicions aren't disseminated. It does no IO, so
+/// the caller passes in the time and delivers the messages.
+pub struct Membership {
+    config: SwimConfig,
+    members: HashMap<String, Member>,
+    /// The order members are probed in, reshuffled after every pass.
+    order: Vec<String>,
+    next: usize,
+    seq: u64,
+    probe: Option<Probe>,
+    last_probe: Option<Instant>,
+    /// Pings sent on behalf of another member: the requester and its `seq`, by own `seq`.
+    relays: HashMap<u64, (String, u64, Instant)>,
+}
+
+impl Membership {
+    pub fn new(config: SwimConfig) -> Self {
+        Self {
+            config,
+            members: HashMap::new(),
+            order: Vec::new(),
+            next: 0,
+            seq: 0,
+            probe: None,
+            last_probe: None,
+            relays: HashMap::new(),
+        }
+    }
+
+    /// Replaces the members. Those already known keep their state, new ones start alive.
+    pub fn set_members(&mut self, members: impl IntoIterator<Item = String>, now: Instant) {
+        let mut known = std::mem::take(&mut self.members);
+        self.members = members
+            .into_iter()
+            .map(|id| {
+                let member = known.remove(&id).unwrap_or(Member {
+                    state: MemberState::Alive,
+                    since: now,
+                });
+                (id, member)
+            })
+            .collect();
+        // Sorted, like the helpers below, so that a seeded `Rng` makes the same choices.
+        self.order = self.members.keys().cloned().collect();
+        self.order.sort();
+        self.next = self.order.len();
+    }
+
+    pub fn state(&self, id: &str) -> Option<MemberState> {
+        self.members.get(id).map(|member| member.state)
+    }
+
+    /// Whether messages to `id` are worth sending: it isn't a member known to be faulty.
+    pub fn is_available(&self, id: &str) -> bool {
+        self.state(id) != Some(MemberState::Faulty)
+    }
+
+    /// Records a sign of life from `id`, such as any message from it.
+    pub fn observe(&mut self, id: &str, now: Instant) -> Option<MembershipEvent> {
+        let member = self.members.get_mut(id)?;
+        if member.state == MemberState::Alive {
+            return None;
+        }
+        member.state = MemberState::Alive;
+        member.since = now;
+        Some(MembershipEvent::Alive(id.to_string()))
+    }
+
+    /// Advances probes and suspicions, and starts the next probe once it's due.
+    pub fn tick(&mut self, now: Instant, rng: &mut Rng) -> Output {
+        let mut output = Output::default();
+
+        for (id, member) in &mut self.members {
+            if member.state == MemberState::Suspect
+                && now >= member.since + self.config.suspect_timeout
+            {
+                member.state = MemberState::Faulty;
+                member.since = now;
+                output.events.push(MembershipEvent::Faulty(id.clone()));
+            }
+        }
+        self.relays
+            .retain(|_, (_, _, sent)| now < *sent + self.config.probe_interval);
+
+        if let Some(probe) = &mut self.probe {
+            if now >= probe.sent + self.config.probe_interval {
+                let target = probe.target.clone();
+                self.probe = None;
+                if let Some(member) = self.members.get_mut(&target) {
+                    if member.state == MemberState::Alive {
+                        member.state = MemberState::Suspect;
+                        member.since = now;
+                        output.events.push(MembershipEvent::Suspect(target));
+                    }
+                }
+            } else if !probe.indirect && now >= probe.sent + self.config.ping_timeout {
+                probe.indirect = true;
+                let mut helpers: Vec<String> = self
+                    .members
+                    .iter()
+                    .filter(|(id, member)| {
+                        **id != probe.target && member.state == MemberState::Alive
+                    })
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                helpers.sort();
+                for helper in rng.sample(&helpers, self.config.indirect_probes) {
+                    let request = SwimMessage::PingReq {
+                        target: probe.target.clone(),
+                        seq: probe.seq,
+                    };
+                    output.messages.push((helper, request));
+                }
+            }
+        }
+
+        let due = self
+            .last_probe
+            .is_none_or(|last| now >= last + self.config.probe_interval);
+        if self.probe.is_none() && due && !self.order.is_empty() {
+            if self.next >= self.order.len() {
+                self.order = rng.sample(&self.order, self.order.len());
+                self.next = 0;
+            }
+            let target = self.order[self.next].clone();
+            self.next += 1;
+            self.seq += 1;
+            self.last_probe = Some(now);
+            self.probe = Some(Probe {
+                target: target.clone(),
+                seq: self.seq,
+                sent: now,
+                indirect: false,
+            });
+            output
+                .messages
+                .push((target, SwimMessage::Ping { seq: self.seq }));
+        }
+        output
+    }
+
+    /// Handles a message from `from`, which proves it alive.
+    pub fn receive(&mut self, from: &str, message: SwimMessage, now: Instant) -> Output {
+        let mut output = Output::default();
+        output.events.extend(self.observe(from, now));
+
+        match message {
+            SwimMessage::Ping { seq } => {
+                output
+                    .messages
+                    .push((from.to_string(), SwimMessage::Ack { seq }));
+            }
+
+            SwimMessage::PingReq { target, seq } => {
+                self.seq += 1;
+                self.relays.insert(self.seq, (from.to_string(), seq, now));
+                output
+                    .messages
+                    .push((target, SwimMessage::Ping { seq: self.seq }));
+            }
+
+            SwimMessage::Ack { seq } => {
+                if let Some((requester, seq, _)) = self.relays.remove(&seq) {
+                    output.messages.push((requester, SwimMessage::Ack { seq }));
+                } else if let Some(probe) = self.probe.take_if(|probe| probe.seq == seq) {
+                    // An indirect ack comes from a helper, not the target.
+                    output.events.extend(self.observe(&probe.target, now));
+                }
+            }
+        }
+        output
+    }
+}
@@ -0,0 +1,185 @@
+use std::cell::RefCell;
+use std::io::Write;
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use crate::error::{ErrorBody, ErrorCode};
+use crate::metrics;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message<B> {
+    pub src: String,
+    pub dest: String,
+    pub body: B,
+}
+
+/// Just enough of a body to address an error reply when the full body can't be decoded,
+/// or to route a raw message.
+#[derive(Debug, Deserialize)]
+struct RequestHeader {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    msg_id: Option<u64>,
+    in_reply_to: Option<u64>,
+}
+
+/// A message whose body is kept as the JSON it arrived as, whatever its type and fields.
+/// It can be looked at, decoded once it's known what it is, or sent on unchanged, with
+/// fields this node doesn't know about intact.
+pub type RawMessage = Message<Box<RawValue>>;
+
+impl RawMessage {
+    /// Parses a line received from Maelstrom. Only fails if the line isn't a message at all.
+    pub fn parse_raw(line: impl AsRef<[u8]>) -> Result<Self, anyhow::Error> {
+        serde_json::from_slice(line.as_ref()).context("Failed to deserialize provided input")
+    }
+
+    fn header(&self) -> Option<RequestHeader> {
+        serde_json::from_str(self.body.get()).ok()
+    }
+
+    /// The `type` of the body, if it has one.
+    pub fn kind(&self) -> Option<String> {
+        self.header().and_then(|header| header.kind)
+    }
+
+    pub fn msg_id(&self) -> Option<u64> {
+        self.header().and_then(|header| header.msg_id)
+    }
+
+    pub fn in_reply_to(&self) -> Option<u64> {
+        self.header().and_then(|header| header.in_reply_to)
+    }
+
+    pub fn decode<B: DeserializeOwned>(&self) -> Result<Message<B>, serde_json::Error> {
+        Ok(Message {
+            src: self.src.clone(),
+            dest: self.dest.clone(),
+            body: serde_json::from_str(self.body.get())?,
+        })
+    }
+}
+
+impl<B> Message<B> {
+    pub fn reply<R>(&self, body: R) -> Message<R> {
+        Message {
+            src: self.dest.clone(),
+            dest: self.src.clone(),
+            body,
+        }
+    }
+
+    pub fn error_reply<R: From<ErrorBody>>(
+        &self,
+        in_reply_to: u64,
+        code: ErrorCode,
+        text: impl Into<String>,
+    ) -> Message<R> {
+        self.reply(ErrorBody::new(in_reply_to, code, text).into())
+    }
+}
+
+impl<B: Serialize> Message<B> {
+    pub fn send<W: Write>(&self, writer: &mut W) -> Result<(), anyhow::Error> {
+        write_line(self, writer)
+    }
+}
+
+/// A message that borrows its addresses, so that sending it doesn't copy them.
+#[derive(Debug, Serialize)]
+pub struct Outgoing<'a, B> {
+    pub src: &'a str,
+    pub dest: &'a str,
+    pub body: B,
+}
+
+impl<B> Message<B> {
+    /// Like `reply`, but borrows the addresses from this message instead of cloning them.
+    pub fn reply_ref<R>(&self, body: R) -> Outgoing<'_, R> {
+        Outgoing {
+            src: &self.dest,
+            dest: &self.src,
+            body,
+        }
+    }
+}
+
+impl<B: Serialize> Outgoing<'_, B> {
+    pub fn send<W: Write>(&self, writer: &mut W) -> Result<(), anyhow::Error> {
+        write_line(self, writer)
+    }
+}
+
+thread_local! {
+    /// The line being sent, reused so that sending allocates nothing once it's large enough.
+    static LINE: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Writes `message` as a line, and counts it in the metrics.
+fn write_line<T: Serialize, W: Write>(message: &T, writer: &mut W) -> Result<(), anyhow::Error> {
+    LINE.with_borrow_mut(|line| {
+        line.clear();
+        serde_json::to_writer(&mut *line, message).context("Failed to serialize reply message")?;
+        metrics::record_sent(line);
+        line.push(b'\n');
+        writer.write_all(line).context("Failed to write message")
+    })
+}
+
+impl<B: DeserializeOwned + From<ErrorBody>> Message<B> {
+    /// Parses a line received from Maelstrom.
+    ///
+    /// A line whose envelope is intact but whose body can't be decoded yields `Ok(Err(reply))`
+    /// with a `not-supported` (unknown type) or `malformed-request` error to send back.
+    /// Lines that can't be answered at all are returned as errors.
+    pub fn parse(line: impl AsRef<[u8]>) -> Result<Result<Self, Self>, anyhow::Error> {
+        let line = line.as_ref();
+        let error = match serde_json::from_slice(line) {
+            Ok(message) => return Ok(Ok(message)),
+            Err(error) => error,
+        };
+
+        let header: Message<RequestHeader> = serde_json::from_slice(line)
+            .with_context(|| format!("Failed to deserialize provided input: {error}"))?;
+
+        let Some(msg_id) = header.body.msg_id else {
+            return Err(error).context("Failed to deserialize message without msg_id");
+        };
+
+        let (code, text) = match &header.body.kind {
+            Some(kind) if error.to_string().starts_with("unknown variant") => (
+                ErrorCode::NotSupported,
+                format!("Unsupported message type: {kind}"),
+            ),
+            Some(kind) => (
+                ErrorCode::MalformedRequest,
+                format!("Malformed {kind} request: {error}"),
+            ),
+            None => (
+                ErrorCode::MalformedRequest,
+                format!("Malformed request: {error}"),
+            ),
+        };
+
+        Ok(Err(header.error_reply(msg_id, code, text)))
+    }
+}
+
+impl<B: Serialize + DeserializeOwned + From<ErrorBody>> Message<B> {
+    /// Like `parse`, but returns the message, or the error reply, as JSON, so that it can be
+    /// looked at without naming the body type, as fuzz targets do. The message is encoded
+    /// again on the way, so that a body which decodes but can't be sent fails here too.
+    pub fn parse_value(line: impl AsRef<[u8]>) -> Result<Result<Value, Value>, anyhow::Error> {
+        let encode = |message: Self| {
+            serde_json::to_value(message).context("Failed to serialize parsed message")
+        };
+        Ok(match Self::parse(line)? {
+            Ok(message) => Ok(encode(message)?),
+            Err(error_reply) => Err(encode(error_reply)?),
+        })
+    }
+}
@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Message types counted on their own. Any further type is counted as `other`, so that a
+/// node sent junk can't grow the registry without bound.
+const MAX_KINDS: usize = 256;
+
+/// Latency buckets: bucket `i` counts handlers that took less than 2^i microseconds, and the
+/// last one those that took longer.
+const BUCKETS: usize = 32;
+
+/// The type of a message, interned so that counting and passing it on allocates nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Kind(&'static str);
+
+impl Kind {
+    pub const OTHER: Kind = Kind("other");
+
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Histogram {
+    count: u64,
+    sum_micros: u64,
+    max_micros: u64,
+    buckets: [u64; BUCKETS],
+}
+
+impl Histogram {
+    fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum_micros = self.sum_micros.saturating_add(micros);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    /// The upper bound of the bucket the `q` quantile falls in, capped at the maximum.
+    fn quantile(&self, q: f64) -> u64 {
+        let rank = (q * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return (1u64 << bucket).saturating_sub(1).min(self.max_micros);
+            }
+        }
+        self.max_micros
+    }
+
+    fn summary(&self) -> Latency {
+        Latency {
+            count: self.count,
+            mean_us: self.sum_micros.checked_div(self.count).unwrap_or_default(),
+            p50_us: self.quantile(0.5),
+            p99_us: self.quantile(0.99),
+            max_us: self.max_micros,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    kinds: BTreeSet<&'static str>,
+    received: BTreeMap<Kind, u64>,
+    sent: BTreeMap<Kind, Traffic>,
+    counters: BTreeMap<&'static str, u64>,
+    latency: BTreeMap<Kind, Histogram>,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Registry {
+            kinds: BTreeSet::new(),
+            received: BTreeMap::new(),
+            sent: BTreeMap::new(),
+            counters: BTreeMap::new(),
+            latency: BTreeMap::new(),
+        }
+    }
+
+    fn intern(&mut self, kind: &str) -> Kind {
+        if let Some(&kind) = self.kinds.get(kind) {
+            return Kind(kind);
+        }
+        if self.kinds.len() >= MAX_KINDS {
+            return Kind::OTHER;
+        }
+        let kind: &'static str = Box::leak(kind.into());
+        self.kinds.insert(kind);
+        Kind(kind)
+    }
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+fn registry() -> MutexGuard<'static, Registry> {
+    // The counts are still good if a thread panicked while holding the lock.
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(borrow)]
+    body: BodyHeader<'a>,
+}
+
+#[derive(Deserialize)]
+struct BodyHeader<'a> {
+    #[serde(borrow, rename = "type")]
+    kind: Option<Cow<'a, str>>,
+}
+
+/// Counts a line received from Maelstrom, and returns the type of its message, or `other`
+/// if it has none.
+pub fn record_received(line: &[u8]) -> Kind {
+    let header = serde_json::from_slice::<Header>(line).ok();
+    let mut registry = registry();
+    let kind = match header.and_then(|header| header.body.kind) {
+        Some(kind) => registry.intern(&kind),
+        None => Kind::OTHER,
+    };
+    *registry.received.entry(kind).or_default() += 1;
+    kind
+}
+
+/// Counts a line sent, and its bytes, by the type of its message. The type is found
+/// without parsing the line, as the `type` tag of a body is serialized first.
+pub fn record_sent(line: &[u8]) {
+    const TAG: &[u8] = b"\"body\":{\"type\":\"";
+    let kind = line
+        .windows(TAG.len())
+        .position(|window| window == TAG)
+        .map(|start| &line[start + TAG.len()..])
+        .and_then(|rest| Some(&rest[..rest.iter().position(|&b| b == b'"')?]))
+        .and_then(|kind| std::str::from_utf8(kind).ok());
+
+    let mut registry = registry();
+    let kind = match kind {
+        Some(kind) => registry.intern(kind),
+        None => Kind::OTHER,
+    };
+    let traffic = registry.sent.entry(kind).or_default();
+    traffic.messages += 1;
+    traffic.bytes += line.len() as u64;
+}
+
+/// Records how long handling a message of `kind` took.
+pub fn record_latency(kind: Kind, elapsed: Duration) {
+    registry().latency.entry(kind).or_default().record(elapsed);
+}
+
+/// Requests sent again because no reply arrived in time, or an error did.
+pub const RETRIES: &str = "retries";
+
+/// Adds one to the counter `name`, such as `RETRIES`.
+pub fn increment(name: &'static str) {
+    *registry().counters.entry(name).or_default() += 1;
+}
+
+/// Messages of one type sent, and their size.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Traffic {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+/// How long the handlers of one message type took, in microseconds. The quantiles are upper
+/// bounds, within a factor of two.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Latency {
+    pub count: u64,
+    pub mean_us: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// Everything the node counted since it started, by message type.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub received: BTreeMap<String, u64>,
+    pub sent: BTreeMap<String, Traffic>,
+    pub counters: BTreeMap<String, u64>,
+    pub latency: BTreeMap<String, Latency>,
+}
+
+pub fn snapshot() -> Stats {
+    let registry = registry();
+    Stats {
+        received: registry
+            .received
+            .iter()
+            .map(|(kind, &count)| (kind.as_str().to_string(), count))
+            .collect(),
+        sent: registry
+            .sent
+            .iter()
+            .map(|(kind, &traffic)| (kind.as_str().to_string(), traffic))
+            .collect(),
+        counters: registry
+            .counters
+            .iter()
+            .map(|(&name, &count)| (name.to_string(), count))
+            .collect(),
+        latency: registry
+            .latency
+            .iter()
+            .map(|(kind, histogram)| (kind.as_str().to_string(), histogram.summary()))
+            .collect(),
+    }
+}
+
+/// Writes the stats to the log, at the end of a node's life.
+pub fn log_snapshot() {
+    if log::log_enabled!(log::Level::Info) {
+        match serde_json::to_string(&snapshot()) {
+            Ok(stats) => log::info!("Stats: {stats}"),
+            Err(error) => log::error!("Failed to serialize stats: {error}"),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::Bound;
+
+use crate::hlc::HlcTimestamp;
+
+/// A multi-versioned key-value store: every write adds a version of its key instead of
+/// replacing the value, so that a reader sees the store as it was at a snapshot, whatever
+/// was written after it. Versions are ordered by `T`, such as the HLC timestamps of the
+/// writes, or the ids of the transactions that made them.
+///
+/// Deleting a key writes a tombstone version, so that snapshots from before the deletion
+/// still read the value. Versions no snapshot still reads are dropped with `gc`.
+#[derive(Debug, Clone)]
+pub struct MvccStore<K, V, T = HlcTimestamp> {
+    versions: HashMap<K, BTreeMap<T, Option<V>>>,
+}
+
+impl<K, V, T> Default for MvccStore<K, V, T> {
+    fn default() -> Self {
+        Self {
+            versions: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, T: Ord + Copy> MvccStore<K, V, T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of keys with at least one version, tombstones included.
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    /// Number of versions of every key, tombstones included.
+    pub fn version_count(&self) -> usize {
+        self.versions.values().map(BTreeMap::len).sum()
+    }
+
+    /// Adds the version `version` of `key`. A version written again, e.g. by a replicated
+    /// write that arrives twice, replaces the value it had.
+    pub fn write(&mut self, key: K, version: T, value: V) {
+        self.versions
+            .entry(key)
+            .or_default()
+            .insert(version, Some(value));
+    }
+
+    /// Deletes `key` from the version `version` on.
+    pub fn delete(&mut self, key: K, version: T) {
+        self.versions.entry(key).or_default().insert(version, None);
+    }
+
+    /// The value of `key` at `snapshot`: that of its latest version at or before it.
+    pub fn read(&self, key: &K, snapshot: T) -> Option<&V> {
+        let versions = self.versions.get(key)?;
+        let (_, value) = versions.range(..=snapshot).next_back()?;
+        value.as_ref()
+    }
+
+    /// The value of the latest version of `key`.
+    pub fn latest(&self, key: &K) -> Option<&V> {
+        let (_, value) = self.versions.get(key)?.last_key_value()?;
+        value.as_ref()
+    }
+
+    /// The latest version of `key`, deletions included.
+    pub fn latest_version(&self, key: &K) -> Option<T> {
+        let (version, _) = self.versions.get(key)?.last_key_value()?;
+        Some(*version)
+    }
+
+    /// Whether `key` got a version after `snapshot`, which a transaction that read at
+    /// `snapshot` conflicts with under snapshot isolation if it writes the key too.
+    pub fn written_since(&self, key: &K, snapshot: T) -> bool {
+        self.versions.get(key).is_some_and(|versions| {
+            versions
+                .range((Bound::Excluded(snapshot), Bound::Unbounded))
+                .next()
+                .is_some()
+        })
+    }
+
+    /// The store as it was at `snapshot`, which later writes don't change.
+    pub fn snapshot(&self, snapshot: T) -> Snapshot<'_, K, V, T> {
+        Snapshot {
+            store: self,
+            at: snapshot,
+        }
+    }
+
+    /// Drops the versions that no snapshot at or after `horizon` reads: those older than
+    /// the version each key has at `horizon`, and that version too if it's a tombstone.
+    /// Readers must not take snapshots before `horizon` afterwards, such as the start of
+    /// the oldest transaction still running. Returns how many versions were dropped.
+    pub fn gc(&mut self, horizon: T) -> usize {
+        let mut dropped = 0;
+        self.versions.retain(|_, versions| {
+            let Some(visible) = versions.range(..=horizon).next_back().map(|(v, _)| *v) else {
+                return true;
+            };
+            let newer = versions.split_off(&visible);
+            dropped += versions.len();
+            *versions = newer;
+            if versions.get(&visible).is_some_and(Option::is_none) {
+                versions.remove(&visible);
+                dropped += 1;
+            }
+            !versions.is_empty()
+        });
+        dropped
+    }
+}
+
+/// A read-only view of an `MvccStore` at a version.
+#[derive(Debug)]
+pub struct Snapshot<'a, K, V, T> {
+    store: &'a MvccStore<K, V, T>,
+    at: T,
+}
+
+impl<K: Eq + Hash, V, T: Ord + Copy> Snapshot<'_, K, V, T> {
+    /// The version the snapshot was taken at.
+    pub fn version(&self) -> T {
+        self.at
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.store.read(key, self.at)
+    }
+
+    /// Every key with a value at the snapshot, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.store
+            .versions
+            .keys()
+            .filter_map(|key| Some((key, self.get(key)?)))
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// A node id interned in a `NodeIds` table. It's a small index, so that it's cheap to copy,
+/// hash, and compare, unlike the string it stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+/// The strings behind `NodeId`s. Nodes fill it with the ids `init` lists, and intern any
+/// other id as they come across it. Ids are only turned back into strings to be sent.
+#[derive(Debug, Default)]
+pub struct NodeIds {
+    names: Vec<String>,
+    ids: HashMap<String, NodeId>,
+}
+
+impl NodeIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> NodeId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = NodeId(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// The id of `name`, if it was interned.
+    pub fn get(&self, name: &str) -> Option<NodeId> {
+        self.ids.get(name).copied()
+    }
+
+    /// The string behind `id`. Panics if `id` comes from another table.
+    pub fn name(&self, id: NodeId) -> &str {
+        &self.names[id.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for NodeIds {
+    fn from_iter<I: IntoIterator<Item = S>>(names: I) -> Self {
+        let mut ids = Self::new();
+        for name in names {
+            ids.intern(name.as_ref());
+        }
+        ids
+    }
+}
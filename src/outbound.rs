@@ -0,0 +1,116 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::Instant;
+
+use crate::metrics;
+
+/// Messages that waited in a queue because their destination's rate was used up.
+pub const THROTTLED: &str = "messages_throttled";
+
+/// Allows `rate` messages a second on average, and bursts of up to `burst` messages. Starts
+/// full.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    /// Takes a token if one is left, after adding those earned since the last call.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let earned = now.saturating_duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + earned).min(self.burst);
+        self.last = self.last.max(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// A queue of messages to one destination, and the bucket that paces it.
+#[derive(Debug)]
+struct Queue<M> {
+    bucket: TokenBucket,
+    messages: VecDeque<M>,
+}
+
+/// Messages to send, in a queue per destination that goes out as fast as its token bucket
+/// allows, so that a node doesn't send a peer more at once than it can take, such as
+/// everything that piled up while a partition kept them apart. Messages to a destination
+/// keep their order.
+#[derive(Debug)]
+pub struct OutboundQueues<D, M> {
+    rate: f64,
+    burst: u32,
+    queues: HashMap<D, Queue<M>>,
+}
+
+impl<D: Eq + Hash + Clone, M> OutboundQueues<D, M> {
+    /// Sends at most `rate` messages a second to each destination on average, and `burst`
+    /// at once.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate,
+            burst,
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Returns `message` if it may be sent to `dest` right away, or queues it until
+    /// `drain` lets it go.
+    pub fn push(&mut self, dest: D, message: M, now: Instant) -> Option<M> {
+        let (rate, burst) = (self.rate, self.burst);
+        let queue = self.queues.entry(dest).or_insert_with(|| Queue {
+            bucket: TokenBucket::new(rate, burst, now),
+            messages: VecDeque::new(),
+        });
+        if queue.messages.is_empty() && queue.bucket.try_take(now) {
+            return Some(message);
+        }
+        metrics::increment(THROTTLED);
+        queue.messages.push_back(message);
+        None
+    }
+
+    /// Takes the queued messages that may be sent by now, with their destinations.
+    pub fn drain(&mut self, now: Instant) -> Vec<(D, M)> {
+        let mut ready = Vec::new();
+        for (dest, queue) in &mut self.queues {
+            while !queue.messages.is_empty() && queue.bucket.try_take(now) {
+                let message = queue.messages.pop_front().expect("Checked it's not empty");
+                ready.push((dest.clone(), message));
+            }
+        }
+        ready
+    }
+
+    /// Number of messages waiting to go to `dest`.
+    pub fn queued(&self, dest: &D) -> usize {
+        self.queues
+            .get(dest)
+            .map_or(0, |queue| queue.messages.len())
+    }
+
+    /// Number of messages waiting, to any destination.
+    pub fn len(&self) -> usize {
+        self.queues.values().map(|queue| queue.messages.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,414 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::message::Message;
+
+/// A proposer that hasn't reached a decision within a round timeout starts a new round with
+/// a higher ballot. Rounds are jittered by up to one more timeout, so that competing
+/// proposers don't keep preempting each other.
+const ROUND_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Ballots are ordered by round first, and the node id makes them unique across proposers.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: u64,
+    pub node_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcceptedValue<V> {
+    pub ballot: Ballot,
+    pub value: V,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PaxosBody<V> {
+    Prepare {
+        msg_id: u64,
+        ballot: Ballot,
+    },
+
+    /// The acceptor won't accept anything below `ballot` anymore, and reports the value it
+    /// accepted last, if any.
+    Promise {
+        msg_id: u64,
+        in_reply_to: u64,
+        ballot: Ballot,
+        accepted: Option<AcceptedValue<V>>,
+    },
+
+    Accept {
+        msg_id: u64,
+        ballot: Ballot,
+        value: V,
+    },
+
+    AcceptOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        ballot: Ballot,
+    },
+
+    /// Rejects a `prepare` or `accept`, as the acceptor already promised a higher ballot.
+    Nack {
+        msg_id: u64,
+        in_reply_to: u64,
+        ballot: Ballot,
+        promised: Ballot,
+    },
+
+    /// Sent by the proposer to every other node once a majority accepted its value.
+    Decided {
+        msg_id: u64,
+        value: V,
+    },
+}
+
+#[derive(Deserialize)]
+struct BodyType<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+struct Envelope<'a> {
+    #[serde(borrow)]
+    body: BodyType<'a>,
+}
+
+/// Returns the message if the line carries one of the Paxos bodies.
+pub fn parse<V: DeserializeOwned>(
+    line: impl AsRef<[u8]>,
+) -> Result<Option<Message<PaxosBody<V>>>, anyhow::Error> {
+    let line = line.as_ref();
+    let envelope: Envelope =
+        serde_json::from_slice(line).context("Failed to deserialize provided input to STDIN.")?;
+
+    match envelope.body.kind.as_ref() {
+        "prepare" | "promise" | "accept" | "accept_ok" | "nack" | "decided" => {
+            let message =
+                serde_json::from_slice(line).context("Failed to deserialize Paxos message.")?;
+            Ok(Some(message))
+        }
+        _ => Ok(None),
+    }
+}
+
+enum Phase<V> {
+    /// Waiting for a majority of promises, with the value each acceptor accepted last.
+    Prepare(HashMap<String, Option<AcceptedValue<V>>>),
+    /// Waiting for a majority to accept `value`.
+    Accept { value: V, acks: HashSet<String> },
+}
+
+/// The round this node is running as a proposer.
+struct Round<V> {
+    ballot: Ballot,
+    phase: Phase<V>,
+    deadline: Instant,
+}
+
+/// A single-decree Paxos node that acts as proposer, acceptor, and learner, and doesn't do
+/// any I/O itself: messages are fed in with `handle`, time with `tick`, and outgoing
+/// messages are collected with `take_messages`. Once a value is chosen, every node that
+/// learns about it returns it from `decided`.
+pub struct Paxos<V> {
+    node_id: String,
+    node_ids: Vec<String>,
+    /// The value this node proposes, unless it learns about one accepted before.
+    proposal: Option<V>,
+    round: Option<Round<V>>,
+    highest_round: u64,
+    promised: Option<Ballot>,
+    accepted: Option<AcceptedValue<V>>,
+    decided: Option<V>,
+    msg_id: u64,
+    outbox: Vec<Message<PaxosBody<V>>>,
+}
+
+impl<V: Clone> Paxos<V> {
+    pub fn new(node_id: impl Into<String>, node_ids: &[String]) -> Self {
+        Self {
+            node_id: node_id.into(),
+            node_ids: node_ids.to_vec(),
+            proposal: None,
+            round: None,
+            highest_round: 0,
+            promised: None,
+            accepted: None,
+            decided: None,
+            msg_id: 0,
+            outbox: Vec::new(),
+        }
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// The chosen value, once this node knows it. It never changes afterwards, and may be a
+    /// value proposed by another node.
+    pub fn decided(&self) -> Option<&V> {
+        self.decided.as_ref()
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message<PaxosBody<V>>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Starts proposing `value`, and keeps retrying with higher ballots on `tick` until some
+    /// value is decided. Proposing again only replaces the value for later rounds.
+    pub fn propose(&mut self, value: V, now: Instant) {
+        if self.decided.is_some() {
+            return;
+        }
+
+        self.proposal = Some(value);
+        if self.round.is_none() {
+            self.start_round(now);
+        }
+    }
+
+    /// Starts a new round when the current one didn't reach a decision in time.
+    pub fn tick(&mut self, now: Instant) {
+        if self.decided.is_some() {
+            return;
+        }
+
+        if self
+            .round
+            .as_ref()
+            .is_some_and(|round| now >= round.deadline)
+        {
+            self.start_round(now);
+        }
+    }
+
+    pub fn handle(&mut self, message: Message<PaxosBody<V>>, now: Instant) {
+        let src = message.src;
+
+        match message.body {
+            PaxosBody::Prepare { msg_id, ballot } => {
+                let body = match self.receive_prepare(&ballot) {
+                    Ok(accepted) => PaxosBody::Promise {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: msg_id,
+                        ballot,
+                        accepted,
+                    },
+                    Err(promised) => PaxosBody::Nack {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: msg_id,
+                        ballot,
+                        promised,
+                    },
+                };
+                self.send(src, body);
+            }
+
+            PaxosBody::Promise {
+                ballot, accepted, ..
+            } => self.promised(src, &ballot, accepted),
+
+            PaxosBody::Accept {
+                msg_id,
+                ballot,
+                value,
+            } => {
+                let body = match self.receive_accept(&ballot, value) {
+                    Ok(()) => PaxosBody::AcceptOk {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: msg_id,
+                        ballot,
+                    },
+                    Err(promised) => PaxosBody::Nack {
+                        msg_id: self.incremented_msg_id(),
+                        in_reply_to: msg_id,
+                        ballot,
+                        promised,
+                    },
+                };
+                self.send(src, body);
+            }
+
+            PaxosBody::AcceptOk { ballot, .. } => self.accepted_by(src, &ballot),
+
+            PaxosBody::Nack {
+                ballot, promised, ..
+            } => {
+                self.highest_round = self.highest_round.max(promised.round);
+                // Somebody else is ahead, so give them a round to finish before retrying.
+                if let Some(round) = self.round.as_mut().filter(|round| round.ballot == ballot) {
+                    round.deadline = round_deadline(now);
+                }
+            }
+
+            PaxosBody::Decided { value, .. } => self.decide(value),
+        }
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn send(&mut self, dest: String, body: PaxosBody<V>) {
+        self.outbox.push(Message {
+            src: self.node_id.clone(),
+            dest,
+            body,
+        });
+    }
+
+    fn peers(&self) -> Vec<String> {
+        self.node_ids
+            .iter()
+            .filter(|&id| *id != self.node_id)
+            .cloned()
+            .collect()
+    }
+
+    fn quorum(&self) -> usize {
+        self.node_ids.len() / 2 + 1
+    }
+
+    /// This node is one of the acceptors, so its own messages are handled directly.
+    fn start_round(&mut self, now: Instant) {
+        self.highest_round += 1;
+        let ballot = Ballot {
+            round: self.highest_round,
+            node_id: self.node_id.clone(),
+        };
+        self.round = Some(Round {
+            ballot: ballot.clone(),
+            phase: Phase::Prepare(HashMap::new()),
+            deadline: round_deadline(now),
+        });
+
+        for peer in self.peers() {
+            let body = PaxosBody::Prepare {
+                msg_id: self.incremented_msg_id(),
+                ballot: ballot.clone(),
+            };
+            self.send(peer, body);
+        }
+        if let Ok(accepted) = self.receive_prepare(&ballot) {
+            self.promised(self.node_id.clone(), &ballot, accepted);
+        }
+    }
+
+    fn receive_prepare(&mut self, ballot: &Ballot) -> Result<Option<AcceptedValue<V>>, Ballot> {
+        self.highest_round = self.highest_round.max(ballot.round);
+        match &self.promised {
+            Some(promised) if promised > ballot => Err(promised.clone()),
+            _ => {
+                self.promised = Some(ballot.clone());
+                Ok(self.accepted.clone())
+            }
+        }
+    }
+
+    fn receive_accept(&mut self, ballot: &Ballot, value: V) -> Result<(), Ballot> {
+        self.highest_round = self.highest_round.max(ballot.round);
+        match &self.promised {
+            Some(promised) if promised > ballot => Err(promised.clone()),
+            _ => {
+                self.promised = Some(ballot.clone());
+                self.accepted = Some(AcceptedValue {
+                    ballot: ballot.clone(),
+                    value,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Once a majority promised, proposes the value accepted with the highest ballot among
+    /// them, as it may already have been chosen, and this node's own value otherwise.
+    fn promised(&mut self, src: String, ballot: &Ballot, accepted: Option<AcceptedValue<V>>) {
+        let quorum = self.quorum();
+        let Some(round) = self.round.as_mut().filter(|round| round.ballot == *ballot) else {
+            return;
+        };
+        let Phase::Prepare(promises) = &mut round.phase else {
+            return;
+        };
+
+        promises.insert(src, accepted);
+        if promises.len() < quorum {
+            return;
+        }
+
+        let value = promises
+            .values()
+            .flatten()
+            .max_by(|a, b| a.ballot.cmp(&b.ballot))
+            .map(|accepted| accepted.value.clone())
+            .or_else(|| self.proposal.clone());
+        let Some(value) = value else {
+            return;
+        };
+
+        round.phase = Phase::Accept {
+            value: value.clone(),
+            acks: HashSet::new(),
+        };
+        for peer in self.peers() {
+            let body = PaxosBody::Accept {
+                msg_id: self.incremented_msg_id(),
+                ballot: ballot.clone(),
+                value: value.clone(),
+            };
+            self.send(peer, body);
+        }
+        if self.receive_accept(ballot, value).is_ok() {
+            self.accepted_by(self.node_id.clone(), ballot);
+        }
+    }
+
+    fn accepted_by(&mut self, src: String, ballot: &Ballot) {
+        let quorum = self.quorum();
+        let Some(round) = self.round.as_mut().filter(|round| round.ballot == *ballot) else {
+            return;
+        };
+        let Phase::Accept { value, acks } = &mut round.phase else {
+            return;
+        };
+
+        acks.insert(src);
+        if acks.len() < quorum {
+            return;
+        }
+
+        let value = value.clone();
+        for peer in self.peers() {
+            let body = PaxosBody::Decided {
+                msg_id: self.incremented_msg_id(),
+                value: value.clone(),
+            };
+            self.send(peer, body);
+        }
+        self.decide(value);
+    }
+
+    fn decide(&mut self, value: V) {
+        if self.decided.is_none() {
+            self.decided = Some(value);
+        }
+        self.round = None;
+    }
+}
+
+fn round_deadline(now: Instant) -> Instant {
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = random % ROUND_TIMEOUT.as_millis() as u64;
+    now + ROUND_TIMEOUT + Duration::from_millis(jitter)
+}
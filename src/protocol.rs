@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The version of this crate's extensions to Maelstrom's protocol. Maelstrom's `init` has
+/// no `protocol_version`, which is taken as version 0: plain Maelstrom, with no extensions.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Extensions to the protocol, by name, each with the version of it that's spoken, e.g.
+/// `{"compressed_gossip": 1}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(pub BTreeMap<String, u32>);
+
+impl Capabilities {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The version of `name` spoken, if any.
+    pub fn version(&self, name: &str) -> Option<u32> {
+        self.0.get(name).copied()
+    }
+
+    /// The extensions both sides know, each at the lower of the two versions.
+    pub fn agree(&self, other: &Capabilities) -> Capabilities {
+        Capabilities(
+            self.0
+                .iter()
+                .filter_map(|(name, &ours)| {
+                    let theirs = other.version(name)?;
+                    Some((name.clone(), ours.min(theirs)))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for Capabilities {
+    type Err = anyhow::Error;
+
+    /// Parses a comma separated list of `name=version`, e.g. `compressed_gossip=1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|capability| !capability.is_empty())
+            .map(|capability| {
+                let (name, version) = capability
+                    .split_once('=')
+                    .with_context(|| format!("Expected name=version, got {capability}"))?;
+                let version = version
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid version of {name}: {version}"))?;
+                Ok((name.trim().to_string(), version))
+            })
+            .collect::<Result<_, anyhow::Error>>()
+            .map(Capabilities)
+    }
+}
+
+/// The optional fields of `init` and `init_ok` that negotiate the protocol, flattened into
+/// their bodies. In `init`, whoever inits the node offers a version and the extensions every
+/// node of the cluster may use. In `init_ok`, the node answers with what it agreed to. Left
+/// out of `init`, nothing is negotiated, and `init_ok` leaves them out too.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Capabilities::is_empty")]
+    pub capabilities: Capabilities,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// What this node can speak.
+    supported: Capabilities,
+    /// What was agreed at `init`, if it negotiated anything.
+    agreed: Option<Handshake>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    supported: Capabilities(BTreeMap::new()),
+    agreed: None,
+});
+
+fn state() -> MutexGuard<'static, State> {
+    STATE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Makes this node able to agree to `version` of the extension `name`. Must be called
+/// before `init` arrives.
+pub fn support(name: &str, version: u32) {
+    state().supported.0.insert(name.to_string(), version);
+}
+
+/// Agrees to what `offered` offers, as far as this node supports it. Called by the runtime
+/// for every `init`.
+pub fn negotiate(offered: &Handshake) {
+    let mut state = state();
+    state.agreed = offered.protocol_version.map(|version| Handshake {
+        protocol_version: Some(version.min(PROTOCOL_VERSION)),
+        capabilities: state.supported.agree(&offered.capabilities),
+    });
+}
+
+/// What to answer `init` with: what was agreed, or nothing if nothing was negotiated.
+pub fn answer() -> Handshake {
+    state().agreed.clone().unwrap_or_default()
+}
+
+/// The version of the extension `name` agreed to at `init`, if any. Nodes only use an
+/// extension when it was agreed to, so that they understand each other.
+pub fn agreed(name: &str) -> Option<u32> {
+    state()
+        .agreed
+        .as_ref()
+        .and_then(|agreed| agreed.capabilities.version(name))
+}
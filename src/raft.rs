@@ -0,0 +1,1173 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::message::Message;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Followers wait between one and two election timeouts before standing for election, so
+/// that nodes rarely time out together and split the vote.
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Maximum number of entries sent to a follower in a single `AppendEntries`.
+const MAX_ENTRIES_PER_APPEND: usize = 100;
+
+/// Number of applied entries kept in the log before it is compacted into a snapshot.
+const SNAPSHOT_THRESHOLD: u64 = 1000;
+
+/// The replicated state every node applies committed commands to.
+pub trait StateMachine {
+    type Command: Clone + Serialize + DeserializeOwned;
+    type Output;
+    /// The whole state, taken when the log is compacted and sent to followers that fell
+    /// behind the compacted part of the log.
+    type Snapshot: Serialize + DeserializeOwned;
+
+    fn apply(&mut self, command: &Self::Command) -> Self::Output;
+
+    fn snapshot(&self) -> Self::Snapshot;
+
+    fn restore(&mut self, snapshot: Self::Snapshot);
+}
+
+/// A log entry. Leaders append an entry without a command when elected, so that entries
+/// from earlier terms get committed as soon as possible. Membership changes are entries
+/// carrying a configuration, which nodes use as soon as it is in their log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry<C> {
+    pub term: u64,
+    pub command: Option<C>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<Configuration>,
+}
+
+/// The nodes whose votes count. While membership changes, the cluster goes through a joint
+/// configuration in which elections and commits need a majority of both the old `voters`
+/// and the `new_voters`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Configuration {
+    pub voters: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_voters: Option<BTreeSet<String>>,
+}
+
+impl Configuration {
+    pub fn is_joint(&self) -> bool {
+        self.new_voters.is_some()
+    }
+
+    pub fn contains(&self, node_id: &str) -> bool {
+        self.members().any(|member| member == node_id)
+    }
+
+    /// Voters of both the old and the new configuration.
+    pub fn members(&self) -> impl Iterator<Item = &String> {
+        let new_voters = self.new_voters.iter().flatten();
+        self.voters
+            .iter()
+            .chain(new_voters.filter(|voter| !self.voters.contains(*voter)))
+    }
+
+    fn has_quorum(&self, agrees: impl Fn(&str) -> bool) -> bool {
+        let majority = |voters: &BTreeSet<String>| {
+            voters.iter().filter(|voter| agrees(voter)).count() > voters.len() / 2
+        };
+        majority(&self.voters) && self.new_voters.as_ref().is_none_or(majority)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaftBody<C> {
+    /// Asks whether the sender could win an election for `term` without anyone adopting the
+    /// term yet, so a node cut off from the cluster doesn't disrupt it when it comes back.
+    PreVote {
+        msg_id: u64,
+        term: u64,
+        candidate_id: String,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+
+    PreVoteOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        term: u64,
+        vote_granted: bool,
+    },
+
+    RequestVote {
+        msg_id: u64,
+        term: u64,
+        candidate_id: String,
+        last_log_index: u64,
+        last_log_term: u64,
+        /// Set on elections started by a leadership transfer, which followers of the outgoing
+        /// leader must not ignore.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        leadership_transfer: bool,
+    },
+
+    RequestVoteOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        term: u64,
+        vote_granted: bool,
+    },
+
+    AppendEntries {
+        msg_id: u64,
+        term: u64,
+        leader_id: String,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        leader_commit: u64,
+    },
+
+    /// On success `match_index` is the last index known to match the leader's log, otherwise
+    /// it hints where the leader should retry from.
+    AppendEntriesOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+
+    /// Replaces the follower's state with a snapshot of everything up to and including
+    /// `last_included_index`.
+    InstallSnapshot {
+        msg_id: u64,
+        term: u64,
+        leader_id: String,
+        last_included_index: u64,
+        last_included_term: u64,
+        snapshot: Value,
+        config: Configuration,
+    },
+
+    InstallSnapshotOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        term: u64,
+        last_included_index: u64,
+    },
+
+    /// Sent by a leader handing over leadership, once the target's log is up to date, to
+    /// make it start an election right away.
+    TimeoutNow { msg_id: u64, term: u64 },
+}
+
+impl<C> RaftBody<C> {
+    fn term(&self) -> u64 {
+        match self {
+            RaftBody::PreVote { term, .. }
+            | RaftBody::PreVoteOk { term, .. }
+            | RaftBody::RequestVote { term, .. }
+            | RaftBody::RequestVoteOk { term, .. }
+            | RaftBody::AppendEntries { term, .. }
+            | RaftBody::AppendEntriesOk { term, .. }
+            | RaftBody::InstallSnapshot { term, .. }
+            | RaftBody::InstallSnapshotOk { term, .. }
+            | RaftBody::TimeoutNow { term, .. } => *term,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BodyType<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+struct Envelope<'a> {
+    #[serde(borrow)]
+    body: BodyType<'a>,
+}
+
+/// Returns the message if the line carries one of the Raft bodies.
+pub fn parse<C: DeserializeOwned>(
+    line: impl AsRef<[u8]>,
+) -> Result<Option<Message<RaftBody<C>>>, anyhow::Error> {
+    let line = line.as_ref();
+    let envelope: Envelope =
+        serde_json::from_slice(line).context("Failed to deserialize provided input to STDIN.")?;
+
+    match envelope.body.kind.as_ref() {
+        "pre_vote"
+        | "pre_vote_ok"
+        | "request_vote"
+        | "request_vote_ok"
+        | "append_entries"
+        | "append_entries_ok"
+        | "install_snapshot"
+        | "install_snapshot_ok"
+        | "timeout_now" => {
+            let message =
+                serde_json::from_slice(line).context("Failed to deserialize Raft message.")?;
+            Ok(Some(message))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    /// Collecting pre-votes before starting an election.
+    PreCandidate,
+    Candidate,
+    Leader,
+}
+
+/// Where a proposed command was appended. It was committed if an `Applied` with the same
+/// index and term comes out of the log; an entry of another term at that index means the
+/// command was lost with its leader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Proposal {
+    pub index: u64,
+    pub term: u64,
+}
+
+/// A leadership transfer in progress. It is abandoned if the target hasn't taken over
+/// within an election timeout.
+struct Transfer {
+    target: String,
+    deadline: Instant,
+    timeout_now_sent: bool,
+}
+
+/// A read waiting for a quorum to confirm that this node was still the leader after the read
+/// arrived. It can be served from the state machine once everything committed at that point
+/// is applied.
+struct PendingRead {
+    id: u64,
+    index: u64,
+    /// Only replies to messages sent after the read count as confirmation.
+    last_msg_id: u64,
+    acks: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub struct Applied<O> {
+    pub index: u64,
+    pub term: u64,
+    pub output: O,
+}
+
+/// A Raft node that doesn't do any I/O itself: messages are fed in with `handle`, time with
+/// `tick`, and outgoing messages and applied commands are collected with `take_messages` and
+/// `take_applied`.
+pub struct Raft<S: StateMachine> {
+    node_id: String,
+    /// The latest configuration in the log, committed or not.
+    config: Configuration,
+    role: Role,
+    current_term: u64,
+    voted_for: Option<String>,
+    leader_id: Option<String>,
+    /// Entries after the snapshot, so the first one has index `snapshot_index + 1`.
+    log: Vec<Entry<S::Command>>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    snapshot: Value,
+    snapshot_config: Configuration,
+    snapshot_threshold: u64,
+    commit_index: u64,
+    last_applied: u64,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    votes: HashSet<String>,
+    /// When this node last heard from the leader of its current term.
+    leader_contact: Option<Instant>,
+    election_deadline: Instant,
+    next_heartbeat: Instant,
+    transfer: Option<Transfer>,
+    reads: Vec<PendingRead>,
+    read_id: u64,
+    ready_reads: Vec<u64>,
+    msg_id: u64,
+    state_machine: S,
+    outbox: Vec<Message<RaftBody<S::Command>>>,
+    applied: Vec<Applied<S::Output>>,
+}
+
+impl<S: StateMachine> Raft<S> {
+    pub fn new(
+        node_id: impl Into<String>,
+        node_ids: &[String],
+        state_machine: S,
+        now: Instant,
+    ) -> Self {
+        let config = Configuration {
+            voters: node_ids.iter().cloned().collect(),
+            new_voters: None,
+        };
+
+        Self {
+            node_id: node_id.into(),
+            config: config.clone(),
+            role: Role::Follower,
+            current_term: 0,
+            voted_for: None,
+            leader_id: None,
+            log: Vec::new(),
+            snapshot_index: 0,
+            snapshot_term: 0,
+            snapshot: Value::Null,
+            snapshot_config: config,
+            snapshot_threshold: SNAPSHOT_THRESHOLD,
+            commit_index: 0,
+            last_applied: 0,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            votes: HashSet::new(),
+            leader_contact: None,
+            election_deadline: election_deadline(now),
+            next_heartbeat: now,
+            transfer: None,
+            reads: Vec::new(),
+            read_id: 0,
+            ready_reads: Vec::new(),
+            msg_id: 0,
+            state_machine,
+            outbox: Vec::new(),
+            applied: Vec::new(),
+        }
+    }
+
+    /// Compacts the log once it holds `entries` applied entries, instead of the default 1000.
+    pub fn with_snapshot_threshold(mut self, entries: u64) -> Self {
+        self.snapshot_threshold = entries.max(1);
+        self
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.current_term
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    /// The leader of the current term, if this node has heard from it.
+    pub fn leader(&self) -> Option<&str> {
+        self.leader_id.as_deref()
+    }
+
+    /// The latest configuration in the log, which may not be committed yet.
+    pub fn configuration(&self) -> &Configuration {
+        &self.config
+    }
+
+    /// Whether the latest configuration is committed, so another change can start.
+    pub fn configuration_committed(&self) -> bool {
+        self.config_index() <= self.commit_index
+    }
+
+    /// The node leadership is being handed over to.
+    pub fn transferring_to(&self) -> Option<&str> {
+        self.transfer
+            .as_ref()
+            .map(|transfer| transfer.target.as_str())
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    /// The index of the last entry in the log, counting those compacted into the snapshot.
+    pub fn last_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    /// Entries up to this index were applied, either one by one or through a snapshot.
+    pub fn last_applied(&self) -> u64 {
+        self.last_applied
+    }
+
+    pub fn state_machine(&self) -> &S {
+        &self.state_machine
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message<RaftBody<S::Command>>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub fn take_applied(&mut self) -> Vec<Applied<S::Output>> {
+        std::mem::take(&mut self.applied)
+    }
+
+    /// Reads started with `read_index` that can now be served from the state machine.
+    pub fn take_reads(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.ready_reads)
+    }
+
+    /// Starts a linearizable read that doesn't go through the log: the leader notes its commit
+    /// index and sends a round of heartbeats, and once a quorum answers and the state machine
+    /// caught up with that index, the read comes out of `take_reads`. Returns the id of the
+    /// read, or None if this node isn't the leader or hasn't committed an entry of its term
+    /// yet, so it may not know about everything committed before it was elected. Reads are
+    /// dropped if this node loses leadership.
+    pub fn read_index(&mut self) -> Option<u64> {
+        if !self.is_leader() || self.term_at(self.commit_index) != self.current_term {
+            return None;
+        }
+
+        self.read_id += 1;
+        self.reads.push(PendingRead {
+            id: self.read_id,
+            index: self.commit_index,
+            last_msg_id: self.msg_id,
+            acks: HashSet::from([self.node_id.clone()]),
+        });
+        for peer in self.peers() {
+            self.send_append_entries(&peer);
+        }
+        self.release_reads();
+
+        Some(self.read_id)
+    }
+
+    /// Appends a command to the log if this node is the leader and isn't handing over
+    /// leadership.
+    pub fn propose(&mut self, command: S::Command) -> Option<Proposal> {
+        if !self.is_leader() || self.transfer.is_some() {
+            return None;
+        }
+
+        self.log.push(Entry {
+            term: self.current_term,
+            command: Some(command),
+            config: None,
+        });
+        let proposal = Proposal {
+            index: self.last_index(),
+            term: self.current_term,
+        };
+
+        self.advance_commit_index();
+        for peer in self.peers() {
+            self.send_append_entries(&peer);
+        }
+
+        Some(proposal)
+    }
+
+    /// Hands leadership over to `target`: the leader stops accepting proposals, brings the
+    /// target's log up to date, and then tells it to start an election. Returns false if this
+    /// node isn't the leader or `target` isn't one of its peers.
+    pub fn transfer_leadership(&mut self, target: &str, now: Instant) -> bool {
+        if !self.is_leader() || target == self.node_id || !self.config.contains(target) {
+            return false;
+        }
+
+        self.transfer = Some(Transfer {
+            target: target.to_string(),
+            deadline: now + ELECTION_TIMEOUT,
+            timeout_now_sent: false,
+        });
+        self.send_append_entries(target);
+        self.send_timeout_now();
+        true
+    }
+
+    /// Changes the voting members of the cluster to `voters`, going through a joint
+    /// configuration of the old and new voters first. New nodes are sent the log as soon as
+    /// the change starts. Returns false if this node isn't the leader or another change or a
+    /// leadership transfer is in progress.
+    pub fn reconfigure(&mut self, voters: impl IntoIterator<Item = String>) -> bool {
+        let voters: BTreeSet<String> = voters.into_iter().collect();
+        if !self.is_leader()
+            || self.transfer.is_some()
+            || !self.configuration_committed()
+            || voters.is_empty()
+        {
+            return false;
+        }
+        if voters == self.config.voters {
+            return true;
+        }
+
+        self.append_config(Configuration {
+            voters: self.config.voters.clone(),
+            new_voters: Some(voters),
+        });
+        true
+    }
+
+    /// Starts an election when the leader has been silent for too long, and sends heartbeats
+    /// when this node is the leader.
+    pub fn tick(&mut self, now: Instant) {
+        if self
+            .transfer
+            .as_ref()
+            .is_some_and(|transfer| now >= transfer.deadline)
+        {
+            self.transfer = None;
+        }
+
+        match self.role {
+            Role::Leader if now >= self.next_heartbeat => {
+                self.next_heartbeat = now + HEARTBEAT_INTERVAL;
+                for peer in self.peers() {
+                    self.send_append_entries(&peer);
+                }
+            }
+            // Nodes that aren't members yet or anymore don't stand for election.
+            Role::Follower | Role::PreCandidate | Role::Candidate
+                if now >= self.election_deadline && self.config.contains(&self.node_id) =>
+            {
+                self.start_pre_vote(now);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle(&mut self, message: Message<RaftBody<S::Command>>, now: Instant) {
+        match message.body {
+            // A pre-vote only asks about a term, it doesn't start one.
+            RaftBody::PreVote { .. } => {}
+            // Nodes that hear from a leader ignore candidates, so that a node that was cut off
+            // and started an election anyway can't dethrone a leader the others still follow.
+            RaftBody::RequestVote {
+                leadership_transfer: false,
+                ..
+            } if self.has_live_leader(now) => return,
+            ref body if body.term() > self.current_term => self.step_down(body.term()),
+            _ => {}
+        }
+
+        let src = message.src;
+
+        match message.body {
+            RaftBody::PreVote {
+                msg_id,
+                term,
+                last_log_index,
+                last_log_term,
+                ..
+            } => {
+                let up_to_date =
+                    (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+                let vote_granted =
+                    term > self.current_term && up_to_date && !self.has_live_leader(now);
+
+                let body = RaftBody::PreVoteOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: msg_id,
+                    term: self.current_term,
+                    vote_granted,
+                };
+                self.send(src, body);
+            }
+
+            RaftBody::PreVoteOk { vote_granted, .. } => {
+                if self.role == Role::PreCandidate && vote_granted {
+                    self.votes.insert(src);
+                    if self.has_votes_quorum() {
+                        self.start_election(now, false);
+                    }
+                }
+            }
+
+            RaftBody::RequestVote {
+                msg_id,
+                term,
+                candidate_id,
+                last_log_index,
+                last_log_term,
+                ..
+            } => {
+                let up_to_date =
+                    (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+                let vote_granted = term == self.current_term
+                    && up_to_date
+                    && self
+                        .voted_for
+                        .as_ref()
+                        .is_none_or(|voted_for| *voted_for == candidate_id);
+
+                if vote_granted {
+                    self.voted_for = Some(candidate_id);
+                    self.election_deadline = election_deadline(now);
+                }
+
+                let body = RaftBody::RequestVoteOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: msg_id,
+                    term: self.current_term,
+                    vote_granted,
+                };
+                self.send(src, body);
+            }
+
+            RaftBody::RequestVoteOk {
+                term, vote_granted, ..
+            } => {
+                if self.role == Role::Candidate && term == self.current_term && vote_granted {
+                    self.votes.insert(src);
+                    if self.has_votes_quorum() {
+                        self.become_leader(now);
+                    }
+                }
+            }
+
+            RaftBody::AppendEntries {
+                msg_id,
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                let (success, match_index) = if term < self.current_term {
+                    (false, 0)
+                } else {
+                    self.role = Role::Follower;
+                    self.leader_id = Some(leader_id);
+                    self.leader_contact = Some(now);
+                    self.election_deadline = election_deadline(now);
+                    self.append_entries(prev_log_index, prev_log_term, entries, leader_commit)
+                };
+
+                let body = RaftBody::AppendEntriesOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: msg_id,
+                    term: self.current_term,
+                    success,
+                    match_index,
+                };
+                self.send(src, body);
+            }
+
+            RaftBody::AppendEntriesOk {
+                in_reply_to,
+                term,
+                success,
+                match_index,
+                ..
+            } => {
+                if self.role != Role::Leader || term != self.current_term {
+                    return;
+                }
+                self.acknowledge_reads(&src, in_reply_to);
+
+                let next_index = self.next_index.entry(src.clone()).or_insert(1);
+                if success {
+                    let matched = self.match_index.entry(src.clone()).or_insert(0);
+                    *matched = (*matched).max(match_index);
+                    *next_index = (*next_index).max(match_index + 1);
+                    self.advance_commit_index();
+                    self.send_timeout_now();
+                } else {
+                    *next_index = (*next_index - 1).min(match_index + 1).max(1);
+                }
+
+                if self.next_index[&src] <= self.last_index() {
+                    self.send_append_entries(&src);
+                }
+            }
+
+            RaftBody::InstallSnapshot {
+                msg_id,
+                term,
+                leader_id,
+                last_included_index,
+                last_included_term,
+                snapshot,
+                config,
+            } => {
+                if term == self.current_term {
+                    self.role = Role::Follower;
+                    self.leader_id = Some(leader_id);
+                    self.leader_contact = Some(now);
+                    self.election_deadline = election_deadline(now);
+                    self.install_snapshot(
+                        last_included_index,
+                        last_included_term,
+                        snapshot,
+                        config,
+                    );
+                }
+
+                let body = RaftBody::InstallSnapshotOk {
+                    msg_id: self.incremented_msg_id(),
+                    in_reply_to: msg_id,
+                    term: self.current_term,
+                    last_included_index,
+                };
+                self.send(src, body);
+            }
+
+            RaftBody::InstallSnapshotOk {
+                in_reply_to,
+                term,
+                last_included_index,
+                ..
+            } => {
+                if self.role != Role::Leader || term != self.current_term {
+                    return;
+                }
+                self.acknowledge_reads(&src, in_reply_to);
+
+                let matched = self.match_index.entry(src.clone()).or_insert(0);
+                *matched = (*matched).max(last_included_index);
+                let next_index = self.next_index.entry(src.clone()).or_insert(1);
+                *next_index = (*next_index).max(last_included_index + 1);
+                self.advance_commit_index();
+                self.send_timeout_now();
+
+                if self.next_index[&src] <= self.last_index() {
+                    self.send_append_entries(&src);
+                }
+            }
+
+            RaftBody::TimeoutNow { term, .. } => {
+                if term == self.current_term && self.role != Role::Leader {
+                    self.start_election(now, true);
+                }
+            }
+        }
+    }
+
+    fn incremented_msg_id(&mut self) -> u64 {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    fn send(&mut self, dest: String, body: RaftBody<S::Command>) {
+        self.outbox.push(Message {
+            src: self.node_id.clone(),
+            dest,
+            body,
+        });
+    }
+
+    /// Every other member of the latest configuration.
+    fn peers(&self) -> Vec<String> {
+        self.config
+            .members()
+            .filter(|&member| *member != self.node_id)
+            .cloned()
+            .collect()
+    }
+
+    fn has_votes_quorum(&self) -> bool {
+        self.config.has_quorum(|id| self.votes.contains(id))
+    }
+
+    /// Index of the entry holding the latest configuration, or of the snapshot if the log has
+    /// none.
+    fn config_index(&self) -> u64 {
+        self.log
+            .iter()
+            .rposition(|entry| entry.config.is_some())
+            .map_or(self.snapshot_index, |position| {
+                self.snapshot_index + position as u64 + 1
+            })
+    }
+
+    /// The configuration as of the given index.
+    fn config_at(&self, index: u64) -> Configuration {
+        self.log[..(index - self.snapshot_index) as usize]
+            .iter()
+            .rev()
+            .find_map(|entry| entry.config.clone())
+            .unwrap_or_else(|| self.snapshot_config.clone())
+    }
+
+    /// Picks up the latest configuration after the log changed.
+    fn refresh_config(&mut self) {
+        self.config = self.config_at(self.last_index());
+    }
+
+    fn last_term(&self) -> u64 {
+        self.term_at(self.last_index())
+    }
+
+    /// Position of an entry after the snapshot in `log`.
+    fn position(&self, index: u64) -> usize {
+        (index - self.snapshot_index - 1) as usize
+    }
+
+    /// Log indexes start at 1; index 0 stands for the empty log with term 0. Only the last
+    /// index covered by the snapshot and those after it have a known term.
+    fn term_at(&self, index: u64) -> u64 {
+        if index == self.snapshot_index {
+            self.snapshot_term
+        } else {
+            self.log[self.position(index)].term
+        }
+    }
+
+    fn step_down(&mut self, term: u64) {
+        self.current_term = term;
+        self.role = Role::Follower;
+        self.voted_for = None;
+        self.leader_id = None;
+        self.leader_contact = None;
+        self.transfer = None;
+        self.reads.clear();
+    }
+
+    /// Whether this node is the leader or heard from one within the last election timeout.
+    fn has_live_leader(&self, now: Instant) -> bool {
+        self.role == Role::Leader
+            || self
+                .leader_contact
+                .is_some_and(|contact| now < contact + ELECTION_TIMEOUT)
+    }
+
+    /// Asks the peers whether they would vote for this node before increasing the term, as a
+    /// node that can't win would otherwise force the whole cluster into a higher term.
+    fn start_pre_vote(&mut self, now: Instant) {
+        self.role = Role::PreCandidate;
+        self.leader_id = None;
+        self.leader_contact = None;
+        self.votes = HashSet::from([self.node_id.clone()]);
+        self.election_deadline = election_deadline(now);
+
+        if self.has_votes_quorum() {
+            self.start_election(now, false);
+            return;
+        }
+
+        for peer in self.peers() {
+            let body = RaftBody::PreVote {
+                msg_id: self.incremented_msg_id(),
+                term: self.current_term + 1,
+                candidate_id: self.node_id.clone(),
+                last_log_index: self.last_index(),
+                last_log_term: self.last_term(),
+            };
+            self.send(peer, body);
+        }
+    }
+
+    fn start_election(&mut self, now: Instant, leadership_transfer: bool) {
+        self.current_term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.node_id.clone());
+        self.leader_id = None;
+        self.leader_contact = None;
+        self.votes = HashSet::from([self.node_id.clone()]);
+        self.election_deadline = election_deadline(now);
+
+        if self.has_votes_quorum() {
+            self.become_leader(now);
+            return;
+        }
+
+        for peer in self.peers() {
+            let body = RaftBody::RequestVote {
+                msg_id: self.incremented_msg_id(),
+                term: self.current_term,
+                candidate_id: self.node_id.clone(),
+                last_log_index: self.last_index(),
+                last_log_term: self.last_term(),
+                leadership_transfer,
+            };
+            self.send(peer, body);
+        }
+    }
+
+    fn become_leader(&mut self, now: Instant) {
+        self.role = Role::Leader;
+        self.leader_id = Some(self.node_id.clone());
+        self.log.push(Entry {
+            term: self.current_term,
+            command: None,
+            config: None,
+        });
+
+        for peer in self.peers() {
+            self.next_index.insert(peer.clone(), self.last_index());
+            self.match_index.insert(peer.clone(), 0);
+        }
+
+        self.advance_commit_index();
+        self.next_heartbeat = now + HEARTBEAT_INTERVAL;
+        for peer in self.peers() {
+            self.send_append_entries(&peer);
+        }
+    }
+
+    /// Tells the transfer target to start an election once it has every entry of the log.
+    fn send_timeout_now(&mut self) {
+        let Some(transfer) = self.transfer.as_ref() else {
+            return;
+        };
+        let caught_up = self.match_index.get(&transfer.target).copied() == Some(self.last_index());
+        if transfer.timeout_now_sent || !caught_up {
+            return;
+        }
+
+        let target = transfer.target.clone();
+        let body = RaftBody::TimeoutNow {
+            msg_id: self.incremented_msg_id(),
+            term: self.current_term,
+        };
+        self.send(target, body);
+        if let Some(transfer) = self.transfer.as_mut() {
+            transfer.timeout_now_sent = true;
+        }
+    }
+
+    fn send_append_entries(&mut self, peer: &str) {
+        let next_index = self.next_index.get(peer).copied().unwrap_or(1);
+        if next_index <= self.snapshot_index {
+            let body = RaftBody::InstallSnapshot {
+                msg_id: self.incremented_msg_id(),
+                term: self.current_term,
+                leader_id: self.node_id.clone(),
+                last_included_index: self.snapshot_index,
+                last_included_term: self.snapshot_term,
+                snapshot: self.snapshot.clone(),
+                config: self.snapshot_config.clone(),
+            };
+            self.send(peer.to_string(), body);
+            return;
+        }
+
+        let prev_log_index = next_index - 1;
+        let entries = self.log[self.position(next_index)..]
+            .iter()
+            .take(MAX_ENTRIES_PER_APPEND)
+            .cloned()
+            .collect();
+
+        let body = RaftBody::AppendEntries {
+            msg_id: self.incremented_msg_id(),
+            term: self.current_term,
+            leader_id: self.node_id.clone(),
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index),
+            entries,
+            leader_commit: self.commit_index,
+        };
+        self.send(peer.to_string(), body);
+    }
+
+    /// Returns whether the entries were appended, and the index to report to the leader.
+    fn append_entries(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        mut entries: Vec<Entry<S::Command>>,
+        leader_commit: u64,
+    ) -> (bool, u64) {
+        let (mut prev_log_index, mut prev_log_term) = (prev_log_index, prev_log_term);
+        if prev_log_index < self.snapshot_index {
+            // Entries covered by the snapshot are committed, so they match the leader's.
+            let covered = (self.snapshot_index - prev_log_index) as usize;
+            entries.drain(..covered.min(entries.len()));
+            prev_log_index = self.snapshot_index;
+            prev_log_term = self.snapshot_term;
+        }
+
+        if prev_log_index > self.last_index() {
+            return (false, self.last_index());
+        }
+
+        if self.term_at(prev_log_index) != prev_log_term {
+            // Skip the whole conflicting term instead of going back one entry per round trip.
+            let conflicting_term = self.term_at(prev_log_index);
+            let mut index = prev_log_index;
+            while index > self.commit_index && self.term_at(index) == conflicting_term {
+                index -= 1;
+            }
+            return (false, index);
+        }
+
+        let mut index = prev_log_index;
+        for entry in entries {
+            index += 1;
+            if index <= self.last_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+                self.log.truncate(self.position(index));
+            }
+            self.log.push(entry);
+        }
+        self.refresh_config();
+
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(index);
+            self.apply_committed();
+        }
+
+        (true, index)
+    }
+
+    /// Commits the highest entry of the current term that a quorum has replicated.
+    fn advance_commit_index(&mut self) {
+        for index in (self.commit_index + 1..=self.last_index()).rev() {
+            if self.term_at(index) != self.current_term {
+                break;
+            }
+
+            let replicated = self.config.has_quorum(|id| {
+                id == self.node_id || self.match_index.get(id).is_some_and(|&m| m >= index)
+            });
+            if replicated {
+                self.commit_index = index;
+                self.apply_committed();
+                self.continue_reconfiguration();
+                return;
+            }
+        }
+    }
+
+    /// Once the leader's joint configuration is committed it moves on to the new one. A leader
+    /// that isn't part of the new configuration steps down once that is committed too.
+    fn continue_reconfiguration(&mut self) {
+        if !self.is_leader() || self.config_index() > self.commit_index {
+            return;
+        }
+
+        if let Some(new_voters) = self.config.new_voters.clone() {
+            self.append_config(Configuration {
+                voters: new_voters,
+                new_voters: None,
+            });
+        } else if !self.config.voters.contains(&self.node_id) {
+            self.role = Role::Follower;
+            self.leader_id = None;
+            self.transfer = None;
+            self.reads.clear();
+        }
+    }
+
+    fn append_config(&mut self, config: Configuration) {
+        self.log.push(Entry {
+            term: self.current_term,
+            command: None,
+            config: Some(config),
+        });
+        self.refresh_config();
+
+        for peer in self.peers() {
+            self.send_append_entries(&peer);
+        }
+        self.advance_commit_index();
+    }
+
+    fn acknowledge_reads(&mut self, src: &str, in_reply_to: u64) {
+        for read in &mut self.reads {
+            if in_reply_to > read.last_msg_id {
+                read.acks.insert(src.to_string());
+            }
+        }
+        self.release_reads();
+    }
+
+    /// Hands out the reads confirmed by a quorum whose index has been applied. Reads are
+    /// started in order, so the oldest ones are released first.
+    fn release_reads(&mut self) {
+        let ready = self
+            .reads
+            .iter()
+            .take_while(|read| {
+                self.last_applied >= read.index
+                    && self.config.has_quorum(|id| read.acks.contains(id))
+            })
+            .count();
+        self.ready_reads
+            .extend(self.reads.drain(..ready).map(|read| read.id));
+    }
+
+    fn apply_committed(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let entry = &self.log[self.position(self.last_applied)];
+
+            if let Some(command) = &entry.command {
+                let output = self.state_machine.apply(command);
+                self.applied.push(Applied {
+                    index: self.last_applied,
+                    term: entry.term,
+                    output,
+                });
+            }
+        }
+
+        self.release_reads();
+        if self.last_applied - self.snapshot_index >= self.snapshot_threshold {
+            self.compact();
+        }
+    }
+
+    /// Replaces the applied entries of the log with a snapshot of the state machine.
+    fn compact(&mut self) {
+        let snapshot = match serde_json::to_value(self.state_machine.snapshot()) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                log::error!("Failed to serialize snapshot: {error}");
+                return;
+            }
+        };
+
+        self.snapshot_term = self.term_at(self.last_applied);
+        self.snapshot_config = self.config_at(self.last_applied);
+        self.log.drain(..=self.position(self.last_applied));
+        self.snapshot_index = self.last_applied;
+        self.snapshot = snapshot;
+    }
+
+    fn install_snapshot(&mut self, index: u64, term: u64, snapshot: Value, config: Configuration) {
+        // Everything the snapshot covers is already committed here.
+        if index <= self.commit_index {
+            return;
+        }
+
+        let state = match serde_json::from_value(snapshot.clone()) {
+            Ok(state) => state,
+            Err(error) => {
+                log::error!("Failed to deserialize snapshot: {error}");
+                return;
+            }
+        };
+
+        if index <= self.last_index() && self.term_at(index) == term {
+            self.log.drain(..=self.position(index));
+        } else {
+            self.log.clear();
+        }
+
+        self.state_machine.restore(state);
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        self.snapshot = snapshot;
+        self.snapshot_config = config;
+        self.commit_index = index;
+        self.last_applied = index;
+        self.refresh_config();
+    }
+}
+
+fn election_deadline(now: Instant) -> Instant {
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = random % ELECTION_TIMEOUT.as_millis() as u64;
+    now + ELECTION_TIMEOUT + Duration::from_millis(jitter)
+}
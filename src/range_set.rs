@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+/// A set of numbers stored as the ranges of consecutive numbers it's made of, so that its
+/// memory grows with the number of gaps rather than the number of elements. A set of the
+/// first million numbers takes a single entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeSet {
+    /// The first number of every range, mapped to its last one.
+    ranges: BTreeMap<u64, u64>,
+    len: u64,
+}
+
+impl RangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many numbers the set holds.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many ranges the set is stored as.
+    pub fn range_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn contains(&self, number: u64) -> bool {
+        self.ranges
+            .range(..=number)
+            .next_back()
+            .is_some_and(|(_, &last)| number <= last)
+    }
+
+    /// Adds `number`, merging it with the ranges right before and after it. Returns whether
+    /// it wasn't in the set already.
+    pub fn insert(&mut self, number: u64) -> bool {
+        let before = self.ranges.range(..=number).next_back();
+        if before.is_some_and(|(_, &last)| number <= last) {
+            return false;
+        }
+
+        let start = match before {
+            Some((&start, &last)) if last + 1 == number => start,
+            _ => number,
+        };
+        let last = match number
+            .checked_add(1)
+            .and_then(|next| self.ranges.remove(&next))
+        {
+            Some(last) => last,
+            None => number,
+        };
+        self.ranges.insert(start, last);
+        self.len += 1;
+        true
+    }
+}
+
+impl Extend<u64> for RangeSet {
+    fn extend<I: IntoIterator<Item = u64>>(&mut self, numbers: I) {
+        for number in numbers {
+            self.insert(number);
+        }
+    }
+}
+
+impl FromIterator<u64> for RangeSet {
+    fn from_iter<I: IntoIterator<Item = u64>>(numbers: I) -> Self {
+        let mut set = Self::new();
+        set.extend(numbers);
+        set
+    }
+}
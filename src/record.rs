@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::runtime;
+
+/// A line a node received, with when it arrived. A recording is a file of these, one per
+/// line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Microseconds since the node started.
+    pub at_us: u64,
+    /// The line as received, without its newline.
+    pub line: String,
+}
+
+/// Writes every line a node receives to a file of its own in a directory, named after the
+/// node id in the `init` it receives first. Each entry is written as soon as it's recorded,
+/// so a recording is complete up to the moment the node was killed.
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    started: Instant,
+    file: Option<File>,
+}
+
+impl Recorder {
+    /// Records into `dir`, which is created if needed.
+    pub fn create(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Recorder {
+            dir,
+            started: Instant::now(),
+            file: None,
+        })
+    }
+
+    /// The file lines are recorded to, once the first one arrived.
+    pub fn path(&self, first_line: &[u8]) -> PathBuf {
+        let name = runtime::init_node_id(first_line)
+            .unwrap_or_else(|| format!("pid-{}", std::process::id()));
+        self.dir.join(format!("{name}.jsonl"))
+    }
+
+    pub fn record(&mut self, line: &[u8]) -> Result<(), anyhow::Error> {
+        let at_us = self.started.elapsed().as_micros() as u64;
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let path = self.path(line);
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                self.file.insert(file)
+            }
+        };
+        let entry = Entry {
+            at_us,
+            line: String::from_utf8_lossy(line.trim_ascii_end()).into_owned(),
+        };
+        let mut json = serde_json::to_vec(&entry)?;
+        json.push(b'\n');
+        file.write_all(&json).context("Failed to write recording")
+    }
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+/// Makes `recorder` record the lines of the whole process, from now on.
+pub fn install(recorder: Recorder) {
+    *RECORDER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(recorder);
+}
+
+/// Records a line received, if a recorder is installed. A recorder that fails to write is
+/// dropped, so that the node goes on without it.
+pub fn line(line: &[u8]) {
+    let mut recorder = RECORDER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(Err(error)) = recorder.as_mut().map(|recorder| recorder.record(line)) {
+        log::error!("Stopped recording: {error:#}");
+        *recorder = None;
+    }
+}
+
+/// Reads the entries of a recording.
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Entry>, anyhow::Error> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            serde_json::from_str(&line).with_context(|| {
+                format!("Invalid entry on line {} of {}", index + 1, path.display())
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,50 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::message::Message;
+
+/// Replies to recent requests, keyed by the client and the request's `msg_id`, so that a
+/// request retried by its client is answered with the original reply instead of being
+/// executed again. Only the most recent `capacity` replies are kept.
+pub struct ReplyCache<B> {
+    replies: HashMap<(String, u64), Message<B>>,
+    order: VecDeque<(String, u64)>,
+    capacity: usize,
+}
+
+impl<B: Clone> ReplyCache<B> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            replies: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.replies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty()
+    }
+
+    /// The reply sent to the request `msg_id` from `src`, if it is still remembered.
+    pub fn get(&self, src: &str, msg_id: u64) -> Option<Message<B>> {
+        self.replies.get(&(src.to_string(), msg_id)).cloned()
+    }
+
+    /// Remembers the reply to a request, forgetting the oldest one when full.
+    pub fn insert(&mut self, src: &str, msg_id: u64, reply: Message<B>) {
+        let key = (src.to_string(), msg_id);
+        if self.replies.insert(key.clone(), reply).is_some() {
+            return;
+        }
+
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+
+/// A value of EDN, the Clojure notation Maelstrom writes its results and histories in. Only
+/// what's needed to read those is kept: keywords and symbols lose their leading `:`, ratios
+/// become floats, and the tag of a tagged value is kept as a string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edn {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Keyword(String),
+    Symbol(String),
+    Char(String),
+    /// A vector or a list.
+    Seq(Vec<Edn>),
+    Set(Vec<Edn>),
+    /// A map's entries, in the order they're written.
+    Map(Vec<(Edn, Edn)>),
+    Tagged(String, Box<Edn>),
+}
+
+impl Edn {
+    /// The value of a map under the keyword `key`.
+    pub fn get(&self, key: &str) -> Option<&Edn> {
+        match self {
+            Edn::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+                Edn::Keyword(k) if k == key => Some(v),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Edn::Int(n) => Some(n as f64),
+            Edn::Float(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Edn::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_keyword(&self) -> Option<&str> {
+        match self {
+            Edn::Keyword(k) => Some(k),
+            _ => None,
+        }
+    }
+
+    pub fn entries(&self) -> &[(Edn, Edn)] {
+        match self {
+            Edn::Map(entries) => entries,
+            _ => &[],
+        }
+    }
+}
+
+/// Parses the first value in `s`.
+pub fn parse(s: &str) -> Result<Edn, anyhow::Error> {
+    Parser::new(s)
+        .next_value()?
+        .context("Expected an EDN value, found nothing")
+}
+
+/// Parses every value in `s`, such as the operations of a history, one per line.
+pub fn parse_all(s: &str) -> Result<Vec<Edn>, anyhow::Error> {
+    let mut parser = Parser::new(s);
+    let mut values = Vec::new();
+    while let Some(value) = parser.next_value()? {
+        values.push(value);
+    }
+    Ok(values)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    /// Skips whitespace, commas, comments, and values discarded with `#_`.
+    fn skip(&mut self) -> Result<(), anyhow::Error> {
+        while let Some(c) = self.peek() {
+            let rest = &self.input[self.pos..];
+            if c == ';' {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if c.is_whitespace() || c == ',' {
+                self.pos += c.len_utf8();
+            } else if rest.starts_with("#_") {
+                self.pos += 2;
+                self.next_value()?.context("Nothing to discard after #_")?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The next value, or `None` at the end of the input.
+    fn next_value(&mut self) -> Result<Option<Edn>, anyhow::Error> {
+        self.skip()?;
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+        let value = match c {
+            '{' => {
+                self.pos += 1;
+                let items = self.items('}')?;
+                anyhow::ensure!(
+                    items.len() % 2 == 0,
+                    "A map at {} has an odd count",
+                    self.pos
+                );
+                let mut items = items.into_iter();
+                let mut entries = Vec::new();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    entries.push((key, value));
+                }
+                Edn::Map(entries)
+            }
+            '[' => {
+                self.pos += 1;
+                Edn::Seq(self.items(']')?)
+            }
+            '(' => {
+                self.pos += 1;
+                Edn::Seq(self.items(')')?)
+            }
+            '"' => Edn::String(self.string()?),
+            '#' => {
+                self.pos += 1;
+                match self.peek() {
+                    Some('{') => {
+                        self.pos += 1;
+                        Edn::Set(self.items('}')?)
+                    }
+                    Some('#') => {
+                        self.pos += 1;
+                        match self.token() {
+                            "Inf" => Edn::Float(f64::INFINITY),
+                            "-Inf" => Edn::Float(f64::NEG_INFINITY),
+                            _ => Edn::Float(f64::NAN),
+                        }
+                    }
+                    _ => {
+                        let tag = self.token().to_string();
+                        let value = self.next_value()?.context("Nothing after a tag")?;
+                        Edn::Tagged(tag, Box::new(value))
+                    }
+                }
+            }
+            ':' => {
+                self.pos += 1;
+                Edn::Keyword(self.token().to_string())
+            }
+            '\\' => {
+                self.pos += 1;
+                let first = self.peek().context("Nothing after \\")?;
+                self.pos += first.len_utf8();
+                let rest = self.token();
+                Edn::Char(format!("{first}{rest}"))
+            }
+            ')' | ']' | '}' => anyhow::bail!("Unexpected {c} at {}", self.pos),
+            _ => atom(self.token()),
+        };
+        Ok(Some(value))
+    }
+
+    /// Values up to `end`, which is consumed.
+    fn items(&mut self, end: char) -> Result<Vec<Edn>, anyhow::Error> {
+        let mut items = Vec::new();
+        loop {
+            self.skip()?;
+            match self.peek() {
+                Some(c) if c == end => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                Some(_) => items.extend(self.next_value()?),
+                None => anyhow::bail!("Expected {end} before the end of the input"),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, anyhow::Error> {
+        self.pos += 1;
+        let mut string = String::new();
+        let mut chars = self.input[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(string);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, 'r')) => string.push('\r'),
+                    Some((_, other)) => string.push(other),
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        anyhow::bail!("A string isn't closed before the end of the input")
+    }
+
+    /// A run of characters up to a delimiter.
+    fn token(&mut self) -> &'a str {
+        let rest = &self.input[self.pos..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || ",()[]{}\"".contains(c))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+}
+
+/// A number, `nil`, `true`, `false`, or else a symbol.
+fn atom(token: &str) -> Edn {
+    match token {
+        "nil" => return Edn::Nil,
+        "true" => return Edn::Bool(true),
+        "false" => return Edn::Bool(false),
+        _ => {}
+    }
+    let number = token.trim_end_matches(['N', 'M']);
+    if let Ok(n) = number.parse() {
+        return Edn::Int(n);
+    }
+    if let Ok(n) = number.parse() {
+        return Edn::Float(n);
+    }
+    if let Some((numerator, denominator)) = number.split_once('/') {
+        if let (Ok(numerator), Ok(denominator)) =
+            (numerator.parse::<f64>(), denominator.parse::<f64>())
+        {
+            return Edn::Float(numerator / denominator);
+        }
+    }
+    Edn::Symbol(token.to_string())
+}
+
+/// Reads the results of a Maelstrom run: a `results.edn`, a `jepsen.log`, whose last results
+/// map is used, or a run's directory, such as `store/latest`, with either of them in it.
+pub fn read(path: impl AsRef<Path>) -> Result<Edn, anyhow::Error> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        let results = path.join("results.edn");
+        return read(if results.exists() {
+            results
+        } else {
+            path.join("jepsen.log")
+        });
+    }
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    // Jepsen logs the results map as a whole, starting with its first check.
+    let start = if path.extension().is_some_and(|ext| ext == "edn") {
+        0
+    } else {
+        text.rfind("{:perf")
+            .with_context(|| format!("No results in {}", path.display()))?
+    };
+    parse(&text[start..]).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Whether each check of a run passed, by name, and whether the run as a whole did. A check
+/// is any entry of the results map with a `:valid?` of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// `None` when Maelstrom couldn't tell, as it says with `:unknown`.
+    pub valid: Option<bool>,
+    pub checks: Vec<(String, Option<bool>)>,
+    /// Messages per operation between servers, and among everyone including clients.
+    pub server_msgs_per_op: Option<f64>,
+    pub all_msgs_per_op: Option<f64>,
+    /// How long broadcast values took to become visible on every node, by quantile.
+    pub stable_latencies: Vec<(f64, Duration)>,
+}
+
+fn validity(value: Option<&Edn>) -> Option<bool> {
+    value.and_then(Edn::as_bool)
+}
+
+impl Summary {
+    pub fn of(results: &Edn) -> Self {
+        let checks = results
+            .entries()
+            .iter()
+            .filter_map(|(key, value)| {
+                let valid = value.get("valid?")?;
+                Some((key.as_keyword()?.to_string(), validity(Some(valid))))
+            })
+            .collect();
+        let msgs_per_op = |group| results.get("net")?.get(group)?.get("msgs-per-op")?.as_f64();
+        let stable_latencies = results
+            .get("workload")
+            .and_then(|workload| workload.get("stable-latencies"))
+            .map(|latencies| {
+                latencies
+                    .entries()
+                    .iter()
+                    .filter_map(|(quantile, ms)| Some((quantile.as_f64()?, millis(ms.as_f64()?))))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            valid: validity(results.get("valid?")),
+            checks,
+            server_msgs_per_op: msgs_per_op("servers"),
+            all_msgs_per_op: msgs_per_op("all"),
+            stable_latencies,
+        }
+    }
+}
+
+fn millis(ms: f64) -> Duration {
+    Duration::from_secs_f64(ms.max(0.0) / 1000.0)
+}
+
+/// The latencies of the operations of a history that completed with `:ok`, from invocation
+/// to completion, sorted, by function.
+pub fn latencies(history: &[Edn]) -> BTreeMap<String, Vec<Duration>> {
+    let mut invoked: HashMap<String, i64> = HashMap::new();
+    let mut latencies: BTreeMap<String, Vec<Duration>> = BTreeMap::new();
+    for op in history {
+        // Maelstrom's processes run one operation at a time, so a completion belongs to the
+        // last invocation of its process.
+        let (Some(process), Some(time)) = (op.get("process"), op.get("time")) else {
+            continue;
+        };
+        let process = format!("{process:?}");
+        let Edn::Int(time) = *time else {
+            continue;
+        };
+        match op.get("type").and_then(Edn::as_keyword) {
+            Some("invoke") => {
+                invoked.insert(process, time);
+            }
+            Some("ok") => {
+                let (Some(start), Some(f)) = (
+                    invoked.remove(&process),
+                    op.get("f").and_then(Edn::as_keyword),
+                ) else {
+                    continue;
+                };
+                let nanos = u64::try_from(time - start).unwrap_or_default();
+                latencies
+                    .entry(f.to_string())
+                    .or_default()
+                    .push(Duration::from_nanos(nanos));
+            }
+            Some(_) => {
+                invoked.remove(&process);
+            }
+            None => {}
+        }
+    }
+    for durations in latencies.values_mut() {
+        durations.sort();
+    }
+    latencies
+}
+
+/// The latency at `quantile` of `sorted`, by nearest rank.
+pub fn quantile(sorted: &[Duration], quantile: f64) -> Duration {
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted
+        .get(rank.saturating_sub(1))
+        .copied()
+        .unwrap_or_default()
+}
@@ -0,0 +1,60 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::digest::{hash_bytes, mix};
+
+/// A small pseudo-random number generator (SplitMix64). Seeding it makes a node's random
+/// choices, such as which peers it gossips to, the same on every run.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeds the generator from the standard library's random hasher keys.
+    pub fn from_entropy() -> Self {
+        Self::from_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Seeds a node's generator with `seed` combined with the node's id, so that nodes
+    /// sharing a seed still make different choices. Without a seed, from entropy.
+    pub fn for_node(seed: Option<u64>, node_id: &str) -> Self {
+        match seed {
+            Some(seed) => Self::from_seed(seed ^ hash_bytes(node_id.as_bytes())),
+            None => Self::from_entropy(),
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let value = mix(self.state);
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        value
+    }
+
+    /// A number in `0..bound`. `bound` must not be zero.
+    pub fn below(&mut self, bound: usize) -> usize {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as usize
+    }
+
+    /// `true` with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next_u64() >> 11) as f64) < p * (1u64 << 53) as f64
+    }
+
+    /// `count` distinct elements of `items` picked at random, or all of them in random order
+    /// if there aren't that many.
+    pub fn sample<T: Clone>(&mut self, items: &[T], count: usize) -> Vec<T> {
+        let mut items = items.to_vec();
+        let count = count.min(items.len());
+        for i in 0..count {
+            let j = i + self.below(items.len() - i);
+            items.swap(i, j);
+        }
+        items.truncate(count);
+        items
+    }
+}
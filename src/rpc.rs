@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+
+/// Requests sent to other nodes or services, waiting for a reply with a matching `in_reply_to`.
+pub struct PendingRequests<T> {
+    pending: HashMap<u64, (T, Instant)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl<T> PendingRequests<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Times requests with `clock` instead of the system clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            pending: HashMap::new(),
+            clock,
+        }
+    }
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.clock.now().saturating_duration_since(since)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn insert(&mut self, msg_id: u64, context: T) {
+        self.pending.insert(msg_id, (context, self.clock.now()));
+    }
+
+    /// Removes the request answered by a reply. Unknown or duplicate replies yield `None`.
+    pub fn complete(&mut self, in_reply_to: u64) -> Option<T> {
+        self.pending
+            .remove(&in_reply_to)
+            .map(|(context, _)| context)
+    }
+
+    /// The context of a request still waiting, for requests that take several replies.
+    pub fn get_mut(&mut self, in_reply_to: u64) -> Option<&mut T> {
+        self.pending
+            .get_mut(&in_reply_to)
+            .map(|(context, _)| context)
+    }
+
+    /// Like `complete`, also returning how long the request waited for its reply.
+    pub fn complete_timed(&mut self, in_reply_to: u64) -> Option<(T, Duration)> {
+        let (context, sent_at) = self.pending.remove(&in_reply_to)?;
+        Some((context, self.elapsed(sent_at)))
+    }
+
+    /// Removes and returns every request that has been waiting longer than `timeout`.
+    pub fn expire(&mut self, timeout: Duration) -> Vec<(u64, T)> {
+        self.expire_with(|_| timeout)
+    }
+
+    /// Like `expire`, with a timeout that depends on the request, e.g. on its destination.
+    pub fn expire_with(&mut self, timeout: impl Fn(&T) -> Duration) -> Vec<(u64, T)> {
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, (context, sent_at))| self.elapsed(*sent_at) >= timeout(context))
+            .map(|(&msg_id, _)| msg_id)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|msg_id| {
+                self.pending
+                    .remove(&msg_id)
+                    .map(|(context, _)| (msg_id, context))
+            })
+            .collect()
+    }
+}
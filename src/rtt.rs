@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+/// Estimates the round-trip time to a peer from request/reply pairs, and derives how long to
+/// wait for a reply before retrying, the way TCP does (Jacobson/Karels, RFC 6298). The
+/// timeout adapts to the latency and its jitter, and doubles after every timeout until a
+/// reply arrives again.
+#[derive(Debug, Clone, Copy)]
+pub struct RttEstimator {
+    /// Smoothed round-trip time, once there's a sample.
+    srtt: Option<Duration>,
+    /// Smoothed deviation of the round-trip time.
+    rttvar: Duration,
+    rto: Duration,
+    min_rto: Duration,
+    max_rto: Duration,
+}
+
+impl RttEstimator {
+    /// Waits `initial_rto` until the first sample, and never less than `min_rto` or more
+    /// than `max_rto`.
+    pub fn new(initial_rto: Duration, min_rto: Duration, max_rto: Duration) -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: initial_rto.clamp(min_rto, max_rto),
+            min_rto,
+            max_rto,
+        }
+    }
+
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// How long to wait for a reply before retrying.
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    /// Takes in the round-trip time of a request that wasn't retried.
+    pub fn sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                (srtt * 7 + rtt) / 8
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt + self.rttvar * 4).clamp(self.min_rto, self.max_rto);
+    }
+
+    /// Doubles the timeout after a request timed out.
+    pub fn back_off(&mut self) {
+        self.rto = (self.rto * 2).min(self.max_rto);
+    }
+}
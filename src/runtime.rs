@@ -0,0 +1,474 @@
+use std::borrow::Cow;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::sync::mpsc::{SyncSender, TryRecvError, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::admin::{self, AdminRequest};
+use crate::chaos::{self, ChaosWriter};
+use crate::chunk::{ChunkingWriter, CHUNK_SIZE};
+use crate::clock::{Clock, SystemClock, Ticker};
+use crate::event_queue::{self, EventReceiver, EventSender, Priority};
+use crate::journal;
+use crate::message::Message;
+use crate::metrics;
+use crate::protocol::{self, Handshake};
+use crate::record;
+use crate::transport;
+
+/// Events of each priority a threaded node can have queued. Once full, the stdin reader
+/// waits, which slows Maelstrom down instead of letting the queue grow without bound.
+pub const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+/// Batches of output not written yet. Once full, a flush waits for the writer.
+const OUTPUT_QUEUE_CAPACITY: usize = 64;
+
+/// Hooks a node runs at the start and the end of its life, and one that shows what it's up
+/// to. All do nothing by default.
+pub trait Lifecycle {
+    /// Runs when `init` arrives, before the node handles it.
+    fn on_init(&mut self, _node_id: &str) {}
+
+    /// Runs once Maelstrom closed stdin and every message before that was handled. Timers
+    /// should be stopped here, and anything outstanding finished. What it writes to
+    /// `output` is flushed before the node exits.
+    fn on_shutdown(&mut self, _output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// A snapshot of the node's internal state, such as its peers, what's pending, and the
+    /// size of its logs, for answering `debug_state` requests.
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+}
+
+/// What a node does with a line from Maelstrom it can't parse, and can't answer with an
+/// error either. Set by `MALFORMED_INPUT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MalformedInput {
+    /// Stops the node with the parse error.
+    #[default]
+    Abort,
+    /// Logs the parse error to stderr, and goes on with the next line.
+    Skip,
+}
+
+impl MalformedInput {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        match std::env::var("MALFORMED_INPUT") {
+            Ok(mode) => mode.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Passes on the result of handling a line, unless it failed because the line is
+    /// malformed and such lines are skipped. The error is then logged, and `None` returned.
+    pub fn check<T>(self, result: Result<T, anyhow::Error>) -> Result<Option<T>, anyhow::Error> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self == Self::Skip && is_malformed(&error) => {
+                log::warn!("Skipped malformed input: {error:#}");
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl FromStr for MalformedInput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(MalformedInput::Abort),
+            "skip" => Ok(MalformedInput::Skip),
+            other => anyhow::bail!("Unknown malformed input mode: {other}"),
+        }
+    }
+}
+
+/// In which order a threaded node handles the lines it reads. Set by `EVENT_ORDER`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventOrder {
+    /// Requests of clients and responses of services go before messages from other nodes.
+    #[default]
+    Priority,
+    /// Every line is handled in the order it was read, for a run that doesn't depend on
+    /// how fast the node reads its input, such as a golden transcript.
+    Arrival,
+}
+
+impl EventOrder {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        match std::env::var("EVENT_ORDER") {
+            Ok(order) => order.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// The priority `line` is queued with.
+    pub fn priority_of(self, line: impl AsRef<[u8]>) -> Priority {
+        match self {
+            EventOrder::Priority => priority_of(line),
+            EventOrder::Arrival => Priority::Low,
+        }
+    }
+}
+
+impl FromStr for EventOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "priority" => Ok(EventOrder::Priority),
+            "arrival" => Ok(EventOrder::Arrival),
+            other => anyhow::bail!("Unknown event order: {other}"),
+        }
+    }
+}
+
+/// Whether `error` comes from deserializing a line, rather than from reading or writing.
+pub fn is_malformed(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<serde_json::Error>()
+        .is_some_and(|error| !error.is_io())
+}
+
+/// What the event loop of a threaded node receives: its own events, plus the start and the
+/// end of its input. Events made from a line of input come with the type of its message.
+pub enum Input<E> {
+    Init(String),
+    Event(E),
+    Received(metrics::Kind, E),
+    /// Answered by the runtime, without the node seeing it.
+    Admin(Message<AdminRequest>),
+    Eof,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename = "init")]
+struct InitHeader {
+    node_id: String,
+    #[serde(flatten)]
+    handshake: Handshake,
+}
+
+/// Records a line received, if recording, and counts it in the metrics. Returns the type of
+/// its message. An `init` negotiates the protocol, and names the journal, if journaling.
+pub fn received(line: &[u8]) -> metrics::Kind {
+    record::line(line);
+    if let Some(init) = init_header(line) {
+        protocol::negotiate(&init.handshake);
+        if journal::enabled() {
+            journal::set_node_id(&init.node_id);
+        }
+    }
+    metrics::record_received(line)
+}
+
+/// The node id of an `init` message, or `None` for any other line.
+pub fn init_node_id(line: impl AsRef<[u8]>) -> Option<String> {
+    init_header(line.as_ref()).map(|init| init.node_id)
+}
+
+fn init_header(line: &[u8]) -> Option<InitHeader> {
+    if !line.windows(6).any(|window| window == b"\"init\"") {
+        return None;
+    }
+    let message: Message<InitHeader> = serde_json::from_slice(line).ok()?;
+    Some(message.body)
+}
+
+/// Reads the next line into `buffer`, replacing what it held, so that reading a line
+/// doesn't allocate once the buffer is large enough. Returns `false` at the end of the
+/// input. The newline is kept, which JSON ignores.
+fn read_line(input: &mut impl BufRead, buffer: &mut Vec<u8>) -> Result<bool, anyhow::Error> {
+    buffer.clear();
+    let read = input
+        .read_until(b'\n', buffer)
+        .context("Failed to read from STDIN.")?;
+    Ok(read > 0)
+}
+
+/// Runs a single-threaded node: hands every line of `input` to `handle`, and runs the
+/// node's lifecycle hooks around them. Flushes `output` after every line. Lines `handle`
+/// can't parse stop the node, unless `MALFORMED_INPUT` says to skip them. Admin requests
+/// are answered here, every line is counted in the metrics, and every one handled is
+/// journaled.
+pub fn run_lines<N: Lifecycle, W: Write>(
+    node: &mut N,
+    mut input: impl BufRead,
+    output: &mut W,
+    mut handle: impl FnMut(&mut N, &[u8], &mut W) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let malformed = MalformedInput::from_env()?;
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        if let Some(node_id) = init_node_id(&line) {
+            node.on_init(&node_id);
+        }
+        let kind = received(&line);
+        if let Some(request) = admin::parse(kind, &line) {
+            request.answer(|| node.debug_state()).send(output)?;
+        } else {
+            let start = Instant::now();
+            if malformed.check(handle(node, &line, output))?.is_some() {
+                metrics::record_latency(kind, start.elapsed());
+                journal::event(kind.as_str(), || node.debug_state());
+            }
+        }
+        output.flush().context("Failed to flush STDOUT.")?;
+    }
+
+    node.on_shutdown(output)?;
+    metrics::log_snapshot();
+    output.flush().context("Failed to flush STDOUT.")
+}
+
+/// The queue between the stdin reader, timers, and the event loop of a threaded node.
+pub fn event_channel<E>() -> (EventSender<Input<E>>, EventReceiver<Input<E>>) {
+    event_queue::channel(EVENT_QUEUE_CAPACITY)
+}
+
+#[derive(Deserialize)]
+struct Source<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+}
+
+/// How urgent a line from Maelstrom is. Messages from other nodes, such as gossip, wait for
+/// the requests of clients and the responses of services, which a client is waiting on.
+pub fn priority_of(line: impl AsRef<[u8]>) -> Priority {
+    match serde_json::from_slice::<Source>(line.as_ref()) {
+        Ok(Source { src }) if is_node_id(&src) => Priority::Low,
+        _ => Priority::High,
+    }
+}
+
+/// Whether `id` is that of a node, like `n3`, rather than of a client or a service.
+pub fn is_node_id(id: &str) -> bool {
+    id.strip_prefix('n')
+        .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Reads lines from stdin, or the transport installed, on a thread of its own, and sends
+/// each one to the event loop as an event made by `parse`, with the priority of the line.
+/// Ends with `Input::Eof`, also when a line can't be parsed and `MALFORMED_INPUT` doesn't
+/// say to skip it, in which case the thread returns the error.
+pub fn spawn_stdin_reader<E: Send + 'static>(
+    sender: EventSender<Input<E>>,
+    mut parse: impl FnMut(&[u8]) -> Result<E, anyhow::Error> + Send + 'static,
+) -> JoinHandle<Result<(), anyhow::Error>> {
+    std::thread::spawn(move || {
+        let mut read = || {
+            let malformed = MalformedInput::from_env()?;
+            let order = EventOrder::from_env()?;
+            let mut input = transport::input();
+            let mut line = Vec::new();
+            while read_line(&mut input, &mut line)? {
+                if let Some(node_id) = init_node_id(&line) {
+                    if sender.send(Input::Init(node_id), Priority::High).is_err() {
+                        break;
+                    }
+                }
+                let kind = received(&line);
+                let input = match admin::parse(kind, &line) {
+                    Some(request) => Input::Admin(request),
+                    None => match malformed.check(parse(&line))? {
+                        Some(event) => Input::Received(kind, event),
+                        None => continue,
+                    },
+                };
+                if sender.send(input, order.priority_of(&line)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        let result = read();
+        // Behind every event from stdin, as those of low priority are received last.
+        let _ = sender.send(Input::Eof, Priority::Low);
+        result
+    })
+}
+
+/// Runs the event loop of a threaded node until its input ends: hands every event to
+/// `process`, and runs the node's lifecycle hooks. Flushes `output` whenever no more events
+/// are queued, and at the end. Returns the reader's error, if it stopped because of one.
+/// Every event processed is journaled, those not received as `timer`.
+pub fn run_events<N: Lifecycle, E, W: Write>(
+    node: &mut N,
+    receiver: EventReceiver<Input<E>>,
+    reader: JoinHandle<Result<(), anyhow::Error>>,
+    output: &mut W,
+    mut process: impl FnMut(&mut N, E, &mut W) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let mut next = receiver.recv();
+    while let Some(input) = next {
+        match input {
+            Input::Init(node_id) => node.on_init(&node_id),
+            Input::Event(event) => {
+                process(node, event, output)?;
+                journal::event("timer", || node.debug_state());
+            }
+            Input::Received(kind, event) => {
+                let start = Instant::now();
+                process(node, event, output)?;
+                metrics::record_latency(kind, start.elapsed());
+                journal::event(kind.as_str(), || node.debug_state());
+            }
+            Input::Admin(request) => request.answer(|| node.debug_state()).send(output)?,
+            Input::Eof => break,
+        }
+        // Events that queued up while one was processed form a batch, whose output is
+        // written at once.
+        next = match receiver.try_recv() {
+            Ok(input) => Some(input),
+            Err(TryRecvError::Empty) => {
+                output.flush().context("Failed to flush STDOUT.")?;
+                receiver.recv()
+            }
+            Err(TryRecvError::Disconnected) => None,
+        };
+    }
+
+    node.on_shutdown(output)?;
+    metrics::log_snapshot();
+    output.flush().context("Failed to flush STDOUT.")?;
+    reader
+        .join()
+        .map_err(|e| anyhow::anyhow!("Thread panicked: {:?}", e))?
+}
+
+/// Sends a low priority event at a fixed interval, until it is dropped. An event is
+/// skipped while the queue is full: the node is behind already, and a tick that's still
+/// queued will do the same work.
+#[derive(Debug)]
+pub struct Timer {
+    _ticker: Ticker,
+}
+
+impl Timer {
+    /// Ticks on a thread of its own, in real time.
+    pub fn start<E: Send + 'static>(
+        interval: Duration,
+        sender: EventSender<E>,
+        event: impl Fn() -> E + Send + 'static,
+    ) -> Self {
+        Self::start_on(&SystemClock, interval, sender, event)
+    }
+
+    /// Ticks as scheduled by `clock`.
+    pub fn start_on<E: Send + 'static>(
+        clock: &dyn Clock,
+        interval: Duration,
+        sender: EventSender<E>,
+        event: impl Fn() -> E + Send + 'static,
+    ) -> Self {
+        let tick = move || match sender.try_send(event(), Priority::Low) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        };
+        Self {
+            _ticker: clock.schedule(interval, Box::new(tick)),
+        }
+    }
+}
+
+/// What a node writes, buffered until it's flushed and then written by a thread of its own.
+/// Serializing a batch of messages thus costs no syscalls, and the node doesn't wait for the
+/// write.
+pub struct Output {
+    buffer: Vec<u8>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    handle: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl Output {
+    /// Writes to stdout, or the transport installed instead, through the chaos installed, if
+    /// any. Messages to other nodes too large for a line are split into chunks, once chunking
+    /// was agreed to.
+    pub fn stdout() -> Self {
+        let output = transport::output();
+        match chaos::installed() {
+            Some(chaos) => Self::spawn(ChunkingWriter::new(
+                ChaosWriter::new(output, chaos),
+                CHUNK_SIZE,
+            )),
+            None => Self::spawn(ChunkingWriter::new(output, CHUNK_SIZE)),
+        }
+    }
+
+    /// Writes to `writer`, flushing it once per batch, or once per few batches if they
+    /// arrive faster than they're written.
+    pub fn spawn(mut writer: impl Write + Send + 'static) -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<Vec<u8>>(OUTPUT_QUEUE_CAPACITY);
+        let handle = std::thread::spawn(move || {
+            while let Ok(batch) = receiver.recv() {
+                writer.write_all(&batch)?;
+                while let Ok(batch) = receiver.try_recv() {
+                    writer.write_all(&batch)?;
+                }
+                writer.flush()?;
+            }
+            Ok(())
+        });
+
+        Self {
+            buffer: Vec::new(),
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Writes what's buffered, waits until everything is written, and returns the error the
+    /// writer stopped with, if any.
+    pub fn finish(mut self) -> Result<(), anyhow::Error> {
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        self.flush().context("Failed to flush STDOUT.")?;
+        self.sender.take();
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|e| anyhow::anyhow!("Thread panicked: {:?}", e))?
+                .context("Failed to write to STDOUT."),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Hands the buffer to the writer thread. Fails if the thread stopped because of an error.
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.buffer);
+        match &self.sender {
+            Some(sender) if sender.send(batch).is_ok() => Ok(()),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "STDOUT writer stopped",
+            )),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The version of every origin's entry a node has, as scuttlebutt reconciles state (van
+/// Renesse et al., "Efficient Reconciliation and Flow Control for Anti-Entropy Protocols"):
+/// a node sends a peer its digest, and the peer answers with only the entries it has newer
+/// versions of, and its own digest for the node to do the same. An entry's version must
+/// grow whenever the entry does, like the count of a grow-only counter, so that a higher
+/// version always means a newer entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Digest(BTreeMap<String, u64>);
+
+impl Digest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The version of `origin`'s entry, zero if there's none.
+    pub fn get(&self, origin: &str) -> u64 {
+        self.0.get(origin).copied().unwrap_or(0)
+    }
+
+    /// Raises the version of `origin`'s entry to `version`, unless it's higher already.
+    pub fn observe(&mut self, origin: &str, version: u64) {
+        let entry = self.0.entry(origin.to_string()).or_insert(0);
+        *entry = (*entry).max(version);
+    }
+
+    /// The origins whose version in `newer` is above the one in this digest: the entries
+    /// the node this digest came from lacks, of those of the node `newer` came from.
+    pub fn missing_from<'a>(&'a self, newer: &'a Digest) -> impl Iterator<Item = &'a str> + 'a {
+        newer
+            .0
+            .iter()
+            .filter(|(origin, &version)| version > self.get(origin))
+            .map(|(origin, _)| origin.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0
+            .iter()
+            .map(|(origin, &version)| (origin.as_str(), version))
+    }
+}
+
+impl<'a> FromIterator<(&'a str, u64)> for Digest {
+    fn from_iter<I: IntoIterator<Item = (&'a str, u64)>>(iter: I) -> Self {
+        let mut digest = Digest::new();
+        for (origin, version) in iter {
+            digest.observe(origin, version);
+        }
+        digest
+    }
+}
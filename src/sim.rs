@@ -0,0 +1,431 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::clock::{Clock, VirtualClock};
+use crate::message::{Message, RawMessage};
+use crate::rng::Rng;
+
+/// How much virtual time passes between two chances for nodes to tick, while no message is
+/// due.
+const TICK: Duration = Duration::from_millis(1);
+
+/// How long `Network::request` waits for a reply, in virtual time.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The client the network sends requests from.
+const CLIENT: &str = "c1";
+
+/// A node of a workload, driven by a `Network` instead of stdin and stdout. Every line it
+/// writes is a message the network routes.
+pub trait SimNode {
+    /// Handles a line received, like the node's runtime does.
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error>;
+
+    /// Does the work that's due by the network's clock, such as a gossip round. Does nothing
+    /// by default.
+    fn tick(&mut self, _output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// A single-threaded node as a `SimNode`, which handles lines with `handle` like it does
+/// under `runtime::run_lines`.
+pub fn lines<N: 'static>(
+    node: N,
+    handle: impl FnMut(&mut N, &[u8], &mut Vec<u8>) -> Result<(), anyhow::Error> + 'static,
+) -> Box<dyn SimNode> {
+    Box::new(Lines { node, handle })
+}
+
+struct Lines<N, F> {
+    node: N,
+    handle: F,
+}
+
+impl<N, F> SimNode for Lines<N, F>
+where
+    F: FnMut(&mut N, &[u8], &mut Vec<u8>) -> Result<(), anyhow::Error>,
+{
+    fn handle(&mut self, line: &str, output: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        (self.handle)(&mut self.node, line.as_bytes(), output)
+    }
+}
+
+/// The faults of the links between nodes. Messages between a node and a client always
+/// arrive, after the latency.
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    /// How long a message takes to arrive.
+    pub latency: Duration,
+    /// Fraction of the messages between nodes that are held back by up to ten times the
+    /// latency, so that later ones overtake them.
+    pub reorder_rate: f64,
+    /// Fraction of the messages between nodes that are lost.
+    pub drop_rate: f64,
+    /// Seeds which messages are held back or lost.
+    pub seed: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(1),
+            reorder_rate: 0.0,
+            drop_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A message the network delivered, in the order of `Network::trace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    /// Virtual time since the network started.
+    pub at: Duration,
+    pub src: String,
+    pub dest: String,
+    pub kind: Option<String>,
+}
+
+/// Runs nodes in-process, in virtual time, connected through an in-memory router. Time
+/// only passes while the network runs, straight to the next message that's due, so a run
+/// takes as long as the nodes need to handle their messages. Messages to ids that aren't
+/// nodes are replies to the client, and are kept until asked for. Services such as `seq-kv`
+/// don't exist here, so messages to them are lost.
+///
+/// Nodes that read the time from `Network::clock`, and seed their random choices, run the
+/// same way every time: the faults of each link are drawn from a generator of its own,
+/// seeded with the network's seed, so they don't depend on the order in which nodes happen
+/// to send to different peers.
+pub struct Network {
+    config: NetworkConfig,
+    clock: Arc<VirtualClock>,
+    nodes: BTreeMap<String, Box<dyn SimNode>>,
+    /// The generator of the faults of each link, by its source and destination.
+    links: BTreeMap<(String, String), Rng>,
+    /// The group of each node while the network is partitioned.
+    partition: BTreeMap<String, usize>,
+    /// Messages on their way, by when they arrive and then in the order they were sent.
+    queue: BinaryHeap<Reverse<(Instant, u64, String)>>,
+    sent: u64,
+    next_msg_id: u64,
+    /// Replies to the client, by the request they answer.
+    replies: BTreeMap<u64, Value>,
+    dropped: u64,
+    trace: Vec<Delivery>,
+}
+
+impl Network {
+    pub fn new(config: NetworkConfig) -> Self {
+        Self {
+            config,
+            clock: Arc::new(VirtualClock::new()),
+            nodes: BTreeMap::new(),
+            links: BTreeMap::new(),
+            partition: BTreeMap::new(),
+            queue: BinaryHeap::new(),
+            sent: 0,
+            next_msg_id: 0,
+            replies: BTreeMap::new(),
+            dropped: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    /// The clock of the network, for nodes to read the time from.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Virtual time since the network started.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    /// Adds a node with the id `id`, which gets its `init` from `init`.
+    pub fn add_node(&mut self, id: impl Into<String>, node: Box<dyn SimNode>) {
+        self.nodes.insert(id.into(), node);
+    }
+
+    pub fn node_ids(&self) -> Vec<String> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    /// Number of messages between nodes lost so far, also to partitions.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Every message delivered so far, to nodes and to the client.
+    pub fn trace(&self) -> &[Delivery] {
+        &self.trace
+    }
+
+    /// Splits the nodes into `groups`, between which messages are lost until `heal`. Nodes
+    /// in no group form one more.
+    pub fn partition(&mut self, groups: &[Vec<String>]) {
+        self.partition = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, nodes)| nodes.iter().map(move |node| (node.clone(), group)))
+            .collect();
+    }
+
+    pub fn heal(&mut self) {
+        self.partition.clear();
+    }
+
+    fn connected(&self, src: &str, dest: &str) -> bool {
+        self.partition.is_empty() || self.partition.get(src) == self.partition.get(dest)
+    }
+
+    /// Sends every node its `init`, and waits for them to answer it.
+    pub fn init(&mut self) -> Result<(), anyhow::Error> {
+        let node_ids = self.node_ids();
+        let requests: Vec<u64> = node_ids
+            .iter()
+            .map(|node_id| {
+                self.send(
+                    node_id,
+                    json!({"type": "init", "node_id": node_id, "node_ids": node_ids}),
+                )
+            })
+            .collect();
+        for msg_id in requests {
+            self.reply(msg_id)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a request from the client to `node`, filling in its `msg_id`, and returns the
+    /// `msg_id`.
+    pub fn send(&mut self, node: &str, mut body: Value) -> u64 {
+        self.next_msg_id += 1;
+        body["msg_id"] = json!(self.next_msg_id);
+        let message = Message {
+            src: CLIENT.to_string(),
+            dest: node.to_string(),
+            body,
+        };
+        let line = serde_json::to_string(&message).expect("A message serializes");
+        self.enqueue(self.config.latency, line);
+        self.next_msg_id
+    }
+
+    /// Runs the network until the reply to the request with `msg_id` arrives, and returns
+    /// its body. Fails if the reply takes longer than a few seconds.
+    pub fn reply(&mut self, msg_id: u64) -> Result<Value, anyhow::Error> {
+        let deadline = self.clock.now() + REQUEST_TIMEOUT;
+        self.run_until(deadline, |network| network.replies.contains_key(&msg_id))?;
+        self.replies
+            .remove(&msg_id)
+            .with_context(|| format!("No reply to request {msg_id}"))
+    }
+
+    /// Sends a request to `node`, and returns the body of the reply.
+    pub fn request(&mut self, node: &str, body: Value) -> Result<Value, anyhow::Error> {
+        let msg_id = self.send(node, body);
+        self.reply(msg_id)
+    }
+
+    /// Runs the network for `duration` of virtual time.
+    pub fn run_for(&mut self, duration: Duration) -> Result<(), anyhow::Error> {
+        self.run_until(self.clock.now() + duration, |_| false)
+    }
+
+    /// Delivers messages as they arrive, and lets nodes tick in between, until `done` holds
+    /// or `deadline` passes.
+    fn run_until(
+        &mut self,
+        deadline: Instant,
+        mut done: impl FnMut(&Self) -> bool,
+    ) -> Result<(), anyhow::Error> {
+        loop {
+            self.step()?;
+            let now = self.clock.now();
+            if done(self) || now >= deadline {
+                return Ok(());
+            }
+            let next = self
+                .queue
+                .peek()
+                .map_or(deadline, |Reverse((at, _, _))| *at)
+                .min(now + TICK)
+                .min(deadline);
+            self.clock.advance_to(next);
+        }
+    }
+
+    /// Delivers the messages that arrived by now, and then lets every node tick.
+    fn step(&mut self) -> Result<(), anyhow::Error> {
+        let now = self.clock.now();
+        while self
+            .queue
+            .peek()
+            .is_some_and(|Reverse((at, _, _))| *at <= now)
+        {
+            let Some(Reverse((_, _, line))) = self.queue.pop() else {
+                break;
+            };
+            self.deliver(line)?;
+        }
+
+        for id in self.node_ids() {
+            let mut output = Vec::new();
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.tick(&mut output)
+                    .with_context(|| format!("Node {id} failed"))?;
+            }
+            self.route(&output)?;
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, line: String) -> Result<(), anyhow::Error> {
+        let message = RawMessage::parse_raw(&line)?;
+        self.trace.push(Delivery {
+            at: self.clock.elapsed(),
+            src: message.src.clone(),
+            dest: message.dest.clone(),
+            kind: message.kind(),
+        });
+        let Some(node) = self.nodes.get_mut(&message.dest) else {
+            if let Some(in_reply_to) = message.in_reply_to() {
+                let body = serde_json::from_str(message.body.get())?;
+                self.replies.insert(in_reply_to, body);
+            }
+            return Ok(());
+        };
+        let mut output = Vec::new();
+        node.handle(&line, &mut output)
+            .with_context(|| format!("Node {} failed", message.dest))?;
+        self.route(&output)
+    }
+
+    /// Sends on every message in `output`, unless its link loses it.
+    fn route(&mut self, output: &[u8]) -> Result<(), anyhow::Error> {
+        for line in output
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+        {
+            let message = RawMessage::parse_raw(line)?;
+            let mut delay = self.config.latency;
+            if self.nodes.contains_key(&message.dest) {
+                if !self.connected(&message.src, &message.dest) {
+                    self.dropped += 1;
+                    continue;
+                }
+                let seed = self.config.seed;
+                let rng = self
+                    .links
+                    .entry((message.src.clone(), message.dest.clone()))
+                    .or_insert_with(|| {
+                        Rng::for_node(Some(seed), &format!("{}>{}", message.src, message.dest))
+                    });
+                if rng.chance(self.config.drop_rate) {
+                    self.dropped += 1;
+                    continue;
+                }
+                if rng.chance(self.config.reorder_rate) {
+                    let held = rng.below(10 * self.config.latency.as_micros() as usize + 1);
+                    delay += Duration::from_micros(held as u64);
+                }
+            }
+            self.enqueue(delay, String::from_utf8_lossy(line).into_owned());
+        }
+        Ok(())
+    }
+
+    fn enqueue(&mut self, delay: Duration, line: String) {
+        self.sent += 1;
+        self.queue
+            .push(Reverse((self.clock.now() + delay, self.sent, line)));
+    }
+
+    /// Runs the steps of a schedule one after the other. Requests aren't waited on, and
+    /// their replies are kept like any other.
+    pub fn play(&mut self, steps: &[Step]) -> Result<(), anyhow::Error> {
+        for step in steps {
+            match step {
+                Step::Request { node, body } => {
+                    self.send(node, body.clone());
+                }
+                Step::Partition(groups) => self.partition(groups),
+                Step::Heal => self.heal(),
+                Step::Wait(duration) => self.run_for(*duration)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Something that happens to a network in a `Schedule`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// A request from the client to a node.
+    Request {
+        node: String,
+        body: Value,
+    },
+    Partition(Vec<Vec<String>>),
+    Heal,
+    /// Lets the network run for a while.
+    Wait(Duration),
+}
+
+/// A scenario that runs the same way every time: the seed of the network, and of the
+/// random choices of its nodes, and the steps played on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub seed: u64,
+    pub steps: Vec<Step>,
+}
+
+impl Schedule {
+    /// The smallest schedule found for which `fails` still holds, given one for which it
+    /// does. Tries to leave out runs of steps, from halves of the schedule down to single
+    /// steps, and then to halve the waits, until neither makes it fail any more. `fails` has
+    /// to run the schedule on a network of its own, so that every try starts afresh.
+    pub fn shrink(&self, mut fails: impl FnMut(&Schedule) -> bool) -> Schedule {
+        let mut smallest = self.clone();
+        loop {
+            let mut shrunk = false;
+            let mut chunk = smallest.steps.len().div_ceil(2);
+            while chunk > 0 {
+                let mut start = 0;
+                while start < smallest.steps.len() {
+                    let mut candidate = smallest.clone();
+                    let end = (start + chunk).min(candidate.steps.len());
+                    candidate.steps.drain(start..end);
+                    if fails(&candidate) {
+                        smallest = candidate;
+                        shrunk = true;
+                    } else {
+                        start += chunk;
+                    }
+                }
+                chunk /= 2;
+            }
+
+            for i in 0..smallest.steps.len() {
+                let Step::Wait(duration) = smallest.steps[i] else {
+                    continue;
+                };
+                let mut candidate = smallest.clone();
+                candidate.steps[i] = Step::Wait(duration / 2);
+                if duration >= 2 * TICK && fails(&candidate) {
+                    smallest = candidate;
+                    shrunk = true;
+                }
+            }
+            if !shrunk {
+                return smallest;
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::message::{Message, RawMessage};
+use crate::sim::SimNode;
+
+/// The client that `TestNode::request` sends from.
+pub const CLIENT: &str = "c1";
+
+/// A message a node sent, with its body as JSON.
+pub type Sent = Message<Value>;
+
+/// A single node under test, handed one message at a time, which returns what the node sent
+/// in response. It panics when the node fails, as only tests use it.
+pub struct TestNode {
+    node: Box<dyn SimNode>,
+    id: String,
+    next_msg_id: u64,
+}
+
+impl TestNode {
+    /// Inits `node` as `id`, in a cluster of `node_ids`. Panics unless it answers with
+    /// `init_ok`.
+    pub fn init(node: Box<dyn SimNode>, id: &str, node_ids: &[&str]) -> Self {
+        Self::start(node, id, node_ids).0
+    }
+
+    /// Like `init`, also returning what the node sent other nodes as it started, such as
+    /// requests for state it lost.
+    pub fn start(node: Box<dyn SimNode>, id: &str, node_ids: &[&str]) -> (Self, Vec<Sent>) {
+        let mut node = Self {
+            node,
+            id: id.to_string(),
+            next_msg_id: 0,
+        };
+        let sent = node.request(json!({"type": "init", "node_id": id, "node_ids": node_ids}));
+        assert_eq!(replies(&sent), ["init_ok"], "{id} didn't answer init");
+        let to_nodes = sent
+            .into_iter()
+            .filter(|message| message.dest != CLIENT)
+            .collect();
+        (node, to_nodes)
+    }
+
+    /// Hands the node a message from `src`, with a `msg_id` unless `body` has one already.
+    pub fn receive(&mut self, src: &str, mut body: Value) -> Vec<Sent> {
+        if body.get("msg_id").is_none() {
+            self.next_msg_id += 1;
+            body["msg_id"] = json!(self.next_msg_id);
+        }
+        let message = Message {
+            src: src.to_string(),
+            dest: self.id.clone(),
+            body,
+        };
+        let line = serde_json::to_string(&message).expect("A message serializes");
+        let mut output = Vec::new();
+        if let Err(error) = self.node.handle(&line, &mut output) {
+            panic!("{} failed to handle {line}: {error:#}", self.id);
+        }
+        parse(&output)
+    }
+
+    /// Hands the node a request from the client.
+    pub fn request(&mut self, body: Value) -> Vec<Sent> {
+        self.receive(CLIENT, body)
+    }
+
+    /// Lets the node do the work that's due, such as a gossip round.
+    pub fn tick(&mut self) -> Vec<Sent> {
+        let mut output = Vec::new();
+        if let Err(error) = self.node.tick(&mut output) {
+            panic!("{} failed to tick: {error:#}", self.id);
+        }
+        parse(&output)
+    }
+}
+
+fn parse(output: &[u8]) -> Vec<Sent> {
+    output
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let message = RawMessage::parse_raw(line).expect("Nodes send messages");
+            message.decode().expect("Bodies are JSON")
+        })
+        .collect()
+}
+
+/// The `type` of a message.
+pub fn kind(message: &Sent) -> &str {
+    message.body["type"].as_str().unwrap_or_default()
+}
+
+/// The types of the messages sent to the client, in order.
+pub fn replies(sent: &[Sent]) -> Vec<&str> {
+    sent.iter()
+        .filter(|message| message.dest == CLIENT)
+        .map(kind)
+        .collect()
+}
+
+/// The messages sent to other nodes, in order.
+pub fn to_nodes(sent: &[Sent]) -> Vec<&Sent> {
+    sent.iter()
+        .filter(|message| message.dest != CLIENT)
+        .collect()
+}
+
+/// A body of the type `kind`, without any fields yet.
+pub fn body(kind: &str) -> Value {
+    json!({ "type": kind })
+}
+
+/// A value for a field of a body, as `assert_replies!` builds them.
+pub fn value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).expect("A field serializes")
+}
+
+/// Sends a request with the given type and fields to a `TestNode`, and asserts the types of
+/// the replies the client gets. Evaluates to everything the node sent, for further checks:
+///
+/// ```ignore
+/// let sent = assert_replies!(node, broadcast { message: 5 }, [broadcast_ok]);
+/// assert_replies!(node, read, [read_ok]);
+/// ```
+#[macro_export]
+macro_rules! assert_replies {
+    ($node:expr, $kind:ident $({ $($field:ident : $value:expr),* $(,)? })?, [$($reply:ident),* $(,)?]) => {{
+        #[allow(unused_mut)]
+        let mut body = $crate::testkit::body(stringify!($kind));
+        $($(body[stringify!($field)] = $crate::testkit::value($value);)*)?
+        let sent = $node.request(body);
+        let expected: &[&str] = &[$(stringify!($reply)),*];
+        assert_eq!($crate::testkit::replies(&sent), expected, "Replies to {}", stringify!($kind));
+        sent
+    }};
+}
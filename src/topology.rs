@@ -0,0 +1,111 @@
+use std::str::FromStr;
+
+/// An overlay a node can build from the cluster's node ids instead of using the topology
+/// Maelstrom suggests. Every node builds the same one, and links always go both ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    /// Whatever Maelstrom sends in the `topology` message.
+    #[default]
+    Maelstrom,
+    /// A spanning tree in which every node has up to `fanout` children. Depth, and so
+    /// latency, shrinks as the fanout grows.
+    Tree { fanout: usize },
+    /// Every node linked to the next and previous one. Fewest links, highest latency.
+    Ring,
+    /// Nodes laid out row by row on a square grid, linked to the nodes above, below, left,
+    /// and right.
+    Grid,
+    /// `hubs` nodes linked to each other, and every other node linked to one of the hubs.
+    /// A single hub makes a star.
+    HubSpoke { hubs: usize },
+}
+
+impl FromStr for Topology {
+    type Err = anyhow::Error;
+
+    /// Parses `maelstrom`, `tree:<fanout>`, `ring`, `grid`, `star`, or `hubs:<count>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let count = |arg: Option<&str>| -> Result<usize, anyhow::Error> {
+            match arg.map(str::parse::<usize>) {
+                Some(Ok(count)) if count > 0 => Ok(count),
+                _ => anyhow::bail!("Topology {name} needs a positive count, e.g. {name}:4"),
+            }
+        };
+
+        match name {
+            "maelstrom" => Ok(Topology::Maelstrom),
+            "tree" => Ok(Topology::Tree {
+                fanout: count(arg)?,
+            }),
+            "ring" => Ok(Topology::Ring),
+            "grid" => Ok(Topology::Grid),
+            "star" => Ok(Topology::HubSpoke { hubs: 1 }),
+            "hubs" => Ok(Topology::HubSpoke { hubs: count(arg)? }),
+            other => anyhow::bail!("Unknown topology: {other}"),
+        }
+    }
+}
+
+impl Topology {
+    /// The neighbours of `node_id`, or `None` if Maelstrom's topology should be used.
+    pub fn neighbours(&self, node_id: &str, node_ids: &[String]) -> Option<Vec<String>> {
+        let mut nodes = node_ids.to_vec();
+        nodes.sort();
+        let count = nodes.len();
+        let index = nodes.iter().position(|id| id == node_id)?;
+
+        let mut neighbours: Vec<usize> = match *self {
+            Topology::Maelstrom => return None,
+
+            Topology::Tree { fanout } => {
+                let parent = (index > 0).then(|| (index - 1) / fanout);
+                let children =
+                    (index * fanout + 1..=index * fanout + fanout).filter(|&i| i < count);
+                parent.into_iter().chain(children).collect()
+            }
+
+            Topology::Ring => vec![(index + 1) % count, (index + count - 1) % count],
+
+            Topology::Grid => {
+                let columns = (1..=count).find(|c| c * c >= count).unwrap_or(1);
+                let (row, column) = (index / columns, index % columns);
+                let mut cells = vec![index + columns];
+                if row > 0 {
+                    cells.push(index - columns);
+                }
+                if column > 0 {
+                    cells.push(index - 1);
+                }
+                if column + 1 < columns {
+                    cells.push(index + 1);
+                }
+                cells.into_iter().filter(|&i| i < count).collect()
+            }
+
+            Topology::HubSpoke { hubs } => {
+                let hubs = hubs.min(count);
+                if index < hubs {
+                    (0..hubs)
+                        .chain((hubs..count).filter(|i| i % hubs == index))
+                        .collect()
+                } else {
+                    vec![index % hubs]
+                }
+            }
+        };
+
+        neighbours.sort();
+        neighbours.dedup();
+        Some(
+            neighbours
+                .into_iter()
+                .filter(|&i| i != index)
+                .map(|i| nodes[i].clone())
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,511 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{self, Reassembling};
+use crate::protocol;
+
+/// How long connecting to a peer may take. The writer waits meanwhile, so a peer that's
+/// down costs at most this much per message sent to it.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where a node's messages come from and go to, one JSON message per line. A node reads
+/// every line it receives from `input`, whoever sent it, and writes every line it sends to
+/// `output`, which delivers it to its `dest`.
+pub trait Transport: Send {
+    fn input(&mut self) -> Box<dyn BufRead>;
+    fn output(&mut self) -> Box<dyn Write + Send>;
+}
+
+/// Maelstrom's transport: stdin and stdout, with Maelstrom doing the routing.
+#[derive(Debug, Default)]
+pub struct Stdio;
+
+impl Transport for Stdio {
+    fn input(&mut self) -> Box<dyn BufRead> {
+        Box::new(std::io::stdin().lock())
+    }
+
+    fn output(&mut self) -> Box<dyn Write + Send> {
+        Box::new(std::io::stdout())
+    }
+}
+
+static TRANSPORT: Mutex<Option<Box<dyn Transport>>> = Mutex::new(None);
+
+/// Makes the node of this process send and receive over `transport` instead of stdio.
+pub fn install(transport: Box<dyn Transport>) {
+    *TRANSPORT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(transport);
+}
+
+/// The lines the node receives, over the transport installed, or else stdin, with messages
+/// split into chunks put back together. As it can then take chunks, the node agrees to
+/// chunking from now on.
+pub fn input() -> Box<dyn BufRead> {
+    protocol::support(chunk::CAPABILITY, 1);
+    let input = match &mut *TRANSPORT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+    {
+        Some(transport) => transport.input(),
+        None => Stdio.input(),
+    };
+    Box::new(Reassembling::new(input))
+}
+
+/// Where the node writes what it sends, over the transport installed, or else stdout.
+pub fn output() -> Box<dyn Write + Send> {
+    match &mut *TRANSPORT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+    {
+        Some(transport) => transport.output(),
+        None => Stdio.output(),
+    }
+}
+
+/// The addresses of other nodes, given as a comma separated list of `id=host:port`, e.g.
+/// `n1=10.0.0.2:7000,n2=10.0.0.3:7000`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Peers(pub HashMap<String, String>);
+
+impl FromStr for Peers {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|peer| !peer.is_empty())
+            .map(|peer| {
+                let (id, address) = peer
+                    .split_once('=')
+                    .with_context(|| format!("Expected id=address, got {peer}"))?;
+                Ok((id.trim().to_string(), address.trim().to_string()))
+            })
+            .collect::<Result<_, anyhow::Error>>()
+            .map(Peers)
+    }
+}
+
+/// How messages are written on a connection between nodes over TCP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Codec {
+    /// A line of JSON each, like Maelstrom's.
+    #[default]
+    Json,
+    /// A MessagePack value each, which is smaller and cheaper to parse.
+    #[value(name = "msgpack")]
+    MessagePack,
+}
+
+impl Codec {
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::MessagePack => "msgpack",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Codec::Json),
+            "msgpack" => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Writes a line of JSON, with its newline, to `writer` as this codec says.
+    fn write(self, writer: &mut impl Write, line: &[u8]) -> std::io::Result<()> {
+        match self {
+            Codec::Json => writer.write_all(line),
+            Codec::MessagePack => {
+                let value: serde_json::Value = serde_json::from_slice(line)?;
+                let bytes = rmp_serde::to_vec(&value).map_err(std::io::Error::other)?;
+                writer.write_all(&bytes)
+            }
+        }
+    }
+
+    /// Reads a message from `reader` into `line` as a line of JSON, with its newline.
+    /// Returns false at the end of the connection.
+    fn read(self, reader: &mut impl BufRead, line: &mut Vec<u8>) -> bool {
+        match self {
+            Codec::Json => matches!(reader.read_until(b'\n', line), Ok(read) if read > 0),
+            Codec::MessagePack => {
+                let Ok(value) = rmp_serde::from_read::<_, serde_json::Value>(reader) else {
+                    return false;
+                };
+                serde_json::to_writer(&mut *line, &value).is_ok() && {
+                    line.push(b'\n');
+                    true
+                }
+            }
+        }
+    }
+}
+
+/// The first line a node sends on a connection it made to another, when it would rather
+/// not use JSON. The other node answers with the codec both use from then on: the same one
+/// if it knows it, or else JSON.
+#[derive(Serialize, Deserialize)]
+struct Hello<'a> {
+    #[serde(borrow)]
+    codec: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+    #[serde(borrow)]
+    dest: Cow<'a, str>,
+}
+
+/// Newline-delimited JSON over TCP, for running nodes as networked processes without
+/// Maelstrom. The node listens for connections from clients and other nodes, and connects
+/// to the other nodes in `Peers` when it first sends them something. A message goes out
+/// over the connection its `dest` sent from, if any, so replies to clients go back the way
+/// requests came. A message to a node that can't be reached is lost, as on a network.
+///
+/// Connections start out as JSON, so that clients can use `nc`. A node that connects to
+/// another asks for its `Codec`, and they use it if both know it.
+///
+/// Connections are accepted until the process exits, so the input never ends.
+pub struct Tcp {
+    router: Arc<Router>,
+    receiver: Option<Receiver<Vec<u8>>>,
+}
+
+impl Tcp {
+    /// Listens on `address`, such as `0.0.0.0:7000`, and asks for `codec` on connections to
+    /// peers.
+    pub fn bind(address: &str, peers: Peers, codec: Codec) -> Result<Self, anyhow::Error> {
+        let listener =
+            TcpListener::bind(address).with_context(|| format!("Failed to listen on {address}"))?;
+        log::info!("Listening on {}", listener.local_addr()?);
+        let (lines, receiver) = mpsc::channel();
+        let router = Arc::new(Router {
+            peers: peers.0,
+            codec,
+            connections: Mutex::new(HashMap::new()),
+            lines,
+        });
+        let accepting = Arc::clone(&router);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => accepting.accept(stream),
+                    Err(error) => log::warn!("Failed to accept a connection: {error}"),
+                }
+            }
+        });
+        Ok(Self {
+            router,
+            receiver: Some(receiver),
+        })
+    }
+}
+
+impl Transport for Tcp {
+    /// Only the first call gets the lines. Later ones get an input that's at its end.
+    fn input(&mut self) -> Box<dyn BufRead> {
+        Box::new(Lines {
+            receiver: self.receiver.take(),
+            line: Vec::new(),
+            read: 0,
+        })
+    }
+
+    fn output(&mut self) -> Box<dyn Write + Send> {
+        Box::new(RoutedWriter {
+            router: Arc::clone(&self.router),
+            partial: Vec::new(),
+        })
+    }
+}
+
+/// A connection to write to, and how.
+struct Connection {
+    stream: TcpStream,
+    codec: Codec,
+}
+
+struct Router {
+    peers: HashMap<String, String>,
+    /// The codec asked for on connections to peers.
+    codec: Codec,
+    /// Where to write messages to each node or client, by its id.
+    connections: Mutex<HashMap<String, Connection>>,
+    lines: Sender<Vec<u8>>,
+}
+
+impl Router {
+    /// Reads from a connection that was accepted, on a thread of its own, answering a
+    /// `Hello` first if it starts with one.
+    fn accept(self: &Arc<Self>, stream: TcpStream) {
+        let router = Arc::clone(self);
+        std::thread::spawn(move || {
+            let Ok(reader) = stream.try_clone() else {
+                return;
+            };
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            if !Codec::Json.read(&mut reader, &mut line) {
+                return;
+            }
+            let codec = match serde_json::from_slice::<Hello>(&line) {
+                Ok(hello) => {
+                    let codec = Codec::from_name(&hello.codec).unwrap_or_default();
+                    let answer = Hello {
+                        codec: Cow::Borrowed(codec.name()),
+                    };
+                    let Ok(answer) = serde_json::to_string(&answer) else {
+                        return;
+                    };
+                    if writeln!(&stream, "{answer}").is_err() {
+                        return;
+                    }
+                    line.clear();
+                    codec
+                }
+                Err(_) => Codec::Json,
+            };
+            router.read(stream, reader, codec, line);
+        });
+    }
+
+    /// Reads messages from `stream`, starting with `line` if it isn't empty, and hands them
+    /// to the node as lines of JSON. Messages to whoever sent them go out over `stream` from
+    /// then on, unless they have a connection already, and until `stream` is closed.
+    fn read(
+        &self,
+        stream: TcpStream,
+        mut reader: BufReader<TcpStream>,
+        codec: Codec,
+        mut line: Vec<u8>,
+    ) {
+        let _ = stream.set_nodelay(true);
+        let mut senders = Vec::new();
+        while !line.is_empty() || codec.read(&mut reader, &mut line) {
+            if let Ok(header) = serde_json::from_slice::<Header>(&line) {
+                let mut connections = self.lock();
+                if !connections.contains_key(header.src.as_ref()) {
+                    if let Ok(stream) = stream.try_clone() {
+                        connections.insert(header.src.to_string(), Connection { stream, codec });
+                        senders.push(header.src.to_string());
+                    }
+                }
+            }
+            if self.lines.send(std::mem::take(&mut line)).is_err() {
+                break;
+            }
+        }
+        let mut connections = self.lock();
+        for sender in senders {
+            connections.remove(&sender);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Connection>> {
+        self.connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Writes `line` to its `dest`, connecting to it if it's a peer without a connection.
+    fn send(self: &Arc<Self>, line: &[u8]) {
+        let Ok(header) = serde_json::from_slice::<Header>(line) else {
+            log::warn!("Can't route a line that isn't a message");
+            return;
+        };
+        let dest = header.dest.as_ref();
+        let mut connections = self.lock();
+        if !connections.contains_key(dest) {
+            let Some(address) = self.peers.get(dest) else {
+                log::warn!("No connection or address for {dest}, dropping a message");
+                return;
+            };
+            match connect(address, self.codec) {
+                Ok((reader, stream, codec)) => {
+                    log::debug!("Connected to {dest} at {address}, using {}", codec.name());
+                    let writer = stream.try_clone();
+                    connections.insert(dest.to_string(), Connection { stream, codec });
+                    if let Ok(writer) = writer {
+                        let router = Arc::clone(self);
+                        std::thread::spawn(move || router.read(writer, reader, codec, Vec::new()));
+                    }
+                }
+                Err(error) => {
+                    log::debug!("Failed to connect to {dest} at {address}: {error}");
+                    return;
+                }
+            }
+        }
+        let connection = connections.get_mut(dest).expect("Connected above");
+        if let Err(error) = connection.codec.write(&mut connection.stream, line) {
+            log::debug!("Lost the connection to {dest}: {error}");
+            connections.remove(dest);
+        }
+    }
+}
+
+/// Newline-delimited JSON over a Unix domain socket that a harness listens on, one socket
+/// per node. The harness knows which node is at the other end of a connection by the socket
+/// it came in on, so it doesn't have to trust the `src` of what the node sends, and it routes
+/// messages between nodes itself. The input ends when the harness closes the connection.
+pub struct Unix {
+    stream: Arc<UnixStream>,
+    reader: Option<UnixStream>,
+}
+
+impl Unix {
+    /// Connects to the socket at `path`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+        let reader = stream.try_clone()?;
+        Ok(Self {
+            stream: Arc::new(stream),
+            reader: Some(reader),
+        })
+    }
+}
+
+impl Transport for Unix {
+    /// Only the first call gets the lines. Later ones get an input that's at its end.
+    fn input(&mut self) -> Box<dyn BufRead> {
+        match self.reader.take() {
+            Some(reader) => Box::new(BufReader::new(reader)),
+            None => Box::new(std::io::empty()),
+        }
+    }
+
+    fn output(&mut self) -> Box<dyn Write + Send> {
+        Box::new(SharedStream(Arc::clone(&self.stream)))
+    }
+}
+
+/// Writes to a stream that's shared, as a `&UnixStream` can be written to.
+struct SharedStream(Arc<UnixStream>);
+
+impl Write for SharedStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+/// Connects to `address`, giving up after `CONNECT_TIMEOUT`, and asks for `codec` unless
+/// it's JSON. Returns the stream to read from, the stream to write to, and the codec agreed.
+fn connect(
+    address: &str,
+    codec: Codec,
+) -> std::io::Result<(BufReader<TcpStream>, TcpStream, Codec)> {
+    let mut error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let codec = negotiate(&stream, &mut reader, codec)?;
+                return Ok((reader, stream, codec));
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| std::io::Error::other("The address resolves to nothing")))
+}
+
+/// Sends a `Hello` asking for `codec`, and returns the codec the other node answers with.
+fn negotiate(
+    stream: &TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    codec: Codec,
+) -> std::io::Result<Codec> {
+    if codec == Codec::Json {
+        return Ok(codec);
+    }
+    let hello = Hello {
+        codec: Cow::Borrowed(codec.name()),
+    };
+    writeln!(&*stream, "{}", serde_json::to_string(&hello)?)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    stream.set_read_timeout(None)?;
+    let answer: Hello = serde_json::from_slice(&line)?;
+    Ok(Codec::from_name(&answer.codec).unwrap_or_default())
+}
+
+/// The lines of every connection, in the order they arrived.
+struct Lines {
+    receiver: Option<Receiver<Vec<u8>>>,
+    line: Vec<u8>,
+    read: usize,
+}
+
+impl Read for Lines {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for Lines {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.read == self.line.len() {
+            match self.receiver.as_ref().map(Receiver::recv) {
+                Some(Ok(line)) => {
+                    self.line = line;
+                    self.read = 0;
+                }
+                Some(Err(_)) | None => return Ok(&[]),
+            }
+        }
+        Ok(&self.line[self.read..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.read = (self.read + amount).min(self.line.len());
+    }
+}
+
+/// Routes each complete line written to the connection of its `dest`.
+struct RoutedWriter {
+    router: Arc<Router>,
+    partial: Vec<u8>,
+}
+
+impl Write for RoutedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        if let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') {
+            let complete: Vec<u8> = self.partial.drain(..=end).collect();
+            for line in complete.split_inclusive(|&b| b == b'\n') {
+                self.router.send(line);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SystemClock};
+use crate::hlc::{Hlc, HlcTimestamp};
+
+/// A single read or write of the txn-rw-register workload, encoded as `["r", key, value]`
+/// or `["w", key, value]`. Reads carry `null` until they are executed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    try_from = "(String, u64, Option<u64>)",
+    into = "(String, u64, Option<u64>)"
+)]
+pub enum MicroOp {
+    Read { key: u64, value: Option<u64> },
+    Write { key: u64, value: u64 },
+}
+
+impl TryFrom<(String, u64, Option<u64>)> for MicroOp {
+    type Error = String;
+
+    fn try_from((kind, key, value): (String, u64, Option<u64>)) -> Result<Self, Self::Error> {
+        match (kind.as_str(), value) {
+            ("r", value) => Ok(MicroOp::Read { key, value }),
+            ("w", Some(value)) => Ok(MicroOp::Write { key, value }),
+            ("w", None) => Err(format!("write to key {key} is missing a value")),
+            (other, _) => Err(format!("unknown micro-op type: {other}")),
+        }
+    }
+}
+
+impl From<MicroOp> for (String, u64, Option<u64>) {
+    fn from(op: MicroOp) -> Self {
+        match op {
+            MicroOp::Read { key, value } => ("r".to_string(), key, value),
+            MicroOp::Write { key, value } => ("w".to_string(), key, Some(value)),
+        }
+    }
+}
+
+/// Hybrid logical clock timestamp used to resolve concurrent writes with
+/// last-writer-wins, so that the latest write in real time wins unless clocks are skewed.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub time: HlcTimestamp,
+    pub node_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Write {
+    pub key: u64,
+    pub value: u64,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug)]
+struct Versioned {
+    value: u64,
+    timestamp: Timestamp,
+}
+
+/// How much of a transaction other transactions may observe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isolation {
+    /// Writes are installed as they are executed and replicated one by one.
+    #[default]
+    ReadUncommitted,
+    /// Writes are buffered and only the final write-set is installed and replicated on commit.
+    ReadCommitted,
+}
+
+impl FromStr for Isolation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-uncommitted" => Ok(Isolation::ReadUncommitted),
+            "read-committed" => Ok(Isolation::ReadCommitted),
+            other => anyhow::bail!("Unknown isolation level: {other}"),
+        }
+    }
+}
+
+/// Where each key is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Partitioning {
+    /// Every node stores every key and replicates its writes to the others.
+    #[default]
+    Replicated,
+    /// Each key is stored by a single owner, and transactions spanning several owners are
+    /// committed with two-phase commit.
+    Partitioned,
+}
+
+impl FromStr for Partitioning {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replicated" => Ok(Partitioning::Replicated),
+            "partitioned" => Ok(Partitioning::Partitioned),
+            other => anyhow::bail!("Unknown partitioning: {other}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Store {
+    node_id: String,
+    isolation: Isolation,
+    clock: Hlc,
+    values: HashMap<u64, Versioned>,
+}
+
+impl Store {
+    pub fn new(node_id: impl Into<String>) -> Self {
+        Self {
+            node_id: node_id.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_isolation(node_id: impl Into<String>, isolation: Isolation) -> Self {
+        Self {
+            node_id: node_id.into(),
+            isolation,
+            ..Self::default()
+        }
+    }
+
+    pub fn get(&self, key: u64) -> Option<u64> {
+        self.values.get(&key).map(|versioned| versioned.value)
+    }
+
+    /// Number of keys with a value.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Applies the micro-ops in order and returns them with read values filled in, together
+    /// with the timestamped writes that should be replicated to other nodes.
+    pub fn apply(&mut self, txn: &[MicroOp]) -> (Vec<MicroOp>, Vec<Write>) {
+        let timestamp = Timestamp {
+            time: self.clock.now(),
+            node_id: self.node_id.clone(),
+        };
+
+        match self.isolation {
+            Isolation::ReadUncommitted => self.apply_uncommitted(txn, timestamp),
+            Isolation::ReadCommitted => self.apply_committed(txn, timestamp),
+        }
+    }
+
+    fn apply_uncommitted(
+        &mut self,
+        txn: &[MicroOp],
+        timestamp: Timestamp,
+    ) -> (Vec<MicroOp>, Vec<Write>) {
+        let mut writes = Vec::new();
+
+        let txn = txn
+            .iter()
+            .map(|op| match *op {
+                MicroOp::Read { key, .. } => MicroOp::Read {
+                    key,
+                    value: self.get(key),
+                },
+                MicroOp::Write { key, value } => {
+                    let write = Write {
+                        key,
+                        value,
+                        timestamp: timestamp.clone(),
+                    };
+                    self.install(&write);
+                    writes.push(write);
+                    MicroOp::Write { key, value }
+                }
+            })
+            .collect();
+
+        (txn, writes)
+    }
+
+    fn apply_committed(
+        &mut self,
+        txn: &[MicroOp],
+        timestamp: Timestamp,
+    ) -> (Vec<MicroOp>, Vec<Write>) {
+        let mut write_set: BTreeMap<u64, u64> = BTreeMap::new();
+
+        let txn = txn
+            .iter()
+            .map(|op| match *op {
+                MicroOp::Read { key, .. } => MicroOp::Read {
+                    key,
+                    value: write_set.get(&key).copied().or_else(|| self.get(key)),
+                },
+                MicroOp::Write { key, value } => {
+                    write_set.insert(key, value);
+                    MicroOp::Write { key, value }
+                }
+            })
+            .collect();
+
+        let writes = self.install_write_set(write_set, timestamp);
+
+        (txn, writes)
+    }
+
+    /// Installs the final value of every key written by a transaction, all with the next
+    /// timestamp, and returns the writes for replication.
+    pub fn commit(&mut self, write_set: BTreeMap<u64, u64>) -> Vec<Write> {
+        let timestamp = Timestamp {
+            time: self.clock.now(),
+            node_id: self.node_id.clone(),
+        };
+
+        self.install_write_set(write_set, timestamp)
+    }
+
+    fn install_write_set(
+        &mut self,
+        write_set: BTreeMap<u64, u64>,
+        timestamp: Timestamp,
+    ) -> Vec<Write> {
+        let writes: Vec<Write> = write_set
+            .into_iter()
+            .map(|(key, value)| Write {
+                key,
+                value,
+                timestamp: timestamp.clone(),
+            })
+            .collect();
+
+        self.merge(&writes);
+
+        writes
+    }
+
+    /// Installs replicated writes, keeping the one with the highest timestamp for each key.
+    pub fn merge(&mut self, writes: &[Write]) {
+        for write in writes {
+            self.clock.observe(write.timestamp.time);
+            self.install(write);
+        }
+    }
+
+    fn install(&mut self, write: &Write) {
+        match self.values.get_mut(&write.key) {
+            Some(versioned) if versioned.timestamp > write.timestamp => {}
+            Some(versioned) => {
+                versioned.value = write.value;
+                versioned.timestamp = write.timestamp.clone();
+            }
+            None => {
+                self.values.insert(
+                    write.key,
+                    Versioned {
+                        value: write.value,
+                        timestamp: write.timestamp.clone(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// A transaction that voted to commit: its keys stay locked until the coordinator decides.
+#[derive(Debug)]
+struct Prepared {
+    txn: Vec<MicroOp>,
+    write_set: BTreeMap<u64, u64>,
+}
+
+/// The participant side of two-phase commit. Preparing a transaction locks every key it
+/// touches, executes its reads, and buffers its writes; the writes are installed on commit
+/// and dropped on abort. Locks are never waited for: a transaction touching a key locked by
+/// another one votes to abort instead, so transactions can't deadlock.
+#[derive(Debug, Default)]
+pub struct Participant {
+    store: Store,
+    locks: HashMap<u64, String>,
+    prepared: HashMap<String, Prepared>,
+}
+
+impl Participant {
+    pub fn new(node_id: impl Into<String>) -> Self {
+        Self {
+            store: Store::new(node_id),
+            ..Self::default()
+        }
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn is_prepared(&self, txn_id: &str) -> bool {
+        self.prepared.contains_key(txn_id)
+    }
+
+    /// Number of transactions prepared and not decided yet, whose keys are locked.
+    pub fn prepared_count(&self) -> usize {
+        self.prepared.len()
+    }
+
+    /// Returns the micro-ops with read values filled in if the transaction can commit, and
+    /// None if one of its keys is locked by another transaction. Preparing the same
+    /// transaction again returns the same result.
+    pub fn prepare(&mut self, txn_id: &str, txn: &[MicroOp]) -> Option<Vec<MicroOp>> {
+        if let Some(prepared) = self.prepared.get(txn_id) {
+            return Some(prepared.txn.clone());
+        }
+
+        let keys: HashSet<u64> = txn
+            .iter()
+            .map(|op| match *op {
+                MicroOp::Read { key, .. } | MicroOp::Write { key, .. } => key,
+            })
+            .collect();
+        if keys.iter().any(|key| self.locks.contains_key(key)) {
+            return None;
+        }
+
+        let mut write_set: BTreeMap<u64, u64> = BTreeMap::new();
+        let txn: Vec<MicroOp> = txn
+            .iter()
+            .map(|op| match *op {
+                MicroOp::Read { key, .. } => MicroOp::Read {
+                    key,
+                    value: write_set.get(&key).copied().or_else(|| self.store.get(key)),
+                },
+                MicroOp::Write { key, value } => {
+                    write_set.insert(key, value);
+                    MicroOp::Write { key, value }
+                }
+            })
+            .collect();
+
+        for key in keys {
+            self.locks.insert(key, txn_id.to_string());
+        }
+        self.prepared.insert(
+            txn_id.to_string(),
+            Prepared {
+                txn: txn.clone(),
+                write_set,
+            },
+        );
+
+        Some(txn)
+    }
+
+    /// Installs the buffered writes and releases the locks. Committing a transaction that
+    /// isn't prepared, e.g. a repeated decision, does nothing.
+    pub fn commit(&mut self, txn_id: &str) {
+        if let Some(prepared) = self.prepared.remove(txn_id) {
+            self.release(txn_id);
+            self.store.commit(prepared.write_set);
+        }
+    }
+
+    pub fn abort(&mut self, txn_id: &str) {
+        if self.prepared.remove(txn_id).is_some() {
+            self.release(txn_id);
+        }
+    }
+
+    fn release(&mut self, txn_id: &str) {
+        self.locks.retain(|_, holder| holder != txn_id);
+    }
+}
+
+/// A transaction a `Coordinator` collects the votes on.
+struct Voting<T> {
+    state: T,
+    participants: Vec<String>,
+    /// Participants that haven't voted yet.
+    waiting: HashSet<String>,
+    started_at: Instant,
+}
+
+/// The outcome of a transaction, waiting for the participants told to acknowledge it, with
+/// when it was last sent to each.
+struct Decision {
+    commit: bool,
+    unacknowledged: HashMap<String, Instant>,
+}
+
+/// How voting on a transaction ended, with what the node kept about it.
+#[derive(Debug)]
+pub struct Outcome<T> {
+    pub txn_id: String,
+    pub commit: bool,
+    pub state: T,
+    pub participants: Vec<String>,
+}
+
+/// The coordinator side of two-phase commit. It collects the votes of the participants of
+/// every transaction, along with whatever `T` the node keeps about it, and once voting
+/// ended, keeps the decision until the participants told acknowledge it. Sending prepares
+/// and decisions is left to the node, so that workloads carry them in messages of their own.
+pub struct Coordinator<T> {
+    voting: HashMap<String, Voting<T>>,
+    decisions: HashMap<String, Decision>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T> Default for Coordinator<T> {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl<T> Coordinator<T> {
+    /// Times votes and decisions with `clock` instead of the system clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            voting: HashMap::new(),
+            decisions: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// Starts collecting the votes of `participants` on `txn_id`.
+    pub fn start(
+        &mut self,
+        txn_id: &str,
+        participants: impl IntoIterator<Item = String>,
+        state: T,
+    ) {
+        let participants: Vec<String> = participants.into_iter().collect();
+        let voting = Voting {
+            state,
+            waiting: participants.iter().cloned().collect(),
+            participants,
+            started_at: self.clock.now(),
+        };
+        self.voting.insert(txn_id.to_string(), voting);
+    }
+
+    pub fn is_voting(&self, txn_id: &str) -> bool {
+        self.voting.contains_key(txn_id)
+    }
+
+    /// Number of transactions still collecting votes.
+    pub fn voting_count(&self) -> usize {
+        self.voting.len()
+    }
+
+    /// Number of decisions some participant hasn't acknowledged yet.
+    pub fn decision_count(&self) -> usize {
+        self.decisions.len()
+    }
+
+    /// What the node keeps about a transaction still collecting votes.
+    pub fn state_mut(&mut self, txn_id: &str) -> Option<&mut T> {
+        self.voting.get_mut(txn_id).map(|voting| &mut voting.state)
+    }
+
+    /// Counts the vote of `voter`, and returns the outcome once every participant voted to
+    /// commit, or one voted to abort. Votes on transactions decided already, or of nodes
+    /// that aren't participants, are ignored.
+    pub fn vote(&mut self, txn_id: &str, voter: &str, commit: bool) -> Option<Outcome<T>> {
+        let voting = self.voting.get_mut(txn_id)?;
+        if !voting
+            .participants
+            .iter()
+            .any(|participant| participant == voter)
+        {
+            return None;
+        }
+        voting.waiting.remove(voter);
+        if commit && !voting.waiting.is_empty() {
+            return None;
+        }
+        self.decide(txn_id, commit)
+    }
+
+    /// Ends voting on `txn_id` with `commit`, e.g. to abort it before every vote arrived.
+    pub fn decide(&mut self, txn_id: &str, commit: bool) -> Option<Outcome<T>> {
+        let voting = self.voting.remove(txn_id)?;
+        Some(Outcome {
+            txn_id: txn_id.to_string(),
+            commit,
+            state: voting.state,
+            participants: voting.participants,
+        })
+    }
+
+    /// Aborts the transactions whose votes didn't all arrive within `timeout`.
+    pub fn expire(&mut self, timeout: Duration) -> Vec<Outcome<T>> {
+        let now = self.clock.now();
+        let expired: Vec<String> = self
+            .voting
+            .iter()
+            .filter(|(_, voting)| now >= voting.started_at + timeout)
+            .map(|(txn_id, _)| txn_id.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|txn_id| self.decide(txn_id, false))
+            .collect()
+    }
+
+    /// Waits for `participants`, which were just sent the decision on `txn_id`, to
+    /// acknowledge it, besides those it waits for already.
+    pub fn tell(
+        &mut self,
+        txn_id: &str,
+        commit: bool,
+        participants: impl IntoIterator<Item = String>,
+    ) {
+        let now = self.clock.now();
+        let mut participants = participants.into_iter().peekable();
+        if participants.peek().is_none() {
+            return;
+        }
+        let decision = self
+            .decisions
+            .entry(txn_id.to_string())
+            .or_insert_with(|| Decision {
+                commit,
+                unacknowledged: HashMap::new(),
+            });
+        decision
+            .unacknowledged
+            .extend(participants.map(|participant| (participant, now)));
+    }
+
+    /// Takes the acknowledgement of the decision on `txn_id` by `participant`, and returns
+    /// whether the decision waited for it. A decision is forgotten once every participant
+    /// told acknowledged it.
+    pub fn acknowledged(&mut self, txn_id: &str, participant: &str) -> bool {
+        let Some(decision) = self.decisions.get_mut(txn_id) else {
+            return false;
+        };
+        let waited = decision.unacknowledged.remove(participant).is_some();
+        if decision.unacknowledged.is_empty() {
+            self.decisions.remove(txn_id);
+        }
+        waited
+    }
+
+    /// The decision on `txn_id`, while a participant hasn't acknowledged it.
+    pub fn decision(&self, txn_id: &str) -> Option<bool> {
+        self.decisions.get(txn_id).map(|decision| decision.commit)
+    }
+
+    /// The decisions a participant hasn't acknowledged within `timeout`, as the participant,
+    /// the transaction, and whether it committed, to be sent again. They're timed anew.
+    pub fn unacknowledged(&mut self, timeout: Duration) -> Vec<(String, String, bool)> {
+        let now = self.clock.now();
+        let mut resends = Vec::new();
+        for (txn_id, decision) in &mut self.decisions {
+            for (participant, sent_at) in &mut decision.unacknowledged {
+                if now >= *sent_at + timeout {
+                    *sent_at = now;
+                    resends.push((participant.clone(), txn_id.clone(), decision.commit));
+                }
+            }
+        }
+        resends
+    }
+}
+
+/// Transactions a participant prepared and hasn't heard the decision on, with whatever `T`
+/// the node keeps about each, such as who coordinates it. Those that wait too long are due
+/// for the node to find out the decision by itself, as the coordinator may have crashed.
+pub struct AwaitingDecisions<T> {
+    waiting: HashMap<String, (T, Instant)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T> Default for AwaitingDecisions<T> {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl<T> AwaitingDecisions<T> {
+    /// Times transactions with `clock` instead of the system clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            waiting: HashMap::new(),
+            clock,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    /// Waits for the decision on `txn_id`, timed from now.
+    pub fn insert(&mut self, txn_id: &str, state: T) {
+        self.waiting
+            .insert(txn_id.to_string(), (state, self.clock.now()));
+    }
+
+    pub fn get_mut(&mut self, txn_id: &str) -> Option<&mut T> {
+        self.waiting.get_mut(txn_id).map(|(state, _)| state)
+    }
+
+    /// Stops waiting for the decision on `txn_id`, as it arrived.
+    pub fn remove(&mut self, txn_id: &str) -> Option<T> {
+        self.waiting.remove(txn_id).map(|(state, _)| state)
+    }
+
+    /// The transactions that waited for longer than `timeout`, which are timed anew.
+    pub fn overdue(&mut self, timeout: Duration) -> Vec<String> {
+        let now = self.clock.now();
+        let mut overdue = Vec::new();
+        for (txn_id, (_, since)) in &mut self.waiting {
+            if now >= *since + timeout {
+                *since = now;
+                overdue.push(txn_id.clone());
+            }
+        }
+        overdue
+    }
+}
@@ -0,0 +1,72 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Counts events per node. Nodes missing from the clock count as zero, so clocks only
+/// hold the nodes they have seen events from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock(BTreeMap<String, u64>);
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    /// Counts another event of `node` and returns its new count.
+    pub fn increment(&mut self, node: &str) -> u64 {
+        let count = self.0.entry(node.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Takes the highest count of every node, so the result has seen everything either
+    /// clock has seen.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node, &count) in &other.0 {
+            let entry = self.0.entry(node.clone()).or_insert(0);
+            *entry = (*entry).max(count);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0.iter().map(|(node, &count)| (node.as_str(), count))
+    }
+
+    /// Neither clock has seen everything the other has.
+    pub fn is_concurrent(&self, other: &VectorClock) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl PartialEq for VectorClock {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl Eq for VectorClock {}
+
+impl PartialOrd for VectorClock {
+    /// A clock is below another if no node counts more events in it, and at least one
+    /// counts fewer. Clocks with events unknown to each other can't be compared.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+
+        for node in self.0.keys().chain(other.0.keys()) {
+            match (ordering, self.get(node).cmp(&other.get(node))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, order) => ordering = order,
+                (current, order) if current != order => return None,
+                _ => {}
+            }
+        }
+
+        Some(ordering)
+    }
+}
@@ -0,0 +1,80 @@
+use distributed_system::admin::AdminReply;
+use distributed_system::message::Message;
+use distributed_system::metrics;
+use distributed_system::runtime::{self, Lifecycle};
+use serde_json::{json, Value};
+
+struct Node {
+    handled: u64,
+}
+
+impl Lifecycle for Node {
+    fn debug_state(&self) -> Value {
+        json!({ "handled": self.handled })
+    }
+}
+
+fn run(input: &str) -> (Node, Vec<Message<AdminReply>>) {
+    let mut node = Node { handled: 0 };
+    let mut output = Vec::new();
+    runtime::run_lines(&mut node, input.as_bytes(), &mut output, |node, _, _| {
+        node.handled += 1;
+        Ok(())
+    })
+    .unwrap();
+    let replies = serde_json::Deserializer::from_slice(&output)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    (node, replies)
+}
+
+#[test]
+fn stats_requests_are_answered_by_the_runtime() {
+    let (node, replies) = run(concat!(
+        r#"{"src":"c1","dest":"n1","body":{"type":"admin_request","msg_id":1}}"#,
+        "\n",
+        r#"{"src":"c1","dest":"n1","body":{"type":"stats","msg_id":2}}"#,
+        "\n",
+    ));
+    metrics::increment("admin_test_counter");
+
+    assert_eq!(node.handled, 1);
+    let [reply] = replies.as_slice() else {
+        panic!("Expected a single reply, got {replies:?}");
+    };
+    assert_eq!((reply.src.as_str(), reply.dest.as_str()), ("n1", "c1"));
+    let AdminReply::StatsOk { in_reply_to, stats } = &reply.body else {
+        panic!("Expected stats_ok, got {:?}", reply.body);
+    };
+    assert_eq!(*in_reply_to, 2);
+    assert_eq!(stats.received["admin_request"], 1);
+    assert_eq!(stats.latency["admin_request"].count, 1);
+    assert_eq!(metrics::snapshot().counters["admin_test_counter"], 1);
+}
+
+#[test]
+fn debug_state_requests_get_the_state_of_the_node() {
+    let (node, replies) = run(concat!(
+        r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1}}"#,
+        "\n",
+        r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2}}"#,
+        "\n",
+        r#"{"src":"c2","dest":"n1","body":{"type":"debug_state","msg_id":3}}"#,
+        "\n",
+        r#"{"src":"c2","dest":"n1","body":{"type":"debug_state"}}"#,
+        "\n",
+    ));
+
+    // Without a `msg_id`, it can't be answered, so it's left to the node.
+    assert_eq!(node.handled, 3);
+    let [reply] = replies.as_slice() else {
+        panic!("Expected a single reply, got {replies:?}");
+    };
+    assert_eq!(reply.dest, "c2");
+    let AdminReply::DebugStateOk { in_reply_to, state } = &reply.body else {
+        panic!("Expected debug_state_ok, got {:?}", reply.body);
+    };
+    assert_eq!(*in_reply_to, 3);
+    assert_eq!(*state, json!({ "handled": 2 }));
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "tokio")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use distributed_system::async_runtime::{self, Handler, Node};
+use distributed_system::{ErrorBody, Message};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Echo {
+        msg_id: u64,
+        echo: Value,
+    },
+    EchoOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        echo: Value,
+    },
+    /// Makes the node call a peer that never responds.
+    Stall {
+        msg_id: u64,
+    },
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+struct Echo;
+
+impl Handler for Echo {
+    type Body = Body;
+
+    async fn handle(
+        self: Arc<Self>,
+        node: Node<Body>,
+        message: Message<Body>,
+    ) -> Result<(), anyhow::Error> {
+        match message.body {
+            Body::Echo { msg_id, ref echo } => node.reply(
+                &message,
+                Body::EchoOk {
+                    msg_id: node.next_msg_id(),
+                    in_reply_to: msg_id,
+                    echo: echo.clone(),
+                },
+            ),
+            Body::Stall { .. } => {
+                let stall = |msg_id| Body::Stall { msg_id };
+                node.call("n1", stall, Duration::from_secs(3600)).await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+const INIT: &str = r#"{"src":"c0","dest":"n0","body":{"type":"init","msg_id":1,"node_id":"n0","node_ids":["n0","n1"]}}"#;
+
+fn run(lines: &[&str]) -> Vec<Value> {
+    let input = lines.join("\n");
+    let output = async_runtime::run_with(|_| Ok(Echo), input.as_bytes(), Vec::new()).unwrap();
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn init_and_requests_are_answered() {
+    let output = run(&[
+        INIT,
+        r#"{"src":"c1","dest":"n0","body":{"type":"echo","msg_id":7,"echo":"hi"}}"#,
+    ]);
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0]["body"]["type"], "init_ok");
+    assert_eq!(output[0]["body"]["in_reply_to"], 1);
+    assert_eq!(output[1]["dest"], "c1");
+    assert_eq!(output[1]["body"]["type"], "echo_ok");
+    assert_eq!(output[1]["body"]["in_reply_to"], 7);
+    assert_eq!(output[1]["body"]["echo"], "hi");
+}
+
+#[test]
+fn unsupported_requests_get_an_error() {
+    let output = run(&[
+        INIT,
+        r#"{"src":"c1","dest":"n0","body":{"type":"bogus","msg_id":3}}"#,
+    ]);
+
+    assert_eq!(output[1]["body"]["type"], "error");
+    assert_eq!(output[1]["body"]["code"], 10);
+    assert_eq!(output[1]["body"]["in_reply_to"], 3);
+}
+
+#[test]
+fn pending_calls_are_stopped_at_shutdown() {
+    let output = run(&[
+        INIT,
+        r#"{"src":"c1","dest":"n0","body":{"type":"stall","msg_id":2}}"#,
+    ]);
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[1]["dest"], "n1");
+    assert_eq!(output[1]["body"]["type"], "stall");
+}
+
+#[test]
+fn init_must_come_first() {
+    let input = r#"{"src":"c1","dest":"n0","body":{"type":"echo","msg_id":7,"echo":"hi"}}"#;
+    assert!(async_runtime::run_with(|_| Ok(Echo), input.as_bytes(), Vec::new()).is_err());
+}
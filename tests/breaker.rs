@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+use distributed_system::breaker::CircuitBreaker;
+
+#[test]
+fn opens_after_timeouts_in_a_row_and_closes_on_a_reply() {
+    let start = Instant::now();
+    let mut breaker = CircuitBreaker::new(3, Duration::from_secs(1));
+    assert!(!breaker.failure(start));
+    assert!(!breaker.failure(start));
+    assert!(!breaker.success());
+    assert!(!breaker.failure(start));
+    assert!(!breaker.failure(start));
+    assert!(breaker.allows(start));
+
+    assert!(breaker.failure(start));
+    assert!(breaker.is_open());
+    assert!(!breaker.failure(start));
+    assert!(breaker.success());
+    assert!(!breaker.is_open());
+    assert!(breaker.allows(start));
+}
+
+#[test]
+fn open_circuits_allow_a_probe_every_interval() {
+    let start = Instant::now();
+    let mut breaker = CircuitBreaker::new(1, Duration::from_secs(1));
+    // Requests sent while closed aren't probes.
+    breaker.probed(start);
+    assert!(breaker.failure(start));
+    assert!(!breaker.allows(start + Duration::from_millis(999)));
+
+    let probe = start + Duration::from_secs(1);
+    assert!(breaker.allows(probe));
+    breaker.probed(probe);
+    assert!(!breaker.allows(probe + Duration::from_millis(500)));
+    assert!(breaker.allows(probe + Duration::from_secs(1)));
+}
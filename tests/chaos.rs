@@ -0,0 +1,109 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use distributed_system::chaos::{Chaos, ChaosWriter};
+
+/// A writer whose output can be read while it's owned by a `ChaosWriter`.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Shared {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn line(dest: &str, msg_id: u64) -> String {
+    format!(r#"{{"src":"n0","dest":"{dest}","body":{{"type":"gossip","msg_id":{msg_id}}}}}"#)
+}
+
+/// Writes ten messages to `n1` and one to `c1` through `chaos`, and returns what came out.
+fn run(chaos: &str) -> Vec<String> {
+    let shared = Shared::default();
+    let mut writer = ChaosWriter::new(shared.clone(), chaos.parse().unwrap());
+    for msg_id in 0..10 {
+        writeln!(writer, "{}", line("n1", msg_id)).unwrap();
+    }
+    writeln!(writer, "{}", line("c1", 10)).unwrap();
+    drop(writer);
+    shared.lines()
+}
+
+#[test]
+fn only_messages_to_nodes_are_dropped() {
+    assert_eq!(run("drop=1"), [line("c1", 10)]);
+    assert_eq!(run("").len(), 11);
+}
+
+#[test]
+fn duplicated_messages_are_sent_twice() {
+    let lines = run("duplicate=1");
+    assert_eq!(lines.len(), 21);
+    assert_eq!(lines[0], lines[1]);
+    assert_eq!(lines[20], line("c1", 10));
+}
+
+#[test]
+fn delayed_messages_are_sent_once_due() {
+    let shared = Shared::default();
+    let chaos = "delay=1,max-delay-ms=50".parse().unwrap();
+    let mut writer = ChaosWriter::new(shared.clone(), chaos);
+    writeln!(writer, "{}", line("n1", 1)).unwrap();
+    writeln!(writer, "{}", line("c1", 2)).unwrap();
+    assert_eq!(shared.lines(), [line("c1", 2)]);
+
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(shared.lines(), [line("c1", 2), line("n1", 1)]);
+
+    writeln!(writer, "{}", line("n1", 3)).unwrap();
+    drop(writer);
+    assert_eq!(
+        shared.lines().len(),
+        3,
+        "Dropping the writer sends what's held back"
+    );
+}
+
+#[test]
+fn faults_repeat_with_a_seed() {
+    let chaos = "drop=0.5,duplicate=0.3,seed=7";
+    let lines = run(chaos);
+    assert_eq!(lines, run(chaos));
+    assert_ne!(lines.len(), 11);
+}
+
+#[test]
+fn chaos_is_parsed_from_settings() {
+    let chaos: Chaos = "drop=0.1,duplicate=0.2,delay=0.3,max-delay-ms=20,seed=5"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        chaos,
+        Chaos {
+            drop: 0.1,
+            duplicate: 0.2,
+            delay: 0.3,
+            max_delay: Duration::from_millis(20),
+            seed: Some(5),
+        }
+    );
+    assert!("drop=1.5".parse::<Chaos>().is_err());
+    assert!("reorder=0.1".parse::<Chaos>().is_err());
+    assert!("drop".parse::<Chaos>().is_err());
+}
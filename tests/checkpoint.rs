@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use distributed_system::checkpoint::{Checkpoint, MSG_ID_LEASE};
+
+/// A fresh directory under the system's temporary directory.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("checkpoint-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn restarted_node_resumes_past_every_used_msg_id() {
+    let dir = temp_dir("resume");
+    let mut checkpoint = Checkpoint::new(&dir, "n1").unwrap();
+    assert!(checkpoint.restore::<()>().unwrap().is_none());
+    assert!(checkpoint.needs_save(0));
+
+    checkpoint.save(7, &()).unwrap();
+    assert!(!checkpoint.needs_save(7 + MSG_ID_LEASE / 4));
+    assert!(checkpoint.needs_save(7 + MSG_ID_LEASE / 2));
+
+    let mut restarted = Checkpoint::new(&dir, "n1").unwrap();
+    let (msg_id, ()) = restarted.restore().unwrap().unwrap();
+    assert_eq!(msg_id, 7 + MSG_ID_LEASE);
+    assert!(restarted.needs_save(msg_id));
+}
+
+#[test]
+fn state_survives_a_restart() {
+    let dir = temp_dir("state");
+    let mut checkpoint = Checkpoint::new(&dir, "n1").unwrap();
+    let counters = HashMap::from([("n1".to_string(), 5), ("n2".to_string(), 3)]);
+    checkpoint.save(1, &counters).unwrap();
+
+    let mut restarted = Checkpoint::new(&dir, "n1").unwrap();
+    let (_, restored): (u64, HashMap<String, u64>) = restarted.restore().unwrap().unwrap();
+    assert_eq!(restored, counters);
+}
+
+#[test]
+fn checkpoint_of_another_node_is_rejected() {
+    let dir = temp_dir("identity");
+    Checkpoint::new(&dir, "n1").unwrap().save(1, &()).unwrap();
+    std::fs::rename(dir.join("n1.json"), dir.join("n2.json")).unwrap();
+
+    let mut other = Checkpoint::new(&dir, "n2").unwrap();
+    assert!(other.restore::<()>().is_err());
+}
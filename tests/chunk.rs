@@ -0,0 +1,93 @@
+use std::io::{BufRead, Write};
+
+use distributed_system::chunk::{self, ChunkingWriter, Reassembler, Reassembling, CAPABILITY};
+use distributed_system::protocol::{self, Handshake};
+use serde_json::{json, Value};
+
+fn line(dest: &str, payload: &str) -> String {
+    json!({"src": "n0", "dest": dest, "body": {"type": "gossip", "payload": payload}}).to_string()
+        + "\n"
+}
+
+/// Writes `lines` through a `ChunkingWriter` with chunks of 16 bytes, and returns what
+/// came out.
+fn chunked(lines: &[String]) -> Vec<String> {
+    let mut written = Vec::new();
+    let mut writer = ChunkingWriter::new(&mut written, 16);
+    for line in lines {
+        writer.write_all(line.as_bytes()).unwrap();
+    }
+    drop(writer);
+    String::from_utf8(written)
+        .unwrap()
+        .lines()
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+#[test]
+fn lines_are_split_between_characters() {
+    assert_eq!(chunk::split("abcdefg", 3), ["abc", "def", "g"]);
+    assert_eq!(chunk::split("aéb", 2), ["aé", "b"]);
+    assert!(chunk::split("", 3).is_empty());
+}
+
+#[test]
+fn long_lines_to_nodes_are_chunked_once_agreed_to_and_reassembled() {
+    let lines = [
+        line("n1", "a long payload for n1"),
+        line("c1", "a long payload for c1"),
+    ];
+    assert_eq!(chunked(&lines), lines);
+
+    protocol::support(CAPABILITY, 1);
+    protocol::negotiate(&Handshake {
+        protocol_version: Some(1),
+        capabilities: "chunking=1".parse().unwrap(),
+    });
+    let written = chunked(&lines);
+    assert_eq!(written.last(), Some(&lines[1]));
+    let chunks: Vec<Value> = written[..written.len() - 1]
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(chunks.len() > 1);
+    for (index, chunk) in chunks.iter().enumerate() {
+        assert_eq!(chunk["src"], "n0");
+        assert_eq!(chunk["dest"], "n1");
+        assert_eq!(chunk["body"]["type"], "chunk");
+        assert_eq!(chunk["body"]["index"], index);
+        assert_eq!(chunk["body"]["count"], chunks.len());
+    }
+
+    let written = written.concat();
+    let mut input = Reassembling::new(written.as_bytes());
+    let mut reassembled = Vec::new();
+    let mut read = String::new();
+    while input.read_line(&mut read).unwrap() > 0 {
+        reassembled.push(std::mem::take(&mut read));
+    }
+    assert_eq!(reassembled, lines);
+
+    // Chunks may arrive out of order, or more than once.
+    let original = line("n1", "reordered and duplicated on the way");
+    let mut chunks = chunked(std::slice::from_ref(&original));
+    chunks.reverse();
+    chunks.insert(1, chunks[0].clone());
+
+    let mut reassembler = Reassembler::default();
+    let (last, rest) = chunks.split_last().unwrap();
+    for chunk in rest {
+        assert_eq!(reassembler.receive(chunk.as_bytes()), None);
+    }
+    let message = reassembler.receive(last.as_bytes()).unwrap();
+    assert_eq!(message.as_ref(), original.as_bytes());
+
+    let other = line("n1", "not a chunk");
+    assert_eq!(
+        reassembler.receive(other.as_bytes()).unwrap().as_ref(),
+        other.as_bytes()
+    );
+    let bad = json!({"src": "n0", "dest": "n1", "body": {"type": "chunk", "id": 1, "index": 2, "count": 2, "data": "x"}});
+    assert_eq!(reassembler.receive(bad.to_string().as_bytes()), None);
+}
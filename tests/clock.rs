@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use distributed_system::clock::{Clock, VirtualClock};
+use distributed_system::event_queue::Priority;
+use distributed_system::runtime::{self, Input, Timer};
+
+#[test]
+fn schedules_tick_when_the_virtual_clock_passes_them() {
+    let clock = VirtualClock::new();
+    let start = clock.now();
+    let ticks = Arc::new(Mutex::new(Vec::new()));
+    let tick = |name: &'static str, limit: usize| {
+        let ticks = ticks.clone();
+        let mut count = 0;
+        Box::new(move || {
+            ticks.lock().unwrap().push(name);
+            count += 1;
+            count < limit
+        })
+    };
+    let _fast = clock.schedule(Duration::from_millis(10), tick("fast", usize::MAX));
+    let slow = clock.schedule(Duration::from_millis(20), tick("slow", usize::MAX));
+    let _once = clock.schedule(Duration::from_millis(15), tick("once", 1));
+
+    clock.advance(Duration::from_millis(5));
+    assert!(ticks.lock().unwrap().is_empty());
+    clock.advance(Duration::from_millis(35));
+    assert_eq!(
+        *ticks.lock().unwrap(),
+        ["fast", "once", "fast", "slow", "fast", "fast", "slow"]
+    );
+    assert_eq!(clock.now() - start, Duration::from_millis(40));
+
+    drop(slow);
+    ticks.lock().unwrap().clear();
+    clock.sleep(Duration::from_millis(20));
+    assert_eq!(*ticks.lock().unwrap(), ["fast", "fast"]);
+}
+
+#[test]
+fn timers_send_events_on_the_given_clock() {
+    let clock = VirtualClock::new();
+    let (sender, receiver) = runtime::event_channel::<&str>();
+    let timer = Timer::start_on(&clock, Duration::from_millis(10), sender.clone(), || {
+        Input::Event("tick")
+    });
+
+    clock.advance(Duration::from_millis(25));
+    assert!(sender.send(Input::Event("end"), Priority::Low).is_ok());
+    drop(timer);
+    clock.advance(Duration::from_millis(25));
+    drop(sender);
+
+    let mut events = Vec::new();
+    while let Some(input) = receiver.recv() {
+        if let Input::Event(event) = input {
+            events.push(event);
+        }
+    }
+    assert_eq!(events, ["tick", "tick", "end"]);
+}
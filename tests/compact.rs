@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+
+use distributed_system::compact::{decode_ranges, encode_ranges};
+use proptest::prelude::*;
+
+#[test]
+fn consecutive_numbers_encode_as_ranges() {
+    assert_eq!(encode_ranges(&[3, 1, 2, 7, 8, 10]), vec![1, 3, 3, 2, 1, 1]);
+    assert_eq!(
+        encode_ranges(&(0..10_000).collect::<Vec<u64>>()),
+        vec![0, 10_000]
+    );
+    assert!(encode_ranges(&[]).is_empty());
+}
+
+#[test]
+fn largest_number_round_trips() {
+    let numbers = vec![u64::MAX - 1, u64::MAX];
+    assert_eq!(encode_ranges(&numbers), vec![u64::MAX - 1, 2]);
+    assert_eq!(
+        decode_ranges::<Vec<u64>>(&[u64::MAX - 1, 2]).unwrap(),
+        numbers
+    );
+}
+
+#[test]
+fn malformed_ranges_are_rejected() {
+    assert!(decode_ranges::<Vec<u64>>(&[1, 2, 3]).is_err());
+    assert!(decode_ranges::<Vec<u64>>(&[u64::MAX, 1, 1, 1]).is_err());
+    assert!(decode_ranges::<Vec<u64>>(&[u64::MAX, 2]).is_err());
+    assert!(decode_ranges::<Vec<u64>>(&[1, 0]).is_err());
+}
+
+#[test]
+fn sets_serialize_as_ranges() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Gossip {
+        #[serde(with = "distributed_system::compact")]
+        messages: HashSet<u64>,
+    }
+
+    let gossip = Gossip {
+        messages: (0..100).chain(200..300).collect(),
+    };
+    let json = serde_json::to_string(&gossip).unwrap();
+    assert_eq!(json, r#"{"messages":[0,100,100,100]}"#);
+
+    let decoded: Gossip = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.messages, gossip.messages);
+}
+
+proptest! {
+    #[test]
+    fn ranges_round_trip(numbers in prop::collection::hash_set(any::<u64>(), 0..100)) {
+        let decoded: HashSet<u64> = decode_ranges(&encode_ranges(&numbers)).unwrap();
+        prop_assert_eq!(decoded, numbers);
+    }
+
+    #[test]
+    fn dense_sets_round_trip(numbers in prop::collection::hash_set(0..500u64, 0..400)) {
+        let decoded: HashSet<u64> = decode_ranges(&encode_ranges(&numbers)).unwrap();
+        prop_assert_eq!(decoded, numbers);
+    }
+}
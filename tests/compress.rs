@@ -0,0 +1,68 @@
+use std::collections::BTreeSet;
+
+use distributed_system::compress::{self, CAPABILITY};
+use distributed_system::protocol::{self, Capabilities, Handshake};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Gossip {
+    msg_id: u64,
+    #[serde(flatten, with = "distributed_system::compress")]
+    messages: BTreeSet<u64>,
+}
+
+/// Every other number, which the compact encoding can't shorten.
+fn scattered(count: u64) -> BTreeSet<u64> {
+    (0..count).map(|i| i * 2).collect()
+}
+
+#[test]
+fn compressed_bytes_come_back_as_they_were() {
+    let bytes = json!(scattered(1000).into_iter().collect::<Vec<_>>()).to_string();
+    let compressed = compress::compress(bytes.as_bytes());
+    assert!(compressed.len() < bytes.len());
+    assert_eq!(compress::decompress(&compressed).unwrap(), bytes.as_bytes());
+
+    assert!(compress::decompress("not base64!").is_err());
+    assert!(compress::decompress("aGVsbG8=").is_err());
+}
+
+#[test]
+fn large_sets_are_compressed_once_agreed_to() {
+    protocol::support(CAPABILITY, 1);
+    let gossip = |messages| Gossip {
+        msg_id: 1,
+        messages,
+    };
+
+    let large = serde_json::to_value(gossip(scattered(1000))).unwrap();
+    assert!(large.get("messages").is_some());
+    assert!(large.get("compressed_messages").is_none());
+
+    protocol::negotiate(&Handshake {
+        protocol_version: Some(1),
+        capabilities: "compressed_gossip=1".parse::<Capabilities>().unwrap(),
+    });
+    let large = serde_json::to_value(gossip(scattered(1000))).unwrap();
+    assert!(large.get("messages").is_none());
+    assert!(large["compressed_messages"].is_string());
+    assert_eq!(
+        serde_json::from_value::<Gossip>(large).unwrap(),
+        gossip(scattered(1000))
+    );
+
+    let small = serde_json::to_value(gossip(scattered(10))).unwrap();
+    assert_eq!(
+        small,
+        json!({"msg_id": 1, "messages": [0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]})
+    );
+
+    assert!(serde_json::from_value::<Gossip>(json!({"msg_id": 1})).is_err());
+    assert!(serde_json::from_value::<Gossip>(json!({
+        "msg_id": 1,
+        "messages": [],
+        "compressed_messages": compress::compress(b"[]"),
+    }))
+    .is_err());
+}
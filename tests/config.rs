@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Parser;
+use distributed_system::config::{self, LogLevel, NodeArgs, RetryArgs};
+
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(flatten)]
+    node: NodeArgs,
+    #[arg(long, value_parser = config::millis, default_value = "150")]
+    interval_ms: Duration,
+    #[arg(long, env = "CONFIG_TEST_FANOUT")]
+    fanout: Option<usize>,
+    #[arg(long)]
+    flood: bool,
+    #[command(flatten)]
+    retry: RetryArgs,
+}
+
+/// Writes `contents` to a config file of its own, named after the test.
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "distributed-system-config-{name}-{}.toml",
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn parse(path: &Path, args: &[&str]) -> Result<Cli, anyhow::Error> {
+    let mut all = vec!["node".to_string(), format!("--config={}", path.display())];
+    all.extend(args.iter().map(|arg| arg.to_string()));
+    config::parse_from(all, "broadcast")
+}
+
+#[test]
+fn options_come_from_the_file_unless_given_as_flags() {
+    let path = config_file(
+        "flags",
+        "log-level = \"debug\"\n\
+         [broadcast]\n\
+         interval-ms = 300\n\
+         flood = true\n\
+         retry-timeout-ms = 250\n",
+    );
+
+    let cli = parse(&path, &[]).unwrap();
+    assert_eq!(cli.node.log_level, LogLevel::Debug);
+    assert_eq!(cli.interval_ms, Duration::from_millis(300));
+    assert!(cli.flood);
+    assert_eq!(cli.retry.timeout, Duration::from_millis(250));
+    assert_eq!(cli.retry.max_timeout, Duration::from_millis(5000));
+
+    let cli = parse(&path, &["--interval-ms", "50", "--log-level=warn"]).unwrap();
+    assert_eq!(cli.node.log_level, LogLevel::Warn);
+    assert_eq!(cli.interval_ms, Duration::from_millis(50));
+    assert!(cli.flood);
+}
+
+#[test]
+fn environment_variables_take_precedence_over_the_file() {
+    let path = config_file("env", "fanout = 2\n");
+    assert_eq!(parse(&path, &[]).unwrap().fanout, Some(2));
+
+    std::env::set_var("CONFIG_TEST_FANOUT", "5");
+    let cli = parse(&path, &[]);
+    std::env::remove_var("CONFIG_TEST_FANOUT");
+    assert_eq!(cli.unwrap().fanout, Some(5));
+}
+
+#[test]
+fn the_workload_table_overrides_the_top_level_and_other_tables_are_ignored() {
+    let path = config_file(
+        "tables",
+        "interval-ms = 100\n\
+         fanout = 3\n\
+         [broadcast]\n\
+         interval-ms = 200\n\
+         [kafka]\n\
+         interval-ms = 400\n\
+         poll-limit = 10\n",
+    );
+
+    let cli = parse(&path, &[]).unwrap();
+    assert_eq!(cli.interval_ms, Duration::from_millis(200));
+    assert_eq!(cli.fanout, Some(3));
+}
+
+#[test]
+fn unknown_and_invalid_options_are_errors() {
+    let path = config_file("unknown", "[broadcast]\ngossip = 1\n");
+    let error = parse(&path, &[]).unwrap_err();
+    assert!(error.to_string().contains("Unknown option gossip"));
+
+    let path = config_file("invalid", "interval-ms = \"soon\"\n");
+    assert!(parse(&path, &[]).is_err());
+
+    let path = config_file("array", "fanout = [1, 2]\n");
+    assert!(parse(&path, &[]).is_err());
+}
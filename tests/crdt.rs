@@ -0,0 +1,338 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use clap::Parser;
+use distributed_system::crdt::{
+    Crdt, GCounter, GSet, LwwMap, LwwRegister, Merge, MvRegister, ORSet, PNCounter,
+};
+use distributed_system::hlc::HlcTimestamp;
+use distributed_system::sim::{Network, NetworkConfig, Schedule, Step};
+use proptest::prelude::*;
+use serde_json::json;
+
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+/// Counters over a few nodes, so that merged counters often share nodes.
+fn counter() -> impl Strategy<Value = GCounter> {
+    prop::collection::vec((0..NODES.len(), 0..100u64), 0..6).prop_map(|adds| {
+        let mut counter = GCounter::new();
+        for (node, delta) in adds {
+            counter.increment(NODES[node], delta);
+        }
+        counter
+    })
+}
+
+fn merged(a: &GCounter, b: &GCounter) -> GCounter {
+    let mut merged = a.clone();
+    merged.merge(b);
+    merged
+}
+
+proptest! {
+    #[test]
+    fn merge_is_commutative(a in counter(), b in counter()) {
+        prop_assert_eq!(merged(&a, &b), merged(&b, &a));
+    }
+
+    #[test]
+    fn merge_is_associative(a in counter(), b in counter(), c in counter()) {
+        prop_assert_eq!(merged(&merged(&a, &b), &c), merged(&a, &merged(&b, &c)));
+    }
+
+    #[test]
+    fn merge_is_idempotent(a in counter(), b in counter()) {
+        prop_assert_eq!(merged(&a, &a), a.clone());
+        let ab = merged(&a, &b);
+        let mut again = ab.clone();
+        prop_assert!(!again.merge(&b));
+        prop_assert_eq!(again, ab);
+    }
+
+    #[test]
+    fn deltas_merge_like_the_count_of_their_node(a in counter(), b in counter(), node in 0..NODES.len()) {
+        let node = NODES[node];
+        let merged_delta = merged(&b, &a.delta(node));
+        for other in NODES {
+            let expected = if other == node { merged(&b, &a).get(other) } else { b.get(other) };
+            prop_assert_eq!(merged_delta.get(other), expected);
+        }
+    }
+
+    #[test]
+    fn reads_never_go_down(
+        a in counter(),
+        steps in prop::collection::vec((any::<bool>(), 0..NODES.len(), 0..10u64, counter()), 0..10),
+    ) {
+        let mut counter = a;
+        for (add, node, delta, other) in steps {
+            let before = counter.value();
+            if add {
+                counter.increment(NODES[node], delta);
+            } else {
+                counter.merge(&other);
+                prop_assert!(counter.value() >= other.value());
+            }
+            prop_assert!(counter.value() >= before);
+        }
+    }
+}
+
+/// A replica of an OR-Set over a few elements, made of adds with tags of a few nodes and of
+/// removes. Tags are unique across replicas, as the tag's number is the index of the step.
+fn or_set() -> impl Strategy<Value = ORSet<u8>> {
+    prop::collection::vec((any::<bool>(), 0..4u8, 0..NODES.len(), any::<u32>()), 0..8).prop_map(
+        |steps| {
+            let mut set = ORSet::new();
+            for (add, element, node, seq) in steps {
+                if add {
+                    set.insert(element, (NODES[node].to_string(), u64::from(seq)));
+                } else {
+                    set.remove(&element);
+                }
+            }
+            set
+        },
+    )
+}
+
+fn merged_sets(a: &ORSet<u8>, b: &ORSet<u8>) -> ORSet<u8> {
+    let mut merged = a.clone();
+    merged.merge(b);
+    merged
+}
+
+proptest! {
+    #[test]
+    fn g_sets_merge_into_the_union(a in prop::collection::btree_set(0..20u8, 0..8), b in prop::collection::btree_set(0..20u8, 0..8)) {
+        let (mut x, mut y) = (GSet::new(), GSet::new());
+        a.iter().for_each(|&element| { x.insert(element); });
+        b.iter().for_each(|&element| { y.insert(element); });
+        let mut xy = x.clone();
+        xy.merge(&y);
+        let mut yx = y.clone();
+        yx.merge(&x);
+        prop_assert_eq!(&xy, &yx);
+        prop_assert_eq!(xy.iter().copied().collect::<BTreeSet<u8>>(), &a | &b);
+        prop_assert!(!xy.merge(&y));
+    }
+
+    #[test]
+    fn or_set_merge_is_commutative_associative_and_idempotent(a in or_set(), b in or_set(), c in or_set()) {
+        prop_assert_eq!(merged_sets(&a, &b), merged_sets(&b, &a));
+        prop_assert_eq!(
+            merged_sets(&merged_sets(&a, &b), &c),
+            merged_sets(&a, &merged_sets(&b, &c))
+        );
+        let ab = merged_sets(&a, &b);
+        let mut again = ab.clone();
+        prop_assert!(!again.merge(&b));
+        prop_assert_eq!(again, ab);
+    }
+}
+
+/// Whether merging the replicas of any CRDT converges, whatever order they merge in, and
+/// however often.
+fn converges<T: Crdt + PartialEq + std::fmt::Debug>(
+    a: &T,
+    b: &T,
+    c: &T,
+) -> Result<(), TestCaseError> {
+    let merged = |x: &T, y: &T| {
+        let mut merged = x.clone();
+        merged.merge(y);
+        merged
+    };
+    prop_assert_eq!(merged(a, b), merged(b, a));
+    prop_assert_eq!(merged(&merged(a, b), c), merged(a, &merged(b, c)));
+    let ab = merged(a, b);
+    let mut again = ab.clone();
+    prop_assert!(!again.merge(b));
+    prop_assert_eq!(&again, &ab);
+    prop_assert_eq!(T::join([a, b, c]), merged(&ab, c));
+    Ok(())
+}
+
+/// Counters over a few nodes, each of which adds and subtracts.
+fn pn_counter() -> impl Strategy<Value = PNCounter> {
+    prop::collection::vec((0..NODES.len(), -100..100i64), 0..6).prop_map(|adds| {
+        let mut counter = PNCounter::new();
+        for (node, delta) in adds {
+            counter.add(NODES[node], delta);
+        }
+        counter
+    })
+}
+
+/// Maps over a few keys, written by a few nodes at a few timestamps, so that writes often
+/// tie on their timestamp, and sometimes on their node too.
+fn lww_map() -> impl Strategy<Value = LwwMap<u64>> {
+    prop::collection::vec((0..3u8, 0..5u64, 0..NODES.len(), 0..3u64), 0..6).prop_map(|writes| {
+        let mut map = LwwMap::new();
+        for (key, wall, node, value) in writes {
+            let timestamp = HlcTimestamp { wall, logical: 0 };
+            map.insert(&key.to_string(), value, timestamp, NODES[node]);
+        }
+        map
+    })
+}
+
+proptest! {
+    #[test]
+    fn pn_counters_converge_on_what_every_node_added(a in pn_counter(), b in pn_counter(), c in pn_counter()) {
+        converges(&a, &b, &c)?;
+        let sum = |counter: &PNCounter| counter.increments().value() as i64 - counter.decrements().value() as i64;
+        prop_assert_eq!(a.value(), sum(&a));
+    }
+
+    #[test]
+    fn lww_maps_converge_on_the_latest_write_of_every_key(a in lww_map(), b in lww_map(), c in lww_map()) {
+        converges(&a, &b, &c)?;
+        let merged = LwwMap::join([&a, &b]);
+        for (key, register) in a.iter().chain(b.iter()) {
+            prop_assert!(merged.register(key).unwrap().timestamp() >= register.timestamp());
+        }
+    }
+}
+
+#[test]
+fn a_pn_counter_goes_down_by_what_nodes_subtract() {
+    let mut a = PNCounter::new();
+    a.add("n0", 5);
+    let mut b = PNCounter::new();
+    b.add("n1", -8);
+    b.add("n1", 2);
+    assert!(a.merge(&b));
+    assert_eq!(a.value(), -1);
+    assert_eq!((a.increments().get("n1"), a.decrements().get("n1")), (2, 8));
+}
+
+#[test]
+fn an_or_set_add_wins_over_a_concurrent_remove() {
+    let mut a = ORSet::new();
+    a.insert(1, ("n0".to_string(), 1));
+    let mut b = a.clone();
+
+    // a removes what it saw of 1, while b adds it again.
+    assert!(a.remove(&1));
+    b.insert(1, ("n1".to_string(), 1));
+    a.merge(&b);
+    b.merge(&a);
+    assert!(a.contains(&1) && b.contains(&1));
+    assert_eq!(a, b);
+
+    // A remove that saw every add removes the element everywhere.
+    assert!(a.remove(&1));
+    b.merge(&a);
+    assert!(!b.contains(&1));
+    assert!(b.is_empty());
+}
+
+#[test]
+fn an_lww_register_keeps_the_latest_write_and_breaks_ties_by_node_then_value() {
+    let at = |wall| HlcTimestamp { wall, logical: 0 };
+    let mut register = LwwRegister::new(1, at(10), "n1");
+    assert!(!register.merge(&LwwRegister::new(2, at(9), "n2")));
+    assert!(register.merge(&LwwRegister::new(3, at(10), "n2")));
+    assert!(!register.merge(&LwwRegister::new(4, at(10), "n0")));
+    assert!(register.merge(&LwwRegister::new(5, at(10), "n2")));
+    assert!(!register.merge(&LwwRegister::new(3, at(10), "n2")));
+    assert_eq!((*register.value(), register.timestamp()), (5, at(10)));
+}
+
+#[test]
+fn an_mv_register_keeps_concurrent_writes_until_one_saw_them() {
+    let mut a = MvRegister::new();
+    a.write(1, "n0");
+    let mut b = a.clone();
+    a.write(2, "n0");
+    b.write(3, "n1");
+
+    let mut merged = a.clone();
+    assert!(merged.merge(&b));
+    let mut other = b.clone();
+    assert!(other.merge(&a));
+    assert_eq!(merged, other);
+    let mut values: Vec<i32> = merged.values().copied().collect();
+    values.sort();
+    assert_eq!(values, [2, 3]);
+
+    // A write that saw both replaces them, also where they were merged in another order.
+    merged.write(4, "n2");
+    assert!(other.merge(&merged));
+    assert!(!other.merge(&a));
+    assert_eq!(other.values().collect::<Vec<_>>(), [&4]);
+}
+
+/// Broadcasts to random nodes, with waits between them, over a network whose seed decides
+/// how messages are delayed, reordered, and lost.
+fn broadcasts() -> impl Strategy<Value = (Schedule, f64, f64)> {
+    let step = (0..NODES.len(), 0..1_000u64, 0..20u64).prop_map(|(node, message, wait)| {
+        vec![
+            Step::Request {
+                node: NODES[node].to_string(),
+                body: json!({"type": "broadcast", "message": message}),
+            },
+            Step::Wait(Duration::from_millis(wait)),
+        ]
+    });
+    (
+        any::<u64>(),
+        prop::collection::vec(step, 1..15),
+        0.0..0.5,
+        0.0..0.3,
+    )
+        .prop_map(|(seed, steps, reorder_rate, drop_rate)| {
+            let steps = steps.into_iter().flatten().collect();
+            (Schedule { seed, steps }, reorder_rate, drop_rate)
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn broadcast_nodes_converge_on_the_union_of_broadcasts(
+        (schedule, reorder_rate, drop_rate) in broadcasts(),
+    ) {
+        let mut network = Network::new(NetworkConfig {
+            latency: Duration::from_millis(2),
+            reorder_rate,
+            drop_rate,
+            seed: schedule.seed,
+        });
+        for id in NODES {
+            let seed = format!("--seed={}", schedule.seed);
+            let cli = broadcast::Cli::parse_from(["broadcast", "--gossip-interval-ms=10", &seed]);
+            network.add_node(id, broadcast::simulated(cli, network.clock()).unwrap());
+        }
+        network.init().unwrap();
+        let topology = json!({"n0": ["n1"], "n1": ["n0", "n2"], "n2": ["n1"]});
+        for id in NODES {
+            network.request(id, json!({"type": "topology", "topology": topology})).unwrap();
+        }
+
+        network.play(&schedule.steps).unwrap();
+        // Long enough for a gossip that was lost a few times to be retried after the longest
+        // back-off.
+        network.run_for(Duration::from_secs(10)).unwrap();
+
+        let broadcast: BTreeSet<u64> = schedule
+            .steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Request { body, .. } => body["message"].as_u64(),
+                _ => None,
+            })
+            .collect();
+        for id in NODES {
+            let reply = network.request(id, json!({"type": "read"})).unwrap();
+            let read: BTreeSet<u64> = serde_json::from_value(reply["messages"].clone()).unwrap();
+            prop_assert_eq!(&read, &broadcast, "{} disagrees", id);
+        }
+    }
+}
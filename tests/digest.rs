@@ -0,0 +1,46 @@
+use distributed_system::digest::{hash_bytes, BloomFilter, SetDigest};
+
+#[test]
+fn digest_does_not_depend_on_order() {
+    assert_eq!(SetDigest::of(&[1, 2, 3]), SetDigest::of(&[3, 1, 2]));
+    assert_eq!(SetDigest::of(&[]), SetDigest::default());
+}
+
+#[test]
+fn different_sets_have_different_digests() {
+    assert_ne!(SetDigest::of(&[1, 2, 3]), SetDigest::of(&[1, 2, 4]));
+    assert_ne!(SetDigest::of(&[1, 2]), SetDigest::of(&[1, 2, 3]));
+}
+
+#[test]
+fn bloom_filter_contains_every_element() {
+    let elements: Vec<u64> = (0..1000).map(|i| i * 7).collect();
+    let filter = BloomFilter::new(elements.iter(), 42);
+
+    assert!(elements.iter().all(|&element| filter.contains(element)));
+}
+
+#[test]
+fn bloom_filter_has_few_false_positives() {
+    let elements: Vec<u64> = (0..1000).collect();
+    let filter = BloomFilter::new(elements.iter(), 42);
+
+    let false_positives = (1000..11_000).filter(|&i| filter.contains(i)).count();
+    assert!(false_positives < 300, "{false_positives} false positives");
+}
+
+#[test]
+fn empty_bloom_filter_contains_nothing() {
+    let filter = BloomFilter::new([].iter(), 0);
+
+    assert!(!filter.contains(0));
+    assert!(!filter.contains(u64::MAX));
+}
+
+#[test]
+fn byte_hashes_depend_on_every_byte() {
+    assert_eq!(hash_bytes(b"hello"), hash_bytes(b"hello"));
+    assert_ne!(hash_bytes(b"hello"), hash_bytes(b"hellp"));
+    assert_ne!(hash_bytes(b""), hash_bytes(b"\0"));
+    assert_ne!(hash_bytes(b"12345678"), hash_bytes(b"123456789"));
+}
@@ -0,0 +1,59 @@
+use std::sync::mpsc::{TryRecvError, TrySendError};
+use std::time::Duration;
+
+use distributed_system::event_queue::{self, Priority};
+
+#[test]
+fn high_priority_events_come_first() {
+    let (sender, receiver) = event_queue::channel(10);
+    sender.send("gossip 1", Priority::Low).unwrap();
+    sender.send("read 1", Priority::High).unwrap();
+    sender.send("gossip 2", Priority::Low).unwrap();
+    sender.send("read 2", Priority::High).unwrap();
+
+    let received: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+    assert_eq!(received, ["read 1", "read 2", "gossip 1", "gossip 2"]);
+}
+
+#[test]
+fn each_priority_is_bounded_on_its_own() {
+    let (sender, receiver) = event_queue::channel(1);
+    sender.try_send(1, Priority::Low).unwrap();
+    assert!(matches!(
+        sender.try_send(2, Priority::Low),
+        Err(TrySendError::Full(2))
+    ));
+    sender.try_send(3, Priority::High).unwrap();
+
+    assert_eq!(receiver.recv(), Some(3));
+    assert_eq!(receiver.recv(), Some(1));
+    sender.try_send(2, Priority::Low).unwrap();
+}
+
+#[test]
+fn full_queues_make_senders_wait() {
+    let (sender, receiver) = event_queue::channel(1);
+    sender.send(1, Priority::Low).unwrap();
+    let blocked = std::thread::spawn(move || sender.send(2, Priority::Low));
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(!blocked.is_finished());
+
+    assert_eq!(receiver.recv(), Some(1));
+    blocked.join().unwrap().unwrap();
+    assert_eq!(receiver.recv(), Some(2));
+    assert_eq!(receiver.recv(), None);
+}
+
+#[test]
+fn dropping_either_end_disconnects() {
+    let (sender, receiver) = event_queue::channel::<u32>(1);
+    let clone = sender.clone();
+    drop(sender);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    drop(clone);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (sender, receiver) = event_queue::channel(1);
+    drop(receiver);
+    assert_eq!(sender.send(1, Priority::High), Err(1));
+}
@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+
+/// Feeds every `<case>.in.jsonl` transcript in `tests/golden/<workload>` to the workload's
+/// binary, and compares what it sends with `<case>.out.jsonl`. With `UPDATE_GOLDEN` set,
+/// writes what it sent there instead, for a review of the diff.
+fn check(workload: &str, binary: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(workload);
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".in.jsonl"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "No transcripts in {}", dir.display());
+
+    for input in cases {
+        let golden = PathBuf::from(input.to_string_lossy().replace(".in.jsonl", ".out.jsonl"));
+        let sent = normalize(&run(binary, &std::fs::read(&input).unwrap()));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let lines: Vec<String> = sent.iter().map(Value::to_string).collect();
+            std::fs::write(&golden, lines.join("\n") + "\n").unwrap();
+            continue;
+        }
+        let expected: Vec<Value> = std::fs::read_to_string(&golden)
+            .unwrap_or_else(|_| panic!("No golden file {}", golden.display()))
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            sent,
+            expected,
+            "{} differs from {}",
+            input.display(),
+            golden.display()
+        );
+    }
+}
+
+/// Runs `binary` with `input` on its stdin, and returns the messages it wrote to stdout.
+fn run(binary: &str, input: &[u8]) -> Vec<Value> {
+    let mut child = Command::new(binary)
+        .env("LOG_LEVEL", "error")
+        .env("EVENT_ORDER", "arrival")
+        .env_remove("NODE_CONFIG")
+        .env_remove("NODE_RECORD")
+        .env_remove("NODE_JOURNAL")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{binary} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn is_node_id(id: &str) -> bool {
+    id.strip_prefix('n')
+        .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Leaves out the messages to other nodes, which depend on the timing of timers, and
+/// numbers the `msg_id`s the node picked in the order they appear, so that a change in how
+/// a node counts its messages doesn't fail every transcript.
+fn normalize(sent: &[Value]) -> Vec<Value> {
+    let mut next_msg_id = 0;
+    sent.iter()
+        .filter(|message| !message["dest"].as_str().is_some_and(is_node_id))
+        .map(|message| {
+            let mut message = message.clone();
+            if message["body"].get("msg_id").is_some() {
+                next_msg_id += 1;
+                message["body"]["msg_id"] = json!(next_msg_id);
+            }
+            message
+        })
+        .collect()
+}
+
+#[test]
+fn echo() {
+    check("echo", env!("CARGO_BIN_EXE_echo"));
+}
+
+#[test]
+fn unique_ids() {
+    check("unique_ids", env!("CARGO_BIN_EXE_unique_ids"));
+}
+
+#[test]
+fn broadcast() {
+    check("broadcast", env!("CARGO_BIN_EXE_broadcast"));
+}
+
+#[test]
+fn g_counter() {
+    check("g_counter", env!("CARGO_BIN_EXE_g_counter"));
+}
+
+#[test]
+fn pn_counter() {
+    check("pn_counter", env!("CARGO_BIN_EXE_pn_counter"));
+}
+
+#[test]
+fn g_set() {
+    check("g_set", env!("CARGO_BIN_EXE_g_set"));
+}
+
+#[test]
+fn register() {
+    check("register", env!("CARGO_BIN_EXE_register"));
+}
+
+#[test]
+fn kafka() {
+    check("kafka", env!("CARGO_BIN_EXE_kafka"));
+}
+
+#[test]
+fn txn() {
+    check("txn", env!("CARGO_BIN_EXE_txn"));
+}
+
+#[test]
+fn node() {
+    check("node", env!("CARGO_BIN_EXE_node"));
+}
@@ -0,0 +1,391 @@
+use std::ffi::OsString;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use distributed_system::harness::{
+    self, Cluster, Generator, GeneratorConfig, KeyDistribution, Link, Mix, Op, Workload,
+};
+use distributed_system::rng::Rng;
+use serde_json::{json, Value};
+
+fn cluster(binary: &str, count: usize) -> Cluster {
+    cluster_over(binary, count, Link::Pipes)
+}
+
+fn cluster_over(binary: &str, count: usize, link: Link) -> Cluster {
+    std::env::set_var("LOG_LEVEL", "error");
+    let mut cluster = Cluster::start(vec![OsString::from(binary)], count, None, link).unwrap();
+    cluster.init().unwrap();
+    cluster
+}
+
+fn deadline() -> Instant {
+    Instant::now() + Duration::from_secs(5)
+}
+
+fn op(node: &str, request: Value, reply: Option<Value>) -> Op {
+    Op {
+        node: node.to_string(),
+        request,
+        sent_at: Instant::now(),
+        replied_at: reply.as_ref().map(|_| Instant::now()),
+        reply,
+    }
+}
+
+#[test]
+fn nodes_answer_their_clients() {
+    let mut cluster = cluster(env!("CARGO_BIN_EXE_echo"), 2);
+    let reply = cluster
+        .request(
+            "c1",
+            "n1",
+            json!({"type": "echo", "echo": "hi"}),
+            deadline(),
+        )
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(reply.src, "n1");
+    assert_eq!(reply.body["type"], "echo_ok");
+    assert_eq!(reply.body["echo"], "hi");
+    assert!(cluster.stop().unwrap().is_empty());
+}
+
+#[test]
+fn nodes_gossip_over_unix_sockets() {
+    let mut cluster = cluster_over(env!("CARGO_BIN_EXE_broadcast"), 2, Link::Unix);
+    let topology = json!({"type": "topology", "topology": {"n0": ["n1"], "n1": ["n0"]}});
+    for node in ["n0", "n1"] {
+        cluster
+            .request("c0", node, topology.clone(), deadline())
+            .unwrap()
+            .unwrap();
+    }
+    cluster
+        .request(
+            "c1",
+            "n0",
+            json!({"type": "broadcast", "message": 7}),
+            deadline(),
+        )
+        .unwrap()
+        .unwrap();
+
+    let converged = (0..50).any(|_| {
+        std::thread::sleep(Duration::from_millis(50));
+        let read = cluster
+            .request("c1", "n1", json!({"type": "read"}), deadline())
+            .unwrap()
+            .unwrap();
+        read.body["messages"] == json!([7])
+    });
+    assert!(converged, "n1 never got the message from n0");
+    assert!(cluster.delivered() > 0);
+
+    cluster.kill("n1").unwrap();
+    cluster.restart("n1").unwrap();
+    assert!(cluster.stop().unwrap().is_empty());
+}
+
+#[test]
+fn a_partition_drops_messages_between_groups() {
+    let mut cluster = cluster(env!("CARGO_BIN_EXE_broadcast"), 2);
+    let topology = json!({"type": "topology", "topology": {"n0": ["n1"], "n1": ["n0"]}});
+    for node in ["n0", "n1"] {
+        cluster
+            .request("c0", node, topology.clone(), deadline())
+            .unwrap()
+            .unwrap();
+    }
+    cluster.partition(&[vec!["n0".to_string()], vec!["n1".to_string()]]);
+    cluster
+        .request(
+            "c1",
+            "n0",
+            json!({"type": "broadcast", "message": 1}),
+            deadline(),
+        )
+        .unwrap()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let read = cluster
+        .request("c1", "n1", json!({"type": "read"}), deadline())
+        .unwrap()
+        .unwrap();
+    assert_eq!(read.body["messages"], json!([]));
+    assert!(cluster.dropped() > 0);
+
+    cluster.heal();
+    let converged = (0..50).any(|_| {
+        std::thread::sleep(Duration::from_millis(100));
+        let read = cluster
+            .request("c1", "n1", json!({"type": "read"}), deadline())
+            .unwrap()
+            .unwrap();
+        read.body["messages"] == json!([1])
+    });
+    assert!(
+        converged,
+        "n1 never got the message after the partition healed"
+    );
+}
+
+#[test]
+fn a_restarted_node_is_initialized_again() {
+    let mut cluster = cluster(env!("CARGO_BIN_EXE_echo"), 2);
+    cluster.kill("n0").unwrap();
+    assert_eq!(cluster.alive(), ["n1"]);
+
+    cluster.restart("n0").unwrap();
+    assert_eq!(cluster.alive(), ["n0", "n1"]);
+    let reply = cluster
+        .request(
+            "c1",
+            "n0",
+            json!({"type": "echo", "echo": "back"}),
+            deadline(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply.body["echo"], "back");
+}
+
+#[test]
+fn kafka_polls_continue_from_what_was_polled() {
+    let mut generator = Generator::new(Workload::Kafka, Rng::from_seed(1));
+    generator.observe(&json!({"type": "poll_ok", "msgs": {"k1": [[3, 10], [4, 11]]}}));
+
+    let poll = (0..100)
+        .map(|_| generator.next_request())
+        .find(|request| request["type"] == "poll")
+        .unwrap();
+    assert_eq!(poll["offsets"]["k1"], 5);
+    assert_eq!(poll["offsets"]["k0"], 0);
+}
+
+#[test]
+fn the_mix_decides_which_requests_are_sent() {
+    let config = GeneratorConfig {
+        mix: Some("send=3,poll=1".parse().unwrap()),
+        ..GeneratorConfig::default()
+    };
+    let mut generator = Generator::with_config(Workload::Kafka, config, Rng::from_seed(1)).unwrap();
+    let mut sends = 0;
+    for _ in 0..1000 {
+        match generator.next_request()["type"].as_str().unwrap() {
+            "send" => sends += 1,
+            "poll" => {}
+            other => panic!("Sent {other}, which isn't in the mix"),
+        }
+    }
+    assert!((650..850).contains(&sends), "{sends} of 1000 were sends");
+
+    let config = GeneratorConfig {
+        mix: Some("read=1".parse().unwrap()),
+        ..GeneratorConfig::default()
+    };
+    let error = Generator::with_config(Workload::Kafka, config, Rng::from_seed(1)).unwrap_err();
+    assert_eq!(error.to_string(), "kafka has no read requests");
+    assert!("send=0".parse::<Mix>().is_err());
+    assert!("send".parse::<Mix>().is_err());
+}
+
+#[test]
+fn zipfian_keys_favour_the_first_ones() {
+    let config = GeneratorConfig {
+        mix: Some("send=1".parse().unwrap()),
+        keys: 10,
+        key_distribution: "zipfian:1.5".parse().unwrap(),
+        ..GeneratorConfig::default()
+    };
+    let mut generator = Generator::with_config(Workload::Kafka, config, Rng::from_seed(2)).unwrap();
+    let mut counts = [0; 10];
+    for _ in 0..2000 {
+        let key = generator.next_request()["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        counts[key[1..].parse::<usize>().unwrap()] += 1;
+    }
+    assert!(counts[0] > counts[1] && counts[1] > counts[9], "{counts:?}");
+    assert!(counts[0] > 2000 / 3, "{counts:?}");
+
+    assert_eq!(
+        "zipfian".parse::<KeyDistribution>().unwrap(),
+        KeyDistribution::Zipfian { exponent: 1.0 }
+    );
+    assert!("zipfian:-1".parse::<KeyDistribution>().is_err());
+    assert!("normal".parse::<KeyDistribution>().is_err());
+}
+
+#[test]
+fn values_are_padded_to_their_size() {
+    let config = GeneratorConfig {
+        mix: Some("broadcast=1".parse().unwrap()),
+        value_size: 32,
+        ..GeneratorConfig::default()
+    };
+    let mut generator =
+        Generator::with_config(Workload::Broadcast, config, Rng::from_seed(3)).unwrap();
+    let first = generator.next_request()["message"].clone();
+    let second = generator.next_request()["message"].clone();
+    assert_eq!(first.as_str().unwrap().len(), 32);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn latencies_are_summarized_by_type() {
+    let sent_at = Instant::now();
+    let answered = |ms| Op {
+        node: "n0".to_string(),
+        request: json!({"type": "read"}),
+        sent_at,
+        reply: Some(json!({"type": "read_ok"})),
+        replied_at: Some(sent_at + Duration::from_millis(ms)),
+    };
+    let mut ops: Vec<Op> = (1..=100).map(answered).collect();
+    ops.push(op("n1", json!({"type": "add", "delta": 1}), None));
+
+    let latencies = harness::latencies(&ops);
+    assert_eq!(latencies.len(), 2);
+    assert_eq!(
+        (
+            latencies[0].kind.as_str(),
+            latencies[0].sent,
+            latencies[0].ok
+        ),
+        ("add", 1, 0)
+    );
+    assert_eq!(latencies[0].max, Duration::ZERO);
+    let reads = &latencies[1];
+    assert_eq!((reads.sent, reads.ok), (100, 100));
+    assert_eq!(reads.p50, Duration::from_millis(50));
+    assert_eq!(reads.p95, Duration::from_millis(95));
+    assert_eq!(reads.p99, Duration::from_millis(99));
+    assert_eq!(reads.max, Duration::from_millis(100));
+}
+
+#[test]
+fn broadcast_checks_find_lost_and_made_up_messages() {
+    let ops = [
+        op(
+            "n0",
+            json!({"type": "broadcast", "message": 1}),
+            Some(json!({"type": "broadcast_ok"})),
+        ),
+        op("n0", json!({"type": "broadcast", "message": 2}), None),
+    ];
+    let reads = [
+        op(
+            "n0",
+            json!({"type": "read"}),
+            Some(json!({"type": "read_ok", "messages": [1, 2]})),
+        ),
+        op(
+            "n1",
+            json!({"type": "read"}),
+            Some(json!({"type": "read_ok", "messages": [3]})),
+        ),
+    ];
+
+    let checks = harness::check(Workload::Broadcast, &ops, &reads);
+    assert_eq!(checks.len(), 2);
+    assert_eq!(
+        checks[0].failure.as_deref(),
+        Some("n1 misses 1 acknowledged messages")
+    );
+    assert_eq!(
+        checks[1].failure.as_deref(),
+        Some("n1 read 3, which nobody broadcast")
+    );
+}
+
+#[test]
+fn g_set_checks_find_lost_and_made_up_elements() {
+    let ops = [
+        op(
+            "n0",
+            json!({"type": "add", "element": 1}),
+            Some(json!({"type": "add_ok"})),
+        ),
+        op("n1", json!({"type": "add", "element": 2}), None),
+    ];
+    let read = |node, value: Value| {
+        op(
+            node,
+            json!({"type": "read"}),
+            Some(json!({"type": "read_ok", "value": value})),
+        )
+    };
+
+    let checks = harness::check(Workload::GSet, &ops, &[read("n0", json!([1, 2]))]);
+    assert!(checks.iter().all(|check| check.passed()), "{checks:?}");
+    let checks = harness::check(Workload::GSet, &ops, &[read("n1", json!([4]))]);
+    assert_eq!(
+        checks[0].failure.as_deref(),
+        Some("n1 misses 1 acknowledged elements")
+    );
+    assert_eq!(
+        checks[1].failure.as_deref(),
+        Some("n1 read 4, which nobody added")
+    );
+}
+
+#[test]
+fn counter_reads_may_include_adds_that_timed_out() {
+    let ops = [
+        op(
+            "n0",
+            json!({"type": "add", "delta": 2}),
+            Some(json!({"type": "add_ok"})),
+        ),
+        op("n1", json!({"type": "add", "delta": 3}), None),
+    ];
+    let read = |value| {
+        op(
+            "n0",
+            json!({"type": "read"}),
+            Some(json!({"type": "read_ok", "value": value})),
+        )
+    };
+
+    for value in [2, 5] {
+        let checks = harness::check(Workload::GCounter, &ops, &[read(value)]);
+        assert!(checks[0].passed(), "{checks:?}");
+    }
+    let checks = harness::check(Workload::GCounter, &ops, &[read(6)]);
+    assert_eq!(
+        checks[0].failure.as_deref(),
+        Some("n0 read 6, expected between 2 and 5")
+    );
+}
+
+#[test]
+fn the_harness_runs_broadcast_through_partitions() {
+    let output = Command::new(env!("CARGO_BIN_EXE_harness"))
+        .env("LOG_LEVEL", "error")
+        .args([
+            "--workload",
+            "broadcast",
+            "--node-count",
+            "5",
+            "--seed",
+            "7",
+        ])
+        .args(["--time-limit-ms", "1500", "--recovery-ms", "1000"])
+        .args(["--nemesis", "partition", "--nemesis-interval-ms", "500"])
+        .arg(env!("CARGO_BIN_EXE_broadcast"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{stdout}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("delivered: ok"), "{stdout}");
+    assert!(stdout.contains("Everything looks good"), "{stdout}");
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use distributed_system::clock::VirtualClock;
+use distributed_system::hlc::{Hlc, HlcTimestamp};
+use proptest::prelude::*;
+
+#[test]
+fn timestamps_follow_the_wall_clock() {
+    let mut clock = Hlc::new();
+    assert_eq!(
+        clock.now_at(100),
+        HlcTimestamp {
+            wall: 100,
+            logical: 0
+        }
+    );
+    assert_eq!(
+        clock.now_at(100),
+        HlcTimestamp {
+            wall: 100,
+            logical: 1
+        }
+    );
+    assert_eq!(
+        clock.now_at(105),
+        HlcTimestamp {
+            wall: 105,
+            logical: 0
+        }
+    );
+}
+
+#[test]
+fn timestamps_are_read_from_the_given_clock() {
+    let time = Arc::new(VirtualClock::new());
+    let mut clock = Hlc::with_clock(time.clone());
+    let first = clock.now();
+    assert_eq!(
+        clock.now(),
+        HlcTimestamp {
+            logical: 1,
+            ..first
+        }
+    );
+    time.advance(Duration::from_millis(3));
+    assert_eq!(
+        clock.now(),
+        HlcTimestamp {
+            wall: first.wall + 3,
+            logical: 0
+        }
+    );
+}
+
+#[test]
+fn timestamps_keep_increasing_when_the_wall_clock_goes_backwards() {
+    let mut clock = Hlc::new();
+    let before = clock.now_at(1_000);
+    let after = clock.now_at(10);
+    assert!(after > before);
+    assert_eq!(after.wall, 1_000);
+}
+
+#[test]
+fn receiving_a_timestamp_from_ahead_moves_the_clock_past_it() {
+    let mut sender = Hlc::new();
+    let mut receiver = Hlc::new();
+    let sent = sender.now_at(5_000);
+
+    let received = receiver.observe_at(sent, 4_000);
+    assert!(received > sent);
+    assert!(receiver.now_at(4_001) > received);
+}
+
+#[test]
+fn exhausted_logical_counter_moves_to_the_next_millisecond() {
+    let mut clock = Hlc::new();
+    let last = HlcTimestamp {
+        wall: 7,
+        logical: u16::MAX,
+    };
+    assert_eq!(
+        clock.observe_at(last, 7),
+        HlcTimestamp {
+            wall: 8,
+            logical: 0
+        }
+    );
+}
+
+#[test]
+fn serializes_as_a_single_number() {
+    let timestamp = HlcTimestamp {
+        wall: 3,
+        logical: 2,
+    };
+    assert_eq!(serde_json::to_string(&timestamp).unwrap(), "196610");
+    assert_eq!(
+        serde_json::from_str::<HlcTimestamp>("196610").unwrap(),
+        timestamp
+    );
+}
+
+/// A local event at the given wall-clock time, or the receipt of a message that another
+/// node stamped at the given time.
+#[derive(Debug, Clone)]
+enum Event {
+    Local(u64),
+    Receive { wall: u64, remote: u64 },
+}
+
+fn event() -> impl Strategy<Value = Event> {
+    let wall = 0..1_000u64;
+    prop_oneof![
+        wall.clone().prop_map(Event::Local),
+        (wall.clone(), wall).prop_map(|(wall, remote)| Event::Receive { wall, remote }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn timestamps_are_strictly_monotonic(events in prop::collection::vec(event(), 1..100)) {
+        let mut clock = Hlc::new();
+        let mut last = None;
+
+        for event in events {
+            let timestamp = match event {
+                Event::Local(wall) => clock.now_at(wall),
+                Event::Receive { wall, remote } => {
+                    let remote = HlcTimestamp { wall: remote, logical: 0 };
+                    let timestamp = clock.observe_at(remote, wall);
+                    prop_assert!(timestamp > remote);
+                    timestamp
+                }
+            };
+            prop_assert!(last < Some(timestamp));
+            last = Some(timestamp);
+        }
+    }
+
+    #[test]
+    fn packing_preserves_order(a in 0..1u64 << 48, b in 0..1u64 << 48, la: u16, lb: u16) {
+        let a = HlcTimestamp { wall: a, logical: la };
+        let b = HlcTimestamp { wall: b, logical: lb };
+        prop_assert_eq!(HlcTimestamp::from_u64(a.to_u64()), a);
+        prop_assert_eq!(a.cmp(&b), a.to_u64().cmp(&b.to_u64()));
+        if a != b {
+            prop_assert_eq!(a.cmp(&b), a.unique_id(1).cmp(&b.unique_id(0)));
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use distributed_system::clock::VirtualClock;
+use distributed_system::ids::{
+    encode_ulid, split_snowflake, ulid_millis, Snowflake, Ulid, SNOWFLAKE_EPOCH,
+};
+
+#[test]
+fn snowflake_packs_timestamp_node_and_sequence() {
+    let mut snowflake = Snowflake::new(5).unwrap();
+    let id = snowflake.next_id_at(SNOWFLAKE_EPOCH + 42);
+    assert_eq!(split_snowflake(id), (42, 5, 0));
+
+    let id = snowflake.next_id_at(SNOWFLAKE_EPOCH + 42);
+    assert_eq!(split_snowflake(id), (42, 5, 1));
+}
+
+#[test]
+fn snowflake_rejects_too_many_nodes() {
+    assert!(Snowflake::new(1023).is_some());
+    assert!(Snowflake::new(1024).is_none());
+}
+
+#[test]
+fn snowflake_ids_increase_when_the_clock_goes_backwards() {
+    let mut snowflake = Snowflake::new(0).unwrap();
+    let before = snowflake.next_id_at(SNOWFLAKE_EPOCH + 1_000);
+    let after = snowflake.next_id_at(SNOWFLAKE_EPOCH + 10);
+    assert!(after > before);
+    assert_eq!(split_snowflake(after), (1_000, 0, 1));
+}
+
+#[test]
+fn snowflake_moves_to_the_next_millisecond_when_the_sequence_overflows() {
+    let mut snowflake = Snowflake::new(3).unwrap();
+    let ids: Vec<u64> = (0..5_000)
+        .map(|_| snowflake.next_id_at(SNOWFLAKE_EPOCH + 7))
+        .collect();
+
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(split_snowflake(ids[4_095]), (7, 3, 4_095));
+    assert_eq!(split_snowflake(ids[4_096]), (8, 3, 0));
+}
+
+#[test]
+fn snowflake_ids_of_different_nodes_sort_by_time() {
+    let mut early = Snowflake::new(9).unwrap();
+    let mut late = Snowflake::new(1).unwrap();
+    assert!(early.next_id_at(SNOWFLAKE_EPOCH + 1) < late.next_id_at(SNOWFLAKE_EPOCH + 2));
+}
+
+#[test]
+fn snowflake_reads_the_time_from_its_clock() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut snowflake = Snowflake::with_clock(2, clock.clone()).unwrap();
+    let (millis, _, _) = split_snowflake(snowflake.next_id());
+    assert_eq!(split_snowflake(snowflake.next_id()), (millis, 2, 1));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(split_snowflake(snowflake.next_id()), (millis + 1_000, 2, 0));
+}
+
+#[test]
+fn ulid_is_encoded_in_crockford_base32() {
+    assert_eq!(encode_ulid(0), "00000000000000000000000000");
+    assert_eq!(encode_ulid(u128::MAX), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+    assert_eq!(encode_ulid(32 * 10 + 31), "000000000000000000000000AZ");
+}
+
+#[test]
+fn ulids_sort_lexicographically_and_embed_the_timestamp() {
+    let mut ulid = Ulid::new();
+    let ids: Vec<u128> = [5, 5, 5, 3, 9]
+        .into_iter()
+        .map(|millis| ulid.next_id_at(1_700_000_000_000 + millis))
+        .collect();
+
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    let encoded: Vec<String> = ids.iter().map(|&id| encode_ulid(id)).collect();
+    assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+
+    assert_eq!(ulid_millis(ids[0]), 1_700_000_000_005);
+    assert_eq!(ulid_millis(ids[3]), 1_700_000_000_005);
+    assert_eq!(ulid_millis(ids[4]), 1_700_000_000_009);
+}
+
+#[test]
+fn ulid_reads_the_time_from_its_clock() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut ulid = Ulid::with_clock(clock.clone());
+    let millis = ulid_millis(ulid.next_id());
+    clock.advance(Duration::from_millis(250));
+    assert_eq!(ulid_millis(ulid.next_id()), millis + 250);
+}
@@ -0,0 +1,48 @@
+use distributed_system::journal::{self, Delta, Journal};
+use serde_json::json;
+
+#[test]
+fn a_delta_turns_the_old_state_into_the_new_one() {
+    let old = json!({"messages": 2, "peers": {"n2": 1, "n3": 4}, "leader": "n1", "a/b": [1]});
+    let new = json!({"messages": 3, "peers": {"n2": 1}, "leader": null, "a/b": [1, 2], "x": {}});
+
+    let delta = Delta::between(&old, &new);
+    assert_eq!(
+        serde_json::to_value(&delta).unwrap(),
+        json!({
+            "set": {"/messages": 3, "/leader": null, "/a~1b": [1, 2], "/x": {}},
+            "unset": ["/peers/n3"],
+        })
+    );
+    let mut state = old.clone();
+    delta.apply(&mut state);
+    assert_eq!(state, new);
+
+    assert!(Delta::between(&new, &new).is_empty());
+    let mut state = serde_json::Value::Null;
+    Delta::between(&state, &new).apply(&mut state);
+    assert_eq!(state, new);
+}
+
+#[test]
+fn states_are_rebuilt_from_the_journal_at_any_event() {
+    let dir =
+        std::env::temp_dir().join(format!("distributed-system-journal-{}", std::process::id()));
+    let mut journal = Journal::create(&dir).unwrap();
+    journal.set_node_id("n2");
+    journal.write("init", json!({"messages": 0})).unwrap();
+    journal.write("broadcast", json!({"messages": 1})).unwrap();
+    journal.write("timer", json!({"messages": 1})).unwrap();
+
+    let entries = journal::read(dir.join("n2.jsonl")).unwrap();
+    let events: Vec<(u64, &str)> = entries
+        .iter()
+        .map(|entry| (entry.index, entry.event.as_str()))
+        .collect();
+    assert_eq!(events, [(1, "init"), (2, "broadcast"), (3, "timer")]);
+    assert!(entries[2].delta.is_empty());
+    assert_eq!(journal::state_at(&entries, 0), serde_json::Value::Null);
+    assert_eq!(journal::state_at(&entries, 1), json!({"messages": 0}));
+    assert_eq!(journal::state_at(&entries, 9), json!({"messages": 1}));
+    std::fs::remove_dir_all(dir).unwrap();
+}
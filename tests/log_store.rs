@@ -0,0 +1,130 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use distributed_system::hlc::HlcTimestamp;
+use distributed_system::log_store::{DiskLogStore, LogStore, TxnMark, TxnState};
+
+/// A fresh directory under the system's temporary directory.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("log-store-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn at(wall: u64) -> HlcTimestamp {
+    HlcTimestamp { wall, logical: 0 }
+}
+
+/// Appends `bytes` to a file of the store, like a write cut short by a crash.
+fn append_raw(path: &Path, bytes: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[test]
+fn disk_log_store_recovers_logs_commits_and_txn_marks_when_reopened() {
+    let dir = temp_dir("reopen");
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    for msg in [10, 20, 30] {
+        store.append("k1", msg, at(msg)).unwrap();
+    }
+    store.append("k2", 5, at(5)).unwrap();
+    store.commit("g1", "k1", 2, Some(3)).unwrap();
+    let mark = TxnMark {
+        txn: "n0-1".to_string(),
+        primary: "k1".to_string(),
+        start: 0,
+        end: 1,
+        state: TxnState::Pending,
+    };
+    store.mark_txn("k2", mark.clone()).unwrap();
+    drop(store);
+
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    assert_eq!(
+        store.read("k1", 0, 10),
+        Some(vec![[0, 10], [1, 20], [2, 30]])
+    );
+    assert_eq!(store.timestamps("k1", 1, 1), [at(20)]);
+    assert_eq!(store.read("k2", 0, 10), Some(vec![[0, 5]]));
+    assert_eq!(store.committed("g1", "k1"), Some(2));
+    assert_eq!(store.epoch("g1", "k1"), Some(3));
+    assert_eq!(store.txns("k2"), [mark]);
+
+    assert_eq!(store.append("k1", 40, at(40)).unwrap(), 3);
+    drop(store);
+    let store = DiskLogStore::open(&dir).unwrap();
+    assert_eq!(store.log_end("k1"), 4);
+}
+
+#[test]
+fn disk_log_store_cuts_off_a_torn_trailing_line() {
+    let dir = temp_dir("torn");
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    store.append("k1", 10, at(1)).unwrap();
+    store.append("k1", 20, at(2)).unwrap();
+    store.commit("g1", "k1", 1, None).unwrap();
+    drop(store);
+    append_raw(&dir.join("segments/6b31-0.log"), b"30 1");
+    append_raw(
+        &dir.join("offsets.log"),
+        br#"{"group":"g1","key":"k1","off"#,
+    );
+
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    assert_eq!(store.read("k1", 0, 10), Some(vec![[0, 10], [1, 20]]));
+    assert_eq!(store.committed("g1", "k1"), Some(1));
+
+    // Appends after the cut start on a line of their own.
+    assert_eq!(store.append("k1", 30, at(3)).unwrap(), 2);
+    store.commit("g1", "k1", 2, None).unwrap();
+    drop(store);
+    let store = DiskLogStore::open(&dir).unwrap();
+    assert_eq!(
+        store.read("k1", 0, 10),
+        Some(vec![[0, 10], [1, 20], [2, 30]])
+    );
+    assert_eq!(store.committed("g1", "k1"), Some(2));
+}
+
+#[test]
+fn disk_log_store_keeps_the_offsets_of_a_truncated_log_when_reopened() {
+    let dir = temp_dir("truncate");
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    for msg in 0..5 {
+        store.append("k1", msg, at(msg)).unwrap();
+    }
+    store.truncate("k1", 3).unwrap();
+    store.append("k1", 5, at(5)).unwrap();
+    drop(store);
+
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    assert_eq!(store.log_start("k1"), 3);
+    assert_eq!(store.read("k1", 0, 10), Some(vec![[3, 3], [4, 4], [5, 5]]));
+    assert_eq!(store.append("k1", 6, at(6)).unwrap(), 6);
+}
+
+#[test]
+fn disk_log_store_reopens_from_the_newest_segment_after_a_crash_in_truncate() {
+    let dir = temp_dir("truncate-crash");
+    let mut store = DiskLogStore::open(&dir).unwrap();
+    for msg in 0..4 {
+        store.append("k1", msg, at(msg)).unwrap();
+    }
+    drop(store);
+    // A crash after the new segment was written, before the old one was removed, and one
+    // while another segment was still being written.
+    let segments = dir.join("segments");
+    std::fs::write(segments.join("6b31-2.log"), "2 2\n3 3\n").unwrap();
+    std::fs::write(segments.join("6b31-3.tmp"), "3 3\n").unwrap();
+
+    let store = DiskLogStore::open(&dir).unwrap();
+    assert_eq!(store.read("k1", 0, 10), Some(vec![[2, 2], [3, 3]]));
+    let mut left: Vec<String> = std::fs::read_dir(&segments)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["6b31-2.log"]);
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use distributed_system::config::LogLevel;
+use distributed_system::logging::{Filter, LogFormat, Logger};
+use log::{Level, Record};
+
+#[test]
+fn the_most_specific_directive_applies() {
+    let filter: Filter = "broadcast=debug, distributed_system=warn,raft=trace"
+        .parse()
+        .unwrap();
+    assert_eq!(filter.level("broadcast"), Some(LogLevel::Debug));
+    assert_eq!(filter.level("node::broadcast"), Some(LogLevel::Debug));
+    assert_eq!(
+        filter.level("distributed_system::raft"),
+        Some(LogLevel::Trace)
+    );
+    assert_eq!(
+        filter.level("distributed_system::runtime"),
+        Some(LogLevel::Warn)
+    );
+    assert_eq!(filter.level("broadcast_async"), None);
+    assert_eq!(filter.level("kafka"), None);
+}
+
+#[test]
+fn malformed_filters_are_errors() {
+    assert!("broadcast".parse::<Filter>().is_err());
+    assert!("broadcast=loud".parse::<Filter>().is_err());
+    assert_eq!("".parse::<Filter>().unwrap(), Filter::default());
+}
+
+#[test]
+fn records_are_formatted_as_one_line() {
+    let now = UNIX_EPOCH + Duration::from_millis(86_400_000 + 3_723_004);
+    let args = format_args!("Received error: \"timeout\"");
+    let record = Record::builder()
+        .args(args)
+        .level(Level::Warn)
+        .target("broadcast")
+        .build();
+
+    let human = Logger::new(LogLevel::Info, Filter::default(), LogFormat::Human);
+    assert_eq!(
+        human.format(&record, now),
+        "01:02:03.004 WARN  broadcast: Received error: \"timeout\""
+    );
+
+    let json = Logger::new(LogLevel::Info, Filter::default(), LogFormat::Json);
+    let line: serde_json::Value = serde_json::from_str(&json.format(&record, now)).unwrap();
+    assert_eq!(
+        line,
+        serde_json::json!({
+            "ts": 90_123_004,
+            "level": "WARN",
+            "target": "broadcast",
+            "msg": "Received error: \"timeout\"",
+        })
+    );
+}
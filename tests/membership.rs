@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use distributed_system::membership::{
+    MemberState, Membership, MembershipEvent, SwimConfig, SwimMessage,
+};
+use distributed_system::rng::Rng;
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+fn membership(members: &[&str], start: Instant) -> Membership {
+    let mut membership = Membership::new(SwimConfig::default());
+    membership.set_members(members.iter().map(|id| id.to_string()), start);
+    membership
+}
+
+fn pinged(messages: &[(String, SwimMessage)]) -> (String, u64) {
+    match messages {
+        [(target, SwimMessage::Ping { seq })] => (target.clone(), *seq),
+        other => panic!("expected a single ping, got {other:?}"),
+    }
+}
+
+#[test]
+fn acked_probe_keeps_member_alive() {
+    let start = Instant::now();
+    let mut rng = Rng::from_seed(1);
+    let mut membership = membership(&["n2"], start);
+
+    let (target, seq) = pinged(&membership.tick(start, &mut rng).messages);
+    assert_eq!(target, "n2");
+    membership.receive("n2", SwimMessage::Ack { seq }, start + ms(100));
+
+    let output = membership.tick(start + ms(2000), &mut rng);
+    assert!(output.events.is_empty());
+    assert_eq!(membership.state("n2"), Some(MemberState::Alive));
+    assert_eq!(pinged(&output.messages).0, "n2");
+}
+
+#[test]
+fn unresponsive_member_is_probed_indirectly_then_suspected_then_faulty() {
+    let start = Instant::now();
+    let mut rng = Rng::from_seed(1);
+    let mut membership = membership(&["n2", "n3"], start);
+
+    let (target, seq) = pinged(&membership.tick(start, &mut rng).messages);
+    let helper = if target == "n2" { "n3" } else { "n2" };
+
+    let output = membership.tick(start + ms(300), &mut rng);
+    assert_eq!(
+        output.messages,
+        vec![(
+            helper.to_string(),
+            SwimMessage::PingReq {
+                target: target.clone(),
+                seq
+            }
+        )]
+    );
+
+    let output = membership.tick(start + ms(1000), &mut rng);
+    assert!(output
+        .events
+        .contains(&MembershipEvent::Suspect(target.clone())));
+    assert!(membership.is_available(&target));
+
+    let output = membership.tick(start + ms(4000), &mut rng);
+    assert!(output
+        .events
+        .contains(&MembershipEvent::Faulty(target.clone())));
+    assert!(!membership.is_available(&target));
+
+    let output = membership.receive(helper, SwimMessage::Ping { seq: 9 }, start + ms(4100));
+    assert_eq!(
+        output.messages,
+        vec![(helper.to_string(), SwimMessage::Ack { seq: 9 })]
+    );
+    let event = membership.observe(&target, start + ms(4200));
+    assert_eq!(event, Some(MembershipEvent::Alive(target.clone())));
+    assert_eq!(membership.state(&target), Some(MemberState::Alive));
+}
+
+#[test]
+fn indirect_ack_proves_target_alive() {
+    let start = Instant::now();
+    let mut rng = Rng::from_seed(1);
+    let mut membership = membership(&["n2"], start);
+
+    let (_, seq) = pinged(&membership.tick(start, &mut rng).messages);
+    // The probe that gave up is followed by the next one right away.
+    let output = membership.tick(start + ms(1000), &mut rng);
+    assert_eq!(membership.state("n2"), Some(MemberState::Suspect));
+
+    // The next probe's ack, relayed by a helper, clears the suspicion.
+    let (_, next_seq) = pinged(&output.messages);
+    assert_ne!(seq, next_seq);
+    let output = membership.receive("n9", SwimMessage::Ack { seq: next_seq }, start + ms(1500));
+    assert_eq!(
+        output.events,
+        vec![MembershipEvent::Alive("n2".to_string())]
+    );
+}
+
+#[test]
+fn ping_req_is_relayed_to_target_and_back() {
+    let start = Instant::now();
+    let mut membership = membership(&["n1", "n3"], start);
+
+    let output = membership.receive(
+        "n1",
+        SwimMessage::PingReq {
+            target: "n3".to_string(),
+            seq: 7,
+        },
+        start,
+    );
+    let (target, seq) = pinged(&output.messages);
+    assert_eq!(target, "n3");
+
+    let output = membership.receive("n3", SwimMessage::Ack { seq }, start + ms(100));
+    assert_eq!(
+        output.messages,
+        vec![("n1".to_string(), SwimMessage::Ack { seq: 7 })]
+    );
+}
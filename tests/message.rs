@@ -0,0 +1,91 @@
+use distributed_system::message::RawMessage;
+use distributed_system::{ErrorBody, ErrorCode, Message};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    Echo { msg_id: u64, echo: String },
+    Error(ErrorBody),
+}
+
+impl From<ErrorBody> for Body {
+    fn from(error: ErrorBody) -> Self {
+        Body::Error(error)
+    }
+}
+
+fn error_code(reply: Message<Body>) -> ErrorCode {
+    match reply.body {
+        Body::Error(error) => error.code,
+        body => panic!("Expected an error reply, got {body:?}"),
+    }
+}
+
+#[test]
+fn unknown_fields_are_ignored() {
+    let line = r#"{"id":4,"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi","extra":[1]}}"#;
+    let message = Message::<Body>::parse(line).unwrap().unwrap();
+
+    assert!(matches!(message.body, Body::Echo { msg_id: 1, echo } if echo == "hi"));
+}
+
+#[test]
+fn unknown_and_malformed_requests_get_error_replies() {
+    let unknown = r#"{"src":"c1","dest":"n1","body":{"type":"shout","msg_id":2}}"#;
+    let reply = Message::<Body>::parse(unknown).unwrap().unwrap_err();
+    assert_eq!(reply.dest, "c1");
+    assert_eq!(error_code(reply), ErrorCode::NotSupported);
+
+    let malformed = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":3}}"#;
+    let reply = Message::<Body>::parse(malformed).unwrap().unwrap_err();
+    assert_eq!(error_code(reply), ErrorCode::MalformedRequest);
+
+    let untyped = r#"{"src":"c1","dest":"n1","body":{"msg_id":4}}"#;
+    let reply = Message::<Body>::parse(untyped).unwrap().unwrap_err();
+    assert_eq!(error_code(reply), ErrorCode::MalformedRequest);
+}
+
+#[test]
+fn unanswerable_lines_are_errors() {
+    assert!(Message::<Body>::parse("not json").is_err());
+    assert!(Message::<Body>::parse(r#"{"src":"n2","dest":"n1","body":{"type":"shout"}}"#).is_err());
+}
+
+#[test]
+fn parsed_values_are_the_message_or_its_error_reply() {
+    let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi"}}"#;
+    let message = Message::<Body>::parse_value(line).unwrap().unwrap();
+    assert_eq!(message["body"]["echo"], "hi");
+
+    let unknown = r#"{"src":"c1","dest":"n1","body":{"type":"shout","msg_id":2}}"#;
+    let reply = Message::<Body>::parse_value(unknown).unwrap().unwrap_err();
+    assert_eq!(reply["dest"], "c1");
+    assert_eq!(reply["body"]["type"], "error");
+    assert_eq!(reply["body"]["in_reply_to"], 2);
+
+    assert!(Message::<Body>::parse_value("not json").is_err());
+}
+
+#[test]
+fn raw_message_is_sent_on_verbatim() {
+    let line =
+        r#"{"src":"n2","dest":"n1","body":{"type":"shout","in_reply_to":7,"loud":{"very":true}}}"#;
+    let message = RawMessage::parse_raw(line).unwrap();
+
+    assert_eq!(message.kind().as_deref(), Some("shout"));
+    assert_eq!(message.in_reply_to(), Some(7));
+    assert_eq!(message.msg_id(), None);
+    assert_eq!(serde_json::to_string(&message).unwrap(), line);
+}
+
+#[test]
+fn raw_message_decodes_once_known() {
+    let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi"}}"#;
+    let message = RawMessage::parse_raw(line).unwrap();
+
+    let echo: Message<Body> = message.decode().unwrap();
+    assert_eq!(echo.src, "c1");
+    assert!(matches!(echo.body, Body::Echo { msg_id: 1, .. }));
+    assert!(message.decode::<ErrorBody>().is_err());
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use distributed_system::message::Message;
+use distributed_system::metrics::{self, Latency, Traffic};
+use serde::Serialize;
+
+// The registry is shared by the whole process, so every test counts messages of types of
+// its own.
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Body {
+    MetricsSendOk { in_reply_to: u64 },
+}
+
+#[test]
+fn sent_messages_are_counted_by_type_with_their_size() {
+    let message = Message {
+        src: "n1".to_string(),
+        dest: "c1".to_string(),
+        body: Body::MetricsSendOk { in_reply_to: 1 },
+    };
+    let mut output = Vec::new();
+    message.send(&mut output).unwrap();
+    message.send(&mut output).unwrap();
+
+    let stats = metrics::snapshot();
+    assert_eq!(
+        stats.sent["metrics_send_ok"],
+        Traffic {
+            messages: 2,
+            bytes: output.len() as u64 - 2,
+        }
+    );
+}
+
+#[test]
+fn handler_latency_is_summarized_by_type() {
+    let kind =
+        metrics::record_received(br#"{"src":"c1","dest":"n1","body":{"type":"metrics_latency"}}"#);
+    for micros in [100, 200, 300, 5000] {
+        metrics::record_latency(kind, Duration::from_micros(micros));
+    }
+
+    let stats = metrics::snapshot();
+    assert_eq!(stats.received["metrics_latency"], 1);
+    assert_eq!(
+        stats.latency["metrics_latency"],
+        Latency {
+            count: 4,
+            mean_us: 1400,
+            p50_us: 255,
+            p99_us: 5000,
+            max_us: 5000,
+        }
+    );
+}
@@ -0,0 +1,87 @@
+use distributed_system::hlc::HlcTimestamp;
+use distributed_system::mvcc::MvccStore;
+use proptest::prelude::*;
+
+fn at(wall: u64) -> HlcTimestamp {
+    HlcTimestamp { wall, logical: 0 }
+}
+
+#[test]
+fn a_snapshot_reads_the_latest_version_at_or_before_it() {
+    let mut store = MvccStore::new();
+    store.write("k", at(10), 1);
+    store.write("k", at(20), 2);
+    store.delete("k", at(30));
+
+    assert_eq!(store.read(&"k", at(5)), None);
+    assert_eq!(store.read(&"k", at(10)), Some(&1));
+    assert_eq!(store.read(&"k", at(19)), Some(&1));
+    assert_eq!(store.read(&"k", at(25)), Some(&2));
+    assert_eq!(store.read(&"k", at(30)), None);
+    assert_eq!(store.latest(&"k"), None);
+    assert_eq!(store.latest_version(&"k"), Some(at(30)));
+
+    // Writes after a snapshot was taken don't show in it.
+    let snapshot = store.snapshot(at(25));
+    assert_eq!(snapshot.get(&"k"), Some(&2));
+    assert_eq!(snapshot.iter().collect::<Vec<_>>(), [(&"k", &2)]);
+}
+
+#[test]
+fn a_transaction_conflicts_with_writes_after_its_snapshot() {
+    // Versions may be the ids of the transactions that wrote them.
+    let mut store: MvccStore<u64, u64, u64> = MvccStore::new();
+    store.write(1, 3, 30);
+
+    assert!(store.written_since(&1, 2));
+    assert!(!store.written_since(&1, 3));
+    assert!(!store.written_since(&2, 0));
+}
+
+#[test]
+fn gc_drops_the_versions_before_the_horizon_and_keys_deleted_by_then() {
+    let mut store = MvccStore::new();
+    store.write("a", 1, 1);
+    store.write("a", 2, 2);
+    store.write("a", 5, 5);
+    store.write("b", 1, 1);
+    store.delete("b", 2);
+
+    assert_eq!(store.gc(3), 3);
+    assert_eq!(store.version_count(), 2);
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.read(&"a", 3), Some(&2));
+    assert_eq!(store.read(&"a", 5), Some(&5));
+    assert_eq!(store.read(&"b", 3), None);
+}
+
+/// Writes and deletions of a few keys, at versions that often collide.
+fn operations() -> impl Strategy<Value = Vec<(u8, u64, Option<u64>)>> {
+    prop::collection::vec((0..3u8, 0..20u64, prop::option::of(0..100u64)), 0..30)
+}
+
+proptest! {
+    #[test]
+    fn gc_keeps_what_snapshots_from_the_horizon_on_read(
+        operations in operations(),
+        horizon in 0..20u64,
+    ) {
+        let mut store = MvccStore::new();
+        for (key, version, value) in operations {
+            match value {
+                Some(value) => store.write(key, version, value),
+                None => store.delete(key, version),
+            }
+        }
+        let mut collected = store.clone();
+        let before = store.version_count();
+
+        let dropped = collected.gc(horizon);
+        prop_assert_eq!(collected.version_count(), before - dropped);
+        for key in 0..3 {
+            for version in horizon..20 {
+                prop_assert_eq!(collected.read(&key, version), store.read(&key, version));
+            }
+        }
+    }
+}
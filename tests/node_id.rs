@@ -0,0 +1,25 @@
+use distributed_system::node_id::NodeIds;
+
+#[test]
+fn interning_is_idempotent() {
+    let mut ids: NodeIds = ["n0", "n1", "n2"].into_iter().collect();
+    assert_eq!(ids.len(), 3);
+
+    let n1 = ids.intern("n1");
+    assert_eq!(ids.get("n1"), Some(n1));
+    assert_eq!(ids.name(n1), "n1");
+    assert_eq!(ids.len(), 3);
+}
+
+#[test]
+fn unknown_ids_are_added() {
+    let mut ids = NodeIds::new();
+    assert!(ids.is_empty());
+    assert_eq!(ids.get("c4"), None);
+
+    let c4 = ids.intern("c4");
+    let n0 = ids.intern("n0");
+    assert_ne!(c4, n0);
+    assert_eq!(ids.name(c4), "c4");
+    assert_eq!(ids.name(n0), "n0");
+}
@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+use distributed_system::outbound::{OutboundQueues, TokenBucket};
+
+#[test]
+fn bucket_allows_a_burst_then_the_rate() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(10.0, 3, start);
+    assert!((0..3).all(|_| bucket.try_take(start)));
+    assert!(!bucket.try_take(start));
+
+    assert!(!bucket.try_take(start + Duration::from_millis(50)));
+    assert!(bucket.try_take(start + Duration::from_millis(100)));
+    assert!(!bucket.try_take(start + Duration::from_millis(100)));
+
+    // Idle time refills no more than the burst.
+    let later = start + Duration::from_secs(10);
+    assert!((0..3).all(|_| bucket.try_take(later)));
+    assert!(!bucket.try_take(later));
+}
+
+#[test]
+fn queues_pace_each_destination_and_keep_its_order() {
+    let start = Instant::now();
+    let mut queues = OutboundQueues::new(10.0, 2);
+    let sent: Vec<_> = (0..4).filter_map(|i| queues.push("n1", i, start)).collect();
+    assert_eq!(sent, [0, 1]);
+    assert_eq!(queues.push("n2", 0, start), Some(0));
+    assert_eq!(queues.queued(&"n1"), 2);
+    assert_eq!(queues.queued(&"n2"), 0);
+    assert_eq!(queues.len(), 2);
+
+    assert!(queues.drain(start).is_empty());
+    assert_eq!(
+        queues.drain(start + Duration::from_millis(100)),
+        [("n1", 2)]
+    );
+    // Behind what's queued, even with a token to spare.
+    assert_eq!(
+        queues.push("n1", 4, start + Duration::from_millis(200)),
+        None
+    );
+    assert_eq!(
+        queues.drain(start + Duration::from_millis(300)),
+        [("n1", 3), ("n1", 4)]
+    );
+    assert!(queues.is_empty());
+}
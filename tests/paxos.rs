@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+use distributed_system::paxos::Paxos;
+
+/// Long enough for any round to time out.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Cluster {
+    nodes: BTreeMap<String, Paxos<u64>>,
+    isolated: HashSet<String>,
+    now: Instant,
+}
+
+impl Cluster {
+    fn new(size: usize) -> Self {
+        let node_ids: Vec<String> = (1..=size).map(|i| format!("n{i}")).collect();
+        let nodes = node_ids
+            .iter()
+            .map(|id| (id.clone(), Paxos::new(id, &node_ids)))
+            .collect();
+
+        Self {
+            nodes,
+            isolated: HashSet::new(),
+            now: Instant::now(),
+        }
+    }
+
+    fn node(&mut self, id: &str) -> &mut Paxos<u64> {
+        self.nodes.get_mut(id).unwrap()
+    }
+
+    fn propose(&mut self, id: &str, value: u64) {
+        let now = self.now;
+        self.node(id).propose(value, now);
+    }
+
+    /// Lets every node's round time out.
+    fn time_out(&mut self) {
+        self.now += TIMEOUT;
+        let now = self.now;
+        for node in self.nodes.values_mut() {
+            node.tick(now);
+        }
+        self.deliver();
+    }
+
+    /// Delivers messages until none are left, dropping those from or to isolated nodes.
+    fn deliver(&mut self) {
+        loop {
+            let messages: Vec<_> = self
+                .nodes
+                .values_mut()
+                .flat_map(|node| node.take_messages())
+                .collect();
+            if messages.is_empty() {
+                return;
+            }
+
+            for message in messages {
+                if self.isolated.contains(&message.src) || self.isolated.contains(&message.dest) {
+                    continue;
+                }
+                let now = self.now;
+                self.node(&message.dest.clone()).handle(message, now);
+            }
+        }
+    }
+
+    fn decided(&self, id: &str) -> Option<u64> {
+        self.nodes[id].decided().copied()
+    }
+}
+
+#[test]
+fn single_proposer_decides_its_value_everywhere() {
+    let mut cluster = Cluster::new(3);
+    cluster.propose("n1", 7);
+    cluster.deliver();
+
+    for id in ["n1", "n2", "n3"] {
+        assert_eq!(cluster.decided(id), Some(7));
+    }
+}
+
+#[test]
+fn minority_cannot_decide() {
+    let mut cluster = Cluster::new(3);
+    cluster.isolated.insert("n2".to_string());
+    cluster.isolated.insert("n3".to_string());
+    cluster.propose("n1", 1);
+    cluster.deliver();
+    cluster.time_out();
+    assert_eq!(cluster.decided("n1"), None);
+
+    cluster.isolated.clear();
+    cluster.time_out();
+    for id in ["n1", "n2", "n3"] {
+        assert_eq!(cluster.decided(id), Some(1));
+    }
+}
+
+#[test]
+fn competing_proposers_agree_on_one_value() {
+    let mut cluster = Cluster::new(5);
+    for (i, id) in ["n1", "n2", "n3"].into_iter().enumerate() {
+        cluster.propose(id, i as u64);
+    }
+    cluster.deliver();
+    for _ in 0..10 {
+        cluster.time_out();
+    }
+
+    let decided = cluster.decided("n1");
+    assert!(decided.is_some());
+    for id in ["n2", "n3", "n4", "n5"] {
+        assert_eq!(cluster.decided(id), decided);
+    }
+}
+
+#[test]
+fn later_proposer_adopts_an_accepted_value() {
+    let mut cluster = Cluster::new(3);
+    cluster.propose("n1", 1);
+    // n1 accepts its own value after the promises, but its accept requests are lost.
+    let prepares = cluster.node("n1").take_messages();
+    for message in prepares {
+        let now = cluster.now;
+        cluster.node(&message.dest.clone()).handle(message, now);
+    }
+    let promises: Vec<_> = ["n2", "n3"]
+        .into_iter()
+        .flat_map(|id| cluster.node(id).take_messages())
+        .collect();
+    for message in promises {
+        let now = cluster.now;
+        cluster.node("n1").handle(message, now);
+    }
+    cluster.node("n1").take_messages();
+
+    cluster.isolated.insert("n3".to_string());
+    cluster.propose("n2", 2);
+    cluster.deliver();
+
+    assert_eq!(cluster.decided("n2"), Some(1));
+    assert_eq!(cluster.decided("n1"), Some(1));
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use distributed_system::protocol::{self, Capabilities, Handshake, PROTOCOL_VERSION};
+use serde_json::json;
+
+fn capabilities(entries: &[(&str, u32)]) -> Capabilities {
+    Capabilities(
+        entries
+            .iter()
+            .map(|&(name, version)| (name.to_string(), version))
+            .collect::<BTreeMap<_, _>>(),
+    )
+}
+
+#[test]
+fn both_sides_agree_to_the_lower_version_of_what_they_share() {
+    let ours = capabilities(&[("compressed_gossip", 2), ("chunking", 1)]);
+    let theirs = capabilities(&[("compressed_gossip", 1), ("batched_bodies", 1)]);
+
+    assert_eq!(
+        ours.agree(&theirs),
+        capabilities(&[("compressed_gossip", 1)])
+    );
+    assert!(ours.agree(&Capabilities::default()).is_empty());
+}
+
+#[test]
+fn a_handshake_is_left_out_unless_negotiated() {
+    assert_eq!(json!(Handshake::default()), json!({}));
+    let handshake: Handshake = serde_json::from_value(json!({
+        "protocol_version": 1,
+        "capabilities": {"compressed_gossip": 1},
+    }))
+    .unwrap();
+    assert_eq!(handshake.protocol_version, Some(1));
+    assert_eq!(handshake.capabilities.version("compressed_gossip"), Some(1));
+}
+
+#[test]
+fn init_agrees_to_what_the_node_supports() {
+    protocol::support("compressed_gossip", 1);
+    assert_eq!(protocol::agreed("compressed_gossip"), None);
+
+    protocol::negotiate(&Handshake {
+        protocol_version: Some(PROTOCOL_VERSION + 1),
+        capabilities: capabilities(&[("compressed_gossip", 3), ("chunking", 1)]),
+    });
+    assert_eq!(
+        protocol::answer(),
+        Handshake {
+            protocol_version: Some(PROTOCOL_VERSION),
+            capabilities: capabilities(&[("compressed_gossip", 1)]),
+        }
+    );
+    assert_eq!(protocol::agreed("compressed_gossip"), Some(1));
+    assert_eq!(protocol::agreed("chunking"), None);
+
+    protocol::negotiate(&Handshake::default());
+    assert_eq!(protocol::answer(), Handshake::default());
+    assert_eq!(protocol::agreed("compressed_gossip"), None);
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+use distributed_system::raft::{Raft, Role, StateMachine};
+
+/// Appends every command to a list and returns its length.
+#[derive(Default)]
+struct History(Vec<u64>);
+
+impl StateMachine for History {
+    type Command = u64;
+    type Output = usize;
+    type Snapshot = Vec<u64>;
+
+    fn apply(&mut self, command: &u64) -> usize {
+        self.0.push(*command);
+        self.0.len()
+    }
+
+    fn snapshot(&self) -> Vec<u64> {
+        self.0.clone()
+    }
+
+    fn restore(&mut self, snapshot: Vec<u64>) {
+        self.0 = snapshot;
+    }
+}
+
+/// Long enough for any election timeout to expire.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Cluster {
+    nodes: BTreeMap<String, Raft<History>>,
+    isolated: HashSet<String>,
+    now: Instant,
+}
+
+impl Cluster {
+    fn new(size: usize) -> Self {
+        Self::with_snapshot_threshold(size, u64::MAX)
+    }
+
+    fn with_snapshot_threshold(size: usize, entries: u64) -> Self {
+        let now = Instant::now();
+        let node_ids: Vec<String> = (1..=size).map(|i| format!("n{i}")).collect();
+        let nodes = node_ids
+            .iter()
+            .map(|id| {
+                (
+                    id.clone(),
+                    Raft::new(id, &node_ids, History::default(), now)
+                        .with_snapshot_threshold(entries),
+                )
+            })
+            .collect();
+
+        Self {
+            nodes,
+            isolated: HashSet::new(),
+            now,
+        }
+    }
+
+    /// Starts a node that isn't a member yet and waits to be added with `reconfigure`.
+    fn join(&mut self, id: &str) {
+        let members: Vec<String> = self.nodes.keys().cloned().collect();
+        let node = Raft::new(id, &members, History::default(), self.now);
+        self.nodes.insert(id.to_string(), node);
+    }
+
+    fn node(&mut self, id: &str) -> &mut Raft<History> {
+        self.nodes.get_mut(id).unwrap()
+    }
+
+    /// Lets a single node's election timeout expire.
+    fn time_out(&mut self, id: &str) {
+        self.now += TIMEOUT;
+        let now = self.now;
+        self.node(id).tick(now);
+        self.deliver();
+    }
+
+    /// Delivers messages until none are left, dropping those from or to isolated nodes.
+    fn deliver(&mut self) {
+        loop {
+            let messages: Vec<_> = self
+                .nodes
+                .values_mut()
+                .flat_map(|node| node.take_messages())
+                .collect();
+            if messages.is_empty() {
+                return;
+            }
+
+            for message in messages {
+                if self.isolated.contains(&message.src) || self.isolated.contains(&message.dest) {
+                    continue;
+                }
+                let now = self.now;
+                self.node(&message.dest.clone()).handle(message, now);
+            }
+        }
+    }
+
+    fn history(&self, id: &str) -> &[u64] {
+        &self.nodes[id].state_machine().0
+    }
+}
+
+#[test]
+fn single_node_commits_immediately() {
+    let mut cluster = Cluster::new(1);
+    cluster.time_out("n1");
+
+    let proposal = cluster.node("n1").propose(7).unwrap();
+    let applied = cluster.node("n1").take_applied();
+
+    assert_eq!(applied.len(), 1);
+    assert_eq!(
+        (applied[0].index, applied[0].term),
+        (proposal.index, proposal.term)
+    );
+    assert_eq!(cluster.history("n1"), [7]);
+}
+
+#[test]
+fn leader_replicates_commands_to_every_follower() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+    assert_eq!(cluster.node("n1").role(), Role::Leader);
+    assert_eq!(cluster.node("n2").leader(), Some("n1"));
+    assert!(cluster.node("n2").propose(1).is_none());
+
+    for command in 1..=3 {
+        cluster.node("n1").propose(command).unwrap();
+        cluster.deliver();
+    }
+    // Followers learn the final commit index with the next heartbeat.
+    cluster.time_out("n1");
+
+    for id in ["n1", "n2", "n3"] {
+        assert_eq!(cluster.history(id), [1, 2, 3]);
+    }
+}
+
+#[test]
+fn uncommitted_entries_of_isolated_leader_are_replaced() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+
+    cluster.isolated.insert("n1".to_string());
+    let lost = cluster.node("n1").propose(1).unwrap();
+    cluster.deliver();
+
+    cluster.time_out("n2");
+    assert_eq!(cluster.node("n2").role(), Role::Leader);
+    cluster.node("n2").propose(2).unwrap();
+    cluster.deliver();
+
+    cluster.isolated.clear();
+    cluster.time_out("n2");
+    cluster.time_out("n2");
+
+    assert_eq!(cluster.node("n1").role(), Role::Follower);
+    let applied = cluster.node("n1").take_applied();
+    assert!(applied
+        .iter()
+        .all(|applied| (applied.index, applied.term) != (lost.index, lost.term)));
+    for id in ["n1", "n2", "n3"] {
+        assert_eq!(cluster.history(id), [2]);
+    }
+}
+
+#[test]
+fn lagging_follower_catches_up_from_snapshot() {
+    let mut cluster = Cluster::with_snapshot_threshold(3, 2);
+    cluster.time_out("n1");
+
+    cluster.isolated.insert("n3".to_string());
+    for command in 1..=5 {
+        cluster.node("n1").propose(command).unwrap();
+        cluster.deliver();
+    }
+    cluster.time_out("n1");
+    assert!(cluster.history("n3").is_empty());
+
+    cluster.isolated.clear();
+    cluster.time_out("n1");
+    cluster.time_out("n1");
+
+    assert_eq!(cluster.history("n3"), [1, 2, 3, 4, 5]);
+    assert_eq!(
+        cluster.node("n3").last_applied(),
+        cluster.node("n1").last_applied()
+    );
+}
+
+#[test]
+fn rejoining_node_does_not_disrupt_leader() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+    let term = cluster.node("n1").term();
+
+    cluster.isolated.insert("n3".to_string());
+    for _ in 0..3 {
+        cluster.time_out("n3");
+    }
+    assert_eq!(cluster.node("n3").role(), Role::PreCandidate);
+    assert_eq!(cluster.node("n3").term(), term);
+
+    cluster.isolated.clear();
+    cluster.time_out("n1");
+    cluster.node("n1").propose(1).unwrap();
+    cluster.deliver();
+
+    assert_eq!(cluster.node("n1").role(), Role::Leader);
+    assert_eq!(cluster.node("n3").leader(), Some("n1"));
+    for id in ["n1", "n2", "n3"] {
+        assert_eq!(cluster.node(id).term(), term);
+    }
+}
+
+#[test]
+fn followers_of_a_live_leader_ignore_candidates() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+    let term = cluster.node("n1").term();
+
+    // n3 misses a heartbeat and times out, while n2 still hears from the leader.
+    cluster.isolated.insert("n3".to_string());
+    cluster.time_out("n1");
+    cluster.isolated.clear();
+    let now = cluster.now;
+    cluster.node("n3").tick(now);
+    cluster.deliver();
+
+    assert_eq!(cluster.node("n1").role(), Role::Leader);
+    assert_eq!(cluster.node("n2").term(), term);
+}
+
+#[test]
+fn leadership_is_transferred_to_a_lagging_follower() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+
+    cluster.isolated.insert("n3".to_string());
+    for command in 1..=3 {
+        cluster.node("n1").propose(command).unwrap();
+        cluster.deliver();
+    }
+    cluster.isolated.clear();
+
+    let now = cluster.now;
+    assert!(cluster.node("n1").transfer_leadership("n3", now));
+    assert!(cluster.node("n1").propose(4).is_none());
+    cluster.deliver();
+
+    assert_eq!(cluster.node("n3").role(), Role::Leader);
+    assert_eq!(cluster.node("n1").role(), Role::Follower);
+    assert_eq!(cluster.node("n1").transferring_to(), None);
+
+    cluster.node("n3").propose(4).unwrap();
+    cluster.deliver();
+    cluster.time_out("n3");
+    for id in ["n1", "n2", "n3"] {
+        assert_eq!(cluster.history(id), [1, 2, 3, 4]);
+    }
+}
+
+fn node_ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+#[test]
+fn joining_node_becomes_a_voter_after_reconfiguration() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+    cluster.node("n1").propose(1).unwrap();
+    cluster.deliver();
+
+    cluster.join("n4");
+    cluster.time_out("n4");
+    assert_eq!(cluster.node("n4").role(), Role::Follower);
+
+    assert!(cluster
+        .node("n1")
+        .reconfigure(node_ids(&["n1", "n2", "n3", "n4"])));
+    assert!(!cluster.node("n1").reconfigure(node_ids(&["n1"])));
+    cluster.deliver();
+    cluster.time_out("n1");
+
+    for id in ["n1", "n2", "n3", "n4"] {
+        let config = cluster.node(id).configuration().clone();
+        assert!(!config.is_joint());
+        assert_eq!(config.voters.len(), 4);
+        assert_eq!(cluster.history(id), [1]);
+    }
+    assert!(cluster.node("n1").configuration_committed());
+}
+
+#[test]
+fn removed_leader_steps_down_and_the_rest_elect_a_new_one() {
+    let mut cluster = Cluster::new(3);
+    cluster.time_out("n1");
+
+    assert!(cluster.node("n1").reconfigure(node_ids(&["n2", "n3"])));
+    cluster.deliver();
+    assert_eq!(cluster.node("n1").role(), Role::Follower);
+
+    // n1 stepped down without telling the others, so they elect a leader once they time out.
+    cluster.time_out("n1");
+    assert_eq!(cluster.node("n1").role(), Role::Follower);
+    cluster.time_out("n2");
+    assert_eq!(cluster.node("n2").role(), Role::Leader);
+
+    cluster.node("n2").propose(1).unwrap();
+    cluster.deliver();
+    cluster.time_out("n2");
+    assert_eq!(cluster.history("n3"), [1]);
+    assert!(cluster.history("n1").is_empty());
+}
+
+#[test]
+fn read_index_needs_a_quorum_to_confirm_leadership() {
+    let mut cluster = Cluster::new(3);
+    assert_eq!(cluster.node("n1").read_index(), None);
+    cluster.time_out("n1");
+
+    let read = cluster.node("n1").read_index().unwrap();
+    assert!(cluster.node("n1").take_reads().is_empty());
+    cluster.deliver();
+    assert_eq!(cluster.node("n1").take_reads(), [read]);
+
+    cluster.isolated.insert("n1".to_string());
+    cluster.node("n1").read_index().unwrap();
+    cluster.deliver();
+    cluster.time_out("n2");
+    cluster.isolated.clear();
+    cluster.time_out("n2");
+
+    assert_eq!(cluster.node("n1").role(), Role::Follower);
+    assert!(cluster.node("n1").take_reads().is_empty());
+    assert!(cluster.history("n1").is_empty());
+}
@@ -0,0 +1,50 @@
+use std::collections::HashSet;
+
+use distributed_system::range_set::RangeSet;
+use proptest::prelude::*;
+
+#[test]
+fn consecutive_numbers_merge_into_one_range() {
+    let mut set = RangeSet::new();
+    assert!(set.insert(1));
+    assert!(set.insert(3));
+    assert_eq!(set.range_count(), 2);
+
+    assert!(set.insert(2));
+    assert!(!set.insert(2));
+    assert_eq!(set.range_count(), 1);
+    assert_eq!(set.len(), 3);
+    assert!(!set.contains(0) && set.contains(2) && !set.contains(4));
+}
+
+#[test]
+fn memory_grows_with_gaps_not_elements() {
+    let set: RangeSet = (0..100_000).rev().chain(200_000..300_000).collect();
+
+    assert_eq!(set.len(), 200_000);
+    assert_eq!(set.range_count(), 2);
+}
+
+#[test]
+fn largest_number_is_stored() {
+    let set: RangeSet = [u64::MAX, u64::MAX - 1].into_iter().collect();
+
+    assert!(set.contains(u64::MAX));
+    assert_eq!(set.range_count(), 1);
+}
+
+proptest! {
+    #[test]
+    fn behaves_like_a_hash_set(numbers in prop::collection::vec(0..200u64, 0..300)) {
+        let mut set = RangeSet::new();
+        let mut expected = HashSet::new();
+        for number in numbers {
+            prop_assert_eq!(set.insert(number), expected.insert(number));
+        }
+
+        prop_assert_eq!(set.len(), expected.len() as u64);
+        for number in 0..200 {
+            prop_assert_eq!(set.contains(number), expected.contains(&number));
+        }
+    }
+}
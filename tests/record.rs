@@ -0,0 +1,35 @@
+use distributed_system::record::{self, Recorder};
+
+#[test]
+fn lines_are_recorded_to_a_file_named_after_the_node() {
+    let dir =
+        std::env::temp_dir().join(format!("distributed-system-record-{}", std::process::id()));
+    let mut recorder = Recorder::create(&dir).unwrap();
+    let init = r#"{"src":"c1","dest":"n3","body":{"type":"init","msg_id":1,"node_id":"n3","node_ids":["n3"]}}"#;
+    let echo = r#"{"src":"c1","dest":"n3","body":{"type":"echo","msg_id":2,"echo":"a\nb"}}"#;
+    recorder.record(format!("{init}\n").as_bytes()).unwrap();
+    recorder.record(format!("{echo}\n").as_bytes()).unwrap();
+    recorder.record(b"not json").unwrap();
+
+    let entries = record::read(dir.join("n3.jsonl")).unwrap();
+    let lines: Vec<&str> = entries.iter().map(|entry| entry.line.as_str()).collect();
+    assert_eq!(lines, [init, echo, "not json"]);
+    assert!(entries
+        .windows(2)
+        .all(|pair| pair[0].at_us <= pair[1].at_us));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_recording_without_init_is_named_after_the_process() {
+    let dir = std::env::temp_dir().join(format!(
+        "distributed-system-record-pid-{}",
+        std::process::id()
+    ));
+    let recorder = Recorder::create(&dir).unwrap();
+    assert_eq!(
+        recorder.path(b"{}"),
+        dir.join(format!("pid-{}.jsonl", std::process::id()))
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,33 @@
+use distributed_system::reply_cache::ReplyCache;
+use distributed_system::Message;
+
+fn reply(dest: &str, in_reply_to: u64) -> Message<u64> {
+    Message {
+        src: "n1".to_string(),
+        dest: dest.to_string(),
+        body: in_reply_to,
+    }
+}
+
+#[test]
+fn retried_request_gets_the_original_reply() {
+    let mut cache = ReplyCache::new(10);
+    cache.insert("c1", 1, reply("c1", 1));
+
+    assert_eq!(cache.get("c1", 1).map(|reply| reply.body), Some(1));
+    assert!(cache.get("c1", 2).is_none());
+    assert!(cache.get("c2", 1).is_none());
+}
+
+#[test]
+fn oldest_replies_are_forgotten_when_full() {
+    let mut cache = ReplyCache::new(2);
+    for msg_id in 1..=3 {
+        cache.insert("c1", msg_id, reply("c1", msg_id));
+    }
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("c1", 1).is_none());
+    assert!(cache.get("c1", 2).is_some());
+    assert!(cache.get("c1", 3).is_some());
+}
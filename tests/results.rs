@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use distributed_system::results::{self, Edn, Summary};
+
+/// Trimmed from the results of a Maelstrom broadcast run.
+const RESULTS: &str = r#"{:perf {:latency-graph {:valid? true},
+        :rate-graph {:valid? true},
+        :valid? true},
+ :timeline {:valid? true},
+ :exceptions {:valid? true},
+ :stats {:valid? true,
+         :count 1993,
+         :ok-count 1993,
+         :by-f {:broadcast {:valid? true, :count 1002}}},
+ :availability {:valid? true, :ok-fraction 1.0},
+ :net {:all {:send-count 56102,
+             :recv-count 56102,
+             :msg-count 56102,
+             :msgs-per-op 28.149523},
+       :clients {:send-count 4086, :recv-count 4086, :msg-count 4086},
+       :servers {:send-count 52016,
+                 :recv-count 52016,
+                 :msg-count 52016,
+                 :msgs-per-op 26.099348},
+       :valid? true},
+ :workload {:worst-stale ({:element 47, :outcome :stable, :stable-latency 481}),
+            :duplicated-count 0,
+            :valid? false,
+            :lost-count 1,
+            :lost #{907},
+            :stable-latencies {0 0, 0.5 252, 0.95 466, 0.99 494, 1 540},
+            :attempt-count 1002},
+ :valid? false}
+"#;
+
+#[test]
+fn edn_values_are_parsed() {
+    let value = results::parse(
+        r#"{:a [1 -2 3.5 1/4 "x\"y"] :b #{nil true} ; a comment
+            :c (:k sym #_ignored) :d #inst "2024-01-01" :e ##Inf :f \newline}"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        value.get("a"),
+        Some(&Edn::Seq(vec![
+            Edn::Int(1),
+            Edn::Int(-2),
+            Edn::Float(3.5),
+            Edn::Float(0.25),
+            Edn::String("x\"y".to_string()),
+        ]))
+    );
+    assert_eq!(
+        value.get("b"),
+        Some(&Edn::Set(vec![Edn::Nil, Edn::Bool(true)]))
+    );
+    assert_eq!(
+        value.get("c"),
+        Some(&Edn::Seq(vec![
+            Edn::Keyword("k".to_string()),
+            Edn::Symbol("sym".to_string())
+        ]))
+    );
+    assert_eq!(
+        value.get("d"),
+        Some(&Edn::Tagged(
+            "inst".to_string(),
+            Box::new(Edn::String("2024-01-01".to_string()))
+        ))
+    );
+    assert_eq!(value.get("e"), Some(&Edn::Float(f64::INFINITY)));
+    assert_eq!(value.get("f"), Some(&Edn::Char("newline".to_string())));
+
+    assert!(results::parse("{:a 1").is_err());
+    assert!(results::parse("{:a}").is_err());
+}
+
+#[test]
+fn results_are_summarized_per_check() {
+    let summary = Summary::of(&results::parse(RESULTS).unwrap());
+
+    assert_eq!(summary.valid, Some(false));
+    let checks: Vec<(&str, Option<bool>)> = summary
+        .checks
+        .iter()
+        .map(|(check, valid)| (check.as_str(), *valid))
+        .collect();
+    assert_eq!(
+        checks,
+        [
+            ("perf", Some(true)),
+            ("timeline", Some(true)),
+            ("exceptions", Some(true)),
+            ("stats", Some(true)),
+            ("availability", Some(true)),
+            ("net", Some(true)),
+            ("workload", Some(false)),
+        ]
+    );
+    assert_eq!(summary.server_msgs_per_op, Some(26.099348));
+    assert_eq!(summary.all_msgs_per_op, Some(28.149523));
+    assert_eq!(summary.stable_latencies.len(), 5);
+    assert_eq!(
+        summary.stable_latencies[2],
+        (0.95, Duration::from_millis(466))
+    );
+}
+
+#[test]
+fn the_results_in_a_jepsen_log_are_found() {
+    let dir =
+        std::env::temp_dir().join(format!("distributed-system-results-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = format!(
+        "INFO [2024-01-01 00:00:00,000] jepsen test runner - jepsen.core {RESULTS}\n\
+         Analysis invalid! (ﾉಥ益ಥ）ﾉ ┻━┻\n"
+    );
+    std::fs::write(dir.join("jepsen.log"), log).unwrap();
+
+    let summary = Summary::of(&results::read(&dir).unwrap());
+    assert_eq!(summary.valid, Some(false));
+    assert_eq!(summary.checks.len(), 7);
+
+    std::fs::write(dir.join("results.edn"), "{:valid? true}").unwrap();
+    let summary = Summary::of(&results::read(&dir).unwrap());
+    assert_eq!(summary.valid, Some(true));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn latencies_are_measured_from_invocation_to_completion() {
+    let history = results::parse_all(
+        "{:type :invoke, :f :broadcast, :value 1, :time 1000000, :process 0, :index 0}\n\
+         {:type :invoke, :f :read, :value nil, :time 2000000, :process 1, :index 1}\n\
+         {:type :ok, :f :broadcast, :value 1, :time 4000000, :process 0, :index 2}\n\
+         {:type :fail, :f :read, :value nil, :time 5000000, :process 1, :index 3}\n\
+         {:type :invoke, :f :read, :value nil, :time 6000000, :process 1, :index 4}\n\
+         {:type :ok, :f :read, :value [1], :time 8000000, :process 1, :index 5}\n",
+    )
+    .unwrap();
+
+    let latencies = results::latencies(&history);
+    assert_eq!(latencies["broadcast"], [Duration::from_millis(3)]);
+    assert_eq!(latencies["read"], [Duration::from_millis(2)]);
+    assert_eq!(
+        results::quantile(&latencies["read"], 0.5),
+        Duration::from_millis(2)
+    );
+    assert_eq!(results::quantile(&[], 0.5), Duration::ZERO);
+}
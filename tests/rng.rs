@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+
+use distributed_system::rng::Rng;
+
+#[test]
+fn same_seed_gives_same_sequence() {
+    let mut a = Rng::from_seed(7);
+    let mut b = Rng::from_seed(7);
+    let mut c = Rng::from_seed(8);
+
+    let a: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+    let b: Vec<u64> = (0..10).map(|_| b.next_u64()).collect();
+    let c: Vec<u64> = (0..10).map(|_| c.next_u64()).collect();
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn nodes_sharing_a_seed_differ() {
+    let mut n1 = Rng::for_node(Some(7), "n1");
+    let mut n1_again = Rng::for_node(Some(7), "n1");
+    let mut n2 = Rng::for_node(Some(7), "n2");
+
+    let first = n1.next_u64();
+    assert_eq!(first, n1_again.next_u64());
+    assert_ne!(first, n2.next_u64());
+}
+
+#[test]
+fn below_stays_in_bounds_and_covers_them() {
+    let mut rng = Rng::from_seed(1);
+    let values: HashSet<usize> = (0..1000).map(|_| rng.below(10)).collect();
+
+    assert_eq!(values, (0..10).collect());
+}
+
+#[test]
+fn sample_picks_distinct_elements() {
+    let mut rng = Rng::from_seed(3);
+    let items: Vec<u32> = (0..20).collect();
+
+    for _ in 0..100 {
+        let sample = rng.sample(&items, 5);
+        assert_eq!(sample.len(), 5);
+        assert_eq!(sample.iter().collect::<HashSet<_>>().len(), 5);
+    }
+    assert_eq!(rng.sample(&items, 50).len(), 20);
+
+    let picked: HashSet<u32> = (0..100).flat_map(|_| rng.sample(&items, 1)).collect();
+    assert!(picked.len() > 15, "only {} distinct picks", picked.len());
+}
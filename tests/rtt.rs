@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use distributed_system::rpc::PendingRequests;
+use distributed_system::rtt::RttEstimator;
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+fn estimator() -> RttEstimator {
+    RttEstimator::new(ms(500), ms(50), ms(5000))
+}
+
+#[test]
+fn first_sample_sets_the_timeout() {
+    let mut rtt = estimator();
+    assert_eq!(rtt.rto(), ms(500));
+    assert_eq!(rtt.srtt(), None);
+
+    rtt.sample(ms(200));
+    assert_eq!(rtt.srtt(), Some(ms(200)));
+    // srtt + 4 * rttvar, with rttvar starting at half the sample.
+    assert_eq!(rtt.rto(), ms(600));
+}
+
+#[test]
+fn steady_round_trips_tighten_the_timeout() {
+    let mut rtt = estimator();
+    for _ in 0..50 {
+        rtt.sample(ms(200));
+    }
+
+    assert_eq!(rtt.srtt(), Some(ms(200)));
+    assert!(rtt.rto() < ms(210), "rto is {:?}", rtt.rto());
+}
+
+#[test]
+fn jitter_widens_the_timeout() {
+    let mut steady = estimator();
+    let mut jittery = estimator();
+    for i in 0..50 {
+        steady.sample(ms(200));
+        jittery.sample(if i % 2 == 0 { ms(100) } else { ms(300) });
+    }
+
+    assert!(jittery.rto() > steady.rto() + ms(200));
+}
+
+#[test]
+fn timeouts_back_off_within_bounds() {
+    let mut rtt = estimator();
+    rtt.sample(ms(1));
+    assert_eq!(rtt.rto(), ms(50));
+
+    rtt.back_off();
+    assert_eq!(rtt.rto(), ms(100));
+    for _ in 0..10 {
+        rtt.back_off();
+    }
+    assert_eq!(rtt.rto(), ms(5000));
+
+    rtt.sample(ms(1));
+    assert!(rtt.rto() < ms(100));
+}
+
+#[test]
+fn pending_requests_expire_with_their_own_timeouts() {
+    let mut pending = PendingRequests::new();
+    pending.insert(1, ms(0));
+    pending.insert(2, ms(60_000));
+
+    let expired = pending.expire_with(|timeout| *timeout);
+    assert_eq!(expired, vec![(1, ms(0))]);
+
+    let (timeout, waited) = pending.complete_timed(2).unwrap();
+    assert_eq!(timeout, ms(60_000));
+    assert!(waited < ms(60_000));
+    assert!(pending.is_empty());
+}
@@ -0,0 +1,195 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use distributed_system::event_queue::{self, Priority};
+use distributed_system::runtime::{self, Lifecycle, MalformedInput, Output, Timer};
+use distributed_system::{ErrorBody, Message};
+
+#[derive(Default)]
+struct Recorder {
+    node_id: Option<String>,
+    lines: Vec<String>,
+    shut_down: bool,
+}
+
+impl Lifecycle for Recorder {
+    fn on_init(&mut self, node_id: &str) {
+        self.node_id = Some(node_id.to_string());
+    }
+
+    fn on_shutdown(&mut self, output: &mut dyn Write) -> Result<(), anyhow::Error> {
+        self.shut_down = true;
+        writeln!(output, "bye")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn init_lines_are_recognized() {
+    let init = r#"{"src":"c0","dest":"n3","body":{"type":"init","msg_id":1,"node_id":"n3","node_ids":["n3"]}}"#;
+    assert_eq!(runtime::init_node_id(init), Some("n3".to_string()));
+
+    let echo = r#"{"src":"c1","dest":"n3","body":{"type":"echo","msg_id":2,"echo":"init"}}"#;
+    assert_eq!(runtime::init_node_id(echo), None);
+}
+
+#[test]
+fn only_messages_from_nodes_have_low_priority() {
+    let from = |src: &str| format!(r#"{{"src":"{src}","dest":"n0","body":{{"type":"read"}}}}"#);
+    assert_eq!(runtime::priority_of(from("n12")), Priority::Low);
+    assert_eq!(runtime::priority_of(from("c3")), Priority::High);
+    assert_eq!(runtime::priority_of(from("lin-kv")), Priority::High);
+    assert_eq!(runtime::priority_of(from("n")), Priority::High);
+    assert_eq!(runtime::priority_of("not json"), Priority::High);
+}
+
+#[test]
+fn lines_are_handled_before_shutdown() {
+    let input = concat!(
+        r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#,
+        "\n",
+        r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2,"echo":"hi"}}"#,
+        "\n",
+    );
+    let mut recorder = Recorder::default();
+    let mut output = Vec::new();
+    runtime::run_lines(
+        &mut recorder,
+        input.as_bytes(),
+        &mut output,
+        |node, line, _| {
+            assert!(!node.shut_down);
+            node.lines.push(String::from_utf8(line.to_vec())?);
+            Ok(())
+        },
+    )
+    .unwrap();
+
+    assert_eq!(recorder.node_id.as_deref(), Some("n1"));
+    assert_eq!(recorder.lines.len(), 2);
+    assert!(recorder.shut_down);
+    assert_eq!(output, b"bye\n");
+}
+
+#[test]
+fn malformed_lines_are_skipped_only_when_asked() {
+    let parse = || {
+        Message::<ErrorBody>::parse(b"{\"src\": 1}")
+            .context("Failed to deserialize provided input to STDIN.")
+            .map(|_| ())
+    };
+    assert!(runtime::is_malformed(&parse().unwrap_err()));
+    assert!(MalformedInput::Abort.check(parse()).is_err());
+    assert!(MalformedInput::Skip.check(parse()).unwrap().is_none());
+
+    let write: Result<(), _> = Err(anyhow::anyhow!("Failed to write to STDOUT."));
+    assert!(MalformedInput::Skip.check(write).is_err());
+    assert_eq!(MalformedInput::Skip.check(Ok(1)).unwrap(), Some(1));
+    assert_eq!(
+        "skip".parse::<MalformedInput>().unwrap(),
+        MalformedInput::Skip
+    );
+}
+
+#[test]
+fn timer_ticks_until_dropped() {
+    let (sender, receiver) = event_queue::channel(1);
+    let timer = Timer::start(Duration::from_millis(5), sender, || ());
+    for _ in 0..3 {
+        receiver.recv().unwrap();
+    }
+
+    drop(timer);
+    while receiver.try_recv().is_ok() {}
+    assert!(receiver.recv().is_none(), "the thread should have exited");
+}
+
+#[test]
+fn timer_skips_ticks_while_the_queue_is_full() {
+    let (sender, receiver) = event_queue::channel(2);
+    let timer = Timer::start(Duration::from_millis(1), sender, || ());
+    std::thread::sleep(Duration::from_millis(50));
+    drop(timer);
+
+    assert!(receiver.try_recv().is_ok());
+    assert!(receiver.try_recv().is_ok());
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn timer_stops_without_waiting_for_its_interval() {
+    let (sender, _receiver) = event_queue::channel::<()>(1);
+    let timer = Timer::start(Duration::from_secs(60), sender, || ());
+    let start = Instant::now();
+    drop(timer);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+/// A writer that records what reaches it, and how often it's flushed.
+#[derive(Clone, Default)]
+struct Sink {
+    written: Arc<Mutex<Vec<u8>>>,
+    flushes: Arc<Mutex<usize>>,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        *self.flushes.lock().unwrap() += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn output_is_written_in_batches() {
+    let sink = Sink::default();
+    let mut output = Output::spawn(sink.clone());
+    writeln!(output, "one").unwrap();
+    writeln!(output, "two").unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(sink.written.lock().unwrap().is_empty());
+
+    output.flush().unwrap();
+    writeln!(output, "three").unwrap();
+    output.finish().unwrap();
+    assert_eq!(*sink.written.lock().unwrap(), b"one\ntwo\nthree\n");
+    let flushes = *sink.flushes.lock().unwrap();
+    assert!((1..=2).contains(&flushes), "{flushes} flushes");
+}
+
+#[test]
+fn events_are_flushed_once_per_batch() {
+    let (sender, receiver) = runtime::event_channel();
+    for event in 0..3 {
+        sender
+            .send(runtime::Input::Event(event), Priority::Low)
+            .ok();
+    }
+    sender.send(runtime::Input::Eof, Priority::Low).ok();
+    let reader = std::thread::spawn(|| Ok(()));
+
+    let sink = Sink::default();
+    let mut output = Output::spawn(sink.clone());
+    let mut recorder = Recorder::default();
+    runtime::run_events(
+        &mut recorder,
+        receiver,
+        reader,
+        &mut output,
+        |_, event, output| {
+            writeln!(output, "{event}")?;
+            Ok(())
+        },
+    )
+    .unwrap();
+    output.finish().unwrap();
+
+    assert_eq!(*sink.written.lock().unwrap(), b"0\n1\n2\nbye\n");
+    assert_eq!(*sink.flushes.lock().unwrap(), 1);
+}
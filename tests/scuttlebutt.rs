@@ -0,0 +1,26 @@
+use distributed_system::scuttlebutt::Digest;
+
+#[test]
+fn digests_tell_which_entries_a_node_lacks() {
+    let ours: Digest = [("n0", 3), ("n1", 5), ("n2", 1)].into_iter().collect();
+    let theirs: Digest = [("n0", 3), ("n1", 2), ("n3", 4)].into_iter().collect();
+
+    let missing: Vec<&str> = theirs.missing_from(&ours).collect();
+    assert_eq!(missing, ["n1", "n2"]);
+    let missing: Vec<&str> = ours.missing_from(&theirs).collect();
+    assert_eq!(missing, ["n3"]);
+    assert_eq!(ours.missing_from(&ours).count(), 0);
+}
+
+#[test]
+fn versions_only_grow() {
+    let mut digest = Digest::new();
+    assert_eq!(digest.get("n0"), 0);
+    digest.observe("n0", 4);
+    digest.observe("n0", 2);
+    assert_eq!(digest.get("n0"), 4);
+    assert_eq!(
+        serde_json::to_value(&digest).unwrap(),
+        serde_json::json!({"n0": 4})
+    );
+}
@@ -0,0 +1,236 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use distributed_system::clock::Clock;
+use distributed_system::sim::{Delivery, Network, NetworkConfig, Schedule, SimNode, Step};
+use serde_json::json;
+
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+#[allow(dead_code)]
+#[path = "../src/bin/g_counter.rs"]
+mod g_counter;
+#[allow(dead_code)]
+#[path = "../src/bin/pn_counter.rs"]
+mod pn_counter;
+
+fn network(
+    config: NetworkConfig,
+    nodes: usize,
+    node: impl Fn(Arc<dyn Clock>) -> Box<dyn SimNode>,
+) -> Network {
+    let mut network = Network::new(config);
+    for i in 0..nodes {
+        network.add_node(format!("n{i}"), node(network.clock()));
+    }
+    network.init().unwrap();
+    network
+}
+
+fn g_counter_node(clock: Arc<dyn Clock>) -> Box<dyn SimNode> {
+    let cli = g_counter::Cli::parse_from(["g-counter", "--sync-interval-ms=10"]);
+    g_counter::simulated(cli, clock)
+}
+
+fn broadcast_node(seed: u64) -> impl Fn(Arc<dyn Clock>) -> Box<dyn SimNode> {
+    move |clock| {
+        let seed = format!("--seed={seed}");
+        let cli = broadcast::Cli::parse_from(["broadcast", "--gossip-interval-ms=10", &seed]);
+        broadcast::simulated(cli, clock).unwrap()
+    }
+}
+
+/// Five broadcast nodes in a ring, which got 20 messages over a lossy network.
+fn lossy_broadcast(seed: u64) -> Network {
+    let config = NetworkConfig {
+        latency: Duration::from_millis(2),
+        reorder_rate: 0.3,
+        drop_rate: 0.2,
+        seed,
+    };
+    let mut network = network(config, 5, broadcast_node(seed));
+    let node_ids = network.node_ids();
+    let topology: serde_json::Map<_, _> = node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let next = &node_ids[(i + 1) % node_ids.len()];
+            let previous = &node_ids[(i + node_ids.len() - 1) % node_ids.len()];
+            (id.clone(), json!([next, previous]))
+        })
+        .collect();
+    for id in &node_ids {
+        let reply = network
+            .request(id, json!({"type": "topology", "topology": topology}))
+            .unwrap();
+        assert_eq!(reply["type"], "topology_ok");
+    }
+
+    for message in 0..20 {
+        let node = &node_ids[message % node_ids.len()];
+        let reply = network
+            .request(node, json!({"type": "broadcast", "message": message}))
+            .unwrap();
+        assert_eq!(reply["type"], "broadcast_ok");
+    }
+    network
+}
+
+#[test]
+fn broadcasts_converge_despite_loss_and_reordering() {
+    let mut network = lossy_broadcast(7);
+    network.run_for(Duration::from_millis(500)).unwrap();
+
+    assert!(network.dropped() > 0);
+    for id in network.node_ids() {
+        let reply = network.request(&id, json!({"type": "read"})).unwrap();
+        let mut messages: Vec<u64> = serde_json::from_value(reply["messages"].clone()).unwrap();
+        messages.sort();
+        assert_eq!(messages, (0..20).collect::<Vec<_>>(), "{id}");
+    }
+}
+
+#[test]
+fn a_seed_replays_the_same_run() {
+    let trace = |seed| -> Vec<Delivery> {
+        let mut network = lossy_broadcast(seed);
+        network.run_for(Duration::from_millis(200)).unwrap();
+        network.trace().to_vec()
+    };
+
+    let first = trace(11);
+    assert!(first.last().unwrap().at >= Duration::from_millis(200));
+    assert_eq!(first, trace(11));
+    assert_ne!(first, trace(12));
+}
+
+#[test]
+fn counters_add_up_on_every_node() {
+    let config = NetworkConfig {
+        latency: Duration::from_millis(2),
+        reorder_rate: 0.5,
+        seed: 3,
+        ..NetworkConfig::default()
+    };
+    let mut network = network(config, 3, g_counter_node);
+    let node_ids = network.node_ids();
+
+    for delta in 1..=10 {
+        let node = &node_ids[delta % node_ids.len()];
+        let reply = network
+            .request(node, json!({"type": "add", "delta": delta}))
+            .unwrap();
+        assert_eq!(reply["type"], "add_ok");
+    }
+    network.run_for(Duration::from_millis(100)).unwrap();
+
+    for id in &node_ids {
+        let reply = network.request(id, json!({"type": "read"})).unwrap();
+        assert_eq!(reply["value"], 55, "{id}");
+    }
+}
+
+/// Adds `deltas` over the nodes of a lossy network, and returns what each node reads once
+/// they had time to reconcile.
+fn reads_after_adds(
+    nodes: usize,
+    deltas: &[i64],
+    node: impl Fn(Arc<dyn Clock>) -> Box<dyn SimNode>,
+) -> Vec<i64> {
+    let config = NetworkConfig {
+        latency: Duration::from_millis(2),
+        drop_rate: 0.2,
+        seed: 7,
+        ..NetworkConfig::default()
+    };
+    let mut network = network(config, nodes, node);
+    let node_ids = network.node_ids();
+    for (i, delta) in deltas.iter().enumerate() {
+        let node = &node_ids[i % node_ids.len()];
+        let reply = network
+            .request(node, json!({"type": "add", "delta": delta}))
+            .unwrap();
+        assert_eq!(reply["type"], "add_ok");
+    }
+    network.run_for(Duration::from_secs(2)).unwrap();
+
+    node_ids
+        .iter()
+        .map(|id| {
+            let reply = network.request(id, json!({"type": "read"})).unwrap();
+            reply["value"].as_i64().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn counters_converge_over_scuttlebutt_despite_lost_messages() {
+    let deltas: Vec<i64> = (1..=20).collect();
+    let reads = reads_after_adds(7, &deltas, |clock| {
+        let args = ["g-counter", "--scuttlebutt", "--sync-interval-ms=10"];
+        g_counter::simulated(g_counter::Cli::parse_from(args), clock)
+    });
+    assert_eq!(reads, [210; 7]);
+
+    let deltas: Vec<i64> = (1..=20)
+        .map(|delta| if delta % 4 == 0 { -delta } else { delta })
+        .collect();
+    let reads = reads_after_adds(7, &deltas, |clock| {
+        let args = ["pn-counter", "--scuttlebutt", "--sync-interval-ms=10"];
+        pn_counter::simulated(pn_counter::Cli::parse_from(args), clock)
+    });
+    assert_eq!(reads, [90; 7]);
+}
+
+#[test]
+fn counters_converge_despite_lost_syncs() {
+    let deltas: Vec<i64> = (1..=20)
+        .map(|delta| if delta % 4 == 0 { -delta } else { delta })
+        .collect();
+    let reads = reads_after_adds(3, &deltas, |clock| {
+        let args = ["pn-counter", "--sync-interval-ms=10", "--full-sync-ms=100"];
+        pn_counter::simulated(pn_counter::Cli::parse_from(args), clock)
+    });
+    assert_eq!(reads, [90; 3]);
+}
+
+#[test]
+fn failing_schedules_shrink_to_the_steps_that_matter() {
+    // Fails once `n0` reads more than 10, which takes a few of the adds below.
+    let fails = |schedule: &Schedule| {
+        let config = NetworkConfig {
+            drop_rate: 0.1,
+            seed: schedule.seed,
+            ..NetworkConfig::default()
+        };
+        let mut network = network(config, 3, g_counter_node);
+        network.play(&schedule.steps).unwrap();
+        network.heal();
+        let reply = network.request("n0", json!({"type": "read"})).unwrap();
+        reply["value"].as_u64().unwrap() > 10
+    };
+    let mut steps = Vec::new();
+    for delta in 1..=8 {
+        steps.push(Step::Request {
+            node: format!("n{}", delta % 3),
+            body: json!({"type": "add", "delta": delta}),
+        });
+        steps.push(Step::Wait(Duration::from_millis(20)));
+        if delta == 4 {
+            steps.push(Step::Partition(vec![vec!["n0".into()]]));
+        }
+    }
+    let schedule = Schedule { seed: 5, steps };
+    assert!(fails(&schedule));
+
+    let shrunk = schedule.shrink(fails);
+    assert!(fails(&shrunk));
+    assert!(shrunk.steps.len() < schedule.steps.len());
+    for i in 0..shrunk.steps.len() {
+        let mut smaller = shrunk.clone();
+        smaller.steps.remove(i);
+        assert!(!fails(&smaller), "{:?} is not needed", shrunk.steps[i]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use distributed_system::topology::Topology;
+
+fn node_ids(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("n{i}")).collect()
+}
+
+fn overlay(topology: Topology, count: usize) -> HashMap<String, Vec<String>> {
+    let node_ids = node_ids(count);
+    node_ids
+        .iter()
+        .map(|id| (id.clone(), topology.neighbours(id, &node_ids).unwrap()))
+        .collect()
+}
+
+/// The greatest number of hops from `start` to any node, or `None` if some node can't be
+/// reached.
+fn eccentricity(overlay: &HashMap<String, Vec<String>>, start: &str) -> Option<usize> {
+    let mut hops = HashMap::from([(start.to_string(), 0)]);
+    let mut queue = VecDeque::from([start.to_string()]);
+    while let Some(node) = queue.pop_front() {
+        for neighbour in &overlay[&node] {
+            if !hops.contains_key(neighbour) {
+                hops.insert(neighbour.clone(), hops[&node] + 1);
+                queue.push_back(neighbour.clone());
+            }
+        }
+    }
+    (hops.len() == overlay.len()).then(|| hops.into_values().max().unwrap_or(0))
+}
+
+const TOPOLOGIES: [Topology; 6] = [
+    Topology::Tree { fanout: 1 },
+    Topology::Tree { fanout: 4 },
+    Topology::Ring,
+    Topology::Grid,
+    Topology::HubSpoke { hubs: 1 },
+    Topology::HubSpoke { hubs: 3 },
+];
+
+#[test]
+fn overlays_are_connected_and_symmetric() {
+    for topology in TOPOLOGIES {
+        for count in [1, 2, 5, 25] {
+            let overlay = overlay(topology, count);
+            assert!(
+                eccentricity(&overlay, "n0").is_some(),
+                "{topology:?} with {count} nodes"
+            );
+
+            for (node, neighbours) in &overlay {
+                let unique: HashSet<_> = neighbours.iter().collect();
+                assert_eq!(unique.len(), neighbours.len());
+                assert!(!neighbours.contains(node));
+                for neighbour in neighbours {
+                    assert!(overlay[neighbour].contains(node), "{topology:?} {node}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn wider_trees_are_shallower() {
+    let narrow = overlay(Topology::Tree { fanout: 2 }, 25);
+    let wide = overlay(Topology::Tree { fanout: 8 }, 25);
+    assert!(eccentricity(&wide, "n0") < eccentricity(&narrow, "n0"));
+    assert!(wide["n0"].len() == 8);
+}
+
+#[test]
+fn star_links_every_node_to_the_hub() {
+    let star = overlay(Topology::HubSpoke { hubs: 1 }, 25);
+    assert_eq!(star["n0"].len(), 24);
+    assert_eq!(eccentricity(&star, "n0"), Some(1));
+}
+
+#[test]
+fn maelstrom_topology_is_not_overridden() {
+    assert_eq!(Topology::Maelstrom.neighbours("n0", &node_ids(3)), None);
+}
+
+#[test]
+fn topologies_are_parsed() {
+    assert_eq!(
+        "tree:4".parse::<Topology>().unwrap(),
+        Topology::Tree { fanout: 4 }
+    );
+    assert_eq!(
+        "star".parse::<Topology>().unwrap(),
+        Topology::HubSpoke { hubs: 1 }
+    );
+    assert_eq!(
+        "hubs:5".parse::<Topology>().unwrap(),
+        Topology::HubSpoke { hubs: 5 }
+    );
+    assert!("tree".parse::<Topology>().is_err());
+    assert!("tree:0".parse::<Topology>().is_err());
+    assert!("mesh".parse::<Topology>().is_err());
+}
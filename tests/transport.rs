@@ -0,0 +1,131 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use distributed_system::transport::Peers;
+use serde_json::{json, Value};
+
+/// Kills the node when the test ends, whichever way.
+struct Node(Child);
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn start(binary: &str, listen: &str, peers: &str, codec: &str) -> Node {
+    Node(
+        Command::new(binary)
+            .args(["--listen", listen, "--peers", peers, "--codec", codec])
+            .env("LOG_LEVEL", "error")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    )
+}
+
+/// A client connected to one node.
+struct Client {
+    id: String,
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    next_msg_id: u64,
+}
+
+impl Client {
+    /// Connects to `address`, waiting for the node to listen.
+    fn connect(id: &str, address: &str) -> Self {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(20))
+                }
+                Err(error) => panic!("Failed to connect to {address}: {error}"),
+            }
+        };
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Self {
+            id: id.to_string(),
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+            next_msg_id: 0,
+        }
+    }
+
+    fn request(&mut self, node: &str, mut body: Value) -> Value {
+        self.next_msg_id += 1;
+        body["msg_id"] = json!(self.next_msg_id);
+        let message = json!({"src": self.id, "dest": node, "body": body});
+        writeln!(self.stream, "{message}").unwrap();
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let reply: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(reply["body"]["in_reply_to"], self.next_msg_id);
+        reply["body"].clone()
+    }
+}
+
+/// Starts two broadcast nodes that ask each other for `codec`, and checks that a message
+/// broadcast to one reaches the other.
+fn gossip(codec: &str) {
+    let addresses = [free_address(), free_address()];
+    let peers = format!("n0={},n1={}", addresses[0], addresses[1]);
+    let binary = env!("CARGO_BIN_EXE_broadcast");
+    let _nodes: Vec<Node> = addresses
+        .iter()
+        .map(|address| start(binary, address, &peers, codec))
+        .collect();
+
+    let mut clients: Vec<Client> = addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| Client::connect(&format!("c{i}"), address))
+        .collect();
+    for (i, client) in clients.iter_mut().enumerate() {
+        let node = format!("n{i}");
+        let init = json!({"type": "init", "node_id": node, "node_ids": ["n0", "n1"]});
+        assert_eq!(client.request(&node, init)["type"], "init_ok");
+        let topology = json!({"type": "topology", "topology": {"n0": ["n1"], "n1": ["n0"]}});
+        assert_eq!(client.request(&node, topology)["type"], "topology_ok");
+    }
+
+    let reply = clients[0].request("n0", json!({"type": "broadcast", "message": 42}));
+    assert_eq!(reply["type"], "broadcast_ok");
+    let converged = (0..100).any(|_| {
+        std::thread::sleep(Duration::from_millis(50));
+        clients[1].request("n1", json!({"type": "read"}))["messages"] == json!([42])
+    });
+    assert!(converged, "n1 never got the message from n0");
+}
+
+#[test]
+fn broadcast_nodes_gossip_over_tcp() {
+    gossip("json");
+}
+
+#[test]
+fn broadcast_nodes_gossip_in_msgpack_while_clients_use_json() {
+    gossip("msgpack");
+}
+
+#[test]
+fn peers_are_parsed_by_id() {
+    let Peers(peers) = "n0=127.0.0.1:7000, n1=host:7001".parse().unwrap();
+    assert_eq!(peers.len(), 2);
+    assert_eq!(peers["n0"], "127.0.0.1:7000");
+    assert_eq!(peers["n1"], "host:7001");
+    assert!("n0".parse::<Peers>().is_err());
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use distributed_system::clock::VirtualClock;
+use distributed_system::txn::{
+    AwaitingDecisions, Coordinator, Isolation, MicroOp, Participant, Store,
+};
+
+fn read(key: u64) -> MicroOp {
+    MicroOp::Read { key, value: None }
+}
+
+fn write(key: u64, value: u64) -> MicroOp {
+    MicroOp::Write { key, value }
+}
+
+#[test]
+fn read_committed_reads_own_buffered_writes() {
+    let mut store = Store::with_isolation("n1", Isolation::ReadCommitted);
+
+    let (txn, _) = store.apply(&[write(1, 5), read(1), read(2)]);
+
+    assert_eq!(
+        txn,
+        vec![
+            write(1, 5),
+            MicroOp::Read {
+                key: 1,
+                value: Some(5)
+            },
+            MicroOp::Read {
+                key: 2,
+                value: None
+            },
+        ]
+    );
+}
+
+#[test]
+fn read_committed_never_replicates_intermediate_writes() {
+    let mut n1 = Store::with_isolation("n1", Isolation::ReadCommitted);
+    let mut n2 = Store::with_isolation("n2", Isolation::ReadCommitted);
+
+    let (_, writes) = n1.apply(&[write(1, 1), write(1, 2), write(2, 3)]);
+    assert_eq!(
+        writes.iter().map(|w| (w.key, w.value)).collect::<Vec<_>>(),
+        vec![(1, 2), (2, 3)]
+    );
+
+    n2.merge(&writes);
+    let (txn, _) = n2.apply(&[read(1), read(2)]);
+    assert_eq!(
+        txn,
+        vec![
+            MicroOp::Read {
+                key: 1,
+                value: Some(2)
+            },
+            MicroOp::Read {
+                key: 2,
+                value: Some(3)
+            },
+        ]
+    );
+}
+
+#[test]
+fn read_uncommitted_replicates_every_write() {
+    let mut store = Store::with_isolation("n1", Isolation::ReadUncommitted);
+
+    let (_, writes) = store.apply(&[write(1, 1), write(1, 2)]);
+
+    assert_eq!(writes.len(), 2);
+    assert_eq!(store.get(1), Some(2));
+}
+
+#[test]
+fn concurrent_write_sets_are_never_interleaved() {
+    let mut n1 = Store::with_isolation("n1", Isolation::ReadCommitted);
+    let mut n2 = Store::with_isolation("n2", Isolation::ReadCommitted);
+
+    let (_, from_n1) = n1.apply(&[write(1, 10), write(2, 10)]);
+    let (_, from_n2) = n2.apply(&[write(1, 20), write(2, 20)]);
+
+    n1.merge(&from_n2);
+    n2.merge(&from_n1);
+
+    for store in [&n1, &n2] {
+        assert_eq!(store.get(1), store.get(2));
+    }
+    assert_eq!(n1.get(1), n2.get(1));
+}
+
+#[test]
+fn participant_votes_to_abort_when_a_key_is_locked() {
+    let mut participant = Participant::new("n1");
+
+    assert!(participant.prepare("t1", &[write(1, 5)]).is_some());
+    assert_eq!(participant.prepare("t2", &[read(1)]), None);
+    assert_eq!(participant.prepare("t2", &[read(2)]), Some(vec![read(2)]));
+
+    participant.abort("t1");
+    assert_eq!(participant.store().get(1), None);
+    assert!(participant.prepare("t3", &[write(1, 6)]).is_some());
+}
+
+#[test]
+fn participant_installs_writes_on_commit() {
+    let mut participant = Participant::new("n1");
+
+    let txn = participant.prepare("t1", &[write(1, 5), read(1)]).unwrap();
+    assert_eq!(
+        txn[1],
+        MicroOp::Read {
+            key: 1,
+            value: Some(5)
+        }
+    );
+    assert_eq!(participant.store().get(1), None);
+
+    participant.commit("t1");
+    participant.commit("t1");
+    assert!(!participant.is_prepared("t1"));
+    assert_eq!(participant.store().get(1), Some(5));
+}
+
+fn participants(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn coordinator_commits_once_every_participant_voted_to() {
+    let mut coordinator = Coordinator::default();
+    coordinator.start("t1", participants(&["n1", "n2"]), "state");
+
+    assert!(coordinator.vote("t1", "n3", false).is_none());
+    assert!(coordinator.vote("t1", "n1", true).is_none());
+    let outcome = coordinator.vote("t1", "n2", true).unwrap();
+    assert!(outcome.commit);
+    assert_eq!(outcome.state, "state");
+    assert!(!coordinator.is_voting("t1"));
+    assert!(coordinator.vote("t1", "n2", false).is_none());
+
+    coordinator.start("t2", participants(&["n1", "n2"]), "state");
+    let outcome = coordinator.vote("t2", "n2", false).unwrap();
+    assert!(!outcome.commit);
+    assert_eq!(outcome.participants.len(), 2);
+}
+
+#[test]
+fn coordinator_aborts_transactions_whose_votes_take_too_long() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut coordinator = Coordinator::with_clock(clock.clone());
+    coordinator.start("t1", participants(&["n1"]), ());
+    clock.advance(Duration::from_millis(500));
+    coordinator.start("t2", participants(&["n1"]), ());
+    clock.advance(Duration::from_millis(500));
+
+    let expired = coordinator.expire(Duration::from_millis(1000));
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].txn_id, "t1");
+    assert!(!expired[0].commit);
+    assert!(coordinator.is_voting("t2"));
+}
+
+#[test]
+fn coordinator_resends_a_decision_until_every_participant_acknowledged_it() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut coordinator: Coordinator<()> = Coordinator::with_clock(clock.clone());
+    coordinator.tell("t1", true, participants(&["n1", "n2"]));
+    assert!(coordinator.acknowledged("t1", "n1"));
+    assert!(!coordinator.acknowledged("t1", "n1"));
+
+    clock.advance(Duration::from_millis(500));
+    let resends = coordinator.unacknowledged(Duration::from_millis(500));
+    assert_eq!(resends, [("n2".to_string(), "t1".to_string(), true)]);
+    assert!(coordinator
+        .unacknowledged(Duration::from_millis(500))
+        .is_empty());
+
+    assert_eq!(coordinator.decision("t1"), Some(true));
+    assert!(coordinator.acknowledged("t1", "n2"));
+    assert_eq!(coordinator.decision("t1"), None);
+}
+
+#[test]
+fn awaiting_decisions_are_overdue_once_per_timeout() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut awaiting = AwaitingDecisions::with_clock(clock.clone());
+    awaiting.insert("t1", "n1");
+    clock.advance(Duration::from_millis(500));
+    awaiting.insert("t2", "n2");
+    clock.advance(Duration::from_millis(500));
+
+    assert_eq!(awaiting.overdue(Duration::from_millis(1000)), ["t1"]);
+    assert!(awaiting.overdue(Duration::from_millis(1000)).is_empty());
+    assert_eq!(awaiting.remove("t1"), Some("n1"));
+    assert_eq!(awaiting.len(), 1);
+}
@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+
+use distributed_system::vector_clock::VectorClock;
+use proptest::prelude::*;
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+
+/// Clocks over a few nodes with small counts, so that equal and comparable clocks are
+/// generated often enough.
+fn clock() -> impl Strategy<Value = VectorClock> {
+    prop::collection::vec((0..NODES.len(), 0..4u64), 0..6).prop_map(|events| {
+        let mut clock = VectorClock::new();
+        for (node, count) in events {
+            for _ in 0..count {
+                clock.increment(NODES[node]);
+            }
+        }
+        clock
+    })
+}
+
+fn merged(a: &VectorClock, b: &VectorClock) -> VectorClock {
+    let mut merged = a.clone();
+    merged.merge(b);
+    merged
+}
+
+proptest! {
+    #[test]
+    fn order_is_reflexive(a in clock()) {
+        prop_assert_eq!(a.partial_cmp(&a), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn order_is_antisymmetric(a in clock(), b in clock()) {
+        if a <= b && b <= a {
+            prop_assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn order_is_transitive(a in clock(), b in clock(), c in clock()) {
+        if a <= b && b <= c {
+            prop_assert!(a <= c);
+        }
+    }
+
+    #[test]
+    fn comparison_is_consistent_both_ways(a in clock(), b in clock()) {
+        prop_assert_eq!(a.partial_cmp(&b), b.partial_cmp(&a).map(Ordering::reverse));
+        prop_assert_eq!(a.is_concurrent(&b), b.is_concurrent(&a));
+    }
+
+    #[test]
+    fn merge_is_the_least_upper_bound(a in clock(), b in clock(), c in clock()) {
+        let ab = merged(&a, &b);
+        prop_assert!(a <= ab && b <= ab);
+        if a <= c && b <= c {
+            prop_assert!(ab <= c);
+        }
+    }
+
+    #[test]
+    fn merge_is_commutative_associative_and_idempotent(
+        a in clock(),
+        b in clock(),
+        c in clock(),
+    ) {
+        prop_assert_eq!(merged(&a, &b), merged(&b, &a));
+        prop_assert_eq!(merged(&merged(&a, &b), &c), merged(&a, &merged(&b, &c)));
+        prop_assert_eq!(merged(&a, &a), a);
+    }
+
+    #[test]
+    fn increment_moves_strictly_forward(a in clock(), node in 0..NODES.len()) {
+        let mut b = a.clone();
+        b.increment(NODES[node]);
+        prop_assert!(a < b);
+        prop_assert_eq!(b.get(NODES[node]), a.get(NODES[node]) + 1);
+    }
+}
+
+#[test]
+fn clocks_with_events_unknown_to_each_other_are_concurrent() {
+    let mut a = VectorClock::new();
+    a.increment("n1");
+    let mut b = VectorClock::new();
+    b.increment("n2");
+
+    assert!(a.is_concurrent(&b));
+    assert_eq!(
+        serde_json::to_string(&merged(&a, &b)).unwrap(),
+        r#"{"n1":1,"n2":1}"#
+    );
+}
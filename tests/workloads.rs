@@ -0,0 +1,1880 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::Parser;
+use distributed_system::assert_replies;
+use distributed_system::clock::{Clock, VirtualClock};
+use distributed_system::compact::encode_ranges;
+use distributed_system::kafka;
+use distributed_system::testkit::{self, TestNode};
+use serde_json::{json, Value};
+
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+#[allow(dead_code)]
+#[path = "../src/bin/echo.rs"]
+mod echo;
+#[allow(dead_code)]
+#[path = "../src/bin/g_counter.rs"]
+mod g_counter;
+#[allow(dead_code)]
+#[path = "../src/bin/g_set.rs"]
+mod g_set;
+#[allow(dead_code)]
+#[path = "../src/bin/pn_counter.rs"]
+mod pn_counter;
+#[allow(dead_code)]
+#[path = "../src/bin/register.rs"]
+mod register;
+#[allow(dead_code)]
+#[path = "../src/bin/unique_ids.rs"]
+mod unique_ids;
+
+#[test]
+fn echo_returns_what_it_got() {
+    let mut node = TestNode::init(echo::simulated(), "n0", &["n0"]);
+    let sent = assert_replies!(node, echo { echo: "hello" }, [echo_ok]);
+    assert_eq!(sent[0].body["echo"], "hello");
+    assert_eq!(sent[0].body["in_reply_to"], 2);
+}
+
+#[test]
+fn echo_never_answers_a_reply() {
+    let mut node = TestNode::init(echo::simulated(), "n0", &["n0"]);
+    assert_replies!(node, init_ok { in_reply_to: 1 }, []);
+    assert_replies!(
+        node,
+        echo_ok {
+            in_reply_to: 1,
+            echo: "hello"
+        },
+        []
+    );
+}
+
+#[test]
+fn unique_ids_never_repeat() {
+    let clock = Arc::new(VirtualClock::new());
+    for format in ["counter", "hlc", "snowflake", "ulid"] {
+        let cli = unique_ids::Cli::parse_from(["unique-ids", "--format", format]);
+        let mut node = TestNode::init(
+            unique_ids::simulated(cli, clock.clone()),
+            "n1",
+            &["n0", "n1"],
+        );
+        let mut ids: Vec<String> = (0..20)
+            .map(|_| {
+                let sent = assert_replies!(node, generate, [generate_ok]);
+                sent[0].body["id"].to_string()
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 20, "{format}");
+    }
+}
+
+#[test]
+fn unique_ids_rejects_a_count_out_of_range() {
+    let cli = unique_ids::Cli::parse_from(["unique-ids"]);
+    let mut node = TestNode::init(
+        unique_ids::simulated(cli, Arc::new(VirtualClock::new())),
+        "n0",
+        &["n0"],
+    );
+    let sent = assert_replies!(node, generate { count: 0 }, [error]);
+    assert_eq!(sent[0].body["code"], 12);
+    let sent = assert_replies!(node, generate { count: 3 }, [generate_ok]);
+    assert_eq!(sent[0].body["ids"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn broadcast_gossips_new_messages_to_its_neighbours() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = broadcast::Cli::parse_from(["broadcast", "--gossip-interval-ms=100"]);
+    let node = broadcast::simulated(cli, clock.clone()).unwrap();
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    let topology = json!({"n0": ["n1", "n2"], "n1": ["n0"], "n2": ["n0"]});
+    assert_replies!(node, topology { topology: topology }, [topology_ok]);
+    assert_replies!(node, broadcast { message: 5 }, [broadcast_ok]);
+    assert!(node.tick().is_empty());
+
+    clock.advance(Duration::from_millis(100));
+    let sent = node.tick();
+    let gossips = testkit::to_nodes(&sent);
+    let dests: Vec<&str> = gossips.iter().map(|gossip| gossip.dest.as_str()).collect();
+    assert_eq!(dests, ["n1", "n2"]);
+    assert!(gossips
+        .iter()
+        .all(|gossip| testkit::kind(gossip) == "gossip"));
+
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["messages"], json!([5]));
+}
+
+#[test]
+fn broadcast_acknowledges_gossip_and_learns_its_messages() {
+    let cli = broadcast::Cli::parse_from(["broadcast"]);
+    let node = broadcast::simulated(cli, Arc::new(VirtualClock::new())).unwrap();
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    let sent = node.receive(
+        "n1",
+        json!({"type": "gossip", "msg_id": 7, "messages": encode_ranges(&[3, 4, 9])}),
+    );
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        (sent[0].dest.as_str(), testkit::kind(&sent[0])),
+        ("n1", "gossip_ok")
+    );
+    assert_eq!(sent[0].body["in_reply_to"], 7);
+
+    let sent = assert_replies!(node, read, [read_ok]);
+    let mut messages: Vec<u64> = serde_json::from_value(sent[0].body["messages"].clone()).unwrap();
+    messages.sort();
+    assert_eq!(messages, [3, 4, 9]);
+}
+
+#[test]
+fn broadcast_paces_gossip_to_a_neighbour_that_doesnt_ack() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = broadcast::Cli::parse_from([
+        "broadcast",
+        "--gossip-interval-ms=100",
+        "--peer-rate=1",
+        "--peer-burst=1",
+    ]);
+    let node = broadcast::simulated(cli, clock.clone()).unwrap();
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    let topology = json!({"n0": ["n1"], "n1": ["n0"]});
+    assert_replies!(node, topology { topology: topology }, [topology_ok]);
+
+    let mut gossips = 0;
+    for message in 0..30 {
+        assert_replies!(node, broadcast { message: message }, [broadcast_ok]);
+        clock.advance(Duration::from_millis(100));
+        gossips += testkit::to_nodes(&node.tick()).len();
+    }
+    // One a second after the first, however many rounds and retries went by.
+    assert_eq!(gossips, 3);
+}
+
+/// Broadcasts a message every 100ms for 3s to a node whose only neighbour never acks, and
+/// returns how many gossips it sent.
+fn gossips_to_a_silent_neighbour(options: &[&str]) -> usize {
+    let clock = Arc::new(VirtualClock::new());
+    let args = [
+        "broadcast",
+        "--gossip-interval-ms=100",
+        "--retry-timeout-ms=100",
+    ];
+    let cli = broadcast::Cli::parse_from(args.iter().chain(options));
+    let node = broadcast::simulated(cli, clock.clone()).unwrap();
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    let topology = json!({"n0": ["n1"], "n1": ["n0"]});
+    assert_replies!(node, topology { topology: topology }, [topology_ok]);
+
+    let mut gossips = 0;
+    for message in 0..30 {
+        assert_replies!(node, broadcast { message: message }, [broadcast_ok]);
+        clock.advance(Duration::from_millis(100));
+        gossips += testkit::to_nodes(&node.tick()).len();
+    }
+    gossips
+}
+
+#[test]
+fn broadcast_only_probes_a_neighbour_once_its_circuit_opens() {
+    assert_eq!(gossips_to_a_silent_neighbour(&[]), 30);
+    // Two rounds with timeouts open it at 400ms, then it's probed every second.
+    let options = ["--circuit-breaker-after=2", "--circuit-probe-ms=1000"];
+    assert_eq!(gossips_to_a_silent_neighbour(&options), 5);
+}
+
+#[test]
+fn broadcast_repairs_a_read_from_a_neighbour() {
+    let clock = Arc::new(VirtualClock::new());
+    let topology = json!({"n0": ["n1"], "n1": ["n0"]});
+    let mut nodes = ["n0", "n1"].map(|id| {
+        let cli = broadcast::Cli::parse_from(["broadcast", "--read-repair"]);
+        let node = broadcast::simulated(cli, clock.clone()).unwrap();
+        let mut node = TestNode::init(node, id, &["n0", "n1"]);
+        assert_replies!(
+            node,
+            topology {
+                topology: topology.clone()
+            },
+            [topology_ok]
+        );
+        node
+    });
+    for message in [1, 2] {
+        assert_replies!(nodes[1], broadcast { message: message }, [broadcast_ok]);
+    }
+    assert_replies!(nodes[0], broadcast { message: 3 }, [broadcast_ok]);
+
+    let sent = assert_replies!(nodes[0], read, []);
+    assert_eq!(testkit::kind(&sent[0]), "repair");
+    let sent = nodes[1].receive("n0", sent[0].body.clone());
+    assert_eq!(testkit::kind(&sent[0]), "repair_ok");
+    let sent = nodes[0].receive("n1", sent[0].body.clone());
+    assert_eq!(testkit::replies(&sent), ["read_ok"]);
+    let mut messages: Vec<u64> = serde_json::from_value(sent[0].body["messages"].clone()).unwrap();
+    messages.sort();
+    assert_eq!(messages, [1, 2, 3]);
+
+    // A neighbour that doesn't answer only delays the read.
+    assert_replies!(nodes[1], read, []);
+    clock.advance(Duration::from_millis(150));
+    let sent = nodes[1].tick();
+    assert_eq!(testkit::replies(&sent), ["read_ok"]);
+    assert_eq!(sent[0].body["messages"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn broadcast_keeps_a_value_apart_from_an_integer_equal_to_its_hash() {
+    let clock = Arc::new(VirtualClock::new());
+    let topology = json!({"n0": ["n1"], "n1": ["n0"]});
+    let mut nodes = ["n0", "n1"].map(|id| {
+        let cli = broadcast::Cli::parse_from(["broadcast", "--gossip-interval-ms=100"]);
+        let node = broadcast::simulated(cli, clock.clone()).unwrap();
+        let mut node = TestNode::init(node, id, &["n0", "n1"]);
+        assert_replies!(
+            node,
+            topology {
+                topology: topology.clone()
+            },
+            [topology_ok]
+        );
+        node
+    });
+    let hash = distributed_system::digest::hash_bytes(br#""hello""#);
+    let messages = [json!("hello"), json!(hash >> 1), json!(1 << 62 | hash >> 2)];
+    for message in &messages {
+        assert_replies!(nodes[0], broadcast { message: message }, [broadcast_ok]);
+    }
+
+    clock.advance(Duration::from_millis(100));
+    let sent = nodes[0].tick();
+    let gossip = testkit::to_nodes(&sent)[0];
+    nodes[1].receive("n0", gossip.body.clone());
+    for node in &mut nodes {
+        let sent = assert_replies!(node, read, [read_ok]);
+        let read = sent[0].body["messages"].as_array().unwrap();
+        assert_eq!(read.len(), 3, "{read:?}");
+        assert!(messages.iter().all(|message| read.contains(message)));
+    }
+}
+
+#[test]
+fn g_counter_syncs_adds_to_the_other_nodes() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = g_counter::Cli::parse_from(["g-counter", "--sync-interval-ms=100"]);
+    let node = g_counter::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    let sent = assert_replies!(node, add { delta: 3 }, [add_ok]);
+    assert!(testkit::to_nodes(&sent).is_empty());
+    assert_replies!(node, add { delta: 2 }, [add_ok]);
+
+    clock.advance(Duration::from_millis(100));
+    let syncs = node.tick();
+    assert_eq!(syncs.len(), 2);
+    assert!(syncs.iter().all(|sync| testkit::kind(sync) == "sync"));
+    assert_eq!(syncs[0].body["counters"], json!({"n0": 5}));
+
+    let sent = node.receive(
+        "n1",
+        json!({"type": "sync", "msg_id": 9, "counters": {"n1": 4}}),
+    );
+    assert_eq!(testkit::kind(&sent[0]), "sync_ok");
+    assert_eq!(sent[0].body["in_reply_to"], 9);
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["value"], 9);
+}
+
+#[test]
+fn g_counter_only_resends_deltas_a_peer_didnt_acknowledge() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = g_counter::Cli::parse_from(["g-counter", "--sync-interval-ms=100"]);
+    let node = g_counter::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    assert_replies!(node, add { delta: 3 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    let syncs = node.tick();
+    assert_eq!(syncs.len(), 2);
+    let to_n1 = syncs.iter().find(|sync| sync.dest == "n1").unwrap();
+    node.receive(
+        "n1",
+        json!({"type": "sync_ok", "in_reply_to": to_n1.body["msg_id"]}),
+    );
+
+    // Nothing new, and the sync to n2 is still in flight.
+    clock.advance(Duration::from_millis(100));
+    assert!(node.tick().is_empty());
+
+    // Once it times out, only n2 gets it again.
+    clock.advance(Duration::from_millis(400));
+    let syncs = node.tick();
+    let dests: Vec<&str> = syncs.iter().map(|sync| sync.dest.as_str()).collect();
+    assert_eq!(dests, ["n2"]);
+    assert_eq!(syncs[0].body["counters"], json!({"n0": 3}));
+}
+
+#[test]
+fn pn_counter_subtracts_negative_deltas() {
+    let cli = pn_counter::Cli::parse_from(["pn-counter"]);
+    let node = pn_counter::simulated(cli, Arc::new(VirtualClock::new()));
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    assert_replies!(node, add { delta: 5 }, [add_ok]);
+    assert_replies!(node, add { delta: -7 }, [add_ok]);
+    node.receive(
+        "n1",
+        json!({"type": "sync", "increments": {"n1": 1}, "decrements": {}}),
+    );
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["value"], -1);
+}
+
+#[test]
+fn pn_counter_takes_in_nodes_it_first_hears_of_in_a_sync() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = pn_counter::Cli::parse_from(["pn-counter", "--sync-interval-ms=100"]);
+    let node = pn_counter::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    node.receive(
+        "n1",
+        json!({"type": "sync", "increments": {"n1": 2, "c1": 9}, "decrements": {"n7": 5}}),
+    );
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["value"], -3);
+
+    assert_replies!(node, add { delta: 1 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    let syncs = node.tick();
+    let dests: Vec<&str> = syncs.iter().map(|sync| sync.dest.as_str()).collect();
+    assert_eq!(dests, ["n1", "n7"]);
+}
+
+#[test]
+fn pn_counter_syncs_a_burst_of_adds_in_one_round() {
+    let clock = Arc::new(VirtualClock::new());
+    let args = [
+        "pn-counter",
+        "--sync-interval-ms=100",
+        "--full-sync-ms=1000",
+    ];
+    let node = pn_counter::simulated(pn_counter::Cli::parse_from(args), clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    for delta in [5, -2, 4] {
+        let sent = assert_replies!(node, add { delta: delta }, [add_ok]);
+        assert!(testkit::to_nodes(&sent).is_empty());
+    }
+
+    clock.advance(Duration::from_millis(100));
+    let syncs = node.tick();
+    let dests: Vec<&str> = syncs.iter().map(|sync| sync.dest.as_str()).collect();
+    assert_eq!(dests, ["n1", "n2"]);
+    assert_eq!(syncs[0].body["increments"], json!({"n0": 9}));
+    assert_eq!(syncs[0].body["decrements"], json!({"n0": 2}));
+
+    // Nothing new until the full sync.
+    let mut rounds = Vec::new();
+    for _ in 0..9 {
+        clock.advance(Duration::from_millis(100));
+        rounds.push(node.tick().len());
+    }
+    assert_eq!(rounds, [0, 0, 0, 0, 0, 0, 0, 0, 2]);
+}
+
+#[test]
+fn g_counter_sends_its_count_every_full_sync_even_without_adds() {
+    let clock = Arc::new(VirtualClock::new());
+    let args = ["g-counter", "--sync-interval-ms=100", "--full-sync-ms=1000"];
+    let node = g_counter::simulated(g_counter::Cli::parse_from(args), clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    assert_replies!(node, add { delta: 3 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    let sent = node.tick();
+    node.receive(
+        "n1",
+        json!({"type": "sync_ok", "in_reply_to": sent[0].body["msg_id"]}),
+    );
+
+    let mut syncs = Vec::new();
+    for _ in 0..10 {
+        clock.advance(Duration::from_millis(100));
+        syncs.extend(node.tick());
+    }
+    assert_eq!(syncs.len(), 1);
+    assert_eq!(syncs[0].body["counters"], json!({"n0": 3}));
+}
+
+#[test]
+fn g_counter_takes_in_nodes_it_first_hears_of_in_a_sync() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = g_counter::Cli::parse_from(["g-counter", "--sync-interval-ms=100"]);
+    let node = g_counter::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1"]);
+    node.receive(
+        "n1",
+        json!({"type": "sync", "msg_id": 1, "counters": {"n1": 2, "n5": 4, "c1": 100}}),
+    );
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["value"], 6);
+
+    assert_replies!(node, add { delta: 1 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    let syncs = node.tick();
+    let dests: Vec<&str> = syncs.iter().map(|sync| sync.dest.as_str()).collect();
+    assert_eq!(dests, ["n1", "n5"]);
+}
+
+#[test]
+fn g_counter_answers_a_digest_with_the_counts_it_lacks() {
+    let cli = g_counter::Cli::parse_from(["g-counter", "--scuttlebutt"]);
+    let node = g_counter::simulated(cli, Arc::new(VirtualClock::new()));
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    assert_replies!(node, add { delta: 3 }, [add_ok]);
+    node.receive(
+        "n2",
+        json!({"type": "sync", "msg_id": 1, "counters": {"n2": 4}}),
+    );
+
+    let sent = node.receive(
+        "n1",
+        json!({"type": "digest", "msg_id": 2, "digest": {"n0": 3, "n1": 6, "n2": 1}}),
+    );
+    assert_eq!(testkit::kind(&sent[0]), "digest_ok");
+    assert_eq!(sent[0].body["counters"], json!({"n2": 4}));
+    assert_eq!(sent[0].body["digest"], json!({"n0": 3, "n1": 0, "n2": 4}));
+
+    // The peer's answer is pushed back with what it's behind on.
+    let sent = node.receive(
+        "n1",
+        json!({
+            "type": "digest_ok",
+            "msg_id": 3,
+            "in_reply_to": 1,
+            "digest": {"n1": 6},
+            "counters": {"n1": 6},
+        }),
+    );
+    assert_eq!(testkit::kind(&sent[0]), "sync");
+    assert_eq!(sent[0].body["counters"], json!({"n0": 3, "n2": 4}));
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["value"], 13);
+}
+
+#[test]
+fn g_counter_answers_a_quorum_read_once_a_majority_sent_their_counts() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = g_counter::Cli::parse_from(["g-counter", "--quorum-reads"]);
+    let node = g_counter::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2", "n3", "n4"]);
+    assert_replies!(node, add { delta: 1 }, [add_ok]);
+
+    let sent = assert_replies!(node, read, []);
+    let dests: Vec<&str> = sent.iter().map(|read| read.dest.as_str()).collect();
+    assert_eq!(dests, ["n1", "n2", "n3", "n4"]);
+    assert_eq!(testkit::kind(&sent[0]), "read_counters");
+    let read = sent[0].body["msg_id"].clone();
+
+    let answer = |counters: Value| json!({"type": "read_counters_ok", "in_reply_to": read, "counters": counters});
+    assert!(node.receive("n1", answer(json!({"n1": 2}))).is_empty());
+    // A node answering twice is still one of the majority.
+    assert!(node.receive("n1", answer(json!({"n1": 2}))).is_empty());
+    let sent = node.receive("n3", answer(json!({"n1": 1, "n3": 4})));
+    assert_eq!(testkit::replies(&sent), ["read_ok"]);
+    assert_eq!(sent[0].body["value"], 7);
+    assert!(node.receive("n4", answer(json!({"n4": 8}))).is_empty());
+
+    // Without a majority, the read fails.
+    assert_replies!(node, read, []);
+    clock.advance(Duration::from_millis(1_000));
+    let sent = node.tick();
+    assert_eq!(testkit::replies(&sent), ["error"]);
+    assert_eq!(sent[0].body["code"], 11);
+
+    let sent = node.receive("n2", json!({"type": "read_counters"}));
+    assert_eq!(testkit::kind(&sent[0]), "read_counters_ok");
+    assert_eq!(
+        sent[0].body["counters"],
+        json!({"n0": 1, "n1": 2, "n2": 0, "n3": 4, "n4": 8})
+    );
+}
+
+#[test]
+fn g_counter_repairs_a_read_from_a_random_peer() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = g_counter::Cli::parse_from(["g-counter", "--read-repair"]);
+    let node = g_counter::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    assert_replies!(node, add { delta: 1 }, [add_ok]);
+
+    let sent = assert_replies!(node, read, []);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(testkit::kind(&sent[0]), "read_counters");
+    let peer = sent[0].dest.clone();
+    let sent = node.receive(
+        &peer,
+        json!({"type": "read_counters_ok", "in_reply_to": sent[0].body["msg_id"], "counters": {"n2": 5}}),
+    );
+    assert_eq!(testkit::replies(&sent), ["read_ok"]);
+    assert_eq!(sent[0].body["value"], 6);
+
+    // A peer that doesn't answer only delays the read.
+    assert_replies!(node, read, []);
+    clock.advance(Duration::from_millis(100));
+    let sent = node.tick();
+    assert_eq!(testkit::replies(&sent), ["read_ok"]);
+    assert_eq!(sent[0].body["value"], 6);
+}
+
+#[test]
+fn g_set_syncs_added_elements_to_the_other_nodes() {
+    let clock = Arc::new(VirtualClock::new());
+    let cli = g_set::Cli::parse_from(["g-set", "--sync-interval-ms=100"]);
+    let node = g_set::simulated(cli, clock.clone());
+    let mut node = TestNode::init(node, "n0", &["n0", "n1", "n2"]);
+    for element in [3, 1] {
+        assert_replies!(node, add { element: element }, [add_ok]);
+    }
+    let sent = assert_replies!(node, remove { element: 1 }, [error]);
+    assert_eq!(sent[0].body["code"], 10);
+
+    clock.advance(Duration::from_millis(100));
+    let syncs = node.tick();
+    assert_eq!(syncs.len(), 2);
+    assert_eq!(syncs[0].body["elements"], json!([1, 3]));
+    // Nothing changed, so nothing is sent until the next full sync.
+    clock.advance(Duration::from_millis(100));
+    assert!(node.tick().is_empty());
+
+    node.receive("n1", json!({"type": "sync", "elements": [2, 3]}));
+    let sent = assert_replies!(node, read, [read_ok]);
+    assert_eq!(sent[0].body["value"], json!([1, 2, 3]));
+}
+
+#[test]
+fn or_set_keeps_elements_added_concurrently_with_a_remove() {
+    let clock = Arc::new(VirtualClock::new());
+    let nodes = ["n0", "n1"].map(|id| {
+        let cli = g_set::Cli::parse_from(["g-set", "--or-set", "--sync-interval-ms=100"]);
+        TestNode::init(g_set::simulated(cli, clock.clone()), id, &["n0", "n1"])
+    });
+    let [mut n0, mut n1] = nodes;
+    assert_replies!(n0, add { element: 1 }, [add_ok]);
+    assert_replies!(n0, add { element: 2 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    let sync = n0.tick().remove(0);
+    n1.receive("n0", sync.body);
+
+    // n1 removes both, while n0 adds 1 again.
+    assert_replies!(n1, remove { element: 1 }, [remove_ok]);
+    assert_replies!(n1, remove { element: 2 }, [remove_ok]);
+    assert_replies!(n0, add { element: 1 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    let from_n0 = n0.tick().remove(0);
+    let from_n1 = n1.tick().remove(0);
+    n0.receive("n1", from_n1.body);
+    n1.receive("n0", from_n0.body);
+
+    for node in [&mut n0, &mut n1] {
+        let sent = assert_replies!(node, read, [read_ok]);
+        assert_eq!(sent[0].body["value"], json!([1]));
+    }
+}
+
+#[test]
+fn or_set_keeps_an_element_added_again_by_a_restarted_node() {
+    let clock = Arc::new(VirtualClock::new());
+    let or_set_node = |id| {
+        let cli = g_set::Cli::parse_from(["g-set", "--or-set", "--sync-interval-ms=100"]);
+        TestNode::init(g_set::simulated(cli, clock.clone()), id, &["n0", "n1"])
+    };
+    let mut n0 = or_set_node("n0");
+    let mut n1 = or_set_node("n1");
+    assert_replies!(n0, add { element: 1 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    n1.receive("n0", n0.tick().remove(0).body);
+    assert_replies!(n1, remove { element: 1 }, [remove_ok]);
+
+    // n0 restarts without its set, hears of the remove, and adds 1 again.
+    let mut n0 = or_set_node("n0");
+    clock.advance(Duration::from_millis(100));
+    n0.receive("n1", n1.tick().remove(0).body);
+    assert_replies!(n0, add { element: 1 }, [add_ok]);
+    clock.advance(Duration::from_millis(100));
+    n1.receive("n0", n0.tick().remove(0).body);
+
+    for node in [&mut n0, &mut n1] {
+        let sent = assert_replies!(node, read, [read_ok]);
+        assert_eq!(sent[0].body["value"], json!([1]));
+    }
+}
+
+/// Two register nodes of the given kind, syncing every 100ms.
+fn register_nodes(clock: &Arc<VirtualClock>, options: &[&str]) -> [TestNode; 2] {
+    ["n0", "n1"].map(|id| {
+        let args = ["register", "--sync-interval-ms=100"];
+        let cli = register::Cli::parse_from(args.iter().chain(options));
+        TestNode::init(register::simulated(cli, clock.clone()), id, &["n0", "n1"])
+    })
+}
+
+/// Advances the clock to the next sync round, and hands each node's syncs to the other.
+fn exchange_syncs(clock: &VirtualClock, nodes: &mut [TestNode; 2]) {
+    clock.advance(Duration::from_millis(100));
+    let syncs = [nodes[0].tick(), nodes[1].tick()];
+    for (from, syncs) in ["n0", "n1"].into_iter().zip(syncs) {
+        let to = if from == "n0" { 1 } else { 0 };
+        for sync in syncs {
+            nodes[to].receive(from, sync.body);
+        }
+    }
+}
+
+#[test]
+fn register_keeps_the_last_write_and_checks_cas_locally() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = register_nodes(&clock, &[]);
+    let sent = assert_replies!(nodes[0], read { key: 1 }, [error]);
+    assert_eq!(sent[0].body["code"], 20);
+    let sent = assert_replies!(
+        nodes[0],
+        cas {
+            key: 1,
+            from: 2,
+            to: 3
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 20);
+
+    assert_replies!(nodes[1], write { key: 1, value: 5 }, [write_ok]);
+    clock.advance(Duration::from_millis(1));
+    assert_replies!(nodes[0], write { key: 1, value: 6 }, [write_ok]);
+    exchange_syncs(&clock, &mut nodes);
+    for node in &mut nodes {
+        let sent = assert_replies!(node, read { key: 1 }, [read_ok]);
+        assert_eq!(sent[0].body["value"], 6);
+    }
+
+    let sent = assert_replies!(
+        nodes[1],
+        cas {
+            key: 1,
+            from: 5,
+            to: 7
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 22);
+    assert_replies!(
+        nodes[1],
+        cas {
+            key: 1,
+            from: 6,
+            to: 7
+        },
+        [cas_ok]
+    );
+    assert_replies!(
+        nodes[1],
+        cas {
+            key: 2,
+            from: 0,
+            to: 1,
+            create_if_not_exists: true
+        },
+        [cas_ok]
+    );
+    // The swap of n1 is later than the write it replaced, so it wins on n0 too.
+    exchange_syncs(&clock, &mut nodes);
+    let sent = assert_replies!(nodes[0], read { key: 1 }, [read_ok]);
+    assert_eq!(sent[0].body["value"], 7);
+}
+
+#[test]
+fn multi_value_register_reads_every_concurrent_write() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = register_nodes(&clock, &["--multi-value"]);
+    assert_replies!(nodes[0], write { key: "k", value: 1 }, [write_ok]);
+    exchange_syncs(&clock, &mut nodes);
+    assert_replies!(nodes[0], write { key: "k", value: 2 }, [write_ok]);
+    assert_replies!(nodes[1], write { key: "k", value: 3 }, [write_ok]);
+    exchange_syncs(&clock, &mut nodes);
+
+    for node in &mut nodes {
+        let sent = assert_replies!(node, read { key: "k" }, [read_ok]);
+        let mut values: Vec<u64> = serde_json::from_value(sent[0].body["value"].clone()).unwrap();
+        values.sort();
+        assert_eq!(values, [2, 3]);
+    }
+    let sent = assert_replies!(
+        nodes[1],
+        cas {
+            key: "k",
+            from: 3,
+            to: 4
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 22);
+
+    // A write that saw both values replaces them.
+    assert_replies!(nodes[1], write { key: "k", value: 4 }, [write_ok]);
+    exchange_syncs(&clock, &mut nodes);
+    let sent = assert_replies!(nodes[0], read { key: "k" }, [read_ok]);
+    assert_eq!(sent[0].body["value"], json!([4]));
+    assert_replies!(
+        nodes[0],
+        cas {
+            key: "k",
+            from: 4,
+            to: 5
+        },
+        [cas_ok]
+    );
+}
+
+#[test]
+fn kafka_polls_what_was_sent_from_an_offset() {
+    let cli = kafka::Cli::parse_from(["kafka"]);
+    let clock = Arc::new(VirtualClock::new());
+    let mut node = TestNode::init(kafka::simulated(cli, clock).unwrap(), "n0", &["n0"]);
+    for msg in [10, 11, 12] {
+        assert_replies!(
+            node,
+            send {
+                key: "k1",
+                msg: msg
+            },
+            [send_ok]
+        );
+    }
+    let sent = assert_replies!(
+        node,
+        poll {
+            offsets: json!({"k1": 1})
+        },
+        [poll_ok]
+    );
+    let msgs: Vec<[u64; 2]> = serde_json::from_value(sent[0].body["msgs"]["k1"].clone()).unwrap();
+    assert_eq!(
+        msgs.iter().map(|[_, msg]| *msg).collect::<Vec<_>>(),
+        [11, 12]
+    );
+
+    assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 2})
+        },
+        [commit_offsets_ok]
+    );
+    let sent = assert_replies!(
+        node,
+        list_committed_offsets { keys: ["k1"] },
+        [list_committed_offsets_ok]
+    );
+    assert_eq!(sent[0].body["offsets"], json!({"k1": 2}));
+}
+
+#[test]
+fn kafka_polls_at_most_max_msgs_and_the_next_poll_resumes_after_them() {
+    let cli = kafka::Cli::parse_from(["kafka"]);
+    let clock = Arc::new(VirtualClock::new());
+    let mut node = TestNode::init(kafka::simulated(cli, clock).unwrap(), "n0", &["n0"]);
+    for msg in 10..15 {
+        assert_replies!(
+            node,
+            send {
+                key: "k1",
+                msg: msg
+            },
+            [send_ok]
+        );
+    }
+
+    let mut offset = 0;
+    let mut polled = Vec::new();
+    for expected in [2, 2, 1, 0] {
+        let sent = assert_replies!(
+            node,
+            poll {
+                offsets: json!({ "k1": offset }),
+                max_msgs: 2
+            },
+            [poll_ok]
+        );
+        let msgs: Vec<[u64; 2]> =
+            serde_json::from_value(sent[0].body["msgs"]["k1"].clone()).unwrap_or_default();
+        assert_eq!(msgs.len(), expected);
+        offset = msgs.last().map_or(offset, |[last, _]| last + 1);
+        polled.extend(msgs);
+    }
+    assert_eq!(polled, [[0, 10], [1, 11], [2, 12], [3, 13], [4, 14]]);
+}
+
+#[test]
+fn kafka_appends_a_batch_to_every_key_and_returns_the_offsets_of_each() {
+    // n2 owns k1, and n0 owns k3.
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    assert_replies!(nodes[0], send { key: "k3", msg: 1 }, [send_ok]);
+
+    let sent = assert_replies!(
+        nodes[1],
+        send_batch {
+            msgs: json!({"k1": [10, 11, 12], "k3": [30]}),
+            // Not the msg_id of the send to n0, or the batch would count as its retry.
+            msg_id: 10
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["send_batch_ok"]);
+    assert_eq!(
+        replies[0].body["offsets"],
+        json!({"k1": [0, 2], "k3": [1, 1]})
+    );
+
+    let sent = assert_replies!(
+        nodes[2],
+        send_batch {
+            msgs: json!({"k1": [13]})
+        },
+        [send_batch_ok]
+    );
+    assert_eq!(sent[0].body["offsets"], json!({"k1": [3, 3]}));
+    let sent = assert_replies!(
+        nodes[2],
+        poll {
+            offsets: json!({"k1": 0})
+        },
+        [poll_ok]
+    );
+    assert_eq!(
+        sent[0].body["msgs"],
+        json!({"k1": [[0, 10], [1, 11], [2, 12], [3, 13]]})
+    );
+}
+
+#[test]
+fn kafka_answers_a_retried_send_with_its_original_offset() {
+    // n2 owns k1.
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    let send =
+        |msg_id: u64, msg: u64| json!({"type": "send", "msg_id": msg_id, "key": "k1", "msg": msg});
+    for (node, msg_id, msg, offset) in [(2, 5, 10, 0), (2, 6, 11, 1), (2, 5, 10, 0), (0, 5, 10, 0)]
+    {
+        let sent = nodes[node].request(send(msg_id, msg));
+        let replies = settle(&mut nodes, &[], sent);
+        assert_eq!(testkit::replies(&replies), ["send_ok"]);
+        assert_eq!(
+            replies[0].body["offset"], offset,
+            "msg_id {msg_id} to n{node}"
+        );
+    }
+
+    let sent = assert_replies!(
+        nodes[2],
+        poll {
+            offsets: json!({"k1": 0})
+        },
+        [poll_ok]
+    );
+    assert_eq!(sent[0].body["msgs"], json!({"k1": [[0, 10], [1, 11]]}));
+}
+
+#[test]
+fn kafka_nodes_agree_on_the_owner_of_every_key() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    for (key, owner) in [("k1", "n2"), ("k3", "n0"), ("k7", "n1")] {
+        for (index, id) in ["n0", "n1", "n2"].into_iter().enumerate() {
+            let sent = nodes[index].request(json!({"type": "send", "key": key, "msg": 1}));
+            if id == owner {
+                assert_eq!(testkit::replies(&sent), ["send_ok"], "{key}");
+                assert!(testkit::to_nodes(&sent).is_empty(), "{key}");
+            } else {
+                let forwarded = testkit::to_nodes(&sent);
+                assert_eq!(forwarded.len(), 1, "{key}");
+                assert_eq!(forwarded[0].dest, owner, "{key}");
+                assert_eq!(testkit::kind(forwarded[0]), "send_batch", "{key}");
+                let replies = settle(&mut nodes, &[], sent);
+                assert_eq!(testkit::replies(&replies), ["send_ok"], "{key}");
+            }
+        }
+    }
+}
+
+#[test]
+fn kafka_forwards_requests_to_the_owners_of_their_keys_and_relays_the_answers() {
+    // n2 owns k1, and n0 owns k3.
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    for (msg, offset) in [(10, 0), (11, 1)] {
+        let sent = assert_replies!(
+            nodes[0],
+            send {
+                key: "k1",
+                msg: msg
+            },
+            []
+        );
+        let replies = settle(&mut nodes, &[], sent);
+        assert_eq!(testkit::replies(&replies), ["send_ok"]);
+        assert_eq!(replies[0].body["offset"], offset);
+    }
+    assert_replies!(nodes[0], send { key: "k3", msg: 30 }, [send_ok]);
+
+    // n1 owns neither key, and answers with what both owners have.
+    let sent = assert_replies!(
+        nodes[1],
+        poll {
+            offsets: json!({"k1": 0, "k3": 0})
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["poll_ok"]);
+    assert_eq!(
+        replies[0].body["msgs"],
+        json!({"k1": [[0, 10], [1, 11]], "k3": [[0, 30]]})
+    );
+
+    let sent = assert_replies!(
+        nodes[1],
+        commit_offsets {
+            offsets: json!({"k1": 2, "k3": 1})
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["commit_offsets_ok"]);
+    let sent = assert_replies!(nodes[2], list_committed_offsets { keys: ["k1", "k3"] }, []);
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(replies[0].body["offsets"], json!({"k1": 2, "k3": 1}));
+}
+
+#[test]
+fn kafka_keeps_the_committed_offsets_of_each_consumer_group_apart() {
+    // n2 owns k1, and n0 owns k3.
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    let commits = [
+        json!({"type": "commit_offsets", "offsets": {"k1": 2, "k3": 1}, "group": "g"}),
+        json!({"type": "commit_offsets", "offsets": {"k1": 5}, "group": "h"}),
+        json!({"type": "commit_offsets", "offsets": {"k3": 7}}),
+    ];
+    for (msg_id, mut commit) in (1..).zip(commits) {
+        commit["msg_id"] = json!(msg_id);
+        let sent = nodes[1].request(commit);
+        let replies = settle(&mut nodes, &[], sent);
+        assert_eq!(testkit::replies(&replies), ["commit_offsets_ok"]);
+    }
+
+    let expected = [
+        (json!("g"), json!({"k1": 2, "k3": 1})),
+        (json!("h"), json!({"k1": 5})),
+        (json!(null), json!({"k3": 7})),
+    ];
+    for (msg_id, (group, offsets)) in (10..).zip(expected) {
+        let mut list =
+            json!({"type": "list_committed_offsets", "msg_id": msg_id, "keys": ["k1", "k3"]});
+        if !group.is_null() {
+            list["group"] = group.clone();
+        }
+        let sent = nodes[1].request(list);
+        let replies = settle(&mut nodes, &[], sent);
+        assert_eq!(replies[0].body["offsets"], offsets, "group {group}");
+    }
+}
+
+#[test]
+fn kafka_fences_commits_of_a_consumer_with_a_stale_epoch() {
+    let cli = kafka::Cli::parse_from(["kafka"]);
+    let clock = Arc::new(VirtualClock::new());
+    let mut node = TestNode::init(kafka::simulated(cli, clock).unwrap(), "n0", &["n0"]);
+    assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 3}),
+            group: "g",
+            epoch: 2
+        },
+        [commit_offsets_ok]
+    );
+    // A consumer from before the group moved on to epoch 2 can't roll the offset back.
+    let sent = assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 1, "k2": 1}),
+            group: "g",
+            epoch: 1
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 22);
+    // Other groups keep their own epochs.
+    assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 1}),
+            group: "h",
+            epoch: 1
+        },
+        [commit_offsets_ok]
+    );
+    assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 4}),
+            group: "g",
+            epoch: 2
+        },
+        [commit_offsets_ok]
+    );
+
+    let sent = assert_replies!(
+        node,
+        list_committed_offsets {
+            keys: ["k1", "k2"],
+            group: "g"
+        },
+        [list_committed_offsets_ok]
+    );
+    assert_eq!(sent[0].body["offsets"], json!({"k1": 4}));
+}
+
+#[test]
+fn kafka_fences_commits_of_clients_that_claim_their_epoch_was_checked() {
+    let cli = kafka::Cli::parse_from(["kafka"]);
+    let clock = Arc::new(VirtualClock::new());
+    let mut node = TestNode::init(kafka::simulated(cli, clock).unwrap(), "n0", &["n0"]);
+    assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 3}),
+            group: "g",
+            epoch: 2
+        },
+        [commit_offsets_ok]
+    );
+    let sent = assert_replies!(
+        node,
+        commit_offsets {
+            offsets: json!({"k1": 1}),
+            group: "g",
+            epoch: 1,
+            checked: true
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 22);
+    // Only nodes forward the parts of a commit that the heads of its keys checked.
+    let sent = assert_replies!(
+        node,
+        commit_checked_offsets {
+            offsets: json!({"k1": 1}),
+            group: "g",
+            epoch: 1
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 10);
+
+    let sent = assert_replies!(
+        node,
+        list_committed_offsets {
+            keys: ["k1"],
+            group: "g"
+        },
+        [list_committed_offsets_ok]
+    );
+    assert_eq!(sent[0].body["offsets"], json!({"k1": 3}));
+}
+
+#[test]
+fn kafka_commits_keys_of_several_nodes_only_if_none_has_a_newer_epoch() {
+    // n0 owns k2, and n1 owns k1.
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = ["n0", "n1"].map(|id| {
+        let cli = kafka::Cli::parse_from(["kafka"]);
+        TestNode::init(
+            kafka::simulated(cli, clock.clone()).unwrap(),
+            id,
+            &["n0", "n1"],
+        )
+    });
+    assert_replies!(
+        nodes[1],
+        commit_offsets {
+            offsets: json!({"k1": 3}),
+            group: "g",
+            epoch: 2
+        },
+        [commit_offsets_ok]
+    );
+
+    // n0 could commit k2 with epoch 1, but n1 can't commit k1, so neither is.
+    let sent = assert_replies!(
+        nodes[0],
+        commit_offsets {
+            offsets: json!({"k1": 1, "k2": 1}),
+            group: "g",
+            epoch: 1
+        },
+        []
+    );
+    assert_eq!(testkit::kind(&sent[0]), "check_epochs");
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["error"]);
+    assert_eq!(replies[0].body["code"], 22);
+
+    let sent = assert_replies!(
+        nodes[0],
+        commit_offsets {
+            offsets: json!({"k1": 4, "k2": 4}),
+            group: "g",
+            epoch: 2
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["commit_offsets_ok"]);
+
+    let sent = assert_replies!(
+        nodes[0],
+        list_committed_offsets {
+            keys: ["k1", "k2"],
+            group: "g"
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(replies[0].body["offsets"], json!({"k1": 4, "k2": 4}));
+}
+
+#[test]
+fn kafka_polls_report_where_logs_start_and_end_and_what_was_committed() {
+    for (legacy, expected) in [
+        (
+            false,
+            json!({"metadata": {"k1": {"log_start": 2, "log_end": 4, "committed": 3}}}),
+        ),
+        (true, json!({"low_watermarks": {"k1": 2}})),
+    ] {
+        let mut args = vec!["kafka", "--retention=2"];
+        if legacy {
+            args.push("--legacy-poll-ok");
+        }
+        let cli = kafka::Cli::parse_from(args);
+        let clock = Arc::new(VirtualClock::new());
+        let mut node = TestNode::init(kafka::simulated(cli, clock).unwrap(), "n0", &["n0"]);
+        for msg in [10, 11, 12, 13] {
+            assert_replies!(
+                node,
+                send {
+                    key: "k1",
+                    msg: msg
+                },
+                [send_ok]
+            );
+        }
+        assert_replies!(
+            node,
+            commit_offsets {
+                offsets: json!({"k1": 3}),
+                group: "g"
+            },
+            [commit_offsets_ok]
+        );
+
+        let sent = assert_replies!(
+            node,
+            poll {
+                offsets: json!({"k1": 0}),
+                group: "g"
+            },
+            [poll_ok]
+        );
+        assert_eq!(sent[0].body["msgs"], json!({"k1": [[2, 12], [3, 13]]}));
+        for field in ["metadata", "low_watermarks"] {
+            assert_eq!(sent[0].body.get(field), expected.get(field), "{field}");
+        }
+    }
+}
+
+/// Answers what `sent` asks of the KV services, keeping their keys in `store`, and what the
+/// node sends in turn, until only messages for the client are left. Returns those.
+fn serve_kv(
+    node: &mut TestNode,
+    store: &mut HashMap<String, Value>,
+    sent: Vec<testkit::Sent>,
+) -> Vec<testkit::Sent> {
+    let mut sent = std::collections::VecDeque::from(sent);
+    let mut replies = Vec::new();
+    while let Some(message) = sent.pop_front() {
+        if message.dest == testkit::CLIENT {
+            replies.push(message);
+            continue;
+        }
+        let body = &message.body;
+        let key = format!("{}/{}", message.dest, body["key"]);
+        let in_reply_to = body["msg_id"].clone();
+        let missing = json!({"type": "error", "in_reply_to": in_reply_to, "code": 20});
+        let reply = match (testkit::kind(&message), store.get(&key)) {
+            ("read", Some(value)) => {
+                json!({"type": "read_ok", "in_reply_to": in_reply_to, "value": value})
+            }
+            ("write", _) => {
+                store.insert(key, body["value"].clone());
+                json!({"type": "write_ok", "in_reply_to": in_reply_to})
+            }
+            ("cas", current) if current.is_none() && body["create_if_not_exists"] == true => {
+                store.insert(key, body["to"].clone());
+                json!({"type": "cas_ok", "in_reply_to": in_reply_to})
+            }
+            ("cas", Some(value)) if *value == body["from"] => {
+                store.insert(key, body["to"].clone());
+                json!({"type": "cas_ok", "in_reply_to": in_reply_to})
+            }
+            ("cas", Some(_)) => json!({"type": "error", "in_reply_to": in_reply_to, "code": 22}),
+            _ => missing,
+        };
+        sent.extend(node.receive(&message.dest, reply));
+    }
+    replies
+}
+
+#[test]
+fn kafka_polls_with_lin_kv_storage_report_where_logs_end_and_what_was_committed() {
+    let cli = kafka::Cli::parse_from(["kafka", "--storage=lin-kv"]);
+    let clock = Arc::new(VirtualClock::new());
+    let mut node = TestNode::init(kafka::simulated(cli, clock).unwrap(), "n0", &["n0"]);
+    let mut store = HashMap::new();
+    let requests = [
+        json!({"type": "send", "key": "k1", "msg": 10}),
+        json!({"type": "send", "key": "k1", "msg": 11}),
+        json!({"type": "commit_offsets", "offsets": {"k1": 1}, "group": "g"}),
+    ];
+    for request in requests {
+        let sent = node.request(request);
+        let replies = serve_kv(&mut node, &mut store, sent);
+        assert_eq!(testkit::replies(&replies).len(), 1);
+        assert_ne!(testkit::kind(&replies[0]), "error");
+    }
+
+    let sent = node.request(json!({"type": "poll", "offsets": {"k1": 0, "k2": 0}, "group": "g"}));
+    let replies = serve_kv(&mut node, &mut store, sent);
+    assert_eq!(testkit::replies(&replies), ["poll_ok"]);
+    assert_eq!(replies[0].body["msgs"], json!({"k1": [[0, 10], [1, 11]]}));
+    assert_eq!(
+        replies[0].body["metadata"],
+        json!({
+            "k1": {"log_start": 0, "log_end": 2, "committed": 1},
+            "k2": {"log_start": 0, "log_end": 0},
+        })
+    );
+}
+
+#[test]
+fn kafka_lists_the_first_offset_appended_at_or_after_a_time() {
+    let cli = kafka::Cli::parse_from(["kafka"]);
+    let clock = Arc::new(VirtualClock::new());
+    let mut node = TestNode::init(kafka::simulated(cli, clock.clone()).unwrap(), "n0", &["n0"]);
+    let millis = || {
+        let elapsed = clock.system_time().duration_since(UNIX_EPOCH).unwrap();
+        elapsed.as_millis() as u64
+    };
+    let mut sent_at = Vec::new();
+    for msg in [10, 11, 12] {
+        sent_at.push(millis());
+        assert_replies!(
+            node,
+            send {
+                key: "k1",
+                msg: msg
+            },
+            [send_ok]
+        );
+        clock.advance(Duration::from_secs(5));
+    }
+
+    for (timestamp, offset) in [
+        (sent_at[0] - 1, 0),
+        (sent_at[1], 1),
+        (sent_at[1] + 1, 2),
+        (millis(), 3),
+    ] {
+        let sent = assert_replies!(
+            node,
+            list_offsets {
+                timestamps: json!({"k1": timestamp, "k2": timestamp})
+            },
+            [list_offsets_ok]
+        );
+        assert_eq!(sent[0].body["offsets"], json!({"k1": offset, "k2": 0}));
+    }
+}
+
+/// A kafka node of `n0` and `n1`, each of which follows the keys of the other, and the
+/// requests for its lost logs it sent as it started.
+fn replicated_kafka_node(id: &str) -> (TestNode, Vec<testkit::Sent>) {
+    let cli = kafka::Cli::parse_from(["kafka", "--replicas=1", "--acks=all"]);
+    let clock = Arc::new(VirtualClock::new());
+    TestNode::start(kafka::simulated(cli, clock).unwrap(), id, &["n0", "n1"])
+}
+
+/// Hands `to` the messages `from` sent it, and returns what it sent back.
+fn deliver(from: &str, sent: &[testkit::Sent], to: &mut TestNode) -> Vec<testkit::Sent> {
+    sent.iter()
+        .flat_map(|message| to.receive(from, message.body.clone()))
+        .collect()
+}
+
+#[test]
+fn kafka_acknowledges_a_send_once_followers_have_it_and_recovers_from_them() {
+    // n0 owns k2, and n1 follows it.
+    let (mut n0, recover) = replicated_kafka_node("n0");
+    let (mut n1, recover_n0) = replicated_kafka_node("n1");
+    let answers = deliver("n0", &recover, &mut n1);
+    deliver("n1", &answers, &mut n0);
+    let answers = deliver("n1", &recover_n0, &mut n0);
+    deliver("n0", &answers, &mut n1);
+
+    let sent = assert_replies!(n0, send { key: "k2", msg: 7 }, []);
+    assert_eq!(testkit::kind(&sent[0]), "replicate");
+    let acks = deliver("n0", &sent, &mut n1);
+    let sent = deliver("n1", &acks, &mut n0);
+    assert_eq!(testkit::replies(&sent), ["send_ok"]);
+
+    // A restarted n0 fails requests until n1 sent it its copy of k2.
+    let (mut n0, recover) = replicated_kafka_node("n0");
+    let sent = assert_replies!(
+        n0,
+        poll {
+            offsets: json!({"k2": 0})
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 11);
+    let answers = deliver("n0", &recover, &mut n1);
+    deliver("n1", &answers, &mut n0);
+    let sent = assert_replies!(
+        n0,
+        poll {
+            offsets: json!({"k2": 0})
+        },
+        [poll_ok]
+    );
+    assert_eq!(sent[0].body["msgs"], json!({"k2": [[0, 7]]}));
+
+    // A restarted n1 gets the entries it lost with the next send.
+    let (mut n1, _) = replicated_kafka_node("n1");
+    let mut sent = assert_replies!(n0, send { key: "k2", msg: 8 }, []);
+    while testkit::replies(&sent).is_empty() {
+        let acks = deliver("n0", &sent, &mut n1);
+        sent = deliver("n1", &acks, &mut n0);
+    }
+    assert_eq!(testkit::replies(&sent), ["send_ok"]);
+    assert_eq!(sent[0].body["offset"], 1);
+}
+
+#[test]
+fn kafka_followers_keep_the_commits_and_epochs_a_restarted_owner_lost() {
+    // n0 owns k2, and n1 follows it.
+    let (mut n0, recover) = replicated_kafka_node("n0");
+    let (mut n1, recover_n0) = replicated_kafka_node("n1");
+    let answers = deliver("n0", &recover, &mut n1);
+    deliver("n1", &answers, &mut n0);
+    let answers = deliver("n1", &recover_n0, &mut n0);
+    deliver("n0", &answers, &mut n1);
+
+    let sent = assert_replies!(
+        n0,
+        commit_offsets {
+            offsets: json!({"k2": 3}),
+            group: "g",
+            epoch: 2
+        },
+        []
+    );
+    assert_eq!(testkit::kind(&sent[0]), "replicate");
+    let acks = deliver("n0", &sent, &mut n1);
+    let sent = deliver("n1", &acks, &mut n0);
+    assert_eq!(testkit::replies(&sent), ["commit_offsets_ok"]);
+
+    // A restarted n0 gets the commit back from n1, and still fences the older epoch.
+    let (mut n0, recover) = replicated_kafka_node("n0");
+    let answers = deliver("n0", &recover, &mut n1);
+    deliver("n1", &answers, &mut n0);
+    let sent = assert_replies!(
+        n0,
+        commit_offsets {
+            offsets: json!({"k2": 1}),
+            group: "g",
+            epoch: 1
+        },
+        [error]
+    );
+    assert_eq!(sent[0].body["code"], 22);
+    let sent = assert_replies!(
+        n0,
+        list_committed_offsets {
+            keys: ["k2"],
+            group: "g"
+        },
+        [list_committed_offsets_ok]
+    );
+    assert_eq!(sent[0].body["offsets"], json!({"k2": 3}));
+}
+
+/// Hands the messages of `sent` to the nodes of `nodes` they're for, and what they send in
+/// turn, until only messages for the client or for nodes that are `down` are left. Returns
+/// those for the client.
+fn settle(nodes: &mut [TestNode], down: &[&str], sent: Vec<testkit::Sent>) -> Vec<testkit::Sent> {
+    let mut sent = std::collections::VecDeque::from(sent);
+    let mut replies = Vec::new();
+    while let Some(message) = sent.pop_front() {
+        if message.dest == testkit::CLIENT {
+            replies.push(message);
+        } else if !down.contains(&message.dest.as_str()) {
+            let index: usize = message.dest[1..].parse().unwrap();
+            sent.extend(nodes[index].receive(&message.src, message.body));
+        }
+    }
+    replies
+}
+
+#[test]
+fn kafka_chain_passes_sends_to_the_tail_and_leaves_out_a_node_that_failed() {
+    let clock = Arc::new(VirtualClock::new());
+    let ids = ["n0", "n1", "n2"];
+    let mut started = Vec::new();
+    let mut nodes = ids.map(|id| {
+        let cli = kafka::Cli::parse_from(["kafka", "--replicas=2", "--replication=chain"]);
+        let (node, sent) = TestNode::start(kafka::simulated(cli, clock.clone()).unwrap(), id, &ids);
+        started.extend(sent);
+        node
+    });
+    settle(&mut nodes, &[], started);
+
+    // n0 owns k3, and heads the chain n0, n1, n2.
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 7 }, []);
+    assert_eq!(
+        (sent[0].dest.as_str(), testkit::kind(&sent[0])),
+        ("n1", "replicate")
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["send_ok"]);
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k3": 0})
+        },
+        []
+    );
+    assert_eq!(
+        (sent[0].dest.as_str(), testkit::kind(&sent[0])),
+        ("n2", "poll")
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(replies[0].body["msgs"], json!({"k3": [[0, 7]]}));
+
+    // n2 stops answering, so n1 takes it for failed, and acknowledges the send as the tail.
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 8 }, []);
+    let mut replies = settle(&mut nodes, &["n2"], sent);
+    assert!(replies.is_empty());
+    clock.advance(Duration::from_secs(1));
+    let sent = assert_replies!(
+        nodes[1],
+        poll {
+            offsets: json!({"k3": 0})
+        },
+        [poll_ok]
+    );
+    replies.extend(settle(&mut nodes, &["n2"], sent));
+    assert_eq!(testkit::replies(&replies), ["poll_ok", "send_ok"]);
+    assert_eq!(replies[0].body["msgs"], json!({"k3": [[0, 7], [1, 8]]}));
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k3": 1})
+        },
+        []
+    );
+    assert_eq!(sent[0].dest, "n1");
+}
+
+#[test]
+fn kafka_chain_takes_back_a_node_cut_off_by_a_partition_once_it_caught_up() {
+    let clock = Arc::new(VirtualClock::new());
+    let ids = ["n0", "n1", "n2"];
+    let mut started = Vec::new();
+    let mut nodes = ids.map(|id| {
+        let cli = kafka::Cli::parse_from(["kafka", "--replicas=2", "--replication=chain"]);
+        let (node, sent) = TestNode::start(kafka::simulated(cli, clock.clone()).unwrap(), id, &ids);
+        started.extend(sent);
+        node
+    });
+    settle(&mut nodes, &[], started);
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 7 }, []);
+    settle(&mut nodes, &[], sent);
+
+    // n2, the tail of k3, is cut off, so n1 takes over as the tail, and acknowledges sends.
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 8 }, []);
+    settle(&mut nodes, &["n2"], sent);
+    clock.advance(Duration::from_secs(1));
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 9 }, []);
+    let replies = settle(&mut nodes, &["n2"], sent);
+    assert_eq!(testkit::replies(&replies), ["send_ok", "send_ok"]);
+    assert_eq!(replies[1].body["offset"], 2);
+
+    // The partition heals. n2 heads k1, and the send it passes on tells the others it's
+    // back, but it only takes polls of k3 again once it got the entries it missed.
+    let sent = assert_replies!(nodes[2], send { key: "k1", msg: 1 }, []);
+    assert_eq!(sent[0].dest, "n0");
+    let notice = nodes[0].receive("n2", sent[0].body.clone());
+    assert_eq!(
+        (notice[0].dest.as_str(), testkit::kind(&notice[0])),
+        ("n2", "reconfigure")
+    );
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k3": 0})
+        },
+        []
+    );
+    assert_eq!(sent[0].dest, "n1");
+    let replies = settle(&mut nodes, &[], notice.into_iter().chain(sent).collect());
+    assert_eq!(testkit::replies(&replies), ["poll_ok", "send_ok"]);
+    assert_eq!(
+        replies[0].body["msgs"],
+        json!({"k3": [[0, 7], [1, 8], [2, 9]]})
+    );
+
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 10 }, []);
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(replies[0].body["offset"], 3);
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k3": 0})
+        },
+        []
+    );
+    assert_eq!(sent[0].dest, "n2");
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(
+        replies[0].body["msgs"],
+        json!({"k3": [[0, 7], [1, 8], [2, 9], [3, 10]]})
+    );
+}
+
+#[test]
+fn kafka_chain_polls_report_the_offsets_committed_at_the_head() {
+    let clock = Arc::new(VirtualClock::new());
+    let ids = ["n0", "n1", "n2"];
+    let mut started = Vec::new();
+    let mut nodes = ids.map(|id| {
+        let cli = kafka::Cli::parse_from(["kafka", "--replicas=2", "--replication=chain"]);
+        let (node, sent) = TestNode::start(kafka::simulated(cli, clock.clone()).unwrap(), id, &ids);
+        started.extend(sent);
+        node
+    });
+    settle(&mut nodes, &[], started);
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 7 }, []);
+    settle(&mut nodes, &[], sent);
+
+    // n0 heads the chain of k3, and the commit passes down to n2, which answers polls.
+    let sent = assert_replies!(
+        nodes[0],
+        commit_offsets {
+            offsets: json!({"k3": 1}),
+            group: "g"
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["commit_offsets_ok"]);
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k3": 0}),
+            group: "g"
+        },
+        []
+    );
+    assert_eq!(sent[0].dest, "n2");
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(
+        replies[0].body["metadata"],
+        json!({"k3": {"log_start": 0, "log_end": 1, "committed": 1}})
+    );
+}
+
+#[test]
+fn kafka_appends_a_transaction_to_every_key_on_commit_or_not_at_all() {
+    // n0 owns k2, and n1 owns k1.
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = ["n0", "n1"].map(|id| {
+        let cli = kafka::Cli::parse_from(["kafka"]);
+        TestNode::init(
+            kafka::simulated(cli, clock.clone()).unwrap(),
+            id,
+            &["n0", "n1"],
+        )
+    });
+    let sent = assert_replies!(nodes[0], begin, [begin_ok]);
+    let txn_id = sent[0].body["txn_id"].clone();
+    for (key, msg) in [("k1", json!(10)), ("k2", json!([20, 21]))] {
+        assert_replies!(
+            nodes[0],
+            produce {
+                txn_id: txn_id.clone(),
+                key: key,
+                msg: msg
+            },
+            [produce_ok]
+        );
+    }
+    // Nothing is appended before the commit.
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k1": 0, "k2": 0})
+        },
+        []
+    );
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(replies[0].body["msgs"], json!({}));
+
+    let sent = assert_replies!(
+        nodes[0],
+        commit {
+            txn_id: txn_id.clone()
+        },
+        []
+    );
+    assert_eq!(testkit::kind(&sent[0]), "prepare_txn");
+    let replies = settle(&mut nodes, &[], sent);
+    assert_eq!(testkit::replies(&replies), ["commit_ok"]);
+    assert_eq!(
+        replies[0].body["offsets"],
+        json!({"k1": [0, 0], "k2": [0, 1]})
+    );
+
+    // A transaction that isn't committed in time is aborted, and appends nothing.
+    let sent = assert_replies!(nodes[0], begin, [begin_ok]);
+    let txn_id = sent[0].body["txn_id"].clone();
+    assert_replies!(
+        nodes[0],
+        produce {
+            txn_id: txn_id.clone(),
+            key: "k2",
+            msg: 22
+        },
+        [produce_ok]
+    );
+    clock.advance(Duration::from_secs(10));
+    let sent = assert_replies!(nodes[0], commit { txn_id: txn_id }, [error]);
+    assert_eq!(sent[0].body["code"], 14);
+    let sent = assert_replies!(nodes[0], send { key: "k2", msg: 23 }, [send_ok]);
+    assert_eq!(sent[0].body["offset"], 2);
+}
+
+/// Three kafka nodes that don't replicate, of which n0 owns k3, and n2 owns k1.
+fn kafka_nodes(clock: &Arc<VirtualClock>) -> [TestNode; 3] {
+    let ids = ["n0", "n1", "n2"];
+    ids.map(|id| {
+        let cli = kafka::Cli::parse_from(["kafka"]);
+        TestNode::init(kafka::simulated(cli, clock.clone()).unwrap(), id, &ids)
+    })
+}
+
+/// Hands the messages of `sent` to the nodes they're for, and returns what those sent.
+fn step(nodes: &mut [TestNode], sent: Vec<testkit::Sent>) -> Vec<testkit::Sent> {
+    sent.into_iter()
+        .flat_map(|message| {
+            let index: usize = message.dest[1..].parse().unwrap();
+            nodes[index].receive(&message.src, message.body)
+        })
+        .collect()
+}
+
+/// Has n1 begin a transaction of k1 and k3, whose primary key is k1, and commit it.
+/// Returns what n1 sent the owners.
+fn commit_transaction(nodes: &mut [TestNode; 3]) -> Vec<testkit::Sent> {
+    let sent = assert_replies!(nodes[1], begin, [begin_ok]);
+    let txn_id = sent[0].body["txn_id"].clone();
+    for (key, msg) in [("k1", 10), ("k3", 30)] {
+        assert_replies!(
+            nodes[1],
+            produce {
+                txn_id: txn_id.clone(),
+                key: key,
+                msg: msg
+            },
+            [produce_ok]
+        );
+    }
+    assert_replies!(nodes[1], commit { txn_id: txn_id }, [])
+}
+
+fn poll_msgs(node: &mut TestNode, key: &str) -> Value {
+    let sent = assert_replies!(
+        node,
+        poll {
+            offsets: json!({ key: 0 })
+        },
+        [poll_ok]
+    );
+    sent[0].body["msgs"][key].clone()
+}
+
+#[test]
+fn kafka_commits_a_transaction_the_primary_key_committed_if_its_coordinator_crashed() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    let prepares = commit_transaction(&mut nodes);
+    assert_eq!(testkit::kind(&prepares[0]), "prepare_txn");
+    let votes = step(&mut nodes, prepares);
+    // The owners appended the messages, but polls leave them out until the commit.
+    assert_eq!(poll_msgs(&mut nodes[2], "k1"), json!([]));
+
+    let decision = step(&mut nodes, votes);
+    assert_eq!(
+        (decision[0].dest.as_str(), testkit::kind(&decision[0])),
+        ("n2", "decide_txn")
+    );
+    let answer = step(&mut nodes, decision);
+    assert_eq!(poll_msgs(&mut nodes[2], "k1"), json!([[0, 10]]));
+
+    // n1 crashes before n0 hears the decision, so n0 asks n2 once it waited long enough.
+    let decision = step(&mut nodes, answer);
+    assert_eq!(decision[0].dest, "n0");
+    assert_eq!(poll_msgs(&mut nodes[0], "k3"), json!([]));
+    clock.advance(Duration::from_secs(6));
+    let sent = assert_replies!(
+        nodes[0],
+        poll {
+            offsets: json!({"k3": 0})
+        },
+        [poll_ok]
+    );
+    settle(&mut nodes, &["n1"], sent);
+    assert_eq!(poll_msgs(&mut nodes[0], "k3"), json!([[0, 30]]));
+}
+
+#[test]
+fn kafka_aborts_a_transaction_whose_coordinator_crashed_before_deciding() {
+    let clock = Arc::new(VirtualClock::new());
+    let mut nodes = kafka_nodes(&clock);
+    let prepares = commit_transaction(&mut nodes);
+    let votes = step(&mut nodes, prepares);
+
+    // n1 doesn't get the votes in time. n2 settles k1, the primary key, by aborting the
+    // transaction, and tells n0 so when it asks.
+    clock.advance(Duration::from_secs(6));
+    let sent = assert_replies!(nodes[0], send { key: "k3", msg: 31 }, [send_ok]);
+    assert_eq!(testkit::kind(&sent[0]), "decide_txn");
+    settle(&mut nodes, &["n1"], sent);
+    assert_eq!(poll_msgs(&mut nodes[0], "k3"), json!([[1, 31]]));
+    assert_eq!(poll_msgs(&mut nodes[2], "k1"), json!([]));
+
+    // The commit fails once the votes arrive.
+    let replies = settle(&mut nodes, &[], votes);
+    assert_eq!(testkit::replies(&replies), ["error"]);
+    assert_eq!(replies[0].body["code"], 14);
+    assert_eq!(poll_msgs(&mut nodes[0], "k3"), json!([[1, 31]]));
+}
+
+#[test]
+fn kafka_followers_keep_the_pending_entries_of_a_transaction_a_restarted_owner_lost() {
+    // n1 owns k1, and n0 follows it.
+    let (mut n0, recover) = replicated_kafka_node("n0");
+    let (mut n1, recover_n0) = replicated_kafka_node("n1");
+    let answers = deliver("n0", &recover, &mut n1);
+    deliver("n1", &answers, &mut n0);
+    let answers = deliver("n1", &recover_n0, &mut n0);
+    deliver("n0", &answers, &mut n1);
+
+    let sent = assert_replies!(n0, begin, [begin_ok]);
+    let txn_id = sent[0].body["txn_id"].clone();
+    assert_replies!(
+        n0,
+        produce {
+            txn_id: txn_id.clone(),
+            key: "k1",
+            msg: 10
+        },
+        [produce_ok]
+    );
+    let prepare = assert_replies!(n0, commit { txn_id: txn_id }, []);
+    let replicate = deliver("n0", &prepare, &mut n1);
+    let acks = deliver("n1", &replicate, &mut n0);
+    let votes = deliver("n0", &acks, &mut n1);
+    assert_eq!(testkit::kind(&votes[0]), "prepare_txn_ok");
+
+    // A restarted n1 gets the entry back from n0, still pending.
+    let (mut n1, recover) = replicated_kafka_node("n1");
+    let answers = deliver("n1", &recover, &mut n0);
+    deliver("n0", &answers, &mut n1);
+    assert_eq!(poll_msgs(&mut n1, "k1"), json!([]));
+
+    let mut sent = deliver("n1", &votes, &mut n0);
+    while testkit::replies(&sent).is_empty() {
+        let answers = deliver("n0", &sent, &mut n1);
+        sent = deliver("n1", &answers, &mut n0);
+    }
+    assert_eq!(testkit::replies(&sent), ["commit_ok"]);
+    assert_eq!(poll_msgs(&mut n1, "k1"), json!([[0, 10]]));
+}
+
+/// Lines a fuzzer finds quickly. None of them may panic a workload's parser, whether it's
+/// answered or rejected.
+const MALFORMED: [&str; 9] = [
+    "",
+    "{",
+    "null",
+    r#"{"src":"c1","dest":"n0"}"#,
+    r#"{"src":"c1","dest":"n0","body":[]}"#,
+    r#"{"src":"c1","dest":"n0","body":{"type":7,"msg_id":1}}"#,
+    r#"{"src":"c1","dest":"n0","body":{"type":"read","msg_id":-1}}"#,
+    r#"{"src":"c1","dest":"n0","body":{"type":"add","msg_id":1,"delta":1e400}}"#,
+    r#"{"src":"n1","dest":"n0","body":{"type":"gossip","msg_id":1,"messages":{"a":1}}}"#,
+];
+
+type Parse = fn(&[u8]) -> Result<Result<Value, Value>, anyhow::Error>;
+
+#[test]
+fn malformed_lines_are_answered_or_rejected() {
+    let parsers: [(&str, Parse); 8] = [
+        ("echo", echo::parse_message),
+        ("unique_ids", unique_ids::parse_message),
+        ("broadcast", broadcast::parse_message),
+        ("g_counter", g_counter::parse_message),
+        ("pn_counter", pn_counter::parse_message),
+        ("g_set", g_set::parse_message),
+        ("register", register::parse_message),
+        ("kafka", kafka::parse_message),
+    ];
+    for (workload, parse) in parsers {
+        for line in MALFORMED {
+            if let Ok(Ok(message)) = parse(line.as_bytes()) {
+                panic!("{workload} accepted {line} as {message}");
+            }
+        }
+        let reply = parse(br#"{"src":"c1","dest":"n0","body":{"type":"shout","msg_id":3}}"#)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(reply["body"]["code"], 10, "{workload}");
+    }
+}